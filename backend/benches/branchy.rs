@@ -0,0 +1,96 @@
+//! Run time of a branchy loop with and without if-conversion.
+//!
+//! One TB loops over an LCG, summing the absolute value of each
+//! output through a brcond diamond, the shape a guest `abs()`
+//! or `max()` translates to. The sign is random, so the branchy
+//! code mispredicts about half the time. Run with
+//! `cargo bench -p tcg-backend --bench branchy`.
+
+use std::time::{Duration, Instant};
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::optimize::OptimizeOptions;
+use tcg_backend::translate::{analyze_with, codegen};
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::{Cond, Context, Type};
+
+const ITERS: u64 = 10_000_000;
+const ROUNDS: usize = 5;
+
+/// env: [x, n, sum]. Loops `n` times.
+fn build(ctx: &mut Context) {
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let x = ctx.new_global(Type::I64, env, 0, "x");
+    let n = ctx.new_global(Type::I64, env, 8, "n");
+    let sum = ctx.new_global(Type::I64, env, 16, "sum");
+    let mul = ctx.new_const(Type::I64, 6364136223846793005);
+    let inc = ctx.new_const(Type::I64, 1442695040888963407);
+    let zero = ctx.new_const(Type::I64, 0);
+    let one = ctx.new_const(Type::I64, 1);
+    let d = ctx.new_temp_tb(Type::I64);
+    let (head, neg, join) = (ctx.new_label(), ctx.new_label(), ctx.new_label());
+
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_set_label(head);
+    ctx.gen_mul(Type::I64, x, x, mul);
+    ctx.gen_add(Type::I64, x, x, inc);
+    ctx.gen_brcond(Type::I64, x, zero, Cond::Lt, neg);
+    ctx.gen_mov(Type::I64, d, x);
+    ctx.gen_br(join);
+    ctx.gen_set_label(neg);
+    ctx.gen_neg(Type::I64, d, x);
+    ctx.gen_set_label(join);
+    ctx.gen_add(Type::I64, sum, sum, d);
+    ctx.gen_sub(Type::I64, n, n, one);
+    ctx.gen_brcond(Type::I64, n, zero, Cond::Ne, head);
+    ctx.gen_exit_tb_raw(0);
+}
+
+/// Best ns per iteration, and the final sum.
+fn measure(opts: &OptimizeOptions) -> (f64, u64) {
+    let mut backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(64 * 1024).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    build(&mut ctx);
+    analyze_with(&mut ctx, opts);
+    let start = codegen(&mut ctx, &backend, &mut buf).unwrap();
+
+    let mut best = Duration::MAX;
+    let mut env = [0u64; 3];
+    for _ in 0..ROUNDS {
+        env = [1, ITERS, 0];
+        let t = Instant::now();
+        unsafe {
+            let prologue: unsafe extern "C" fn(
+                *mut u8,
+                *const u8,
+                *mut u64,
+            ) -> usize = std::mem::transmute(buf.base_ptr());
+            prologue(
+                env.as_mut_ptr().cast(),
+                buf.ptr_at(start),
+                tcg_core::helper::pending_ptr(),
+            );
+        }
+        best = best.min(t.elapsed());
+    }
+    (best.as_secs_f64() * 1e9 / ITERS as f64, env[2])
+}
+
+fn main() {
+    let (on, sum_on) = measure(&OptimizeOptions::default());
+    let off_opts = OptimizeOptions {
+        if_convert: false,
+        ..OptimizeOptions::default()
+    };
+    let (off, sum_off) = measure(&off_opts);
+    assert_eq!(sum_on, sum_off, "if-conversion changed the result");
+    println!(
+        "branchy/abs: {on:.2} ns/iter if-converted, {off:.2} ns/iter \
+         branching (best of {ROUNDS} rounds of {ITERS})"
+    );
+}
@@ -0,0 +1,135 @@
+//! Per-TB translation time for small TBs.
+//!
+//! Builds 10,000 Contexts shaped like the one- and two-insn TBs a
+//! RISC-V guest spends most of its translations on, and times
+//! `translate()` on each. Run with `cargo bench -p tcg-backend`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::translate;
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::tb::TbExit;
+use tcg_core::{Cond, Context, TempIdx, Type};
+
+const TBS: usize = 10_000;
+const ROUNDS: usize = 10;
+
+/// env, x0..x31 and pc, as the RISC-V frontend registers them.
+fn new_ctx() -> (Context, Vec<TempIdx>, TempIdx) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let x = (0..32)
+        .map(|i| ctx.new_global(Type::I64, env, 8 * i, "x"))
+        .collect();
+    let pc = ctx.new_global(Type::I64, env, 8 * 32, "pc");
+    (ctx, x, pc)
+}
+
+/// Build one small TB into `ctx`, cycling through four shapes.
+fn build(ctx: &mut Context, x: &[TempIdx], pc: TempIdx, n: usize) {
+    let base = 0x1_0000 + 8 * n as u64;
+    let next = |ctx: &mut Context, off: u64| {
+        let c = ctx.new_const(Type::I64, base + off);
+        ctx.gen_mov(Type::I64, pc, c);
+    };
+    ctx.gen_insn_start(base);
+    match n % 4 {
+        // addi a0, a0, 1; fallthrough
+        0 => {
+            let one = ctx.new_const(Type::I64, 1);
+            ctx.gen_add(Type::I64, x[10], x[10], one);
+            ctx.gen_goto_tb(0);
+            next(ctx, 4);
+            ctx.gen_exit_tb(TbExit::Chain(0));
+        }
+        // bne t0, t1, L
+        1 => {
+            let taken = ctx.new_label();
+            ctx.gen_brcond(Type::I64, x[5], x[6], Cond::Ne, taken);
+            ctx.gen_goto_tb(0);
+            next(ctx, 4);
+            ctx.gen_exit_tb(TbExit::Chain(0));
+            ctx.gen_set_label(taken);
+            ctx.gen_goto_tb(1);
+            next(ctx, 0x40);
+            ctx.gen_exit_tb(TbExit::Chain(1));
+        }
+        // ld t0, 8(sp); add t1, t1, t0
+        2 => {
+            let eight = ctx.new_const(Type::I64, 8);
+            let addr = ctx.new_temp(Type::I64);
+            ctx.gen_add(Type::I64, addr, x[2], eight);
+            ctx.gen_qemu_ld(Type::I64, x[5], addr, 3);
+            ctx.gen_insn_start(base + 4);
+            ctx.gen_add(Type::I64, x[6], x[6], x[5]);
+            ctx.gen_goto_tb(0);
+            next(ctx, 8);
+            ctx.gen_exit_tb(TbExit::Chain(0));
+        }
+        // add a0, a1, a2; ret
+        _ => {
+            ctx.gen_add(Type::I64, x[10], x[11], x[12]);
+            ctx.gen_insn_start(base + 4);
+            let mask = ctx.new_const(Type::I64, !1);
+            let t = ctx.new_temp(Type::I64);
+            ctx.gen_and(Type::I64, t, x[1], mask);
+            ctx.gen_mov(Type::I64, pc, t);
+            ctx.gen_exit_tb(TbExit::Normal);
+        }
+    }
+}
+
+/// Contexts built and then translated together, so the clock is
+/// read once per batch rather than once per TB.
+const BATCH: usize = 100;
+
+/// Mean time to translate one TB over one round of `TBS`.
+fn round(backend: &X86_64CodeGen, buf: &mut CodeBuffer) -> Duration {
+    let mut total = Duration::ZERO;
+    for first in (0..TBS).step_by(BATCH) {
+        let mut ctxs: Vec<Context> = (first..first + BATCH)
+            .map(|n| {
+                let (mut ctx, x, pc) = new_ctx();
+                backend.init_context(&mut ctx);
+                build(&mut ctx, &x, pc, n);
+                ctx
+            })
+            .collect();
+        let start = Instant::now();
+        for ctx in &mut ctxs {
+            backend.clear_goto_tb_offsets();
+            black_box(translate(ctx, backend, buf).unwrap());
+        }
+        total += start.elapsed();
+    }
+    total / TBS as u32
+}
+
+fn main() {
+    let mut backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(64 * 1024 * 1024).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let code_start = buf.offset();
+
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        buf.set_offset(code_start);
+        best = best.min(round(&backend, &mut buf));
+    }
+    // Identifies the emitted code, so a speedup can be checked
+    // not to have come from generating something else.
+    let code = &buf.as_slice()[code_start..];
+    let hash = code.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    println!(
+        "translate/small: {} ns/TB (best of {ROUNDS} rounds of {TBS}), \
+         code {} bytes, hash {hash:#018x}",
+        best.as_nanos(),
+        code.len()
+    );
+}
@@ -0,0 +1,654 @@
+use crate::aarch64::emitter::*;
+use crate::aarch64::regs::{
+    Reg, CALLEE_SAVED, CALL_ARG_REGS, HELPER_PANIC_SLOT, PUSH_SIZE,
+    STACK_ADDEND, STATIC_CALL_ARGS_SIZE, TCG_AREG0, TCG_GUEST_BASE_REG,
+    TCG_REG_TMP0, TCG_REG_TMP1,
+};
+use crate::code_buffer::CodeBuffer;
+use crate::constraint::OpConstraint;
+use crate::HostCodeGen;
+#[cfg(debug_assertions)]
+use tcg_core::tb::TB_EXIT_STACK_OVERFLOW;
+use tcg_core::tb::{TbExit, TB_EXIT_HELPER_PANIC};
+use tcg_core::{Cond, Context, Op, Opcode, RelocKind, Type};
+
+/// Bytes of code `emit_goto_tb` emits after the jump offset.
+const GOTO_TB_SIZE: usize = 16;
+
+impl Aarch64CodeGen {
+    /// Emit `cbz Xt, <after the code `body` emits>`.
+    fn emit_skip_if_zero(
+        buf: &mut CodeBuffer,
+        rt: Reg,
+        body: impl FnOnce(&mut CodeBuffer),
+    ) {
+        let cbz = buf.offset();
+        buf.emit_u32(OPC_CBZ | rt as u32);
+        body(buf);
+        let imm19 = ((buf.offset() - cbz) >> 2) as u32;
+        buf.patch_u32(cbz, OPC_CBZ | imm19 << 5 | rt as u32);
+    }
+
+    fn flush(buf: &CodeBuffer, start: usize, end: usize) {
+        flush_icache_range(
+            buf.ptr_at(start) as usize,
+            buf.ptr_at(end) as usize,
+        );
+    }
+}
+
+impl HostCodeGen for Aarch64CodeGen {
+    fn op_constraint(&self, opc: Opcode) -> &'static OpConstraint {
+        crate::aarch64::constraints::op_constraint(opc)
+    }
+
+    fn allocatable_regs(&self) -> tcg_core::RegSet {
+        crate::aarch64::regs::ALLOCATABLE_REGS
+    }
+
+    fn call_clobbered_regs(&self) -> tcg_core::RegSet {
+        crate::aarch64::regs::CALL_CLOBBERED
+    }
+
+    fn branch_reloc(&self, opc: Opcode) -> RelocKind {
+        match opc {
+            Opcode::BrCond => RelocKind::Imm19,
+            _ => RelocKind::Imm26,
+        }
+    }
+
+    /// No AArch64 decoder yet: one `.word` per instruction.
+    fn disas_insn(&self, _pc: u64, code: &[u8]) -> (String, usize) {
+        match code.get(..4) {
+            Some(w) => {
+                let w = u32::from_le_bytes(w.try_into().unwrap());
+                (format!(".word {w:#010x}"), 4)
+            }
+            None => (".byte ???".into(), 0),
+        }
+    }
+
+    fn emit_prologue(&mut self, buf: &mut CodeBuffer) {
+        self.prologue_offset = buf.offset();
+        // stp x29, x30, [sp, #-PUSH_SIZE]!; mov x29, sp
+        emit_ldst_pair(
+            buf,
+            OPC_STP_PRE,
+            Reg::X29,
+            Reg::X30,
+            Reg::Sp,
+            -(PUSH_SIZE as i32),
+        );
+        emit_addsub_imm(buf, OPC_ADDI, true, Reg::X29, Reg::Sp, 0);
+        for (i, pair) in CALLEE_SAVED.chunks(2).enumerate() {
+            let off = 16 * (i as i32 + 1);
+            emit_ldst_pair(buf, OPC_STP, pair[0], pair[1], Reg::Sp, off);
+        }
+        // mov x19, x0 (env)
+        emit_mov_rr(buf, true, TCG_AREG0, CALL_ARG_REGS[0]);
+        // Load guest_base: ldr x28, [x19, #520]
+        emit_ldst(
+            buf,
+            LdSt::LdrX,
+            TCG_GUEST_BASE_REG,
+            TCG_AREG0,
+            520, // GUEST_BASE_OFFSET
+        );
+        emit_addsub_imm(
+            buf,
+            OPC_SUBI,
+            true,
+            Reg::Sp,
+            Reg::Sp,
+            STACK_ADDEND as u32,
+        );
+        // str x2, [sp, #HELPER_PANIC_SLOT] (helper-panic word)
+        emit_ldst(
+            buf,
+            LdSt::StrX,
+            CALL_ARG_REGS[2],
+            Reg::Sp,
+            HELPER_PANIC_SLOT as i64,
+        );
+        // br x1 (TB code pointer)
+        emit_br(buf, CALL_ARG_REGS[1]);
+        self.code_gen_start = buf.offset();
+        Self::flush(buf, self.prologue_offset, buf.offset());
+    }
+
+    fn emit_epilogue(&mut self, buf: &mut CodeBuffer) {
+        let start = buf.offset();
+        // Helper-panic exit, reached by `bl` from the post-call
+        // check: record the call site (the link register) in the
+        // pending word and leave with TB_EXIT_HELPER_PANIC.
+        self.helper_panic_offset = start;
+        emit_ldst(
+            buf,
+            LdSt::LdrX,
+            TCG_REG_TMP0,
+            Reg::Sp,
+            HELPER_PANIC_SLOT as i64,
+        );
+        emit_ldst(buf, LdSt::StrX, Reg::X30, TCG_REG_TMP0, 0);
+        emit_mov_ri(buf, false, Reg::X0, TB_EXIT_HELPER_PANIC as u64);
+        let jmp_offset = buf.offset();
+        buf.emit_u32(OPC_B);
+
+        self.epilogue_return_zero_offset = buf.offset();
+        emit_mov_ri(buf, false, Reg::X0, 0);
+        self.tb_ret_offset = buf.offset();
+        emit_addsub_imm(
+            buf,
+            OPC_ADDI,
+            true,
+            Reg::Sp,
+            Reg::Sp,
+            STACK_ADDEND as u32,
+        );
+        for (i, pair) in CALLEE_SAVED.chunks(2).enumerate() {
+            let off = 16 * (i as i32 + 1);
+            emit_ldst_pair(buf, OPC_LDP, pair[0], pair[1], Reg::Sp, off);
+        }
+        emit_ldst_pair(
+            buf,
+            OPC_LDP_POST,
+            Reg::X29,
+            Reg::X30,
+            Reg::Sp,
+            PUSH_SIZE as i32,
+        );
+        emit_ret(buf);
+        let disp = (self.tb_ret_offset - jmp_offset) as u32 >> 2;
+        buf.patch_u32(jmp_offset, OPC_B | disp);
+        Self::flush(buf, start, buf.offset());
+    }
+
+    /// Point the `goto_tb` at `jump_offset` to `target_offset`:
+    /// a direct `b` when in range, else `ldr x16, lit` with the
+    /// absolute target stored in the literal first.
+    fn patch_jump(
+        &self,
+        buf: &CodeBuffer,
+        jump_offset: usize,
+        target_offset: usize,
+    ) {
+        let insn = if b_reaches(jump_offset, target_offset) {
+            let disp = RelocKind::Imm26.disp(jump_offset, target_offset);
+            OPC_B | (disp >> 2) as u32 & 0x3ff_ffff
+        } else {
+            let addr = buf.ptr_at(target_offset) as u64;
+            buf.patch_u64(jump_offset + 8, addr);
+            OPC_LDR_LIT | 2 << 5 | TCG_REG_TMP0 as u32
+        };
+        buf.patch_u32(jump_offset, insn);
+        Self::flush(buf, jump_offset, jump_offset + GOTO_TB_SIZE);
+    }
+
+    fn jump_target(&self, buf: &CodeBuffer, jump_offset: usize) -> usize {
+        let insn = buf.read_u32(jump_offset);
+        if insn & 0xfc00_0000 == OPC_B {
+            let disp = ((insn << 6) as i32 >> 4) as i64;
+            (jump_offset as i64 + disp) as usize
+        } else {
+            let addr = buf.read_u64(jump_offset + 8) as usize;
+            addr - buf.base_ptr() as usize
+        }
+    }
+
+    fn epilogue_offset(&self) -> usize {
+        self.tb_ret_offset
+    }
+
+    fn init_context(&self, ctx: &mut tcg_core::Context) {
+        use crate::aarch64::regs;
+        ctx.reserved_regs = regs::RESERVED_REGS;
+        ctx.env_reg = TCG_AREG0 as u8;
+        ctx.set_frame(
+            Reg::Sp as u8,
+            STATIC_CALL_ARGS_SIZE as i64,
+            (regs::CPU_TEMP_BUF_NLONGS * 8) as i64,
+        );
+    }
+
+    fn tcg_out_mov(&self, buf: &mut CodeBuffer, ty: Type, dst: u8, src: u8) {
+        if dst == src {
+            return;
+        }
+        let is64 = ty == Type::I64;
+        emit_mov_rr(buf, is64, Reg::from_u8(dst), Reg::from_u8(src));
+    }
+
+    /// Constants needing more than two MOVZ/MOVN/MOVK come from
+    /// the TB's constant pool with one `ldr` literal.
+    fn tcg_out_movi(&self, buf: &mut CodeBuffer, ty: Type, dst: u8, val: u64) {
+        let is64 = ty == Type::I64;
+        if is64 && mov_ri_len(is64, val) > 2 {
+            let slot = buf.intern_const(val);
+            buf.add_pool_reloc(buf.offset(), RelocKind::Imm19, slot);
+            buf.emit_u32(OPC_LDR_LIT | dst as u32);
+        } else {
+            emit_mov_ri(buf, is64, Reg::from_u8(dst), val);
+        }
+    }
+
+    fn tcg_out_ld(
+        &self,
+        buf: &mut CodeBuffer,
+        ty: Type,
+        dst: u8,
+        base: u8,
+        offset: i64,
+    ) {
+        let op = if ty == Type::I64 {
+            LdSt::LdrX
+        } else {
+            LdSt::LdrW
+        };
+        emit_ldst(buf, op, Reg::from_u8(dst), Reg::from_u8(base), offset);
+    }
+
+    fn tcg_out_st(
+        &self,
+        buf: &mut CodeBuffer,
+        ty: Type,
+        src: u8,
+        base: u8,
+        offset: i64,
+    ) {
+        let op = if ty == Type::I64 {
+            LdSt::StrX
+        } else {
+            LdSt::StrW
+        };
+        emit_ldst(buf, op, Reg::from_u8(src), Reg::from_u8(base), offset);
+    }
+
+    fn tcg_out_op(
+        &self,
+        buf: &mut CodeBuffer,
+        ctx: &Context,
+        op: &Op,
+        oregs: &[u8],
+        iregs: &[u8],
+        cargs: &[u32],
+    ) {
+        let is64 = op.op_type == Type::I64;
+        let d = || Reg::from_u8(oregs[0]);
+        let a = || Reg::from_u8(iregs[0]);
+        let b = || Reg::from_u8(iregs[1]);
+        let rrr = |buf: &mut CodeBuffer, opc| {
+            emit_rrr(buf, opc, is64, d(), a(), b());
+        };
+        match op.opc {
+            Opcode::Add => rrr(buf, OPC_ADD),
+            Opcode::Sub => rrr(buf, OPC_SUB),
+            Opcode::And => rrr(buf, OPC_AND),
+            Opcode::Or => rrr(buf, OPC_ORR),
+            Opcode::Xor => rrr(buf, OPC_EOR),
+            Opcode::AndC => rrr(buf, OPC_BIC),
+            Opcode::Shl => rrr(buf, OPC_LSLV),
+            Opcode::Shr => rrr(buf, OPC_LSRV),
+            Opcode::Sar => rrr(buf, OPC_ASRV),
+            Opcode::RotR => rrr(buf, OPC_RORV),
+            // Rotate left by n is rotate right by -n; RORV
+            // takes the count modulo the width.
+            Opcode::RotL => {
+                emit_rrr(buf, OPC_SUB, is64, TCG_REG_TMP0, Reg::XZR, b());
+                emit_rrr(buf, OPC_RORV, is64, d(), a(), TCG_REG_TMP0);
+            }
+            Opcode::Mul => emit_madd(buf, is64, d(), a(), b(), Reg::XZR),
+            Opcode::Neg => emit_rrr(buf, OPC_SUB, is64, d(), Reg::XZR, a()),
+            Opcode::Not => emit_rrr(buf, OPC_ORN, is64, d(), Reg::XZR, a()),
+            // -- Double-width multiply: both halves land in TMP0
+            // and TMP1 first, so outputs may reuse input registers --
+            Opcode::MulS2 | Opcode::MulU2 | Opcode::MulSU2 => {
+                let (t0, t1) = (TCG_REG_TMP0, TCG_REG_TMP1);
+                let hi = Reg::from_u8(oregs[1]);
+                if is64 {
+                    emit_madd(buf, true, t0, a(), b(), Reg::XZR);
+                    let opc = if op.opc == Opcode::MulS2 {
+                        OPC_SMULH
+                    } else {
+                        OPC_UMULH
+                    };
+                    emit_rrr(buf, opc, true, t1, a(), b());
+                    // Signed * unsigned: the unsigned high half,
+                    // minus b when a is negative.
+                    if op.opc == Opcode::MulSU2 {
+                        emit_addsub_imm(buf, OPC_SUBSI, true, Reg::XZR, a(), 0);
+                        emit_csel(
+                            buf,
+                            OPC_CSEL,
+                            true,
+                            hi,
+                            b(),
+                            Reg::XZR,
+                            A64Cond::Lt,
+                        );
+                        emit_rrr(buf, OPC_SUB, true, t1, t1, hi);
+                    }
+                    emit_mov_rr(buf, true, d(), t0);
+                    emit_mov_rr(buf, true, hi, t1);
+                } else {
+                    // The 64-bit product of the 32-bit inputs; a
+                    // signed a times an unsigned b fits in 64 bits.
+                    match op.opc {
+                        Opcode::MulS2 => {
+                            emit_rrr(buf, OPC_SMULL, true, t0, a(), b())
+                        }
+                        Opcode::MulU2 => {
+                            emit_rrr(buf, OPC_UMULL, true, t0, a(), b())
+                        }
+                        _ => {
+                            emit_bfm(buf, OPC_SBFM, true, t0, a(), 0, 31);
+                            emit_mov_rr(buf, false, t1, b());
+                            emit_madd(buf, true, t0, t0, t1, Reg::XZR);
+                        }
+                    }
+                    emit_mov_rr(buf, false, d(), t0);
+                    emit_lsr_ri(buf, true, hi, t0, 32);
+                }
+            }
+            // -- Carry/borrow arithmetic. The carry lives in the C
+            // flag, which subtraction sets to NOT borrow; SBC
+            // subtracts NOT C, so borrows chain the same way --
+            Opcode::AddCO => rrr(buf, OPC_ADDS),
+            Opcode::AddCI => rrr(buf, OPC_ADC),
+            Opcode::AddCIO => rrr(buf, OPC_ADCS),
+            Opcode::AddC1O => {
+                // subs wzr, wzr, wzr sets C.
+                emit_rrr(buf, OPC_SUBS, false, Reg::XZR, Reg::XZR, Reg::XZR);
+                rrr(buf, OPC_ADCS);
+            }
+            Opcode::SubBO => rrr(buf, OPC_SUBS),
+            Opcode::SubBI => rrr(buf, OPC_SBC),
+            Opcode::SubBIO => rrr(buf, OPC_SBCS),
+            Opcode::SubB1O => {
+                // adds wzr, wzr, wzr clears C: a borrow in.
+                emit_rrr(buf, OPC_ADDS, false, Reg::XZR, Reg::XZR, Reg::XZR);
+                rrr(buf, OPC_SBCS);
+            }
+            Opcode::CtPop => emit_ctpop(buf, is64, d(), a()),
+            Opcode::SetCond | Opcode::NegSetCond => {
+                let cond = cond_from_u32(cargs[0]);
+                emit_cmp(buf, is64, cond.is_tst(), a(), b());
+                let c = A64Cond::from_tcg(cond);
+                if op.opc == Opcode::SetCond {
+                    emit_cset(buf, is64, d(), c);
+                } else {
+                    emit_csetm(buf, is64, d(), c);
+                }
+            }
+            Opcode::MovCond => {
+                let cond = cond_from_u32(cargs[0]);
+                emit_cmp(buf, is64, cond.is_tst(), a(), b());
+                let (v1, v2) = (Reg::from_u8(iregs[2]), Reg::from_u8(iregs[3]));
+                let c = A64Cond::from_tcg(cond);
+                emit_csel(buf, OPC_CSEL, is64, d(), v1, v2, c);
+            }
+            // The branch is the last instruction, for the label
+            // use `branch_reloc` describes.
+            Opcode::BrCond => {
+                let cond = cond_from_u32(cargs[0]);
+                emit_cmp(buf, is64, cond.is_tst(), a(), b());
+                let c = A64Cond::from_tcg(cond);
+                let label = ctx.label(cargs[1]);
+                if label.has_value {
+                    emit_bcond(buf, c, label.value);
+                } else {
+                    buf.emit_u32(OPC_BCOND | c as u32);
+                }
+            }
+            Opcode::Br => {
+                let label = ctx.label(cargs[0]);
+                if label.has_value {
+                    emit_b(buf, label.value);
+                } else {
+                    buf.emit_u32(OPC_B);
+                }
+            }
+            // -- Bit counting: the fallback input when a == 0 --
+            Opcode::Clz | Opcode::Ctz => {
+                if op.opc == Opcode::Ctz {
+                    emit_rr(buf, OPC_RBIT, is64, TCG_REG_TMP0, a());
+                    emit_rr(buf, OPC_CLZ, is64, TCG_REG_TMP0, TCG_REG_TMP0);
+                } else {
+                    emit_rr(buf, OPC_CLZ, is64, TCG_REG_TMP0, a());
+                }
+                emit_addsub_imm(buf, OPC_SUBSI, is64, Reg::XZR, a(), 0);
+                emit_csel(
+                    buf,
+                    OPC_CSEL,
+                    is64,
+                    d(),
+                    TCG_REG_TMP0,
+                    b(),
+                    A64Cond::Ne,
+                );
+            }
+            // -- Bit fields --
+            Opcode::Extract | Opcode::SExtract => {
+                let (ofs, len) = (cargs[0], cargs[1]);
+                let opc = if op.opc == Opcode::Extract {
+                    OPC_UBFM
+                } else {
+                    OPC_SBFM
+                };
+                emit_bfm(buf, opc, is64, d(), a(), ofs, ofs + len - 1);
+            }
+            // Constraints guarantee oregs[0] == iregs[0].
+            Opcode::Deposit => {
+                let (ofs, len) = (cargs[0], cargs[1]);
+                let bits = if is64 { 64 } else { 32 };
+                let immr = (bits - ofs) % bits;
+                emit_bfm(buf, OPC_BFM, is64, d(), b(), immr, len - 1);
+            }
+            Opcode::Extract2 => {
+                emit_extr(buf, is64, d(), b(), a(), cargs[0]);
+            }
+            // -- Byte swap; TCG_BSWAP_OS = 4 sign-extends the
+            // result, otherwise it is zero-extended --
+            Opcode::Bswap16 => {
+                emit_rr(buf, OPC_REV16, false, d(), a());
+                if cargs[0] & 4 != 0 {
+                    emit_bfm(buf, OPC_SBFM, is64, d(), d(), 0, 15);
+                } else {
+                    emit_bfm(buf, OPC_UBFM, false, d(), d(), 0, 15);
+                }
+            }
+            Opcode::Bswap32 => {
+                emit_rr(buf, OPC_REV32, false, d(), a());
+                if cargs[0] & 4 != 0 {
+                    emit_bfm(buf, OPC_SBFM, true, d(), d(), 0, 31);
+                }
+            }
+            Opcode::Bswap64 => emit_rr(buf, OPC_REV64, true, d(), a()),
+            // -- Type conversions --
+            Opcode::ExtI32I64 => emit_bfm(buf, OPC_SBFM, true, d(), a(), 0, 31),
+            // MOV w, w zero-extends (and truncates).
+            Opcode::ExtUI32I64 | Opcode::ExtrlI64I32 => {
+                emit_mov_rr(buf, false, d(), a());
+            }
+            Opcode::ExtrhI64I32 => emit_lsr_ri(buf, true, d(), a(), 32),
+            // -- Host memory: [base + offset] --
+            Opcode::Ld
+            | Opcode::Ld8U
+            | Opcode::Ld8S
+            | Opcode::Ld16U
+            | Opcode::Ld16S
+            | Opcode::Ld32U
+            | Opcode::Ld32S => {
+                let ldst = match op.opc {
+                    Opcode::Ld if is64 => LdSt::LdrX,
+                    Opcode::Ld | Opcode::Ld32U => LdSt::LdrW,
+                    Opcode::Ld8U => LdSt::Ldrb,
+                    Opcode::Ld8S if is64 => LdSt::LdrsbX,
+                    Opcode::Ld8S => LdSt::LdrsbW,
+                    Opcode::Ld16U => LdSt::Ldrh,
+                    Opcode::Ld16S if is64 => LdSt::LdrshX,
+                    Opcode::Ld16S => LdSt::LdrshW,
+                    _ => LdSt::Ldrsw,
+                };
+                emit_ldst(buf, ldst, d(), a(), cargs[0] as i32 as i64);
+            }
+            Opcode::St | Opcode::St8 | Opcode::St16 | Opcode::St32 => {
+                let ldst = match op.opc {
+                    Opcode::St if is64 => LdSt::StrX,
+                    Opcode::St | Opcode::St32 => LdSt::StrW,
+                    Opcode::St8 => LdSt::Strb,
+                    _ => LdSt::Strh,
+                };
+                emit_ldst(buf, ldst, a(), b(), cargs[0] as i32 as i64);
+            }
+            // -- Guest memory (user-mode: [X28 + addr]) --
+            // One LDR/STR whatever the MemOp alignment: unaligned
+            // data accesses do not fault on Linux, and an aligned
+            // access of up to 8 bytes is single-copy atomic.
+            Opcode::QemuLd => {
+                let memop = cargs[0] as u16;
+                let ldst = match (memop & 3, memop & 4 != 0) {
+                    (0, false) => LdSt::Ldrb,
+                    (0, true) if is64 => LdSt::LdrsbX,
+                    (0, true) => LdSt::LdrsbW,
+                    (1, false) => LdSt::Ldrh,
+                    (1, true) if is64 => LdSt::LdrshX,
+                    (1, true) => LdSt::LdrshW,
+                    (2, false) => LdSt::LdrW,
+                    (2, true) => LdSt::Ldrsw,
+                    _ => LdSt::LdrX,
+                };
+                emit_ldst_reg(buf, ldst, d(), TCG_GUEST_BASE_REG, a());
+            }
+            Opcode::QemuSt => {
+                let ldst = match cargs[0] & 3 {
+                    0 => LdSt::Strb,
+                    1 => LdSt::Strh,
+                    2 => LdSt::StrW,
+                    _ => LdSt::StrX,
+                };
+                emit_ldst_reg(buf, ldst, a(), TCG_GUEST_BASE_REG, b());
+            }
+            Opcode::Mb => emit_dmb(buf),
+            Opcode::ExitTb => {
+                let encoded = TbExit::encode(ctx.tb_idx, cargs[0]);
+                self.emit_exit_tb(buf, encoded);
+            }
+            Opcode::GotoTb => {
+                let (jmp, reset) = self.emit_goto_tb(buf);
+                self.goto_tb_info.lock().unwrap().push((jmp, reset));
+            }
+            Opcode::GotoPtr => Aarch64CodeGen::emit_goto_ptr(buf, a()),
+            Opcode::Call => {
+                let func = (cargs[1] as u64) << 32 | (cargs[0] as u64);
+                emit_mov_ri(buf, true, TCG_REG_TMP0, func);
+                emit_blr(buf, TCG_REG_TMP0);
+                if !self.call_may_panic(cargs[2]) {
+                    return;
+                }
+                // if (*[sp+HELPER_PANIC_SLOT]) bl helper_panic
+                emit_ldst(
+                    buf,
+                    LdSt::LdrX,
+                    TCG_REG_TMP0,
+                    Reg::Sp,
+                    HELPER_PANIC_SLOT as i64,
+                );
+                emit_ldst(buf, LdSt::LdrX, TCG_REG_TMP0, TCG_REG_TMP0, 0);
+                let target = self.helper_panic_offset;
+                Self::emit_skip_if_zero(buf, TCG_REG_TMP0, |buf| {
+                    emit_call(buf, target);
+                });
+            }
+            _ => {
+                panic!("tcg_out_op: unhandled {:?}", op.opc);
+            }
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn set_stack_check(&mut self, on: bool) {
+        self.stack_check = on;
+    }
+
+    #[cfg(debug_assertions)]
+    fn emit_stack_check(&self, buf: &mut CodeBuffer) {
+        if !self.stack_check {
+            return;
+        }
+        // ldr x16, [sp+HELPER_PANIC_SLOT]
+        // ldr x17, [x16+STACK_LIMIT_OFFSET]
+        // mov x16, sp; cmp x16, x17; b.hs body
+        // mov w0, TB_EXIT_STACK_OVERFLOW; b tb_ret
+        emit_ldst(
+            buf,
+            LdSt::LdrX,
+            TCG_REG_TMP0,
+            Reg::Sp,
+            HELPER_PANIC_SLOT as i64,
+        );
+        emit_ldst(
+            buf,
+            LdSt::LdrX,
+            TCG_REG_TMP1,
+            TCG_REG_TMP0,
+            tcg_core::helper::STACK_LIMIT_OFFSET as i64,
+        );
+        emit_addsub_imm(buf, OPC_ADDI, true, TCG_REG_TMP0, Reg::Sp, 0);
+        emit_cmp(buf, true, false, TCG_REG_TMP0, TCG_REG_TMP1);
+        let bcond = buf.offset();
+        buf.emit_u32(OPC_BCOND);
+        emit_mov_ri(buf, false, Reg::X0, TB_EXIT_STACK_OVERFLOW as u64);
+        emit_jmp(buf, self.tb_ret_offset);
+        let imm19 = ((buf.offset() - bcond) >> 2) as u32;
+        buf.patch_u32(bcond, OPC_BCOND | imm19 << 5 | A64Cond::Hs as u32);
+    }
+
+    fn goto_tb_offsets(&self) -> Vec<(usize, usize)> {
+        self.goto_tb_info.lock().unwrap().clone()
+    }
+
+    fn clear_goto_tb_offsets(&self) {
+        self.goto_tb_info.lock().unwrap().clear();
+    }
+
+    fn flush_icache(&self, buf: &CodeBuffer, start: usize, end: usize) {
+        Self::flush(buf, start, end);
+    }
+
+    fn emit_align(&self, buf: &mut CodeBuffer, align: usize) -> usize {
+        let pad = buf.padding_to(align);
+        emit_nops(buf, pad);
+        pad
+    }
+
+    fn max_atomic_bytes(&self) -> u32 {
+        8
+    }
+
+    /// Every op `tcg_out_op` lowers has a constraint; the rest,
+    /// such as `divs2`/`divu2` with no AArch64 equivalent, do not.
+    fn op_supported(&self, opc: Opcode) -> bool {
+        let ct = crate::aarch64::constraints::op_constraint(opc);
+        ct.args.iter().any(|a| !a.regs.is_empty())
+    }
+}
+
+fn cond_from_u32(val: u32) -> Cond {
+    match val {
+        0 => Cond::Never,
+        1 => Cond::Always,
+        8 => Cond::Eq,
+        9 => Cond::Ne,
+        10 => Cond::Lt,
+        11 => Cond::Ge,
+        12 => Cond::Le,
+        13 => Cond::Gt,
+        14 => Cond::Ltu,
+        15 => Cond::Geu,
+        16 => Cond::Leu,
+        17 => Cond::Gtu,
+        18 => Cond::TstEq,
+        19 => Cond::TstNe,
+        _ => panic!("invalid Cond value: {val}"),
+    }
+}
@@ -0,0 +1,127 @@
+use crate::aarch64::regs::{Reg, ALLOCATABLE_REGS};
+use crate::constraint::*;
+use tcg_core::Opcode;
+
+const R: tcg_core::RegSet = ALLOCATABLE_REGS;
+
+/// Return the static register constraint for an opcode on
+/// AArch64. Everything is three-address, so only Deposit
+/// (BFM keeps the other bits of its destination) and calls
+/// pin registers. Ops without a constraint have no lowering:
+/// `op_supported` reports them.
+///
+/// Mirrors QEMU's `tcg_target_op_def()` in
+/// `tcg/aarch64/tcg-target.c.inc`.
+pub fn op_constraint(opc: Opcode) -> &'static OpConstraint {
+    match opc {
+        // -- Binary ALU, shifts, rotates --
+        Opcode::Add
+        | Opcode::Sub
+        | Opcode::Mul
+        | Opcode::And
+        | Opcode::Or
+        | Opcode::Xor
+        | Opcode::AndC
+        | Opcode::Shl
+        | Opcode::Shr
+        | Opcode::Sar
+        | Opcode::RotL
+        | Opcode::RotR
+        | Opcode::Extract2
+        | Opcode::AddCO
+        | Opcode::AddCI
+        | Opcode::AddCIO
+        | Opcode::AddC1O
+        | Opcode::SubBO
+        | Opcode::SubBI
+        | Opcode::SubBIO
+        | Opcode::SubB1O => {
+            static C: OpConstraint = o1_i2(R, R, R);
+            &C
+        }
+        // -- Unary --
+        Opcode::Neg
+        | Opcode::Not
+        | Opcode::CtPop
+        | Opcode::Extract
+        | Opcode::SExtract
+        | Opcode::Bswap16
+        | Opcode::Bswap32
+        | Opcode::Bswap64
+        | Opcode::ExtI32I64
+        | Opcode::ExtUI32I64
+        | Opcode::ExtrlI64I32
+        | Opcode::ExtrhI64I32 => {
+            static C: OpConstraint = o1_i1(R, R);
+            &C
+        }
+        // -- Compare and select: CSET/CSETM/CSEL --
+        Opcode::SetCond | Opcode::NegSetCond | Opcode::Clz | Opcode::Ctz => {
+            static C: OpConstraint = o1_i2(R, R, R);
+            &C
+        }
+        // -- Double-width multiply: inputs are read before
+        // either output is written --
+        Opcode::MulS2 | Opcode::MulU2 | Opcode::MulSU2 => {
+            static C: OpConstraint = o2_i2(R, R, R, R);
+            &C
+        }
+        Opcode::MovCond => {
+            static C: OpConstraint = o1_i4(R, R, R, R, R);
+            &C
+        }
+        Opcode::BrCond => {
+            static C: OpConstraint = o0_i2(R, R);
+            &C
+        }
+        // -- Deposit: BFM inserts into its destination --
+        Opcode::Deposit => {
+            static C: OpConstraint = o1_i2_alias(R, R, R);
+            &C
+        }
+        Opcode::GotoPtr => {
+            static C: OpConstraint = o0_i1(R);
+            &C
+        }
+        // -- Host and guest loads: output, base/addr input --
+        Opcode::Ld
+        | Opcode::Ld8U
+        | Opcode::Ld8S
+        | Opcode::Ld16U
+        | Opcode::Ld16S
+        | Opcode::Ld32U
+        | Opcode::Ld32S
+        | Opcode::QemuLd => {
+            static C: OpConstraint = o1_i1(R, R);
+            &C
+        }
+        // -- Host and guest stores: value input, base/addr input --
+        Opcode::St
+        | Opcode::St8
+        | Opcode::St16
+        | Opcode::St32
+        | Opcode::QemuSt => {
+            static C: OpConstraint = o0_i2(R, R);
+            &C
+        }
+        // -- Call: X0 result, arguments in X0-X5 --
+        Opcode::Call => {
+            const CALL_C: OpConstraint = OpConstraint {
+                args: [
+                    fixed(Reg::X0 as u8),
+                    fixed(Reg::X0 as u8),
+                    fixed(Reg::X1 as u8),
+                    fixed(Reg::X2 as u8),
+                    fixed(Reg::X3 as u8),
+                    fixed(Reg::X4 as u8),
+                    fixed(Reg::X5 as u8),
+                    ArgConstraint::UNUSED,
+                    ArgConstraint::UNUSED,
+                    ArgConstraint::UNUSED,
+                ],
+            };
+            &CALL_C
+        }
+        _ => &OpConstraint::EMPTY,
+    }
+}
@@ -0,0 +1,679 @@
+use std::sync::Mutex;
+
+use crate::aarch64::regs::{Reg, TCG_REG_TMP0, TCG_VEC_TMP};
+use crate::code_buffer::CodeBuffer;
+use tcg_core::helper::CALL_NO_PANIC;
+
+// -- Instruction encodings (32-bit forms; `SF` selects 64-bit) --
+
+/// `sf` bit: 64-bit operation.
+pub const SF: u32 = 1 << 31;
+/// `N` bit of the bitfield and EXTR encodings, set with `SF`.
+pub const BITFIELD_N: u32 = 1 << 22;
+
+// Data processing (shifted register, shift amount 0)
+pub const OPC_AND: u32 = 0x0a00_0000;
+pub const OPC_BIC: u32 = 0x0a20_0000;
+pub const OPC_ORR: u32 = 0x2a00_0000;
+pub const OPC_ORN: u32 = 0x2a20_0000;
+pub const OPC_EOR: u32 = 0x4a00_0000;
+pub const OPC_ANDS: u32 = 0x6a00_0000;
+pub const OPC_ADD: u32 = 0x0b00_0000;
+pub const OPC_ADDS: u32 = 0x2b00_0000;
+pub const OPC_SUB: u32 = 0x4b00_0000;
+pub const OPC_SUBS: u32 = 0x6b00_0000;
+
+// Add/subtract with carry
+pub const OPC_ADC: u32 = 0x1a00_0000;
+pub const OPC_ADCS: u32 = 0x3a00_0000;
+pub const OPC_SBC: u32 = 0x5a00_0000;
+pub const OPC_SBCS: u32 = 0x7a00_0000;
+
+// Data processing (2 source)
+pub const OPC_LSLV: u32 = 0x1ac0_2000;
+pub const OPC_LSRV: u32 = 0x1ac0_2400;
+pub const OPC_ASRV: u32 = 0x1ac0_2800;
+pub const OPC_RORV: u32 = 0x1ac0_2c00;
+
+// Data processing (3 source)
+pub const OPC_MADD: u32 = 0x1b00_0000;
+/// SMULL/UMULL Xd, Wn, Wm (SMADDL/UMADDL with XZR); 64-bit only.
+pub const OPC_SMULL: u32 = 0x9b20_7c00;
+pub const OPC_UMULL: u32 = 0x9ba0_7c00;
+/// SMULH/UMULH Xd, Xn, Xm; 64-bit only.
+pub const OPC_SMULH: u32 = 0x9b40_7c00;
+pub const OPC_UMULH: u32 = 0x9bc0_7c00;
+
+// Data processing (1 source)
+pub const OPC_RBIT: u32 = 0x5ac0_0000;
+pub const OPC_REV16: u32 = 0x5ac0_0400;
+/// REV of a W register; REV32 with `SF`.
+pub const OPC_REV32: u32 = 0x5ac0_0800;
+/// REV of an X register; only valid with `SF`.
+pub const OPC_REV64: u32 = 0x5ac0_0c00;
+pub const OPC_CLZ: u32 = 0x5ac0_1000;
+
+// SIMD moves and bit counting, for `ctpop`
+pub const OPC_FMOV_SW: u32 = 0x1e27_0000;
+pub const OPC_FMOV_DX: u32 = 0x9e67_0000;
+pub const OPC_FMOV_WS: u32 = 0x1e26_0000;
+pub const OPC_CNT_8B: u32 = 0x0e20_5800;
+pub const OPC_ADDV_8B: u32 = 0x0e31_b800;
+
+// Conditional select
+pub const OPC_CSEL: u32 = 0x1a80_0000;
+pub const OPC_CSINC: u32 = 0x1a80_0400;
+pub const OPC_CSINV: u32 = 0x5a80_0000;
+
+// Bitfield and extract
+pub const OPC_SBFM: u32 = 0x1300_0000;
+pub const OPC_BFM: u32 = 0x3300_0000;
+pub const OPC_UBFM: u32 = 0x5300_0000;
+pub const OPC_EXTR: u32 = 0x1380_0000;
+
+// Add/subtract (immediate)
+pub const OPC_ADDI: u32 = 0x1100_0000;
+pub const OPC_SUBI: u32 = 0x5100_0000;
+pub const OPC_SUBSI: u32 = 0x7100_0000;
+
+// Move wide (immediate)
+pub const OPC_MOVN: u32 = 0x1280_0000;
+pub const OPC_MOVZ: u32 = 0x5280_0000;
+pub const OPC_MOVK: u32 = 0x7280_0000;
+
+// Loads and stores; size and opc come from `LdSt`.
+pub const OPC_LDST_UIMM: u32 = 0x3900_0000;
+pub const OPC_LDST_IMM9: u32 = 0x3800_0000;
+/// `[Xn, Xm]` (extend LSL, no shift).
+pub const OPC_LDST_REG: u32 = 0x3820_6800;
+pub const OPC_LDR_LIT: u32 = 0x5800_0000;
+pub const OPC_STP: u32 = 0xa900_0000;
+pub const OPC_LDP: u32 = 0xa940_0000;
+pub const OPC_STP_PRE: u32 = 0xa980_0000;
+pub const OPC_LDP_POST: u32 = 0xa8c0_0000;
+
+// Branches
+pub const OPC_B: u32 = 0x1400_0000;
+pub const OPC_BL: u32 = 0x9400_0000;
+pub const OPC_BCOND: u32 = 0x5400_0000;
+pub const OPC_CBZ: u32 = 0xb400_0000;
+pub const OPC_BR: u32 = 0xd61f_0000;
+pub const OPC_BLR: u32 = 0xd63f_0000;
+pub const OPC_RET: u32 = 0xd65f_03c0;
+
+// System
+pub const OPC_NOP: u32 = 0xd503_201f;
+pub const OPC_DMB_ISH: u32 = 0xd503_3bbf;
+
+// -- Sub-operation enums --
+
+/// Load/store variants: access size (bits 31:30) and opc
+/// (bits 23:22) of the load/store encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LdSt {
+    Strb = 0b00_00,
+    Ldrb = 0b00_01,
+    LdrsbX = 0b00_10,
+    LdrsbW = 0b00_11,
+    Strh = 0b01_00,
+    Ldrh = 0b01_01,
+    LdrshX = 0b01_10,
+    LdrshW = 0b01_11,
+    StrW = 0b10_00,
+    LdrW = 0b10_01,
+    Ldrsw = 0b10_10,
+    StrX = 0b11_00,
+    LdrX = 0b11_01,
+}
+
+impl LdSt {
+    /// log2 of the access size in bytes.
+    pub const fn size(self) -> u32 {
+        self as u32 >> 2
+    }
+
+    /// The size and opc fields in place.
+    pub const fn bits(self) -> u32 {
+        (self.size() << 30) | ((self as u32 & 3) << 22)
+    }
+}
+
+/// AArch64 condition codes for B.cond/CSEL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum A64Cond {
+    Eq = 0x0,
+    Ne = 0x1,
+    Hs = 0x2,
+    Lo = 0x3,
+    Mi = 0x4,
+    Pl = 0x5,
+    Vs = 0x6,
+    Vc = 0x7,
+    Hi = 0x8,
+    Ls = 0x9,
+    Ge = 0xA,
+    Lt = 0xB,
+    Gt = 0xC,
+    Le = 0xD,
+    Al = 0xE,
+    Nv = 0xF,
+}
+
+impl A64Cond {
+    /// Map a TCG condition to the flag test after CMP (or TST
+    /// for the Tst conditions).
+    pub fn from_tcg(cond: tcg_core::Cond) -> Self {
+        match cond {
+            tcg_core::Cond::Eq | tcg_core::Cond::TstEq => A64Cond::Eq,
+            tcg_core::Cond::Ne | tcg_core::Cond::TstNe => A64Cond::Ne,
+            tcg_core::Cond::Lt => A64Cond::Lt,
+            tcg_core::Cond::Ge => A64Cond::Ge,
+            tcg_core::Cond::Le => A64Cond::Le,
+            tcg_core::Cond::Gt => A64Cond::Gt,
+            tcg_core::Cond::Ltu => A64Cond::Lo,
+            tcg_core::Cond::Geu => A64Cond::Hs,
+            tcg_core::Cond::Leu => A64Cond::Ls,
+            tcg_core::Cond::Gtu => A64Cond::Hi,
+            tcg_core::Cond::Always => A64Cond::Al,
+            tcg_core::Cond::Never => A64Cond::Nv,
+        }
+    }
+
+    /// Return the inverted condition.
+    pub fn invert(self) -> Self {
+        // Flip the low bit
+        unsafe { core::mem::transmute(self as u8 ^ 1) }
+    }
+}
+
+#[inline]
+fn sf(is64: bool) -> u32 {
+    if is64 {
+        SF
+    } else {
+        0
+    }
+}
+
+// -- Data processing --
+
+/// Emit a three-register instruction `opc Rd, Rn, Rm`
+/// (shifted-register, with-carry and 2-source groups).
+pub fn emit_rrr(
+    buf: &mut CodeBuffer,
+    opc: u32,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    rm: Reg,
+) {
+    buf.emit_u32(
+        opc | sf(is64) | (rm as u32) << 16 | (rn as u32) << 5 | rd as u32,
+    );
+}
+
+/// Emit a one-source instruction `opc Rd, Rn` (RBIT, REV, CLZ).
+pub fn emit_rr(buf: &mut CodeBuffer, opc: u32, is64: bool, rd: Reg, rn: Reg) {
+    buf.emit_u32(opc | sf(is64) | (rn as u32) << 5 | rd as u32);
+}
+
+/// Emit the population count of Rn into Rd through V31:
+/// `fmov`, `cnt` per byte, `addv` across bytes, `fmov` back.
+pub fn emit_ctpop(buf: &mut CodeBuffer, is64: bool, rd: Reg, rn: Reg) {
+    let v = TCG_VEC_TMP;
+    let to_vec = if is64 { OPC_FMOV_DX } else { OPC_FMOV_SW };
+    buf.emit_u32(to_vec | (rn as u32) << 5 | v);
+    buf.emit_u32(OPC_CNT_8B | v << 5 | v);
+    buf.emit_u32(OPC_ADDV_8B | v << 5 | v);
+    buf.emit_u32(OPC_FMOV_WS | v << 5 | rd as u32);
+}
+
+/// Emit MOV Rd, Rm (ORR Rd, XZR, Rm).
+pub fn emit_mov_rr(buf: &mut CodeBuffer, is64: bool, rd: Reg, rm: Reg) {
+    emit_rrr(buf, OPC_ORR, is64, rd, Reg::XZR, rm);
+}
+
+/// Emit MADD Rd, Rn, Rm, Ra (Rd = Ra + Rn * Rm).
+pub fn emit_madd(
+    buf: &mut CodeBuffer,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    rm: Reg,
+    ra: Reg,
+) {
+    buf.emit_u32(
+        OPC_MADD
+            | sf(is64)
+            | (rm as u32) << 16
+            | (ra as u32) << 10
+            | (rn as u32) << 5
+            | rd as u32,
+    );
+}
+
+/// Emit a conditional select `opc Rd, Rn, Rm, cond`.
+pub fn emit_csel(
+    buf: &mut CodeBuffer,
+    opc: u32,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    rm: Reg,
+    cond: A64Cond,
+) {
+    buf.emit_u32(
+        opc | sf(is64)
+            | (rm as u32) << 16
+            | (cond as u32) << 12
+            | (rn as u32) << 5
+            | rd as u32,
+    );
+}
+
+/// Emit CSET Rd, cond (Rd = cond ? 1 : 0).
+pub fn emit_cset(buf: &mut CodeBuffer, is64: bool, rd: Reg, cond: A64Cond) {
+    emit_csel(buf, OPC_CSINC, is64, rd, Reg::XZR, Reg::XZR, cond.invert());
+}
+
+/// Emit CSETM Rd, cond (Rd = cond ? -1 : 0).
+pub fn emit_csetm(buf: &mut CodeBuffer, is64: bool, rd: Reg, cond: A64Cond) {
+    emit_csel(buf, OPC_CSINV, is64, rd, Reg::XZR, Reg::XZR, cond.invert());
+}
+
+/// Emit CMP Rn, Rm, or TST Rn, Rm when `tst`.
+pub fn emit_cmp(buf: &mut CodeBuffer, is64: bool, tst: bool, rn: Reg, rm: Reg) {
+    let opc = if tst { OPC_ANDS } else { OPC_SUBS };
+    emit_rrr(buf, opc, is64, Reg::XZR, rn, rm);
+}
+
+/// Emit an add/subtract immediate `opc Rd, Rn, #imm12`. Rd and
+/// Rn may be SP (except for the flag-setting forms' Rd).
+pub fn emit_addsub_imm(
+    buf: &mut CodeBuffer,
+    opc: u32,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    imm: u32,
+) {
+    assert!(imm < 4096, "add/sub immediate out of range: {imm}");
+    buf.emit_u32(opc | sf(is64) | imm << 10 | (rn as u32) << 5 | rd as u32);
+}
+
+/// Emit a bitfield move `opc Rd, Rn, #immr, #imms`.
+pub fn emit_bfm(
+    buf: &mut CodeBuffer,
+    opc: u32,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    immr: u32,
+    imms: u32,
+) {
+    let n = if is64 { SF | BITFIELD_N } else { 0 };
+    buf.emit_u32(
+        opc | n | immr << 16 | imms << 10 | (rn as u32) << 5 | rd as u32,
+    );
+}
+
+/// Emit EXTR Rd, Rn, Rm, #lsb (Rd = (Rn:Rm) >> lsb).
+pub fn emit_extr(
+    buf: &mut CodeBuffer,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    rm: Reg,
+    lsb: u32,
+) {
+    let n = if is64 { SF | BITFIELD_N } else { 0 };
+    buf.emit_u32(
+        OPC_EXTR
+            | n
+            | (rm as u32) << 16
+            | lsb << 10
+            | (rn as u32) << 5
+            | rd as u32,
+    );
+}
+
+/// Emit LSR Rd, Rn, #sh.
+pub fn emit_lsr_ri(
+    buf: &mut CodeBuffer,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    sh: u32,
+) {
+    let top = if is64 { 63 } else { 31 };
+    emit_bfm(buf, OPC_UBFM, is64, rd, rn, sh, top);
+}
+
+/// Emit ASR Rd, Rn, #sh.
+pub fn emit_asr_ri(
+    buf: &mut CodeBuffer,
+    is64: bool,
+    rd: Reg,
+    rn: Reg,
+    sh: u32,
+) {
+    let top = if is64 { 63 } else { 31 };
+    emit_bfm(buf, OPC_SBFM, is64, rd, rn, sh, top);
+}
+
+/// Halfwords `emit_mov_ri` builds `val` from, and the halfword
+/// value (0 or 0xffff) its first MOVZ or MOVN leaves elsewhere.
+fn mov_ri_halves(is64: bool, val: u64) -> (&'static [usize], [u32; 4], u32) {
+    const PARTS: [usize; 4] = [0, 1, 2, 3];
+    let parts = if is64 { &PARTS[..] } else { &PARTS[..2] };
+    let halves = PARTS.map(|i| (val >> (16 * i)) as u32 & 0xffff);
+    let zeros = parts.iter().filter(|&&i| halves[i] == 0).count();
+    let ones = parts.iter().filter(|&&i| halves[i] == 0xffff).count();
+    // MOVN starts from all-ones halfwords, MOVZ from zeros.
+    let fill = if ones > zeros { 0xffff } else { 0 };
+    (parts, halves, fill)
+}
+
+/// Instructions `emit_mov_ri` takes to load `val`.
+pub fn mov_ri_len(is64: bool, val: u64) -> usize {
+    let (parts, halves, fill) = mov_ri_halves(is64, val);
+    parts.iter().filter(|&&i| halves[i] != fill).count().max(1)
+}
+
+/// Emit a load of `val` into `rd`: MOVZ or MOVN for the first
+/// halfword, MOVK for each remaining one that differs. A 32-bit
+/// load zero-extends.
+pub fn emit_mov_ri(buf: &mut CodeBuffer, is64: bool, rd: Reg, val: u64) {
+    let (parts, halves, fill) = mov_ri_halves(is64, val);
+    let first_opc = if fill == 0 { OPC_MOVZ } else { OPC_MOVN };
+    let first = parts.iter().position(|&i| halves[i] != fill).unwrap_or(0);
+    let wide = |opc: u32, hw: usize, imm: u32| {
+        opc | sf(is64) | (hw as u32) << 21 | imm << 5 | rd as u32
+    };
+    buf.emit_u32(wide(first_opc, first, halves[first] ^ fill));
+    for &i in &parts[first + 1..] {
+        if halves[i] != fill {
+            buf.emit_u32(wide(OPC_MOVK, i, halves[i]));
+        }
+    }
+}
+
+// -- Memory operations --
+
+/// Emit `op Rt, [Rn, #offset]`, using the scaled unsigned
+/// offset form, else the unscaled 9-bit form, else the offset
+/// in TMP0 as an index register.
+pub fn emit_ldst(
+    buf: &mut CodeBuffer,
+    op: LdSt,
+    rt: Reg,
+    rn: Reg,
+    offset: i64,
+) {
+    let size = op.size();
+    let scaled = offset >> size;
+    let regs = (rn as u32) << 5 | rt as u32;
+    if offset >= 0 && scaled << size == offset && scaled < 4096 {
+        let imm12 = (scaled as u32) << 10;
+        buf.emit_u32(OPC_LDST_UIMM | op.bits() | imm12 | regs);
+    } else if (-256..256).contains(&offset) {
+        let imm9 = (offset as u32 & 0x1ff) << 12;
+        buf.emit_u32(OPC_LDST_IMM9 | op.bits() | imm9 | regs);
+    } else {
+        assert_ne!(rn, TCG_REG_TMP0, "far load/store base is TMP0");
+        emit_mov_ri(buf, true, TCG_REG_TMP0, offset as u64);
+        emit_ldst_reg(buf, op, rt, rn, TCG_REG_TMP0);
+    }
+}
+
+/// Emit `op Rt, [Rn, Rm]`.
+pub fn emit_ldst_reg(
+    buf: &mut CodeBuffer,
+    op: LdSt,
+    rt: Reg,
+    rn: Reg,
+    rm: Reg,
+) {
+    buf.emit_u32(
+        OPC_LDST_REG
+            | op.bits()
+            | (rm as u32) << 16
+            | (rn as u32) << 5
+            | rt as u32,
+    );
+}
+
+/// Emit a 64-bit register-pair load/store `opc Rt, Rt2,
+/// [Rn, #offset]` (offset a multiple of 8 in -512..512).
+pub fn emit_ldst_pair(
+    buf: &mut CodeBuffer,
+    opc: u32,
+    rt: Reg,
+    rt2: Reg,
+    rn: Reg,
+    offset: i32,
+) {
+    assert!(
+        offset % 8 == 0 && (-512..512).contains(&offset),
+        "pair offset out of range: {offset}"
+    );
+    let imm7 = (offset / 8) as u32 & 0x7f;
+    buf.emit_u32(
+        opc | imm7 << 15 | (rt2 as u32) << 10 | (rn as u32) << 5 | rt as u32,
+    );
+}
+
+// -- Branches --
+
+/// Word displacement field of `bits` bits for a branch at the
+/// current offset to `target_offset`. Out-of-range values are
+/// truncated; callers that may be out of range check first.
+fn branch_field(buf: &CodeBuffer, target_offset: usize, bits: u32) -> u32 {
+    let disp = (target_offset as i64 - buf.offset() as i64) >> 2;
+    disp as u32 & ((1 << bits) - 1)
+}
+
+/// Emit B to an offset in the buffer.
+pub fn emit_b(buf: &mut CodeBuffer, target_offset: usize) {
+    let imm26 = branch_field(buf, target_offset, 26);
+    buf.emit_u32(OPC_B | imm26);
+}
+
+/// Emit B.cond to an offset in the buffer.
+pub fn emit_bcond(buf: &mut CodeBuffer, cond: A64Cond, target_offset: usize) {
+    let imm19 = branch_field(buf, target_offset, 19);
+    buf.emit_u32(OPC_BCOND | imm19 << 5 | cond as u32);
+}
+
+/// Emit CBZ Xt to an offset in the buffer.
+pub fn emit_cbz(buf: &mut CodeBuffer, rt: Reg, target_offset: usize) {
+    let imm19 = branch_field(buf, target_offset, 19);
+    buf.emit_u32(OPC_CBZ | imm19 << 5 | rt as u32);
+}
+
+/// Whether a B/BL at `from` reaches `to`.
+pub fn b_reaches(from: usize, to: usize) -> bool {
+    tcg_core::RelocKind::Imm26.fits(tcg_core::RelocKind::Imm26.disp(from, to))
+}
+
+/// Emit a jump to an offset in the buffer: B when in range,
+/// else through TMP0.
+pub fn emit_jmp(buf: &mut CodeBuffer, target_offset: usize) {
+    if b_reaches(buf.offset(), target_offset) {
+        emit_b(buf, target_offset);
+    } else {
+        let addr = buf.ptr_at(target_offset) as u64;
+        emit_mov_ri(buf, true, TCG_REG_TMP0, addr);
+        emit_br(buf, TCG_REG_TMP0);
+    }
+}
+
+/// Emit a call to an offset in the buffer: BL when in range,
+/// else BLR through TMP0.
+pub fn emit_call(buf: &mut CodeBuffer, target_offset: usize) {
+    if b_reaches(buf.offset(), target_offset) {
+        let imm26 = branch_field(buf, target_offset, 26);
+        buf.emit_u32(OPC_BL | imm26);
+    } else {
+        let addr = buf.ptr_at(target_offset) as u64;
+        emit_mov_ri(buf, true, TCG_REG_TMP0, addr);
+        emit_blr(buf, TCG_REG_TMP0);
+    }
+}
+
+/// Emit BR Xn.
+pub fn emit_br(buf: &mut CodeBuffer, rn: Reg) {
+    buf.emit_u32(OPC_BR | (rn as u32) << 5);
+}
+
+/// Emit BLR Xn.
+pub fn emit_blr(buf: &mut CodeBuffer, rn: Reg) {
+    buf.emit_u32(OPC_BLR | (rn as u32) << 5);
+}
+
+/// Emit RET.
+pub fn emit_ret(buf: &mut CodeBuffer) {
+    buf.emit_u32(OPC_RET);
+}
+
+// -- Miscellaneous --
+
+/// Emit DMB ISH (full memory barrier).
+pub fn emit_dmb(buf: &mut CodeBuffer) {
+    buf.emit_u32(OPC_DMB_ISH);
+}
+
+/// Emit `n` bytes (a multiple of 4) of NOP padding.
+pub fn emit_nops(buf: &mut CodeBuffer, n: usize) {
+    assert!(
+        n.is_multiple_of(4),
+        "AArch64 padding must be whole instructions"
+    );
+    for _ in 0..n / 4 {
+        buf.emit_u32(OPC_NOP);
+    }
+}
+
+/// Make `[start, end)` of host memory, just written as code,
+/// visible to instruction fetch.
+#[cfg(target_arch = "aarch64")]
+pub fn flush_icache_range(start: usize, end: usize) {
+    use core::arch::asm;
+    let ctr: u64;
+    // SAFETY: CTR_EL0 is readable at EL0 on Linux, and cache
+    // maintenance by VA only needs the range to be mapped.
+    unsafe {
+        asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack));
+        let dline = 4usize << ((ctr >> 16) & 0xf);
+        let mut p = start & !(dline - 1);
+        while p < end {
+            asm!("dc cvau, {}", in(reg) p, options(nostack));
+            p += dline;
+        }
+        asm!("dsb ish", options(nostack));
+        let iline = 4usize << (ctr & 0xf);
+        let mut p = start & !(iline - 1);
+        while p < end {
+            asm!("ic ivau, {}", in(reg) p, options(nostack));
+            p += iline;
+        }
+        asm!("dsb ish", "isb", options(nostack));
+    }
+}
+
+/// Other hosts build the AArch64 backend only to inspect its
+/// output, never to run it.
+#[cfg(not(target_arch = "aarch64"))]
+pub fn flush_icache_range(_start: usize, _end: usize) {}
+
+// -- Code generator state --
+
+/// AArch64 host code generator.
+pub struct Aarch64CodeGen {
+    pub prologue_offset: usize,
+    /// `mov w0, #0` falling into the epilogue: the "no TB
+    /// found" sentinel, as on x86-64.
+    pub epilogue_return_zero_offset: usize,
+    /// Epilogue proper; expects the exit value in `x0`.
+    pub tb_ret_offset: usize,
+    /// Exit stub taken when a helper call reports a panic.
+    pub helper_panic_offset: usize,
+    /// Emit the host stack check at every TB entry.
+    #[cfg(debug_assertions)]
+    pub stack_check: bool,
+    pub code_gen_start: usize,
+    /// Recorded (jmp_offset, reset_offset) for each goto_tb.
+    pub(crate) goto_tb_info: Mutex<Vec<(usize, usize)>>,
+}
+
+impl Aarch64CodeGen {
+    pub fn new() -> Self {
+        Self {
+            prologue_offset: 0,
+            epilogue_return_zero_offset: 0,
+            tb_ret_offset: 0,
+            helper_panic_offset: 0,
+            #[cfg(debug_assertions)]
+            stack_check: false,
+            code_gen_start: 0,
+            goto_tb_info: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Emit `exit_tb(val)`: load the return value into x0 and
+    /// jump to the epilogue, or straight to the zero sentinel.
+    pub fn emit_exit_tb(&self, buf: &mut CodeBuffer, val: u64) {
+        if val == 0 {
+            emit_jmp(buf, self.epilogue_return_zero_offset);
+        } else {
+            emit_mov_ri(buf, val > u32::MAX as u64, Reg::X0, val);
+            emit_jmp(buf, self.tb_ret_offset);
+        }
+    }
+
+    /// Whether a call with `flags` needs the pending-panic test
+    /// after it; see `helper::CALL_NO_PANIC`.
+    pub fn call_may_panic(&self, flags: u32) -> bool {
+        #[cfg(debug_assertions)]
+        if self.stack_check {
+            return true;
+        }
+        flags & CALL_NO_PANIC == 0
+    }
+
+    /// Emit `goto_tb(n)`, 16 bytes on an 8-byte boundary:
+    ///
+    /// ```text
+    /// jmp:    b     reset      // or b target, or ldr x16, lit
+    ///         br    x16
+    /// lit:    .quad 0          // absolute target, when far
+    /// reset:
+    /// ```
+    ///
+    /// `patch_jump` rewrites the first instruction with one
+    /// aligned store; a far target goes through the literal.
+    pub fn emit_goto_tb(&self, buf: &mut CodeBuffer) -> (usize, usize) {
+        let pad = buf.padding_to(8);
+        emit_nops(buf, pad);
+        let jmp_offset = buf.offset();
+        buf.emit_u32(OPC_B | 4);
+        emit_br(buf, TCG_REG_TMP0);
+        buf.emit_u64(0);
+        let reset_offset = buf.offset();
+        (jmp_offset, reset_offset)
+    }
+
+    /// Emit `goto_ptr(reg)`: a single `br reg`. Reaching the
+    /// epilogue this way (the zero sentinel) exits with 0.
+    pub fn emit_goto_ptr(buf: &mut CodeBuffer, reg: Reg) {
+        emit_br(buf, reg);
+    }
+}
+
+impl Default for Aarch64CodeGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
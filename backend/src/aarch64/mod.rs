@@ -0,0 +1,7 @@
+pub mod codegen;
+pub mod constraints;
+pub mod emitter;
+pub mod regs;
+
+pub use emitter::Aarch64CodeGen;
+pub use regs::Reg;
@@ -0,0 +1,143 @@
+use tcg_core::RegSet;
+
+/// AArch64 general-purpose register indices.
+///
+/// Encoding matches the 5-bit register fields. Number 31 is SP
+/// or XZR depending on the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Reg {
+    X0 = 0,
+    X1 = 1,
+    X2 = 2,
+    X3 = 3,
+    X4 = 4,
+    X5 = 5,
+    X6 = 6,
+    X7 = 7,
+    X8 = 8,
+    X9 = 9,
+    X10 = 10,
+    X11 = 11,
+    X12 = 12,
+    X13 = 13,
+    X14 = 14,
+    X15 = 15,
+    X16 = 16,
+    X17 = 17,
+    X18 = 18,
+    X19 = 19,
+    X20 = 20,
+    X21 = 21,
+    X22 = 22,
+    X23 = 23,
+    X24 = 24,
+    X25 = 25,
+    X26 = 26,
+    X27 = 27,
+    X28 = 28,
+    X29 = 29,
+    X30 = 30,
+    Sp = 31,
+}
+
+impl Reg {
+    /// The zero register, sharing SP's encoding.
+    pub const XZR: Reg = Reg::Sp;
+
+    /// Convert a raw register number (0-31) to Reg.
+    #[inline]
+    pub fn from_u8(val: u8) -> Self {
+        assert!(val < 32, "invalid register number: {val}");
+        // SAFETY: Reg is repr(u8) with variants 0..=31.
+        unsafe { core::mem::transmute(val) }
+    }
+}
+
+/// TCG_AREG0 = X19: pointer to CPUArchState (env).
+pub const TCG_AREG0: Reg = Reg::X19;
+
+/// TCG_GUEST_BASE_REG = X28: guest memory base pointer.
+pub const TCG_GUEST_BASE_REG: Reg = Reg::X28;
+
+/// Scratch registers for multi-instruction sequences, never
+/// allocated (the AAPCS64 intra-procedure-call registers).
+pub const TCG_REG_TMP0: Reg = Reg::X16;
+pub const TCG_REG_TMP1: Reg = Reg::X17;
+
+/// Scratch SIMD register (V31) for `ctpop`, which counts bits
+/// with `cnt`. Caller-saved, unlike the low halves of V8-V15.
+pub const TCG_VEC_TMP: u32 = 31;
+
+/// Callee-saved registers the prologue saves in pairs, after
+/// the frame pointer and link register.
+pub const CALLEE_SAVED: &[Reg] = &[
+    Reg::X19,
+    Reg::X20,
+    Reg::X21,
+    Reg::X22,
+    Reg::X23,
+    Reg::X24,
+    Reg::X25,
+    Reg::X26,
+    Reg::X27,
+    Reg::X28,
+];
+
+/// Function argument registers (AAPCS64).
+pub const CALL_ARG_REGS: &[Reg] = &[
+    Reg::X0,
+    Reg::X1,
+    Reg::X2,
+    Reg::X3,
+    Reg::X4,
+    Reg::X5,
+    Reg::X6,
+    Reg::X7,
+];
+
+/// Caller-saved registers a helper call may clobber: X0-X15
+/// and the scratch registers; X18 and LR are never allocated.
+pub const CALL_CLOBBERED: RegSet = RegSet::from_raw(0x3_ffff);
+
+/// Registers reserved by the backend: scratch, platform (X18),
+/// env, guest_base, frame pointer, link register and SP.
+pub const RESERVED_REGS: RegSet = RegSet::from_raw(
+    (1 << TCG_REG_TMP0 as u64)
+        | (1 << TCG_REG_TMP1 as u64)
+        | (1 << Reg::X18 as u64)
+        | (1 << TCG_AREG0 as u64)
+        | (1 << TCG_GUEST_BASE_REG as u64)
+        | (1 << Reg::X29 as u64)
+        | (1 << Reg::X30 as u64)
+        | (1 << Reg::Sp as u64),
+);
+
+/// Stack frame constants (matching the x86-64 layout).
+pub const STACK_ALIGN: usize = 16;
+/// Space reserved for outgoing call arguments on the stack.
+pub const STATIC_CALL_ARGS_SIZE: usize = 128;
+/// Number of longs in the CPU temp buffer (for spilling).
+pub const CPU_TEMP_BUF_NLONGS: usize = 128;
+
+/// Bytes of register pairs the prologue pushes: FP/LR plus
+/// the callee-saved registers.
+pub const PUSH_SIZE: usize = (2 + CALLEE_SAVED.len()) * 8;
+
+/// Frame offset (from SP after the prologue) of the pointer to
+/// the thread's helper-panic word; see `tcg_core::helper`.
+pub const HELPER_PANIC_SLOT: usize =
+    STATIC_CALL_ARGS_SIZE + CPU_TEMP_BUF_NLONGS * 8;
+
+/// Stack adjustment below the pushed registers (16-byte
+/// aligned, and small enough for one `sub sp, sp, #imm12`).
+pub const STACK_ADDEND: usize =
+    (HELPER_PANIC_SLOT + 8 + STACK_ALIGN - 1) & !(STACK_ALIGN - 1);
+
+/// Total frame size.
+pub const FRAME_SIZE: usize = PUSH_SIZE + STACK_ADDEND;
+
+/// All GPRs available for register allocation: X0-X15 and
+/// X20-X27.
+pub const ALLOCATABLE_REGS: RegSet =
+    RegSet::from_raw(0xffff_ffff & !RESERVED_REGS.raw());
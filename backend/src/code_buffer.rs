@@ -1,23 +1,65 @@
+use std::collections::HashMap;
 use std::io;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tcg_core::RelocKind;
 
 /// Default code buffer size: 16 MiB.
 const DEFAULT_CODE_BUF_SIZE: usize = 16 * 1024 * 1024;
 
+/// Largest code buffer: TBs and the epilogue reach each other
+/// with rel32 jumps, so the whole buffer must stay well inside
+/// +/-2 GiB.
+pub const MAX_CODE_BUF_SIZE: usize = 1 << 30;
+
 /// JIT code buffer backed by mmap'd memory.
 ///
 /// Manages a region of memory for writing and executing generated host code.
 /// Follows W^X discipline: the buffer is either writable
 /// or executable, never both.
+///
+/// Emitted code never holds absolute addresses inside the
+/// buffer (exits and chains are rel32), but the exec loop hands
+/// out host pointers into it, so the buffer can only grow in
+/// place, never move.
 pub struct CodeBuffer {
     ptr: *mut u8,
-    size: usize,
-    offset: usize,
+    /// Usable bytes. Atomic because the buffer grows under
+    /// translate_lock while other vCPUs read it unlocked.
+    size: AtomicUsize,
+    /// Mapped bytes; those past `size` are an inaccessible
+    /// reservation to grow into.
+    mapped: usize,
+    /// Write offset. Atomic so that unlocked readers such as
+    /// the metrics scrape can sample it during translation.
+    offset: AtomicUsize,
+    /// Constants interned since the last `emit_pool`.
+    pool: ConstPool,
+}
+
+/// A code field that loads a pool constant: the `kind` field
+/// at `offset` is patched to reach byte `slot` of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolReloc {
+    pub offset: usize,
+    pub kind: RelocKind,
+    pub slot: usize,
+}
+
+#[derive(Default)]
+struct ConstPool {
+    vals: Vec<u64>,
+    /// Value to byte slot, so equal constants share one.
+    slots: HashMap<u64, usize>,
+    relocs: Vec<PoolReloc>,
 }
 
 // SAFETY: CodeBuffer owns its mmap'd memory exclusively.
 // - emit_* methods require &mut self, serialized by translate_lock.
 // - patch_* methods use &self; aligned u32 writes are atomic.
+// - size and offset change only under translate_lock and are
+//   atomic, so &self readers may race with growth and emission.
 // - read methods (ptr_at, base_ptr) are inherently safe.
 unsafe impl Send for CodeBuffer {}
 unsafe impl Sync for CodeBuffer {}
@@ -54,11 +96,117 @@ impl CodeBuffer {
 
         Ok(Self {
             ptr: ptr as *mut u8,
-            size,
-            offset: 0,
+            size: AtomicUsize::new(size),
+            mapped: size,
+            offset: AtomicUsize::new(0),
+            pool: ConstPool::default(),
         })
     }
 
+    /// Allocate `size` usable bytes followed by inaccessible
+    /// address space up to `reserve` bytes in total, so that
+    /// `try_grow_in_place` up to `reserve` cannot fail.
+    pub fn with_reserve(size: usize, reserve: usize) -> io::Result<Self> {
+        let page_size = page_size();
+        let size = (size + page_size - 1) & !(page_size - 1);
+        let reserve = (reserve.max(size) + page_size - 1) & !(page_size - 1);
+        if size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "code buffer size must be non-zero",
+            ));
+        }
+
+        // SAFETY: anonymous private mapping; the reservation
+        // costs address space only.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                reserve,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = Self {
+            ptr: ptr as *mut u8,
+            size: AtomicUsize::new(0),
+            mapped: reserve,
+            offset: AtomicUsize::new(0),
+            pool: ConstPool::default(),
+        };
+        buf.protect_tail(size)?;
+        Ok(buf)
+    }
+
+    /// Grow the buffer to `new_size` bytes without moving it,
+    /// first into the reservation and then by extending the
+    /// mapping with `mremap` (no `MREMAP_MAYMOVE`). Fails if
+    /// the address space after the buffer is taken; the buffer
+    /// is left unchanged then.
+    pub fn try_grow_in_place(&mut self, new_size: usize) -> io::Result<()> {
+        let page_size = page_size();
+        let new_size = (new_size + page_size - 1) & !(page_size - 1);
+        if new_size <= self.capacity() {
+            return Ok(());
+        }
+        if new_size > MAX_CODE_BUF_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "code buffer would exceed rel32 reach",
+            ));
+        }
+        if new_size > self.mapped {
+            // mremap needs a single mapping: open up what is
+            // left of the reservation first.
+            self.protect_tail(self.mapped)?;
+            // SAFETY: ptr/mapped describe our own mapping; without
+            // MREMAP_MAYMOVE the address cannot change.
+            let ret = unsafe {
+                libc::mremap(
+                    self.ptr as *mut libc::c_void,
+                    self.mapped,
+                    new_size,
+                    0,
+                )
+            };
+            if ret == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            debug_assert_eq!(ret as *mut u8, self.ptr);
+            self.mapped = new_size;
+            self.size.store(new_size, Ordering::Release);
+            return Ok(());
+        }
+        self.protect_tail(new_size)
+    }
+
+    /// Make `size..new_size` of the mapping usable.
+    fn protect_tail(&mut self, new_size: usize) -> io::Result<()> {
+        let size = self.capacity();
+        if new_size <= size {
+            return Ok(());
+        }
+        // SAFETY: the range lies inside our mapping.
+        let ret = unsafe {
+            libc::mprotect(
+                self.ptr.add(size) as *mut libc::c_void,
+                new_size - size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Publish only once the new pages are accessible.
+        self.size.store(new_size, Ordering::Release);
+        Ok(())
+    }
+
     /// Allocate with the default size (16 MiB).
     pub fn with_default_size() -> io::Result<Self> {
         Self::new(DEFAULT_CODE_BUF_SIZE)
@@ -67,19 +215,19 @@ impl CodeBuffer {
     /// Current write offset.
     #[inline]
     pub fn offset(&self) -> usize {
-        self.offset
+        self.offset.load(Ordering::Acquire)
     }
 
     /// Total capacity in bytes.
     #[inline]
     pub fn capacity(&self) -> usize {
-        self.size
+        self.size.load(Ordering::Acquire)
     }
 
     /// Remaining writable bytes.
     #[inline]
     pub fn remaining(&self) -> usize {
-        self.size - self.offset
+        self.capacity() - self.offset()
     }
 
     /// Raw pointer to the start of the buffer.
@@ -92,73 +240,95 @@ impl CodeBuffer {
     #[inline]
     pub fn current_ptr(&self) -> *const u8 {
         // SAFETY: offset is always <= size.
-        unsafe { self.ptr.add(self.offset) as *const u8 }
+        unsafe { self.ptr.add(self.offset()) as *const u8 }
     }
 
     /// Pointer at a given offset.
     #[inline]
     pub fn ptr_at(&self, offset: usize) -> *const u8 {
-        assert!(offset <= self.size);
+        assert!(offset <= self.capacity());
         unsafe { self.ptr.add(offset) as *const u8 }
     }
 
     /// Set the write offset (e.g. to resume writing at a saved position).
     #[inline]
     pub fn set_offset(&mut self, offset: usize) {
-        assert!(offset <= self.size);
-        self.offset = offset;
+        assert!(offset <= *self.size.get_mut());
+        self.offset.store(offset, Ordering::Release);
+    }
+
+    /// Bytes of padding needed to reach the next multiple of
+    /// `align` (a power of two).
+    #[inline]
+    pub fn padding_to(&self, align: usize) -> usize {
+        debug_assert!(align.is_power_of_two());
+        let offset = self.offset();
+        offset.next_multiple_of(align) - offset
     }
 
     // -- Emit methods --
 
+    /// Check that `n` more bytes fit; returns the write offset.
+    #[inline]
+    fn claim(&mut self, n: usize) -> usize {
+        let off = *self.offset.get_mut();
+        assert!(off + n <= *self.size.get_mut(), "code buffer overflow");
+        off
+    }
+
+    /// Move the write offset past `n` bytes written at it. A
+    /// store rather than `get_mut`, as unlocked readers may be
+    /// loading it.
+    #[inline]
+    fn advance(&mut self, off: usize, n: usize) {
+        self.offset.store(off + n, Ordering::Release);
+    }
+
     #[inline]
     pub fn emit_u8(&mut self, val: u8) {
-        assert!(self.offset < self.size, "code buffer overflow");
-        unsafe { self.ptr.add(self.offset).write(val) };
-        self.offset += 1;
+        let off = self.claim(1);
+        unsafe { self.ptr.add(off).write(val) };
+        self.advance(off, 1);
     }
 
     #[inline]
     pub fn emit_u16(&mut self, val: u16) {
-        assert!(self.offset + 2 <= self.size, "code buffer overflow");
-        unsafe { (self.ptr.add(self.offset) as *mut u16).write_unaligned(val) };
-        self.offset += 2;
+        let off = self.claim(2);
+        unsafe { (self.ptr.add(off) as *mut u16).write_unaligned(val) };
+        self.advance(off, 2);
     }
 
     #[inline]
     pub fn emit_u32(&mut self, val: u32) {
-        assert!(self.offset + 4 <= self.size, "code buffer overflow");
-        unsafe { (self.ptr.add(self.offset) as *mut u32).write_unaligned(val) };
-        self.offset += 4;
+        let off = self.claim(4);
+        unsafe { (self.ptr.add(off) as *mut u32).write_unaligned(val) };
+        self.advance(off, 4);
     }
 
     #[inline]
     pub fn emit_u64(&mut self, val: u64) {
-        assert!(self.offset + 8 <= self.size, "code buffer overflow");
-        unsafe { (self.ptr.add(self.offset) as *mut u64).write_unaligned(val) };
-        self.offset += 8;
+        let off = self.claim(8);
+        unsafe { (self.ptr.add(off) as *mut u64).write_unaligned(val) };
+        self.advance(off, 8);
     }
 
     #[inline]
     pub fn emit_bytes(&mut self, data: &[u8]) {
-        assert!(
-            self.offset + data.len() <= self.size,
-            "code buffer overflow"
-        );
+        let off = self.claim(data.len());
         unsafe {
             ptr::copy_nonoverlapping(
                 data.as_ptr(),
-                self.ptr.add(self.offset),
+                self.ptr.add(off),
                 data.len(),
             );
         }
-        self.offset += data.len();
+        self.advance(off, data.len());
     }
 
     /// Patch a u8 at the given offset (for back-patching jumps).
     #[inline]
     pub fn patch_u8(&self, offset: usize, val: u8) {
-        assert!(offset < self.size);
+        assert!(offset < self.capacity());
         unsafe { self.ptr.add(offset).write(val) };
     }
 
@@ -170,10 +340,10 @@ impl CodeBuffer {
     /// ensure no concurrent readers for unaligned patches).
     #[inline]
     pub fn patch_u32(&self, offset: usize, val: u32) {
-        assert!(offset + 4 <= self.size);
+        assert!(offset + 4 <= self.capacity());
         let ptr = unsafe { self.ptr.add(offset) };
-        if (ptr as usize) % 4 == 0 {
-            use std::sync::atomic::{AtomicU32, Ordering};
+        if (ptr as usize).is_multiple_of(4) {
+            use std::sync::atomic::AtomicU32;
             // SAFETY: ptr is within our mmap'd region and
             // 4-byte aligned.
             let atomic = unsafe { &*(ptr as *const AtomicU32) };
@@ -186,10 +356,122 @@ impl CodeBuffer {
     /// Read a u32 at the given offset.
     #[inline]
     pub fn read_u32(&self, offset: usize) -> u32 {
-        assert!(offset + 4 <= self.size);
+        assert!(offset + 4 <= self.capacity());
         unsafe { (self.ptr.add(offset) as *const u32).read_unaligned() }
     }
 
+    /// Patch a u64 at an 8-byte aligned offset with one atomic
+    /// store, for literals read by running code.
+    #[inline]
+    pub fn patch_u64(&self, offset: usize, val: u64) {
+        assert!(offset + 8 <= self.capacity());
+        let ptr = unsafe { self.ptr.add(offset) };
+        assert!((ptr as usize).is_multiple_of(8), "unaligned u64 patch");
+        use std::sync::atomic::AtomicU64;
+        // SAFETY: ptr is within our mmap'd region and 8-byte
+        // aligned.
+        let atomic = unsafe { &*(ptr as *const AtomicU64) };
+        atomic.store(val, Ordering::Release);
+    }
+
+    /// Read a u64 at the given offset.
+    #[inline]
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        assert!(offset + 8 <= self.capacity());
+        unsafe { (self.ptr.add(offset) as *const u64).read_unaligned() }
+    }
+
+    /// Point the `kind` field at `offset` to `target`. Returns
+    /// the displacement when it does not fit.
+    pub fn patch_reloc(
+        &self,
+        kind: RelocKind,
+        offset: usize,
+        target: usize,
+    ) -> Result<(), i64> {
+        let disp = kind.disp(offset, target);
+        if !kind.fits(disp) {
+            return Err(disp);
+        }
+        let (field, mask) = match kind {
+            RelocKind::Rel32 => (disp as u32, u32::MAX),
+            RelocKind::Imm26 => ((disp >> 2) as u32 & 0x3ff_ffff, 0x3ff_ffff),
+            RelocKind::Imm19 => {
+                (((disp >> 2) as u32 & 0x7_ffff) << 5, 0xff_ffe0)
+            }
+        };
+        let old = self.read_u32(offset);
+        self.patch_u32(offset, old & !mask | field);
+        Ok(())
+    }
+
+    // -- Constant pool --
+
+    /// Intern `val` in the pending constant pool and return its
+    /// byte offset within the pool. Equal values share a slot.
+    pub fn intern_const(&mut self, val: u64) -> usize {
+        let pool = &mut self.pool;
+        *pool.slots.entry(val).or_insert_with(|| {
+            pool.vals.push(val);
+            (pool.vals.len() - 1) * 8
+        })
+    }
+
+    /// Record that the `kind` field at `offset` loads pool
+    /// byte `slot`, as returned by `intern_const`.
+    pub fn add_pool_reloc(
+        &mut self,
+        offset: usize,
+        kind: RelocKind,
+        slot: usize,
+    ) {
+        debug_assert!(slot < self.pool.vals.len() * 8);
+        self.pool.relocs.push(PoolReloc { offset, kind, slot });
+    }
+
+    /// Bytes of constants waiting for `emit_pool`.
+    pub fn pool_size(&self) -> usize {
+        self.pool.vals.len() * 8
+    }
+
+    /// Place the pending constants at the next 8-byte boundary
+    /// and patch every load of them. Does nothing when none are
+    /// pending.
+    ///
+    /// # Panics
+    /// If a load cannot reach its slot; the pool follows the
+    /// TB that uses it, so only a TB larger than the load's
+    /// reach can trigger this.
+    pub fn emit_pool(&mut self) {
+        let pool = std::mem::take(&mut self.pool);
+        if pool.vals.is_empty() {
+            return;
+        }
+        for _ in 0..self.padding_to(8) {
+            self.emit_u8(0);
+        }
+        let base = self.offset();
+        for &val in &pool.vals {
+            self.emit_u64(val);
+        }
+        for r in &pool.relocs {
+            if let Err(disp) = self.patch_reloc(r.kind, r.offset, base + r.slot)
+            {
+                panic!(
+                    "constant pool slot out of reach: {:?} at {:#x}, \
+                     disp {disp}",
+                    r.kind, r.offset
+                );
+            }
+        }
+    }
+
+    /// Drop the pending constants and their loads, as when the
+    /// code using them is abandoned.
+    pub fn discard_pool(&mut self) {
+        self.pool = ConstPool::default();
+    }
+
     // -- Permission management (W^X) --
 
     /// Make the buffer executable and non-writable.
@@ -197,7 +479,7 @@ impl CodeBuffer {
         let ret = unsafe {
             libc::mprotect(
                 self.ptr as *mut libc::c_void,
-                self.size,
+                self.capacity(),
                 libc::PROT_READ | libc::PROT_EXEC,
             )
         };
@@ -213,7 +495,7 @@ impl CodeBuffer {
         let ret = unsafe {
             libc::mprotect(
                 self.ptr as *mut libc::c_void,
-                self.size,
+                self.capacity(),
                 libc::PROT_READ | libc::PROT_WRITE,
             )
         };
@@ -227,7 +509,7 @@ impl CodeBuffer {
     /// Get the generated code as a byte slice (up to current offset).
     pub fn as_slice(&self) -> &[u8] {
         // SAFETY: ptr..ptr+offset has been written.
-        unsafe { std::slice::from_raw_parts(self.ptr, self.offset) }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.offset()) }
     }
 }
 
@@ -235,7 +517,7 @@ impl Drop for CodeBuffer {
     fn drop(&mut self) {
         if !self.ptr.is_null() {
             unsafe {
-                libc::munmap(self.ptr as *mut libc::c_void, self.size);
+                libc::munmap(self.ptr as *mut libc::c_void, self.mapped);
             }
         }
     }
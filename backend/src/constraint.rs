@@ -190,6 +190,21 @@ pub const fn o0_i1(i0: RegSet) -> OpConstraint {
     OpConstraint { args }
 }
 
+/// 2 outputs, 2 inputs.
+pub const fn o2_i2(
+    o0: RegSet,
+    o1: RegSet,
+    i0: RegSet,
+    i1: RegSet,
+) -> OpConstraint {
+    let mut args = [ArgConstraint::UNUSED; MAX_OP_ARGS];
+    args[0] = r(o0);
+    args[1] = r(o1);
+    args[2] = r(i0);
+    args[3] = r(i1);
+    OpConstraint { args }
+}
+
 /// 2 fixed outputs, 2 inputs (o0 alias i0, i1 free).
 /// For MulS2/MulU2: o0=RAX, o1=RDX, i0=RAX, i1=R.
 pub const fn o2_i2_fixed(o0_reg: u8, o1_reg: u8, i1: RegSet) -> OpConstraint {
@@ -213,6 +228,22 @@ pub const fn o2_i2_fixed(o0_reg: u8, o1_reg: u8, i1: RegSet) -> OpConstraint {
     OpConstraint { args }
 }
 
+/// 2 fixed outputs, 2 inputs outside them.
+/// For MulSU2: o0=RAX, o1=RDX, i0=R, i1=R.
+pub const fn o2_i2_fixed_out(
+    o0_reg: u8,
+    o1_reg: u8,
+    i0: RegSet,
+    i1: RegSet,
+) -> OpConstraint {
+    let mut args = [ArgConstraint::UNUSED; MAX_OP_ARGS];
+    args[0] = fixed(o0_reg);
+    args[1] = fixed(o1_reg);
+    args[2] = r(i0);
+    args[3] = r(i1);
+    OpConstraint { args }
+}
+
 /// 2 fixed outputs, 3 inputs (o0 alias i0, o1 alias i1,
 /// i2 free).
 /// For DivS2/DivU2: o0=RAX, o1=RDX, i0=RAX, i1=RDX,
@@ -280,3 +311,21 @@ pub const fn o1_i4_alias2(
     args[4] = r(i3);
     OpConstraint { args }
 }
+
+/// 1 output, 4 inputs, no alias.
+/// For MovCond on AArch64: CMP i0,i1 -> CSEL d,i2,i3.
+pub const fn o1_i4(
+    o0: RegSet,
+    i0: RegSet,
+    i1: RegSet,
+    i2: RegSet,
+    i3: RegSet,
+) -> OpConstraint {
+    let mut args = [ArgConstraint::UNUSED; MAX_OP_ARGS];
+    args[0] = r(o0);
+    args[1] = r(i0);
+    args[2] = r(i1);
+    args[3] = r(i2);
+    args[4] = r(i3);
+    OpConstraint { args }
+}
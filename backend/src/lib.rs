@@ -1,15 +1,25 @@
+pub mod aarch64;
 pub mod code_buffer;
 pub mod constraint;
 pub mod liveness;
 pub mod optimize;
+pub mod peephole;
 pub mod regalloc;
 pub mod translate;
 pub mod x86_64;
 
+pub use aarch64::Aarch64CodeGen;
 pub use code_buffer::CodeBuffer;
 pub use constraint::{ArgConstraint, OpConstraint};
+pub use translate::TranslateError;
 pub use x86_64::X86_64CodeGen;
 
+/// The code generator for the host this crate is built for.
+#[cfg(target_arch = "x86_64")]
+pub type NativeCodeGen = X86_64CodeGen;
+#[cfg(target_arch = "aarch64")]
+pub type NativeCodeGen = Aarch64CodeGen;
+
 /// Trait for host architecture code generators.
 ///
 /// Each target architecture (x86-64, AArch64, RISC-V, etc.)
@@ -37,6 +47,10 @@ pub trait HostCodeGen {
         target_offset: usize,
     );
 
+    /// Offset the direct jump at `jump_offset` currently goes
+    /// to; the inverse of `patch_jump`.
+    fn jump_target(&self, buf: &CodeBuffer, jump_offset: usize) -> usize;
+
     /// Return the offset of the TB return path.
     fn epilogue_offset(&self) -> usize;
 
@@ -47,6 +61,17 @@ pub trait HostCodeGen {
     /// Return the register constraint for an opcode.
     fn op_constraint(&self, opc: tcg_core::Opcode) -> &'static OpConstraint;
 
+    /// Host registers the allocator may hand out.
+    fn allocatable_regs(&self) -> tcg_core::RegSet;
+
+    /// Registers a helper call may clobber (caller-saved in the
+    /// host C ABI).
+    fn call_clobbered_regs(&self) -> tcg_core::RegSet;
+
+    /// Relocation of the label field `tcg_out_op` leaves in the
+    /// last 4 bytes of a `Br` or `BrCond`.
+    fn branch_reloc(&self, opc: tcg_core::Opcode) -> tcg_core::RelocKind;
+
     // -- Register allocator primitives --
 
     /// Emit host mov between two registers.
@@ -67,6 +92,19 @@ pub trait HostCodeGen {
         val: u64,
     );
 
+    /// `tcg_out_movi` that leaves the host flags alone, used
+    /// while a carry is live between a carry-out op and the
+    /// carry-in op that reads it.
+    fn tcg_out_movi_keep_flags(
+        &self,
+        buf: &mut CodeBuffer,
+        ty: tcg_core::Type,
+        dst: u8,
+        val: u64,
+    ) {
+        self.tcg_out_movi(buf, ty, dst, val);
+    }
+
     /// Emit host load from memory [base + offset] into register.
     fn tcg_out_ld(
         &self,
@@ -107,4 +145,47 @@ pub trait HostCodeGen {
 
     /// Clear recorded goto_tb offsets before a new codegen pass.
     fn clear_goto_tb_offsets(&self);
+
+    /// Make code written to `buf[start..end]` visible to
+    /// instruction fetch. Nothing to do on hosts whose caches
+    /// are coherent.
+    fn flush_icache(&self, _buf: &CodeBuffer, _start: usize, _end: usize) {}
+
+    /// Pad with host no-ops up to the next multiple of `align`
+    /// (a power of two). Returns the number of bytes emitted.
+    fn emit_align(&self, buf: &mut CodeBuffer, align: usize) -> usize;
+
+    /// Alignment for labels targeted by backward branches (loop
+    /// heads) and the most padding worth spending to reach it.
+    /// `(1, 0)` disables loop-head alignment.
+    fn loop_head_align(&self) -> (usize, usize) {
+        (1, 0)
+    }
+
+    /// Widest guest access, in bytes, the host makes as one
+    /// single-copy atomic access. Wider `MemOp::ATOM` accesses
+    /// fail translation with `TranslateError::UnsupportedAtomic`.
+    fn max_atomic_bytes(&self) -> u32;
+
+    /// Whether `tcg_out_op` can emit `opc`. Unsupported ops fail
+    /// translation with `TranslateError::UnsupportedOp`.
+    fn op_supported(&self, _opc: tcg_core::Opcode) -> bool {
+        true
+    }
+
+    /// Disassemble the host instruction at the start of `code`,
+    /// located at `pc`. Returns its text and length in bytes.
+    fn disas_insn(&self, pc: u64, code: &[u8]) -> (String, usize);
+
+    /// Emit the host stack check at the start of every TB from
+    /// now on. Debug builds only.
+    #[cfg(debug_assertions)]
+    fn set_stack_check(&mut self, on: bool);
+
+    /// Emit the TB entry check, when enabled: leave with
+    /// `TB_EXIT_STACK_OVERFLOW` if the stack pointer is below
+    /// the limit stored `tcg_core::helper::STACK_LIMIT_OFFSET`
+    /// bytes past the helper-panic word.
+    #[cfg(debug_assertions)]
+    fn emit_stack_check(&self, buf: &mut CodeBuffer);
 }
@@ -1,14 +1,30 @@
 use tcg_core::op::LifeData;
 use tcg_core::temp::TempKind;
-use tcg_core::{Context, OpFlags, Opcode, OPCODE_DEFS};
+use tcg_core::types::{Type, TYPE_COUNT};
+use tcg_core::{Context, OpFlags, Opcode, TempIdx, OPCODE_DEFS};
+
+pub use tcg_core::pressure::LivenessReport;
 
 /// Perform backward liveness analysis over the IR ops in `ctx`.
 ///
 /// Sets `LifeData` on each op indicating which arguments are
 /// dead after the op and which need to be synced to memory.
+/// Pure ops whose results are all dead EBB temps, or TB temps
+/// no op reads, are turned into `Nop`. Their inputs are not
+/// counted as read, so the ops feeding only them go too.
 pub fn liveness_analysis(ctx: &mut Context) {
+    // Dropping the last read of a TB temp frees the ops writing
+    // it, which a loop may place after the read.
+    while liveness_pass(ctx) {}
+}
+
+/// One backward walk of `liveness_analysis()`. Returns whether
+/// it dropped the last read of some TB temp.
+fn liveness_pass(ctx: &mut Context) -> bool {
     let nb_temps = ctx.nb_temps() as usize;
     let nb_globals = ctx.nb_globals() as usize;
+    let mut reads = tb_reads(ctx);
+    let mut again = false;
 
     // temp_state[i] = true means temp i is live
     let mut temp_state = vec![false; nb_temps];
@@ -22,8 +38,9 @@ pub fn liveness_analysis(ctx: &mut Context) {
 
     // Walk ops in reverse
     for oi in (0..num_ops).rev() {
-        let op = ctx.ops()[oi].clone();
-        let def = &OPCODE_DEFS[op.opc as usize];
+        let op = &ctx.ops()[oi];
+        let (idx, opc, args) = (op.idx, op.opc, op.args);
+        let def = &OPCODE_DEFS[opc as usize];
         let flags = def.flags;
 
         // At BB_END, mark all globals live
@@ -35,17 +52,33 @@ pub fn liveness_analysis(ctx: &mut Context) {
 
         // Skip ops that don't produce host code and have
         // no liveness impact beyond BB_END handling above.
-        if op.opc == Opcode::Nop || op.opc == Opcode::InsnStart {
+        if opc == Opcode::Nop || opc == Opcode::InsnStart {
             continue;
         }
 
-        let mut life = LifeData(0);
         let nb_oargs = def.nb_oargs as usize;
         let nb_iargs = def.nb_iargs as usize;
 
+        if is_removable(ctx, opc, &args, &temp_state, &reads) {
+            for t in &args[nb_oargs..nb_oargs + nb_iargs] {
+                if let Some(n) = reads.get_mut(t.0 as usize) {
+                    if *n > 0 {
+                        *n -= 1;
+                        again |= *n == 0;
+                    }
+                }
+            }
+            let op_mut = ctx.op_mut(idx);
+            op_mut.opc = Opcode::Nop;
+            op_mut.nargs = 0;
+            continue;
+        }
+
+        let mut life = LifeData(0);
+
         // Process output args
-        for i in 0..nb_oargs {
-            let tidx = op.args[i].0 as usize;
+        for (i, t) in args[..nb_oargs].iter().enumerate() {
+            let tidx = t.0 as usize;
             if tidx < nb_temps && !temp_state[tidx] {
                 life.set_dead(i as u32);
             }
@@ -57,7 +90,7 @@ pub fn liveness_analysis(ctx: &mut Context) {
         // Process input args
         for i in 0..nb_iargs {
             let arg_pos = nb_oargs + i;
-            let tidx = op.args[arg_pos].0 as usize;
+            let tidx = args[arg_pos].0 as usize;
             if tidx >= nb_temps {
                 continue;
             }
@@ -65,8 +98,7 @@ pub fn liveness_analysis(ctx: &mut Context) {
                 // Last use — mark dead
                 life.set_dead(arg_pos as u32);
                 // If global, needs sync before death
-                let kind = ctx.temp(tcg_core::TempIdx(tidx as u32)).kind;
-                if kind == TempKind::Global {
+                if ctx.temps()[tidx].kind == TempKind::Global {
                     life.set_sync(arg_pos as u32);
                 }
             }
@@ -74,7 +106,125 @@ pub fn liveness_analysis(ctx: &mut Context) {
         }
 
         // Store computed life data back
-        let op_mut = ctx.op_mut(op.idx);
-        op_mut.life = life;
+        ctx.op_mut(idx).life = life;
+    }
+    again
+}
+
+/// Number of op inputs reading each TB temp, 0 for other temps.
+fn tb_reads(ctx: &Context) -> Vec<u32> {
+    let mut reads = vec![0; ctx.nb_temps() as usize];
+    for op in ctx.ops() {
+        for t in op.iargs() {
+            if ctx.temp_kind(*t) == TempKind::Tb {
+                reads[t.0 as usize] += 1;
+            }
+        }
+    }
+    reads
+}
+
+/// Whether `op` only computes values nobody reads: dead EBB
+/// temps, or TB temps with no `reads` left. Globals, and TB
+/// temps the backward walk finds dead, may still be read after
+/// a branch it has not seen yet.
+fn is_removable(
+    ctx: &Context,
+    opc: Opcode,
+    args: &[TempIdx],
+    live: &[bool],
+    reads: &[u32],
+) -> bool {
+    let def = &OPCODE_DEFS[opc as usize];
+    let keep = OpFlags::SIDE_EFFECTS
+        .union(OpFlags::BB_END)
+        .union(OpFlags::BB_EXIT)
+        .union(OpFlags::CALL_CLOBBER)
+        .union(OpFlags::CARRY_OUT)
+        .union(OpFlags::CARRY_IN)
+        .union(OpFlags::NOT_PRESENT)
+        .union(OpFlags::VECTOR);
+    if def.nb_oargs == 0
+        || (opc != Opcode::Mov && def.flags.bits() & keep.bits() != 0)
+    {
+        return false;
+    }
+    args[..def.nb_oargs as usize].iter().all(|&t| {
+        let i = t.0 as usize;
+        match ctx.temp_kind(t) {
+            TempKind::Ebb => i < live.len() && !live[i],
+            TempKind::Tb => reads.get(i) == Some(&0),
+            _ => false,
+        }
+    })
+}
+
+/// Forward pass over the `LifeData` computed by
+/// `liveness_analysis()` measuring how many temps the allocator
+/// must keep in registers at each op, counting spill sites
+/// against the host's `nb_regs` allocatable registers.
+pub fn pressure_report(ctx: &Context, nb_regs: u32) -> LivenessReport {
+    let nb_temps = ctx.nb_temps() as usize;
+    let mut resident = vec![false; nb_temps];
+    let mut live = [0u32; TYPE_COUNT];
+    let mut report = LivenessReport {
+        timeline: vec![0; ctx.num_ops()],
+        ..Default::default()
+    };
+
+    for (oi, op) in ctx.ops().iter().enumerate() {
+        if op.opc == Opcode::Nop || op.opc == Opcode::InsnStart {
+            continue;
+        }
+        let def = &OPCODE_DEFS[op.opc as usize];
+        let nargs = (def.nb_oargs + def.nb_iargs) as usize;
+        let counted = |i: usize| {
+            let tidx = op.args[i];
+            if tidx.0 as usize >= nb_temps {
+                return None;
+            }
+            let t = ctx.temp(tidx);
+            match t.kind {
+                TempKind::Const | TempKind::Fixed => None,
+                _ => Some((tidx.0 as usize, t.ty as usize)),
+            }
+        };
+
+        for i in 0..nargs {
+            if let Some((t, ty)) = counted(i) {
+                if !resident[t] {
+                    resident[t] = true;
+                    live[ty] += 1;
+                }
+            }
+        }
+
+        let total: u32 = live.iter().sum();
+        report.timeline[oi] = total;
+        for (max, &n) in report.max_live_by_type.iter_mut().zip(&live) {
+            *max = (*max).max(n);
+        }
+        if total > report.max_live {
+            report.max_live = total;
+            report.max_op = Some(oi);
+        }
+        // An I128 temp is two I64 halves, counted as I64.
+        let int_regs = live[Type::I32 as usize] + live[Type::I64 as usize];
+        if int_regs > nb_regs {
+            report.spill_sites += 1;
+        }
+
+        for i in 0..nargs {
+            if !op.life.is_dead(i as u32) {
+                continue;
+            }
+            if let Some((t, ty)) = counted(i) {
+                if resident[t] {
+                    resident[t] = false;
+                    live[ty] -= 1;
+                }
+            }
+        }
     }
+    report
 }
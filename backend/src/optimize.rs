@@ -1,11 +1,17 @@
-// TCG IR optimizer — single-pass constant folding, copy propagation,
-// algebraic simplification. Runs before liveness analysis.
+// TCG IR optimizer — single-pass constant folding and propagation,
+// copy propagation, algebraic simplification and local value
+// numbering (CSE), after an if-conversion prepass over short branch
+// diamonds. Runs before liveness analysis, which deletes the ops
+// folding and CSE leave dead.
 //
 // Reference: ~/qemu/tcg/optimize.c
 
-use tcg_core::op::OpIdx;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use tcg_core::op::{Op, OpIdx};
 use tcg_core::opcode::{OpFlags, Opcode};
-use tcg_core::temp::TempIdx;
+use tcg_core::temp::{TempIdx, TempKind};
 use tcg_core::types::{Cond, Type};
 use tcg_core::Context;
 
@@ -70,20 +76,53 @@ fn cond_from_carg(t: TempIdx) -> Cond {
     }
 }
 
-/// Main optimizer entry point.
+/// Optimizer knobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// Rewrite short branch diamonds into `movcond` selects.
+    pub if_convert: bool,
+    /// Most ops either arm of a diamond may hold. Both arms run
+    /// unconditionally once converted.
+    pub if_convert_max_ops: usize,
+    /// Fuse adjacent op pairs after optimizing (`peephole`).
+    pub peephole: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            if_convert: true,
+            if_convert_max_ops: 4,
+            peephole: true,
+        }
+    }
+}
+
+/// Main optimizer entry point, with default options.
 pub fn optimize(ctx: &mut Context) {
+    optimize_with(ctx, &OptimizeOptions::default());
+}
+
+pub fn optimize_with(ctx: &mut Context, opts: &OptimizeOptions) {
+    if opts.if_convert {
+        if_convert(ctx, opts.if_convert_max_ops);
+    }
+    if !has_opportunities(ctx) {
+        return;
+    }
     let n_temps = ctx.nb_temps() as usize;
     let mut info: Vec<TempInfo> = vec![TempInfo::default(); n_temps];
 
     // Seed const info from existing const temps.
     for (i, ti) in info.iter_mut().enumerate().take(n_temps) {
-        let t = ctx.temp(TempIdx(i as u32));
-        if t.is_const() {
+        if let Some(val) = ctx.temp_const_val(TempIdx(i as u32)) {
             ti.is_const = true;
-            ti.val = t.val;
+            ti.val = val;
         }
     }
 
+    let mut cse = ValueTable::default();
+
     let num_ops = ctx.num_ops();
     for oi in 0..num_ops {
         let op_idx = OpIdx(oi as u32);
@@ -105,9 +144,35 @@ pub fn optimize(ctx: &mut Context) {
                 | Opcode::Call
         ) {
             invalidate_outputs(&mut info, def, &args, ctx);
-            reset_copies(&mut info);
+            reset_info(&mut info, ctx);
+            cse.clear();
             continue;
         }
+        if opc == Opcode::Mb {
+            cse.clear();
+        }
+
+        // --- Copy and constant propagation on inputs ---
+        // Also into side-effecting ops, so guest loads and
+        // stores pick up addresses shared by CSE. A temp known
+        // constant is read as its constant, leaving the op that
+        // computed it dead.
+        if !def.flags.contains(OpFlags::VECTOR) {
+            let iarg_start = def.nb_oargs as usize;
+            let iarg_end = iarg_start + def.nb_iargs as usize;
+            for (slot, &tidx) in args[iarg_start..iarg_end].iter().enumerate() {
+                let known = ti(&info, tidx);
+                let src = if let Some(src) = resolve_copy(&info, tidx) {
+                    src
+                } else if known.is_const && !ctx.temp(tidx).is_const() {
+                    let ty = ctx.temp_type(tidx);
+                    const_temp(ctx, &mut info, ty, known.val)
+                } else {
+                    continue;
+                };
+                ctx.op_mut(op_idx).args[iarg_start + slot] = src;
+            }
+        }
 
         // Skip ops we don't optimize, but still invalidate
         // their outputs so stale info doesn't leak.
@@ -118,20 +183,13 @@ pub fn optimize(ctx: &mut Context) {
             || opc == Opcode::Discard
         {
             invalidate_outputs(&mut info, def, &args, ctx);
+            kill_outputs(&mut cse, def, &args);
             continue;
         }
 
-        // --- Copy propagation on inputs ---
-        let iarg_start = def.nb_oargs as usize;
-        let iarg_end = iarg_start + def.nb_iargs as usize;
-        for (slot, &tidx) in args[iarg_start..iarg_end].iter().enumerate() {
-            if let Some(src) = resolve_copy(&info, tidx) {
-                ctx.op_mut(op_idx).args[iarg_start + slot] = src;
-            }
-        }
-
         // Re-read args after copy propagation.
         let args = ctx.op(op_idx).args;
+        let key = ExprKey::new(&info, opc, op_type, &args);
 
         // --- Per-opcode optimization ---
         match opc {
@@ -168,7 +226,502 @@ pub fn optimize(ctx: &mut Context) {
                 invalidate_outputs(&mut info, def, &args, ctx);
             }
         }
+
+        // --- Value numbering ---
+        // Only ops that folding left untouched are numbered;
+        // anything it rewrote just kills its outputs.
+        kill_outputs(&mut cse, def, &args);
+        let op = ctx.op(op_idx);
+        let (Some(key), true) = (key, op.opc == opc) else {
+            continue;
+        };
+        let dst = args[0];
+        if let Some(prev) = cse.lookup(&key) {
+            let op = ctx.op_mut(op_idx);
+            op.opc = Opcode::Mov;
+            op.args[0] = dst;
+            op.args[1] = prev;
+            op.nargs = 2;
+            invalidate_one(&mut info, dst);
+            set_copy(&mut info, dst, prev);
+        } else if !key.reads(dst) {
+            cse.insert(key, dst);
+        }
+    }
+}
+
+// ---- Fast path ----
+
+/// Temps tracked by the `has_opportunities` scan; contexts with
+/// more always run the full pass.
+const SCAN_TEMPS: usize = 512;
+
+/// Cheap forward scan for anything `optimize` could rewrite: a
+/// read of a temp some `mov` wrote (copy or constant
+/// propagation), an op the folders would change, or a pure
+/// opcode seen twice (value numbering). Most small TBs have
+/// none, and skipping the pass for them leaves the IR exactly
+/// as the pass would.
+fn has_opportunities(ctx: &Context) -> bool {
+    let n_temps = ctx.nb_temps() as usize;
+    if n_temps > SCAN_TEMPS {
+        return true;
+    }
+    let mut moved = [0u64; SCAN_TEMPS / 64];
+    let mut seen = [0u64; (Opcode::Count as usize).div_ceil(64)];
+    let bit = |set: &[u64], i: usize| set[i / 64] & (1 << (i % 64)) != 0;
+    let temps = ctx.temps();
+    for op in ctx.ops() {
+        let opc = op.opc;
+        let def = opc.def();
+        if def.flags.contains(OpFlags::VECTOR) {
+            continue;
+        }
+        let oargs = &op.args[..def.nb_oargs as usize];
+        let iargs = op.iargs();
+        let mut consts = 0;
+        for &t in iargs {
+            let i = t.0 as usize;
+            if bit(&moved, i) {
+                return true;
+            }
+            if temps[i].is_const() {
+                consts += 1;
+                if folds_with(opc, temps[i].val & type_mask(op.op_type)) {
+                    return true;
+                }
+            }
+        }
+        if is_foldable(opc) && !iargs.is_empty() && consts == iargs.len() {
+            return true;
+        }
+        if iargs.len() == 2 && iargs[0] == iargs[1] && is_simplifiable(opc) {
+            return true;
+        }
+        if is_pure(opc) {
+            if bit(&seen, opc as usize) {
+                return true;
+            }
+            seen[opc as usize / 64] |= 1 << (opc as usize % 64);
+        }
+        if opc == Opcode::Mov {
+            for &t in oargs {
+                moved[t.0 as usize / 64] |= 1 << (t.0 % 64);
+            }
+        }
     }
+    false
+}
+
+// --- If-conversion ---
+//
+//   brcond c1, c2, cond, L          <A> into fresh temps
+//   <A>                             <B> into fresh temps
+//   br M                      =>    d = cond(c1, c2) ? b_d : a_d
+//   set_label L                     ...
+//   <B>
+//   set_label M
+//
+// Both arms must be pure, short, and write the same non-EBB
+// temps; EBB temps die at M. L and M must have no other users.
+// The rewrite is in place: dropping the brcond, br and two labels
+// leaves room for up to four selects, the rest becomes Nop.
+
+/// Selects (plus a copy of a compared temp) a diamond can hold.
+const IF_CONVERT_SLOTS: usize = 4;
+
+struct Diamond {
+    /// The brcond.
+    start: usize,
+    arm_a: Range<usize>,
+    arm_b: Range<usize>,
+    /// The set_label of M.
+    end: usize,
+    dests: Vec<TempIdx>,
+}
+
+fn if_convert(ctx: &mut Context, max_ops: usize) {
+    if !ctx.ops().iter().any(|op| op.opc == Opcode::BrCond) {
+        return;
+    }
+    let refs = label_refs(ctx);
+    let mut oi = 0;
+    while oi < ctx.num_ops() {
+        match match_diamond(ctx, oi, max_ops, &refs) {
+            Some(d) => {
+                rewrite_diamond(ctx, &d);
+                oi = d.end + 1;
+            }
+            None => oi += 1,
+        }
+    }
+}
+
+/// Branches to each label.
+fn label_refs(ctx: &Context) -> Vec<u32> {
+    let mut refs = vec![0; ctx.labels().len()];
+    for op in ctx.ops() {
+        match op.opc {
+            Opcode::Br => refs[op.args[0].0 as usize] += 1,
+            Opcode::BrCond => refs[op.cargs()[1].0 as usize] += 1,
+            _ => {}
+        }
+    }
+    refs
+}
+
+fn match_diamond(
+    ctx: &Context,
+    start: usize,
+    max_ops: usize,
+    refs: &[u32],
+) -> Option<Diamond> {
+    let ops = ctx.ops();
+    let brc = &ops[start];
+    if brc.opc != Opcode::BrCond {
+        return None;
+    }
+    let l = brc.cargs()[1].0;
+    let a_end = arm_end(ops, start + 1, max_ops)?;
+    let br = &ops[a_end];
+    if br.opc != Opcode::Br || refs[l as usize] != 1 {
+        return None;
+    }
+    let m = br.args[0].0;
+    if m == l || refs[m as usize] != 1 || !sets_label(ops, a_end + 1, l) {
+        return None;
+    }
+    let b_end = arm_end(ops, a_end + 2, max_ops)?;
+    if !sets_label(ops, b_end, m) {
+        return None;
+    }
+    let (arm_a, arm_b) = (start + 1..a_end, a_end + 2..b_end);
+    let dests = arm_dests(ctx, &arm_a);
+    if dests != arm_dests(ctx, &arm_b)
+        || dests.iter().any(|&d| ctx.temp_type(d) != brc.op_type)
+    {
+        return None;
+    }
+    let (c1, c2) = (brc.args[0], brc.args[1]);
+    let copy = c1 != c2 && dests.contains(&c1) && dests.contains(&c2);
+    if dests.len() + copy as usize > IF_CONVERT_SLOTS {
+        return None;
+    }
+    Some(Diamond {
+        start,
+        arm_a,
+        arm_b,
+        end: b_end,
+        dests,
+    })
+}
+
+/// End of the arm starting at `start`: the first op that may
+/// not run unconditionally. Division can trap, so it is out.
+fn arm_end(ops: &[Op], start: usize, max_ops: usize) -> Option<usize> {
+    let mut n = 0;
+    for (oi, op) in ops.iter().enumerate().skip(start) {
+        if op.opc == Opcode::Nop {
+            continue;
+        }
+        if !is_pure(op.opc) && op.opc != Opcode::Mov {
+            return Some(oi);
+        }
+        n += 1;
+        if n > max_ops {
+            return None;
+        }
+    }
+    None
+}
+
+fn sets_label(ops: &[Op], oi: usize, label: u32) -> bool {
+    ops.get(oi)
+        .is_some_and(|op| op.opc == Opcode::SetLabel && op.args[0].0 == label)
+}
+
+/// Non-EBB temps written by `arm`, sorted.
+fn arm_dests(ctx: &Context, arm: &Range<usize>) -> Vec<TempIdx> {
+    let mut dests: Vec<TempIdx> = ctx.ops()[arm.clone()]
+        .iter()
+        .flat_map(|op| op.oargs().iter().copied())
+        .filter(|&t| ctx.temp_kind(t) != TempKind::Ebb)
+        .collect();
+    dests.sort_by_key(|t| t.0);
+    dests.dedup();
+    dests
+}
+
+fn rewrite_diamond(ctx: &mut Context, d: &Diamond) {
+    let brc = ctx.op(OpIdx(d.start as u32)).clone();
+    let ty = brc.op_type;
+    let (mut c1, c2, cond) = (brc.args[0], brc.args[1], brc.args[2]);
+    let mut out = Vec::new();
+    if c1 != c2 && d.dests.contains(&c1) && d.dests.contains(&c2) {
+        let t = ctx.new_temp(ty);
+        out.push(Op::with_args(OpIdx(0), Opcode::Mov, ty, &[t, c1]));
+        c1 = t;
+    }
+    let a = rename_arm(ctx, &d.arm_a, &mut out);
+    let b = rename_arm(ctx, &d.arm_b, &mut out);
+    // A select that overwrites a compared temp goes last.
+    let mut dests = d.dests.clone();
+    dests.sort_by_key(|&t| t == c1 || t == c2);
+    for t in dests {
+        let args = [t, c1, c2, b[&t], a[&t], cond];
+        out.push(Op::with_args(OpIdx(0), Opcode::MovCond, ty, &args));
+    }
+    let mut out = out.into_iter();
+    for oi in d.start..=d.end {
+        let idx = OpIdx(oi as u32);
+        let mut op =
+            out.next().unwrap_or_else(|| Op::new(idx, Opcode::Nop, ty));
+        op.idx = idx;
+        *ctx.op_mut(idx) = op;
+    }
+}
+
+/// Append the ops of `arm` to `out` with every output renamed
+/// to a fresh temp; returns the renaming.
+fn rename_arm(
+    ctx: &mut Context,
+    arm: &Range<usize>,
+    out: &mut Vec<Op>,
+) -> HashMap<TempIdx, TempIdx> {
+    let mut map = HashMap::new();
+    for oi in arm.clone() {
+        let mut op = ctx.ops()[oi].clone();
+        if op.opc == Opcode::Nop {
+            continue;
+        }
+        let def = op.opc.def();
+        let (no, ni) = (def.nb_oargs as usize, def.nb_iargs as usize);
+        for t in &mut op.args[no..no + ni] {
+            if let Some(&r) = map.get(t) {
+                *t = r;
+            }
+        }
+        for t in &mut op.args[..no] {
+            let ty = ctx.temp_type(*t);
+            *t = *map.entry(*t).or_insert_with(|| ctx.new_temp(ty));
+        }
+        out.push(op);
+    }
+    map
+}
+
+/// Ops the folders evaluate when every input is constant.
+fn is_foldable(opc: Opcode) -> bool {
+    matches!(
+        opc,
+        Opcode::Neg
+            | Opcode::Not
+            | Opcode::ExtI32I64
+            | Opcode::ExtUI32I64
+            | Opcode::ExtrlI64I32
+            | Opcode::ExtrhI64I32
+            | Opcode::BrCond
+    ) || is_simplifiable(opc)
+}
+
+/// Binary ops `try_simplify` and the same-operand identities
+/// apply to.
+fn is_simplifiable(opc: Opcode) -> bool {
+    matches!(
+        opc,
+        Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::AndC
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar
+            | Opcode::RotL
+            | Opcode::RotR
+    )
+}
+
+/// Whether a constant operand `val` (masked to the op type) may
+/// let `try_simplify` rewrite `opc`: 0, all ones, or 1 for `Mul`.
+/// Checks both operand positions, so may answer yes when the
+/// simplification does not apply.
+fn folds_with(opc: Opcode, val: u64) -> bool {
+    is_simplifiable(opc)
+        && (val == 0
+            || val == u32::MAX as u64
+            || val == u64::MAX
+            || (opc == Opcode::Mul && val == 1))
+}
+
+// ---- Local value numbering ----
+
+/// Input of a value-numbered op. Temps are canonical after copy
+/// propagation; known constants compare by value.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    None,
+    Const(u64),
+    Temp(TempIdx),
+    Carg(u32),
+}
+
+/// A pure computation: same key, same result.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ExprKey {
+    opc: Opcode,
+    ty: Type,
+    args: [Operand; tcg_core::MAX_OP_ARGS],
+}
+
+impl ExprKey {
+    /// Key for `opc`, or `None` if its result may differ
+    /// between two evaluations on the same inputs.
+    fn new(
+        info: &[TempInfo],
+        opc: Opcode,
+        ty: Type,
+        args: &[TempIdx; tcg_core::MAX_OP_ARGS],
+    ) -> Option<Self> {
+        if !is_pure(opc) {
+            return None;
+        }
+        let def = opc.def();
+        if def.nb_oargs != 1 {
+            return None;
+        }
+        let mask = type_mask(ty);
+        let nb_i = def.nb_iargs as usize;
+        let nb_c = def.nb_cargs as usize;
+        let mut ops = [Operand::None; tcg_core::MAX_OP_ARGS];
+        for (i, &t) in args[1..1 + nb_i].iter().enumerate() {
+            let ti = ti(info, t);
+            ops[i] = if ti.is_const {
+                Operand::Const(ti.val & mask)
+            } else {
+                Operand::Temp(t)
+            };
+        }
+        for (i, &c) in args[1 + nb_i..1 + nb_i + nb_c].iter().enumerate() {
+            ops[nb_i + i] = Operand::Carg(c.0);
+        }
+        if is_commutative(opc) && ops[1].rank() < ops[0].rank() {
+            ops.swap(0, 1);
+        }
+        Some(Self { opc, ty, args: ops })
+    }
+
+    fn reads(&self, t: TempIdx) -> bool {
+        self.args.contains(&Operand::Temp(t))
+    }
+}
+
+impl Operand {
+    /// Total order used to canonicalize commutative inputs.
+    fn rank(&self) -> (u8, u64) {
+        match *self {
+            Operand::None => (0, 0),
+            Operand::Const(v) => (1, v),
+            Operand::Temp(t) => (2, t.0 as u64),
+            Operand::Carg(c) => (3, c as u64),
+        }
+    }
+}
+
+/// Available expressions in the current extended basic block.
+#[derive(Default)]
+struct ValueTable {
+    avail: HashMap<ExprKey, TempIdx>,
+}
+
+impl ValueTable {
+    fn lookup(&self, key: &ExprKey) -> Option<TempIdx> {
+        self.avail.get(key).copied()
+    }
+
+    fn insert(&mut self, key: ExprKey, dst: TempIdx) {
+        self.avail.insert(key, dst);
+    }
+
+    /// `t` is being redefined: forget what it holds and every
+    /// expression computed from it.
+    fn kill(&mut self, t: TempIdx) {
+        self.avail.retain(|k, &mut v| v != t && !k.reads(t));
+    }
+
+    fn clear(&mut self) {
+        self.avail.clear();
+    }
+}
+
+fn kill_outputs(
+    cse: &mut ValueTable,
+    def: &tcg_core::OpDef,
+    args: &[TempIdx; tcg_core::MAX_OP_ARGS],
+) {
+    for &t in args.iter().take(def.nb_oargs as usize) {
+        cse.kill(t);
+    }
+}
+
+/// Ops without side effects whose result depends only on
+/// their operands.
+fn is_pure(opc: Opcode) -> bool {
+    matches!(
+        opc,
+        Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::MulSH
+            | Opcode::MulUH
+            | Opcode::Neg
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Not
+            | Opcode::AndC
+            | Opcode::OrC
+            | Opcode::Eqv
+            | Opcode::Nand
+            | Opcode::Nor
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar
+            | Opcode::RotL
+            | Opcode::RotR
+            | Opcode::Extract
+            | Opcode::SExtract
+            | Opcode::Deposit
+            | Opcode::Extract2
+            | Opcode::SetCond
+            | Opcode::NegSetCond
+            | Opcode::MovCond
+            | Opcode::ExtI32I64
+            | Opcode::ExtUI32I64
+            | Opcode::ExtrlI64I32
+            | Opcode::ExtrhI64I32
+            | Opcode::Clz
+            | Opcode::Ctz
+            | Opcode::CtPop
+    )
+}
+
+fn is_commutative(opc: Opcode) -> bool {
+    matches!(
+        opc,
+        Opcode::Add
+            | Opcode::Mul
+            | Opcode::MulSH
+            | Opcode::MulUH
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Eqv
+            | Opcode::Nand
+            | Opcode::Nor
+    )
 }
 
 // ---- Helper functions ----
@@ -183,10 +736,13 @@ fn resolve_copy(info: &[TempInfo], tidx: TempIdx) -> Option<TempIdx> {
     }
 }
 
-/// Reset all copy relationships (at BB boundaries).
-fn reset_copies(info: &mut [TempInfo]) {
-    for ti in info.iter_mut() {
+/// Forget all copies and every value but the constant temps' (at
+/// BB boundaries): another path may reach a label with different
+/// values, and a helper may write globals.
+fn reset_info(info: &mut [TempInfo], ctx: &Context) {
+    for (i, ti) in info.iter_mut().enumerate() {
         ti.copy_of = None;
+        ti.is_const = ctx.temp(TempIdx(i as u32)).is_const();
     }
 }
 
@@ -225,7 +781,7 @@ fn ti(info: &[TempInfo], tidx: TempIdx) -> TempInfo {
 /// Record that `dst` is now a known constant.
 fn set_const(info: &mut Vec<TempInfo>, dst: TempIdx, val: u64) {
     let i = dst.0 as usize;
-    ensure_info(info, i);
+    invalidate_one(info, dst);
     info[i].is_const = true;
     info[i].val = val;
     info[i].copy_of = None;
@@ -233,8 +789,12 @@ fn set_const(info: &mut Vec<TempInfo>, dst: TempIdx, val: u64) {
 
 /// Record that `dst` is a copy of `src`.
 fn set_copy(info: &mut Vec<TempInfo>, dst: TempIdx, src: TempIdx) {
+    if dst == src {
+        return;
+    }
     let i = dst.0 as usize;
-    ensure_info(info, i);
+    // Temps copied from dst no longer alias it.
+    invalidate_one(info, dst);
     let si = ti(info, src);
     if si.is_const {
         info[i].is_const = true;
@@ -252,6 +812,20 @@ fn ensure_info(info: &mut Vec<TempInfo>, idx: usize) {
     }
 }
 
+/// The constant temp for `val`, known to `info`.
+fn const_temp(
+    ctx: &mut Context,
+    info: &mut Vec<TempInfo>,
+    ty: Type,
+    val: u64,
+) -> TempIdx {
+    let c = ctx.new_const(ty, val);
+    ensure_info(info, c.0 as usize);
+    info[c.0 as usize].is_const = true;
+    info[c.0 as usize].val = val;
+    c
+}
+
 /// Replace op with `mov dst, const_val`.
 fn replace_with_const(
     ctx: &mut Context,
@@ -262,11 +836,7 @@ fn replace_with_const(
     ty: Type,
 ) {
     let masked = val & type_mask(ty);
-    let c = ctx.new_const(ty, masked);
-    // Ensure info covers the new const temp.
-    ensure_info(info, c.0 as usize);
-    info[c.0 as usize].is_const = true;
-    info[c.0 as usize].val = masked;
+    let c = const_temp(ctx, info, ty, masked);
 
     let op = ctx.op_mut(op_idx);
     op.opc = Opcode::Mov;
@@ -291,11 +861,9 @@ fn replace_with_mov(
     op.args[1] = src;
     op.nargs = 2;
 
-    // Conservative: just invalidate dst. We don't track
-    // the copy relationship here because the source temp
-    // may be redefined later in the same EBB, and our
-    // invalidation doesn't propagate to derived const info.
-    invalidate_one(info, dst);
+    // Readers of dst now read src, leaving the mov dead unless
+    // dst is a global. Redefining src ends the copy.
+    set_copy(info, dst, src);
 }
 
 // ---- Per-opcode fold functions ----
@@ -549,11 +1117,31 @@ fn try_simplify(
     if ai.is_const {
         let a = ai.val & mask;
         match opc {
-            // 0 + x → mov x
-            Opcode::Add if a == 0 => {
+            // 0 + x, 0 | x, 0 ^ x → mov x (RISC-V reads of x0)
+            Opcode::Add | Opcode::Or | Opcode::Xor if a == 0 => {
+                replace_with_mov(ctx, info, op_idx, dst, b_idx);
+                return true;
+            }
+            // 1 * x, -1 & x → mov x
+            Opcode::Mul if a == 1 => {
                 replace_with_mov(ctx, info, op_idx, dst, b_idx);
                 return true;
             }
+            Opcode::And if a == all_ones => {
+                replace_with_mov(ctx, info, op_idx, dst, b_idx);
+                return true;
+            }
+            // 0 << x, 0 >> x, 0 >>> x, 0 rotated → mov 0
+            Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar
+            | Opcode::RotL
+            | Opcode::RotR
+                if a == 0 =>
+            {
+                replace_with_const(ctx, info, op_idx, dst, 0, ty);
+                return true;
+            }
             // 0 - x → neg x (strength reduction)
             Opcode::Sub if a == 0 => {
                 let op = ctx.op_mut(op_idx);
@@ -0,0 +1,125 @@
+//! Peephole fusion of adjacent IR op pairs, run after the
+//! optimizer and before liveness.
+
+use tcg_core::{Cond, Context, OpIdx, Opcode, TempIdx, TempKind};
+
+/// Ops that cannot fault or touch anything but their operands,
+/// so nothing can observe a temp between a `mov` to it and one
+/// of these overwriting it.
+fn overwrites_quietly(opc: Opcode) -> bool {
+    matches!(
+        opc,
+        Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::AndC
+            | Opcode::OrC
+            | Opcode::Eqv
+            | Opcode::Nand
+            | Opcode::Nor
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar
+            | Opcode::RotL
+            | Opcode::RotR
+            | Opcode::Neg
+            | Opcode::Not
+            | Opcode::SetCond
+            | Opcode::NegSetCond
+    )
+}
+
+/// How many ops read each temp.
+fn read_counts(ctx: &Context) -> Vec<u32> {
+    let mut reads = vec![0; ctx.nb_temps() as usize];
+    for op in ctx.ops() {
+        for &TempIdx(t) in op.iargs() {
+            reads[t as usize] += 1;
+        }
+    }
+    reads
+}
+
+/// Fuse adjacent op pairs:
+///
+/// - `mov d, a; op d, ..d..` → `op d, ..a..`: the copy is dead
+///   once `op` overwrites `d`. Liveness keeps stores to
+///   globals, so this is what drops one overwritten at once.
+/// - `sub t, a, b; setcond d, t, 0, eq|ne` →
+///   `setcond d, a, b, eq|ne` when nothing else reads `t`,
+///   so x86-64 emits `cmp a, b; sete` without the `sub`.
+pub fn peephole(ctx: &mut Context) {
+    let reads = read_counts(ctx);
+    let mut prev: Option<usize> = None;
+    for j in 0..ctx.num_ops() {
+        let opc = ctx.ops()[j].opc;
+        if opc == Opcode::Nop {
+            continue;
+        }
+        if let Some(i) = prev {
+            match ctx.ops()[i].opc {
+                Opcode::Mov => fuse_mov(ctx, i, j),
+                Opcode::Sub => fuse_sub_setcond(ctx, &reads, i, j),
+                _ => {}
+            }
+        }
+        prev = Some(j);
+    }
+}
+
+fn fuse_mov(ctx: &mut Context, i: usize, j: usize) {
+    let mov = &ctx.ops()[i];
+    let (d, a) = (mov.args[0], mov.args[1]);
+    let op = &ctx.ops()[j];
+    if !overwrites_quietly(op.opc)
+        || op.oargs() != [d]
+        || op.op_type != mov.op_type
+        || ctx.temp(d).is_fixed()
+    {
+        return;
+    }
+    let op = ctx.op_mut(OpIdx(j as u32));
+    let nb_oargs = op.oargs().len();
+    let nb_iargs = op.iargs().len();
+    for t in &mut op.args[nb_oargs..nb_oargs + nb_iargs] {
+        if *t == d {
+            *t = a;
+        }
+    }
+    let mov = ctx.op_mut(OpIdx(i as u32));
+    mov.opc = Opcode::Nop;
+    mov.nargs = 0;
+}
+
+fn fuse_sub_setcond(ctx: &mut Context, reads: &[u32], i: usize, j: usize) {
+    let sub = &ctx.ops()[i];
+    let (t, a, b) = (sub.args[0], sub.args[1], sub.args[2]);
+    let op = &ctx.ops()[j];
+    if op.opc != Opcode::SetCond
+        || op.op_type != sub.op_type
+        || !matches!(ctx.temp_kind(t), TempKind::Ebb | TempKind::Tb)
+        || reads[t.0 as usize] != 1
+        || t == a
+        || t == b
+    {
+        return;
+    }
+    let cond = op.cargs()[0].0;
+    if cond != Cond::Eq as u32 && cond != Cond::Ne as u32 {
+        return;
+    }
+    let is_zero = |x: TempIdx| ctx.temp_const_val(x) == Some(0);
+    let (x, y) = (op.args[1], op.args[2]);
+    if !(x == t && is_zero(y) || y == t && is_zero(x)) {
+        return;
+    }
+    let op = ctx.op_mut(OpIdx(j as u32));
+    op.args[1] = a;
+    op.args[2] = b;
+    let sub = ctx.op_mut(OpIdx(i as u32));
+    sub.opc = Opcode::Nop;
+    sub.nargs = 0;
+}
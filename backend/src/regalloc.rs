@@ -1,25 +1,116 @@
 use crate::code_buffer::CodeBuffer;
 use crate::constraint::OpConstraint;
+use crate::translate::TranslateError;
 use crate::HostCodeGen;
-use tcg_core::label::RelocKind;
+use tcg_core::label::{LabelSite, RelocKind};
 use tcg_core::temp::TempKind;
-use tcg_core::types::{RegSet, TempVal};
-use tcg_core::{Context, OpFlags, Opcode, TempIdx, OPCODE_DEFS};
+use tcg_core::types::{MemOp, RegSet, TempVal};
+use tcg_core::{Context, Op, OpFlags, OpIdx, Opcode, TempIdx, OPCODE_DEFS};
+
+/// How the allocator picks a register to spill when none of
+/// the candidates is free.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegAllocMode {
+    /// Spill the lowest-numbered candidate.
+    #[default]
+    Greedy,
+    /// Spill the candidate whose next read lies furthest ahead
+    /// (Belady's rule), from the read sites of every temp
+    /// collected before allocation. Not linear scan: registers
+    /// are still assigned op by op, as in `Greedy`.
+    FurthestUse,
+}
+
+/// The ops that read each temp. Globals and constants are
+/// reloaded after every label, so for them a read past the next
+/// `set_label` does not count.
+struct NextReads {
+    reads: Vec<Vec<u32>>,
+    next_label: Vec<u32>,
+}
+
+impl NextReads {
+    fn new(ctx: &Context) -> Self {
+        let mut reads = vec![Vec::new(); ctx.nb_temps() as usize];
+        let mut next_label = vec![u32::MAX; ctx.num_ops()];
+        for (oi, op) in ctx.ops().iter().enumerate() {
+            for &TempIdx(t) in op.iargs() {
+                reads[t as usize].push(oi as u32);
+            }
+        }
+        let mut label = u32::MAX;
+        for (oi, op) in ctx.ops().iter().enumerate().rev() {
+            next_label[oi] = label;
+            if op.opc == Opcode::SetLabel {
+                label = oi as u32;
+            }
+        }
+        Self { reads, next_label }
+    }
+
+    /// Op index of the next read of `tidx` at or after `oi`, or
+    /// `u32::MAX` if its value is not read again.
+    fn next_read(&self, ctx: &Context, tidx: TempIdx, oi: usize) -> u32 {
+        let reads = &self.reads[tidx.0 as usize];
+        let Some(&next) = reads.get(reads.partition_point(|&r| r < oi as u32))
+        else {
+            return u32::MAX;
+        };
+        let temp = ctx.temp(tidx);
+        if (temp.kind == TempKind::Global || temp.is_const())
+            && next > self.next_label[oi]
+        {
+            return u32::MAX;
+        }
+        next
+    }
+}
 
 /// Register allocator state.
 struct RegAllocState {
-    reg_to_temp: [Option<TempIdx>; 16],
+    reg_to_temp: [Option<TempIdx>; 32],
     free_regs: RegSet,
     allocatable: RegSet,
+    /// Set in `RegAllocMode::FurthestUse`.
+    next_reads: Option<NextReads>,
+    /// Index of the op being allocated.
+    cur_op: usize,
+    /// A carry-out op ran and its carry-in reader has not.
+    carry_live: bool,
 }
 
 impl RegAllocState {
     fn new(allocatable: RegSet) -> Self {
         Self {
-            reg_to_temp: [None; 16],
+            reg_to_temp: [None; 32],
             free_regs: allocatable,
             allocatable,
+            next_reads: None,
+            cur_op: 0,
+            carry_live: false,
+        }
+    }
+
+    /// The register among the occupied `candidates` to spill.
+    /// Ties go to the lowest-numbered register.
+    fn victim(&self, ctx: &Context, candidates: RegSet) -> Option<u8> {
+        let Some(nr) = &self.next_reads else {
+            return candidates.first();
+        };
+        let mut best: Option<(u8, u32)> = None;
+        for reg in 0..self.reg_to_temp.len() as u8 {
+            if !candidates.contains(reg) {
+                continue;
+            }
+            let next = match self.reg_to_temp[reg as usize] {
+                Some(tidx) => nr.next_read(ctx, tidx, self.cur_op),
+                None => u32::MAX,
+            };
+            if best.is_none_or(|(_, b)| next > b) {
+                best = Some((reg, next));
+            }
         }
+        best.map(|(reg, _)| reg)
     }
 
     fn free_reg(&mut self, reg: u8) {
@@ -76,6 +167,10 @@ fn evict_reg(
 /// `preferred`. Evicts an occupant if necessary. If all required
 /// registers are forbidden (e.g. fixed constraint conflicts with
 /// a prior input), evict the forbidden occupant first.
+///
+/// Every choice takes the lowest-numbered candidate, or in
+/// `RegAllocMode::FurthestUse` the spill victim furthest from
+/// its next read, so the emitted code depends only on the IR.
 fn reg_alloc(
     ctx: &mut Context,
     state: &mut RegAllocState,
@@ -97,7 +192,7 @@ fn reg_alloc(
         return r;
     }
     // Try evicting a non-forbidden occupant
-    if let Some(r) = candidates.first() {
+    if let Some(r) = state.victim(ctx, candidates) {
         evict_reg(ctx, state, backend, buf, r);
         return r;
     }
@@ -150,7 +245,11 @@ fn temp_load_to(
                 ctx, state, backend, buf, required, forbidden, preferred,
             );
             state.assign(reg, tidx);
-            backend.tcg_out_movi(buf, ty, reg, val);
+            if state.carry_live {
+                backend.tcg_out_movi_keep_flags(buf, ty, reg, val);
+            } else {
+                backend.tcg_out_movi(buf, ty, reg, val);
+            }
             let t = ctx.temp_mut(tidx);
             t.val_type = TempVal::Reg;
             t.reg = Some(reg);
@@ -226,6 +325,37 @@ fn sync_globals(
     }
 }
 
+/// Drop the register copies of globals after `sync_globals`.
+/// A label can be reached by a jump from a point where they sat
+/// in other registers, so code after it must reload them.
+fn release_globals(ctx: &mut Context, state: &mut RegAllocState) {
+    for i in 0..ctx.nb_globals() {
+        let t = ctx.temp_mut(TempIdx(i));
+        if t.kind != TempKind::Global || t.val_type != TempVal::Reg {
+            continue;
+        }
+        if let Some(reg) = t.reg.take() {
+            state.free_reg(reg);
+        }
+        t.val_type = TempVal::Mem;
+    }
+}
+
+/// Forget the registers constants were loaded into. The path
+/// that loaded one may not be the path that reached the label.
+fn release_consts(ctx: &mut Context, state: &mut RegAllocState) {
+    for i in ctx.nb_globals()..ctx.nb_temps() {
+        let t = ctx.temp_mut(TempIdx(i));
+        if t.kind != TempKind::Const || t.val_type != TempVal::Reg {
+            continue;
+        }
+        if let Some(reg) = t.reg.take() {
+            state.free_reg(reg);
+        }
+        t.val_type = TempVal::Const;
+    }
+}
+
 /// Dedicated register allocation for Call ops.
 ///
 /// Unlike `regalloc_op`, this function:
@@ -250,8 +380,7 @@ fn regalloc_call(
     let nb_cargs = def.nb_cargs as usize;
     let life = op.life;
 
-    // x86-64 System V caller-saved registers.
-    const CALLER_SAVED: [u8; 9] = [0, 1, 2, 6, 7, 8, 9, 10, 11];
+    let clobbered = backend.call_clobbered_regs();
 
     // 1. Sync all globals to memory (helper reads
     //    CPU state via env pointer).
@@ -259,7 +388,10 @@ fn regalloc_call(
 
     // 2. Spill any live local temps in caller-saved
     //    regs (they will be clobbered by the call).
-    for &reg in &CALLER_SAVED {
+    for reg in 0..state.reg_to_temp.len() as u8 {
+        if !clobbered.contains(reg) {
+            continue;
+        }
         if let Some(tidx) = state.reg_to_temp[reg as usize] {
             let temp = ctx.temp(tidx);
             if !temp.is_global_or_fixed() {
@@ -324,7 +456,10 @@ fn regalloc_call(
     }
 
     // 5. Clobber all caller-saved registers.
-    for &reg in &CALLER_SAVED {
+    for reg in 0..state.reg_to_temp.len() as u8 {
+        if !clobbered.contains(reg) {
+            continue;
+        }
         if let Some(tidx) = state.reg_to_temp[reg as usize] {
             let temp = ctx.temp(tidx);
             if temp.is_global_or_fixed() {
@@ -338,13 +473,18 @@ fn regalloc_call(
     }
 
     // 6. Collect cargs and emit call.
-    let cstart = nb_oargs + nb_iargs;
-    let cargs: Vec<u32> =
-        (0..nb_cargs).map(|i| op.args[cstart + i].0).collect();
+    let cargs = const_args(op, nb_oargs + nb_iargs, nb_cargs);
     let out_reg = ct.args[0].regs.first().unwrap();
-    backend.tcg_out_op(buf, ctx, op, &[out_reg], &i_regs[..nb_iargs], &cargs);
+    backend.tcg_out_op(
+        buf,
+        ctx,
+        op,
+        &[out_reg],
+        &i_regs[..nb_iargs],
+        &cargs[..nb_cargs],
+    );
 
-    // 7. Assign output to return register (RAX).
+    // 7. Assign output to the return register.
     let dst_tidx = op.args[0];
     state.assign(out_reg, dst_tidx);
     let t = ctx.temp_mut(dst_tidx);
@@ -358,6 +498,19 @@ fn regalloc_call(
     }
 }
 
+/// The `n` constant args of `op` starting at `start`.
+fn const_args(
+    op: &tcg_core::Op,
+    start: usize,
+    n: usize,
+) -> [u32; tcg_core::MAX_OP_ARGS] {
+    let mut cargs = [0; tcg_core::MAX_OP_ARGS];
+    for (c, a) in cargs.iter_mut().zip(&op.args[start..start + n]) {
+        *c = a.0;
+    }
+    cargs
+}
+
 /// Free a temp's register if it's dead after this op.
 fn temp_dead(ctx: &mut Context, state: &mut RegAllocState, tidx: TempIdx) {
     let temp = ctx.temp(tidx);
@@ -549,9 +702,20 @@ fn regalloc_op(
         o_allocated = o_allocated.set(reg);
     }
 
-    // Fixup: outputs may have evicted/moved inputs.
+    // Fixup: outputs may have evicted/moved inputs. An input
+    // that is also an output now names the output register,
+    // while the op must still read the old value.
     for i in 0..nb_iargs {
         let tidx = op.args[nb_oargs + i];
+        if op.args[..nb_oargs].contains(&tidx) {
+            let old = i_regs[i];
+            if ctx.temp(tidx).reg != Some(old)
+                && state.reg_to_temp[old as usize] == Some(tidx)
+            {
+                state.free_reg(old);
+            }
+            continue;
+        }
         let temp = ctx.temp(tidx);
         if temp.val_type == TempVal::Reg {
             if let Some(reg) = temp.reg {
@@ -561,9 +725,7 @@ fn regalloc_op(
     }
 
     // 3. Collect constant args
-    let cstart = nb_oargs + nb_iargs;
-    let cargs: Vec<u32> =
-        (0..nb_cargs).map(|i| op.args[cstart + i].0).collect();
+    let cargs = const_args(op, nb_oargs + nb_iargs, nb_cargs);
 
     // 4. Emit host code
     backend.tcg_out_op(
@@ -572,7 +734,7 @@ fn regalloc_op(
         op,
         &o_regs[..nb_oargs],
         &i_regs[..nb_iargs],
-        &cargs,
+        &cargs[..nb_cargs],
     );
 
     // 5. Free dead inputs
@@ -624,14 +786,114 @@ fn regalloc_op(
     }
 }
 
+/// Check that a backward branch just emitted (relocated field
+/// in the last 4 bytes) reached its label.
+fn check_backward_branch(
+    buf: &CodeBuffer,
+    kind: RelocKind,
+    label: u32,
+    target: usize,
+    site: LabelSite,
+) -> Result<(), TranslateError> {
+    let disp = kind.disp(buf.offset() - 4, target);
+    if kind.fits(disp) {
+        Ok(())
+    } else {
+        Err(TranslateError::RelocOverflow { label, site, disp })
+    }
+}
+
+/// Emit the branch `op` (`Br` or `BrCond`, inputs already in
+/// `iregs`) and record a use of its label if not yet placed.
+fn emit_branch(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+    op: &Op,
+    iregs: &[u8],
+    cargs: &[u32],
+    site: LabelSite,
+) -> Result<(), TranslateError> {
+    let label_id = cargs[(op.opc == Opcode::BrCond) as usize];
+    let label = ctx.label(label_id);
+    let resolved = label.has_value;
+    let target = label.value;
+    backend.tcg_out_op(buf, ctx, op, &[], iregs, cargs);
+    let kind = backend.branch_reloc(op.opc);
+    if resolved {
+        check_backward_branch(buf, kind, label_id, target, site)
+    } else {
+        let patch_off = buf.offset() - 4;
+        ctx.label_mut(label_id).add_use(patch_off, kind, site);
+        Ok(())
+    }
+}
+
+/// Labels that some later branch jumps back to (loop heads).
+fn find_loop_heads(ctx: &Context) -> Vec<bool> {
+    let mut placed = vec![false; ctx.labels().len()];
+    let mut heads = vec![false; ctx.labels().len()];
+    for op in ctx.ops() {
+        let def = &OPCODE_DEFS[op.opc as usize];
+        let cstart = (def.nb_oargs + def.nb_iargs) as usize;
+        match op.opc {
+            Opcode::SetLabel => placed[op.args[0].0 as usize] = true,
+            Opcode::Br => {
+                let l = op.args[0].0 as usize;
+                heads[l] |= placed[l];
+            }
+            Opcode::BrCond => {
+                let l = op.args[cstart + 1].0 as usize;
+                heads[l] |= placed[l];
+            }
+            _ => {}
+        }
+    }
+    heads
+}
+
+/// Refuse an atomic guest access `backend` cannot make in one
+/// piece.
+fn check_atomic(
+    op: &Op,
+    backend: &impl HostCodeGen,
+    site: LabelSite,
+) -> Result<(), TranslateError> {
+    let pair = match op.opc {
+        Opcode::QemuLd | Opcode::QemuSt => false,
+        Opcode::QemuLd2 | Opcode::QemuSt2 => true,
+        _ => return Ok(()),
+    };
+    let memop = MemOp::new(op.cargs()[0].0 as u16);
+    let bytes = memop.size_bytes() << pair as u32;
+    let max = backend.max_atomic_bytes();
+    if memop.is_atomic() && bytes > max {
+        return Err(TranslateError::UnsupportedAtomic { bytes, max, site });
+    }
+    Ok(())
+}
+
 /// Main register allocation + code generation pass.
 pub fn regalloc_and_codegen(
     ctx: &mut Context,
     backend: &impl HostCodeGen,
     buf: &mut CodeBuffer,
-) {
-    let allocatable = crate::x86_64::regs::ALLOCATABLE_REGS;
+) -> Result<(), TranslateError> {
+    regalloc_and_codegen_with(ctx, backend, buf, RegAllocMode::default())
+}
+
+/// `regalloc_and_codegen()` with an explicit allocator mode.
+pub fn regalloc_and_codegen_with(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+    mode: RegAllocMode,
+) -> Result<(), TranslateError> {
+    let allocatable = backend.allocatable_regs();
     let mut state = RegAllocState::new(allocatable);
+    if mode == RegAllocMode::FurthestUse {
+        state.next_reads = Some(NextReads::new(ctx));
+    }
 
     // Initialize fixed temps (always in their register)
     let nb_globals = ctx.nb_globals();
@@ -645,14 +907,35 @@ pub fn regalloc_and_codegen(
         }
     }
 
+    let (loop_align, loop_max_pad) = backend.loop_head_align();
+    let loop_heads = if loop_align > 1 {
+        find_loop_heads(ctx)
+    } else {
+        Vec::new()
+    };
+
     let num_ops = ctx.num_ops();
+    let mut cur_pc = None;
     for oi in 0..num_ops {
         let op = ctx.ops()[oi].clone();
+        state.cur_op = oi;
         let def = &OPCODE_DEFS[op.opc as usize];
         let flags = def.flags;
+        let site = LabelSite {
+            op: OpIdx(oi as u32),
+            opc: op.opc,
+            pc: cur_pc,
+        };
+        check_atomic(&op, backend, site)?;
 
         match op.opc {
-            Opcode::Nop | Opcode::InsnStart => continue,
+            Opcode::Nop => continue,
+
+            Opcode::InsnStart => {
+                let pc = op.args[0].0 as u64 | (op.args[1].0 as u64) << 32;
+                cur_pc = Some(pc);
+                continue;
+            }
 
             Opcode::Mov => {
                 let dst_idx = op.args[0];
@@ -696,16 +979,32 @@ pub fn regalloc_and_codegen(
             Opcode::SetLabel => {
                 let label_id = op.args[0].0;
                 sync_globals(ctx, backend, buf);
+                release_globals(ctx, &mut state);
+                release_consts(ctx, &mut state);
+                if loop_heads.get(label_id as usize) == Some(&true)
+                    && buf.padding_to(loop_align) <= loop_max_pad
+                {
+                    backend.emit_align(buf, loop_align);
+                }
                 let offset = buf.offset();
                 let label = ctx.label_mut(label_id);
+                if let Some(first) = label.bound_at {
+                    return Err(TranslateError::LabelRebound {
+                        label: label_id,
+                        first,
+                        second: site,
+                    });
+                }
                 label.set_value(offset);
-                let uses: Vec<_> = label.uses.drain(..).collect();
-                for u in uses {
-                    match u.kind {
-                        RelocKind::Rel32 => {
-                            let disp = (offset as i64) - (u.offset as i64 + 4);
-                            buf.patch_u32(u.offset, disp as u32);
-                        }
+                label.bound_at = Some(site);
+                for u in std::mem::take(&mut label.uses) {
+                    if let Err(disp) = buf.patch_reloc(u.kind, u.offset, offset)
+                    {
+                        return Err(TranslateError::RelocOverflow {
+                            label: label_id,
+                            site: u.site,
+                            disp,
+                        });
                     }
                 }
             }
@@ -713,25 +1012,15 @@ pub fn regalloc_and_codegen(
             Opcode::Br => {
                 let label_id = op.args[0].0;
                 sync_globals(ctx, backend, buf);
-                let label = ctx.label(label_id);
-                if label.has_value {
-                    crate::x86_64::emitter::emit_jmp(buf, label.value);
-                } else {
-                    buf.emit_u8(0xE9);
-                    let patch_off = buf.offset();
-                    buf.emit_u32(0);
-                    ctx.label_mut(label_id)
-                        .add_use(patch_off, RelocKind::Rel32);
-                }
+                emit_branch(ctx, backend, buf, &op, &[], &[label_id], site)?;
             }
 
             Opcode::ExitTb | Opcode::GotoTb => {
                 sync_globals(ctx, backend, buf);
                 let nb_cargs = def.nb_cargs as usize;
                 let cstart = (def.nb_oargs + def.nb_iargs) as usize;
-                let cargs: Vec<u32> =
-                    (0..nb_cargs).map(|i| op.args[cstart + i].0).collect();
-                backend.tcg_out_op(buf, ctx, &op, &[], &[], &cargs);
+                let cargs = const_args(&op, cstart, nb_cargs);
+                backend.tcg_out_op(buf, ctx, &op, &[], &[], &cargs[..nb_cargs]);
             }
 
             Opcode::Call => {
@@ -740,8 +1029,10 @@ pub fn regalloc_and_codegen(
             }
 
             Opcode::GotoPtr => {
-                // Load input register, sync globals,
-                // then emit indirect jump.
+                // Load input register, sync globals, then emit
+                // the indirect jump. The target may clobber every
+                // register, the input included, so a still-live
+                // input is spilled rather than left in `reg`.
                 let ct = backend.op_constraint(op.opc);
                 let tidx = op.args[0];
                 let arg_ct = &ct.args[0];
@@ -755,18 +1046,20 @@ pub fn regalloc_and_codegen(
                     RegSet::EMPTY,
                     RegSet::EMPTY,
                 );
-                let life = op.life;
-                if life.is_dead(0) {
+                sync_globals(ctx, backend, buf);
+                if op.life.is_dead(0) {
                     temp_dead(ctx, &mut state, tidx);
+                } else if !ctx.temp(tidx).is_fixed() {
+                    evict_reg(ctx, &mut state, backend, buf, reg);
                 }
-                sync_globals(ctx, backend, buf);
                 backend.tcg_out_op(buf, ctx, &op, &[], &[reg], &[]);
             }
 
             Opcode::Mb => {
                 // NP (NOT_PRESENT): no register allocation,
                 // emit directly.
-                crate::x86_64::emitter::emit_mfence(buf);
+                let cargs = [op.args[0].0];
+                backend.tcg_out_op(buf, ctx, &op, &[], &[], &cargs);
             }
 
             Opcode::BrCond => {
@@ -776,9 +1069,9 @@ pub fn regalloc_and_codegen(
                 let nb_cargs = def.nb_cargs as usize;
                 let life = op.life;
 
-                let mut iregs = Vec::new();
+                let mut iregs = [0u8; 2];
                 let mut i_allocated = RegSet::EMPTY;
-                for i in 0..nb_iargs {
+                for (i, ireg) in iregs[..nb_iargs].iter_mut().enumerate() {
                     let tidx = op.args[nb_oargs + i];
                     let arg_ct = &ct.args[nb_oargs + i];
                     let reg = temp_load_to(
@@ -791,13 +1084,11 @@ pub fn regalloc_and_codegen(
                         i_allocated,
                         RegSet::EMPTY,
                     );
-                    iregs.push(reg);
+                    *ireg = reg;
                     i_allocated = i_allocated.set(reg);
                 }
 
-                let cstart = nb_oargs + nb_iargs;
-                let cargs: Vec<u32> =
-                    (0..nb_cargs).map(|i| op.args[cstart + i].0).collect();
+                let cargs = const_args(&op, nb_oargs + nb_iargs, nb_cargs);
 
                 for i in 0..nb_iargs {
                     let arg_pos = (nb_oargs + i) as u32;
@@ -809,26 +1100,40 @@ pub fn regalloc_and_codegen(
 
                 sync_globals(ctx, backend, buf);
 
-                let label_id = cargs[1];
-                let label = ctx.label(label_id);
-                let label_resolved = label.has_value;
-
-                backend.tcg_out_op(buf, ctx, &op, &[], &iregs, &cargs);
-
-                if !label_resolved {
-                    let patch_off = buf.offset() - 4;
-                    ctx.label_mut(label_id)
-                        .add_use(patch_off, RelocKind::Rel32);
-                }
+                emit_branch(
+                    ctx,
+                    backend,
+                    buf,
+                    &op,
+                    &iregs[..nb_iargs],
+                    &cargs[..nb_cargs],
+                    site,
+                )?;
             }
 
             _ => {
+                if !backend.op_supported(op.opc) {
+                    return Err(TranslateError::UnsupportedOp { site });
+                }
                 let ct = backend.op_constraint(op.opc);
                 regalloc_op(ctx, &mut state, backend, buf, &op, ct);
                 if flags.contains(OpFlags::BB_END) {
                     sync_globals(ctx, backend, buf);
                 }
+                if flags.contains(OpFlags::CARRY_OUT) {
+                    state.carry_live = true;
+                } else if flags.contains(OpFlags::CARRY_IN) {
+                    state.carry_live = false;
+                }
             }
         }
     }
+
+    if let Some(label) = ctx.unresolved_labels().next() {
+        return Err(TranslateError::UnboundLabel {
+            label: label.id,
+            site: label.uses[0].site,
+        });
+    }
+    Ok(())
 }
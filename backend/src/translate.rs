@@ -1,22 +1,184 @@
 use crate::code_buffer::CodeBuffer;
 use crate::liveness::liveness_analysis;
-use crate::optimize::optimize;
-use crate::regalloc::regalloc_and_codegen;
+use crate::optimize::{optimize_with, OptimizeOptions};
+use crate::peephole::peephole;
+use crate::regalloc::{regalloc_and_codegen_with, RegAllocMode};
 use crate::HostCodeGen;
-use tcg_core::Context;
+use std::fmt;
+use tcg_core::{Context, IrError, LabelSite};
 
-/// Full translation pipeline: optimize → liveness → regalloc+codegen.
+/// Failure to turn IR into host code. The IR and the code
+/// emitted so far are unusable; the caller must discard both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranslateError {
+    /// A branch targets a label that no `set_label` placed.
+    UnboundLabel { label: u32, site: LabelSite },
+    /// A label was placed by two `set_label` ops.
+    LabelRebound {
+        label: u32,
+        first: LabelSite,
+        second: LabelSite,
+    },
+    /// A branch displacement does not fit its encoding.
+    RelocOverflow {
+        label: u32,
+        site: LabelSite,
+        disp: i64,
+    },
+    /// An atomic guest access wider than the host can make in
+    /// one piece.
+    UnsupportedAtomic {
+        bytes: u32,
+        max: u32,
+        site: LabelSite,
+    },
+    /// An op the host backend has no lowering for.
+    UnsupportedOp { site: LabelSite },
+    /// `Context::validate` rejected the IR before any pass ran.
+    InvalidIr(IrError),
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnboundLabel { label, site } => {
+                write!(f, "unresolved label L{label} used by {site}")
+            }
+            Self::LabelRebound {
+                label,
+                first,
+                second,
+            } => write!(
+                f,
+                "label L{label} bound twice: first by {first}, again by {second}"
+            ),
+            Self::RelocOverflow { label, site, disp } => write!(
+                f,
+                "branch to label L{label} from {site} out of range \
+                 (displacement {disp:#x})"
+            ),
+            Self::UnsupportedAtomic { bytes, max, site } => write!(
+                f,
+                "atomic {bytes}-byte access by {site}: host atomics are at \
+                 most {max} bytes"
+            ),
+            Self::UnsupportedOp { site } => {
+                write!(f, "{site}: not supported by this host backend")
+            }
+            Self::InvalidIr(e) => write!(f, "invalid IR: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TranslateError {}
+
+/// Full translation pipeline: optimize → peephole → liveness →
+/// regalloc+codegen.
 /// Returns the offset where TB code starts in the buffer.
+///
+/// Debug builds, and release builds with the `validate-ir`
+/// feature, first run `Context::validate`.
 pub fn translate(
     ctx: &mut Context,
     backend: &impl HostCodeGen,
     buf: &mut CodeBuffer,
-) -> usize {
-    optimize(ctx);
+) -> Result<usize, TranslateError> {
+    translate_with(ctx, backend, buf, RegAllocMode::default())
+}
+
+/// `translate()` with an explicit register allocator mode.
+pub fn translate_with(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+    mode: RegAllocMode,
+) -> Result<usize, TranslateError> {
+    #[cfg(any(debug_assertions, feature = "validate-ir"))]
+    match ctx.validate() {
+        // Codegen reports label errors itself, leaving the
+        // unresolved labels for the caller to inspect.
+        Ok(())
+        | Err(IrError::UnsetLabel { .. })
+        | Err(IrError::DuplicateLabel { .. }) => {}
+        Err(e) => return Err(TranslateError::InvalidIr(e)),
+    }
+    analyze(ctx);
+    codegen_with(ctx, backend, buf, mode)
+}
+
+/// `translate()`, then disassemble the TB: one `(offset, text)`
+/// per host instruction, offsets into `buf`.
+pub fn translate_and_disassemble(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+) -> Result<(usize, Vec<(usize, String)>), TranslateError> {
+    let start = translate(ctx, backend, buf)?;
+    Ok((start, disassemble(backend, buf, start, buf.offset())))
+}
+
+/// Disassemble `buf[start..end]` with the backend's decoder.
+pub fn disassemble(
+    backend: &impl HostCodeGen,
+    buf: &CodeBuffer,
+    start: usize,
+    end: usize,
+) -> Vec<(usize, String)> {
+    let code = &buf.as_slice()[..end];
+    let mut insns = Vec::new();
+    let mut off = start;
+    while off < end {
+        let (text, len) = backend.disas_insn(off as u64, &code[off..]);
+        insns.push((off, text));
+        // A truncated instruction at the end takes the rest.
+        off += if len == 0 { end - off } else { len };
+    }
+    insns
+}
+
+/// Run the passes preceding code generation (optimize →
+/// peephole → liveness). `pressure_report()` can then measure
+/// the result.
+pub fn analyze(ctx: &mut Context) {
+    analyze_with(ctx, &OptimizeOptions::default());
+}
+
+/// `analyze()` with explicit optimizer options.
+pub fn analyze_with(ctx: &mut Context, opts: &OptimizeOptions) {
+    optimize_with(ctx, opts);
+    if opts.peephole {
+        peephole(ctx);
+    }
     liveness_analysis(ctx);
+}
+
+/// Register-allocate and emit IR already processed by
+/// `analyze()`. Returns the offset where TB code starts.
+pub fn codegen(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+) -> Result<usize, TranslateError> {
+    codegen_with(ctx, backend, buf, RegAllocMode::default())
+}
+
+/// `codegen()` with an explicit register allocator mode.
+pub fn codegen_with(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+    mode: RegAllocMode,
+) -> Result<usize, TranslateError> {
     let tb_start = buf.offset();
-    regalloc_and_codegen(ctx, backend, buf);
-    tb_start
+    #[cfg(debug_assertions)]
+    backend.emit_stack_check(buf);
+    if let Err(e) = regalloc_and_codegen_with(ctx, backend, buf, mode) {
+        buf.discard_pool();
+        return Err(e);
+    }
+    buf.emit_pool();
+    backend.flush_icache(buf, tb_start, buf.offset());
+    Ok(tb_start)
 }
 
 /// Translate and execute a TB.
@@ -24,24 +186,47 @@ pub fn translate(
 /// # Safety
 /// `env` must point to a valid CPUState-like struct that
 /// matches the globals registered in `ctx`.
+///
+/// # Panics
+/// If translation fails; there is no code to run.
 pub unsafe fn translate_and_execute(
     ctx: &mut Context,
     backend: &impl HostCodeGen,
     buf: &mut CodeBuffer,
     env: *mut u8,
+) -> usize {
+    translate_and_execute_with(ctx, backend, buf, env, RegAllocMode::default())
+}
+
+/// `translate_and_execute()` with an explicit register
+/// allocator mode.
+///
+/// # Safety
+/// As for `translate_and_execute()`.
+pub unsafe fn translate_and_execute_with(
+    ctx: &mut Context,
+    backend: &impl HostCodeGen,
+    buf: &mut CodeBuffer,
+    env: *mut u8,
+    mode: RegAllocMode,
 ) -> usize {
     // Buffer is RWX, no permission switch needed.
-    let tb_start = translate(ctx, backend, buf);
+    let tb_start = translate_with(ctx, backend, buf, mode)
+        .unwrap_or_else(|e| panic!("{e}"));
 
     // Prologue signature:
-    //   fn(env: *mut u8, tb_ptr: *const u8) -> usize
-    // RDI = env, RSI = TB code pointer, returns RAX
-    let prologue_fn: unsafe extern "C" fn(*mut u8, *const u8) -> usize =
-        core::mem::transmute(buf.base_ptr());
+    //   fn(env: *mut u8, tb_ptr: *const u8, panic: *mut u64) -> usize
+    // in the host C ABI (RDI/RSI/RDX -> RAX on x86-64,
+    // X0/X1/X2 -> X0 on AArch64).
+    let prologue_fn: unsafe extern "C" fn(
+        *mut u8,
+        *const u8,
+        *mut u64,
+    ) -> usize = core::mem::transmute(buf.base_ptr());
     let tb_ptr = buf.ptr_at(tb_start);
-    let raw = prologue_fn(env, tb_ptr);
-    // Decode: strip the encoded TB index, return only the
-    // exit code (slot number or exception code).
-    let (_, exit_code) = tcg_core::tb::decode_tb_exit(raw);
-    exit_code
+    let raw = prologue_fn(env, tb_ptr, tcg_core::helper::pending_ptr());
+    // Strip the encoded TB index, return only the exit code
+    // (slot number or exception code).
+    let (_, exit) = tcg_core::tb::TbExit::decode(raw);
+    exit.code() as usize
 }
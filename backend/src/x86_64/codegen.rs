@@ -2,16 +2,36 @@ use crate::code_buffer::CodeBuffer;
 use crate::constraint::OpConstraint;
 use crate::x86_64::emitter::*;
 use crate::x86_64::regs::{
-    Reg, CALLEE_SAVED, CALL_ARG_REGS, STACK_ADDEND, STATIC_CALL_ARGS_SIZE,
+    Reg, CALLEE_SAVED, CALL_ARG_REGS, HELPER_PANIC_SLOT, STACK_ADDEND,
+    STATIC_CALL_ARGS_SIZE,
 };
 use crate::HostCodeGen;
-use tcg_core::{Cond, Context, Op, Opcode, Type};
+#[cfg(debug_assertions)]
+use tcg_core::tb::TB_EXIT_STACK_OVERFLOW;
+use tcg_core::tb::{TbExit, TB_EXIT_HELPER_PANIC};
+use tcg_core::{Cond, Context, Op, Opcode, RelocKind, Type};
 
 impl HostCodeGen for X86_64CodeGen {
     fn op_constraint(&self, opc: Opcode) -> &'static OpConstraint {
         crate::x86_64::constraints::op_constraint(opc)
     }
 
+    fn allocatable_regs(&self) -> tcg_core::RegSet {
+        crate::x86_64::regs::ALLOCATABLE_REGS
+    }
+
+    fn call_clobbered_regs(&self) -> tcg_core::RegSet {
+        crate::x86_64::regs::CALL_CLOBBERED
+    }
+
+    fn branch_reloc(&self, _opc: Opcode) -> RelocKind {
+        RelocKind::Rel32
+    }
+
+    fn disas_insn(&self, pc: u64, code: &[u8]) -> (String, usize) {
+        tcg_disas::x86_64::print_insn_x86_64(pc, code)
+    }
+
     fn emit_prologue(&mut self, buf: &mut CodeBuffer) {
         self.prologue_offset = buf.offset();
         for &reg in CALLEE_SAVED {
@@ -29,12 +49,31 @@ impl HostCodeGen for X86_64CodeGen {
         );
         // sub rsp, STACK_ADDEND
         emit_arith_ri(buf, ArithOp::Sub, true, Reg::Rsp, STACK_ADDEND as i32);
+        // mov [rsp+HELPER_PANIC_SLOT], rdx (helper-panic word)
+        emit_store(
+            buf,
+            true,
+            CALL_ARG_REGS[2],
+            Reg::Rsp,
+            HELPER_PANIC_SLOT as i32,
+        );
         // jmp *rsi (TB code pointer)
         emit_jmp_reg(buf, CALL_ARG_REGS[1]);
         self.code_gen_start = buf.offset();
     }
 
     fn emit_epilogue(&mut self, buf: &mut CodeBuffer) {
+        // Helper-panic exit, reached by `call` from the post-call
+        // check: record the call site in the pending word and
+        // leave with TB_EXIT_HELPER_PANIC.
+        self.helper_panic_offset = buf.offset();
+        emit_pop(buf, Reg::R11);
+        emit_load(buf, true, Reg::Rax, Reg::Rsp, HELPER_PANIC_SLOT as i32);
+        emit_store(buf, true, Reg::R11, Reg::Rax, 0);
+        emit_mov_ri(buf, true, Reg::Rax, TB_EXIT_HELPER_PANIC as u64);
+        let jmp_offset = buf.offset();
+        emit_jmp(buf, jmp_offset);
+
         self.epilogue_return_zero_offset = buf.offset();
         emit_mov_ri(buf, false, Reg::Rax, 0);
         self.tb_ret_offset = buf.offset();
@@ -43,6 +82,7 @@ impl HostCodeGen for X86_64CodeGen {
             emit_pop(buf, reg);
         }
         emit_ret(buf);
+        self.patch_jump(buf, jmp_offset, self.tb_ret_offset);
     }
 
     fn patch_jump(
@@ -59,6 +99,11 @@ impl HostCodeGen for X86_64CodeGen {
         buf.patch_u32(jump_offset + 1, disp as u32);
     }
 
+    fn jump_target(&self, buf: &CodeBuffer, jump_offset: usize) -> usize {
+        let disp = buf.read_u32(jump_offset + 1) as i32;
+        (jump_offset as i64 + 5 + disp as i64) as usize
+    }
+
     fn epilogue_offset(&self) -> usize {
         self.tb_ret_offset
     }
@@ -66,6 +111,7 @@ impl HostCodeGen for X86_64CodeGen {
     fn init_context(&self, ctx: &mut tcg_core::Context) {
         use crate::x86_64::regs;
         ctx.reserved_regs = regs::RESERVED_REGS;
+        ctx.env_reg = regs::TCG_AREG0 as u8;
         ctx.set_frame(
             Reg::Rsp as u8,
             STATIC_CALL_ARGS_SIZE as i64,
@@ -86,6 +132,17 @@ impl HostCodeGen for X86_64CodeGen {
         emit_mov_ri(buf, rexw, Reg::from_u8(dst), val);
     }
 
+    fn tcg_out_movi_keep_flags(
+        &self,
+        buf: &mut CodeBuffer,
+        ty: Type,
+        dst: u8,
+        val: u64,
+    ) {
+        let rexw = ty == Type::I64;
+        emit_mov_ri_keep_flags(buf, rexw, Reg::from_u8(dst), val);
+    }
+
     fn tcg_out_ld(
         &self,
         buf: &mut CodeBuffer,
@@ -222,6 +279,16 @@ impl HostCodeGen for X86_64CodeGen {
                     buf.emit_u32(0);
                 }
             }
+            Opcode::Br => {
+                let label = ctx.label(cargs[0]);
+                if label.has_value {
+                    emit_jmp(buf, label.value);
+                } else {
+                    buf.emit_u8(0xE9);
+                    buf.emit_u32(0);
+                }
+            }
+            Opcode::Mb => emit_mfence(buf),
             Opcode::Ld => {
                 let d = Reg::from_u8(oregs[0]);
                 let base = Reg::from_u8(iregs[0]);
@@ -315,8 +382,7 @@ impl HostCodeGen for X86_64CodeGen {
                 }
             }
             Opcode::ExitTb => {
-                let val = cargs[0] as u64;
-                let encoded = tcg_core::tb::encode_tb_exit(ctx.tb_idx, val);
+                let encoded = TbExit::encode(ctx.tb_idx, cargs[0]);
                 self.emit_exit_tb(buf, encoded);
             }
             Opcode::GotoTb => {
@@ -342,6 +408,19 @@ impl HostCodeGen for X86_64CodeGen {
                 let src = Reg::from_u8(iregs[1]);
                 emit_mul(buf, rexw, src);
             }
+            // Unsigned product, minus b from the high half when
+            // a is negative.
+            Opcode::MulSU2 => {
+                let a = Reg::from_u8(iregs[0]);
+                let b = Reg::from_u8(iregs[1]);
+                emit_mov_rr(buf, rexw, Reg::Rax, b);
+                emit_mul(buf, rexw, a);
+                emit_test_rr(buf, rexw, a, a);
+                let jcc = buf.offset();
+                emit_jcc(buf, X86Cond::Jns, jcc);
+                emit_arith_rr(buf, ArithOp::Sub, rexw, Reg::Rdx, b);
+                buf.patch_u32(jcc + 2, (buf.offset() - jcc - 6) as u32);
+            }
             // -- Double-width divide --
             Opcode::DivS2 => {
                 let divisor = Reg::from_u8(iregs[2]);
@@ -402,7 +481,7 @@ impl HostCodeGen for X86_64CodeGen {
                 let len = cargs[1];
                 assert!(ofs == 0, "Extract: only ofs=0 supported");
                 match len {
-                    8 => emit_movzx(buf, OPC_MOVZBL, d, s),
+                    8 => emit_movzx(buf, OPC_MOVZBL | P_REXB_RM, d, s),
                     16 => emit_movzx(buf, OPC_MOVZWL, d, s),
                     32 => {
                         emit_mov_rr(buf, false, d, s);
@@ -424,7 +503,7 @@ impl HostCodeGen for X86_64CodeGen {
                         } else {
                             OPC_MOVSBL
                         };
-                        emit_movsx(buf, opc, d, s);
+                        emit_movsx(buf, opc | P_REXB_RM, d, s);
                     }
                     16 => {
                         let opc = if rexw {
@@ -571,6 +650,10 @@ impl HostCodeGen for X86_64CodeGen {
                 X86_64CodeGen::emit_goto_ptr(buf, reg);
             }
             // -- Guest memory load (user-mode: [R14 + addr]) --
+            // One MOV whatever the MemOp alignment: x86 does not
+            // fault on misaligned data, and an aligned access of
+            // up to 8 bytes is single-copy atomic, so `ATOM` needs
+            // nothing more. Alignment faults are the frontend's.
             Opcode::QemuLd => {
                 let d = Reg::from_u8(oregs[0]);
                 let addr = Reg::from_u8(iregs[0]);
@@ -615,6 +698,7 @@ impl HostCodeGen for X86_64CodeGen {
                 }
             }
             // -- Guest memory store (user-mode: [R14 + addr]) --
+            // One MOV, as for QemuLd.
             Opcode::QemuSt => {
                 let val = Reg::from_u8(iregs[0]);
                 let addr = Reg::from_u8(iregs[1]);
@@ -641,6 +725,23 @@ impl HostCodeGen for X86_64CodeGen {
                 let func = (cargs[1] as u64) << 32 | (cargs[0] as u64);
                 emit_mov_ri(buf, true, Reg::R11, func);
                 emit_call_reg(buf, Reg::R11);
+                if !self.call_may_panic(cargs[2]) {
+                    return;
+                }
+                // if (*[rsp+HELPER_PANIC_SLOT]) call helper_panic
+                emit_load(
+                    buf,
+                    true,
+                    Reg::R11,
+                    Reg::Rsp,
+                    HELPER_PANIC_SLOT as i32,
+                );
+                emit_load(buf, true, Reg::R11, Reg::R11, 0);
+                emit_test_rr(buf, true, Reg::R11, Reg::R11);
+                // Skip the 5-byte call below.
+                let skip = buf.offset() + 6 + 5;
+                emit_jcc(buf, X86Cond::Je, skip);
+                emit_call(buf, self.helper_panic_offset);
             }
             _ => {
                 panic!("tcg_out_op: unhandled {:?}", op.opc,);
@@ -648,6 +749,39 @@ impl HostCodeGen for X86_64CodeGen {
         }
     }
 
+    #[cfg(debug_assertions)]
+    fn set_stack_check(&mut self, on: bool) {
+        self.stack_check = on;
+    }
+
+    #[cfg(debug_assertions)]
+    fn emit_stack_check(&self, buf: &mut CodeBuffer) {
+        if !self.stack_check {
+            return;
+        }
+        // mov r11, [rsp+HELPER_PANIC_SLOT]
+        // cmp rsp, [r11+STACK_LIMIT_OFFSET]
+        // jae body
+        // mov eax, TB_EXIT_STACK_OVERFLOW; jmp tb_ret
+        // Inline, so that the epilogue and unchecked TBs are
+        // the same as without the check.
+        emit_load(buf, true, Reg::R11, Reg::Rsp, HELPER_PANIC_SLOT as i32);
+        emit_arith_rm(
+            buf,
+            ArithOp::Cmp,
+            true,
+            Reg::Rsp,
+            Reg::R11,
+            tcg_core::helper::STACK_LIMIT_OFFSET,
+        );
+        // Skip the 5-byte mov and 5-byte jmp below.
+        let body = buf.offset() + 6 + 5 + 5;
+        emit_jcc(buf, X86Cond::Jae, body);
+        emit_mov_ri(buf, false, Reg::Rax, TB_EXIT_STACK_OVERFLOW as u64);
+        emit_jmp(buf, self.tb_ret_offset);
+        debug_assert_eq!(buf.offset(), body);
+    }
+
     fn goto_tb_offsets(&self) -> Vec<(usize, usize)> {
         self.goto_tb_info.lock().unwrap().clone()
     }
@@ -655,6 +789,20 @@ impl HostCodeGen for X86_64CodeGen {
     fn clear_goto_tb_offsets(&self) {
         self.goto_tb_info.lock().unwrap().clear();
     }
+
+    fn emit_align(&self, buf: &mut CodeBuffer, align: usize) -> usize {
+        let pad = buf.padding_to(align);
+        emit_nops(buf, pad);
+        pad
+    }
+
+    fn loop_head_align(&self) -> (usize, usize) {
+        self.loop_align
+    }
+
+    fn max_atomic_bytes(&self) -> u32 {
+        8
+    }
 }
 
 fn cond_from_u32(val: u32) -> Cond {
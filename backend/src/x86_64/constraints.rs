@@ -69,6 +69,16 @@ pub fn op_constraint(opc: Opcode) -> &'static OpConstraint {
                 o2_i2_fixed(Reg::Rax as u8, Reg::Rdx as u8, R_NO_RAX_RDX);
             &C
         }
+        // -- Both operands stay live for the sign fixup --
+        Opcode::MulSU2 => {
+            static C: OpConstraint = o2_i2_fixed_out(
+                Reg::Rax as u8,
+                Reg::Rdx as u8,
+                R_NO_RAX_RDX,
+                R_NO_RAX_RDX,
+            );
+            &C
+        }
         // -- Double-width divide: RDX:RAX input/output --
         Opcode::DivS2 | Opcode::DivU2 => {
             static C: OpConstraint =
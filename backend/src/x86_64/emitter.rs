@@ -4,6 +4,7 @@ use std::sync::Mutex;
 
 use crate::code_buffer::CodeBuffer;
 use crate::x86_64::regs::Reg;
+use tcg_core::helper::CALL_NO_PANIC;
 
 // -- Prefix flags (matching QEMU's P_* constants) --
 
@@ -565,6 +566,22 @@ pub fn emit_mov_ri(buf: &mut CodeBuffer, rexw: bool, reg: Reg, val: u64) {
     }
 }
 
+/// Emit MOV reg, imm leaving the flags alone: zero is a
+/// 32-bit MOV rather than XOR reg, reg.
+pub fn emit_mov_ri_keep_flags(
+    buf: &mut CodeBuffer,
+    rexw: bool,
+    reg: Reg,
+    val: u64,
+) {
+    if val == 0 {
+        emit_opc(buf, OPC_MOVL_Iv + (reg.low3() as u32), 0, reg as u8);
+        buf.emit_u32(0);
+    } else {
+        emit_mov_ri(buf, rexw, reg, val);
+    }
+}
+
 /// Emit zero-extend: MOVZBL or MOVZWL.
 pub fn emit_movzx(buf: &mut CodeBuffer, opc: u32, dst: Reg, src: Reg) {
     emit_modrm(buf, opc, dst, src);
@@ -1172,11 +1189,23 @@ fn emit_vex_modrm(buf: &mut CodeBuffer, opc: u32, r: Reg, v: Reg, rm: Reg) {
 /// x86-64 backend code generator.
 pub struct X86_64CodeGen {
     pub prologue_offset: usize,
+    /// `xor eax, eax` falling into the epilogue: the "no TB
+    /// found" sentinel. `exit_tb(0)` jumps here, and a pointer
+    /// lookup that misses hands this address to `goto_ptr`.
     pub epilogue_return_zero_offset: usize,
+    /// Epilogue proper; expects the exit value in `rax`.
     pub tb_ret_offset: usize,
+    /// Exit stub taken when a helper call reports a panic.
+    pub helper_panic_offset: usize,
+    /// Emit the host stack check at every TB entry.
+    #[cfg(debug_assertions)]
+    pub stack_check: bool,
     pub code_gen_start: usize,
     /// Recorded (jmp_offset, reset_offset) for each goto_tb.
     pub(crate) goto_tb_info: Mutex<Vec<(usize, usize)>>,
+    /// Loop-head alignment and padding budget; see
+    /// `HostCodeGen::loop_head_align`.
+    pub loop_align: (usize, usize),
 }
 
 impl X86_64CodeGen {
@@ -1185,21 +1214,48 @@ impl X86_64CodeGen {
             prologue_offset: 0,
             epilogue_return_zero_offset: 0,
             tb_ret_offset: 0,
+            helper_panic_offset: 0,
+            #[cfg(debug_assertions)]
+            stack_check: false,
             code_gen_start: 0,
             goto_tb_info: Mutex::new(Vec::new()),
+            loop_align: (1, 0),
         }
     }
 
+    /// Align loop heads to `align` bytes when that costs at most
+    /// `max_pad` bytes of NOPs.
+    pub fn with_loop_align(mut self, align: usize, max_pad: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        self.loop_align = (align, max_pad);
+        self
+    }
+
     /// Emit `exit_tb(val)`: load return value into rax and jump to epilogue.
+    ///
+    /// Zero costs only the jump, to the sentinel's shared
+    /// `xor eax, eax`; values up to `u32::MAX` use the 5-byte
+    /// zero-extending `mov eax, imm32`, and only wider values
+    /// (chain exits carrying a TB index) need `movabs`.
     pub fn emit_exit_tb(&self, buf: &mut CodeBuffer, val: u64) {
         if val == 0 {
             emit_jmp(buf, self.epilogue_return_zero_offset);
         } else {
-            emit_mov_ri(buf, true, Reg::Rax, val);
+            emit_mov_ri(buf, val > u32::MAX as u64, Reg::Rax, val);
             emit_jmp(buf, self.tb_ret_offset);
         }
     }
 
+    /// Whether a call with `flags` needs the pending-panic test
+    /// after it; see `helper::CALL_NO_PANIC`.
+    pub fn call_may_panic(&self, flags: u32) -> bool {
+        #[cfg(debug_assertions)]
+        if self.stack_check {
+            return true;
+        }
+        flags & CALL_NO_PANIC == 0
+    }
+
     /// Emit `goto_tb(n)`: a patchable direct jump (5 bytes: E9 + disp32).
     ///
     /// The disp32 field is aligned to 4 bytes so that concurrent
@@ -1219,7 +1275,8 @@ impl X86_64CodeGen {
         (jmp_offset, reset_offset)
     }
 
-    /// Emit `goto_ptr(reg)`: indirect jump through a register.
+    /// Emit `goto_ptr(reg)`: a single `jmp *reg`. Reaching the
+    /// epilogue this way (the zero sentinel) exits with 0.
     pub fn emit_goto_ptr(buf: &mut CodeBuffer, reg: Reg) {
         emit_jmp_reg(buf, reg);
     }
@@ -67,6 +67,20 @@ pub const CALLEE_SAVED: &[Reg] =
 pub const CALL_ARG_REGS: &[Reg] =
     &[Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
 
+/// Caller-saved registers a helper call may clobber (System V
+/// AMD64 ABI).
+pub const CALL_CLOBBERED: RegSet = RegSet::from_raw(
+    (1 << Reg::Rax as u64)
+        | (1 << Reg::Rcx as u64)
+        | (1 << Reg::Rdx as u64)
+        | (1 << Reg::Rsi as u64)
+        | (1 << Reg::Rdi as u64)
+        | (1 << Reg::R8 as u64)
+        | (1 << Reg::R9 as u64)
+        | (1 << Reg::R10 as u64)
+        | (1 << Reg::R11 as u64),
+);
+
 /// Registers reserved by the backend — not available for
 /// register allocation.
 /// RSP (stack), RBP (env), R14 (guest_base).
@@ -84,9 +98,14 @@ pub const CPU_TEMP_BUF_NLONGS: usize = 128;
 /// Total push size: return address (implicit) + callee-saved pushes.
 pub const PUSH_SIZE: usize = (1 + CALLEE_SAVED.len()) * 8;
 
+/// Frame offset (from RSP after the prologue) of the pointer to
+/// the thread's helper-panic word; see `tcg_core::helper`.
+pub const HELPER_PANIC_SLOT: usize =
+    STATIC_CALL_ARGS_SIZE + CPU_TEMP_BUF_NLONGS * 8;
+
 /// Total frame size (16-byte aligned).
 pub const FRAME_SIZE: usize = {
-    let raw = PUSH_SIZE + STATIC_CALL_ARGS_SIZE + CPU_TEMP_BUF_NLONGS * 8;
+    let raw = PUSH_SIZE + HELPER_PANIC_SLOT + 8;
     (raw + STACK_ALIGN - 1) & !(STACK_ALIGN - 1)
 };
 
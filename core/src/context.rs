@@ -1,8 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::label::Label;
 use crate::op::{Op, OpIdx};
-use crate::temp::{Temp, TempIdx};
+use crate::temp::{Temp, TempIdx, TempKind};
 use crate::types::{RegSet, Type, TYPE_COUNT};
 
 /// Maximum number of temps per translation context.
@@ -10,6 +10,18 @@ pub const MAX_TEMPS: usize = 512;
 /// Maximum number of guest instructions per TB.
 pub const MAX_INSNS: usize = 512;
 
+/// Decode metadata for one guest instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsnMeta {
+    /// Decode pattern the instruction matched.
+    pub name: &'static str,
+    /// ISA extensions that provide the pattern.
+    pub exts: &'static [&'static str],
+}
+
+/// Env register a `Context` starts with: x86-64 RBP.
+const DEFAULT_ENV_REG: u8 = 5;
+
 /// Per-thread TCG translation context.
 ///
 /// Maps to QEMU's `TCGContext`. Holds all state needed during translation
@@ -36,6 +48,10 @@ pub struct Context {
     // -- Register allocation state --
     /// Registers reserved by the backend (not available for allocation).
     pub reserved_regs: RegSet,
+    /// Host register holding the env pointer, for frontends to
+    /// pin their `env` fixed temp to. Set by the backend's
+    /// `init_context`; defaults to x86-64 RBP.
+    pub env_reg: u8,
 
     // -- Constant deduplication --
     /// Per-type hash map from constant value to TempIdx,
@@ -46,6 +62,12 @@ pub struct Context {
     /// End offset in host code for each guest instruction
     /// (indexed by guest insn number).
     pub gen_insn_end_off: Vec<u16>,
+    /// Decode metadata per guest instruction, keyed by its
+    /// `insn_start` pc; `None` unless enabled.
+    insn_meta: Option<BTreeMap<u64, InsnMeta>>,
+    /// First op of the TB epilogue `tb_stop` emitted, if
+    /// marked.
+    tb_stop_op: Option<usize>,
 
     // -- TB identification --
     /// Index of the TB being translated. Used by the backend to
@@ -56,9 +78,15 @@ pub struct Context {
 
 impl Context {
     pub fn new() -> Self {
+        Self::with_capacity(512, 256)
+    }
+
+    /// A context with room for `ops` ops and `temps` temps
+    /// (globals included) before either vector grows.
+    pub fn with_capacity(ops: usize, temps: usize) -> Self {
         Self {
-            temps: Vec::with_capacity(256),
-            ops: Vec::with_capacity(512),
+            temps: Vec::with_capacity(temps),
+            ops: Vec::with_capacity(ops),
             labels: Vec::with_capacity(32),
             nb_globals: 0,
             frame_reg: None,
@@ -66,8 +94,11 @@ impl Context {
             frame_end: 0,
             frame_alloc_end: 0,
             reserved_regs: RegSet::EMPTY,
+            env_reg: DEFAULT_ENV_REG,
             const_table: Default::default(),
             gen_insn_end_off: Vec::with_capacity(MAX_INSNS),
+            insn_meta: None,
+            tb_stop_op: None,
             tb_idx: 0,
         }
     }
@@ -75,16 +106,19 @@ impl Context {
     /// Reset context for translating a new TB. Preserves globals
     /// but resets their register allocation state so the next
     /// codegen pass starts with all globals in memory.
+    ///
+    /// Globals keep their `TempIdx`; the op, temp and label
+    /// vectors are truncated in place and keep their capacity.
     pub fn reset(&mut self) {
         self.temps.truncate(self.nb_globals as usize);
         // Reset regalloc state on surviving globals
         for t in &mut self.temps {
             match t.kind {
-                crate::temp::TempKind::Fixed => {
+                TempKind::Fixed => {
                     // Fixed temps stay in their register
                     t.mem_coherent = false;
                 }
-                crate::temp::TempKind::Global => {
+                TempKind::Global => {
                     t.val_type = crate::types::TempVal::Mem;
                     t.reg = None;
                     t.mem_coherent = true;
@@ -98,6 +132,10 @@ impl Context {
             table.clear();
         }
         self.gen_insn_end_off.clear();
+        if let Some(meta) = &mut self.insn_meta {
+            meta.clear();
+        }
+        self.tb_stop_op = None;
         self.frame_alloc_end = self.frame_start;
     }
 
@@ -125,6 +163,29 @@ impl Context {
         idx
     }
 
+    /// Allocate an EBB-scoped 128-bit temp: two adjacent I64
+    /// temps with base type I128, low half first. Returns the
+    /// low half; see [`Context::i128_halves`].
+    pub fn new_temp_i128(&mut self) -> TempIdx {
+        let lo = self.new_temp(Type::I64);
+        let hi = self.new_temp(Type::I64);
+        self.temps[lo.0 as usize].base_type = Type::I128;
+        self.temps[hi.0 as usize].base_type = Type::I128;
+        lo
+    }
+
+    /// The `(low, high)` I64 halves of a temp from
+    /// [`Context::new_temp_i128`].
+    pub fn i128_halves(&self, t: TempIdx) -> (TempIdx, TempIdx) {
+        assert_eq!(
+            self.temp(t).base_type,
+            Type::I128,
+            "temp {} is not 128-bit",
+            t.0
+        );
+        (t, TempIdx(t.0 + 1))
+    }
+
     /// Get or create a constant temp (deduplicated per type).
     pub fn new_const(&mut self, ty: Type, val: u64) -> TempIdx {
         let type_idx = ty as usize;
@@ -182,6 +243,20 @@ impl Context {
         &self.temps[idx.0 as usize]
     }
 
+    pub fn temp_kind(&self, idx: TempIdx) -> TempKind {
+        self.temp(idx).kind
+    }
+
+    pub fn temp_type(&self, idx: TempIdx) -> Type {
+        self.temp(idx).ty
+    }
+
+    /// The value of `idx` if it is a constant temp.
+    pub fn temp_const_val(&self, idx: TempIdx) -> Option<u64> {
+        let t = self.temp(idx);
+        t.is_const().then_some(t.val)
+    }
+
     pub fn temp_mut(&mut self, idx: TempIdx) -> &mut Temp {
         &mut self.temps[idx.0 as usize]
     }
@@ -219,6 +294,11 @@ impl Context {
         &self.ops
     }
 
+    /// Ops the context holds before its op vector grows.
+    pub fn op_capacity(&self) -> usize {
+        self.ops.capacity()
+    }
+
     pub fn num_ops(&self) -> usize {
         self.ops.len()
     }
@@ -243,6 +323,47 @@ impl Context {
         &self.labels
     }
 
+    /// Labels that still have branch references waiting to be
+    /// back-patched. Empty once code generation succeeds.
+    pub fn unresolved_labels(&self) -> impl Iterator<Item = &Label> {
+        self.labels.iter().filter(|l| l.has_pending_uses())
+    }
+
+    // -- Instruction metadata --
+
+    /// Start recording decode metadata per guest instruction.
+    /// Survives `reset()`; entries are per TB.
+    pub fn enable_insn_meta(&mut self) {
+        self.insn_meta.get_or_insert_with(BTreeMap::new);
+    }
+
+    pub fn insn_meta_enabled(&self) -> bool {
+        self.insn_meta.is_some()
+    }
+
+    /// Record `meta` for the instruction at `pc`; no-op unless
+    /// enabled.
+    pub fn record_insn_meta(&mut self, pc: u64, meta: InsnMeta) {
+        if let Some(m) = &mut self.insn_meta {
+            m.insert(pc, meta);
+        }
+    }
+
+    /// Metadata recorded for the current TB, keyed by pc.
+    pub fn insn_meta(&self) -> Option<&BTreeMap<u64, InsnMeta>> {
+        self.insn_meta.as_ref()
+    }
+
+    /// Mark that the ops from here on are the TB epilogue, not
+    /// part of the last guest instruction.
+    pub fn mark_tb_stop(&mut self) {
+        self.tb_stop_op = Some(self.ops.len());
+    }
+
+    pub fn tb_stop_op(&self) -> Option<usize> {
+        self.tb_stop_op
+    }
+
     // -- Frame management --
 
     /// Configure the stack frame for spilling.
@@ -292,8 +413,11 @@ impl Context {
             frame_end: 0,
             frame_alloc_end: 0,
             reserved_regs: RegSet::EMPTY,
+            env_reg: DEFAULT_ENV_REG,
             const_table: Default::default(),
             gen_insn_end_off: Vec::new(),
+            insn_meta: None,
+            tb_stop_op: None,
             tb_idx: 0,
         }
     }
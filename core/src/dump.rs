@@ -3,11 +3,14 @@
 //! Mirrors QEMU's `tcg_dump_ops()` in `tcg/tcg.c`.
 
 use std::io::Write;
+use std::ops::Range;
 
 use crate::context::Context;
+use crate::helper::CALL_NO_PANIC;
 use crate::op::Op;
 use crate::opcode::Opcode;
-use crate::temp::TempKind;
+use crate::pressure::LivenessReport;
+use crate::temp::{TempIdx, TempKind};
 use crate::types::Type;
 
 /// Format a condition code as a short name.
@@ -32,7 +35,7 @@ fn cond_name(c: u32) -> &'static str {
 }
 
 /// Format a temp reference for display.
-fn fmt_temp(ctx: &Context, idx: crate::temp::TempIdx, buf: &mut String) {
+fn fmt_temp(ctx: &Context, idx: TempIdx, buf: &mut String) {
     use std::fmt::Write as FmtWrite;
     let i = idx.0 as usize;
     if i >= ctx.nb_temps() as usize {
@@ -100,10 +103,29 @@ pub fn dump_ops_with(
     ctx: &Context,
     w: &mut impl Write,
     insn_anno: impl Fn(u64, &mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    dump_slice(ctx, ctx.ops(), w, insn_anno)
+}
+
+/// Dump the ops of `ctx` in `range`, e.g. one guest
+/// instruction's.
+pub fn dump_op_range(
+    ctx: &Context,
+    range: Range<usize>,
+    w: &mut impl Write,
+) -> std::io::Result<()> {
+    dump_slice(ctx, &ctx.ops()[range], w, |_, _| Ok(()))
+}
+
+fn dump_slice(
+    ctx: &Context,
+    ops: &[Op],
+    w: &mut impl Write,
+    insn_anno: impl Fn(u64, &mut dyn Write) -> std::io::Result<()>,
 ) -> std::io::Result<()> {
     let mut buf = String::with_capacity(128);
 
-    for op in ctx.ops() {
+    for op in ops {
         buf.clear();
         match op.opc {
             Opcode::InsnStart => {
@@ -179,6 +201,9 @@ pub fn dump_ops_with(
                 let hi = cargs[1].0 as u64;
                 let addr = (hi << 32) | lo;
                 write!(w, ", $0x{addr:x}")?;
+                if cargs[2].0 & CALL_NO_PANIC != 0 {
+                    write!(w, ", nopanic")?;
+                }
             }
             _ => {
                 let has_prev = !oargs.is_empty() || !iargs.is_empty();
@@ -196,3 +221,206 @@ pub fn dump_ops_with(
     }
     Ok(())
 }
+
+fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::I128 => "i128",
+        Type::V64 => "v64",
+        Type::V128 => "v128",
+        Type::V256 => "v256",
+    }
+}
+
+/// Write `s` as a JSON string.
+fn json_str(w: &mut dyn Write, s: &str) -> std::io::Result<()> {
+    write!(w, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(w, "\\\"")?,
+            '\\' => write!(w, "\\\\")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{c}")?,
+        }
+    }
+    write!(w, "\"")
+}
+
+/// Write `,"key":[v,...]`.
+fn json_list(
+    w: &mut dyn Write,
+    key: &str,
+    vals: Vec<u32>,
+) -> std::io::Result<()> {
+    write!(w, ",\"{key}\":[")?;
+    for (i, v) in vals.iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "{v}")?;
+    }
+    write!(w, "]")
+}
+
+/// Which constant args of `op` are label ids.
+fn label_cargs(op: &Op) -> &'static [usize] {
+    match op.opc {
+        Opcode::Br | Opcode::SetLabel => &[0],
+        Opcode::BrCond | Opcode::BrCond2I32 => &[1],
+        _ => &[],
+    }
+}
+
+/// Write `,"key":["name",...]` with each temp as the text dump
+/// shows it.
+fn json_names(
+    ctx: &Context,
+    w: &mut dyn Write,
+    key: &str,
+    args: &[TempIdx],
+) -> std::io::Result<()> {
+    write!(w, ",\"{key}\":[")?;
+    let mut buf = String::new();
+    for (i, &a) in args.iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        buf.clear();
+        fmt_temp(ctx, a, &mut buf);
+        json_str(w, &buf)?;
+    }
+    write!(w, "]")
+}
+
+/// Dump the temps and ops of `ctx` as one JSON object, for tools
+/// that would otherwise parse the text dump.
+///
+/// `temps` lists every temp by index with its kind (`Global`,
+/// `Const`, `Temp` or `Fixed`) and type; globals add their env
+/// offset and name, constants their value, fixed temps their
+/// host register. `ops` lists the ops in order with their opcode
+/// name (as in the text dump), type, temp indices and names,
+/// constant args and the label ids among them. `insn_start` ops
+/// also carry their guest `pc`.
+///
+/// Given the TB's `pressure` report, a `pressure` object adds
+/// its peak, the peak per type, the op of the peak (or `null`),
+/// the spill sites and the per-op timeline.
+pub fn dump_ops_json(
+    ctx: &Context,
+    w: &mut dyn Write,
+    pressure: Option<&LivenessReport>,
+) -> std::io::Result<()> {
+    dump_ops_json_with(ctx, w, pressure, |_, _| Ok(()))
+}
+
+/// [`dump_ops_json`] with the annotation callback of
+/// [`dump_ops_with`]: whatever it writes for an `insn_start`,
+/// trimmed, becomes that op's `guest_asm` field.
+pub fn dump_ops_json_with(
+    ctx: &Context,
+    w: &mut dyn Write,
+    pressure: Option<&LivenessReport>,
+    insn_anno: impl Fn(u64, &mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    write!(w, "{{\"temps\":[")?;
+    for (i, t) in ctx.temps().iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        let kind = match t.kind {
+            TempKind::Global => "Global",
+            TempKind::Const => "Const",
+            TempKind::Ebb | TempKind::Tb => "Temp",
+            TempKind::Fixed => "Fixed",
+        };
+        write!(w, "{{\"idx\":{i},\"kind\":\"{kind}\"")?;
+        write!(w, ",\"type\":\"{}\"", type_name(t.ty))?;
+        match t.kind {
+            TempKind::Global => {
+                write!(w, ",\"offset\":{}", t.mem_offset)?;
+                if let Some(base) = t.mem_base {
+                    write!(w, ",\"base\":{}", base.0)?;
+                }
+            }
+            TempKind::Const => write!(w, ",\"value\":{}", t.val)?,
+            TempKind::Fixed => {
+                if let Some(reg) = t.reg {
+                    write!(w, ",\"reg\":{reg}")?;
+                }
+            }
+            TempKind::Ebb => write!(w, ",\"scope\":\"ebb\"")?,
+            TempKind::Tb => write!(w, ",\"scope\":\"tb\"")?,
+        }
+        if let Some(name) = t.name {
+            write!(w, ",\"name\":")?;
+            json_str(w, name)?;
+        }
+        write!(w, "}}")?;
+    }
+    write!(w, "],\"ops\":[")?;
+    for (i, op) in ctx.ops().iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "{{\"opc\":")?;
+        json_str(w, &op_name(op))?;
+        write!(w, ",\"type\":\"{}\"", type_name(op.op_type))?;
+        let idx = |args: &[TempIdx]| args.iter().map(|a| a.0).collect();
+        json_list(w, "oargs", idx(op.oargs()))?;
+        json_list(w, "iargs", idx(op.iargs()))?;
+        json_names(ctx, w, "onames", op.oargs())?;
+        json_names(ctx, w, "inames", op.iargs())?;
+        let cargs: Vec<u32> = idx(op.cargs());
+        let labels = label_cargs(op).iter().map(|&j| cargs[j]).collect();
+        json_list(w, "cargs", cargs)?;
+        json_list(w, "labels", labels)?;
+        if op.opc == Opcode::InsnStart {
+            let cargs = op.cargs();
+            let pc = (cargs[1].0 as u64) << 32 | cargs[0].0 as u64;
+            write!(w, ",\"pc\":{pc}")?;
+            let mut anno = Vec::new();
+            insn_anno(pc, &mut anno)?;
+            let anno = String::from_utf8_lossy(&anno);
+            if !anno.trim().is_empty() {
+                write!(w, ",\"guest_asm\":")?;
+                json_str(w, anno.trim())?;
+            }
+        }
+        write!(w, "}}")?;
+    }
+    write!(w, "]")?;
+    if let Some(r) = pressure {
+        json_pressure(w, r)?;
+    }
+    writeln!(w, "}}")
+}
+
+/// Write `,"pressure":{...}` for `r`.
+fn json_pressure(w: &mut dyn Write, r: &LivenessReport) -> std::io::Result<()> {
+    write!(w, ",\"pressure\":{{\"max_live\":{}", r.max_live)?;
+    write!(w, ",\"max_live_by_type\":{{")?;
+    let types = [
+        Type::I32,
+        Type::I64,
+        Type::I128,
+        Type::V64,
+        Type::V128,
+        Type::V256,
+    ];
+    for (i, ty) in types.into_iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\"{}\":{}", type_name(ty), r.max_live_of(ty))?;
+    }
+    write!(w, "}}")?;
+    match r.max_op {
+        Some(oi) => write!(w, ",\"max_op\":{oi}")?,
+        None => write!(w, ",\"max_op\":null")?,
+    }
+    write!(w, ",\"spill_sites\":{}", r.spill_sites)?;
+    json_list(w, "timeline", r.timeline.clone())?;
+    write!(w, "}}")
+}
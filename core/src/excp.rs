@@ -0,0 +1,165 @@
+//! Exception codes carried by [`TbExit::Exception`].
+//!
+//! The core only defines the mechanism. The exception range
+//! `TB_EXIT_MAX..TB_EXIT_CUSTOM` is split in two: the top
+//! [`EXCP_CORE_COUNT`] codes are reserved for categories the
+//! exec loop and its callers understand without knowing the
+//! guest ([`CoreExcp`]); the rest, from [`EXCP_FRONTEND_BASE`],
+//! is the frontend's. A frontend names its codes in an
+//! [`ExcpRegistry`] at startup; what they mean is up to its
+//! handler.
+//!
+//! [`TbExit::Exception`]: crate::tb::TbExit::Exception
+
+use std::fmt;
+
+use crate::tb::{TB_EXIT_CUSTOM, TB_EXIT_MAX};
+
+/// First frontend-defined exception code.
+pub const EXCP_FRONTEND_BASE: u32 = TB_EXIT_MAX;
+
+/// Codes reserved for [`CoreExcp`] at the top of the range.
+pub const EXCP_CORE_COUNT: u32 = 0x100;
+
+/// First core-reserved exception code.
+pub const EXCP_CORE_BASE: u32 = TB_EXIT_CUSTOM - EXCP_CORE_COUNT;
+
+/// Guest-independent exception categories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CoreExcp {
+    /// Breakpoint or single step for a debugger.
+    Debug = EXCP_CORE_BASE,
+    /// A watched guest address was accessed.
+    Watchpoint,
+    /// The run was interrupted from outside the guest.
+    Interrupted,
+    /// A guest memory access faulted.
+    Fault,
+}
+
+impl CoreExcp {
+    const ALL: [CoreExcp; 4] = [
+        CoreExcp::Debug,
+        CoreExcp::Watchpoint,
+        CoreExcp::Interrupted,
+        CoreExcp::Fault,
+    ];
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.code() == code)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CoreExcp::Debug => "debug",
+            CoreExcp::Watchpoint => "watchpoint",
+            CoreExcp::Interrupted => "interrupted",
+            CoreExcp::Fault => "fault",
+        }
+    }
+}
+
+/// An exception code sorted by owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExcpClass {
+    Core(CoreExcp),
+    /// A frontend-range code, registered or not.
+    Frontend(u32),
+    /// A core-range code with no [`CoreExcp`].
+    Reserved(u32),
+}
+
+impl ExcpClass {
+    /// Sort an exception code; panics outside the exception
+    /// range.
+    pub fn of(code: u32) -> Self {
+        assert!(
+            (EXCP_FRONTEND_BASE..TB_EXIT_CUSTOM).contains(&code),
+            "exception {code:#x} outside the exception range"
+        );
+        if code < EXCP_CORE_BASE {
+            ExcpClass::Frontend(code)
+        } else {
+            CoreExcp::from_code(code)
+                .map_or(ExcpClass::Reserved(code), ExcpClass::Core)
+        }
+    }
+}
+
+/// Names of the frontend's exception codes.
+#[derive(Debug, Clone, Default)]
+pub struct ExcpRegistry {
+    entries: Vec<(u32, &'static str)>,
+}
+
+impl ExcpRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name frontend code `code`. Panics if it lies outside the
+    /// frontend range, or if the code or name is already taken.
+    pub fn register(&mut self, code: u32, name: &'static str) {
+        assert!(
+            (EXCP_FRONTEND_BASE..EXCP_CORE_BASE).contains(&code),
+            "exception {code:#x} outside the frontend range"
+        );
+        assert!(
+            self.name(code).is_none(),
+            "exception {code:#x} registered twice"
+        );
+        assert!(
+            self.code(name).is_none(),
+            "exception name {name:?} registered twice"
+        );
+        self.entries.push((code, name));
+    }
+
+    /// Name of `code`: its own for a core code, the registered
+    /// one for a frontend code.
+    pub fn name(&self, code: u32) -> Option<&'static str> {
+        if let Some(c) = CoreExcp::from_code(code) {
+            return Some(c.name());
+        }
+        self.entries.iter().find(|e| e.0 == code).map(|e| e.1)
+    }
+
+    /// Code named `name`, core or registered.
+    pub fn code(&self, name: &str) -> Option<u32> {
+        CoreExcp::ALL
+            .into_iter()
+            .find(|c| c.name() == name)
+            .map(CoreExcp::code)
+            .or_else(|| self.entries.iter().find(|e| e.1 == name).map(|e| e.0))
+    }
+
+    /// Whether `code` is a core code or registered.
+    pub fn contains(&self, code: u32) -> bool {
+        self.name(code).is_some()
+    }
+
+    /// `code` for messages: its name if known, and the raw value.
+    pub fn describe(&self, code: u32) -> ExcpName<'_> {
+        ExcpName { reg: self, code }
+    }
+}
+
+/// An exception code formatted by [`ExcpRegistry::describe`].
+pub struct ExcpName<'a> {
+    reg: &'a ExcpRegistry,
+    code: u32,
+}
+
+impl fmt::Display for ExcpName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reg.name(self.code) {
+            Some(name) => write!(f, "{name} ({:#x})", self.code),
+            None => write!(f, "unregistered exception {:#x}", self.code),
+        }
+    }
+}
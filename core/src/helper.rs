@@ -0,0 +1,199 @@
+//! Panic containment for runtime helpers.
+//!
+//! Helpers are `extern "C"` functions called from generated
+//! code; a Rust panic must not unwind through JIT frames, and
+//! since Rust 1.81 it aborts at the `extern "C"` boundary. A
+//! helper body therefore runs under [`guard`], which catches the
+//! panic, records it in a thread-local and returns a dummy
+//! value. The backend checks the pending word after each call
+//! and leaves the TB with [`TB_EXIT_HELPER_PANIC`]; the exec loop
+//! then collects the record with [`take_panic`]. Calls flagged
+//! [`CALL_NO_PANIC`] skip that check: their helpers have no
+//! panic path, and should one panic anyway, the exec loop finds
+//! the pending word when the TB returns.
+//!
+//! Debug builds also guard the host stack. Generated code runs
+//! on the exec loop's thread below its frame, beyond Rust's
+//! own overflow detection; once helpers can re-enter the loop,
+//! deep recursion would run off the stack. With a limit set by
+//! [`set_stack_limit`], every TB entry compares the stack
+//! pointer against it and leaves with
+//! [`TB_EXIT_STACK_OVERFLOW`], and [`guard`] makes the same
+//! check before running the helper body, which it skips,
+//! reporting the breach like a panic. [`guard`] also keeps the
+//! last [`BREADCRUMB_LEN`] helper entries for the report.
+//!
+//! [`TB_EXIT_HELPER_PANIC`]: crate::tb::TB_EXIT_HELPER_PANIC
+//! [`TB_EXIT_STACK_OVERFLOW`]: crate::tb::TB_EXIT_STACK_OVERFLOW
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+#[cfg(debug_assertions)]
+use std::collections::VecDeque;
+#[cfg(debug_assertions)]
+use std::panic::Location;
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local! {
+    /// Words generated code reads through the pointer the
+    /// prologue gets. The first is the pending panic: zero when
+    /// no helper has panicked, [`guard`] sets it to 1 and the
+    /// backend's exit stub overwrites it with the host return
+    /// address of the call. The second is the stack limit, 0
+    /// for none; only debug builds set it.
+    static WORDS: [Cell<u64>; 2] = const { [Cell::new(0), Cell::new(0)] };
+    static MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+    #[cfg(debug_assertions)]
+    static BREADCRUMBS: RefCell<VecDeque<Breadcrumb>> =
+        const { RefCell::new(VecDeque::new()) };
+    #[cfg(debug_assertions)]
+    static BREACH: Cell<Option<Breadcrumb>> = const { Cell::new(None) };
+}
+
+/// `Call` flag: the helper cannot panic, so generated code does
+/// not test the pending word after it. Debug builds still test
+/// it while the stack check is on, since [`guard`] may skip the
+/// body.
+pub const CALL_NO_PANIC: u32 = 1 << 0;
+
+/// Byte offset of the stack limit from [`pending_ptr`].
+#[cfg(debug_assertions)]
+pub const STACK_LIMIT_OFFSET: i32 = 8;
+
+/// Helper entries kept while a stack limit is set.
+#[cfg(debug_assertions)]
+pub const BREADCRUMB_LEN: usize = 16;
+
+/// A helper entry seen by [`guard`].
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breadcrumb {
+    /// Where the helper called [`guard`].
+    pub helper: &'static Location<'static>,
+    /// Host stack pointer at the call, approximately.
+    pub sp: u64,
+}
+
+/// A panic caught in a helper.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelperPanic {
+    pub message: String,
+    /// Host address the helper would have returned to, or 0 if
+    /// the panic was not seen by generated code.
+    pub host_ret: usize,
+}
+
+/// Run a helper body, turning a panic into a pending record.
+///
+/// Returns `R::default()` after a panic; generated code
+/// discards it and exits the TB. In debug builds the body is
+/// not run at all when the stack is past its limit.
+#[cfg_attr(debug_assertions, track_caller)]
+pub fn guard<R: Default>(f: impl FnOnce() -> R) -> R {
+    #[cfg(debug_assertions)]
+    if !stack_enter(Location::caller()) {
+        return R::default();
+    }
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(r) => r,
+        Err(payload) => {
+            MESSAGE.with(|m| *m.borrow_mut() = Some(payload_message(&payload)));
+            WORDS.with(|w| w[0].set(1));
+            R::default()
+        }
+    }
+}
+
+/// Record a helper entry and check the stack; false on a
+/// breach, which is then pending like a panic.
+#[cfg(debug_assertions)]
+fn stack_enter(helper: &'static Location<'static>) -> bool {
+    let limit = stack_limit();
+    if limit == 0 {
+        return true;
+    }
+    let here = 0u8;
+    let sp = std::hint::black_box(&here) as *const u8 as u64;
+    let crumb = Breadcrumb { helper, sp };
+    BREADCRUMBS.with(|b| {
+        let mut b = b.borrow_mut();
+        if b.len() == BREADCRUMB_LEN {
+            b.pop_front();
+        }
+        b.push_back(crumb);
+    });
+    if sp >= limit {
+        return true;
+    }
+    if BREACH.with(|b| b.get()).is_none() {
+        BREACH.with(|b| b.set(Some(crumb)));
+    }
+    WORDS.with(|w| {
+        if w[0].get() == 0 {
+            w[0].set(1);
+        }
+    });
+    false
+}
+
+/// Address of this thread's pending word, passed to the
+/// prologue so generated code can test it after each call.
+pub fn pending_ptr() -> *mut u64 {
+    WORDS.with(|w| w[0].as_ptr())
+}
+
+/// Make generated code and [`guard`] on this thread treat a
+/// stack pointer below `limit` as an overflow; 0 disables the
+/// check. Returns the previous limit.
+#[cfg(debug_assertions)]
+pub fn set_stack_limit(limit: u64) -> u64 {
+    WORDS.with(|w| w[1].replace(limit))
+}
+
+/// This thread's stack limit, 0 for none.
+#[cfg(debug_assertions)]
+pub fn stack_limit() -> u64 {
+    WORDS.with(|w| w[1].get())
+}
+
+/// Take this thread's first stack breach seen by [`guard`]
+/// since the last call. The breach is also pending as a panic
+/// without a message; take that with [`take_panic`].
+#[cfg(debug_assertions)]
+pub fn take_stack_breach() -> Option<Breadcrumb> {
+    BREACH.with(|b| b.take())
+}
+
+/// Take this thread's recent helper entries, oldest first.
+#[cfg(debug_assertions)]
+pub fn take_breadcrumbs() -> Vec<Breadcrumb> {
+    BREADCRUMBS.with(|b| b.borrow_mut().drain(..).collect())
+}
+
+/// Whether a helper panic is pending on this thread.
+pub fn panic_pending() -> bool {
+    WORDS.with(|w| w[0].get() != 0)
+}
+
+/// Take and clear this thread's pending helper panic.
+pub fn take_panic() -> Option<HelperPanic> {
+    let pending = WORDS.with(|w| w[0].replace(0));
+    let message = MESSAGE.with(|m| m.borrow_mut().take());
+    if pending == 0 && message.is_none() {
+        return None;
+    }
+    Some(HelperPanic {
+        message: message.unwrap_or_default(),
+        host_ret: if pending > 1 { pending as usize } else { 0 },
+    })
+}
+
+fn payload_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
@@ -0,0 +1,82 @@
+//! Attribution of IR ops to the guest instructions they were
+//! generated for.
+//!
+//! Each instruction owns the ops from its `insn_start` to the
+//! next one. Ops before the first `insn_start` belong to the TB
+//! prologue and ops from `Context::tb_stop_op()` on to the TB
+//! epilogue, so a fall-through `goto_tb` is not charged to the
+//! last instruction. An instruction that ends the TB itself,
+//! like a branch, emits its own exits; those are counted apart
+//! in `InsnOps::exit`.
+
+use std::ops::Range;
+
+use crate::context::Context;
+use crate::opcode::Opcode;
+
+/// Ops generated for one guest instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsnOps {
+    pub pc: u64,
+    /// Op indices, without the `insn_start`.
+    pub ops: Range<usize>,
+    /// Of which leave the TB: `goto_tb`, `exit_tb`, `goto_ptr`.
+    pub exit: usize,
+}
+
+impl InsnOps {
+    /// Ops other than TB exits.
+    pub fn body(&self) -> usize {
+        self.ops.len() - self.exit
+    }
+}
+
+/// A TB's ops split into prologue, instructions and epilogue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TbOps {
+    pub start: Range<usize>,
+    pub insns: Vec<InsnOps>,
+    pub stop: Range<usize>,
+}
+
+/// Split the ops of the TB in `ctx` by guest instruction.
+pub fn insn_ops(ctx: &Context) -> TbOps {
+    let ops = ctx.ops();
+    let end = ctx.tb_stop_op().unwrap_or(ops.len());
+    let starts: Vec<(usize, u64)> = ops[..end]
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| op.opc == Opcode::InsnStart)
+        .map(|(i, op)| {
+            let c = op.cargs();
+            (i, c[0].0 as u64 | (c[1].0 as u64) << 32)
+        })
+        .collect();
+    let first = starts.first().map_or(end, |&(i, _)| i);
+    let insns = starts
+        .iter()
+        .enumerate()
+        .map(|(n, &(i, pc))| {
+            let next = starts.get(n + 1).map_or(end, |&(j, _)| j);
+            let exit = ops[i + 1..next]
+                .iter()
+                .filter(|op| {
+                    matches!(
+                        op.opc,
+                        Opcode::GotoTb | Opcode::ExitTb | Opcode::GotoPtr
+                    )
+                })
+                .count();
+            InsnOps {
+                pc,
+                ops: i + 1..next,
+                exit,
+            }
+        })
+        .collect();
+    TbOps {
+        start: 0..first,
+        insns,
+        stop: end..ops.len(),
+    }
+}
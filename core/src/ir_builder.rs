@@ -1,14 +1,35 @@
 use crate::context::Context;
 use crate::op::Op;
 use crate::opcode::Opcode;
+use crate::tb::{TbExit, TB_EXIT_STACK_OVERFLOW};
 use crate::temp::TempIdx;
-use crate::types::{Cond, Type};
+use crate::types::{Cond, MemOp, Type};
 
 // Constant args are encoded as TempIdx(raw_value as u32).
 fn carg(val: u32) -> TempIdx {
     TempIdx(val)
 }
 
+/// Panic unless `memop` describes an access a `ty` temp can
+/// hold, or a pair of them with `pair`.
+fn check_memop(ty: Type, memop: u32, pair: bool) {
+    let m = MemOp::new(memop as u16);
+    assert!(
+        memop & !(MemOp::VALID as u32) == 0,
+        "memop {memop:#x}: undefined bits"
+    );
+    assert!(
+        ty == Type::I64 || m.size_bytes() <= 4,
+        "memop {memop:#x}: {}-byte access into {ty:?}",
+        m.size_bytes()
+    );
+    let bytes = m.size_bytes() << pair as u32;
+    assert!(
+        !m.is_atomic() || m.align_bytes(bytes) >= bytes,
+        "memop {memop:#x}: atomic {bytes}-byte access may be misaligned"
+    );
+}
+
 impl Context {
     // -- Internal helpers --
 
@@ -299,6 +320,20 @@ impl Context {
         self.emit_op(op);
     }
 
+    /// Signed `a` times unsigned `b`.
+    pub fn gen_mulsu2(
+        &mut self,
+        ty: Type,
+        dl: TempIdx,
+        dh: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) {
+        let idx = self.next_op_idx();
+        let op = Op::with_args(idx, Opcode::MulSU2, ty, &[dl, dh, a, b]);
+        self.emit_op(op);
+    }
+
     pub fn gen_mulsh(
         &mut self,
         ty: Type,
@@ -591,6 +626,98 @@ impl Context {
         d
     }
 
+    // -- 128-bit ops on I64 halves --
+
+    fn emit_binary_i128(
+        &mut self,
+        opc: [Opcode; 2],
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        let (dl, dh) = self.i128_halves(d);
+        let (al, ah) = self.i128_halves(a);
+        let (bl, bh) = self.i128_halves(b);
+        self.emit_binary(opc[0], Type::I64, dl, al, bl);
+        self.emit_binary(opc[1], Type::I64, dh, ah, bh);
+        d
+    }
+
+    pub fn gen_mov_i128(&mut self, d: TempIdx, s: TempIdx) -> TempIdx {
+        let (dl, dh) = self.i128_halves(d);
+        let (sl, sh) = self.i128_halves(s);
+        self.gen_mov(Type::I64, dl, sl);
+        self.gen_mov(Type::I64, dh, sh);
+        d
+    }
+
+    /// Add the low halves with carry out, then the high halves
+    /// with carry in.
+    pub fn gen_add_i128(
+        &mut self,
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        self.emit_binary_i128([Opcode::AddCO, Opcode::AddCI], d, a, b)
+    }
+
+    pub fn gen_sub_i128(
+        &mut self,
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        self.emit_binary_i128([Opcode::SubBO, Opcode::SubBI], d, a, b)
+    }
+
+    pub fn gen_and_i128(
+        &mut self,
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        self.emit_binary_i128([Opcode::And; 2], d, a, b)
+    }
+
+    pub fn gen_or_i128(
+        &mut self,
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        self.emit_binary_i128([Opcode::Or; 2], d, a, b)
+    }
+
+    pub fn gen_xor_i128(
+        &mut self,
+        d: TempIdx,
+        a: TempIdx,
+        b: TempIdx,
+    ) -> TempIdx {
+        self.emit_binary_i128([Opcode::Xor; 2], d, a, b)
+    }
+
+    /// Load: dst = *(base + offset), low half first in memory.
+    pub fn gen_ld_i128(
+        &mut self,
+        dst: TempIdx,
+        base: TempIdx,
+        offset: i64,
+    ) -> TempIdx {
+        let (lo, hi) = self.i128_halves(dst);
+        self.gen_ld(Type::I64, lo, base, offset);
+        self.gen_ld(Type::I64, hi, base, offset + 8);
+        dst
+    }
+
+    /// Store: *(base + offset) = src, low half first in memory.
+    pub fn gen_st_i128(&mut self, src: TempIdx, base: TempIdx, offset: i64) {
+        let (lo, hi) = self.i128_halves(src);
+        self.gen_st(Type::I64, lo, base, offset);
+        self.gen_st(Type::I64, hi, base, offset + 8);
+    }
+
     // -- NegSetCond / MovCond --
 
     pub fn gen_negsetcond(
@@ -910,8 +1037,18 @@ impl Context {
         self.emit_op(op);
     }
 
-    /// ExitTb: 0 oargs, 0 iargs, 1 carg (val)
-    pub fn gen_exit_tb(&mut self, val: u64) {
+    /// ExitTb: 0 oargs, 0 iargs, 1 carg (`exit.code()`)
+    pub fn gen_exit_tb(&mut self, exit: TbExit) {
+        self.gen_exit_tb_raw(exit.code() as u64);
+    }
+
+    /// ExitTb with an untyped code, for tests. Panics on codes the
+    /// exit encoding reserves (see [`TbExit`]).
+    pub fn gen_exit_tb_raw(&mut self, val: u64) {
+        assert!(
+            val < TB_EXIT_STACK_OVERFLOW as u64,
+            "exit_tb code {val:#x} is reserved"
+        );
         let idx = self.next_op_idx();
         let op =
             Op::with_args(idx, Opcode::ExitTb, Type::I64, &[carg(val as u32)]);
@@ -947,14 +1084,17 @@ impl Context {
     }
 
     /// Call helper: dst = helper(args[0..6])
-    /// Call: 1 oarg, 6 iargs, 2 cargs (func_lo, func_hi)
+    /// Call: 1 oarg, 6 iargs, 3 cargs (func_lo, func_hi, flags)
+    ///
+    /// `flags` are `helper::CALL_*` bits.
     pub fn gen_call(
         &mut self,
         dst: TempIdx,
         helper: u64,
+        flags: u32,
         args: &[TempIdx],
     ) -> TempIdx {
-        let mut full_args = Vec::with_capacity(1 + 6 + 2);
+        let mut full_args = Vec::with_capacity(1 + 6 + 3);
         full_args.push(dst);
         let zero = self.new_const(Type::I64, 0);
         for i in 0..6 {
@@ -963,6 +1103,7 @@ impl Context {
         }
         full_args.push(carg(helper as u32));
         full_args.push(carg((helper >> 32) as u32));
+        full_args.push(carg(flags));
         let idx = self.next_op_idx();
         let op = Op::with_args(idx, Opcode::Call, Type::I64, &full_args);
         self.emit_op(op);
@@ -984,6 +1125,7 @@ impl Context {
         addr: TempIdx,
         memop: u32,
     ) -> TempIdx {
+        check_memop(ty, memop, false);
         let idx = self.next_op_idx();
         let op =
             Op::with_args(idx, Opcode::QemuLd, ty, &[dst, addr, carg(memop)]);
@@ -998,6 +1140,7 @@ impl Context {
         addr: TempIdx,
         memop: u32,
     ) {
+        check_memop(ty, memop, false);
         let idx = self.next_op_idx();
         let op =
             Op::with_args(idx, Opcode::QemuSt, ty, &[val, addr, carg(memop)]);
@@ -1012,6 +1155,7 @@ impl Context {
         addr: TempIdx,
         memop: u32,
     ) {
+        check_memop(ty, memop, true);
         let idx = self.next_op_idx();
         let op = Op::with_args(
             idx,
@@ -1030,6 +1174,7 @@ impl Context {
         addr: TempIdx,
         memop: u32,
     ) {
+        check_memop(ty, memop, true);
         let idx = self.next_op_idx();
         let op = Op::with_args(
             idx,
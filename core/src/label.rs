@@ -1,3 +1,6 @@
+use crate::op::OpIdx;
+use crate::opcode::Opcode;
+
 /// A branch target label within a translation block.
 ///
 /// Maps to QEMU's `TCGLabel`. Labels support forward references:
@@ -14,6 +17,27 @@ pub struct Label {
     pub value: usize,
     /// Forward references that need back-patching when the label is resolved.
     pub uses: Vec<LabelUse>,
+    /// The `set_label` op that placed this label, for diagnostics.
+    pub bound_at: Option<LabelSite>,
+}
+
+/// The IR op that referenced or placed a label, plus the guest pc
+/// of the nearest preceding `insn_start` when there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelSite {
+    pub op: OpIdx,
+    pub opc: Opcode,
+    pub pc: Option<u64>,
+}
+
+impl std::fmt::Display for LabelSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at op #{}", self.opc.def().name, self.op.0)?;
+        if let Some(pc) = self.pc {
+            write!(f, " (guest pc {pc:#x})")?;
+        }
+        Ok(())
+    }
 }
 
 /// A forward reference to a label — records where a branch instruction
@@ -24,6 +48,8 @@ pub struct LabelUse {
     pub offset: usize,
     /// Type of relocation needed.
     pub kind: RelocKind,
+    /// The branch op that emitted this reference.
+    pub site: LabelSite,
 }
 
 /// Relocation types for label back-patching.
@@ -31,6 +57,35 @@ pub struct LabelUse {
 pub enum RelocKind {
     /// x86-64 RIP-relative 32-bit displacement (at offset+1 from jmp/jcc opcode).
     Rel32,
+    /// AArch64 `B` 26-bit word displacement, relative to the
+    /// branch instruction at the use offset.
+    Imm26,
+    /// AArch64 `B.cond` 19-bit word displacement, relative to
+    /// the branch instruction at the use offset.
+    Imm19,
+}
+
+impl RelocKind {
+    /// Displacement a use at `offset` needs to reach `target`.
+    pub fn disp(self, offset: usize, target: usize) -> i64 {
+        let from = match self {
+            RelocKind::Rel32 => offset + 4,
+            RelocKind::Imm26 | RelocKind::Imm19 => offset,
+        };
+        target as i64 - from as i64
+    }
+
+    /// Whether `disp` can be encoded by this relocation.
+    pub fn fits(self, disp: i64) -> bool {
+        let words = |bits: u32| {
+            disp % 4 == 0 && (disp >> 2) >> (bits - 1) == (disp >> 63)
+        };
+        match self {
+            RelocKind::Rel32 => i32::try_from(disp).is_ok(),
+            RelocKind::Imm26 => words(26),
+            RelocKind::Imm19 => words(19),
+        }
+    }
 }
 
 impl Label {
@@ -41,12 +96,13 @@ impl Label {
             has_value: false,
             value: 0,
             uses: Vec::new(),
+            bound_at: None,
         }
     }
 
     /// Record a forward reference to this label.
-    pub fn add_use(&mut self, offset: usize, kind: RelocKind) {
-        self.uses.push(LabelUse { offset, kind });
+    pub fn add_use(&mut self, offset: usize, kind: RelocKind, site: LabelSite) {
+        self.uses.push(LabelUse { offset, kind, site });
     }
 
     /// Mark this label as placed at the given code buffer offset.
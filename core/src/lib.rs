@@ -1,18 +1,26 @@
 pub mod context;
 pub mod dump;
+pub mod excp;
+pub mod helper;
+pub mod insn_ops;
 pub mod ir_builder;
 pub mod label;
 pub mod op;
 pub mod opcode;
+pub mod pressure;
 pub mod serialize;
 pub mod tb;
 pub mod temp;
 pub mod types;
+pub mod verify;
 
-pub use context::Context;
-pub use label::{Label, LabelUse, RelocKind};
+pub use context::{Context, InsnMeta};
+pub use label::{Label, LabelSite, LabelUse, RelocKind};
 pub use op::{LifeData, Op, OpIdx, MAX_OP_ARGS};
 pub use opcode::{OpDef, OpFlags, Opcode, OPCODE_DEFS};
-pub use tb::{JumpCache, TranslationBlock, TB_HASH_SIZE, TB_JMP_CACHE_SIZE};
+pub use tb::{
+    InsnStarts, JumpCache, TranslationBlock, TB_HASH_SIZE, TB_JMP_CACHE_SIZE,
+};
 pub use temp::{Temp, TempIdx, TempKind};
 pub use types::{Cond, MemOp, RegSet, TempVal, Type};
+pub use verify::{verify, IrError};
@@ -27,10 +27,11 @@ pub enum Opcode {
     DivU2, // unsigned double-width division
 
     // -- Widening multiply --
-    MulSH, // signed multiply high
-    MulUH, // unsigned multiply high
-    MulS2, // signed multiply -> double width
-    MulU2, // unsigned multiply -> double width
+    MulSH,  // signed multiply high
+    MulUH,  // unsigned multiply high
+    MulS2,  // signed multiply -> double width
+    MulU2,  // unsigned multiply -> double width
+    MulSU2, // signed * unsigned -> double width
 
     // -- Carry arithmetic --
     AddCO, // add with carry out
@@ -411,6 +412,14 @@ pub static OPCODE_DEFS: [OpDef; Opcode::Count as usize] = [
         nb_cargs: 0,
         flags: INT,
     },
+    // MulSU2
+    OpDef {
+        name: "mulsu2",
+        nb_oargs: 2,
+        nb_iargs: 2,
+        nb_cargs: 0,
+        flags: INT,
+    },
     // AddCO
     OpDef {
         name: "addco",
@@ -896,7 +905,7 @@ pub static OPCODE_DEFS: [OpDef; Opcode::Count as usize] = [
         name: "call",
         nb_oargs: 1,
         nb_iargs: 6,
-        nb_cargs: 2,
+        nb_cargs: 3,
         flags: f(CC, NP),
     },
     // PluginCb
@@ -1300,6 +1309,13 @@ impl Opcode {
         &OPCODE_DEFS[self as usize]
     }
 
+    /// The opcode numbered `v`, if there is one.
+    pub fn from_u8(v: u8) -> Option<Self> {
+        // SAFETY: Opcode is repr(u8) and v < Count.
+        ((v as usize) < Opcode::Count as usize)
+            .then(|| unsafe { std::mem::transmute::<u8, Opcode>(v) })
+    }
+
     /// Return the fixed IR type this opcode operates on, if not type-polymorphic.
     pub fn fixed_type(self) -> Option<Type> {
         match self {
@@ -0,0 +1,33 @@
+//! Register pressure report, shared by the backend that
+//! computes it and the JSON IR dump that prints it.
+
+use crate::types::{Type, TYPE_COUNT};
+
+/// Register pressure summary for one TB, produced by the
+/// backend's `liveness::pressure_report()`.
+///
+/// A temp counts as register resident from its first
+/// reference until the op where `LifeData` marks it dead,
+/// which is how the allocator holds it. Constants and fixed
+/// temps are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LivenessReport {
+    /// Peak number of simultaneously resident temps.
+    pub max_live: u32,
+    /// Peak per type, indexed by `Type as usize`.
+    pub max_live_by_type: [u32; TYPE_COUNT],
+    /// Op index where `max_live` is first reached.
+    pub max_op: Option<usize>,
+    /// Ops where integer pressure exceeds the allocatable
+    /// register count, i.e. guaranteed spill sites.
+    pub spill_sites: u32,
+    /// Resident temp count at each op, indexed by op index.
+    pub timeline: Vec<u32>,
+}
+
+impl LivenessReport {
+    /// Peak pressure for a single type.
+    pub fn max_live_of(&self, ty: Type) -> u32 {
+        self.max_live_by_type[ty as usize]
+    }
+}
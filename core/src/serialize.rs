@@ -1,10 +1,19 @@
 //! Binary IR serialization/deserialization (.tcgir format).
 //!
 //! Format (little-endian):
-//!   HEADER: magic[4] + version[2] + flags[2] + nb_globals[4]
-//!           + nb_labels[4] + tb_count[4]
-//!   Per TB: STRING TABLE + TEMP SECTION + OP SECTION
+//!   HEADER: magic "TCGIR" + version[2] + byte-order mark[2]
+//!           + flags[2] + tb_count[4]
+//!   META:   present when `flags & FLAG_META` (see `IrMeta`)
+//!   Per TB: RECORD HEADER + STRING TABLE + TEMP SECTION
+//!           + OP SECTION
+//!   RECORD HEADER: nb_ops[4] + nb_temps[4] + nb_globals[4]
+//!           + nb_labels[4] + pc[8]
+//!
+//! Version 3 files (magic "TCIR", globals and labels counted in
+//! the file header, temp and op counts at their sections, no
+//! pc) are still read.
 
+use std::fmt;
 use std::io::{self, Read, Write};
 
 use crate::context::Context;
@@ -13,9 +22,24 @@ use crate::op::{Op, OpIdx, MAX_OP_ARGS};
 use crate::opcode::Opcode;
 use crate::temp::{Temp, TempIdx, TempKind};
 use crate::types::Type;
+use crate::verify::IrError;
+
+const MAGIC: &[u8; 5] = b"TCGIR";
+const VERSION: u16 = 4;
+
+/// Reads back as `0xFFFE` from a file whose writer did not
+/// byte-swap on a big-endian host.
+const BYTE_ORDER_MARK: u16 = 0xFEFF;
+
+/// Magic and version of the format before record headers.
+const LEGACY_MAGIC: &[u8; 4] = b"TCIR";
+const LEGACY_VERSION: u16 = 3;
 
-const MAGIC: &[u8; 4] = b"TCIR";
-const VERSION: u16 = 1;
+/// Record header pc of a TB without `insn_start`.
+const NO_PC: u64 = u64::MAX;
+
+/// Header flag: a metadata block follows the header.
+const FLAG_META: u16 = 1 << 0;
 
 // -- Write helpers --
 
@@ -71,6 +95,34 @@ fn read_i64(r: &mut impl Read) -> io::Result<i64> {
     Ok(i64::from_le_bytes(buf))
 }
 
+/// Write a 16-bit length or count, refusing one that does not
+/// fit rather than truncating it.
+fn write_len16(w: &mut impl Write, len: usize) -> io::Result<()> {
+    let len = u16::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("length {len} exceeds {}", u16::MAX),
+        )
+    })?;
+    write_u16(w, len)
+}
+
+fn write_str(w: &mut impl Write, s: &str) -> io::Result<()> {
+    write_len16(w, s.len())?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u16(r)? as usize;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| err(&format!("invalid UTF-8: {e}")))
+}
+
+/// Cap on the capacity reserved from a count read off disk, so
+/// a corrupt count fails at EOF instead of in the allocator.
+const PREALLOC_MAX: usize = 4096;
+
 fn err(msg: &str) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, msg)
 }
@@ -99,12 +151,7 @@ fn u8_to_type(v: u8) -> io::Result<Type> {
 }
 
 fn u8_to_opcode(v: u8) -> io::Result<Opcode> {
-    if (v as usize) < Opcode::Count as usize {
-        // SAFETY: Opcode is repr(u8) and v < Count.
-        Ok(unsafe { std::mem::transmute::<u8, Opcode>(v) })
-    } else {
-        Err(err("invalid Opcode"))
-    }
+    Opcode::from_u8(v).ok_or_else(|| err("invalid Opcode"))
 }
 
 // -- String table --
@@ -135,9 +182,7 @@ impl StringTable {
     fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
         write_u32(w, self.strings.len() as u32)?;
         for s in &self.strings {
-            let bytes = s.as_bytes();
-            write_u16(w, bytes.len() as u16)?;
-            w.write_all(bytes)?;
+            write_str(w, s)?;
         }
         Ok(())
     }
@@ -145,7 +190,7 @@ impl StringTable {
 
 fn read_string_table(r: &mut impl Read) -> io::Result<Vec<&'static str>> {
     let count = read_u32(r)? as usize;
-    let mut table = Vec::with_capacity(count);
+    let mut table = Vec::with_capacity(count.min(PREALLOC_MAX));
     for _ in 0..count {
         let len = read_u16(r)? as usize;
         let mut buf = vec![0u8; len];
@@ -159,15 +204,330 @@ fn read_string_table(r: &mut impl Read) -> io::Result<Vec<&'static str>> {
     Ok(table)
 }
 
+// -- Metadata --
+
+/// Self-description of a serialized context: where it came
+/// from and how it was translated.
+///
+/// Transport-level only; `Context` does not carry it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IrMeta {
+    /// Guest architecture name, e.g. `"riscv64"`.
+    pub arch: String,
+    /// Lowest and highest guest pc of the `insn_start` ops.
+    /// Computed by `serialize_with_meta`; any value set by the
+    /// caller is ignored.
+    pub pc_range: Option<(u64, u64)>,
+    /// Hash of the ISA configuration used for translation
+    /// (see `IrMeta::hash_cfg`).
+    pub cfg_hash: u64,
+    /// Producing tool and version, e.g. `"tcg-irdump 0.1.0"`.
+    pub producer: String,
+    /// Free-form key/value pairs.
+    pub extra: Vec<(String, String)>,
+}
+
+impl IrMeta {
+    /// FNV-1a hash of an ISA configuration string.
+    pub fn hash_cfg(isa: &str) -> u64 {
+        isa.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        write_str(w, &self.arch)?;
+        let (lo, hi) = self.pc_range.unwrap_or((1, 0));
+        write_u64(w, lo)?;
+        write_u64(w, hi)?;
+        write_u64(w, self.cfg_hash)?;
+        write_str(w, &self.producer)?;
+        write_len16(w, self.extra.len())?;
+        for (k, v) in &self.extra {
+            write_str(w, k)?;
+            write_str(w, v)?;
+        }
+        Ok(())
+    }
+
+    fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let arch = read_str(r)?;
+        let lo = read_u64(r)?;
+        let hi = read_u64(r)?;
+        let cfg_hash = read_u64(r)?;
+        let producer = read_str(r)?;
+        let n = read_u16(r)? as usize;
+        let mut extra = Vec::with_capacity(n);
+        for _ in 0..n {
+            extra.push((read_str(r)?, read_str(r)?));
+        }
+        Ok(Self {
+            arch,
+            // An empty range (lo > hi) encodes "no insn_start".
+            pc_range: (lo <= hi).then_some((lo, hi)),
+            cfg_hash,
+            producer,
+            extra,
+        })
+    }
+}
+
+impl fmt::Display for IrMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.arch)?;
+        if let Some((lo, hi)) = self.pc_range {
+            write!(f, " pc {lo:#x}..={hi:#x}")?;
+        }
+        write!(f, " cfg {:#018x} by {}", self.cfg_hash, self.producer)?;
+        for (k, v) in &self.extra {
+            write!(f, " {k}={v}")?;
+        }
+        Ok(())
+    }
+}
+
+fn insn_pcs(ctx: &Context) -> impl Iterator<Item = u64> + '_ {
+    ctx.ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::InsnStart)
+        .map(|op| {
+            let c = op.cargs();
+            c[0].0 as u64 | (c[1].0 as u64) << 32
+        })
+}
+
+/// Guest pc of the first `insn_start` of `ctx`.
+pub fn first_pc(ctx: &Context) -> Option<u64> {
+    insn_pcs(ctx).next()
+}
+
+/// Guest pc range covered by the `insn_start` ops of `ctx`.
+pub fn pc_range(ctx: &Context) -> Option<(u64, u64)> {
+    insn_pcs(ctx).fold(None, |acc, pc| match acc {
+        None => Some((pc, pc)),
+        Some((lo, hi)) => Some((lo.min(pc), hi.max(pc))),
+    })
+}
+
+/// A deserialized context with its metadata, if any.
+pub struct IrRecord {
+    pub ctx: Context,
+    pub meta: Option<IrMeta>,
+    /// Guest pc of the first `insn_start`.
+    pub pc: Option<u64>,
+}
+
+/// Why a .tcgir stream could not be read.
+#[derive(Debug)]
+pub enum DeserializeError {
+    /// The reader failed.
+    Io(io::Error),
+    /// A file header starts with neither magic.
+    BadMagic,
+    /// A file header carries a version this build cannot read.
+    UnsupportedVersion(u16),
+    /// The byte-order mark is not `0xFEFF`.
+    ByteOrder(u16),
+    /// The input ends inside a file header.
+    TruncatedHeader,
+    /// The input ends inside TB `tb`, or before it although
+    /// the file header counts it.
+    Truncated { tb: usize },
+    /// TB `tb` holds a value out of range.
+    Corrupt { tb: usize, msg: String },
+    /// The record header pc of TB `tb` is not that of its first
+    /// `insn_start`.
+    PcMismatch {
+        tb: usize,
+        header: Option<u64>,
+        found: Option<u64>,
+    },
+    /// TB `tb` fails `Context::validate`.
+    InvalidIr { tb: usize, err: IrError },
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pc = |pc: &Option<u64>| match pc {
+            Some(pc) => format!("{pc:#x}"),
+            None => "none".to_string(),
+        };
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::BadMagic => write!(f, "bad magic"),
+            Self::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported .tcgir version {v} \
+                     (this build reads {VERSION} and legacy {LEGACY_VERSION})"
+                )
+            }
+            Self::ByteOrder(0xFFFE) => write!(f, "file is big-endian"),
+            Self::ByteOrder(m) => write!(f, "bad byte-order mark {m:#06x}"),
+            Self::TruncatedHeader => write!(f, "file truncated in header"),
+            Self::Truncated { tb } => write!(f, "TB #{tb}: file truncated"),
+            Self::Corrupt { tb, msg } => write!(f, "TB #{tb}: {msg}"),
+            Self::PcMismatch { tb, header, found } => write!(
+                f,
+                "TB #{tb}: header pc {}, first insn_start pc {}",
+                pc(header),
+                pc(found)
+            ),
+            Self::InvalidIr { tb, err } => {
+                write!(f, "TB #{tb}: invalid IR: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::InvalidIr { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Problem found by `check_records`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaWarning {
+    /// A record's arch differs from the expected one.
+    ArchMismatch {
+        tb: usize,
+        found: String,
+        expected: String,
+    },
+    /// A record's metadata disagrees with the first record's.
+    Inconsistent {
+        tb: usize,
+        field: &'static str,
+        found: String,
+        first: String,
+    },
+    /// A record lacks metadata while the first one has it, or
+    /// vice versa.
+    Missing { tb: usize },
+}
+
+impl fmt::Display for MetaWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaWarning::ArchMismatch {
+                tb,
+                found,
+                expected,
+            } => write!(f, "TB #{tb}: arch {found}, expected {expected}"),
+            MetaWarning::Inconsistent {
+                tb,
+                field,
+                found,
+                first,
+            } => write!(
+                f,
+                "TB #{tb}: {field} {found} differs from TB #0 ({first})"
+            ),
+            MetaWarning::Missing { tb } => {
+                write!(f, "TB #{tb}: metadata presence differs from TB #0")
+            }
+        }
+    }
+}
+
+/// Check that `records` agree on arch, ISA configuration and
+/// producer, and (if given) that their arch is `expect_arch`.
+pub fn check_records(
+    records: &[IrRecord],
+    expect_arch: Option<&str>,
+) -> Vec<MetaWarning> {
+    let mut warnings = Vec::new();
+    let first = records.first().and_then(|r| r.meta.as_ref());
+    for (tb, rec) in records.iter().enumerate() {
+        let Some(meta) = &rec.meta else {
+            if first.is_some() {
+                warnings.push(MetaWarning::Missing { tb });
+            }
+            continue;
+        };
+        if let Some(expected) = expect_arch {
+            if meta.arch != expected {
+                warnings.push(MetaWarning::ArchMismatch {
+                    tb,
+                    found: meta.arch.clone(),
+                    expected: expected.to_string(),
+                });
+            }
+        }
+        let Some(first) = first else {
+            warnings.push(MetaWarning::Missing { tb });
+            continue;
+        };
+        let fields = [
+            ("arch", meta.arch.clone(), first.arch.clone()),
+            (
+                "cfg",
+                format!("{:#x}", meta.cfg_hash),
+                format!("{:#x}", first.cfg_hash),
+            ),
+            ("producer", meta.producer.clone(), first.producer.clone()),
+        ];
+        for (field, found, first) in fields {
+            if found != first {
+                warnings.push(MetaWarning::Inconsistent {
+                    tb,
+                    field,
+                    found,
+                    first,
+                });
+            }
+        }
+    }
+    warnings
+}
+
 /// Serialize a single TB's Context to binary .tcgir format.
 pub fn serialize(ctx: &Context, w: &mut impl Write) -> io::Result<()> {
+    write_record(ctx, None, w)
+}
+
+/// Like `serialize`, with a metadata block; the pc range is
+/// computed from `ctx`.
+pub fn serialize_with_meta(
+    ctx: &Context,
+    meta: &IrMeta,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    write_record(ctx, Some(meta), w)
+}
+
+fn write_record(
+    ctx: &Context,
+    meta: Option<&IrMeta>,
+    w: &mut impl Write,
+) -> io::Result<()> {
     // -- Header --
     w.write_all(MAGIC)?;
     write_u16(w, VERSION)?;
-    write_u16(w, 0)?; // flags
+    write_u16(w, BYTE_ORDER_MARK)?;
+    write_u16(w, if meta.is_some() { FLAG_META } else { 0 })?;
+    write_u32(w, 1)?; // tb_count = 1
+
+    // -- Metadata --
+    if let Some(meta) = meta {
+        let meta = IrMeta {
+            pc_range: pc_range(ctx),
+            ..meta.clone()
+        };
+        meta.write_to(w)?;
+    }
+
+    // -- Record header --
+    write_u32(w, ctx.ops().len() as u32)?;
+    write_u32(w, ctx.temps().len() as u32)?;
     write_u32(w, ctx.nb_globals())?;
     write_u32(w, ctx.labels().len() as u32)?;
-    write_u32(w, 1)?; // tb_count = 1
+    write_u64(w, first_pc(ctx).unwrap_or(NO_PC))?;
 
     // -- Build string table --
     let mut strtab = StringTable::new();
@@ -182,7 +542,6 @@ pub fn serialize(ctx: &Context, w: &mut impl Write) -> io::Result<()> {
     strtab.write_to(w)?;
 
     // -- Temps --
-    write_u32(w, ctx.temps().len() as u32)?;
     for (i, t) in ctx.temps().iter().enumerate() {
         write_u8(w, t.kind as u8)?;
         write_u8(w, t.ty as u8)?;
@@ -195,7 +554,6 @@ pub fn serialize(ctx: &Context, w: &mut impl Write) -> io::Result<()> {
     }
 
     // -- Ops --
-    write_u32(w, ctx.ops().len() as u32)?;
     for op in ctx.ops() {
         write_u8(w, op.opc as u8)?;
         write_u8(w, op.op_type as u8)?;
@@ -213,48 +571,173 @@ pub fn serialize(ctx: &Context, w: &mut impl Write) -> io::Result<()> {
 
 /// Deserialize a .tcgir file into a Vec of Contexts (one per TB).
 /// Handles concatenated .tcgir files (each with its own header).
-pub fn deserialize(r: &mut impl Read) -> io::Result<Vec<Context>> {
-    let mut contexts = Vec::new();
-    loop {
-        // Try to read magic; EOF here is normal termination.
-        let mut magic = [0u8; 4];
-        match r.read_exact(&mut magic) {
-            Ok(()) => {}
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                break;
+pub fn deserialize(
+    r: &mut impl Read,
+) -> Result<Vec<Context>, DeserializeError> {
+    Ok(deserialize_records(r)?.into_iter().map(|r| r.ctx).collect())
+}
+
+/// Like `deserialize`, keeping each context's metadata.
+pub fn deserialize_records(
+    r: &mut impl Read,
+) -> Result<Vec<IrRecord>, DeserializeError> {
+    let mut records = Vec::new();
+    while let Some(file) = read_file_header(r)? {
+        let meta = if file.flags & FLAG_META != 0 {
+            let tb = records.len();
+            Some(IrMeta::read_from(r).map_err(|e| record_err(tb, e))?)
+        } else {
+            None
+        };
+        for _ in 0..file.tb_count {
+            let tb = records.len();
+            let hdr = match file.legacy {
+                Some((nb_globals, nb_labels)) => RecordHeader {
+                    nb_ops: None,
+                    nb_temps: None,
+                    nb_globals,
+                    nb_labels,
+                    pc: None,
+                },
+                None => read_record_header(r).map_err(|e| record_err(tb, e))?,
+            };
+            let ctx =
+                deserialize_one_tb(r, &hdr).map_err(|e| record_err(tb, e))?;
+            let pc = first_pc(&ctx);
+            if let Some(header) = hdr.pc {
+                let header = (header != NO_PC).then_some(header);
+                if header != pc {
+                    return Err(DeserializeError::PcMismatch {
+                        tb,
+                        header,
+                        found: pc,
+                    });
+                }
             }
-            Err(e) => return Err(e),
+            ctx.validate()
+                .map_err(|err| DeserializeError::InvalidIr { tb, err })?;
+            records.push(IrRecord {
+                ctx,
+                meta: meta.clone(),
+                pc,
+            });
         }
-        if &magic != MAGIC {
-            return Err(err("bad magic"));
+    }
+    Ok(records)
+}
+
+/// Classify an error met while reading TB `tb`.
+fn record_err(tb: usize, e: io::Error) -> DeserializeError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => DeserializeError::Truncated { tb },
+        io::ErrorKind::InvalidData => DeserializeError::Corrupt {
+            tb,
+            msg: e.to_string(),
+        },
+        _ => DeserializeError::Io(e),
+    }
+}
+
+struct FileHeader {
+    flags: u16,
+    tb_count: u32,
+    /// `nb_globals` and `nb_labels` of a legacy file, shared by
+    /// all its TBs.
+    legacy: Option<(u32, u32)>,
+}
+
+/// Read a file header, or `None` at the end of the input.
+fn read_file_header(
+    r: &mut impl Read,
+) -> Result<Option<FileHeader>, DeserializeError> {
+    let mut magic = [0u8; 5];
+    loop {
+        match r.read(&mut magic[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(DeserializeError::Io(e)),
         }
-        let version = read_u16(r)?;
-        if version != VERSION {
-            return Err(err("unsupported version"));
+    }
+    let eof = |e: io::Error| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            DeserializeError::TruncatedHeader
+        } else {
+            DeserializeError::Io(e)
         }
-        let _flags = read_u16(r)?;
-        let nb_globals = read_u32(r)?;
-        let _nb_labels = read_u32(r)?;
-        let tb_count = read_u32(r)? as usize;
-
-        for _ in 0..tb_count {
-            let ctx = deserialize_one_tb(r, nb_globals)?;
-            contexts.push(ctx);
+    };
+    r.read_exact(&mut magic[1..4]).map_err(eof)?;
+    if &magic[..4] == LEGACY_MAGIC {
+        let version = read_u16(r).map_err(eof)?;
+        if version != LEGACY_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
         }
+        let flags = read_u16(r).map_err(eof)?;
+        let nb_globals = read_u32(r).map_err(eof)?;
+        let nb_labels = read_u32(r).map_err(eof)?;
+        let tb_count = read_u32(r).map_err(eof)?;
+        return Ok(Some(FileHeader {
+            flags,
+            tb_count,
+            legacy: Some((nb_globals, nb_labels)),
+        }));
+    }
+    r.read_exact(&mut magic[4..]).map_err(eof)?;
+    if &magic != MAGIC {
+        return Err(DeserializeError::BadMagic);
+    }
+    let version = read_u16(r).map_err(eof)?;
+    if version != VERSION {
+        return Err(DeserializeError::UnsupportedVersion(version));
     }
-    Ok(contexts)
+    let bom = read_u16(r).map_err(eof)?;
+    if bom != BYTE_ORDER_MARK {
+        return Err(DeserializeError::ByteOrder(bom));
+    }
+    let flags = read_u16(r).map_err(eof)?;
+    let tb_count = read_u32(r).map_err(eof)?;
+    Ok(Some(FileHeader {
+        flags,
+        tb_count,
+        legacy: None,
+    }))
+}
+
+/// Counts of one TB. Legacy files leave the temp and op counts
+/// to their sections and carry no pc.
+struct RecordHeader {
+    nb_ops: Option<u32>,
+    nb_temps: Option<u32>,
+    nb_globals: u32,
+    nb_labels: u32,
+    pc: Option<u64>,
+}
+
+fn read_record_header(r: &mut impl Read) -> io::Result<RecordHeader> {
+    Ok(RecordHeader {
+        nb_ops: Some(read_u32(r)?),
+        nb_temps: Some(read_u32(r)?),
+        nb_globals: read_u32(r)?,
+        nb_labels: read_u32(r)?,
+        pc: Some(read_u64(r)?),
+    })
 }
 
 fn deserialize_one_tb(
     r: &mut impl Read,
-    nb_globals: u32,
+    hdr: &RecordHeader,
 ) -> io::Result<Context> {
+    let (nb_globals, nb_labels) = (hdr.nb_globals, hdr.nb_labels);
+
     // -- String table --
     let strtab = read_string_table(r)?;
 
     // -- Temps --
-    let temp_count = read_u32(r)? as usize;
-    let mut temps = Vec::with_capacity(temp_count);
+    let temp_count = match hdr.nb_temps {
+        Some(n) => n,
+        None => read_u32(r)?,
+    } as usize;
+    let mut temps = Vec::with_capacity(temp_count.min(PREALLOC_MAX));
     for i in 0..temp_count {
         let kind = u8_to_kind(read_u8(r)?)?;
         let ty = u8_to_type(read_u8(r)?)?;
@@ -270,6 +753,9 @@ fn deserialize_one_tb(
         let mem_base = if mem_base_raw == 0xFFFF_FFFF {
             None
         } else {
+            if mem_base_raw as usize >= temp_count {
+                return Err(err("temp mem_base out of range"));
+            }
             Some(TempIdx(mem_base_raw))
         };
         let mem_offset = read_i64(r)?;
@@ -277,7 +763,8 @@ fn deserialize_one_tb(
         let name = if name_idx == 0xFFFF_FFFF {
             None
         } else {
-            Some(strtab[name_idx as usize])
+            let name = strtab.get(name_idx as usize);
+            Some(*name.ok_or_else(|| err("temp name out of range"))?)
         };
 
         temps.push(Temp {
@@ -301,15 +788,34 @@ fn deserialize_one_tb(
         });
     }
 
+    if nb_globals as usize > temp_count {
+        return Err(err("more globals than temps"));
+    }
+    let is_global =
+        |t: &Temp| matches!(t.kind, TempKind::Global | TempKind::Fixed);
+    if temps
+        .iter()
+        .enumerate()
+        .any(|(i, t)| is_global(t) != (i < nb_globals as usize))
+    {
+        return Err(err("nb_globals does not match the temp kinds"));
+    }
+
     // -- Ops --
-    let op_count = read_u32(r)? as usize;
-    let mut ops = Vec::with_capacity(op_count);
+    let op_count = match hdr.nb_ops {
+        Some(n) => n,
+        None => read_u32(r)?,
+    } as usize;
+    let mut ops = Vec::with_capacity(op_count.min(PREALLOC_MAX));
     for i in 0..op_count {
         let opc = u8_to_opcode(read_u8(r)?)?;
         let op_type = u8_to_type(read_u8(r)?)?;
         let param1 = read_u8(r)?;
         let param2 = read_u8(r)?;
         let nargs = read_u8(r)?;
+        if nargs as usize > MAX_OP_ARGS {
+            return Err(err("too many op args"));
+        }
         let mut pad = [0u8; 3];
         r.read_exact(&mut pad)?;
         let mut args = [TempIdx(0); MAX_OP_ARGS];
@@ -325,19 +831,25 @@ fn deserialize_one_tb(
     }
 
     // -- Labels: create fresh labels based on ops --
-    let mut labels = Vec::new();
+    let mut labels = Vec::with_capacity((nb_labels as usize).min(PREALLOC_MAX));
     for op in &ops {
         if op.opc == Opcode::SetLabel {
             let id = op.args[0].0;
+            if id >= nb_labels {
+                return Err(err("label out of range"));
+            }
             while labels.len() <= id as usize {
                 labels.push(Label::new(labels.len() as u32));
             }
         }
-        if op.opc == Opcode::Br || op.opc == Opcode::BrCond {
+        if matches!(op.opc, Opcode::Br | Opcode::BrCond | Opcode::BrCond2I32) {
             let def = op.opc.def();
             let label_pos =
                 (def.nb_oargs + def.nb_iargs + def.nb_cargs - 1) as usize;
             let id = op.args[label_pos].0;
+            if id >= nb_labels {
+                return Err(err("label out of range"));
+            }
             while labels.len() <= id as usize {
                 labels.push(Label::new(labels.len() as u32));
             }
@@ -1,9 +1,38 @@
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use std::sync::Mutex;
 
 /// Sentinel value for "no exit target cached".
 pub const EXIT_TARGET_NONE: usize = usize::MAX;
 
+/// Guest page size used for TB page tracking.
+pub const TARGET_PAGE_BITS: u32 = 12;
+pub const TARGET_PAGE_MASK: u64 = !((1u64 << TARGET_PAGE_BITS) - 1);
+
+/// Lifecycle of a TB as seen by chaining and invalidation.
+///
+/// Transitions happen under the TB's `jmp` lock so that a
+/// chain into a TB and its invalidation are serialized:
+/// `Live → Chained` when the first incoming jump is patched,
+/// `Live | Chained → Dead` on invalidation. A `Dead` TB is
+/// never chained into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TbState {
+    Live = 0,
+    Chained = 1,
+    Dead = 2,
+}
+
+impl TbState {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => TbState::Live,
+            1 => TbState::Chained,
+            _ => TbState::Dead,
+        }
+    }
+}
+
 /// Mutable chaining state protected by per-TB lock.
 pub struct TbJmpState {
     /// Outgoing edge: destination TB index for each slot.
@@ -21,6 +50,70 @@ impl TbJmpState {
     }
 }
 
+/// Guest instruction start offsets of a TB, relative to its pc.
+///
+/// Each byte is the distance from the previous start (the first
+/// from offset 0). RISC-V distances are 2 or 4; anything that
+/// does not fit below `INSN_DELTA_WIDE` is stored as that
+/// escape byte followed by a little-endian u16. Empty when the
+/// frontend emitted no `insn_start` markers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InsnStarts {
+    enc: Box<[u8]>,
+}
+
+/// Escape byte of a u16 `InsnStarts` delta.
+pub const INSN_DELTA_WIDE: u8 = 0xff;
+
+impl InsnStarts {
+    /// Encode ascending start offsets.
+    pub fn from_offsets(offsets: impl IntoIterator<Item = u32>) -> Self {
+        let mut enc = Vec::new();
+        let mut prev = 0;
+        for off in offsets {
+            let delta = off - prev;
+            prev = off;
+            if delta < INSN_DELTA_WIDE as u32 {
+                enc.push(delta as u8);
+            } else {
+                let delta = u16::try_from(delta).expect("TB wider than 64 KiB");
+                enc.push(INSN_DELTA_WIDE);
+                enc.extend_from_slice(&delta.to_le_bytes());
+            }
+        }
+        Self {
+            enc: enc.into_boxed_slice(),
+        }
+    }
+
+    /// Start offsets, ascending.
+    pub fn offsets(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut i = 0;
+        let mut off = 0;
+        std::iter::from_fn(move || {
+            let b = *self.enc.get(i)?;
+            let delta = if b == INSN_DELTA_WIDE {
+                i += 3;
+                u16::from_le_bytes([self.enc[i - 2], self.enc[i - 1]]) as u32
+            } else {
+                i += 1;
+                b as u32
+            };
+            off += delta;
+            Some(off)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.enc.is_empty()
+    }
+
+    /// Heap bytes used by the encoding.
+    pub fn encoded_len(&self) -> usize {
+        self.enc.len()
+    }
+}
+
 /// A cached translated code block.
 ///
 /// Maps to QEMU's `TranslationBlock`. Represents the mapping
@@ -28,7 +121,7 @@ impl TbJmpState {
 ///
 /// Fields above `jmp` are immutable after creation (set during
 /// translation under translate_lock). The `jmp` mutex protects
-/// mutable chaining state. `invalid` is atomic for lock-free
+/// mutable chaining state. `state` is atomic for lock-free
 /// checking.
 pub struct TranslationBlock {
     // -- Immutable after creation --
@@ -43,6 +136,10 @@ pub struct TranslationBlock {
     pub jmp_insn_offset: [Option<u32>; 2],
     pub jmp_reset_offset: [Option<u32>; 2],
     pub phys_pc: u64,
+    /// Probable busy-wait loop (`TranslationInfo::spin_loop`).
+    pub spin_loop: bool,
+    /// Where each translated guest instruction starts.
+    pub insn_offsets: InsnStarts,
     /// Protected by TbStore hash lock.
     pub hash_next: Option<usize>,
 
@@ -50,10 +147,15 @@ pub struct TranslationBlock {
     pub jmp: Mutex<TbJmpState>,
 
     // -- Atomic --
-    pub invalid: AtomicBool,
+    /// `TbState`; written only under the `jmp` lock.
+    pub state: AtomicU8,
     /// Single-entry target cache for indirect exits (atomic,
     /// lock-free). EXIT_TARGET_NONE means no cached target.
     pub exit_target: AtomicUsize,
+    /// CRC-32 of the TB's host code, kept current across jump
+    /// patches (`verify-code` builds only).
+    #[cfg(feature = "verify-code")]
+    pub code_sum: std::sync::atomic::AtomicU32,
 }
 
 /// Compile flags for TranslationBlock.cflags.
@@ -68,6 +170,42 @@ pub mod cflags {
     pub const CF_USE_ICOUNT: u32 = 0x0004_0000;
 }
 
+/// TB termination reason set by a frontend's `translate_insn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisasJumpType {
+    /// Continue to the next sequential instruction.
+    Next,
+    /// Reached the maximum number of instructions per TB.
+    TooMany,
+    /// Unconditional branch / exit — no fall-through.
+    NoReturn,
+}
+
+/// What a frontend translated into one TB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranslationInfo {
+    /// Guest bytes covered, `next_pc - first_pc`; instructions
+    /// may differ in length (RVC).
+    pub guest_len_bytes: u32,
+    pub guest_insns: u32,
+    /// How the last instruction ended the TB.
+    pub is_jmp: DisasJumpType,
+    pub first_pc: u64,
+    /// pc following the last translated instruction.
+    pub next_pc: u64,
+    /// The TB looks like a spin on one memory word: it loads
+    /// the same address every iteration, stores nothing and
+    /// branches back to its own start. A scheduling hint only.
+    pub spin_loop: bool,
+}
+
+/// `TranslationBlock::flags` bit: translate exactly one guest
+/// instruction and exit without chaining.
+///
+/// Part of the TB lookup key, so a single-step translation and
+/// the normal translation of the same pc coexist in the store.
+pub const TB_FLAG_SINGLE_STEP: u32 = 0x8000_0000;
+
 impl std::fmt::Debug for TranslationBlock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TranslationBlock")
@@ -76,7 +214,7 @@ impl std::fmt::Debug for TranslationBlock {
             .field("size", &self.size)
             .field("host_offset", &self.host_offset)
             .field("host_size", &self.host_size)
-            .field("invalid", &self.invalid.load(Ordering::Relaxed))
+            .field("state", &self.state())
             .finish()
     }
 }
@@ -95,13 +233,73 @@ impl TranslationBlock {
             jmp_insn_offset: [None; 2],
             jmp_reset_offset: [None; 2],
             phys_pc: 0,
+            spin_loop: false,
+            insn_offsets: InsnStarts::default(),
             hash_next: None,
             jmp: Mutex::new(TbJmpState::new()),
-            invalid: AtomicBool::new(false),
+            state: AtomicU8::new(TbState::Live as u8),
             exit_target: AtomicUsize::new(EXIT_TARGET_NONE),
+            #[cfg(feature = "verify-code")]
+            code_sum: std::sync::atomic::AtomicU32::new(0),
         }
     }
 
+    pub fn state(&self) -> TbState {
+        TbState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    pub fn is_invalid(&self) -> bool {
+        self.state() == TbState::Dead
+    }
+
+    /// Move `Live → Chained`. Returns false if the TB is dead.
+    /// Caller must hold the `jmp` lock.
+    pub fn mark_chained(&self) -> bool {
+        match self.state.compare_exchange(
+            TbState::Live as u8,
+            TbState::Chained as u8,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => true,
+            Err(cur) => TbState::from_u8(cur) == TbState::Chained,
+        }
+    }
+
+    /// Mark the TB dead. Caller must hold the `jmp` lock.
+    pub fn mark_dead(&self) {
+        self.state.store(TbState::Dead as u8, Ordering::Release);
+    }
+
+    /// Guest page holding the first instruction.
+    pub fn first_page(&self) -> u64 {
+        self.pc & TARGET_PAGE_MASK
+    }
+
+    /// Guest page holding the last instruction.
+    pub fn last_page(&self) -> u64 {
+        (self.pc + (self.size as u64).max(1) - 1) & TARGET_PAGE_MASK
+    }
+
+    /// Guest pc of every translated instruction, ascending.
+    pub fn insn_starts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.insn_offsets.offsets().map(|off| self.pc + off as u64)
+    }
+
+    /// `pc` is where one of this TB's instructions starts.
+    pub fn contains_insn_at(&self, pc: u64) -> bool {
+        pc >= self.pc && self.insn_starts().any(|s| s == pc)
+    }
+
+    /// Start of the translated instruction covering `pc`, if
+    /// `pc` lies inside this TB and its boundaries are known.
+    pub fn insn_containing(&self, pc: u64) -> Option<u64> {
+        if pc < self.pc || pc >= self.pc + self.size as u64 {
+            return None;
+        }
+        self.insn_starts().take_while(|&s| s <= pc).last()
+    }
+
     /// Compute hash bucket index for TB lookup.
     pub fn hash(pc: u64, flags: u32) -> usize {
         let h = pc.wrapping_mul(0x9e3779b97f4a7c15) ^ (flags as u64);
@@ -137,56 +335,128 @@ pub const TB_HASH_SIZE: usize = 1 << 15; // 32768
 /// Number of entries in the per-CPU jump cache.
 pub const TB_JMP_CACHE_SIZE: usize = 1 << 12; // 4096
 
-/// TB exit value encoding (following QEMU `TB_EXIT_*` convention).
+/// Exit codes (following QEMU `TB_EXIT_*` convention).
+///
+/// The low codes are reserved for the exec loop's internal TB
+/// chaining protocol; see [`TbExit`] for the full layout.
+pub const TB_EXIT_IDX0: u32 = 0;
+pub const TB_EXIT_IDX1: u32 = 1;
+pub const TB_EXIT_NOCHAIN: u32 = 2;
+pub const TB_EXIT_MAX: u32 = 3;
+
+/// First code of [`TbExit::Custom`].
+pub const TB_EXIT_CUSTOM: u32 = 0x8000_0000;
+
+/// A helper panicked; see `tcg_core::helper`. Emitted by the
+/// backend's post-call check, never by a frontend.
+pub const TB_EXIT_HELPER_PANIC: u32 = u32::MAX;
+
+/// The host stack reached its red zone at a TB entry; see
+/// `tcg_core::helper::set_stack_limit`. Emitted by the backend's
+/// entry check in debug builds, never by a frontend.
+pub const TB_EXIT_STACK_OVERFLOW: u32 = u32::MAX - 1;
+
+/// Why generated code returned to the exec loop.
 ///
-/// The low values are reserved for the exec loop's internal TB
-/// chaining protocol.  Real guest exits (ECALL, EBREAK, etc.)
-/// must use values >= `TB_EXIT_MAX`.
+/// `exit_tb` carries a 32-bit code; the backend widens it to the
+/// `usize` the prologue returns, adding the source TB for the
+/// codes the exec loop chains from:
 ///
-/// | Value | Constant | Meaning |
-/// |-------|----------|---------|
-/// | 0 | `TB_EXIT_IDX0` | `goto_tb` slot 0 — chainable |
-/// | 1 | `TB_EXIT_IDX1` | `goto_tb` slot 1 — chainable |
-/// | 2 | `TB_EXIT_NOCHAIN` | Indirect jump — look up by PC |
-/// | >=3 | `TB_EXIT_MAX`.. | Real exit — returned to caller |
-pub const TB_EXIT_IDX0: u64 = 0;
-pub const TB_EXIT_IDX1: u64 = 1;
-pub const TB_EXIT_NOCHAIN: u64 = 2;
-pub const TB_EXIT_MAX: u64 = 3;
-
-/// Guest exception exit codes (must be >= `TB_EXIT_MAX`).
-pub const EXCP_ECALL: u64 = TB_EXIT_MAX;
-pub const EXCP_EBREAK: u64 = TB_EXIT_MAX + 1;
-pub const EXCP_UNDEF: u64 = TB_EXIT_MAX + 2;
-
-/// Encode an exit_tb return value with the source TB index.
+/// | Bits 63..32 | Bits 31..0 | Exit |
+/// |-------------|------------|------|
+/// | `tb + 1` | 0, 1 | `Chain(slot)` — chainable |
+/// | `tb + 1` | 2 | `Normal` — look up by PC |
+/// | 0 | 3..`TB_EXIT_CUSTOM` | `Exception(code)` |
+/// | 0 | `TB_EXIT_CUSTOM`..`u32::MAX - 1` | `Custom(code - TB_EXIT_CUSTOM)` |
+/// | 0 | `u32::MAX - 1` | `StackOverflow` |
+/// | 0 | `u32::MAX` | `HelperPanic` |
 ///
-/// For chainable exits (val < `TB_EXIT_MAX`), the upper 32 bits
-/// carry `tb_idx + 1` so the exec loop can identify which TB
-/// actually exited after direct chaining.  Real exits (val >=
-/// `TB_EXIT_MAX`) are returned unchanged.
-#[inline]
-pub fn encode_tb_exit(tb_idx: u32, val: u64) -> u64 {
-    if val < TB_EXIT_MAX {
-        ((tb_idx as u64 + 1) << 32) | val
-    } else {
-        val
-    }
+/// Raw codes with non-zero high bits, and the top two codes,
+/// are reserved: a frontend cannot produce them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TbExit {
+    /// Fell through `goto_tb` slot 0 or 1, not yet chained.
+    Chain(usize),
+    /// Next TB is found by PC (indirect jump, single step).
+    Normal,
+    /// Exception code, returned to the caller; see
+    /// [`crate::excp`] for who owns which codes.
+    Exception(u32),
+    /// Frontend-defined exit, returned to the caller.
+    Custom(u32),
+    /// A helper panicked.
+    HelperPanic,
+    /// The host stack ran into its red zone.
+    StackOverflow,
 }
 
-/// Decode an exit_tb return value.
-///
-/// Returns `(source_tb_idx, exit_code)`.  For chainable exits
-/// `source_tb_idx` is `Some(idx)`; for real exits it is `None`.
-#[inline]
-pub fn decode_tb_exit(raw: usize) -> (Option<usize>, usize) {
-    let marker = raw >> 32;
-    if marker != 0 {
-        let tb_idx = marker - 1;
-        let slot = raw & 3;
-        (Some(tb_idx), slot)
-    } else {
-        (None, raw)
+impl TbExit {
+    /// The `exit_tb` operand for this exit.
+    pub fn code(self) -> u32 {
+        match self {
+            Self::Chain(slot) => {
+                assert!(slot < 2, "goto_tb slot {slot}");
+                TB_EXIT_IDX0 + slot as u32
+            }
+            Self::Normal => TB_EXIT_NOCHAIN,
+            Self::Exception(n) => {
+                assert!(
+                    (TB_EXIT_MAX..TB_EXIT_CUSTOM).contains(&n),
+                    "exception {n:#x} outside the exception range"
+                );
+                n
+            }
+            Self::Custom(n) => {
+                assert!(
+                    n < TB_EXIT_STACK_OVERFLOW - TB_EXIT_CUSTOM,
+                    "custom exit {n:#x} out of range"
+                );
+                TB_EXIT_CUSTOM + n
+            }
+            Self::HelperPanic => TB_EXIT_HELPER_PANIC,
+            Self::StackOverflow => TB_EXIT_STACK_OVERFLOW,
+        }
+    }
+
+    /// The exit an `exit_tb` operand stands for.
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            TB_EXIT_IDX0 | TB_EXIT_IDX1 => Self::Chain(code as usize),
+            TB_EXIT_NOCHAIN => Self::Normal,
+            TB_EXIT_HELPER_PANIC => Self::HelperPanic,
+            TB_EXIT_STACK_OVERFLOW => Self::StackOverflow,
+            c if c >= TB_EXIT_CUSTOM => Self::Custom(c - TB_EXIT_CUSTOM),
+            c => Self::Exception(c),
+        }
+    }
+
+    /// Whether the exec loop continues with another TB.
+    pub fn chains(self) -> bool {
+        matches!(self, Self::Chain(_) | Self::Normal)
+    }
+
+    /// The value generated code returns for `code` leaving TB
+    /// `tb_idx`.
+    #[inline]
+    pub fn encode(tb_idx: u32, code: u32) -> u64 {
+        if code < TB_EXIT_MAX {
+            ((tb_idx as u64 + 1) << 32) | code as u64
+        } else {
+            code as u64
+        }
+    }
+
+    /// Split a value returned by generated code into the TB that
+    /// exited (for chainable exits) and the exit.
+    #[inline]
+    pub fn decode(raw: usize) -> (Option<usize>, Self) {
+        let marker = raw >> 32;
+        let exit = Self::from_code(raw as u32);
+        debug_assert!(
+            marker == 0 || exit.chains(),
+            "TB marker on a real exit: {raw:#x}"
+        );
+        (marker.checked_sub(1), exit)
     }
 }
 
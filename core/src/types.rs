@@ -132,9 +132,11 @@ impl Cond {
 }
 
 /// Memory operation descriptor — encodes size, signedness,
-/// endianness, alignment.
+/// endianness, alignment and atomicity.
 ///
 /// Maps to QEMU's `MemOp`. Bit-packed for compact storage in IR ops.
+/// Without an alignment the access may be at any address; a
+/// frontend wanting alignment faults checks it before the access.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MemOp(u16);
 
@@ -152,6 +154,14 @@ impl MemOp {
     pub const ALIGN_16: u16 = 4 << 4;
     pub const ALIGN_32: u16 = 5 << 4;
     pub const ALIGN_64: u16 = 6 << 4;
+    /// Aligned to the access size.
+    pub const ALIGN: u16 = 7 << 4;
+    pub const ALIGN_MASK: u16 = 7 << 4;
+    /// Single-copy atomic: the host must make the access in one
+    /// piece. Requires an alignment of at least the access size.
+    pub const ATOM: u16 = 1 << 7;
+    /// Every defined bit.
+    pub const VALID: u16 = (1 << 8) - 1;
 
     pub const fn new(bits: u16) -> Self {
         Self(bits)
@@ -194,6 +204,28 @@ impl MemOp {
     pub const fn size_bytes(self) -> u32 {
         1 << self.size()
     }
+
+    /// Require natural alignment.
+    pub const fn aligned(self) -> Self {
+        Self(self.0 & !Self::ALIGN_MASK | Self::ALIGN)
+    }
+    /// Require a single-copy atomic access.
+    pub const fn atomic(self) -> Self {
+        Self(self.0 | Self::ATOM)
+    }
+    pub const fn is_atomic(self) -> bool {
+        self.0 & Self::ATOM != 0
+    }
+    /// Alignment required of an access of `bytes` bytes; 1 when
+    /// any address will do. `bytes` is `size_bytes()` except for
+    /// the two-register ops, which access twice that.
+    pub const fn align_bytes(self, bytes: u32) -> u32 {
+        match (self.0 & Self::ALIGN_MASK) >> 4 {
+            0 => 1,
+            7 => bytes,
+            n => 1 << n,
+        }
+    }
 }
 
 /// Bitmap of host registers, used for register allocation constraints.
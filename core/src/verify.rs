@@ -0,0 +1,215 @@
+//! IR verifier — catches malformed ops before they reach the
+//! backend, where they would surface as a crash or a wrong value.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::context::Context;
+use crate::label::LabelSite;
+use crate::op::Op;
+use crate::opcode::{OpFlags, Opcode};
+use crate::temp::{TempIdx, TempKind};
+use crate::types::Type;
+
+/// Type each temp arg of `op` must have, in `args` order, or
+/// `None` for one any type may fill (host pointers, guest
+/// addresses, helper arguments).
+fn arg_types(op: &Op) -> Vec<Option<Type>> {
+    let def = op.opc.def();
+    let n = (def.nb_oargs + def.nb_iargs) as usize;
+    let ty = Some(op.op_type);
+    match op.opc {
+        // Only the low half of the input is read, of either type.
+        Opcode::ExtI32I64 | Opcode::ExtUI32I64 => vec![Some(Type::I64), None],
+        Opcode::ExtrlI64I32 | Opcode::ExtrhI64I32 => {
+            vec![Some(Type::I32), Some(Type::I64)]
+        }
+        Opcode::BrCond2I32 | Opcode::SetCond2I32 => vec![Some(Type::I32); n],
+        // The last input is the base pointer or guest address.
+        Opcode::Ld8U
+        | Opcode::Ld8S
+        | Opcode::Ld16U
+        | Opcode::Ld16S
+        | Opcode::Ld32U
+        | Opcode::Ld32S
+        | Opcode::Ld
+        | Opcode::St8
+        | Opcode::St16
+        | Opcode::St32
+        | Opcode::St
+        | Opcode::QemuLd
+        | Opcode::QemuSt
+        | Opcode::QemuLd2
+        | Opcode::QemuSt2 => {
+            let mut v = vec![ty; n];
+            v[n - 1] = None;
+            v
+        }
+        _ if def.flags.contains(OpFlags::INT) => vec![ty; n],
+        _ => vec![None; n],
+    }
+}
+
+/// Which constant arg of a branch is its label id.
+fn branch_label(op: &Op) -> Option<u32> {
+    match op.opc {
+        Opcode::Br => Some(op.cargs()[0].0),
+        Opcode::BrCond | Opcode::BrCond2I32 => Some(op.cargs()[1].0),
+        _ => None,
+    }
+}
+
+/// A malformed IR shape found by [`Context::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrError {
+    /// An arg names a temp the context does not have.
+    UnknownTemp { site: LabelSite, temp: u32 },
+    /// A temp is read before any op writes it.
+    ReadBeforeWrite { site: LabelSite, temp: u32 },
+    /// A temp's type differs from the one the op takes there.
+    TypeMismatch {
+        site: LabelSite,
+        temp: u32,
+        expected: Type,
+        found: Type,
+    },
+    /// A `set_label` for a label already set.
+    DuplicateLabel {
+        label: u32,
+        first: LabelSite,
+        second: LabelSite,
+    },
+    /// A branch to a label no `set_label` sets.
+    UnsetLabel { label: u32, site: LabelSite },
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownTemp { site, temp } => {
+                write!(f, "{site}: temp {temp} out of range")
+            }
+            Self::ReadBeforeWrite { site, temp } => {
+                write!(f, "{site}: temp {temp} read before it is written")
+            }
+            Self::TypeMismatch {
+                site,
+                temp,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{site}: temp {temp} is {found:?}, expected {expected:?}"
+            ),
+            Self::DuplicateLabel {
+                label,
+                first,
+                second,
+            } => write!(f, "{second}: label L{label} already set by {first}"),
+            Self::UnsetLabel { label, site } => {
+                write!(f, "{site}: label L{label} is never set")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IrError {}
+
+/// Every error in `ctx`, in op order; branches to unset labels
+/// come last.
+fn check(ctx: &Context) -> Vec<IrError> {
+    let mut errs = Vec::new();
+    let mut written = vec![false; ctx.nb_temps() as usize];
+    let mut set: HashMap<u32, LabelSite> = HashMap::new();
+    let mut branches = Vec::new();
+    let mut pc = None;
+    for op in ctx.ops() {
+        if op.opc == Opcode::InsnStart {
+            let c = op.cargs();
+            pc = Some((c[1].0 as u64) << 32 | c[0].0 as u64);
+        }
+        let site = LabelSite {
+            op: op.idx,
+            opc: op.opc,
+            pc,
+        };
+        let nb_oargs = op.opc.def().nb_oargs as usize;
+        let args = op.oargs().iter().chain(op.iargs());
+        for (j, (&TempIdx(t), want)) in args.zip(arg_types(op)).enumerate() {
+            let Some(temp) = ctx.temps().get(t as usize) else {
+                errs.push(IrError::UnknownTemp { site, temp: t });
+                continue;
+            };
+            if j >= nb_oargs
+                && matches!(temp.kind, TempKind::Ebb | TempKind::Tb)
+                && !written[t as usize]
+            {
+                errs.push(IrError::ReadBeforeWrite { site, temp: t });
+            }
+            if let Some(expected) = want.filter(|&w| w != temp.ty) {
+                errs.push(IrError::TypeMismatch {
+                    site,
+                    temp: t,
+                    expected,
+                    found: temp.ty,
+                });
+            }
+        }
+        for &TempIdx(t) in op.oargs() {
+            if let Some(w) = written.get_mut(t as usize) {
+                *w = true;
+            }
+        }
+        if op.opc == Opcode::SetLabel {
+            let label = op.cargs()[0].0;
+            if let Some(&first) = set.get(&label) {
+                errs.push(IrError::DuplicateLabel {
+                    label,
+                    first,
+                    second: site,
+                });
+            } else {
+                set.insert(label, site);
+            }
+        }
+        if let Some(label) = branch_label(op) {
+            branches.push((label, site));
+        }
+    }
+    for (label, site) in branches {
+        if !set.contains_key(&label) {
+            errs.push(IrError::UnsetLabel { label, site });
+        }
+    }
+    errs
+}
+
+impl Context {
+    /// Check the IR for the shapes [`IrError`] lists, returning
+    /// the first one found.
+    pub fn validate(&self) -> Result<(), IrError> {
+        match check(self).into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check the ops of `ctx` in order for:
+///
+/// - a temp read before any op writes it (globals, constants
+///   and fixed temps always hold a value);
+/// - a temp whose type differs from the one the op takes there;
+/// - a `set_label` for a label already set;
+/// - a branch to a label no `set_label` sets.
+///
+/// Unlike [`Context::validate`], reports every error, each
+/// naming the op as `<opcode> at op #N`.
+pub fn verify(ctx: &Context) -> Result<(), Vec<String>> {
+    let errs = check(ctx);
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(errs.iter().map(IrError::to_string).collect())
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
 
 // ── Data structures ─────────────────────────────────────────────
@@ -15,6 +15,8 @@ pub struct Field {
     pub name: String,
     pub segments: Vec<FieldSegment>,
     pub func: Option<String>,
+    /// Line of the definition in the `.decode` source, from 1.
+    pub line: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -27,8 +29,15 @@ pub struct ArgSet {
 #[derive(Clone, Debug)]
 pub enum FieldMapping {
     FieldRef(String),
-    Inline { pos: u32, len: u32, signed: bool },
-    Const(i32),
+    Inline {
+        pos: u32,
+        len: u32,
+        signed: bool,
+    },
+    Const(i64),
+    /// `field=!function=func`: `func` applied to 0, as to a
+    /// field with no bits.
+    Func(String),
 }
 
 #[derive(Clone, Debug)]
@@ -39,21 +48,76 @@ pub struct Format {
     pub fixedmask: u32,
     pub args_name: String,
     pub field_map: BTreeMap<String, FieldMapping>,
+    pub reserved: Vec<Reserved>,
+    /// Line of the definition in the `.decode` source, from 1.
+    pub line: u32,
+}
+
+/// Values of one field that make an encoding reserved, from
+/// `!reserved field=v,lo-hi,...`.  A pattern does not match an
+/// instruction word whose field holds one of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reserved {
+    pub field: String,
+    /// Inclusive ranges; a single value `v` is `(v, v)`.
+    pub ranges: Vec<(i64, i64)>,
+}
+
+impl Reserved {
+    pub fn contains(&self, v: i64) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&v))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Pattern {
     pub name: String,
+    /// `trans_` method `decode()` calls: from `!trans=`, else
+    /// the pattern name. Patterns may share one.
+    pub trans: String,
     pub fixedbits: u32,
     pub fixedmask: u32,
     pub args_name: String,
     pub field_map: BTreeMap<String, FieldMapping>,
+    /// ISA extensions from `!ext=A,B`, in source order.
+    pub exts: Vec<String>,
+    /// `!reserved` constraints, the format's first.
+    pub reserved: Vec<Reserved>,
+    /// First physical line of the pattern in the `.decode`
+    /// source, from 1.
+    pub line: u32,
+    /// Enclosing groups, outermost first, as indices into
+    /// [`Parsed::groups`].
+    pub groups: Vec<usize>,
+}
+
+/// A `{ }` or `[ ]` block of patterns.
+#[derive(Clone, Debug)]
+pub struct Group {
+    /// `{ }`: members may overlap and the first to match wins.
+    /// `[ ]`: members must not overlap.
+    pub overlap: bool,
+    /// Line of the opening bracket, from 1.
+    pub line: u32,
+    /// Members in source order.
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupMember {
+    /// Index into [`Parsed::patterns`].
+    Pattern(usize),
+    /// Index into [`Parsed::groups`].
+    Group(usize),
 }
 
 pub struct Parsed {
     pub fields: BTreeMap<String, Field>,
     pub argsets: BTreeMap<String, ArgSet>,
     pub patterns: Vec<Pattern>,
+    /// Every group in order of its opening bracket; the nesting
+    /// is in [`Group::members`].
+    pub groups: Vec<Group>,
 }
 
 // ── Bit-pattern parsing ─────────────────────────────────────────
@@ -70,6 +134,7 @@ pub fn is_inline_field(s: &str) -> bool {
     if let Some(idx) = s.find(':') {
         let name = &s[..idx];
         let rest = &s[idx + 1..];
+        let rest = rest.strip_prefix('s').unwrap_or(rest);
         !name.is_empty()
             && name.chars().all(|c| c.is_alphanumeric() || c == '_')
             && !rest.is_empty()
@@ -82,7 +147,8 @@ pub fn is_inline_field(s: &str) -> bool {
 pub struct BitPatternResult {
     pub fixedbits: u32,
     pub fixedmask: u32,
-    pub inline_fields: BTreeMap<String, (u32, u32)>,
+    /// Inline `name:len` / `name:slen` fields as (pos, len, signed).
+    pub inline_fields: BTreeMap<String, (u32, u32, bool)>,
 }
 
 pub fn parse_bit_tokens(
@@ -116,11 +182,14 @@ pub fn parse_bit_tokens(
         } else if is_inline_field(tok) {
             let idx = tok.find(':').unwrap();
             let name = &tok[..idx];
-            let len: u32 = tok[idx + 1..]
+            let rest = &tok[idx + 1..];
+            let signed = rest.starts_with('s');
+            let len: u32 = rest
+                .trim_start_matches('s')
                 .parse()
                 .map_err(|e| format!("bad inline field len: {e}"))?;
             let pos = (bit_pos - len as i32 + 1) as u32;
-            inline_fields.insert(name.to_string(), (pos, len));
+            inline_fields.insert(name.to_string(), (pos, len, signed));
             bit_pos -= len as i32;
         } else {
             break;
@@ -156,6 +225,10 @@ pub fn parse_field_segment(s: &str) -> Result<FieldSegment, String> {
 }
 
 pub fn parse_field(line: &str) -> Result<Field, String> {
+    parse_field_at(line, 0)
+}
+
+fn parse_field_at(line: &str, lineno: u32) -> Result<Field, String> {
     // %name seg1 seg2 ... [!function=func]
     let tokens: Vec<&str> = line.split_whitespace().collect();
     let name = tokens[0][1..].to_string(); // skip %
@@ -163,6 +236,9 @@ pub fn parse_field(line: &str) -> Result<Field, String> {
     let mut func = None;
     for &tok in &tokens[1..] {
         if let Some(f) = tok.strip_prefix("!function=") {
+            if !is_ident(f) {
+                return Err(format!("bad !function name: {f:?}"));
+            }
             func = Some(f.to_string());
         } else {
             segments.push(parse_field_segment(tok)?);
@@ -172,6 +248,7 @@ pub fn parse_field(line: &str) -> Result<Field, String> {
         name,
         segments,
         func,
+        line: lineno,
     })
 }
 
@@ -193,6 +270,29 @@ pub fn parse_argset(line: &str) -> Result<ArgSet, String> {
     })
 }
 
+fn is_ident(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Parse a 64-bit constant: decimal or `0x` hex, either with an
+/// optional `-`. Hex takes any 64-bit pattern, e.g.
+/// `0xffffffffffffffff` for -1.
+fn parse_const(s: &str) -> Option<i64> {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, s),
+    };
+    let v = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => digits.parse::<u64>().ok().and_then(|v| {
+            // Decimal stays within i64, allowing -2^63.
+            (v <= i64::MAX as u64 || (neg && v == 1 << 63)).then_some(v as i64)
+        })?,
+    };
+    Some(if neg { v.wrapping_neg() } else { v })
+}
+
 /// Parse trailing attributes after bit tokens.
 /// Returns (args_name, field_map).
 fn parse_attrs(
@@ -202,7 +302,9 @@ fn parse_attrs(
     let mut args_name = String::new();
     let mut field_map = BTreeMap::new();
     for &tok in tokens {
-        if let Some(a) = tok.strip_prefix('&') {
+        if tok.starts_with('!') {
+            // !function=, !ext= etc, handled by the caller
+        } else if let Some(a) = tok.strip_prefix('&') {
             args_name = a.to_string();
         } else if let Some(f) = tok.strip_prefix('%') {
             // %field_ref → field_name = FieldRef(field_name)
@@ -211,18 +313,22 @@ fn parse_attrs(
         } else if let Some(idx) = tok.find('=') {
             let key = &tok[..idx];
             let val = &tok[idx + 1..];
-            if let Some(fref) = val.strip_prefix('%') {
-                field_map.insert(
-                    key.to_string(),
-                    FieldMapping::FieldRef(fref.to_string()),
-                );
-            } else if let Ok(c) = val.parse::<i32>() {
-                field_map.insert(key.to_string(), FieldMapping::Const(c));
+            let mapping = if let Some(fref) = val.strip_prefix('%') {
+                FieldMapping::FieldRef(fref.to_string())
+            } else if let Some(f) = val.strip_prefix("!function=") {
+                if !is_ident(f) {
+                    return Err(format!("{tok}: bad !function name: {f:?}"));
+                }
+                FieldMapping::Func(f.to_string())
+            } else if let Some(c) = parse_const(val) {
+                FieldMapping::Const(c)
             } else {
-                return Err(format!("bad attr: {tok}"));
-            }
-        } else if tok.starts_with('!') {
-            // !function= etc, skip (handled in field)
+                return Err(format!(
+                    "{tok}: expected %field, !function=name or an \
+                     integer, got {val:?}"
+                ));
+            };
+            field_map.insert(key.to_string(), mapping);
         } else if !tok.starts_with('@') {
             // Unknown token in attrs
             if fields.contains_key(tok) {
@@ -236,8 +342,54 @@ fn parse_attrs(
     Ok((args_name, field_map))
 }
 
+/// Parse the `field=v,lo-hi,...` operand of `!reserved`.
+fn parse_reserved(spec: &str) -> Result<Reserved, String> {
+    let bad = || format!("bad !reserved {spec}");
+    let (field, vals) = spec.split_once('=').ok_or_else(bad)?;
+    if field.is_empty() || vals.is_empty() {
+        return Err(bad());
+    }
+    let mut ranges = Vec::new();
+    for v in vals.split(',') {
+        // A leading '-' is a sign, not a range.
+        let (lo, hi) = match v.get(1..).and_then(|t| t.find('-')) {
+            Some(i) => (&v[..i + 1], &v[i + 2..]),
+            None => (v, v),
+        };
+        let lo: i64 = lo.parse().map_err(|_| bad())?;
+        let hi: i64 = hi.parse().map_err(|_| bad())?;
+        if lo > hi {
+            return Err(bad());
+        }
+        ranges.push((lo, hi));
+    }
+    Ok(Reserved {
+        field: field.to_string(),
+        ranges,
+    })
+}
+
+/// Split `!reserved field=...` pairs out of `tokens`.
+fn split_reserved<'a>(
+    tokens: &[&'a str],
+) -> Result<(Vec<&'a str>, Vec<Reserved>), String> {
+    let mut rest = Vec::new();
+    let mut reserved = Vec::new();
+    let mut it = tokens.iter();
+    while let Some(&tok) = it.next() {
+        if tok == "!reserved" {
+            let spec = it.next().ok_or("!reserved without a field")?;
+            reserved.push(parse_reserved(spec)?);
+        } else {
+            rest.push(tok);
+        }
+    }
+    Ok((rest, reserved))
+}
+
 fn parse_format(
     line: &str,
+    lineno: u32,
     fields: &BTreeMap<String, Field>,
     width: u32,
 ) -> Result<(String, Format), String> {
@@ -246,17 +398,13 @@ fn parse_format(
     let name = tokens[0][1..].to_string(); // skip @
     let bit_count = count_bit_tokens(&tokens[1..]);
     let bp = parse_bit_tokens(&tokens[1..1 + bit_count], width)?;
-    let rest = &tokens[1 + bit_count..];
-    let (args_name, mut field_map) = parse_attrs(rest, fields)?;
+    let (rest, reserved) = split_reserved(&tokens[1 + bit_count..])?;
+    let (args_name, mut field_map) = parse_attrs(&rest, fields)?;
     // Merge inline fields from bit pattern
-    for (fname, (pos, len)) in &bp.inline_fields {
+    for (fname, &(pos, len, signed)) in &bp.inline_fields {
         field_map
             .entry(fname.clone())
-            .or_insert(FieldMapping::Inline {
-                pos: *pos,
-                len: *len,
-                signed: false,
-            });
+            .or_insert(FieldMapping::Inline { pos, len, signed });
     }
     Ok((
         name.clone(),
@@ -266,12 +414,15 @@ fn parse_format(
             fixedmask: bp.fixedmask,
             args_name,
             field_map,
+            reserved,
+            line: lineno,
         },
     ))
 }
 
 fn parse_pattern(
     line: &str,
+    lineno: u32,
     formats: &BTreeMap<String, Format>,
     fields: &BTreeMap<String, Field>,
     auto_args: &mut BTreeMap<String, ArgSet>,
@@ -281,7 +432,30 @@ fn parse_pattern(
     let name = tokens[0].to_string();
     let bit_count = count_bit_tokens(&tokens[1..]);
     let bp = parse_bit_tokens(&tokens[1..1 + bit_count], width)?;
-    let rest = &tokens[1 + bit_count..];
+    let (rest, own_reserved) = split_reserved(&tokens[1 + bit_count..])?;
+    let rest = &rest[..];
+    let exts = rest
+        .iter()
+        .filter_map(|t| t.strip_prefix("!ext="))
+        .flat_map(|v| v.split(','))
+        .map(|e| {
+            if e.is_empty() {
+                Err(format!("empty !ext= in pattern {name}"))
+            } else {
+                Ok(e.to_string())
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut trans = None;
+    for t in rest.iter().filter_map(|t| t.strip_prefix("!trans=")) {
+        if !is_ident(t) {
+            return Err(format!("bad !trans= in pattern {name}: {t:?}"));
+        }
+        if trans.replace(t).is_some() {
+            return Err(format!("more than one !trans= in pattern {name}"));
+        }
+    }
+    let trans = trans.unwrap_or(&name).to_string();
 
     // Find @format reference
     let fmt_ref = rest
@@ -289,6 +463,7 @@ fn parse_pattern(
         .find_map(|t| t.strip_prefix('@').map(|s| s.to_string()));
 
     let (args_name, field_map, fmt_bits, fmt_mask);
+    let mut reserved = Vec::new();
     if let Some(ref fname) = fmt_ref {
         let fmt = formats
             .get(fname)
@@ -300,16 +475,17 @@ fn parse_pattern(
         fm.extend(extra_map);
         args_name = fmt.args_name.clone();
         field_map = fm;
+        reserved.extend(fmt.reserved.iter().cloned());
     } else {
         fmt_bits = 0;
         fmt_mask = 0;
         let (an, mut fm) = parse_attrs(rest, fields)?;
         // Add inline fields from bit pattern
-        for (fname, (pos, len)) in &bp.inline_fields {
+        for (fname, &(pos, len, signed)) in &bp.inline_fields {
             fm.entry(fname.clone()).or_insert(FieldMapping::Inline {
-                pos: *pos,
-                len: *len,
-                signed: false,
+                pos,
+                len,
+                signed,
             });
         }
         if an.is_empty() && !fm.is_empty() {
@@ -330,38 +506,68 @@ fn parse_pattern(
         }
         field_map = fm;
     }
+    reserved.extend(own_reserved);
+    for r in &reserved {
+        let ok = match field_map.get(&r.field) {
+            Some(FieldMapping::Inline { .. }) => true,
+            Some(FieldMapping::FieldRef(f)) => {
+                fields.get(f).is_some_and(|f| f.func.is_none())
+            }
+            _ => false,
+        };
+        if !ok {
+            return Err(format!(
+                "!reserved {} in pattern {name}: not a plain field",
+                r.field
+            ));
+        }
+    }
 
     Ok(Pattern {
         name,
+        trans,
         fixedbits: bp.fixedbits | fmt_bits,
         fixedmask: bp.fixedmask | fmt_mask,
         args_name,
         field_map,
+        exts,
+        reserved,
+        line: lineno,
+        groups: Vec::new(),
     })
 }
 
 /// Merge backslash-continuation lines into single logical
 /// lines.  A trailing `\` joins the next line.
 pub fn merge_continuations(input: &str) -> String {
-    let mut out = String::with_capacity(input.len());
+    logical_lines(input)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Logical lines of `input`, each with the physical line (from
+/// 1) it starts on.
+fn logical_lines(input: &str) -> Vec<(u32, String)> {
+    let mut out: Vec<(u32, String)> = Vec::new();
     let mut cont = false;
-    for line in input.lines() {
-        if cont {
-            // Append to previous logical line (space-separated).
-            out.push(' ');
-            out.push_str(line.trim());
-        } else {
-            if !out.is_empty() {
-                out.push('\n');
+    for (i, line) in input.lines().enumerate() {
+        match out.last_mut() {
+            Some((_, last)) if cont => {
+                // Append to previous logical line (space-separated).
+                last.push(' ');
+                last.push_str(line.trim());
             }
-            out.push_str(line);
+            _ => out.push((i as u32 + 1, line.to_string())),
         }
-        cont = out.ends_with('\\');
+        let last = &mut out.last_mut().unwrap().1;
+        cont = last.ends_with('\\');
         if cont {
-            out.pop(); // remove trailing backslash
-                       // Trim trailing whitespace before the backslash
-            while out.ends_with(' ') {
-                out.pop();
+            last.pop(); // remove trailing backslash
+                        // Trim trailing whitespace before the backslash
+            while last.ends_with(' ') {
+                last.pop();
             }
         }
     }
@@ -369,17 +575,19 @@ pub fn merge_continuations(input: &str) -> String {
 }
 
 pub fn parse_with_width(input: &str, width: u32) -> Result<Parsed, String> {
-    let merged = merge_continuations(input);
     let mut fields = BTreeMap::new();
     let mut argsets = BTreeMap::new();
     let mut formats = BTreeMap::new();
     let mut patterns = Vec::new();
     let mut auto_args = BTreeMap::new();
+    let mut groups: Vec<Group> = Vec::new();
+    // Indices of the open groups, outermost first.
+    let mut open: Vec<usize> = Vec::new();
 
-    for (lineno, raw) in merged.lines().enumerate() {
+    for (lineno, raw) in logical_lines(input) {
         let line = match raw.find('#') {
             Some(i) => &raw[..i],
-            None => raw,
+            None => &raw,
         };
         let line = line.trim();
         if line.is_empty() {
@@ -387,41 +595,61 @@ pub fn parse_with_width(input: &str, width: u32) -> Result<Parsed, String> {
         }
         let first = line.chars().next().unwrap();
         let result: Result<(), String> = match first {
-            '%' => {
-                let f = parse_field(line)?;
+            '%' => parse_field_at(line, lineno).map(|f| {
                 fields.insert(f.name.clone(), f);
-                Ok(())
-            }
-            '&' => {
-                let a = parse_argset(line)?;
+            }),
+            '&' => parse_argset(line).map(|a| {
                 argsets.insert(a.name.clone(), a);
-                Ok(())
-            }
-            '@' => {
-                let (n, f) = parse_format(line, &fields, width)?;
+            }),
+            '@' => parse_format(line, lineno, &fields, width).map(|(n, f)| {
                 formats.insert(n, f);
+            }),
+            '{' | '[' => {
+                let id = groups.len();
+                if let Some(&g) = open.last() {
+                    groups[g].members.push(GroupMember::Group(id));
+                }
+                groups.push(Group {
+                    overlap: first == '{',
+                    line: lineno,
+                    members: Vec::new(),
+                });
+                open.push(id);
                 Ok(())
             }
-            '{' | '}' | '[' | ']' => Ok(()),
-            _ => {
-                let p = parse_pattern(
-                    line,
-                    &formats,
-                    &fields,
-                    &mut auto_args,
-                    width,
-                )?;
+            '}' | ']' => match open.pop() {
+                Some(g) if groups[g].overlap == (first == '}') => Ok(()),
+                _ => Err(format!("unbalanced '{first}'")),
+            },
+            _ => parse_pattern(
+                line,
+                lineno,
+                &formats,
+                &fields,
+                &mut auto_args,
+                width,
+            )
+            .map(|mut p| {
+                if let Some(&g) = open.last() {
+                    let idx = GroupMember::Pattern(patterns.len());
+                    groups[g].members.push(idx);
+                }
+                p.groups = open.clone();
                 patterns.push(p);
-                Ok(())
-            }
+            }),
         };
-        result.map_err(|e: String| format!("line {}: {e}", lineno + 1))?;
+        result.map_err(|e: String| format!("line {lineno}: {e}"))?;
+    }
+    if let Some(&g) = open.last() {
+        let lineno = groups[g].line;
+        return Err(format!("line {lineno}: group is never closed"));
     }
     argsets.extend(auto_args);
     Ok(Parsed {
         fields,
         argsets,
         patterns,
+        groups,
     })
 }
 
@@ -451,32 +679,89 @@ pub fn to_camel(s: &str) -> String {
     result
 }
 
+/// Name of the generated args struct passed to `trans_<pattern>`.
+fn args_struct_name(p: &Pattern) -> String {
+    if p.args_name.is_empty() {
+        "ArgsEmpty".to_string()
+    } else {
+        format!("Args{}", to_camel(&p.args_name))
+    }
+}
+
 fn emit_arg_structs(
     w: &mut dyn Write,
     argsets: &BTreeMap<String, ArgSet>,
+    opts: &GenOptions,
 ) -> std::io::Result<()> {
+    let vis = if opts.args_vis.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", opts.args_vis)
+    };
+    let eq = if opts.derive_eq {
+        ", PartialEq, Eq"
+    } else {
+        ""
+    };
     for a in argsets.values() {
         if a.is_extern {
             continue;
         }
         let sname = format!("Args{}", to_camel(&a.name));
-        writeln!(w, "#[derive(Debug, Clone, Copy, Default)]")?;
-        writeln!(w, "pub struct {sname} {{")?;
+        opts.emit_allow(w)?;
+        writeln!(w, "#[derive(Debug, Clone, Copy, Default{eq})]")?;
+        if a.fields.is_empty() {
+            writeln!(w, "{vis}struct {sname} {{}}\n")?;
+            continue;
+        }
+        writeln!(w, "{vis}struct {sname} {{")?;
         for f in &a.fields {
-            writeln!(w, "    pub {f}: i64,")?;
+            writeln!(w, "    {vis}{f}: i64,")?;
         }
         writeln!(w, "}}\n")?;
     }
     Ok(())
 }
 
+/// Emit a `use` of each `!extern` args struct the patterns take,
+/// from `path`.
+fn emit_extern_uses(
+    w: &mut dyn Write,
+    parsed: &Parsed,
+    path: &str,
+) -> std::io::Result<()> {
+    let names: BTreeSet<String> = parsed
+        .patterns
+        .iter()
+        .filter(|p| {
+            let name = if p.args_name.is_empty() {
+                "empty"
+            } else {
+                &p.args_name
+            };
+            parsed.argsets.get(name).is_some_and(|a| a.is_extern)
+        })
+        .map(args_struct_name)
+        .collect();
+    if names.is_empty() {
+        return Ok(());
+    }
+    for n in names {
+        writeln!(w, "use {path}::{n};")?;
+    }
+    writeln!(w)
+}
+
 fn emit_extract_field(
     w: &mut dyn Write,
     field: &Field,
-    width: u32,
+    opts: &GenOptions,
 ) -> std::io::Result<()> {
+    let width = opts.width;
     let insn_ty = if width <= 16 { "u16" } else { "u32" };
     let signed_ty = if width <= 16 { "i16" } else { "i32" };
+    writeln!(w, "// {}:{}: %{}", opts.source, field.line, field.name)?;
+    opts.emit_allow(w)?;
     writeln!(w, "fn extract_{}(insn: {insn_ty}) -> i64 {{", field.name)?;
     let segs = &field.segments;
     if segs.len() == 1 {
@@ -552,46 +837,72 @@ fn emit_extract_field(
     writeln!(w, "}}\n")
 }
 
-/// Emit the transform expression for a `!function=` handler.
+/// The expression applying built-in `!function=func` to `cast`.
+/// These are the RISC-V ones, inlined.
+fn builtin_func(func: &str, cast: &str) -> Option<String> {
+    Some(match func {
+        "ex_shift_1" => format!("({cast}) << 1"),
+        "ex_shift_2" => format!("({cast}) << 2"),
+        "ex_shift_3" => format!("({cast}) << 3"),
+        "ex_shift_4" => format!("({cast}) << 4"),
+        "ex_shift_12" => format!("({cast}) << 12"),
+        "ex_rvc_register" => format!("({cast}) + 8"),
+        "ex_sreg_register" => {
+            format!("[8,9,18,19,20,21,22,23][({cast}) as usize & 7]")
+        }
+        // Identity for RV64
+        "ex_rvc_shiftli" | "ex_rvc_shiftri" => cast.to_string(),
+        _ => return None,
+    })
+}
+
+/// Emit the transform expression for a `!function=` handler: a
+/// built-in one inline, any other as a call to the including
+/// module's `fn(i64) -> i64`.
 fn emit_func_transform(
     w: &mut dyn Write,
     func: &str,
     cast: &str,
 ) -> std::io::Result<()> {
-    match func {
-        "ex_shift_1" => {
-            writeln!(w, "    ({cast}) << 1")
-        }
-        "ex_shift_2" => {
-            writeln!(w, "    ({cast}) << 2")
-        }
-        "ex_shift_3" => {
-            writeln!(w, "    ({cast}) << 3")
-        }
-        "ex_shift_4" => {
-            writeln!(w, "    ({cast}) << 4")
-        }
-        "ex_shift_12" => {
-            writeln!(w, "    ({cast}) << 12")
-        }
-        "ex_rvc_register" => {
-            writeln!(w, "    ({cast}) + 8")
-        }
-        "ex_sreg_register" => {
-            writeln!(
-                w,
-                "    [8,9,18,19,20,21,22,23]\
-                 [({cast}) as usize & 7]"
-            )
-        }
-        "ex_rvc_shiftli" | "ex_rvc_shiftri" => {
-            // Identity for RV64
-            writeln!(w, "    {cast}")
+    writeln!(w, "    {}", func_expr(func, cast))
+}
+
+fn func_expr(func: &str, cast: &str) -> String {
+    builtin_func(func, cast).unwrap_or_else(|| format!("{func}({cast})"))
+}
+
+/// Check every `!function=` is built in or in `extern_funcs`.
+fn check_funcs(
+    parsed: &Parsed,
+    extern_funcs: &BTreeSet<String>,
+) -> Result<(), String> {
+    let known =
+        |f: &str| builtin_func(f, "").is_some() || extern_funcs.contains(f);
+    for f in parsed.fields.values() {
+        let Some(func) = &f.func else { continue };
+        if !known(func) {
+            return Err(format!(
+                "line {}: %{}: unknown !function={func}; \
+                 declare it in GenOptions::extern_funcs",
+                f.line, f.name
+            ));
         }
-        _ => {
-            writeln!(w, "    // unknown func: {func}\n    {cast}")
+    }
+    for p in &parsed.patterns {
+        for (name, m) in &p.field_map {
+            let FieldMapping::Func(func) = m else {
+                continue;
+            };
+            if !known(func) {
+                return Err(format!(
+                    "line {}: {name}=!function={func}: unknown \
+                     !function; declare it in GenOptions::extern_funcs",
+                    p.line
+                ));
+            }
         }
     }
+    Ok(())
 }
 
 fn emit_field_expr(
@@ -608,12 +919,20 @@ fn emit_field_expr(
         FieldMapping::Inline { pos, len, signed } => {
             if *signed {
                 let shift = width - pos - len;
-                write!(
-                    w,
-                    "(((insn as {signed_ty}) \
-                     << {shift}) >> {}) as i64",
-                    width - len
-                )?;
+                if shift == 0 {
+                    write!(
+                        w,
+                        "((insn as {signed_ty}) >> {}) as i64",
+                        width - len
+                    )?;
+                } else {
+                    write!(
+                        w,
+                        "(((insn as {signed_ty}) \
+                         << {shift}) >> {}) as i64",
+                        width - len
+                    )?;
+                }
             } else {
                 let mask = (1u32 << len) - 1;
                 write!(w, "((insn >> {pos}) & {mask:#x}) as i64")?;
@@ -622,6 +941,9 @@ fn emit_field_expr(
         FieldMapping::Const(c) => {
             write!(w, "{c}_i64")?;
         }
+        FieldMapping::Func(f) => {
+            write!(w, "{}", func_expr(f, "0"))?;
+        }
     }
     Ok(())
 }
@@ -629,124 +951,1087 @@ fn emit_field_expr(
 fn emit_decode_trait(
     w: &mut dyn Write,
     patterns: &[Pattern],
-    argsets: &BTreeMap<String, ArgSet>,
-    width: u32,
+    opts: &GenOptions,
 ) -> std::io::Result<()> {
-    let trait_name = if width <= 16 { "Decode16" } else { "Decode" };
-    writeln!(w, "pub trait {trait_name}<Ir> {{")?;
+    let partial = opts.partial.is_some();
+    opts.emit_allow(w)?;
+    writeln!(w, "pub trait {}<Ir> {{", opts.trait_name())?;
+    if partial {
+        writeln!(
+            w,
+            "    /// Called for a pattern without a `trans_` \
+             implementation;\n    /// `false` takes the \
+             illegal-instruction path."
+        )?;
+        writeln!(
+            w,
+            "    fn unimplemented(\
+             &mut self, name: &'static str, insn: u32\
+             ) -> bool {{"
+        )?;
+        writeln!(w, "        let _ = (name, insn);")?;
+        writeln!(w, "        false")?;
+        writeln!(w, "    }}\n")?;
+        writeln!(w, "    /// The instruction word being decoded.")?;
+        writeln!(w, "    fn insn(&self) -> u32;\n")?;
+    }
     let mut seen = std::collections::HashSet::new();
     for p in patterns {
-        if !seen.insert(&p.name) {
+        if !seen.insert(&p.trans) {
             continue; // skip duplicate trait methods
         }
-        let sname = if p.args_name.is_empty() {
-            "ArgsEmpty".to_string()
+        let sname = args_struct_name(p);
+        if partial {
+            writeln!(
+                w,
+                "    fn trans_{}(\
+                 &mut self, ir: &mut Ir, a: &{sname}\
+                 ) -> bool {{",
+                p.trans
+            )?;
+            writeln!(w, "        let _ = (ir, a);")?;
+            writeln!(w, "        let insn = self.insn();")?;
+            writeln!(w, "        self.unimplemented({:?}, insn)", p.trans)?;
+            writeln!(w, "    }}")?;
         } else {
-            format!("Args{}", to_camel(&p.args_name))
-        };
-        writeln!(
-            w,
-            "    fn trans_{}(\
-             &mut self, ir: &mut Ir, a: &{sname}\
-             ) -> bool;",
-            p.name
-        )?;
+            writeln!(
+                w,
+                "    fn trans_{}(\
+                 &mut self, ir: &mut Ir, a: &{sname}\
+                 ) -> bool;",
+                p.trans
+            )?;
+        }
     }
-    writeln!(w, "}}\n")?;
-    let needs_empty = patterns.iter().any(|p| p.args_name.is_empty());
-    if needs_empty && !argsets.contains_key("empty") {
-        // Already emitted by argsets if &empty exists
+    writeln!(w, "}}\n")
+}
+
+/// Line width rustfmt wraps the generated code at by default.
+const MAX_WIDTH: usize = 100;
+
+/// Emit the `if` opening the arm for `p`.
+fn emit_match(
+    w: &mut dyn Write,
+    p: &Pattern,
+    width: u32,
+    ind: &str,
+) -> std::io::Result<()> {
+    emit_cond(w, p, width, &format!("{ind}if "), "", ind)
+}
+
+/// Emit `head`, the condition for `p` and `tail`, opening a
+/// block. The condition is the fixed bits, then a `!matches!`
+/// per `!reserved` field, wrapped one per line the way rustfmt
+/// does if it overflows.
+fn emit_cond(
+    w: &mut dyn Write,
+    p: &Pattern,
+    width: u32,
+    head: &str,
+    tail: &str,
+    ind: &str,
+) -> std::io::Result<()> {
+    let full_mask: u32 = if width <= 16 { 0xffff } else { 0xffff_ffff };
+    let bits = format_hex(p.fixedbits, width);
+    let mut parts = vec![if p.fixedmask == full_mask {
+        format!("insn == {bits}")
+    } else {
+        format!("insn & {} == {bits}", format_hex(p.fixedmask, width))
+    }];
+    for r in &p.reserved {
+        let mut expr = Vec::new();
+        emit_field_expr(&mut expr, &r.field, &p.field_map[&r.field], width)?;
+        let pats: Vec<String> = r
+            .ranges
+            .iter()
+            .map(|&(lo, hi)| {
+                if lo == hi {
+                    format!("{lo}")
+                } else {
+                    format!("{lo}..={hi}")
+                }
+            })
+            .collect();
+        parts.push(format!(
+            "&& !matches!({}, {})",
+            String::from_utf8_lossy(&expr),
+            pats.join(" | ")
+        ));
+    }
+    let line = format!("{head}{}{tail} {{", parts.join(" "));
+    if line.len() <= MAX_WIDTH {
+        return writeln!(w, "{line}");
+    }
+    writeln!(w, "{head}{}", parts[0])?;
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let tail = if i + 1 == parts.len() { tail } else { "" };
+        writeln!(w, "{ind}    {part}{tail}")?;
+    }
+    writeln!(w, "{ind}{{")
+}
+
+/// A node of the `decode()` decision tree.
+enum DecodeNode {
+    /// Patterns (indices, in source order) told apart by a
+    /// linear if-chain.
+    Leaf(Vec<usize>),
+    /// Dispatch on `insn & mask`, shifted down; an arm holds the
+    /// patterns whose fixed bits under `mask` equal its value.
+    Switch {
+        mask: u32,
+        arms: Vec<(u32, DecodeNode)>,
+    },
+}
+
+/// Partition `idx` on the bits every one of its patterns fixes,
+/// beyond those already tested, like QEMU's decodetree. Patterns
+/// that can match the same word always share an arm, so source
+/// order still decides between them within a leaf.
+fn build_tree(patterns: &[Pattern], idx: Vec<usize>, known: u32) -> DecodeNode {
+    if idx.len() <= 1 {
+        return DecodeNode::Leaf(idx);
+    }
+    let mask = idx.iter().fold(!known, |m, &i| m & patterns[i].fixedmask);
+    if mask == 0 {
+        return DecodeNode::Leaf(idx);
+    }
+    let mut groups: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for i in idx {
+        groups
+            .entry(patterns[i].fixedbits & mask)
+            .or_default()
+            .push(i);
+    }
+    let known = known | mask;
+    if groups.len() == 1 {
+        let (_, idx) = groups.pop_first().unwrap();
+        return build_tree(patterns, idx, known);
+    }
+    let arms = groups
+        .into_iter()
+        .map(|(v, idx)| (v, build_tree(patterns, idx, known)))
+        .collect();
+    DecodeNode::Switch { mask, arms }
+}
+
+/// What a decision tree arm returns for its pattern.
+#[derive(Clone, Copy)]
+enum Dispatch<'a> {
+    /// What the pattern's `trans_` method returns.
+    Trans,
+    /// `Some` variant of the named enum for the pattern.
+    Insn(&'a str),
+}
+
+impl Dispatch<'_> {
+    /// The returned expression for `p`, given its args `a`.
+    fn expr(self, p: &Pattern, a: &str) -> String {
+        match self {
+            Dispatch::Trans => format!("ctx.trans_{}(ir, &{a})", p.trans),
+            Dispatch::Insn(e) => {
+                format!("Some({e}::{}({a}))", to_camel(&p.name))
+            }
+        }
+    }
+}
+
+/// Emit the arm dispatching to `p`.
+fn emit_decode_arm(
+    w: &mut dyn Write,
+    p: &Pattern,
+    argsets: &BTreeMap<String, ArgSet>,
+    width: u32,
+    source: &str,
+    ind: &str,
+    to: Dispatch,
+) -> std::io::Result<()> {
+    writeln!(w, "{ind}// {source}:{}: {}", p.line, p.name)?;
+    emit_match(w, p, width, ind)?;
+    emit_trans_call(w, p, argsets, width, ind, to)?;
+    writeln!(w, "{ind}}}")
+}
+
+/// Emit the body of `p`'s arm: extract its arguments and return
+/// what `to` makes of them.
+fn emit_trans_call(
+    w: &mut dyn Write,
+    p: &Pattern,
+    argsets: &BTreeMap<String, ArgSet>,
+    width: u32,
+    ind: &str,
+    to: Dispatch,
+) -> std::io::Result<()> {
+    let sname = args_struct_name(p);
+    // Build args struct
+    let arg_fields = if p.args_name.is_empty() {
+        Vec::new()
+    } else if let Some(a) = argsets.get(&p.args_name) {
+        a.fields.clone()
+    } else {
+        Vec::new()
+    };
+    if arg_fields.is_empty() {
+        let expr = to.expr(p, &format!("{sname} {{}}"));
+        writeln!(w, "{ind}    return {expr};")?;
+    } else {
+        writeln!(w, "{ind}    let a = {sname} {{")?;
+        for af in &arg_fields {
+            if let Some(mapping) = p.field_map.get(af) {
+                write!(w, "{ind}        {af}: ")?;
+                emit_field_expr(w, af, mapping, width)?;
+                writeln!(w, ",")?;
+            } else {
+                writeln!(w, "{ind}        {af}: 0,")?;
+            }
+        }
+        writeln!(w, "{ind}    }};")?;
+        writeln!(w, "{ind}    return {};", to.expr(p, "a"))?;
+    }
+    Ok(())
+}
+
+fn emit_decode_node(
+    w: &mut dyn Write,
+    node: &DecodeNode,
+    patterns: &[Pattern],
+    argsets: &BTreeMap<String, ArgSet>,
+    opts: &GenOptions,
+    ind: &str,
+    to: Dispatch,
+) -> std::io::Result<()> {
+    let (width, source) = (opts.width, opts.source.as_str());
+    match node {
+        DecodeNode::Leaf(idx) => {
+            for &i in idx {
+                let p = &patterns[i];
+                emit_decode_arm(w, p, argsets, width, source, ind, to)?;
+            }
+        }
+        DecodeNode::Switch { mask, arms } => {
+            // Keys are shifted down to bit 0 to keep them dense.
+            let shift = mask.trailing_zeros();
+            let key = if shift == 0 {
+                format!("insn & {mask:#x}")
+            } else {
+                format!("(insn >> {shift}) & {:#x}", mask >> shift)
+            };
+            writeln!(w, "{ind}match {key} {{")?;
+            let inner = format!("{ind}        ");
+            let arm = format!("{ind}    ");
+            for (v, child) in arms {
+                let v = format!("{:#x}", v >> shift);
+                match child {
+                    // A lone pattern becomes a guarded arm.
+                    DecodeNode::Leaf(idx) if idx.len() == 1 => {
+                        let p = &patterns[idx[0]];
+                        writeln!(w, "{arm}// {source}:{}: {}", p.line, p.name)?;
+                        let head = format!("{arm}{v} if ");
+                        emit_cond(w, p, width, &head, " =>", &arm)?;
+                        emit_trans_call(w, p, argsets, width, &arm, to)?;
+                    }
+                    _ => {
+                        writeln!(w, "{arm}{v} => {{")?;
+                        emit_decode_node(
+                            w, child, patterns, argsets, opts, &inner, to,
+                        )?;
+                    }
+                }
+                writeln!(w, "{arm}}}")?;
+            }
+            writeln!(w, "{arm}_ => {{}}")?;
+            writeln!(w, "{ind}}}")?;
+        }
     }
     Ok(())
 }
 
+/// Emit `decode()`: a decision tree over the fixed bits down to
+/// short if-chains, so a word is matched against a few patterns
+/// instead of all of them.
 fn emit_decode_fn(
     w: &mut dyn Write,
     patterns: &[Pattern],
     argsets: &BTreeMap<String, ArgSet>,
-    width: u32,
+    opts: &GenOptions,
 ) -> std::io::Result<()> {
+    let width = opts.width;
     let insn_ty = if width <= 16 { "u16" } else { "u32" };
-    let trait_name = if width <= 16 { "Decode16" } else { "Decode" };
-    let fn_name = if width <= 16 { "decode16" } else { "decode" };
-    let full_mask: u32 = if width <= 16 { 0xffff } else { 0xffff_ffff };
+    let (fn_name, trait_name) = (opts.fn_name(), opts.trait_name());
+    opts.emit_allow(w)?;
     writeln!(
         w,
         "pub fn {fn_name}<Ir, T: {trait_name}<Ir>>(\
          ctx: &mut T, ir: &mut Ir, insn: {insn_ty}\
          ) -> bool {{"
     )?;
+    let full_mask: u32 = if width <= 16 { 0xffff } else { 0xffff_ffff };
+    let tree = build_tree(patterns, (0..patterns.len()).collect(), !full_mask);
+    emit_decode_node(
+        w,
+        &tree,
+        patterns,
+        argsets,
+        opts,
+        "    ",
+        Dispatch::Trans,
+    )?;
+    writeln!(w, "    false")?;
+    writeln!(w, "}}\n")
+}
+
+/// Emit the `Insn` enum, one variant per pattern holding its
+/// args, and `decode_to_insn()`, which returns the variant
+/// `decode()` would dispatch to.
+fn emit_insn_enum(
+    w: &mut dyn Write,
+    parsed: &Parsed,
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    let width = opts.width;
+    let insn_ty = if width <= 16 { "u16" } else { "u32" };
+    let name = opts.insn_enum_name();
+    let eq = if opts.derive_eq {
+        ", PartialEq, Eq"
+    } else {
+        ""
+    };
+    writeln!(w, "/// The pattern an instruction word decodes to.")?;
+    opts.emit_allow(w)?;
+    writeln!(w, "#[derive(Debug, Clone, Copy{eq})]")?;
+    writeln!(w, "pub enum {name} {{")?;
+    let mut seen = BTreeSet::new();
+    for p in &parsed.patterns {
+        if seen.insert(&p.name) {
+            let sname = args_struct_name(p);
+            writeln!(w, "    {}({sname}),", to_camel(&p.name))?;
+        }
+    }
+    writeln!(w, "}}\n")?;
+    writeln!(
+        w,
+        "/// Decode `insn` to the pattern `{}()` would dispatch it to.",
+        opts.fn_name()
+    )?;
+    opts.emit_allow(w)?;
+    writeln!(
+        w,
+        "pub fn {}_to_insn(insn: {insn_ty}) -> Option<{name}> {{",
+        opts.fn_name()
+    )?;
+    let patterns = &parsed.patterns;
+    let full_mask: u32 = if width <= 16 { 0xffff } else { 0xffff_ffff };
+    let tree = build_tree(patterns, (0..patterns.len()).collect(), !full_mask);
+    let to = Dispatch::Insn(name);
+    emit_decode_node(w, &tree, patterns, &parsed.argsets, opts, "    ", to)?;
+    writeln!(w, "    None")?;
+    writeln!(w, "}}\n")
+}
+
+/// Reject patterns whose names map to the same `Insn` variant.
+fn check_variants(parsed: &Parsed) -> Result<(), String> {
+    let mut variants: BTreeMap<String, &Pattern> = BTreeMap::new();
+    for p in &parsed.patterns {
+        let v = to_camel(&p.name);
+        match variants.get(&v) {
+            Some(q) if q.name != p.name => {
+                return Err(format!(
+                    "line {}: {}: variant {v} already taken by {} \
+                     (line {})",
+                    p.line, p.name, q.name, q.line
+                ));
+            }
+            _ => {
+                variants.insert(v, p);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Emit `decode_meta()`: the name and `!ext=` tags of the pattern
+/// `decode()` dispatches an instruction word to.
+fn emit_meta_fn(
+    w: &mut dyn Write,
+    patterns: &[Pattern],
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    let width = opts.width;
+    let insn_ty = if width <= 16 { "u16" } else { "u32" };
+    writeln!(
+        w,
+        "/// Pattern name and `!ext=` tags of the pattern that \
+         dispatch\n/// picks for `insn`."
+    )?;
+    opts.emit_allow(w)?;
+    writeln!(
+        w,
+        "pub fn {}_meta(insn: {insn_ty}) \
+         -> Option<(&'static str, &'static [&'static str])> {{",
+        opts.fn_name()
+    )?;
     for p in patterns {
-        let sname = if p.args_name.is_empty() {
-            "ArgsEmpty".to_string()
-        } else {
-            format!("Args{}", to_camel(&p.args_name))
-        };
-        if p.fixedmask == full_mask {
-            let bits = format_hex(p.fixedbits, width);
-            writeln!(w, "    if insn == {bits} {{")?;
-        } else {
-            let mask = format_hex(p.fixedmask, width);
-            let bits = format_hex(p.fixedbits, width);
-            writeln!(w, "    if insn & {mask} == {bits} {{")?;
-        }
-        // Build args struct
-        let arg_fields = if p.args_name.is_empty() {
-            Vec::new()
-        } else if let Some(a) = argsets.get(&p.args_name) {
-            a.fields.clone()
-        } else {
-            Vec::new()
-        };
-        if arg_fields.is_empty() {
+        emit_match(w, p, width, "    ")?;
+        let exts: Vec<String> =
+            p.exts.iter().map(|e| format!("{e:?}")).collect();
+        writeln!(
+            w,
+            "        return Some(({:?}, &[{}]));",
+            p.name,
+            exts.join(", ")
+        )?;
+        writeln!(w, "    }}")?;
+    }
+    writeln!(w, "    None")?;
+    writeln!(w, "}}\n")
+}
+
+/// Bits of the first halfword that every pattern fixes, and
+/// their value: what marks a 32-bit instruction in a mixed-width
+/// ISA. `None` if the patterns agree on none.
+fn long_insn_bits(patterns: &[Pattern]) -> Option<(u32, u32)> {
+    let first = patterns.first()?.fixedbits;
+    let mask = patterns
+        .iter()
+        .fold(0xffff, |m, p| m & p.fixedmask & !(p.fixedbits ^ first));
+    (mask != 0).then_some((mask, first & mask))
+}
+
+/// Emit `insn_len()`: the length in bytes of the instruction
+/// starting with a given halfword, 4 unless `long` says which
+/// halfwords start a 32-bit one.
+fn emit_insn_len(
+    w: &mut dyn Write,
+    long: Option<(u32, u32)>,
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    writeln!(
+        w,
+        "/// Length in bytes of the instruction whose first \
+         halfword\n/// is `first_halfword`."
+    )?;
+    opts.emit_allow(w)?;
+    writeln!(w, "pub fn insn_len(first_halfword: u16) -> usize {{")?;
+    match long {
+        Some((mask, bits)) => {
+            let (mask, bits) = (format_hex(mask, 16), format_hex(bits, 16));
+            writeln!(w, "    if first_halfword & {mask} == {bits} {{")?;
+            writeln!(w, "        4")?;
+            writeln!(w, "    }} else {{")?;
+            writeln!(w, "        2")?;
+            writeln!(w, "    }}")?;
+        }
+        None => {
+            writeln!(w, "    let _ = first_halfword;")?;
+            writeln!(w, "    4")?;
+        }
+    }
+    writeln!(w, "}}\n")
+}
+
+/// Emit `UNIMPLEMENTED_PATTERNS`: patterns, in source order,
+/// whose `trans_` method is not in `implemented`.
+fn emit_unimplemented(
+    w: &mut dyn Write,
+    patterns: &[Pattern],
+    implemented: &BTreeSet<String>,
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    let suffix = if opts.width <= 16 { "16" } else { "" };
+    writeln!(w, "/// Patterns that fall back to `unimplemented()`.")?;
+    opts.emit_allow(w)?;
+    let mut seen = BTreeSet::new();
+    let names: Vec<&String> = patterns
+        .iter()
+        .filter(|p| !implemented.contains(&p.trans))
+        .map(|p| &p.name)
+        .filter(|&n| seen.insert(n))
+        .collect();
+    if names.is_empty() {
+        return writeln!(
+            w,
+            "pub const UNIMPLEMENTED_PATTERNS{suffix}: &[&str] = &[];\n"
+        );
+    }
+    writeln!(w, "pub const UNIMPLEMENTED_PATTERNS{suffix}: &[&str] = &[")?;
+    for n in names {
+        writeln!(w, "    {n:?},")?;
+    }
+    writeln!(w, "];\n")
+}
+
+/// Emit `pattern_source()`: the `.decode` file and line of each
+/// pattern name, taking the first definition of a name.
+fn emit_pattern_source(
+    w: &mut dyn Write,
+    patterns: &[Pattern],
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    let suffix = if opts.width <= 16 { "16" } else { "" };
+    let source = &opts.source;
+    writeln!(
+        w,
+        "/// `.decode` file and line where the pattern `name` is \
+         defined."
+    )?;
+    opts.emit_allow(w)?;
+    writeln!(
+        w,
+        "pub fn pattern_source{suffix}(name: &str) \
+         -> Option<(&'static str, u32)> {{"
+    )?;
+    writeln!(w, "    match name {{")?;
+    let mut seen = std::collections::HashSet::new();
+    for p in patterns {
+        if seen.insert(&p.name) {
             writeln!(
                 w,
-                "        return ctx.trans_{}(\
-                 ir, &{sname} {{}});",
-                p.name
+                "        {:?} => Some(({source:?}, {})),",
+                p.name, p.line
             )?;
-        } else {
-            writeln!(w, "        let a = {sname} {{")?;
-            for af in &arg_fields {
-                if let Some(mapping) = p.field_map.get(af) {
-                    write!(w, "            {af}: ")?;
-                    emit_field_expr(w, af, mapping, width)?;
-                    writeln!(w, ",")?;
-                } else {
-                    writeln!(w, "            {af}: 0,")?;
+        }
+    }
+    writeln!(w, "        _ => None,")?;
+    writeln!(w, "    }}")?;
+    writeln!(w, "}}\n")
+}
+
+// ── Decode coverage ────────────────────────────────────────────
+
+/// Whether some instruction word matches both `a` and `b`.
+pub fn patterns_overlap(a: &Pattern, b: &Pattern) -> bool {
+    (a.fixedbits ^ b.fixedbits) & a.fixedmask & b.fixedmask == 0
+}
+
+/// The innermost group enclosing both `a` and `b`.
+fn shared_group(a: &Pattern, b: &Pattern) -> Option<usize> {
+    a.groups
+        .iter()
+        .zip(&b.groups)
+        .take_while(|(x, y)| x == y)
+        .last()
+        .map(|(&g, _)| g)
+}
+
+/// Check that no instruction word matches two patterns unless
+/// an overlap group `{ }` orders them. Patterns outside any such
+/// group would otherwise shadow each other silently, since
+/// `decode()` takes the first match.
+pub fn validate(parsed: &Parsed) -> Result<(), String> {
+    for (i, p) in parsed.patterns.iter().enumerate() {
+        for q in &parsed.patterns[..i] {
+            if !patterns_overlap(p, q) {
+                continue;
+            }
+            let shared = shared_group(p, q).map(|g| &parsed.groups[g]);
+            if shared.is_some_and(|g| g.overlap) {
+                continue;
+            }
+            let mut e = format!(
+                "line {}: pattern {} overlaps {} (line {}), both match {:#x}",
+                p.line,
+                p.name,
+                q.name,
+                q.line,
+                p.fixedbits | q.fixedbits
+            );
+            if let Some(g) = shared {
+                e += &format!(" in the [ ] group at line {}", g.line);
+            }
+            return Err(e);
+        }
+    }
+    check_trans(&parsed.patterns)
+}
+
+/// Check that the patterns sharing a name share its `trans_`
+/// method, and those sharing a method pass it one args struct.
+fn check_trans(patterns: &[Pattern]) -> Result<(), String> {
+    let mut by_name: BTreeMap<&str, &Pattern> = BTreeMap::new();
+    let mut by_trans: BTreeMap<&str, &Pattern> = BTreeMap::new();
+    for p in patterns {
+        let q = *by_name.entry(&p.name).or_insert(p);
+        if q.trans != p.trans {
+            return Err(format!(
+                "line {}: {} calls trans_{}, but line {} calls trans_{}",
+                p.line, p.name, p.trans, q.line, q.trans
+            ));
+        }
+        let q = *by_trans.entry(&p.trans).or_insert(p);
+        let (a, b) = (args_struct_name(p), args_struct_name(q));
+        if a != b {
+            return Err(format!(
+                "line {}: {}: trans_{} takes {b} (line {}), not {a}",
+                p.line, p.name, p.trans, q.line
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Deterministic nonzero value for the `ordinal`-th (from 1)
+/// field of a pattern, in range for a `len`-bit field.  Signed
+/// fields get a negative value so sign extension is exercised.
+pub fn fill_value(ordinal: u32, len: u32, signed: bool) -> i64 {
+    let k = ordinal.max(1) as i64 - 1;
+    if signed {
+        let span = 1i64 << (len - 1);
+        -(1 + k % span)
+    } else {
+        let span = (1i64 << len) - 1;
+        1 + k % span
+    }
+}
+
+/// Instruction bits of the field `name` of `p`; `None` for a
+/// constant.
+fn field_segments(
+    p: &Pattern,
+    fields: &BTreeMap<String, Field>,
+    name: &str,
+) -> Option<Vec<FieldSegment>> {
+    match p.field_map.get(name)? {
+        FieldMapping::FieldRef(r) => fields.get(r).map(|f| f.segments.clone()),
+        FieldMapping::Inline { pos, len, signed } => Some(vec![FieldSegment {
+            pos: *pos,
+            len: *len,
+            signed: *signed,
+        }]),
+        FieldMapping::Const(_) | FieldMapping::Func(_) => None,
+    }
+}
+
+/// Raw value of the field at `segs` in `insn`, before any
+/// `!function=`.
+fn segments_value(segs: &[FieldSegment], insn: u32) -> i64 {
+    let len: u32 = segs.iter().map(|s| s.len).sum();
+    let mut val = 0i64;
+    for s in segs {
+        val = val << s.len | ((insn >> s.pos) & ((1u32 << s.len) - 1)) as i64;
+    }
+    if segs[0].signed && val >> (len - 1) & 1 != 0 {
+        val -= 1 << len;
+    }
+    val
+}
+
+/// Whether `decode` would dispatch `insn` to `p`, were no
+/// earlier pattern to match: its fixed bits, and no `!reserved`
+/// field value.
+pub fn pattern_matches(parsed: &Parsed, p: &Pattern, insn: u32) -> bool {
+    insn & p.fixedmask == p.fixedbits
+        && p.reserved.iter().all(|r| {
+            let segs = field_segments(p, &parsed.fields, &r.field).unwrap();
+            !r.contains(segments_value(&segs, insn))
+        })
+}
+
+/// Build one instruction word that `decode` dispatches to
+/// `patterns[idx]`: its fixed bits, every field filled with
+/// `fill_value` (stepped past `!reserved` values), then bits
+/// flipped until no earlier overlapping pattern matches.  Fails
+/// if an earlier pattern shadows it.
+pub fn canonical_encoding(
+    parsed: &Parsed,
+    idx: usize,
+    width: u32,
+) -> Result<u32, String> {
+    let wmask = if width >= 32 {
+        u32::MAX
+    } else {
+        (1u32 << width) - 1
+    };
+    let p = &parsed.patterns[idx];
+    let mut word = 0u32;
+    let fields = p
+        .field_map
+        .keys()
+        .filter_map(|k| Some((k, field_segments(p, &parsed.fields, k)?)));
+    for (i, (name, segs)) in fields.enumerate() {
+        let len: u32 = segs.iter().map(|s| s.len).sum();
+        let signed = segs[0].signed;
+        let mut val = fill_value(i as u32 + 1, len, signed);
+        let reserved = p.reserved.iter().filter(|r| &r.field == name);
+        for r in reserved {
+            for _ in 0..1u64 << len.min(16) {
+                if !r.contains(val) {
+                    break;
                 }
+                // Next value, wrapping within the field.
+                let (min, span) = if signed {
+                    (-(1i64 << (len - 1)), 1i64 << len)
+                } else {
+                    (0, 1i64 << len)
+                };
+                val = (val + 1 - min).rem_euclid(span) + min;
             }
-            writeln!(w, "        }};")?;
-            writeln!(w, "        return ctx.trans_{}(ir, &a);", p.name)?;
         }
-        writeln!(w, "    }}")?;
+        let raw = val as u32;
+        let mut shift = len;
+        for s in &segs {
+            shift -= s.len;
+            let bits = (raw >> shift) & ((1u32 << s.len) - 1);
+            word |= bits << s.pos;
+        }
     }
-    writeln!(w, "    false")?;
-    writeln!(w, "}}\n")
+    word = p.fixedbits | (word & !p.fixedmask & wmask);
+
+    let earlier: Vec<&Pattern> = parsed.patterns[..idx]
+        .iter()
+        .filter(|q| patterns_overlap(p, q))
+        .collect();
+    for _ in 0..=earlier.len() {
+        if !pattern_matches(parsed, p, word) {
+            break;
+        }
+        let Some(q) = earlier.iter().find(|q| pattern_matches(parsed, q, word))
+        else {
+            return Ok(word);
+        };
+        // Bits `q` fixes but `p` leaves free tell them apart.
+        let free = q.fixedmask & !p.fixedmask & wmask;
+        if free == 0 {
+            return Err(format!(
+                "pattern {} is shadowed by {}",
+                p.name, q.name
+            ));
+        }
+        word ^= free & free.wrapping_neg();
+    }
+    Err(format!("no canonical encoding for pattern {}", p.name))
+}
+
+fn emit_coverage(
+    w: &mut dyn Write,
+    parsed: &Parsed,
+    opts: &GenOptions,
+) -> std::io::Result<()> {
+    let width = opts.width;
+    let (suffix, insn) = if width <= 16 {
+        ("16", "insn as u16")
+    } else {
+        ("", "insn")
+    };
+    let (trait_name, fn_name) = (opts.trait_name(), opts.fn_name());
+    writeln!(
+        w,
+        "/// One instruction word per pattern, each decoding to \
+         that pattern."
+    )?;
+    opts.emit_allow(w)?;
+    writeln!(
+        w,
+        "pub const CANONICAL_ENCODINGS{suffix}: &[(&str, u32)] = &["
+    )?;
+    for (i, p) in parsed.patterns.iter().enumerate() {
+        match canonical_encoding(parsed, i, width) {
+            Ok(insn) => writeln!(
+                w,
+                "    (\"{}\", {}),",
+                p.name,
+                format_hex(insn, width)
+            )?,
+            // Reachable only if an earlier trans_ could decline.
+            Err(e) => writeln!(w, "    // unreachable: {e}")?,
+        }
+    }
+    writeln!(w, "];\n")?;
+
+    writeln!(w, "#[cfg(test)]")?;
+    opts.emit_allow(w)?;
+    writeln!(w, "mod {fn_name}_coverage {{")?;
+    writeln!(w, "    use super::*;\n")?;
+    writeln!(w, "    /// Records which pattern a decode dispatched to.")?;
+    writeln!(w, "    #[derive(Default)]")?;
+    writeln!(w, "    struct Recorder {{")?;
+    writeln!(w, "        hit: Option<&'static str>,")?;
+    writeln!(w, "    }}\n")?;
+    writeln!(w, "    impl {trait_name}<()> for Recorder {{")?;
+    if opts.partial.is_some() {
+        writeln!(w, "        fn insn(&self) -> u32 {{")?;
+        writeln!(w, "            0")?;
+        writeln!(w, "        }}\n")?;
+    }
+    let mut seen = std::collections::HashSet::new();
+    for p in &parsed.patterns {
+        if !seen.insert(&p.trans) {
+            continue;
+        }
+        let sname = args_struct_name(p);
+        writeln!(
+            w,
+            "        fn trans_{}(\
+             &mut self, _ir: &mut (), _a: &{sname}\
+             ) -> bool {{",
+            p.trans
+        )?;
+        writeln!(w, "            self.hit = Some(\"{}\");", p.trans)?;
+        writeln!(w, "            true")?;
+        writeln!(w, "        }}")?;
+    }
+    writeln!(w, "    }}\n")?;
+    // Patterns calling another pattern's trans_ method.
+    let aliases: Vec<&Pattern> = parsed
+        .patterns
+        .iter()
+        .filter(|p| p.trans != p.name)
+        .collect();
+    let aliased = !aliases.is_empty();
+    if aliased {
+        writeln!(w, "    const TRANS: &[(&str, &str)] = &[")?;
+        for p in aliases {
+            writeln!(w, "        ({:?}, {:?}),", p.name, p.trans)?;
+        }
+        writeln!(w, "    ];\n")?;
+    }
+    writeln!(w, "    #[test]")?;
+    writeln!(w, "    fn canonical_encodings_dispatch_to_own_pattern() {{")?;
+    writeln!(
+        w,
+        "        for &(name, insn) in CANONICAL_ENCODINGS{suffix} {{"
+    )?;
+    writeln!(w, "            let mut r = Recorder::default();")?;
+    writeln!(
+        w,
+        "            assert!({fn_name}(&mut r, &mut (), {insn}));"
+    )?;
+    // The trans_ method the pattern should reach.
+    let want = if aliased {
+        writeln!(
+            w,
+            "            let trans = \
+             TRANS.iter().find(|t| t.0 == name).map_or(name, |t| t.1);"
+        )?;
+        "trans"
+    } else {
+        "name"
+    };
+    if opts.source_table {
+        writeln!(
+            w,
+            "            assert_eq!(r.hit, Some({want}), \"{{insn:#x}} \
+             ({{:?}})\", pattern_source{suffix}(name));"
+        )?;
+    } else {
+        writeln!(
+            w,
+            "            assert_eq!(r.hit, Some({want}), \"{{insn:#x}}\");"
+        )?;
+    }
+    writeln!(w, "        }}")?;
+    writeln!(w, "    }}")?;
+    writeln!(w, "}}")
 }
 
 // ── Public API ─────────────────────────────────────────────────
 
-pub fn generate_with_width(
+/// Code generation options.
+#[derive(Clone, Debug)]
+pub struct GenOptions {
+    /// Instruction width in bits (16 or 32).
+    pub width: u32,
+    /// Also emit `CANONICAL_ENCODINGS` and a `cfg(test)` module
+    /// checking that each entry decodes to its own pattern.
+    pub coverage: bool,
+    /// Give every `trans_` method a default that calls the
+    /// trait's `unimplemented()` hook, and emit
+    /// `UNIMPLEMENTED_PATTERNS` listing the patterns not in this
+    /// set (see `implemented_trans`).
+    pub partial: Option<BTreeSet<String>>,
+    /// Name of the `.decode` file, cited with line numbers in
+    /// comments on the generated code.
+    pub source: String,
+    /// Also emit `pattern_source()` (`pattern_source16()` for
+    /// 16-bit), mapping pattern names to their source line.
+    pub source_table: bool,
+    /// The ISA mixes these 32-bit instructions with 16-bit ones
+    /// from another `.decode` file: `insn_len()` tells them apart
+    /// by the low bits every pattern here fixes. Unused for
+    /// 16-bit.
+    pub mixed_width: bool,
+    /// `!function=` names beyond the built-in RISC-V ones. The
+    /// module including the generated code provides each as
+    /// `fn(i64) -> i64`; any other name is an error.
+    pub extern_funcs: BTreeSet<String>,
+    /// Name of the generated trait; `Decode` (`Decode16` for
+    /// 16-bit) if `None`.
+    pub trait_name: Option<String>,
+    /// Name of the generated decode function; `decode`
+    /// (`decode16`) if `None`. `decode_meta()` and the coverage
+    /// module take it as prefix.
+    pub fn_name: Option<String>,
+    /// Visibility of the args structs and their fields, e.g.
+    /// `pub(crate)`; empty for private.
+    pub args_vis: String,
+    /// Also derive `PartialEq` and `Eq` on the args structs.
+    pub derive_eq: bool,
+    /// Lints named in an `#[allow(..)]` on every generated item.
+    pub allow: Vec<String>,
+    /// Module path, e.g. `super`, to import the structs of
+    /// `!extern` argsets from, as when a second `.decode` file
+    /// shares the first one's. `None` leaves them to the module
+    /// including the generated code.
+    pub extern_args: Option<String>,
+    /// Also emit `enum Insn` (`Insn16` for 16-bit), a variant
+    /// per pattern holding its args struct, and
+    /// `decode_to_insn()` returning the one `decode()` would
+    /// dispatch to, for callers that only need to know what
+    /// matched.
+    pub insn_enum: bool,
+}
+
+impl Default for GenOptions {
+    fn default() -> Self {
+        Self {
+            width: 32,
+            coverage: false,
+            partial: None,
+            source: "<input>".to_string(),
+            source_table: false,
+            mixed_width: false,
+            extern_funcs: BTreeSet::new(),
+            trait_name: None,
+            fn_name: None,
+            args_vis: "pub".to_string(),
+            derive_eq: false,
+            allow: Vec::new(),
+            extern_args: None,
+            insn_enum: false,
+        }
+    }
+}
+
+impl GenOptions {
+    fn trait_name(&self) -> &str {
+        match &self.trait_name {
+            Some(name) => name,
+            None if self.width <= 16 => "Decode16",
+            None => "Decode",
+        }
+    }
+
+    fn fn_name(&self) -> &str {
+        match &self.fn_name {
+            Some(name) => name,
+            None if self.width <= 16 => "decode16",
+            None => "decode",
+        }
+    }
+
+    fn insn_enum_name(&self) -> &str {
+        if self.width <= 16 {
+            "Insn16"
+        } else {
+            "Insn"
+        }
+    }
+
+    /// Emit the `#[allow(..)]` line, if any, heading an item.
+    fn emit_allow(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        if self.allow.is_empty() {
+            return Ok(());
+        }
+        writeln!(w, "#[allow({})]", self.allow.join(", "))
+    }
+}
+
+/// Pattern names with a `trans_` method in the `impl
+/// <trait_name><..> for ..` blocks of `src`.  Blocks are taken
+/// to end at the first `}` in column 0, as rustfmt leaves them.
+pub fn implemented_trans(src: &str, trait_name: &str) -> BTreeSet<String> {
+    let header = format!(" {trait_name}<");
+    let mut names = BTreeSet::new();
+    let mut inside = false;
+    for line in src.lines() {
+        if !inside {
+            inside = line.starts_with("impl") && line.contains(&header);
+            continue;
+        }
+        if line == "}" {
+            inside = false;
+            continue;
+        }
+        let Some(rest) = line.trim_start().strip_prefix("fn trans_") else {
+            continue;
+        };
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        names.insert(rest[..end].to_string());
+    }
+    names
+}
+
+pub fn generate_with_options(
     input: &str,
     output: &mut dyn Write,
-    width: u32,
+    opts: &GenOptions,
 ) -> Result<(), String> {
+    let width = opts.width;
     let parsed = parse_with_width(input, width)?;
+    validate(&parsed)?;
+    check_funcs(&parsed, &opts.extern_funcs)?;
+    if opts.insn_enum {
+        check_variants(&parsed)?;
+    }
     writeln!(output, "// Auto-generated by decode.")
         .map_err(|e| e.to_string())?;
     writeln!(output, "// Do not edit.\n").map_err(|e| e.to_string())?;
-    emit_arg_structs(output, &parsed.argsets).map_err(|e| e.to_string())?;
+    if let Some(path) = &opts.extern_args {
+        emit_extern_uses(output, &parsed, path).map_err(|e| e.to_string())?;
+    }
+    emit_arg_structs(output, &parsed.argsets, opts)
+        .map_err(|e| e.to_string())?;
     for field in parsed.fields.values() {
-        emit_extract_field(output, field, width).map_err(|e| e.to_string())?;
+        emit_extract_field(output, field, opts).map_err(|e| e.to_string())?;
     }
-    emit_decode_trait(output, &parsed.patterns, &parsed.argsets, width)
+    emit_decode_trait(output, &parsed.patterns, opts)
         .map_err(|e| e.to_string())?;
-    emit_decode_fn(output, &parsed.patterns, &parsed.argsets, width)
+    emit_decode_fn(output, &parsed.patterns, &parsed.argsets, opts)
         .map_err(|e| e.to_string())?;
+    emit_meta_fn(output, &parsed.patterns, opts).map_err(|e| e.to_string())?;
+    if opts.insn_enum {
+        emit_insn_enum(output, &parsed, opts).map_err(|e| e.to_string())?;
+    }
+    if width > 16 {
+        let long = if opts.mixed_width {
+            let bits = long_insn_bits(&parsed.patterns);
+            if bits.is_none() {
+                return Err("mixed width: the patterns fix no common \
+                            low bits to tell 32-bit instructions by"
+                    .to_string());
+            }
+            bits
+        } else {
+            None
+        };
+        emit_insn_len(output, long, opts).map_err(|e| e.to_string())?;
+    }
+    if let Some(implemented) = &opts.partial {
+        emit_unimplemented(output, &parsed.patterns, implemented, opts)
+            .map_err(|e| e.to_string())?;
+    }
+    if opts.source_table {
+        emit_pattern_source(output, &parsed.patterns, opts)
+            .map_err(|e| e.to_string())?;
+    }
+    if opts.coverage {
+        emit_coverage(output, &parsed, opts).map_err(|e| e.to_string())?;
+    }
     Ok(())
 }
 
+pub fn generate_with_width(
+    input: &str,
+    output: &mut dyn Write,
+    width: u32,
+) -> Result<(), String> {
+    let opts = GenOptions {
+        width,
+        ..GenOptions::default()
+    };
+    generate_with_options(input, output, &opts)
+}
+
 pub fn generate(input: &str, output: &mut dyn Write) -> Result<(), String> {
-    generate_with_width(input, output, 32)
+    generate_with_options(input, output, &GenOptions::default())
 }
@@ -6,3 +6,4 @@
 //! PC and returns a human-readable string plus instruction length.
 
 pub mod riscv;
+pub mod x86_64;
@@ -0,0 +1,506 @@
+//! x86-64 disassembler — the subset the TCG backend emits.
+//!
+//! Intel syntax, integer instructions only: what
+//! `tcg_backend::x86_64::emitter` can produce, plus the multi-byte
+//! NOPs used for padding. Any other byte decodes as `.byte`.
+
+// -- Register names --
+
+const REG64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10",
+    "r11", "r12", "r13", "r14", "r15",
+];
+
+const REG32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d",
+    "r10d", "r11d", "r12d", "r13d", "r14d", "r15d",
+];
+
+const REG16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w",
+    "r11w", "r12w", "r13w", "r14w", "r15w",
+];
+
+/// Byte registers with a REX prefix present.
+const REG8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b",
+    "r11b", "r12b", "r13b", "r14b", "r15b",
+];
+
+/// Byte registers 4–7 without REX.
+const REG8_HIGH: [&str; 4] = ["ah", "ch", "dh", "bh"];
+
+const CC: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l",
+    "ge", "le", "g",
+];
+
+const ARITH: [&str; 8] =
+    ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+
+const SHIFT: [&str; 8] =
+    ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+
+const BT: [&str; 4] = ["bt", "bts", "btr", "btc"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Size {
+    B,
+    W,
+    D,
+    Q,
+}
+
+impl Size {
+    fn ptr(self) -> &'static str {
+        match self {
+            Size::B => "byte",
+            Size::W => "word",
+            Size::D => "dword",
+            Size::Q => "qword",
+        }
+    }
+}
+
+enum Rm {
+    Reg(u8),
+    /// Address expression, brackets included.
+    Mem(String),
+}
+
+struct ModRm {
+    /// The reg field, extended by REX.R.
+    reg: u8,
+    /// The raw reg field, for group opcodes.
+    ext: u8,
+    rm: Rm,
+}
+
+fn imm(v: i64) -> String {
+    if v < 0 {
+        format!("-{:#x}", v.unsigned_abs())
+    } else {
+        format!("{v:#x}")
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    rex: u8,
+    data16: bool,
+    /// 0xF2 or 0xF3 prefix.
+    rep: Option<u8>,
+}
+
+impl Decoder<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let b = self.data.get(self.pos..self.pos + N)?;
+        self.pos += N;
+        b.try_into().ok()
+    }
+
+    fn i8(&mut self) -> Option<i64> {
+        Some(self.u8()? as i8 as i64)
+    }
+
+    fn i32(&mut self) -> Option<i64> {
+        Some(i32::from_le_bytes(self.bytes()?) as i64)
+    }
+
+    /// A `z`-sized immediate: 16 bits under 0x66, else 32.
+    fn iz(&mut self) -> Option<i64> {
+        if self.data16 {
+            Some(i16::from_le_bytes(self.bytes()?) as i64)
+        } else {
+            self.i32()
+        }
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes()?))
+    }
+
+    /// Operand size of a `v`-sized instruction.
+    fn vsize(&self) -> Size {
+        if self.rex & 8 != 0 {
+            Size::Q
+        } else if self.data16 {
+            Size::W
+        } else {
+            Size::D
+        }
+    }
+
+    fn reg(&self, r: u8, size: Size) -> &'static str {
+        let r = r as usize;
+        match size {
+            Size::Q => REG64[r],
+            Size::D => REG32[r],
+            Size::W => REG16[r],
+            Size::B if self.rex == 0 && (4..8).contains(&r) => REG8_HIGH[r - 4],
+            Size::B => REG8[r],
+        }
+    }
+
+    /// Register encoded in the low opcode bits, extended by REX.B.
+    fn opreg(&self, op: u8) -> u8 {
+        op & 7 | (self.rex & 1) << 3
+    }
+
+    fn modrm(&mut self) -> Option<ModRm> {
+        let b = self.u8()?;
+        let (md, ext, rm) = (b >> 6, (b >> 3) & 7, b & 7);
+        let reg = ext | (self.rex & 4) << 1;
+        if md == 3 {
+            let rm = Rm::Reg(rm | (self.rex & 1) << 3);
+            return Some(ModRm { reg, ext, rm });
+        }
+        let mut parts = Vec::new();
+        let mut disp_only = false;
+        if rm == 4 {
+            let sib = self.u8()?;
+            let (scale, idx, base) = (sib >> 6, (sib >> 3) & 7, sib & 7);
+            if base == 5 && md == 0 {
+                disp_only = true;
+            } else {
+                let base = base | (self.rex & 1) << 3;
+                parts.push(REG64[base as usize].to_string());
+            }
+            let idx = idx | (self.rex & 2) << 2;
+            if idx != 4 {
+                parts.push(match scale {
+                    0 => REG64[idx as usize].to_string(),
+                    s => format!("{}*{}", REG64[idx as usize], 1 << s),
+                });
+            }
+        } else if rm == 5 && md == 0 {
+            parts.push("rip".to_string());
+            disp_only = true;
+        } else {
+            let base = rm | (self.rex & 1) << 3;
+            parts.push(REG64[base as usize].to_string());
+        }
+        let disp = match md {
+            1 => self.i8()?,
+            2 => self.i32()?,
+            _ if disp_only => self.i32()?,
+            _ => 0,
+        };
+        let mut addr = parts.join("+");
+        if addr.is_empty() {
+            addr = format!("{:#x}", disp as u32);
+        } else if disp != 0 {
+            let d = imm(disp);
+            let sign = if disp > 0 { "+" } else { "" };
+            addr = format!("{addr}{sign}{d}");
+        }
+        Some(ModRm {
+            reg,
+            ext,
+            rm: Rm::Mem(format!("[{addr}]")),
+        })
+    }
+
+    /// The r/m operand; memory carries its size.
+    fn rm(&self, m: &ModRm, size: Size) -> String {
+        match &m.rm {
+            Rm::Reg(r) => self.reg(*r, size).to_string(),
+            Rm::Mem(addr) => format!("{} ptr {addr}", size.ptr()),
+        }
+    }
+
+    fn insn(&mut self, pc: u64) -> Option<String> {
+        loop {
+            match self.peek()? {
+                0x66 => self.data16 = true,
+                b @ (0xF2 | 0xF3) => self.rep = Some(b),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if let 0x40..=0x4F = self.peek()? {
+            self.rex = self.u8()?;
+        }
+        let op = self.u8()?;
+        if op == 0x0F {
+            return self.insn_0f(pc);
+        }
+        if matches!(op, 0xC4 | 0xC5) && self.rex == 0 {
+            return self.insn_vex(op);
+        }
+        let v = self.vsize();
+        Some(match op {
+            0x00..=0x3F if op & 7 == 1 => {
+                let m = self.modrm()?;
+                let rm = self.rm(&m, v);
+                format!(
+                    "{} {rm}, {}",
+                    ARITH[op as usize >> 3],
+                    self.reg(m.reg, v)
+                )
+            }
+            0x00..=0x3F if op & 7 == 3 => {
+                let m = self.modrm()?;
+                let rm = self.rm(&m, v);
+                format!(
+                    "{} {}, {rm}",
+                    ARITH[op as usize >> 3],
+                    self.reg(m.reg, v)
+                )
+            }
+            0x50..=0x57 => format!("push {}", REG64[self.opreg(op) as usize]),
+            0x58..=0x5F => format!("pop {}", REG64[self.opreg(op) as usize]),
+            0x63 => {
+                let m = self.modrm()?;
+                format!(
+                    "movsxd {}, {}",
+                    self.reg(m.reg, v),
+                    self.rm(&m, Size::D)
+                )
+            }
+            0x68 => format!("push {}", imm(self.iz()?)),
+            0x6A => format!("push {}", imm(self.i8()?)),
+            0x69 | 0x6B => {
+                let m = self.modrm()?;
+                let i = if op == 0x69 { self.iz()? } else { self.i8()? };
+                let rm = self.rm(&m, v);
+                format!("imul {}, {rm}, {}", self.reg(m.reg, v), imm(i))
+            }
+            0x81 | 0x83 => {
+                let m = self.modrm()?;
+                let i = if op == 0x81 { self.iz()? } else { self.i8()? };
+                format!(
+                    "{} {}, {}",
+                    ARITH[m.ext as usize],
+                    self.rm(&m, v),
+                    imm(i)
+                )
+            }
+            0x84 | 0x85 | 0x87 | 0x88 | 0x89 => {
+                let name = match op {
+                    0x84 | 0x85 => "test",
+                    0x87 => "xchg",
+                    _ => "mov",
+                };
+                let size = if op & 1 == 0 { Size::B } else { v };
+                let m = self.modrm()?;
+                format!(
+                    "{name} {}, {}",
+                    self.rm(&m, size),
+                    self.reg(m.reg, size)
+                )
+            }
+            0x8B => {
+                let m = self.modrm()?;
+                format!("mov {}, {}", self.reg(m.reg, v), self.rm(&m, v))
+            }
+            0x8D => {
+                let m = self.modrm()?;
+                let Rm::Mem(addr) = &m.rm else {
+                    return None;
+                };
+                format!("lea {}, {addr}", self.reg(m.reg, v))
+            }
+            0x90 if self.rex & 1 == 0 => "nop".to_string(),
+            0x99 => match v {
+                Size::Q => "cqo",
+                Size::W => "cwd",
+                _ => "cdq",
+            }
+            .to_string(),
+            0xB8..=0xBF if v == Size::Q => {
+                let r = REG64[self.opreg(op) as usize];
+                format!("movabs {r}, {:#x}", self.u64()?)
+            }
+            0xB8..=0xBF if v == Size::D => {
+                let r = self.reg(self.opreg(op), v);
+                format!("mov {r}, {:#x}", self.i32()? as u32)
+            }
+            0xC1 | 0xD1 | 0xD3 => {
+                let m = self.modrm()?;
+                let count = match op {
+                    0xC1 => format!("{:#x}", self.u8()?),
+                    0xD1 => "1".to_string(),
+                    _ => "cl".to_string(),
+                };
+                format!("{} {}, {count}", SHIFT[m.ext as usize], self.rm(&m, v))
+            }
+            0xC3 => "ret".to_string(),
+            0xC6 | 0xC7 => {
+                let m = self.modrm()?;
+                if m.ext != 0 {
+                    return None;
+                }
+                if op == 0xC6 {
+                    format!("mov {}, {:#x}", self.rm(&m, Size::B), self.u8()?)
+                } else {
+                    format!("mov {}, {}", self.rm(&m, v), imm(self.iz()?))
+                }
+            }
+            0xE8 | 0xE9 | 0xEB => {
+                let rel = if op == 0xEB { self.i8()? } else { self.i32()? };
+                let target =
+                    pc.wrapping_add(self.pos as u64).wrapping_add(rel as u64);
+                let name = if op == 0xE8 { "call" } else { "jmp" };
+                format!("{name} {target:#x}")
+            }
+            0xF6 | 0xF7 => {
+                let m = self.modrm()?;
+                let size = if op == 0xF6 { Size::B } else { v };
+                let rm = self.rm(&m, size);
+                match m.ext {
+                    0 if op == 0xF6 => format!("test {rm}, {:#x}", self.u8()?),
+                    0 => format!("test {rm}, {}", imm(self.iz()?)),
+                    2 => format!("not {rm}"),
+                    3 => format!("neg {rm}"),
+                    4 => format!("mul {rm}"),
+                    5 => format!("imul {rm}"),
+                    6 => format!("div {rm}"),
+                    7 => format!("idiv {rm}"),
+                    _ => return None,
+                }
+            }
+            0xF9 => "stc".to_string(),
+            0xFF => {
+                let m = self.modrm()?;
+                match m.ext {
+                    0 => format!("inc {}", self.rm(&m, v)),
+                    1 => format!("dec {}", self.rm(&m, v)),
+                    2 => format!("call {}", self.rm(&m, Size::Q)),
+                    4 => format!("jmp {}", self.rm(&m, Size::Q)),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Two-byte opcodes, after the 0x0F escape.
+    fn insn_0f(&mut self, pc: u64) -> Option<String> {
+        let op = self.u8()?;
+        let v = self.vsize();
+        let f3 = self.rep == Some(0xF3);
+        Some(match op {
+            0x0B => "ud2".to_string(),
+            0x1F => {
+                let m = self.modrm()?;
+                format!("nop {}", self.rm(&m, v))
+            }
+            0x40..=0x4F => {
+                let m = self.modrm()?;
+                let cc = CC[op as usize & 0xF];
+                format!("cmov{cc} {}, {}", self.reg(m.reg, v), self.rm(&m, v))
+            }
+            0x80..=0x8F => {
+                let rel = self.i32()?;
+                let target =
+                    pc.wrapping_add(self.pos as u64).wrapping_add(rel as u64);
+                format!("j{} {target:#x}", CC[op as usize & 0xF])
+            }
+            0x90..=0x9F => {
+                let m = self.modrm()?;
+                format!("set{} {}", CC[op as usize & 0xF], self.rm(&m, Size::B))
+            }
+            0xA4 | 0xAC => {
+                let m = self.modrm()?;
+                let name = if op == 0xA4 { "shld" } else { "shrd" };
+                let rm = self.rm(&m, v);
+                format!(
+                    "{name} {rm}, {}, {:#x}",
+                    self.reg(m.reg, v),
+                    self.u8()?
+                )
+            }
+            0xAE if self.u8()? == 0xF0 => "mfence".to_string(),
+            0xAF => {
+                let m = self.modrm()?;
+                format!("imul {}, {}", self.reg(m.reg, v), self.rm(&m, v))
+            }
+            0xB6 | 0xB7 | 0xBE | 0xBF => {
+                let m = self.modrm()?;
+                let name = if op & 8 == 0 { "movzx" } else { "movsx" };
+                let src = if op & 1 == 0 { Size::B } else { Size::W };
+                format!("{name} {}, {}", self.reg(m.reg, v), self.rm(&m, src))
+            }
+            0xB8 | 0xBC | 0xBD => {
+                let m = self.modrm()?;
+                let name = match (op, f3) {
+                    (0xB8, true) => "popcnt",
+                    (0xBC, true) => "tzcnt",
+                    (0xBD, true) => "lzcnt",
+                    (0xBC, false) => "bsf",
+                    (0xBD, false) => "bsr",
+                    _ => return None,
+                };
+                format!("{name} {}, {}", self.reg(m.reg, v), self.rm(&m, v))
+            }
+            0xBA => {
+                let m = self.modrm()?;
+                let name = BT.get((m.ext as usize).checked_sub(4)?)?;
+                format!("{name} {}, {:#x}", self.rm(&m, v), self.u8()?)
+            }
+            0xC8..=0xCF => format!("bswap {}", self.reg(self.opreg(op), v)),
+            _ => return None,
+        })
+    }
+
+    /// VEX-encoded instructions; only BMI1 `andn`.
+    fn insn_vex(&mut self, op: u8) -> Option<String> {
+        let b1 = self.u8()?;
+        let (map, vvvv, b2) = if op == 0xC5 {
+            (1, b1, b1)
+        } else {
+            let b2 = self.u8()?;
+            (b1 & 0x1F, b2, b2)
+        };
+        self.rex |= (!b1 >> 5) & 4;
+        if op == 0xC4 {
+            self.rex |= (!b1 >> 5) & 3;
+            self.rex |= (b2 >> 4) & 8;
+        }
+        let v = (!vvvv >> 3) & 0xF;
+        let opc = self.u8()?;
+        if map != 2 || opc != 0xF2 || b2 & 3 != 0 {
+            return None;
+        }
+        let m = self.modrm()?;
+        let size = if self.rex & 8 != 0 { Size::Q } else { Size::D };
+        Some(format!(
+            "andn {}, {}, {}",
+            self.reg(m.reg, size),
+            self.reg(v, size),
+            self.rm(&m, size)
+        ))
+    }
+}
+
+/// Disassemble one x86-64 instruction at `pc`.
+///
+/// Returns `(assembly_text, instruction_length_in_bytes)`.
+/// Bytes outside the supported subset come back one at a time
+/// as `.byte`; empty input gives length 0.
+pub fn print_insn_x86_64(pc: u64, data: &[u8]) -> (String, usize) {
+    let mut d = Decoder {
+        data,
+        pos: 0,
+        rex: 0,
+        data16: false,
+        rep: None,
+    };
+    match (d.insn(pc), data.first()) {
+        (Some(text), _) => (text, d.pos),
+        (None, Some(b)) => (format!(".byte {b:#04x}"), 1),
+        (None, None) => (".byte ???".into(), 0),
+    }
+}
@@ -0,0 +1,260 @@
+//! CPU and wall-clock budgets for a vCPU.
+//!
+//! The exec loop accumulates the thread CPU time it runs for
+//! in `PerCpuState::cpu_time`. A `BudgetWatch` adds limits: a
+//! watchdog thread polls the vCPU thread's CPU clock and the
+//! wall clock, and on overrun raises an exit request and
+//! unlinks every chained jump, so a guest looping through
+//! chained TBs returns to the exec loop at its next TB
+//! boundary. The loop checks the request before entering each
+//! TB and returns `ExitReason::TimedOut` with a report of
+//! where the vCPU was. A vCPU blocked in a host system call
+//! is only stopped once the call returns.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tcg_backend::HostCodeGen;
+
+use crate::SharedState;
+
+/// Watchdog polling interval.
+const TICK: Duration = Duration::from_millis(10);
+
+/// TB entries kept for the report.
+pub const RECENT_LEN: usize = 16;
+
+/// CPU time consumed by the calling thread.
+pub fn thread_cpu_time() -> Duration {
+    clock_time(libc::CLOCK_THREAD_CPUTIME_ID).unwrap_or_default()
+}
+
+fn clock_time(clock: libc::clockid_t) -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clock, &mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Limits of one vCPU; `None` is unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// CPU time of the vCPU thread.
+    pub cpu: Option<Duration>,
+    /// Wall-clock time.
+    pub wall: Option<Duration>,
+}
+
+/// The limit that ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overrun {
+    Cpu,
+    Wall,
+}
+
+impl Overrun {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Overrun::Cpu),
+            2 => Some(Overrun::Wall),
+            _ => None,
+        }
+    }
+}
+
+/// Where a vCPU was when its budget ran out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutReport {
+    pub overrun: Overrun,
+    /// The limit that ran out.
+    pub limit: Duration,
+    /// CPU time of the vCPU thread since the budget was set.
+    pub thread_cpu: Duration,
+    /// Of which in the exec loop, i.e. running guest code.
+    pub loop_cpu: Duration,
+    /// Wall-clock time since the budget was set.
+    pub wall: Duration,
+    /// Guest instructions retired.
+    pub insns: u64,
+    /// Guest pc the vCPU would resume at.
+    pub pc: u64,
+    /// pcs of the last TBs the exec loop entered, oldest
+    /// first. TBs reached through chained jumps are missing.
+    pub recent: Vec<u64>,
+}
+
+impl fmt::Display for TimeoutReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.overrun {
+            Overrun::Cpu => "CPU",
+            Overrun::Wall => "wall-clock",
+        };
+        writeln!(
+            f,
+            "{what} time limit of {:.3}s exceeded",
+            self.limit.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "  cpu:    {:.3}s ({:.3}s in guest code)",
+            self.thread_cpu.as_secs_f64(),
+            self.loop_cpu.as_secs_f64()
+        )?;
+        writeln!(f, "  wall:   {:.3}s", self.wall.as_secs_f64())?;
+        writeln!(f, "  insns:  {}", self.insns)?;
+        writeln!(f, "  pc:     {:#x}", self.pc)?;
+        write!(f, "  recent:")?;
+        for pc in &self.recent {
+            write!(f, " {pc:#x}")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// State shared with the watchdog thread.
+struct Watch {
+    /// `Overrun` as 1 or 2 once requested, else 0.
+    request: AtomicU8,
+    /// The exec loop returned `TimedOut`; stop unlinking.
+    acked: AtomicBool,
+    stop: Mutex<bool>,
+    wake: Condvar,
+}
+
+/// A budget enforced on the vCPU that created it.
+pub struct BudgetWatch {
+    budget: Budget,
+    start: Instant,
+    cpu_clock: libc::clockid_t,
+    cpu_start: Duration,
+    watch: Arc<Watch>,
+    thread: Option<JoinHandle<()>>,
+    recent: VecDeque<u64>,
+}
+
+impl BudgetWatch {
+    /// Start enforcing `budget` on the calling thread, which
+    /// must be the one running the vCPU.
+    pub fn start<B>(shared: Arc<SharedState<B>>, budget: Budget) -> Self
+    where
+        B: HostCodeGen + Send + Sync + 'static,
+    {
+        let mut cpu_clock = 0;
+        let r = unsafe {
+            libc::pthread_getcpuclockid(libc::pthread_self(), &mut cpu_clock)
+        };
+        assert_eq!(r, 0, "pthread_getcpuclockid failed");
+        let start = Instant::now();
+        let cpu_start = clock_time(cpu_clock).unwrap_or_default();
+        let watch = Arc::new(Watch {
+            request: AtomicU8::new(0),
+            acked: AtomicBool::new(false),
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+
+        let w = Arc::clone(&watch);
+        let thread = thread::Builder::new()
+            .name("tcg-watchdog".to_string())
+            .spawn(move || {
+                let over = || {
+                    let cpu = clock_time(cpu_clock)?.saturating_sub(cpu_start);
+                    if budget.cpu.is_some_and(|l| cpu >= l) {
+                        return Some(Overrun::Cpu);
+                    }
+                    if budget.wall.is_some_and(|l| start.elapsed() >= l) {
+                        return Some(Overrun::Wall);
+                    }
+                    None
+                };
+                let mut stop = w.stop.lock().unwrap();
+                while !*stop {
+                    if w.request.load(Ordering::SeqCst) == 0 {
+                        if let Some(o) = over() {
+                            w.request.store(o as u8 + 1, Ordering::SeqCst);
+                        }
+                    }
+                    // Until the loop sees the request, keep
+                    // undoing chains other vCPUs may add.
+                    if w.request.load(Ordering::SeqCst) != 0
+                        && !w.acked.load(Ordering::SeqCst)
+                    {
+                        shared.tb_unlink_all();
+                    }
+                    stop = w.wake.wait_timeout(stop, TICK).unwrap().0;
+                }
+            })
+            .expect("failed to spawn the watchdog");
+
+        Self {
+            budget,
+            start,
+            cpu_clock,
+            cpu_start,
+            watch,
+            thread: Some(thread),
+            recent: VecDeque::with_capacity(RECENT_LEN),
+        }
+    }
+
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    /// The limit that ran out, if any.
+    pub fn overrun(&self) -> Option<Overrun> {
+        Overrun::from_u8(self.watch.request.load(Ordering::SeqCst))
+    }
+
+    /// Record entry of the TB at `pc`.
+    pub(crate) fn record(&mut self, pc: u64) {
+        if self.recent.len() == RECENT_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(pc);
+    }
+
+    /// Report `overrun` and stop unlinking chains for it.
+    pub(crate) fn report(
+        &self,
+        overrun: Overrun,
+        loop_cpu: Duration,
+        insns: u64,
+        pc: u64,
+    ) -> TimeoutReport {
+        self.watch.acked.store(true, Ordering::SeqCst);
+        let limit = match overrun {
+            Overrun::Cpu => self.budget.cpu,
+            Overrun::Wall => self.budget.wall,
+        };
+        let cpu = clock_time(self.cpu_clock).unwrap_or_default();
+        TimeoutReport {
+            overrun,
+            limit: limit.unwrap_or_default(),
+            thread_cpu: cpu.saturating_sub(self.cpu_start),
+            loop_cpu,
+            wall: self.start.elapsed(),
+            insns,
+            pc,
+            recent: self.recent.iter().copied().collect(),
+        }
+    }
+}
+
+impl Drop for BudgetWatch {
+    fn drop(&mut self) {
+        *self.watch.stop.lock().unwrap() = true;
+        self.watch.wake.notify_one();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Strict chaining checks.
+//!
+//! Chained TBs jump straight into each other, so a stale
+//! chain, a chain made for the wrong flags or a jump patched
+//! to the wrong place runs the wrong TB without the exec loop
+//! seeing it. With the checker installed the loop records the
+//! guest `(pc, flags)` every slot is chained for, and before
+//! entering a TB walks every chained jump reachable from it:
+//! the target each jump really goes to, read back from the
+//! host code, must be the chain graph's target, valid, and the
+//! TB a fresh hash-table lookup of the recorded key returns.
+//! Every transition that chained execution can take is thus
+//! checked before it is taken.
+//!
+//! A divergence is recorded and its source TB invalidated, so
+//! execution continues through the exec loop.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+use tcg_backend::HostCodeGen;
+
+use crate::SharedState;
+
+/// A chained jump that does not go where a fresh lookup says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Source TB and its `goto_tb` slot.
+    pub src: usize,
+    pub slot: usize,
+    /// TB the host code jumps to, if any.
+    pub taken: Option<usize>,
+    /// TB it should jump to: the fresh lookup, or the chain
+    /// graph's target when the two disagree on chaining.
+    pub expected: Option<usize>,
+    pub what: &'static str,
+    /// Both TBs, as they were when found.
+    report: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.report)
+    }
+}
+
+/// Chain keys and the divergences found so far.
+#[derive(Default)]
+pub struct ChainChecker {
+    /// Guest `(pc, flags)` each `(src, slot)` was chained for.
+    keys: Mutex<HashMap<(usize, usize), (u64, u32)>>,
+    divergences: Mutex<Vec<Divergence>>,
+}
+
+impl ChainChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `src`'s `slot` was chained for guest
+    /// `(pc, flags)`.
+    /// Forget all keys, e.g. after a code cache flush.
+    pub(crate) fn clear(&self) {
+        self.keys.lock().unwrap().clear();
+    }
+
+    pub(crate) fn record(&self, src: usize, slot: usize, pc: u64, flags: u32) {
+        self.keys.lock().unwrap().insert((src, slot), (pc, flags));
+    }
+
+    /// Divergences found so far, oldest first.
+    pub fn divergences(&self) -> Vec<Divergence> {
+        self.divergences.lock().unwrap().clone()
+    }
+
+    /// Check every chained jump reachable from `tb_idx`.
+    /// Returns false if one diverged; its source has then been
+    /// invalidated and `tb_idx` may be among them.
+    pub(crate) fn check_from<B: HostCodeGen>(
+        &self,
+        shared: &SharedState<B>,
+        tb_idx: usize,
+    ) -> bool {
+        let mut seen = HashSet::from([tb_idx]);
+        let mut todo = vec![tb_idx];
+        let mut found = Vec::new();
+        while let Some(src) = todo.pop() {
+            for slot in 0..2 {
+                match self.check_jump(shared, src, slot) {
+                    Ok(Some(dst)) => {
+                        if seen.insert(dst) {
+                            todo.push(dst);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(d) => found.push(d),
+                }
+            }
+        }
+        if found.is_empty() {
+            return true;
+        }
+        for d in &found {
+            shared.tb_store.invalidate(
+                d.src,
+                shared.code_buf(),
+                &shared.backend,
+            );
+        }
+        self.divergences.lock().unwrap().extend(found);
+        false
+    }
+
+    /// Check `src`'s jump in `slot`; returns the TB it chains
+    /// to, if any.
+    fn check_jump<B: HostCodeGen>(
+        &self,
+        shared: &SharedState<B>,
+        src: usize,
+        slot: usize,
+    ) -> Result<Option<usize>, Divergence> {
+        let store = &shared.tb_store;
+        let tb = store.get(src);
+        let (Some(jmp), Some(reset)) =
+            (tb.jmp_insn_offset[slot], tb.jmp_reset_offset[slot])
+        else {
+            return Ok(None);
+        };
+        let graph = tb.jmp.lock().unwrap().jmp_dest[slot];
+        let target =
+            shared.backend.jump_target(shared.code_buf(), jmp as usize);
+        let key = self.keys.lock().unwrap().get(&(src, slot)).copied();
+        let diverge = |taken, expected, what| {
+            Err(self.divergence(shared, src, slot, key, taken, expected, what))
+        };
+
+        if target == reset as usize {
+            return match graph {
+                Some(g) => diverge(None, Some(g), "chained in the graph only"),
+                None => Ok(None),
+            };
+        }
+        let taken = match graph {
+            Some(g) if store.get(g).host_offset == target => Some(g),
+            _ => (0..store.len()).find(|&i| store.get(i).host_offset == target),
+        };
+        let Some(taken) = taken else {
+            return diverge(None, graph, "jumps to no TB entry");
+        };
+        if graph != Some(taken) {
+            return diverge(
+                Some(taken),
+                graph,
+                "jump and chain graph disagree",
+            );
+        }
+        if store.get(taken).is_invalid() {
+            return diverge(Some(taken), None, "chained to an invalid TB");
+        }
+        let Some((pc, flags)) = key else {
+            return diverge(Some(taken), None, "chained outside the exec loop");
+        };
+        let expected = store.lookup(pc, flags);
+        if expected != Some(taken) {
+            return diverge(Some(taken), expected, "not the TB a lookup finds");
+        }
+        Ok(Some(taken))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn divergence<B: HostCodeGen>(
+        &self,
+        shared: &SharedState<B>,
+        src: usize,
+        slot: usize,
+        key: Option<(u64, u32)>,
+        taken: Option<usize>,
+        expected: Option<usize>,
+        what: &'static str,
+    ) -> Divergence {
+        let describe = |idx: Option<usize>| match idx {
+            Some(i) => {
+                let tb = shared.tb_store.get(i);
+                format!(
+                    "TB #{i} (pc {:#x}, flags {:#x}, host {:#x}{})",
+                    tb.pc,
+                    tb.flags,
+                    tb.host_offset,
+                    if tb.is_invalid() { ", invalid" } else { "" }
+                )
+            }
+            None => "none".to_string(),
+        };
+        let key = match key {
+            Some((pc, flags)) => format!("pc {pc:#x}, flags {flags:#x}"),
+            None => "unrecorded".to_string(),
+        };
+        let report = format!(
+            "chain check: {} slot {slot}: {what}\n  key:      {key}\n  \
+             taken:    {}\n  expected: {}",
+            describe(Some(src)),
+            describe(taken),
+            describe(expected),
+        );
+        Divergence {
+            src,
+            slot,
+            taken,
+            expected,
+            what,
+            report,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Periodic checkpoint requests for a vCPU.
+//!
+//! A `CheckpointWatch` makes the exec loop stop at a TB
+//! boundary once `insns` guest instructions have retired or
+//! `wall` has passed since the previous checkpoint, so the
+//! caller can save the guest state while it is consistent.
+//! Instruction counts are only visible to the vCPU thread, so
+//! a poller thread raises a request every `POLL` (or every
+//! `wall`, if shorter) and unlinks every chained jump, as a
+//! `BudgetWatch` does on overrun. The loop checks the request
+//! before entering each TB; it returns
+//! `ExitReason::Checkpoint` if an interval has passed and
+//! drops the request otherwise. An instruction interval is
+//! thus met at the first poll after it passes.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tcg_backend::HostCodeGen;
+
+use crate::SharedState;
+
+/// Longest time between two checks of an instruction interval.
+pub const POLL: Duration = Duration::from_millis(100);
+
+/// Interval at which a pending request unlinks chains again.
+const TICK: Duration = Duration::from_millis(10);
+
+/// When to checkpoint; either interval, whichever passes
+/// first. `None` never triggers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointInterval {
+    /// Guest instructions retired.
+    pub insns: Option<u64>,
+    /// Wall-clock time.
+    pub wall: Option<Duration>,
+}
+
+/// State shared with the poller thread.
+struct Watch {
+    request: AtomicBool,
+    stop: Mutex<bool>,
+    wake: Condvar,
+}
+
+/// Checkpoint requests for the vCPU that created it.
+pub struct CheckpointWatch {
+    interval: CheckpointInterval,
+    /// Retired instructions at the last checkpoint, or at the
+    /// first TB entry.
+    last_insns: Option<u64>,
+    /// Time of the last checkpoint, or of the start.
+    last: Instant,
+    taken: u64,
+    watch: Arc<Watch>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CheckpointWatch {
+    pub fn start<B>(
+        shared: Arc<SharedState<B>>,
+        interval: CheckpointInterval,
+    ) -> Self
+    where
+        B: HostCodeGen + Send + Sync + 'static,
+    {
+        let watch = Arc::new(Watch {
+            request: AtomicBool::new(false),
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let poll = interval.wall.map_or(POLL, |w| w.min(POLL));
+        let w = Arc::clone(&watch);
+        let thread = thread::Builder::new()
+            .name("tcg-checkpoint".to_string())
+            .spawn(move || {
+                let mut next = Instant::now() + poll;
+                let mut stop = w.stop.lock().unwrap();
+                while !*stop {
+                    let now = Instant::now();
+                    if now >= next {
+                        w.request.store(true, Ordering::SeqCst);
+                        next = now + poll;
+                    }
+                    // Until the loop takes the request, keep
+                    // undoing chains it may have patched since.
+                    let wait = if w.request.load(Ordering::SeqCst) {
+                        shared.tb_unlink_all();
+                        TICK
+                    } else {
+                        next - now
+                    };
+                    stop = w.wake.wait_timeout(stop, wait).unwrap().0;
+                }
+            })
+            .expect("failed to spawn the checkpoint poller");
+        Self {
+            interval,
+            last_insns: None,
+            last: Instant::now(),
+            taken: 0,
+            watch,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn interval(&self) -> CheckpointInterval {
+        self.interval
+    }
+
+    /// Checkpoints returned so far.
+    pub fn taken(&self) -> u64 {
+        self.taken
+    }
+
+    /// Called before each TB entry with the vCPU's retired
+    /// instructions; true if a checkpoint is due now.
+    #[inline]
+    pub(crate) fn poll(&mut self, insns: u64) -> bool {
+        let last_insns = *self.last_insns.get_or_insert(insns);
+        if !self.watch.request.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.watch.request.store(false, Ordering::SeqCst);
+        let due = self
+            .interval
+            .insns
+            .is_some_and(|n| insns.saturating_sub(last_insns) >= n)
+            || self.interval.wall.is_some_and(|w| self.last.elapsed() >= w);
+        if due {
+            self.last_insns = Some(insns);
+            self.last = Instant::now();
+            self.taken += 1;
+        }
+        due
+    }
+}
+
+impl Drop for CheckpointWatch {
+    fn drop(&mut self) {
+        *self.watch.stop.lock().unwrap() = true;
+        self.watch.wake.notify_one();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
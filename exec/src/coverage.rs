@@ -0,0 +1,113 @@
+//! Guest basic-block coverage.
+//!
+//! Every TB starts at a guest basic-block boundary, so counting
+//! TB dispatches from the exec loop gives block coverage without
+//! instrumenting generated code. A TB's first execution always
+//! goes through the loop (chains are only patched to TBs the
+//! loop has already found), so no block is missed; later runs
+//! through a patched chain are not counted, making `hits` a
+//! lower bound.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use tcg_core::tb::TranslationBlock;
+
+/// One covered guest range `[start, end)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoveredBlock {
+    pub start: u64,
+    pub end: u64,
+    /// Static guest instruction count.
+    pub insns: u32,
+    /// Guest pc of each instruction; empty if unknown.
+    pub insn_starts: Vec<u64>,
+    /// Exec-loop dispatches, summed over retranslations.
+    pub hits: u64,
+}
+
+/// Per-vCPU coverage recorder, indexed by TB index.
+#[derive(Default)]
+pub struct Coverage {
+    hits: Vec<u64>,
+    /// TB index and its block (hits unset), in first-execution
+    /// order.
+    seen: Vec<(usize, CoveredBlock)>,
+    /// Blocks of TBs dropped by a code cache flush, with hits.
+    retired: Vec<CoveredBlock>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one dispatch of `tb`.
+    #[inline]
+    pub fn record(&mut self, tb_idx: usize, tb: &TranslationBlock) {
+        if tb_idx >= self.hits.len() {
+            self.hits.resize(tb_idx + 1, 0);
+        }
+        if self.hits[tb_idx] == 0 {
+            let block = CoveredBlock {
+                start: tb.pc,
+                end: tb.pc + tb.size as u64,
+                insns: tb.icount as u32,
+                insn_starts: tb.insn_starts().collect(),
+                hits: 0,
+            };
+            self.seen.push((tb_idx, block));
+        }
+        self.hits[tb_idx] += 1;
+    }
+
+    /// Dispatched TB indices with their counts, most
+    /// dispatched first.
+    pub fn hottest(&self) -> Vec<(usize, u64)> {
+        let mut tbs: Vec<(usize, u64)> = self
+            .seen
+            .iter()
+            .map(|&(idx, _)| (idx, self.hits[idx]))
+            .collect();
+        tbs.sort_by_key(|&(idx, hits)| (std::cmp::Reverse(hits), idx));
+        tbs
+    }
+
+    /// Fold the counts of all current TBs into their blocks
+    /// and forget the TB indices, which a flush frees for reuse.
+    pub(crate) fn retire_tbs(&mut self) {
+        for (idx, mut block) in self.seen.drain(..) {
+            block.hits = self.hits[idx];
+            self.retired.push(block);
+        }
+        self.hits.clear();
+    }
+
+    /// Covered blocks sorted by start address.
+    pub fn blocks(&self) -> Vec<CoveredBlock> {
+        let mut map: BTreeMap<(u64, u64), CoveredBlock> = BTreeMap::new();
+        for block in &self.retired {
+            map.entry((block.start, block.end))
+                .and_modify(|b| b.hits += block.hits)
+                .or_insert_with(|| block.clone());
+        }
+        for (idx, block) in &self.seen {
+            let b = map
+                .entry((block.start, block.end))
+                .or_insert_with(|| block.clone());
+            b.hits += self.hits[*idx];
+        }
+        map.into_values().collect()
+    }
+
+    /// Write the raw format: one `start end insns hits` line per
+    /// block, addresses in hex.
+    pub fn write_raw(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "# tcg-rs block coverage v1")?;
+        writeln!(w, "# start end insns hits")?;
+        for b in self.blocks() {
+            writeln!(w, "{:#x} {:#x} {} {}", b.start, b.end, b.insns, b.hits)?;
+        }
+        Ok(())
+    }
+}
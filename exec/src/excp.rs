@@ -0,0 +1,45 @@
+//! Handing exception exits to the frontend.
+//!
+//! The exec loop returns every exception to its caller. Codes in
+//! the core range (`tcg_core::excp::CoreExcp`) mean the same for
+//! every guest and the caller acts on them itself; frontend-range
+//! codes go to an [`ExcpHandler`] supplied with the frontend.
+
+use tcg_core::excp::{CoreExcp, ExcpClass};
+
+/// What the caller does after an exception.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExcpAction {
+    /// Run the guest again.
+    Resume,
+    /// End the run with this exit status.
+    Exit(i32),
+    /// End the run with an error.
+    Fatal(String),
+}
+
+/// Handles the frontend-range exceptions of guest state `C`.
+pub trait ExcpHandler<C> {
+    /// Handle frontend exception `code` raised with `cpu`.
+    fn handle(&mut self, cpu: &mut C, code: u32) -> ExcpAction;
+
+    /// Handle core exception `excp`; by default the run ends.
+    fn handle_core(&mut self, cpu: &mut C, excp: CoreExcp) -> ExcpAction {
+        let _ = cpu;
+        ExcpAction::Fatal(format!("{} exception", excp.name()))
+    }
+}
+
+/// Send exception `code` to `handler` by its class.
+pub fn dispatch<C, H>(handler: &mut H, cpu: &mut C, code: u32) -> ExcpAction
+where
+    H: ExcpHandler<C> + ?Sized,
+{
+    match ExcpClass::of(code) {
+        ExcpClass::Frontend(code) => handler.handle(cpu, code),
+        ExcpClass::Core(excp) => handler.handle_core(cpu, excp),
+        ExcpClass::Reserved(code) => {
+            ExcpAction::Fatal(format!("reserved exception {code:#x}"))
+        }
+    }
+}
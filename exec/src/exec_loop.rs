@@ -1,26 +1,74 @@
 use std::sync::atomic::Ordering;
-
+use std::time::Instant;
+
+use crate::budget::{thread_cpu_time, TimeoutReport};
+#[cfg(feature = "metrics")]
+use crate::metrics::PUBLISH_EVERY;
+#[cfg(debug_assertions)]
+use crate::stack_check::{self, StackOverflowReport};
+use crate::timing::Phase;
 use crate::{
-    ExecEnv, GuestCpu, PerCpuState, SharedState, MIN_CODE_BUF_REMAINING,
+    ChainPolicy, ExecEnv, GuestCpu, JumpPatch, PerCpuState, SharedState,
+    TranslateGuard, MIN_CODE_BUF_REMAINING,
+};
+use tcg_backend::liveness::pressure_report;
+use tcg_backend::translate::{analyze_with, codegen_with};
+use tcg_backend::{HostCodeGen, TranslateError};
+use tcg_core::helper;
+use tcg_core::tb::{
+    cflags, InsnStarts, TbExit, TranslationBlock, EXIT_TARGET_NONE,
 };
-use tcg_backend::translate::translate;
-use tcg_backend::HostCodeGen;
-use tcg_core::tb::{decode_tb_exit, EXIT_TARGET_NONE, TB_EXIT_NOCHAIN};
+use tcg_core::{Context, Opcode};
 
 /// Reason the execution loop exited.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExitReason {
-    /// TB returned a non-zero exit value.
-    Exit(usize),
-    /// Code buffer is full; caller should flush and retry.
+    /// TB left with an exception or a custom exit.
+    Exit(TbExit),
+    /// The TB raised exception `code`, which the registry set
+    /// with `ExecEnv::with_exceptions` does not name. `pc` is
+    /// the guest pc the TB left.
+    UnknownException { code: u32, pc: u64 },
+    /// Code buffer or TB store is full and the buffer could
+    /// not grow in place; with no vCPU in the loop, the caller
+    /// should `SharedState::flush_code_cache` and retry.
     BufferFull,
+    /// The backend rejected the IR for the TB at `pc`. Nothing
+    /// of the failed TB is kept; the guest state is consistent.
+    TranslateFailed { pc: u64, error: TranslateError },
+    /// A helper panicked. `pc` is the guest pc of the TB that
+    /// made the call; the guest state is as the helper left it.
+    HelperPanic { message: String, pc: u64 },
+    /// A spin-marked TB kept re-running; the guest state is
+    /// consistent and the caller may run another vCPU before
+    /// calling the loop again.
+    Yield,
+    /// The budget set with `ExecEnv::with_budget` ran out
+    /// before a TB entry; the guest state is consistent.
+    TimedOut(TimeoutReport),
+    /// `PerCpuState::insn_budget` reached zero: exactly that
+    /// many instructions retired since it was set, and the
+    /// guest state is consistent.
+    BudgetExhausted,
+    /// The interval set with `ExecEnv::with_checkpoint` passed;
+    /// the guest state is consistent, to be saved before the
+    /// loop is called again.
+    Checkpoint,
+    /// The host stack reached the red zone set with
+    /// `ExecEnv::with_stack_check`. The TB or helper call that
+    /// tripped did not run.
+    #[cfg(debug_assertions)]
+    StackOverflow(StackOverflowReport),
 }
 
 /// Main CPU execution loop (single-threaded convenience).
+/// When the code cache fills up it is flushed, once the loop
+/// is back out of every TB, and execution goes on.
 ///
 /// # Safety
 /// The caller must ensure `cpu.env_ptr()` points to a valid
-/// CPU state struct matching the globals in `ir_ctx`.
+/// CPU state struct matching the globals in `ir_ctx`, and that
+/// no other vCPU runs on `env.shared` meanwhile.
 pub unsafe fn cpu_exec_loop<B, C>(
     env: &mut ExecEnv<B>,
     cpu: &mut C,
@@ -29,7 +77,15 @@ where
     B: HostCodeGen,
     C: GuestCpu,
 {
-    cpu_exec_loop_mt(&env.shared, &mut env.per_cpu, cpu)
+    loop {
+        match cpu_exec_loop_mt(&env.shared, &mut env.per_cpu, cpu) {
+            ExitReason::BufferFull => {
+                env.shared.flush_code_cache();
+                env.per_cpu.stats.code_flush += 1;
+            }
+            reason => return reason,
+        }
+    }
 }
 
 /// Multi-thread capable execution loop.
@@ -45,6 +101,45 @@ pub unsafe fn cpu_exec_loop_mt<B, C>(
     per_cpu: &mut PerCpuState,
     cpu: &mut C,
 ) -> ExitReason
+where
+    B: HostCodeGen,
+    C: GuestCpu,
+{
+    mark(per_cpu, Phase::Outside);
+    per_cpu.sync_flush(shared);
+    #[cfg(debug_assertions)]
+    let prev_limit = shared.stack_red_zone.and_then(stack_check::enter);
+    let cpu_start = thread_cpu_time();
+    let reason = exec_loop(shared, per_cpu, cpu, cpu_start);
+    per_cpu.cpu_time += thread_cpu_time().saturating_sub(cpu_start);
+    #[cfg(feature = "metrics")]
+    if let Some(m) = &per_cpu.metrics {
+        m.publish(&per_cpu.stats, per_cpu.cpu_time);
+    }
+    #[cfg(debug_assertions)]
+    if let Some(limit) = prev_limit {
+        helper::set_stack_limit(limit);
+    }
+    mark(per_cpu, Phase::Lookup);
+    reason
+}
+
+/// Charge the time since the last read to `phase`, if timing.
+#[inline]
+fn mark(per_cpu: &mut PerCpuState, phase: Phase) {
+    if let Some(t) = per_cpu.timer.as_mut() {
+        t.mark(phase, &mut per_cpu.stats.time);
+    }
+}
+
+/// Body of `cpu_exec_loop_mt`, between its timestamp reads.
+/// `cpu_start` is the thread CPU time it was entered at.
+unsafe fn exec_loop<B, C>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+    cpu: &mut C,
+    cpu_start: std::time::Duration,
+) -> ExitReason
 where
     B: HostCodeGen,
     C: GuestCpu,
@@ -54,7 +149,39 @@ where
     loop {
         per_cpu.stats.loop_iters += 1;
 
-        let tb_idx = match next_tb_hint.take() {
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &per_cpu.metrics {
+            if per_cpu.stats.loop_iters.is_multiple_of(PUBLISH_EVERY) {
+                let cpu_time = per_cpu.cpu_time
+                    + thread_cpu_time().saturating_sub(cpu_start);
+                m.publish(&per_cpu.stats, cpu_time);
+            }
+        }
+
+        if let Some(budget) = &per_cpu.budget {
+            if let Some(overrun) = budget.overrun() {
+                let loop_cpu = per_cpu.cpu_time
+                    + thread_cpu_time().saturating_sub(cpu_start);
+                return ExitReason::TimedOut(budget.report(
+                    overrun,
+                    loop_cpu,
+                    per_cpu.stats.insns,
+                    cpu.get_pc(),
+                ));
+            }
+        }
+
+        if let Some(ck) = per_cpu.checkpoint.as_mut() {
+            if ck.poll(cpu.insns_retired()) {
+                return ExitReason::Checkpoint;
+            }
+        }
+
+        if per_cpu.insn_budget == Some(0) {
+            return ExitReason::BudgetExhausted;
+        }
+
+        let mut tb_idx = match next_tb_hint.take() {
             Some(idx) => {
                 per_cpu.stats.hint_used += 1;
                 idx
@@ -63,32 +190,90 @@ where
                 let pc = cpu.get_pc();
                 let flags = cpu.get_flags();
                 match tb_find(shared, per_cpu, cpu, pc, flags) {
-                    Some(idx) => idx,
-                    None => return ExitReason::BufferFull,
+                    Ok(idx) => idx,
+                    Err(reason) => return reason,
                 }
             }
         };
 
+        // A TB longer than the budget runs as a one-off
+        // translation cut to fit it.
+        if let Some(left) = per_cpu.insn_budget {
+            let tb = shared.tb_store.get(tb_idx);
+            if tb.icount as u64 > left {
+                let (pc, flags) = (tb.pc, tb.flags);
+                match tb_gen_clamped(shared, per_cpu, cpu, pc, flags, left) {
+                    Ok(idx) => tb_idx = idx,
+                    Err(reason) => return reason,
+                }
+            }
+        }
+
+        mark(per_cpu, Phase::Lookup);
+
+        if let Some(check) = &shared.chain_check {
+            if !check.check_from(shared, tb_idx)
+                && shared.tb_store.get(tb_idx).is_invalid()
+            {
+                continue;
+            }
+        }
+
+        if let Some(after) = shared.spin_yield_after {
+            if spin_streak(shared, per_cpu, tb_idx) > after {
+                per_cpu.spin_streak.1 = 0;
+                per_cpu.stats.spin_yield += 1;
+                return ExitReason::Yield;
+            }
+        }
+
+        if let Some(cov) = per_cpu.coverage.as_mut() {
+            cov.record(tb_idx, shared.tb_store.get(tb_idx));
+        }
+        if let Some(budget) = per_cpu.budget.as_mut() {
+            budget.record(shared.tb_store.get(tb_idx).pc);
+        }
+
+        #[cfg(feature = "verify-code")]
+        if let Some(v) = shared.tb_store.verifier() {
+            per_cpu.verify_tick += 1;
+            if per_cpu.verify_tick.is_multiple_of(v.every()) {
+                let tb = shared.tb_store.get(tb_idx);
+                v.verify(tb_idx, tb, shared.code_buf());
+            }
+        }
+
+        let retired_before = per_cpu.insn_budget.map(|_| cpu.insns_retired());
         let raw_exit = cpu_tb_exec(shared, cpu, tb_idx);
-        let (last_tb, exit_code) = decode_tb_exit(raw_exit);
+        mark(per_cpu, Phase::Execute);
+        per_cpu.stats.insns = cpu.insns_retired();
+        if let (Some(left), Some(before)) =
+            (per_cpu.insn_budget.as_mut(), retired_before)
+        {
+            *left = left.saturating_sub(per_cpu.stats.insns - before);
+        }
+        let (last_tb, mut exit) = TbExit::decode(raw_exit);
         let src_tb = last_tb.unwrap_or(tb_idx);
+        // A panic in a helper called without the post-call check.
+        if exit != TbExit::HelperPanic && helper::panic_pending() {
+            exit = TbExit::HelperPanic;
+        }
 
-        match exit_code {
-            v @ 0..=1 => {
-                let slot = v;
+        match exit {
+            TbExit::Chain(slot) => {
                 per_cpu.stats.chain_exit[slot] += 1;
 
                 let pc = cpu.get_pc();
                 let flags = cpu.get_flags();
                 let dst = match tb_find(shared, per_cpu, cpu, pc, flags) {
-                    Some(idx) => idx,
-                    None => return ExitReason::BufferFull,
+                    Ok(idx) => idx,
+                    Err(reason) => return reason,
                 };
 
-                tb_add_jump(shared, per_cpu, src_tb, slot, dst);
+                tb_add_jump(shared, per_cpu, src_tb, slot, dst, (pc, flags));
                 next_tb_hint = Some(dst);
             }
-            v if v == TB_EXIT_NOCHAIN as usize => {
+            TbExit::Normal => {
                 per_cpu.stats.nochain_exit += 1;
                 let pc = cpu.get_pc();
                 let flags = cpu.get_flags();
@@ -98,39 +283,104 @@ where
                 let cached = stb.exit_target.load(Ordering::Relaxed);
                 if cached != EXIT_TARGET_NONE {
                     let tb = shared.tb_store.get(cached);
-                    if !tb.invalid.load(Ordering::Acquire)
-                        && tb.pc == pc
-                        && tb.flags == flags
-                    {
+                    if !tb.is_invalid() && tb.pc == pc && tb.flags == flags {
                         next_tb_hint = Some(cached);
                         continue;
                     }
                 }
 
                 let dst = match tb_find(shared, per_cpu, cpu, pc, flags) {
-                    Some(idx) => idx,
-                    None => return ExitReason::BufferFull,
+                    Ok(idx) => idx,
+                    Err(reason) => return reason,
                 };
                 let stb = shared.tb_store.get(src_tb);
                 stb.exit_target.store(dst, Ordering::Relaxed);
                 next_tb_hint = Some(dst);
             }
-            _ => {
+            TbExit::HelperPanic => {
+                let p = helper::take_panic()
+                    .expect("helper-panic exit without a pending panic");
+                let base = shared.code_buf().base_ptr() as usize;
+                let pc = p
+                    .host_ret
+                    .checked_sub(base)
+                    .and_then(|off| shared.tb_store.find_by_host(off))
+                    .map_or_else(
+                        || cpu.get_pc(),
+                        |i| shared.tb_store.get(i).pc,
+                    );
+                #[cfg(debug_assertions)]
+                if let Some(b) = helper::take_stack_breach() {
+                    return stack_overflow(pc, Some(b));
+                }
+                return ExitReason::HelperPanic {
+                    message: p.message,
+                    pc,
+                };
+            }
+            // The TB did not start: the pc is its own.
+            #[cfg(debug_assertions)]
+            TbExit::StackOverflow => {
+                return stack_overflow(cpu.get_pc(), None);
+            }
+            #[cfg(not(debug_assertions))]
+            TbExit::StackOverflow => {
+                unreachable!("stack check exit in a release build")
+            }
+            TbExit::Exception(code)
+                if shared.excp.as_ref().is_some_and(|r| !r.contains(code)) =>
+            {
                 per_cpu.stats.real_exit += 1;
-                return ExitReason::Exit(exit_code);
+                return ExitReason::UnknownException {
+                    code,
+                    pc: cpu.get_pc(),
+                };
+            }
+            TbExit::Exception(_) | TbExit::Custom(_) => {
+                per_cpu.stats.real_exit += 1;
+                return ExitReason::Exit(exit);
             }
         }
     }
 }
 
+#[cfg(debug_assertions)]
+fn stack_overflow(pc: u64, helper: Option<helper::Breadcrumb>) -> ExitReason {
+    ExitReason::StackOverflow(StackOverflowReport {
+        pc,
+        limit: helper::stack_limit(),
+        helper,
+        breadcrumbs: helper::take_breadcrumbs(),
+    })
+}
+
+/// Count consecutive entries of spin-marked `tb_idx`; any
+/// other TB resets the streak.
+fn spin_streak<B: HostCodeGen>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+    tb_idx: usize,
+) -> u32 {
+    let streak = &mut per_cpu.spin_streak;
+    if !shared.tb_store.get(tb_idx).spin_loop {
+        *streak = (usize::MAX, 0);
+    } else if streak.0 == tb_idx {
+        streak.1 += 1;
+    } else {
+        *streak = (tb_idx, 1);
+    }
+    streak.1
+}
+
 /// Find a TB for the given (pc, flags), translating if needed.
+/// Fails with `BufferFull` or `TranslateFailed`.
 fn tb_find<B, C>(
     shared: &SharedState<B>,
     per_cpu: &mut PerCpuState,
     cpu: &mut C,
     pc: u64,
     flags: u32,
-) -> Option<usize>
+) -> Result<usize, ExitReason>
 where
     B: HostCodeGen,
     C: GuestCpu,
@@ -138,12 +388,9 @@ where
     // Fast path: jump cache (per-CPU, no lock needed)
     if let Some(idx) = per_cpu.jump_cache.lookup(pc) {
         let tb = shared.tb_store.get(idx);
-        if !tb.invalid.load(Ordering::Acquire)
-            && tb.pc == pc
-            && tb.flags == flags
-        {
+        if !tb.is_invalid() && tb.pc == pc && tb.flags == flags {
             per_cpu.stats.jc_hit += 1;
-            return Some(idx);
+            return Ok(idx);
         }
     }
 
@@ -151,7 +398,11 @@ where
     if let Some(idx) = shared.tb_store.lookup(pc, flags) {
         per_cpu.jump_cache.insert(pc, idx);
         per_cpu.stats.ht_hit += 1;
-        return Some(idx);
+        // Pre-translated TBs are first found here.
+        if !per_cpu.warm.is_empty() && per_cpu.warm.remove(&idx) {
+            per_cpu.stats.warmup_used += 1;
+        }
+        return Ok(idx);
     }
 
     // Miss: translate a new TB
@@ -159,6 +410,22 @@ where
     tb_gen_code(shared, per_cpu, cpu, pc, flags)
 }
 
+/// Double the code buffer in place, up to `code_buf_limit`.
+/// Caller must hold translate_lock.
+fn code_buf_grow<B: HostCodeGen>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+) -> bool {
+    // SAFETY: caller holds translate_lock.
+    let buf = unsafe { shared.code_buf_mut() };
+    let new_size = (buf.capacity() * 2).min(shared.code_buf_limit);
+    if new_size <= buf.capacity() || buf.try_grow_in_place(new_size).is_err() {
+        return false;
+    }
+    per_cpu.stats.code_grow += 1;
+    true
+}
+
 /// Translate guest code at `pc` into a new TB.
 fn tb_gen_code<B, C>(
     shared: &SharedState<B>,
@@ -166,15 +433,31 @@ fn tb_gen_code<B, C>(
     cpu: &mut C,
     pc: u64,
     flags: u32,
-) -> Option<usize>
+) -> Result<usize, ExitReason>
 where
     B: HostCodeGen,
     C: GuestCpu,
 {
-    if shared.code_buf().remaining() < MIN_CODE_BUF_REMAINING {
-        return None;
+    let start = per_cpu.timer.is_some().then(Instant::now);
+    let idx = tb_gen_code_locked(shared, per_cpu, cpu, pc, flags);
+    if let (Some(t), Some(start)) = (per_cpu.timer.as_mut(), start) {
+        t.add_translate(start.elapsed(), &mut per_cpu.stats.time);
     }
+    idx
+}
 
+/// Body of `tb_gen_code`, timed by it.
+fn tb_gen_code_locked<B, C>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+    cpu: &mut C,
+    pc: u64,
+    flags: u32,
+) -> Result<usize, ExitReason>
+where
+    B: HostCodeGen,
+    C: GuestCpu,
+{
     // Acquire translate_lock for exclusive code generation.
     let mut guard = shared.translate_lock.lock().unwrap();
 
@@ -182,22 +465,107 @@ where
     // PC while we waited for the lock.
     if let Some(idx) = shared.tb_store.lookup(pc, flags) {
         per_cpu.jump_cache.insert(pc, idx);
-        return Some(idx);
+        return Ok(idx);
+    }
+
+    if shared.tb_store.is_full()
+        || (shared.code_buf().remaining() < MIN_CODE_BUF_REMAINING
+            && !code_buf_grow(shared, per_cpu))
+    {
+        per_cpu.stats.code_full += 1;
+        return Err(ExitReason::BufferFull);
+    }
+
+    let tb_idx = translate_tb(shared, &mut guard, per_cpu, cpu, pc, flags, 0)
+        .map_err(|error| ExitReason::TranslateFailed { pc, error })?;
+    per_cpu.jump_cache.insert(pc, tb_idx);
+    Ok(tb_idx)
+}
+
+/// Translate a TB of at most `count` instructions at `pc`. It
+/// is not published, so lookups keep finding the full TB.
+fn tb_gen_clamped<B, C>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+    cpu: &mut C,
+    pc: u64,
+    flags: u32,
+    count: u64,
+) -> Result<usize, ExitReason>
+where
+    B: HostCodeGen,
+    C: GuestCpu,
+{
+    let mut guard = shared.translate_lock.lock().unwrap();
+    if shared.tb_store.is_full()
+        || (shared.code_buf().remaining() < MIN_CODE_BUF_REMAINING
+            && !code_buf_grow(shared, per_cpu))
+    {
+        per_cpu.stats.code_full += 1;
+        return Err(ExitReason::BufferFull);
     }
+    per_cpu.stats.translate += 1;
+    let cflags = count as u32 & cflags::CF_COUNT_MASK;
+    translate_tb(shared, &mut guard, per_cpu, cpu, pc, flags, cflags)
+        .map_err(|error| ExitReason::TranslateFailed { pc, error })
+}
 
+/// Translate and publish a new TB for (`pc`, `flags`). Caller
+/// holds translate_lock (`guard`) and has checked that the code
+/// buffer has room. On error the code emitted so far is
+/// discarded and the TB slot is left dead, never published.
+/// A TB with an instruction count in `cflags` is not published
+/// either.
+pub(crate) fn translate_tb<B, C>(
+    shared: &SharedState<B>,
+    guard: &mut TranslateGuard,
+    per_cpu: &mut PerCpuState,
+    cpu: &mut C,
+    pc: u64,
+    flags: u32,
+    cflags: u32,
+) -> Result<usize, TranslateError>
+where
+    B: HostCodeGen,
+    C: GuestCpu,
+{
     // SAFETY: we hold translate_lock, so exclusive access to
     // tbs Vec and code_buf emit methods.
-    let tb_idx = unsafe { shared.tb_store.alloc(pc, flags, 0) };
-
-    guard.ir_ctx.reset();
-    guard.ir_ctx.tb_idx = tb_idx as u32;
-    let guest_size = cpu.gen_code(
-        &mut guard.ir_ctx,
-        pc,
-        tcg_core::tb::TranslationBlock::max_insns(0),
-    );
+    let tb_idx = unsafe { shared.tb_store.alloc(pc, flags, cflags) };
+
+    // Translate, shrinking the TB while register pressure
+    // exceeds the configured limit.
+    let limit = guard.pressure_limit;
+    let opts = guard.optimize;
+    let regalloc = guard.regalloc;
+    let mut max_insns = TranslationBlock::max_insns(cflags);
+    let info = loop {
+        guard.ir_ctx.reset();
+        guard.ir_ctx.tb_idx = tb_idx as u32;
+        let info = cpu.gen_code(&mut guard.ir_ctx, pc, flags, max_insns);
+        analyze_with(&mut guard.ir_ctx, &opts);
+        let Some(limit) = limit else {
+            break info;
+        };
+        let nb_regs = shared.backend.allocatable_regs().count();
+        let report = pressure_report(&guard.ir_ctx, nb_regs);
+        if report.max_live <= limit || info.guest_insns <= 1 {
+            break info;
+        }
+        per_cpu.stats.pressure_split += 1;
+        max_insns = info.guest_insns / 2;
+    };
+    debug_assert_eq!(info.first_pc, pc);
+    per_cpu.stats.insns_translated += info.guest_insns as u64;
+    per_cpu.stats.bytes_translated += info.guest_len_bytes as u64;
+    let starts = insn_offsets(&guard.ir_ctx, pc);
+    per_cpu.stats.insn_starts_bytes += starts.encoded_len() as u64;
     unsafe {
-        shared.tb_store.get_mut(tb_idx).size = guest_size;
+        let tb = shared.tb_store.get_mut(tb_idx);
+        tb.size = info.guest_len_bytes;
+        tb.icount = info.guest_insns as u16;
+        tb.spin_loop = info.spin_loop;
+        tb.insn_offsets = starts;
     }
 
     shared.backend.clear_goto_tb_offsets();
@@ -205,8 +573,24 @@ where
     // SAFETY: translate_lock guarantees exclusive access to
     // code_buf's write cursor.
     let code_buf_mut = unsafe { shared.code_buf_mut() };
-    let host_offset =
-        translate(&mut guard.ir_ctx, &shared.backend, code_buf_mut);
+    let rollback = code_buf_mut.offset();
+    let pad = shared.backend.emit_align(code_buf_mut, shared.tb_align);
+    let host_offset = match codegen_with(
+        &mut guard.ir_ctx,
+        &shared.backend,
+        code_buf_mut,
+        regalloc,
+    ) {
+        Ok(off) => off,
+        Err(e) => {
+            code_buf_mut.set_offset(rollback);
+            let tb = shared.tb_store.get(tb_idx);
+            let _jmp = tb.jmp.lock().unwrap();
+            tb.mark_dead();
+            return Err(e);
+        }
+    };
+    per_cpu.stats.align_pad += pad as u64;
     let host_size = shared.code_buf().offset() - host_offset;
 
     // SAFETY: under translate_lock.
@@ -225,10 +609,30 @@ where
         }
     }
 
-    shared.tb_store.insert(tb_idx);
-    per_cpu.jump_cache.insert(pc, tb_idx);
+    #[cfg(feature = "verify-code")]
+    if let Some(v) = shared.tb_store.verifier() {
+        v.seal(tb_idx, shared.tb_store.get(tb_idx), shared.code_buf());
+    }
 
-    Some(tb_idx)
+    shared.tb_store.link_pages(tb_idx);
+    if cflags & cflags::CF_COUNT_MASK == 0 {
+        shared.tb_store.insert(tb_idx);
+    }
+    Ok(tb_idx)
+}
+
+/// Offsets from `pc` of the `insn_start` markers in `ir`.
+fn insn_offsets(ir: &Context, pc: u64) -> InsnStarts {
+    InsnStarts::from_offsets(
+        ir.ops()
+            .iter()
+            .filter(|op| op.opc == Opcode::InsnStart)
+            .map(|op| {
+                let c = op.cargs();
+                let insn_pc = c[0].0 as u64 | (c[1].0 as u64) << 32;
+                (insn_pc - pc) as u32
+            }),
+    )
 }
 
 /// Execute a single TB and return the exit value.
@@ -245,52 +649,61 @@ where
     let tb_ptr = shared.code_buf().ptr_at(tb.host_offset);
     let env_ptr = cpu.env_ptr();
 
-    let prologue_fn: unsafe extern "C" fn(*mut u8, *const u8) -> usize =
-        core::mem::transmute(shared.code_buf().base_ptr());
-    prologue_fn(env_ptr, tb_ptr)
+    let prologue_fn: unsafe extern "C" fn(
+        *mut u8,
+        *const u8,
+        *mut u64,
+    ) -> usize = core::mem::transmute(shared.code_buf().base_ptr());
+    prologue_fn(env_ptr, tb_ptr, helper::pending_ptr())
 }
 
-/// Patch a goto_tb jump to directly chain src -> dst.
-///
-/// Lock ordering: always lock src first, then dst, to
-/// prevent deadlocks.
+/// Chain src -> dst if the chaining policy allows it.
 fn tb_add_jump<B: HostCodeGen>(
     shared: &SharedState<B>,
     per_cpu: &mut PerCpuState,
     src: usize,
     slot: usize,
     dst: usize,
+    key: (u64, u32),
 ) {
-    let src_tb = shared.tb_store.get(src);
-    let jmp_off = match src_tb.jmp_insn_offset[slot] {
-        Some(off) => off as usize,
-        None => return,
-    };
-
-    if shared.tb_store.get(dst).invalid.load(Ordering::Acquire) {
+    let store = &shared.tb_store;
+    // A chained TB would run on past the instruction budget.
+    if per_cpu.insn_budget.is_some() {
         return;
     }
-
-    // Lock src TB's jmp state.
-    let mut src_jmp = src_tb.jmp.lock().unwrap();
-
-    if src_jmp.jmp_dest[slot] == Some(dst) {
-        per_cpu.stats.chain_already += 1;
+    // A chained spin TB would loop without returning here.
+    if shared.spin_yield_after.is_some() && store.get(src).spin_loop {
+        per_cpu.stats.chain_refused_spin += 1;
         return;
     }
+    match shared.chain_policy {
+        ChainPolicy::Always => {}
+        ChainPolicy::SamePageOnly => {
+            // Either TB spanning a page the other does not would
+            // outlive an invalidation of that page.
+            let (s, d) = (store.get(src), store.get(dst));
+            if (s.first_page(), s.last_page())
+                != (d.first_page(), d.last_page())
+            {
+                per_cpu.stats.chain_refused_page += 1;
+                return;
+            }
+        }
+        ChainPolicy::Never => {
+            per_cpu.stats.chain_refused_never += 1;
+            return;
+        }
+    }
 
-    let abs_dst = shared.tb_store.get(dst).host_offset;
-    shared
-        .backend
-        .patch_jump(shared.code_buf(), jmp_off, abs_dst);
-
-    src_jmp.jmp_dest[slot] = Some(dst);
-    drop(src_jmp);
-
-    // Lock dst TB's jmp state to add incoming edge.
-    let dst_tb = shared.tb_store.get(dst);
-    let mut dst_jmp = dst_tb.jmp.lock().unwrap();
-    dst_jmp.jmp_list.push((src, slot));
-
-    per_cpu.stats.chain_patched += 1;
+    match store.add_jump(src, slot, dst, shared.code_buf(), &shared.backend) {
+        JumpPatch::Patched => {
+            per_cpu.stats.chain_patched += 1;
+            if let Some(check) = &shared.chain_check {
+                check.record(src, slot, key.0, key.1);
+            }
+        }
+        JumpPatch::Already => per_cpu.stats.chain_already += 1,
+        JumpPatch::TargetDead => per_cpu.stats.chain_refused_dead += 1,
+        JumpPatch::NoSlot => {}
+    }
 }
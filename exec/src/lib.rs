@@ -7,24 +7,51 @@
 //! Reference: `~/qemu/accel/tcg/cpu-exec.c`,
 //! `~/qemu/accel/tcg/translate-all.c`.
 
+pub mod budget;
+pub mod chain_check;
+pub mod checkpoint;
+pub mod coverage;
+pub mod excp;
 pub mod exec_loop;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod snapshot;
+#[cfg(debug_assertions)]
+pub mod stack_check;
 pub mod tb_store;
+pub mod timing;
+#[cfg(feature = "verify-code")]
+pub mod verify;
+pub mod warmup;
 
 pub use exec_loop::{cpu_exec_loop, ExitReason};
-pub use tb_store::TbStore;
+pub use tb_store::{JumpPatch, MidInsn, TbStore};
 
 use std::cell::UnsafeCell;
+use std::collections::HashSet;
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use tcg_backend::code_buffer::CodeBuffer;
+use budget::{Budget, BudgetWatch};
+use chain_check::ChainChecker;
+use checkpoint::{CheckpointInterval, CheckpointWatch};
+use coverage::Coverage;
+#[cfg(feature = "metrics")]
+use metrics::{CpuMetrics, MetricsServer};
+use tcg_backend::code_buffer::{CodeBuffer, MAX_CODE_BUF_SIZE};
+use tcg_backend::optimize::OptimizeOptions;
+use tcg_backend::regalloc::RegAllocMode;
 use tcg_backend::HostCodeGen;
-use tcg_core::tb::JumpCache;
+use tcg_core::excp::ExcpRegistry;
+use tcg_core::tb::{JumpCache, TranslationInfo};
 use tcg_core::Context;
+use timing::{PhaseTimer, PhaseTimes};
 
 /// Execution statistics for profiling the TB lookup/chain
 /// pipeline.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ExecStats {
     pub loop_iters: u64,
     // TB lookup
@@ -38,8 +65,40 @@ pub struct ExecStats {
     // Chaining
     pub chain_patched: u64,
     pub chain_already: u64,
+    // Chains refused, by reason
+    pub chain_refused_never: u64,
+    pub chain_refused_page: u64,
+    pub chain_refused_dead: u64,
+    pub chain_refused_spin: u64,
     // Hint
     pub hint_used: u64,
+    // TBs retranslated smaller due to register pressure
+    pub pressure_split: u64,
+    // Guest instructions retired
+    pub insns: u64,
+    // Guest instructions and bytes translated into TBs
+    pub insns_translated: u64,
+    pub bytes_translated: u64,
+    // Bytes of per-TB instruction boundary encoding
+    pub insn_starts_bytes: u64,
+    // NOP bytes emitted to align TB entry points
+    pub align_pad: u64,
+    // Code buffer grown in place instead of reporting full
+    pub code_grow: u64,
+    // Code buffer full and could not grow
+    pub code_full: u64,
+    // Code cache flushed to make room
+    pub code_flush: u64,
+    // TBs invalidated because their guest code changed
+    pub tb_invalidated: u64,
+    // Yields out of a spinning TB
+    pub spin_yield: u64,
+    // TBs pre-translated from warmup hints, and how many of
+    // them the loop went on to dispatch
+    pub warmup_tbs: u64,
+    pub warmup_used: u64,
+    // Wall-clock time by phase, when timing is enabled
+    pub time: PhaseTimes,
 }
 
 impl fmt::Display for ExecStats {
@@ -74,8 +133,42 @@ impl fmt::Display for ExecStats {
         writeln!(f, "--- Chaining ---")?;
         writeln!(f, "  patched:     {}", self.chain_patched)?;
         writeln!(f, "  already:     {}", self.chain_already)?;
+        writeln!(f, "  refused:")?;
+        writeln!(f, "    never:     {}", self.chain_refused_never)?;
+        writeln!(f, "    page:      {}", self.chain_refused_page)?;
+        writeln!(f, "    dead:      {}", self.chain_refused_dead)?;
+        writeln!(f, "    spin:      {}", self.chain_refused_spin)?;
         writeln!(f, "--- Hint ---")?;
         writeln!(f, "  hint used:   {}", self.hint_used)?;
+        writeln!(f, "--- Pressure ---")?;
+        writeln!(f, "  split:       {}", self.pressure_split)?;
+        writeln!(f, "--- Guest ---")?;
+        writeln!(f, "  insns:       {}", self.insns)?;
+        writeln!(f, "  translated:  {} insns", self.insns_translated)?;
+        writeln!(f, "               {} bytes", self.bytes_translated)?;
+        writeln!(f, "  boundaries:  {} bytes", self.insn_starts_bytes)?;
+        writeln!(f, "--- Code ---")?;
+        writeln!(f, "  align pad:   {} bytes", self.align_pad)?;
+        writeln!(f, "  grown:       {}", self.code_grow)?;
+        writeln!(f, "  full:        {}", self.code_full)?;
+        writeln!(f, "  flushed:     {}", self.code_flush)?;
+        writeln!(f, "  invalidated: {}", self.tb_invalidated)?;
+        writeln!(f, "--- Spin ---")?;
+        writeln!(f, "  yields:      {}", self.spin_yield)?;
+        if self.warmup_tbs > 0 {
+            writeln!(f, "--- Warmup ---")?;
+            writeln!(f, "  translated:  {}", self.warmup_tbs)?;
+            writeln!(
+                f,
+                "  used:        {} ({:.1}%)",
+                self.warmup_used,
+                pct(self.warmup_used, self.warmup_tbs)
+            )?;
+        }
+        if self.time.reads > 0 {
+            writeln!(f, "--- Time ---")?;
+            write!(f, "{}", self.time)?;
+        }
         Ok(())
     }
 }
@@ -92,13 +185,49 @@ fn pct(n: u64, total: u64) -> f64 {
 pub trait GuestCpu {
     fn get_pc(&self) -> u64;
     fn get_flags(&self) -> u32;
-    fn gen_code(&mut self, ir: &mut Context, pc: u64, max_insns: u32) -> u32;
+    /// Translate the TB at `pc` for the mode bits in `flags`
+    /// (e.g. `TB_FLAG_SINGLE_STEP`).
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo;
     fn env_ptr(&mut self) -> *mut u8;
+
+    /// Guest instructions retired so far, if counted.
+    fn insns_retired(&self) -> u64 {
+        0
+    }
+}
+
+/// When the exec loop may patch a `goto_tb` exit to jump
+/// directly into the next TB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChainPolicy {
+    /// Chain every chainable exit.
+    #[default]
+    Always,
+    /// Chain only when source and target TBs start on the
+    /// same guest page, so remapping a page never leaves a
+    /// direct jump into it from another page.
+    SamePageOnly,
+    /// Never chain; every exit returns to the exec loop.
+    Never,
 }
 
 /// State protected by translate_lock.
 pub struct TranslateGuard {
     pub ir_ctx: Context,
+    /// When set, a TB whose peak register pressure exceeds
+    /// this limit is retranslated with half as many guest
+    /// instructions instead of emitting spill-heavy code.
+    pub pressure_limit: Option<u32>,
+    /// Optimizer options every translation uses.
+    pub optimize: OptimizeOptions,
+    /// Register allocator every translation uses.
+    pub regalloc: RegAllocMode,
 }
 
 /// Shared across all vCPU threads.
@@ -109,8 +238,29 @@ pub struct SharedState<B: HostCodeGen> {
     code_buf: UnsafeCell<CodeBuffer>,
     pub backend: B,
     pub code_gen_start: usize,
+    pub chain_policy: ChainPolicy,
+    /// Host code alignment of every TB entry point.
+    pub tb_align: usize,
+    /// Size the code buffer may grow to when it fills up.
+    pub code_buf_limit: usize,
+    /// Return `ExitReason::Yield` once a spin-marked TB has run
+    /// this many times in a row; `None` disables spin yields.
+    pub spin_yield_after: Option<u32>,
+    /// Checks chained jumps before every TB entry.
+    pub chain_check: Option<ChainChecker>,
+    /// The frontend's exception codes; when set, other codes
+    /// leave the loop with `ExitReason::UnknownException`.
+    pub excp: Option<ExcpRegistry>,
+    /// Host stack red zone checked at TB and helper entries.
+    #[cfg(debug_assertions)]
+    pub stack_red_zone: Option<usize>,
     /// Serializes code generation (IR + emit).
     pub translate_lock: Mutex<TranslateGuard>,
+    /// Held shared by threads other than vCPUs that walk the
+    /// TBs, exclusively by a flush dropping them.
+    pub flush_lock: RwLock<()>,
+    /// Code cache flushes so far.
+    flushes: AtomicU64,
 }
 
 // SAFETY: code_buf emit is serialized by translate_lock;
@@ -134,33 +284,188 @@ impl<B: HostCodeGen> SharedState<B> {
     pub unsafe fn code_buf_mut(&self) -> &mut CodeBuffer {
         &mut *self.code_buf.get()
     }
+
+    /// Invalidate all TBs translated from guest `[start, end)`,
+    /// e.g. after the guest discarded or moved that memory.
+    pub fn tb_invalidate_range(&self, start: u64, end: u64) -> usize {
+        let _guard = self.translate_lock.lock().unwrap();
+        self.tb_store.invalidate_range(
+            start,
+            end,
+            self.code_buf(),
+            &self.backend,
+        )
+    }
+
+    /// Invalidate all TBs translated from any of `ranges`
+    /// (sorted and disjoint), visiting only the TBs on the
+    /// guest pages they touch.
+    pub fn tb_invalidate_ranges(&self, ranges: &[(u64, u64)]) -> usize {
+        let _guard = self.translate_lock.lock().unwrap();
+        self.tb_store
+            .invalidate_ranges(ranges, self.code_buf(), &self.backend)
+    }
+
+    /// Reset every chained jump; see `TbStore::unlink_all`.
+    /// For threads other than vCPUs, which may race a flush.
+    pub fn tb_unlink_all(&self) -> usize {
+        let _flush = self.flush_lock.read().unwrap();
+        self.tb_store.unlink_all(self.code_buf(), &self.backend)
+    }
+
+    /// Drop every TB and rewind the code buffer to
+    /// `code_gen_start`. Each vCPU forgets the TB indices it
+    /// cached (jump cache, hints) when it next enters the loop.
+    ///
+    /// # Safety
+    /// No vCPU may be inside the exec loop: chained TBs jump
+    /// straight into the code being discarded.
+    pub unsafe fn flush_code_cache(&self) {
+        let _guard = self.translate_lock.lock().unwrap();
+        let _flush = self.flush_lock.write().unwrap();
+        self.code_buf_mut().set_offset(self.code_gen_start);
+        self.tb_store.flush();
+        if let Some(check) = &self.chain_check {
+            check.clear();
+        }
+        self.flushes.fetch_add(1, Ordering::Release);
+    }
+
+    /// Code cache flushes so far.
+    pub fn flushes(&self) -> u64 {
+        self.flushes.load(Ordering::Acquire)
+    }
 }
 
 /// Per-vCPU state (not shared across threads).
 pub struct PerCpuState {
     pub jump_cache: JumpCache,
     pub stats: ExecStats,
+    /// Block coverage, recorded when set.
+    pub coverage: Option<Coverage>,
+    /// TB entries counted for sampled code verification.
+    #[cfg(feature = "verify-code")]
+    pub verify_tick: u64,
+    /// Spin-marked TB entered last and its consecutive runs.
+    pub spin_streak: (usize, u32),
+    /// Phase timing into `stats.time`, when set.
+    pub timer: Option<PhaseTimer>,
+    /// Pre-translated TBs not yet dispatched.
+    pub warm: HashSet<usize>,
+    /// Thread CPU time spent in the exec loop.
+    pub cpu_time: Duration,
+    /// CPU and wall-clock limits, when set.
+    pub budget: Option<BudgetWatch>,
+    /// Checkpoint requests, when set.
+    pub checkpoint: Option<CheckpointWatch>,
+    /// Guest instructions left to run, counted with
+    /// `GuestCpu::insns_retired`. The loop returns
+    /// `ExitReason::BudgetExhausted` once it reaches zero and
+    /// does not chain TBs while it is set.
+    pub insn_budget: Option<u64>,
+    /// Slot this vCPU publishes its stats into, when serving
+    /// metrics.
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<CpuMetrics>,
+    /// `SharedState::flushes()` the cached TB indices belong to.
+    flushes_seen: u64,
+}
+
+impl PerCpuState {
+    pub fn new() -> Self {
+        Self {
+            jump_cache: JumpCache::new(),
+            stats: ExecStats::default(),
+            coverage: None,
+            #[cfg(feature = "verify-code")]
+            verify_tick: 0,
+            spin_streak: (usize::MAX, 0),
+            timer: None,
+            warm: HashSet::new(),
+            cpu_time: Duration::ZERO,
+            budget: None,
+            checkpoint: None,
+            insn_budget: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            flushes_seen: 0,
+        }
+    }
+
+    /// Forget TB indices cached before the code cache was last
+    /// flushed.
+    pub(crate) fn sync_flush<B: HostCodeGen>(
+        &mut self,
+        shared: &SharedState<B>,
+    ) {
+        let flushes = shared.flushes();
+        if self.flushes_seen == flushes {
+            return;
+        }
+        self.flushes_seen = flushes;
+        self.jump_cache.invalidate();
+        self.warm.clear();
+        self.spin_streak = (usize::MAX, 0);
+        if let Some(cov) = self.coverage.as_mut() {
+            cov.retire_tbs();
+        }
+    }
+}
+
+impl Default for PerCpuState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+/// Default host alignment of TB entry points.
+pub const DEFAULT_TB_ALIGN: usize = 16;
+
 /// Minimum remaining bytes in code buffer before refusing
 /// to translate a new TB.
 const MIN_CODE_BUF_REMAINING: usize = 4096;
 
+/// Initial code buffer size of `ExecEnv::new`.
+const DEFAULT_CODE_BUF_SIZE: usize = 16 * 1024 * 1024;
+
+/// Address space reserved after the default code buffer so
+/// that growing it does not depend on what the kernel mapped
+/// next to it.
+const DEFAULT_CODE_BUF_RESERVE: usize = 256 * 1024 * 1024;
+
+/// Ops and temps the shared IR context reserves up front, so
+/// that all but unusually large TBs translate without growing
+/// it.
+const IR_OPS_CAPACITY: usize = 4096;
+const IR_TEMPS_CAPACITY: usize = 1024;
+
 /// Convenience wrapper for single-threaded use.
 pub struct ExecEnv<B: HostCodeGen> {
     pub shared: Arc<SharedState<B>>,
     pub per_cpu: PerCpuState,
+    /// Serves `/metrics` while the environment lives.
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<MetricsServer>,
 }
 
 impl<B: HostCodeGen> ExecEnv<B> {
-    pub fn new(mut backend: B) -> Self {
-        let mut code_buf =
-            CodeBuffer::new(16 * 1024 * 1024).expect("mmap failed");
+    pub fn new(backend: B) -> Self {
+        let code_buf = CodeBuffer::with_reserve(
+            DEFAULT_CODE_BUF_SIZE,
+            DEFAULT_CODE_BUF_RESERVE,
+        )
+        .expect("mmap failed");
+        Self::with_code_buf(backend, code_buf)
+    }
+
+    /// Like `new`, translating into `code_buf`.
+    pub fn with_code_buf(mut backend: B, mut code_buf: CodeBuffer) -> Self {
         backend.emit_prologue(&mut code_buf);
         backend.emit_epilogue(&mut code_buf);
         let code_gen_start = code_buf.offset();
 
-        let mut ir_ctx = Context::new();
+        let mut ir_ctx =
+            Context::with_capacity(IR_OPS_CAPACITY, IR_TEMPS_CAPACITY);
         backend.init_context(&mut ir_ctx);
 
         let shared = Arc::new(SharedState {
@@ -168,15 +473,197 @@ impl<B: HostCodeGen> ExecEnv<B> {
             code_buf: UnsafeCell::new(code_buf),
             backend,
             code_gen_start,
-            translate_lock: Mutex::new(TranslateGuard { ir_ctx }),
+            chain_policy: ChainPolicy::default(),
+            tb_align: DEFAULT_TB_ALIGN,
+            code_buf_limit: MAX_CODE_BUF_SIZE,
+            spin_yield_after: None,
+            chain_check: None,
+            excp: None,
+            #[cfg(debug_assertions)]
+            stack_red_zone: None,
+            translate_lock: Mutex::new(TranslateGuard {
+                ir_ctx,
+                pressure_limit: None,
+                optimize: OptimizeOptions::default(),
+                regalloc: RegAllocMode::default(),
+            }),
+            flush_lock: RwLock::new(()),
+            flushes: AtomicU64::new(0),
         });
 
         Self {
             shared,
-            per_cpu: PerCpuState {
-                jump_cache: JumpCache::new(),
-                stats: ExecStats::default(),
-            },
+            per_cpu: PerCpuState::new(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
+
+    /// Set the TB chaining policy. Must be called before the
+    /// shared state is handed to other threads.
+    pub fn with_chain_policy(mut self, policy: ChainPolicy) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .chain_policy = policy;
+        self
+    }
+
+    /// Align TB entry points to `align` bytes (a power of two;
+    /// 1 disables). Must be called before the shared state is
+    /// handed to other threads.
+    pub fn with_tb_align(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .tb_align = align;
+        self
+    }
+
+    /// Let the code buffer grow in place up to `limit` bytes
+    /// when it fills up; a limit at or below the current size
+    /// disables growth. Must be called before the shared state
+    /// is handed to other threads.
+    pub fn with_code_buf_limit(mut self, limit: usize) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .code_buf_limit = limit.min(MAX_CODE_BUF_SIZE);
+        self
+    }
+
+    /// Leave the exec loop with `ExitReason::Yield` when a TB
+    /// marked as a spin loop runs more than `after` times in a
+    /// row, so the caller can schedule another vCPU. Spin TBs
+    /// are then never chained to themselves. Must be called
+    /// before the shared state is handed to other threads.
+    pub fn with_spin_yield(mut self, after: u32) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .spin_yield_after = Some(after);
+        self
+    }
+
+    /// Retranslate a TB with half as many guest instructions
+    /// while its peak register pressure exceeds `limit`. Must be
+    /// called before the shared state is handed to other
+    /// threads.
+    pub fn with_pressure_limit(mut self, limit: u32) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .translate_lock
+            .get_mut()
+            .unwrap()
+            .pressure_limit = Some(limit);
+        self
+    }
+
+    /// Allocate registers for every translation with `mode`.
+    /// Must be called before the shared state is handed to
+    /// other threads.
+    pub fn with_regalloc(mut self, mode: RegAllocMode) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use")
+            .translate_lock
+            .get_mut()
+            .unwrap()
+            .regalloc = mode;
+        self
+    }
+
+    /// Before every TB entry, check that each chained jump
+    /// reachable from it goes to the TB a fresh lookup of its
+    /// `(pc, flags)` finds, recording divergences in
+    /// `chain_check` and unchaining them. Must be called before
+    /// any translation.
+    pub fn with_chain_check(mut self) -> Self {
+        let shared = Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use");
+        assert!(shared.tb_store.is_empty(), "TBs already translated");
+        shared.chain_check = Some(ChainChecker::new());
+        self
+    }
+
+    /// Check exception exits against `registry`: a code it does
+    /// not name leaves the loop with
+    /// `ExitReason::UnknownException` instead of `Exit`.
+    pub fn with_exceptions(mut self, registry: ExcpRegistry) -> Self {
+        let shared = Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use");
+        shared.excp = Some(registry);
+        self
+    }
+
+    /// Leave the exec loop with `ExitReason::StackOverflow`
+    /// when the host stack comes within `red_zone` bytes of its
+    /// end at a TB entry or a helper call, instead of running
+    /// off it. Debug builds only; must be called before any
+    /// translation.
+    #[cfg(debug_assertions)]
+    pub fn with_stack_check(mut self, red_zone: usize) -> Self {
+        let shared = Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use");
+        assert!(shared.tb_store.is_empty(), "TBs already translated");
+        shared.backend.set_stack_check(true);
+        shared.stack_red_zone = Some(red_zone);
+        self
+    }
+
+    /// Split wall-clock time into exec-loop phases in
+    /// `stats.time`, calibrating the timestamp cost first.
+    pub fn with_timing(mut self) -> Self {
+        self.per_cpu.timer = Some(PhaseTimer::new());
+        self
+    }
+
+    /// Checksum every TB's host code and re-check it on every
+    /// `every`th TB entry from the exec loop, panicking on
+    /// corruption. Must be called before any translation.
+    #[cfg(feature = "verify-code")]
+    pub fn with_verify_code(mut self, every: u64) -> Self {
+        let shared = Arc::get_mut(&mut self.shared)
+            .expect("shared state already in use");
+        assert!(shared.tb_store.is_empty(), "TBs already translated");
+        shared
+            .tb_store
+            .set_verifier(verify::CodeVerifier::new(every));
+        self
+    }
+}
+
+impl<B: HostCodeGen + Send + Sync + 'static> ExecEnv<B> {
+    /// Leave the exec loop with `ExitReason::TimedOut` once the
+    /// calling thread has used `budget.cpu` of CPU time or
+    /// `budget.wall` has passed. The clocks start now, on the
+    /// calling thread, which must be the one running the vCPU.
+    /// Must be the last builder: the watchdog shares the state.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.per_cpu.budget =
+            Some(BudgetWatch::start(Arc::clone(&self.shared), budget));
+        self
+    }
+
+    /// Leave the exec loop with `ExitReason::Checkpoint` at
+    /// the first TB boundary after each `interval`, counted
+    /// from now and from every checkpoint returned. Must follow
+    /// the builders that configure the shared state, which the
+    /// poller thread holds.
+    pub fn with_checkpoint(mut self, interval: CheckpointInterval) -> Self {
+        self.per_cpu.checkpoint =
+            Some(CheckpointWatch::start(Arc::clone(&self.shared), interval));
+        self
+    }
+
+    /// Serve Prometheus metrics of the shared state and of this
+    /// vCPU, as `cpu="0"`, on `addr` until dropped. Further
+    /// vCPUs register with `metrics.register()`. Must follow
+    /// the builders that configure the shared state.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(
+        mut self,
+        addr: impl std::net::ToSocketAddrs,
+    ) -> std::io::Result<Self> {
+        let server = MetricsServer::start(Arc::clone(&self.shared), addr)?;
+        self.per_cpu.metrics = Some(server.register());
+        self.metrics = Some(server);
+        Ok(self)
+    }
 }
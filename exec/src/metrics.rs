@@ -0,0 +1,488 @@
+//! Live metrics over HTTP in the Prometheus text format.
+//!
+//! `MetricsServer` serves `GET /metrics` from a background
+//! thread. Each vCPU publishes a copy of its `ExecStats` into
+//! its `CpuMetrics` slot every `PUBLISH_EVERY` loop iterations
+//! and whenever it leaves the exec loop; a scrape reads those
+//! copies and the shared TB store, so vCPUs are never paused.
+//! TBs reached through chained jumps do not pass through the
+//! loop, so a vCPU spinning in a chained loop publishes only
+//! when it next leaves it.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tcg_backend::HostCodeGen;
+use tcg_core::tb::TB_HASH_SIZE;
+
+use crate::{ExecStats, SharedState};
+
+/// Exec loop iterations between two publications.
+pub const PUBLISH_EVERY: u64 = 1024;
+
+/// Window of the guest MIPS gauge.
+pub const MIPS_WINDOW: Duration = Duration::from_secs(10);
+
+/// Minimum spacing of the samples behind the MIPS gauge.
+const SAMPLE_SPACING: Duration = Duration::from_millis(100);
+
+/// Accept polling interval of the server thread.
+const TICK: Duration = Duration::from_millis(20);
+
+/// Longest request head read.
+const MAX_REQUEST: usize = 8192;
+
+/// What one vCPU last published.
+#[derive(Default)]
+struct CpuSlot {
+    stats: ExecStats,
+    cpu_time: Duration,
+    /// `(when, insns)`, oldest first, spanning `MIPS_WINDOW`.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl CpuSlot {
+    /// Guest MIPS over the window ending `now`.
+    fn mips(&self, now: Instant) -> f64 {
+        let Some(&(_, last)) = self.samples.back() else {
+            return 0.0;
+        };
+        let from = now.checked_sub(MIPS_WINDOW).unwrap_or(now);
+        let &(t0, first) = self
+            .samples
+            .iter()
+            .find(|(t, _)| *t >= from)
+            .unwrap_or(self.samples.back().unwrap());
+        let secs = now.duration_since(t0).as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        (last - first) as f64 / secs / 1e6
+    }
+}
+
+#[derive(Default)]
+struct Hub {
+    cpus: Mutex<Vec<CpuSlot>>,
+}
+
+/// A vCPU's slot, in `PerCpuState::metrics`.
+pub struct CpuMetrics {
+    hub: Arc<Hub>,
+    id: usize,
+}
+
+impl CpuMetrics {
+    /// Label of this vCPU in the exported metrics.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn publish(&self, stats: &ExecStats, cpu_time: Duration) {
+        let now = Instant::now();
+        let mut cpus = self.hub.cpus.lock().unwrap();
+        let slot = &mut cpus[self.id];
+        slot.stats = stats.clone();
+        slot.cpu_time = cpu_time;
+        let due = slot
+            .samples
+            .back()
+            .is_none_or(|(t, _)| now.duration_since(*t) >= SAMPLE_SPACING);
+        if due {
+            slot.samples.push_back((now, stats.insns));
+        }
+        while slot.samples.len() > 1
+            && now.duration_since(slot.samples[0].0) > MIPS_WINDOW
+        {
+            slot.samples.pop_front();
+        }
+    }
+}
+
+/// Background thread serving `/metrics`; stops when dropped.
+pub struct MetricsServer {
+    hub: Arc<Hub>,
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listen on `addr` (port 0 picks a free port) and serve
+    /// metrics of `shared` and of the vCPUs registered with
+    /// `register`.
+    pub fn start<B, A>(shared: Arc<SharedState<B>>, addr: A) -> io::Result<Self>
+    where
+        B: HostCodeGen + Send + Sync + 'static,
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let hub = Arc::new(Hub::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (h, s) = (Arc::clone(&hub), Arc::clone(&stop));
+        let thread = thread::Builder::new()
+            .name("tcg-metrics".to_string())
+            .spawn(move || {
+                while !s.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            // A failed scrape only affects its client.
+                            let _ = serve(stream, &shared, &h);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(TICK);
+                        }
+                        Err(_) => thread::sleep(TICK),
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            hub,
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A slot for one more vCPU, labelled with the next id.
+    pub fn register(&self) -> CpuMetrics {
+        let mut cpus = self.hub.cpus.lock().unwrap();
+        cpus.push(CpuSlot::default());
+        CpuMetrics {
+            hub: Arc::clone(&self.hub),
+            id: cpus.len() - 1,
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Answer one HTTP request on `stream`.
+fn serve<B: HostCodeGen>(
+    mut stream: TcpStream,
+    shared: &SharedState<B>,
+    hub: &Hub,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || head.len() + n > MAX_REQUEST {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut words = line.split(|&b| b == b' ');
+    let (method, path) = (words.next(), words.next());
+    let (status, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            ("200 OK", render(shared, hub, Instant::now()))
+        }
+        (Some(b"GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "GET only\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl ToString) {
+    let value = value.to_string();
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+/// Per-vCPU counter taken from `ExecStats`.
+type StatFn = fn(&ExecStats) -> u64;
+
+/// Counters with one sample per vCPU.
+const CPU_COUNTERS: &[(&str, &str, StatFn)] = &[
+    ("tcg_loop_iterations_total", "Exec loop iterations.", |s| {
+        s.loop_iters
+    }),
+    (
+        "tcg_guest_insns_total",
+        "Guest instructions retired.",
+        |s| s.insns,
+    ),
+    (
+        "tcg_translated_insns_total",
+        "Guest instructions translated.",
+        |s| s.insns_translated,
+    ),
+    (
+        "tcg_translated_bytes_total",
+        "Guest code bytes translated.",
+        |s| s.bytes_translated,
+    ),
+    (
+        "tcg_insn_starts_bytes_total",
+        "Bytes of instruction boundary encoding.",
+        |s| s.insn_starts_bytes,
+    ),
+    ("tcg_chain_patched_total", "Chained jumps patched.", |s| {
+        s.chain_patched
+    }),
+    (
+        "tcg_chain_already_total",
+        "Chain requests already in place.",
+        |s| s.chain_already,
+    ),
+    (
+        "tcg_hint_used_total",
+        "TB lookups skipped by the next-TB hint.",
+        |s| s.hint_used,
+    ),
+    (
+        "tcg_pressure_splits_total",
+        "TBs retranslated smaller for register pressure.",
+        |s| s.pressure_split,
+    ),
+    (
+        "tcg_align_pad_bytes_total",
+        "NOP bytes aligning TB entry points.",
+        |s| s.align_pad,
+    ),
+    (
+        "tcg_code_buffer_grows_total",
+        "Code buffer grown in place.",
+        |s| s.code_grow,
+    ),
+    (
+        "tcg_code_buffer_full_total",
+        "Code buffer full and unable to grow.",
+        |s| s.code_full,
+    ),
+    (
+        "tcg_code_cache_flushes_total",
+        "Code cache flushed to make room.",
+        |s| s.code_flush,
+    ),
+    (
+        "tcg_tb_invalidated_total",
+        "TBs invalidated because their guest code changed.",
+        |s| s.tb_invalidated,
+    ),
+    (
+        "tcg_spin_yields_total",
+        "Yields out of spinning TBs.",
+        |s| s.spin_yield,
+    ),
+    (
+        "tcg_warmup_tbs_total",
+        "TBs pre-translated from warmup hints.",
+        |s| s.warmup_tbs,
+    ),
+    (
+        "tcg_warmup_used_total",
+        "Pre-translated TBs dispatched.",
+        |s| s.warmup_used,
+    ),
+];
+
+/// Label values of a counter and their counts.
+type Labelled = &'static [(&'static str, StatFn)];
+
+/// Labelled counters: name, help, label name and values.
+const CPU_LABELLED: &[(&str, &str, &str, Labelled)] = &[
+    (
+        "tcg_tb_lookups_total",
+        "TB lookups by where the TB was found.",
+        "result",
+        &[
+            ("jump_cache", |s| s.jc_hit),
+            ("hash", |s| s.ht_hit),
+            ("translate", |s| s.translate),
+        ],
+    ),
+    (
+        "tcg_tb_exits_total",
+        "TB exits back to the exec loop by kind.",
+        "kind",
+        &[
+            ("chain_0", |s| s.chain_exit[0]),
+            ("chain_1", |s| s.chain_exit[1]),
+            ("nochain", |s| s.nochain_exit),
+            ("real", |s| s.real_exit),
+        ],
+    ),
+    (
+        "tcg_chain_refused_total",
+        "Chain requests refused by reason.",
+        "reason",
+        &[
+            ("never", |s| s.chain_refused_never),
+            ("page", |s| s.chain_refused_page),
+            ("dead", |s| s.chain_refused_dead),
+            ("spin", |s| s.chain_refused_spin),
+        ],
+    ),
+];
+
+/// The metrics text for `shared` and the vCPUs in `hub`.
+fn render<B: HostCodeGen>(
+    shared: &SharedState<B>,
+    hub: &Hub,
+    now: Instant,
+) -> String {
+    let mut out = String::new();
+
+    let store = &shared.tb_store;
+    let (tbs, invalid) = {
+        let _flush = shared.flush_lock.read().unwrap();
+        let tbs = store.len();
+        (tbs, (0..tbs).filter(|&i| store.get(i).is_invalid()).count())
+    };
+    header(&mut out, "tcg_tbs", "gauge", "TBs in the store by state.");
+    sample(&mut out, "tcg_tbs", "state=\"valid\"", tbs - invalid);
+    sample(&mut out, "tcg_tbs", "state=\"invalid\"", invalid);
+    header(
+        &mut out,
+        "tcg_tb_hash_buckets",
+        "gauge",
+        "TB hash table buckets by use.",
+    );
+    let used = store.buckets_used();
+    sample(&mut out, "tcg_tb_hash_buckets", "state=\"used\"", used);
+    sample(
+        &mut out,
+        "tcg_tb_hash_buckets",
+        "state=\"free\"",
+        TB_HASH_SIZE - used,
+    );
+
+    // Both atomic: the scrape does not wait for translation.
+    let buf = shared.code_buf();
+    let (offset, capacity) = (buf.offset(), buf.capacity());
+    header(
+        &mut out,
+        "tcg_code_buffer_bytes",
+        "gauge",
+        "Code buffer bytes: used by TBs, size and growth limit.",
+    );
+    let tb_bytes = offset.saturating_sub(shared.code_gen_start);
+    sample(&mut out, "tcg_code_buffer_bytes", "kind=\"tbs\"", tb_bytes);
+    sample(&mut out, "tcg_code_buffer_bytes", "kind=\"used\"", offset);
+    sample(&mut out, "tcg_code_buffer_bytes", "kind=\"size\"", capacity);
+    sample(
+        &mut out,
+        "tcg_code_buffer_bytes",
+        "kind=\"limit\"",
+        shared.code_buf_limit,
+    );
+    header(
+        &mut out,
+        "tcg_code_buffer_utilization",
+        "gauge",
+        "Fraction of the code buffer in use.",
+    );
+    sample(
+        &mut out,
+        "tcg_code_buffer_utilization",
+        "",
+        offset as f64 / capacity.max(1) as f64,
+    );
+
+    let cpus = hub.cpus.lock().unwrap();
+    let label = |id: usize| format!("cpu=\"{id}\"");
+    for &(name, help, stat) in CPU_COUNTERS {
+        header(&mut out, name, "counter", help);
+        for (id, c) in cpus.iter().enumerate() {
+            sample(&mut out, name, &label(id), stat(&c.stats));
+        }
+    }
+    for &(name, help, key, values) in CPU_LABELLED {
+        header(&mut out, name, "counter", help);
+        for (id, c) in cpus.iter().enumerate() {
+            for &(value, stat) in values {
+                let labels = format!("{},{key}=\"{value}\"", label(id));
+                sample(&mut out, name, &labels, stat(&c.stats));
+            }
+        }
+    }
+    header(
+        &mut out,
+        "tcg_cpu_seconds_total",
+        "counter",
+        "Thread CPU time spent in the exec loop.",
+    );
+    for (id, c) in cpus.iter().enumerate() {
+        sample(
+            &mut out,
+            "tcg_cpu_seconds_total",
+            &label(id),
+            c.cpu_time.as_secs_f64(),
+        );
+    }
+    header(
+        &mut out,
+        "tcg_phase_seconds_total",
+        "counter",
+        "Wall-clock time by exec-loop phase, when timing.",
+    );
+    for (id, c) in cpus.iter().enumerate() {
+        let t = &c.stats.time;
+        for (phase, d) in [
+            ("lookup", t.lookup),
+            ("translate", t.translate),
+            ("execute", t.execute),
+            ("outside", t.outside),
+        ] {
+            let labels = format!("{},phase=\"{phase}\"", label(id));
+            sample(
+                &mut out,
+                "tcg_phase_seconds_total",
+                &labels,
+                d.as_secs_f64(),
+            );
+        }
+    }
+    header(
+        &mut out,
+        "tcg_guest_mips",
+        "gauge",
+        "Guest MIPS over the last 10s.",
+    );
+    for (id, c) in cpus.iter().enumerate() {
+        sample(&mut out, "tcg_guest_mips", &label(id), c.mips(now));
+    }
+    out
+}
@@ -0,0 +1,96 @@
+//! In-process snapshot and restore of a guest, for fuzzing.
+//!
+//! A harness snapshots once the guest has initialized, then
+//! runs one input after another, restoring between them. The
+//! CPU state is copied whole; guest memory is captured by the
+//! [`SnapshotMemory`] implementation, which tracks writes made
+//! after the snapshot so that a restore only puts back what
+//! changed. TBs stay cached across restores except those
+//! translated from memory the restore rewrote.
+
+use std::io;
+
+use crate::ExecEnv;
+use tcg_backend::HostCodeGen;
+
+/// Guest memory that can be captured and rolled back.
+pub trait SnapshotMemory {
+    /// Captured contents and layout.
+    type Image;
+
+    /// Capture the memory and start tracking writes to it.
+    fn capture(&mut self) -> io::Result<Self::Image>;
+
+    /// Put back the memory as captured in `image`, which must
+    /// be the latest capture. Tracking continues from `image`.
+    fn rollback(&mut self, image: &Self::Image) -> io::Result<Rollback>;
+}
+
+/// What `SnapshotMemory::rollback` put back.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Rollback {
+    /// Pages copied back from the image.
+    pub pages: usize,
+    /// Guest ranges whose contents or mapping changed, sorted
+    /// and disjoint.
+    pub changed: Vec<(u64, u64)>,
+}
+
+/// CPU state and memory image of a guest at one point.
+pub struct EmuSnapshot<C, I> {
+    cpu: C,
+    mem: I,
+}
+
+/// Work done by one `ExecEnv::restore`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreStats {
+    /// Pages copied back from the snapshot.
+    pub pages: usize,
+    /// TBs invalidated because their guest code was rewritten.
+    pub tb_invalidated: usize,
+}
+
+impl<B: HostCodeGen> ExecEnv<B> {
+    /// Snapshot `cpu` and `mem`. Only the latest snapshot of a
+    /// memory can be restored.
+    pub fn snapshot<C, M>(
+        &self,
+        cpu: &C,
+        mem: &mut M,
+    ) -> io::Result<EmuSnapshot<C, M::Image>>
+    where
+        C: Clone,
+        M: SnapshotMemory,
+    {
+        Ok(EmuSnapshot {
+            cpu: cpu.clone(),
+            mem: mem.capture()?,
+        })
+    }
+
+    /// Put `cpu` and `mem` back as they were at `snap`. Costs
+    /// time in the number of pages written since the snapshot
+    /// (or the last restore), not in the size of memory.
+    pub fn restore<C, M>(
+        &mut self,
+        cpu: &mut C,
+        mem: &mut M,
+        snap: &EmuSnapshot<C, M::Image>,
+    ) -> io::Result<RestoreStats>
+    where
+        C: Clone,
+        M: SnapshotMemory,
+    {
+        let rollback = mem.rollback(&snap.mem)?;
+        let tb_invalidated =
+            self.shared.tb_invalidate_ranges(&rollback.changed);
+        self.per_cpu.stats.tb_invalidated += tb_invalidated as u64;
+        self.per_cpu.spin_streak = (usize::MAX, 0);
+        cpu.clone_from(&snap.cpu);
+        Ok(RestoreStats {
+            pages: rollback.pages,
+            tb_invalidated,
+        })
+    }
+}
@@ -0,0 +1,97 @@
+//! Host stack depth checks for debug builds.
+//!
+//! Generated code and the helpers it calls run below the exec
+//! loop's frame, where an overflow would corrupt memory instead
+//! of hitting Rust's guard page handler with a clear message.
+//! With `ExecEnv::with_stack_check`, every loop entry sets the
+//! thread's stack limit in `tcg_core::helper` to the low end of
+//! its stack plus a red zone, restoring the previous one on
+//! return; TB entries and helper shims compare against it and
+//! the loop returns `ExitReason::StackOverflow` with a
+//! `StackOverflowReport`. Release builds have none of this.
+
+use std::cell::Cell;
+use std::fmt;
+
+use tcg_core::helper::{self, Breadcrumb};
+
+/// Default stack headroom kept for reporting a breach.
+pub const DEFAULT_RED_ZONE: usize = 256 * 1024;
+
+thread_local! {
+    /// `[low, high)` of this thread's stack, once looked up.
+    static BOUNDS: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+/// Where the stack ran into its red zone, and how it got
+/// there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackOverflowReport {
+    /// Guest pc of the TB being entered, or of the TB whose
+    /// helper call tripped.
+    pub pc: u64,
+    /// Lowest stack address allowed.
+    pub limit: u64,
+    /// The helper entry that tripped; `None` for a TB entry.
+    pub helper: Option<Breadcrumb>,
+    /// Recent helper entries, oldest first.
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+impl fmt::Display for StackOverflowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.helper {
+            Some(b) => writeln!(
+                f,
+                "host stack overflow in helper at {} (pc={:#x})",
+                b.helper, self.pc
+            )?,
+            None => writeln!(
+                f,
+                "host stack overflow at TB entry (pc={:#x})",
+                self.pc
+            )?,
+        }
+        writeln!(f, "  limit:  {:#x}", self.limit)?;
+        writeln!(f, "  recent helpers, oldest first:")?;
+        for b in &self.breadcrumbs {
+            writeln!(f, "    {} (sp={:#x})", b.helper, b.sp)?;
+        }
+        Ok(())
+    }
+}
+
+/// `[low, high)` of the calling thread's stack.
+pub fn thread_stack() -> Option<(u64, u64)> {
+    if let Some(bounds) = BOUNDS.with(|b| b.get()) {
+        return Some(bounds);
+    }
+    let mut addr = std::ptr::null_mut();
+    let mut size = 0;
+    // SAFETY: `attr` is initialized by pthread_getattr_np before
+    // use and destroyed once.
+    let ok = unsafe {
+        let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let r = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        r == 0
+    };
+    if !ok {
+        return None;
+    }
+    let bounds = (addr as u64, addr as u64 + size as u64);
+    BOUNDS.with(|b| b.set(Some(bounds)));
+    Some(bounds)
+}
+
+/// Set the calling thread's limit `red_zone` bytes above the
+/// low end of its stack; returns the previous limit to restore,
+/// or `None` if the stack is unknown.
+pub(crate) fn enter(red_zone: usize) -> Option<u64> {
+    let (low, high) = thread_stack()?;
+    let limit = (low + red_zone as u64).min(high);
+    Some(helper::set_stack_limit(limit))
+}
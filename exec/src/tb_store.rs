@@ -1,10 +1,52 @@
 use std::cell::UnsafeCell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 
 use tcg_backend::code_buffer::CodeBuffer;
 use tcg_backend::HostCodeGen;
-use tcg_core::tb::{TranslationBlock, TB_HASH_SIZE};
+use tcg_core::tb::{
+    TranslationBlock, TARGET_PAGE_BITS, TARGET_PAGE_MASK, TB_HASH_SIZE,
+};
+
+#[cfg(feature = "verify-code")]
+use crate::verify::CodeVerifier;
+
+/// Result of `TbStore::add_jump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpPatch {
+    /// The jump was patched to the target.
+    Patched,
+    /// The slot already pointed at the target.
+    Already,
+    /// The target was invalidated; nothing was patched.
+    TargetDead,
+    /// The source has no `goto_tb` for this slot.
+    NoSlot,
+}
+
+/// A guest address inside a translated instruction rather than
+/// at its start, e.g. a misplaced breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidInsn {
+    pub pc: u64,
+    /// Start of the instruction covering `pc`.
+    pub insn: u64,
+}
+
+impl fmt::Display for MidInsn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} is not an instruction boundary (inside the \
+             instruction at {:#x})",
+            self.pc, self.insn
+        )
+    }
+}
+
+impl std::error::Error for MidInsn {}
 
 const MAX_TBS: usize = 65536;
 
@@ -16,13 +58,18 @@ pub struct TbStore {
     tbs: UnsafeCell<Vec<TranslationBlock>>,
     len: AtomicUsize,
     hash: Mutex<Vec<Option<usize>>>,
+    /// Valid TBs by the guest pages their code spans, like
+    /// QEMU's `PageDesc` lists.
+    pages: Mutex<BTreeMap<u64, Vec<usize>>>,
+    #[cfg(feature = "verify-code")]
+    verifier: Option<CodeVerifier>,
 }
 
 // SAFETY:
 // - tbs Vec is pre-allocated (no realloc). New entries are
 //   appended under translate_lock, then len is published
 //   with Release. Readers use Acquire on len.
-// - hash is protected by its own Mutex.
+// - hash and pages are each protected by their own Mutex.
 unsafe impl Sync for TbStore {}
 unsafe impl Send for TbStore {}
 
@@ -36,6 +83,9 @@ impl TbStore {
             tbs: UnsafeCell::new(v),
             len: AtomicUsize::new(0),
             hash: Mutex::new(vec![None; TB_HASH_SIZE]),
+            pages: Mutex::new(BTreeMap::new()),
+            #[cfg(feature = "verify-code")]
+            verifier: None,
         }
     }
 
@@ -83,10 +133,7 @@ impl TbStore {
         let mut cur = hash[bucket];
         while let Some(idx) = cur {
             let tb = self.get(idx);
-            if !tb.invalid.load(Ordering::Acquire)
-                && tb.pc == pc
-                && tb.flags == flags
-            {
+            if !tb.is_invalid() && tb.pc == pc && tb.flags == flags {
                 return Some(idx);
             }
             cur = tb.hash_next;
@@ -94,6 +141,33 @@ impl TbStore {
         None
     }
 
+    /// Find the TB whose host code contains code buffer offset
+    /// `host_off`. Linear; for rare paths only.
+    pub fn find_by_host(&self, host_off: usize) -> Option<usize> {
+        (0..self.len()).rev().find(|&idx| {
+            let tb = self.get(idx);
+            (tb.host_offset..tb.host_offset + tb.host_size).contains(&host_off)
+        })
+    }
+
+    /// Check that no live TB translated an instruction that
+    /// covers `pc` without starting there. Addresses outside
+    /// translated code are accepted. Linear; for rare paths
+    /// such as inserting a breakpoint.
+    pub fn check_insn_boundary(&self, pc: u64) -> Result<(), MidInsn> {
+        for idx in 0..self.len() {
+            let tb = self.get(idx);
+            if tb.is_invalid() {
+                continue;
+            }
+            match tb.insn_containing(pc) {
+                Some(insn) if insn != pc => return Err(MidInsn { pc, insn }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Insert a TB into the hash table (prepend to bucket).
     pub fn insert(&self, tb_idx: usize) {
         let tb = self.get(tb_idx);
@@ -110,8 +184,46 @@ impl TbStore {
         hash[bucket] = Some(tb_idx);
     }
 
+    /// Record a translated TB under every guest page its code
+    /// spans, so range invalidation finds it. Called once the
+    /// TB's `size` is final, whether or not it is hashed.
+    pub fn link_pages(&self, tb_idx: usize) {
+        let tb = self.get(tb_idx);
+        let mut pages = self.pages.lock().unwrap();
+        let mut page = tb.first_page();
+        while page <= tb.last_page() {
+            pages.entry(page).or_default().push(tb_idx);
+            page += 1 << TARGET_PAGE_BITS;
+        }
+    }
+
+    /// Indices of the valid TBs whose code spans the guest page
+    /// holding `page`, for tests and diagnostics.
+    pub fn tbs_on_page(&self, page: u64) -> Vec<usize> {
+        let pages = self.pages.lock().unwrap();
+        pages
+            .get(&(page & TARGET_PAGE_MASK))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Drop a TB from the page lists.
+    fn unlink_pages(&self, tb: &TranslationBlock, tb_idx: usize) {
+        let mut pages = self.pages.lock().unwrap();
+        let mut page = tb.first_page();
+        while page <= tb.last_page() {
+            if let Some(list) = pages.get_mut(&page) {
+                list.retain(|&idx| idx != tb_idx);
+                if list.is_empty() {
+                    pages.remove(&page);
+                }
+            }
+            page += 1 << TARGET_PAGE_BITS;
+        }
+    }
+
     /// Mark a TB as invalid, unlink all chained jumps, and
-    /// remove it from the hash chain.
+    /// remove it from the hash chain and the page lists.
     pub fn invalidate<B: HostCodeGen>(
         &self,
         tb_idx: usize,
@@ -119,17 +231,20 @@ impl TbStore {
         backend: &B,
     ) {
         let tb = self.get(tb_idx);
-        tb.invalid.store(true, Ordering::Release);
 
-        // 1. Unlink incoming edges.
+        // 1. Mark dead and unlink incoming edges. Done under the
+        //    jmp lock so a concurrent add_jump either sees Dead
+        //    or has already recorded its edge here.
         let jmp_list = {
             let mut jmp = tb.jmp.lock().unwrap();
+            tb.mark_dead();
             std::mem::take(&mut jmp.jmp_list)
         };
+        self.unlink_pages(tb, tb_idx);
         for (src, slot) in jmp_list {
-            Self::reset_jump(self.get(src), code_buf, backend, slot);
             let src_tb = self.get(src);
             let mut src_jmp = src_tb.jmp.lock().unwrap();
+            self.reset_jump(src, code_buf, backend, slot);
             src_jmp.jmp_dest[slot] = None;
         }
 
@@ -182,20 +297,182 @@ impl TbStore {
         }
     }
 
-    /// Reset a goto_tb jump back to its original target.
+    /// Invalidate every valid TB whose guest code overlaps
+    /// `[start, end)`. Returns the number of TBs invalidated.
+    pub fn invalidate_range<B: HostCodeGen>(
+        &self,
+        start: u64,
+        end: u64,
+        code_buf: &CodeBuffer,
+        backend: &B,
+    ) -> usize {
+        self.invalidate_ranges(&[(start, end)], code_buf, backend)
+    }
+
+    /// Invalidate every valid TB whose guest code overlaps one
+    /// of `ranges`, which must be sorted and disjoint. Only the
+    /// TBs listed on the pages the ranges touch are visited.
+    /// Returns the number of TBs invalidated.
+    pub fn invalidate_ranges<B: HostCodeGen>(
+        &self,
+        ranges: &[(u64, u64)],
+        code_buf: &CodeBuffer,
+        backend: &B,
+    ) -> usize {
+        let mut hits = Vec::new();
+        {
+            let pages = self.pages.lock().unwrap();
+            for &(start, end) in ranges.iter().filter(|&&(s, e)| s < e) {
+                let last = (end - 1) & TARGET_PAGE_MASK;
+                let first = start & TARGET_PAGE_MASK;
+                for (_, list) in pages.range(first..=last) {
+                    for &idx in list {
+                        let tb = self.get(idx);
+                        let tb_end = tb.pc + (tb.size as u64).max(1);
+                        if tb.pc < end && start < tb_end {
+                            hits.push(idx);
+                        }
+                    }
+                }
+            }
+        }
+        // A TB spanning two touched pages is listed twice.
+        let mut count = 0;
+        for idx in hits {
+            if !self.get(idx).is_invalid() {
+                self.invalidate(idx, code_buf, backend);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Reset every chained jump to its exit stub, leaving all
+    /// TBs valid, so a vCPU running through chained TBs returns
+    /// to the exec loop at its next TB boundary. Safe against
+    /// concurrent `add_jump`; chains added meanwhile survive.
+    /// Returns the number of jumps reset.
+    pub fn unlink_all<B: HostCodeGen>(
+        &self,
+        code_buf: &CodeBuffer,
+        backend: &B,
+    ) -> usize {
+        let mut count = 0;
+        for dst in 0..self.len() {
+            let jmp_list =
+                std::mem::take(&mut self.get(dst).jmp.lock().unwrap().jmp_list);
+            for (src, slot) in jmp_list {
+                let mut src_jmp = self.get(src).jmp.lock().unwrap();
+                if src_jmp.jmp_dest[slot] == Some(dst) {
+                    self.reset_jump(src, code_buf, backend, slot);
+                    src_jmp.jmp_dest[slot] = None;
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Patch `src`'s goto_tb `slot` to jump directly to `dst`.
+    ///
+    /// The target's liveness is checked and the incoming edge
+    /// recorded under its `jmp` lock, the same lock
+    /// `invalidate` takes, so a dead TB is never chained into.
+    /// Lock ordering: lower TB index first.
+    pub fn add_jump<B: HostCodeGen>(
+        &self,
+        src: usize,
+        slot: usize,
+        dst: usize,
+        code_buf: &CodeBuffer,
+        backend: &B,
+    ) -> JumpPatch {
+        let src_tb = self.get(src);
+        let Some(jmp_off) = src_tb.jmp_insn_offset[slot] else {
+            return JumpPatch::NoSlot;
+        };
+        let dst_tb = self.get(dst);
+
+        // A self-loop shares one lock; otherwise lock the lower
+        // index first so A->B and B->A chaining cannot deadlock.
+        let (mut src_jmp, mut dst_guard) = if src == dst {
+            (src_tb.jmp.lock().unwrap(), None)
+        } else if src < dst {
+            let s = src_tb.jmp.lock().unwrap();
+            (s, Some(dst_tb.jmp.lock().unwrap()))
+        } else {
+            let d = dst_tb.jmp.lock().unwrap();
+            (src_tb.jmp.lock().unwrap(), Some(d))
+        };
+        if src_jmp.jmp_dest[slot] == Some(dst) {
+            return JumpPatch::Already;
+        }
+        if !dst_tb.mark_chained() {
+            return JumpPatch::TargetDead;
+        }
+        self.patch_jump(src, jmp_off as usize, code_buf, || {
+            backend.patch_jump(code_buf, jmp_off as usize, dst_tb.host_offset)
+        });
+        src_jmp.jmp_dest[slot] = Some(dst);
+        match dst_guard.as_mut() {
+            Some(dst_jmp) => dst_jmp.jmp_list.push((src, slot)),
+            None => src_jmp.jmp_list.push((src, slot)),
+        }
+        JumpPatch::Patched
+    }
+
+    /// Reset `src`'s goto_tb jump back to its original target.
+    /// Called with `src`'s `jmp` lock held.
     fn reset_jump<B: HostCodeGen>(
-        tb: &TranslationBlock,
+        &self,
+        src: usize,
         code_buf: &CodeBuffer,
         backend: &B,
         slot: usize,
     ) {
+        let tb = self.get(src);
         if let (Some(jmp_off), Some(reset_off)) =
             (tb.jmp_insn_offset[slot], tb.jmp_reset_offset[slot])
         {
-            backend.patch_jump(code_buf, jmp_off as usize, reset_off as usize);
+            self.patch_jump(src, jmp_off as usize, code_buf, || {
+                backend.patch_jump(
+                    code_buf,
+                    jmp_off as usize,
+                    reset_off as usize,
+                )
+            });
         }
     }
 
+    /// Apply a jump patch to `src`'s code at `site`, through
+    /// the code verifier when one is installed.
+    #[cfg_attr(not(feature = "verify-code"), allow(unused_variables))]
+    fn patch_jump(
+        &self,
+        src: usize,
+        site: usize,
+        code_buf: &CodeBuffer,
+        patch: impl FnOnce(),
+    ) {
+        #[cfg(feature = "verify-code")]
+        if let Some(v) = &self.verifier {
+            v.patch(src, self.get(src), code_buf, site, patch);
+            return;
+        }
+        patch();
+    }
+
+    /// The code verifier, if installed.
+    #[cfg(feature = "verify-code")]
+    pub fn verifier(&self) -> Option<&CodeVerifier> {
+        self.verifier.as_ref()
+    }
+
+    #[cfg(feature = "verify-code")]
+    pub(crate) fn set_verifier(&mut self, verifier: CodeVerifier) {
+        self.verifier = Some(verifier);
+    }
+
     /// Flush all TBs and reset the hash table.
     ///
     /// # Safety
@@ -205,12 +482,27 @@ impl TbStore {
         tbs.clear();
         self.len.store(0, Ordering::Release);
         self.hash.lock().unwrap().fill(None);
+        self.pages.lock().unwrap().clear();
+        #[cfg(feature = "verify-code")]
+        if let Some(v) = &self.verifier {
+            v.clear();
+        }
+    }
+
+    /// No room for another TB until a flush.
+    pub fn is_full(&self) -> bool {
+        self.len() >= MAX_TBS
     }
 
     pub fn len(&self) -> usize {
         self.len.load(Ordering::Acquire)
     }
 
+    /// Hash table buckets holding at least one TB.
+    pub fn buckets_used(&self) -> usize {
+        self.hash.lock().unwrap().iter().flatten().count()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
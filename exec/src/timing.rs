@@ -0,0 +1,178 @@
+//! Wall-clock time split by exec-loop phase.
+//!
+//! When enabled, the exec loop reads `Instant::now()` twice per
+//! iteration, after the TB lookup and after the TB ran, and
+//! charges the time since the previous reading to the phase
+//! just finished. Translation times itself under
+//! `translate_lock` and is taken out of the lookup phase. Time
+//! between two calls of the loop, which linux-user spends
+//! handling system calls, is charged to `outside`. Disabled
+//! timing costs one `Option` check per reading point.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Timestamp reads used to estimate the cost of one read.
+const CALIBRATION_READS: u32 = 1000;
+
+/// Accumulated phase times, kept in `ExecStats::time`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimes {
+    /// Jump cache, hash table and chaining bookkeeping.
+    pub lookup: Duration,
+    /// Guest-to-host translation.
+    pub translate: Duration,
+    /// Generated code, including TBs reached through chains.
+    pub execute: Duration,
+    /// Between loop calls (system calls, exits).
+    pub outside: Duration,
+    /// Timestamp reads taken.
+    pub reads: u64,
+    /// Calibrated cost of one read.
+    pub read_cost: Duration,
+}
+
+impl PhaseTimes {
+    pub fn total(&self) -> Duration {
+        self.lookup + self.translate + self.execute + self.outside
+    }
+
+    /// Estimated time spent reading timestamps.
+    pub fn overhead(&self) -> Duration {
+        self.read_cost * self.reads.min(u32::MAX as u64) as u32
+    }
+
+    fn phases(&self) -> [(&'static str, Duration); 4] {
+        [
+            ("lookup", self.lookup),
+            ("translate", self.translate),
+            ("execute", self.execute),
+            ("outside", self.outside),
+        ]
+    }
+
+    /// The breakdown as a JSON object, times in nanoseconds.
+    pub fn to_json(&self) -> String {
+        let total = self.total();
+        let mut out = String::from("{");
+        for (name, d) in self.phases() {
+            out += &format!(
+                "\"{name}_ns\":{},\"{name}_pct\":{:.2},",
+                d.as_nanos(),
+                pct(d, total)
+            );
+        }
+        out += &format!(
+            "\"total_ns\":{},\"reads\":{},\"overhead_ns\":{}}}",
+            total.as_nanos(),
+            self.reads,
+            self.overhead().as_nanos()
+        );
+        out
+    }
+}
+
+impl fmt::Display for PhaseTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        for (name, d) in self.phases() {
+            writeln!(
+                f,
+                "  {:<12} {:>10.3} ms ({:.1}%)",
+                format!("{name}:"),
+                ms(d),
+                pct(d, total)
+            )?;
+        }
+        writeln!(f, "  total:       {:>10.3} ms", ms(total))?;
+        writeln!(
+            f,
+            "  overhead:    {:>10.3} ms ({:.1}%, {} reads)",
+            ms(self.overhead()),
+            pct(self.overhead(), total),
+            self.reads
+        )
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
+
+fn pct(d: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.0
+    } else {
+        d.as_secs_f64() / total.as_secs_f64() * 100.0
+    }
+}
+
+/// Exec-loop phase ending at a timestamp read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Lookup,
+    Execute,
+    Outside,
+}
+
+/// Per-vCPU timestamp state, in `PerCpuState::timer`.
+#[derive(Debug)]
+pub struct PhaseTimer {
+    last: Option<Instant>,
+    /// Translation time inside the current lookup window.
+    translate_pending: Duration,
+    read_cost: Duration,
+}
+
+impl PhaseTimer {
+    /// Create a timer, measuring the cost of a timestamp read.
+    pub fn new() -> Self {
+        let start = Instant::now();
+        let mut last = start;
+        for _ in 0..CALIBRATION_READS {
+            last = std::hint::black_box(Instant::now());
+        }
+        Self {
+            last: None,
+            translate_pending: Duration::ZERO,
+            read_cost: (last - start) / CALIBRATION_READS,
+        }
+    }
+
+    pub fn read_cost(&self) -> Duration {
+        self.read_cost
+    }
+
+    /// Charge the time since the previous read to `phase`. The
+    /// first read only starts the clock.
+    #[inline]
+    pub fn mark(&mut self, phase: Phase, times: &mut PhaseTimes) {
+        let now = Instant::now();
+        times.reads += 1;
+        times.read_cost = self.read_cost;
+        let Some(last) = self.last.replace(now) else {
+            return;
+        };
+        let d = now - last;
+        match phase {
+            Phase::Lookup => {
+                let pending = std::mem::take(&mut self.translate_pending);
+                times.lookup += d.saturating_sub(pending);
+            }
+            Phase::Execute => times.execute += d,
+            Phase::Outside => times.outside += d,
+        }
+    }
+
+    /// Record `d` spent translating since the last read.
+    pub fn add_translate(&mut self, d: Duration, times: &mut PhaseTimes) {
+        self.translate_pending += d;
+        times.translate += d;
+    }
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,207 @@
+//! Host code corruption checks (`verify-code` feature).
+//!
+//! Each TB's host code is checksummed when it is emitted and
+//! re-sealed after every legitimate jump patch. The exec loop
+//! re-checks the checksum before entering a TB (every Nth
+//! entry), so a stray write into the code buffer is reported
+//! at the TB it hit rather than as a wrong guest result much
+//! later. Chained execution bypasses the check until control
+//! returns to the exec loop.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_core::tb::TranslationBlock;
+
+/// Number of recent patches kept for corruption reports.
+const PATCH_RING_LEN: usize = 16;
+
+/// Bytes a jump patch may touch, starting at its site.
+const MAX_PATCH_LEN: usize = 8;
+
+/// Differing bytes listed in a corruption report.
+const MAX_REPORTED_DIFFS: usize = 16;
+
+/// CRC-32 (IEEE) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// A jump patch applied to a TB's host code.
+#[derive(Debug, Clone)]
+pub struct PatchRecord {
+    pub tb: usize,
+    /// Code buffer offset of the patched bytes.
+    pub site: usize,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// Checksums TB host code and checks it before execution.
+pub struct CodeVerifier {
+    every: u64,
+    /// Expected host code per TB, for corruption reports.
+    shadow: Mutex<HashMap<usize, Vec<u8>>>,
+    ring: Mutex<VecDeque<PatchRecord>>,
+}
+
+fn host_code<'a>(tb: &TranslationBlock, code: &'a CodeBuffer) -> &'a [u8] {
+    &code.as_slice()[tb.host_offset..tb.host_offset + tb.host_size]
+}
+
+impl CodeVerifier {
+    /// Check a TB on every `every`th entry from the exec loop.
+    pub fn new(every: u64) -> Self {
+        assert!(every > 0, "verification interval must be non-zero");
+        Self {
+            every,
+            shadow: Mutex::new(HashMap::new()),
+            ring: Mutex::new(VecDeque::with_capacity(PATCH_RING_LEN)),
+        }
+    }
+
+    pub fn every(&self) -> u64 {
+        self.every
+    }
+
+    /// Record the freshly emitted code of TB `idx`.
+    pub(crate) fn seal(
+        &self,
+        idx: usize,
+        tb: &TranslationBlock,
+        code: &CodeBuffer,
+    ) {
+        let bytes = host_code(tb, code);
+        tb.code_sum.store(crc32(bytes), Ordering::Release);
+        self.shadow.lock().unwrap().insert(idx, bytes.to_vec());
+    }
+
+    /// Apply `patch` to TB `idx` at code buffer offset `site`.
+    ///
+    /// The TB is checked first, and afterwards only bytes in
+    /// `site..site + MAX_PATCH_LEN` may have changed. Must be
+    /// called with the TB's `jmp` lock held.
+    pub(crate) fn patch(
+        &self,
+        idx: usize,
+        tb: &TranslationBlock,
+        code: &CodeBuffer,
+        site: usize,
+        patch: impl FnOnce(),
+    ) {
+        self.check(idx, tb, code);
+        let before = host_code(tb, code).to_vec();
+        patch();
+        let after = host_code(tb, code);
+
+        let rel = site - tb.host_offset;
+        let window = rel..(rel + MAX_PATCH_LEN).min(after.len());
+        let stray = before
+            .iter()
+            .zip(after)
+            .enumerate()
+            .any(|(i, (a, b))| a != b && !window.contains(&i));
+        if stray {
+            panic!(
+                "{}",
+                self.report(idx, tb, &before, after, "patch outside its site")
+            );
+        }
+
+        let mut ring = self.ring.lock().unwrap();
+        if ring.len() == PATCH_RING_LEN {
+            ring.pop_front();
+        }
+        ring.push_back(PatchRecord {
+            tb: idx,
+            site,
+            old: before[window.clone()].to_vec(),
+            new: after[window].to_vec(),
+        });
+        drop(ring);
+
+        tb.code_sum.store(crc32(after), Ordering::Release);
+        self.shadow.lock().unwrap().insert(idx, after.to_vec());
+    }
+
+    /// Panic if TB `idx`'s host code no longer matches its
+    /// checksum.
+    /// Forget all shadow copies, e.g. after a code cache flush.
+    pub(crate) fn clear(&self) {
+        self.shadow.lock().unwrap().clear();
+    }
+
+    pub fn verify(&self, idx: usize, tb: &TranslationBlock, code: &CodeBuffer) {
+        // Patches happen under the jmp lock; hold it so a
+        // concurrent chain does not look like corruption.
+        let _jmp = tb.jmp.lock().unwrap();
+        self.check(idx, tb, code);
+    }
+
+    fn check(&self, idx: usize, tb: &TranslationBlock, code: &CodeBuffer) {
+        let bytes = host_code(tb, code);
+        if crc32(bytes) == tb.code_sum.load(Ordering::Acquire) {
+            return;
+        }
+        let shadow = self.shadow.lock().unwrap();
+        let expected = shadow.get(&idx).map_or(&[][..], |v| v.as_slice());
+        panic!(
+            "{}",
+            self.report(idx, tb, expected, bytes, "checksum mismatch")
+        );
+    }
+
+    /// Most recent patches, oldest first.
+    pub fn recent_patches(&self) -> Vec<PatchRecord> {
+        self.ring.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn report(
+        &self,
+        idx: usize,
+        tb: &TranslationBlock,
+        expected: &[u8],
+        found: &[u8],
+        what: &str,
+    ) -> String {
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "code verify: TB #{idx} (pc {:#x}, host {:#x}..{:#x}): {what}",
+            tb.pc,
+            tb.host_offset,
+            tb.host_offset + tb.host_size,
+        );
+        let diffs = expected
+            .iter()
+            .zip(found)
+            .enumerate()
+            .filter(|(_, (e, f))| e != f);
+        for (i, (e, f)) in diffs.take(MAX_REPORTED_DIFFS) {
+            let _ = writeln!(
+                s,
+                "  +{i:#x} ({:#x}): expected {e:02x}, found {f:02x}",
+                tb.host_offset + i,
+            );
+        }
+        let _ = writeln!(s, "recent patches:");
+        for p in self.ring.lock().unwrap().iter() {
+            let _ = writeln!(
+                s,
+                "  TB #{} @ {:#x}: {:02x?} -> {:02x?}",
+                p.tb, p.site, p.old, p.new
+            );
+        }
+        s
+    }
+}
@@ -0,0 +1,163 @@
+//! Cross-run warmup hints.
+//!
+//! A short-lived guest translates the same hot TBs on every run.
+//! At exit the most-dispatched TBs (from block coverage) can be
+//! saved as `(pc, flags)` pairs; a later run of the same binary
+//! under the same CPU configuration translates them up front,
+//! under one `translate_lock` hold, before entering the loop.
+//! Only guest addresses are kept, never host code, so nothing
+//! needs relocating or trusting.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::coverage::Coverage;
+use crate::exec_loop::translate_tb;
+use crate::{
+    GuestCpu, PerCpuState, SharedState, TbStore, MIN_CODE_BUF_REMAINING,
+};
+use tcg_backend::HostCodeGen;
+
+const HEADER: &str = "# tcg-rs warmup v1";
+
+/// 64-bit FNV-1a, used to key hints to a binary and a
+/// configuration.
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in data {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h
+}
+
+/// Hot TBs of one run, keyed by what they were translated from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmupHints {
+    /// Hash of the guest binary.
+    pub binary: u64,
+    /// Hash of the CPU configuration (ISA extensions).
+    pub cfg: u64,
+    /// `(pc, flags)`, hottest first.
+    pub tbs: Vec<(u64, u32)>,
+}
+
+impl WarmupHints {
+    /// The `n` most-dispatched TBs recorded in `cov`.
+    pub fn from_coverage(
+        cov: &Coverage,
+        store: &TbStore,
+        binary: u64,
+        cfg: u64,
+        n: usize,
+    ) -> Self {
+        let mut seen = HashSet::new();
+        let tbs = cov
+            .hottest()
+            .into_iter()
+            .map(|(idx, _)| {
+                let tb = store.get(idx);
+                (tb.pc, tb.flags)
+            })
+            .filter(|key| seen.insert(*key))
+            .take(n)
+            .collect();
+        Self { binary, cfg, tbs }
+    }
+
+    /// Whether the hints were recorded for this binary and
+    /// configuration.
+    pub fn matches(&self, binary: u64, cfg: u64) -> bool {
+        self.binary == binary && self.cfg == cfg
+    }
+
+    /// Write the text form: a header, the two keys, then one
+    /// `pc flags` line per TB.
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "{HEADER}")?;
+        writeln!(w, "binary {:#018x}", self.binary)?;
+        writeln!(w, "cfg {:#018x}", self.cfg)?;
+        for &(pc, flags) in &self.tbs {
+            writeln!(w, "{pc:#x} {flags:#x}")?;
+        }
+        Ok(())
+    }
+
+    /// Parse the text form written by `write`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l) != Some(HEADER) {
+            return Err("not a tcg-rs warmup file".to_string());
+        }
+        let mut key = |name: &str| match lines.next() {
+            Some((i, l)) => l
+                .strip_prefix(name)
+                .and_then(|v| v.strip_prefix(' '))
+                .and_then(parse_hex)
+                .ok_or(format!("line {}: expected `{name} <hex>`", i + 1)),
+            None => Err(format!("missing `{name}` line")),
+        };
+        let binary = key("binary")?;
+        let cfg = key("cfg")?;
+        let mut tbs = Vec::new();
+        for (i, line) in lines {
+            let entry = line.split_once(' ').and_then(|(pc, flags)| {
+                Some((parse_hex(pc)?, u32::try_from(parse_hex(flags)?).ok()?))
+            });
+            tbs.push(entry.ok_or(format!("line {}: bad entry", i + 1))?);
+        }
+        Ok(Self { binary, cfg, tbs })
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+/// Translate each `(pc, flags)` of `tbs` not already cached,
+/// holding `translate_lock` throughout. Stops once `budget`
+/// bytes of host code have been emitted or the code buffer is
+/// nearly full, so a bad list cannot crowd out the run's own
+/// translations. Returns the number of TBs translated; they are
+/// counted in `warmup_tbs` and, when first dispatched, in
+/// `warmup_used`.
+///
+/// # Safety
+/// Every `pc` must be readable guest code of `cpu`.
+pub unsafe fn pretranslate<B, C>(
+    shared: &SharedState<B>,
+    per_cpu: &mut PerCpuState,
+    cpu: &mut C,
+    tbs: &[(u64, u32)],
+    budget: usize,
+) -> usize
+where
+    B: HostCodeGen,
+    C: GuestCpu,
+{
+    let mut guard = shared.translate_lock.lock().unwrap();
+    let start = shared.code_buf().offset();
+    let mut done = 0;
+    for &(pc, flags) in tbs {
+        if shared.code_buf().offset() - start >= budget
+            || shared.code_buf().remaining() < MIN_CODE_BUF_REMAINING
+            || shared.tb_store.is_full()
+        {
+            break;
+        }
+        if shared.tb_store.lookup(pc, flags).is_some() {
+            continue;
+        }
+        // A pc that fails to translate fails again when the run
+        // reaches it, which reports the error.
+        let Ok(idx) =
+            translate_tb(shared, &mut guard, per_cpu, cpu, pc, flags, 0)
+        else {
+            continue;
+        };
+        per_cpu.warm.insert(idx);
+        done += 1;
+    }
+    per_cpu.stats.warmup_tbs += done as u64;
+    done
+}
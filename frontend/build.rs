@@ -5,13 +5,28 @@ use std::path::Path;
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
 
+    // Patterns without a trans_ yet decode to the unimplemented()
+    // hook instead of breaking the build.
+    let trans = Path::new("src/riscv/trans.rs");
+    println!("cargo::rerun-if-changed={}", trans.display());
+    let trans_src = fs::read_to_string(trans).expect("failed to read trans.rs");
+
     // 32-bit decoder
     let decode32 = Path::new("src/riscv/insn32.decode");
     println!("cargo::rerun-if-changed={}", decode32.display());
     let input32 =
         fs::read_to_string(decode32).expect("failed to read insn32.decode");
     let mut out32 = Vec::new();
-    decode::generate(&input32, &mut out32)
+    let opts32 = decode::GenOptions {
+        width: 32,
+        coverage: true,
+        partial: Some(decode::implemented_trans(&trans_src, "Decode")),
+        source: decode32.display().to_string(),
+        source_table: true,
+        mixed_width: true,
+        ..decode::GenOptions::default()
+    };
+    decode::generate_with_options(&input32, &mut out32, &opts32)
         .expect("insn32 code generation failed");
     let path32 = Path::new(&out_dir).join("riscv32_decode.rs");
     fs::write(&path32, out32).expect("failed to write riscv32_decode.rs");
@@ -22,7 +37,18 @@ fn main() {
     let input16 =
         fs::read_to_string(decode16).expect("failed to read insn16.decode");
     let mut out16 = Vec::new();
-    decode::generate_with_width(&input16, &mut out16, 16)
+    let opts16 = decode::GenOptions {
+        width: 16,
+        coverage: true,
+        partial: Some(decode::implemented_trans(&trans_src, "Decode16")),
+        source: decode16.display().to_string(),
+        source_table: true,
+        mixed_width: false,
+        // Shares the 32-bit decoder's args structs.
+        extern_args: Some("super".to_string()),
+        ..decode::GenOptions::default()
+    };
+    decode::generate_with_options(&input16, &mut out16, &opts16)
         .expect("insn16 code generation failed");
     let path16 = Path::new(&out_dir).join("riscv16_decode.rs");
     fs::write(&path16, out16).expect("failed to write riscv16_decode.rs");
@@ -5,23 +5,13 @@
 
 pub mod riscv;
 
+use tcg_core::tb::{DisasJumpType, TranslationInfo, TB_FLAG_SINGLE_STEP};
 use tcg_core::Context;
 
 // ---------------------------------------------------------------
 // Generic translation framework
 // ---------------------------------------------------------------
 
-/// TB termination reason set by `translate_insn`.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DisasJumpType {
-    /// Continue to the next sequential instruction.
-    Next,
-    /// Reached the maximum number of instructions per TB.
-    TooMany,
-    /// Unconditional branch / exit — no fall-through.
-    NoReturn,
-}
-
 /// Base context shared by all guest architectures.
 ///
 /// Mirrors QEMU's `DisasContextBase`.
@@ -36,6 +26,51 @@ pub struct DisasContextBase {
     pub num_insns: u32,
     /// Maximum instructions allowed in one TB.
     pub max_insns: u32,
+    /// End of the guest page holding `pc_first` (exclusive).
+    /// The TB stops before an instruction at or across it;
+    /// `u64::MAX` when the code has no known bound.
+    pub pc_page_end: u64,
+    /// Stop after one instruction and exit without chaining,
+    /// with the pc synced (`TB_FLAG_SINGLE_STEP`).
+    pub single_step: bool,
+    /// Set by the frontend when the TB is a probable spin loop
+    /// (`TranslationInfo::spin_loop`).
+    pub spin_loop: bool,
+}
+
+impl DisasContextBase {
+    /// Apply the mode bits of the TB `flags` being translated.
+    pub fn set_tb_flags(&mut self, flags: u32) {
+        self.single_step = flags & TB_FLAG_SINGLE_STEP != 0;
+    }
+
+    /// Decide whether the TB ends after the instruction just
+    /// translated, marking `TooMany` when a limit is reached.
+    pub fn should_stop(&mut self) -> bool {
+        if self.is_jmp != DisasJumpType::Next {
+            return true;
+        }
+        if self.single_step
+            || self.num_insns >= self.max_insns
+            || self.pc_next >= self.pc_page_end
+        {
+            self.is_jmp = DisasJumpType::TooMany;
+            return true;
+        }
+        false
+    }
+
+    /// Summary of the TB translated so far.
+    pub fn translation_info(&self) -> TranslationInfo {
+        TranslationInfo {
+            guest_len_bytes: (self.pc_next - self.pc_first) as u32,
+            guest_insns: self.num_insns,
+            is_jmp: self.is_jmp,
+            first_pc: self.pc_first,
+            next_pc: self.pc_next,
+            spin_loop: self.spin_loop,
+        }
+    }
 }
 
 /// Per-architecture translation operations.
@@ -45,7 +80,8 @@ pub trait TranslatorOps {
     /// Architecture-specific disassembly context.
     type DisasContext;
 
-    /// One-time setup before the translation loop.
+    /// Setup before the translation loop: register the guest
+    /// globals, or bind the ones that survived `Context::reset()`.
     fn init_disas_context(ctx: &mut Self::DisasContext, ir: &mut Context);
 
     /// Called once at the start of the TB (after init).
@@ -78,23 +114,19 @@ pub trait TranslatorOps {
 pub fn translator_loop<T: TranslatorOps>(
     ctx: &mut T::DisasContext,
     ir: &mut Context,
-) {
+) -> TranslationInfo {
     T::init_disas_context(ctx, ir);
     T::tb_start(ctx, ir);
 
     loop {
         T::insn_start(ctx, ir);
         T::translate_insn(ctx, ir);
-
-        let base = T::base(ctx);
-        if base.is_jmp != DisasJumpType::Next {
-            break;
-        }
-        if base.num_insns >= base.max_insns {
-            T::base_mut(ctx).is_jmp = DisasJumpType::TooMany;
+        if T::base_mut(ctx).should_stop() {
             break;
         }
     }
 
+    ir.mark_tb_stop();
     T::tb_stop(ctx, ir);
+    T::base(ctx).translation_info()
 }
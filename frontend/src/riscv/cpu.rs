@@ -1,5 +1,9 @@
 //! RISC-V CPU state for user-mode emulation.
 
+use std::time::Instant;
+
+use tcg_core::helper;
+
 /// Number of general-purpose registers (x0-x31).
 pub const NUM_GPRS: usize = 32;
 /// Number of floating-point registers (f0-f31).
@@ -9,6 +13,7 @@ pub const NUM_FPRS: usize = 32;
 ///
 /// Layout must be `#[repr(C)]` so that TCG global temps can
 /// reference fields at fixed offsets from the env pointer.
+#[derive(Clone)]
 #[repr(C)]
 pub struct RiscvCpu {
     /// General-purpose registers x0-x31.
@@ -46,8 +51,24 @@ pub struct RiscvCpu {
     pub utval: u64,
     /// User interrupt pending (uip).
     pub uip: u64,
+    /// Guest instructions retired, including every instruction
+    /// of the TB currently executing (bumped on TB entry).
+    pub cycle: u64,
+    /// Value of the `time` CSR at its last read.
+    pub time: u64,
+    /// Store that hit the MMIO window: guest address, value and
+    /// `MemOp` bits, valid after an [`EXIT_MMIO_STORE`] exit.
+    pub mmio_addr: u64,
+    pub mmio_val: u64,
+    pub mmio_op: u64,
+    /// Source of the `time` CSR, read by `helper_rdtime`.
+    pub clock: GuestClock,
 }
 
+/// `TbExit::Custom` code of a store to the MMIO window. The
+/// store has not been made; `pc` is past the instruction.
+pub const EXIT_MMIO_STORE: u32 = 0;
+
 // Field offsets (bytes) from the start of RiscvCpu.
 // Used by `Context::new_global()` to bind IR temps.
 
@@ -93,6 +114,16 @@ pub const UCAUSE_OFFSET: i64 = UEPC_OFFSET + 8; // 600
 pub const UTVAL_OFFSET: i64 = UCAUSE_OFFSET + 8; // 608
 /// Byte offset of `uip`.
 pub const UIP_OFFSET: i64 = UTVAL_OFFSET + 8; // 616
+/// Byte offset of `cycle`.
+pub const CYCLE_OFFSET: i64 = UIP_OFFSET + 8; // 624
+/// Byte offset of `time`.
+pub const TIME_OFFSET: i64 = CYCLE_OFFSET + 8; // 632
+/// Byte offset of `mmio_addr`.
+pub const MMIO_ADDR_OFFSET: i64 = TIME_OFFSET + 8; // 640
+/// Byte offset of `mmio_val`.
+pub const MMIO_VAL_OFFSET: i64 = MMIO_ADDR_OFFSET + 8; // 648
+/// Byte offset of `mmio_op`.
+pub const MMIO_OP_OFFSET: i64 = MMIO_VAL_OFFSET + 8; // 656
 
 /// USTATUS FS bits mask.
 pub const USTATUS_FS_MASK: u64 = 0x0000_6000;
@@ -118,8 +149,96 @@ impl RiscvCpu {
             ucause: 0,
             utval: 0,
             uip: 0,
+            cycle: 0,
+            time: 0,
+            mmio_addr: 0,
+            mmio_val: 0,
+            mmio_op: 0,
+            clock: GuestClock::default(),
         }
     }
+
+    /// Refresh the `time` CSR from `clock`. Never moves
+    /// backwards.
+    pub fn update_time(&mut self) {
+        self.time = self.time.max(self.clock.ticks(self.cycle));
+    }
+}
+
+/// `rdtime`: the refreshed `time` CSR. Only TBs reading the
+/// CSR call it, so the host clock is not read on other exits.
+#[no_mangle]
+pub(crate) extern "C" fn helper_rdtime(env: *mut RiscvCpu) -> u64 {
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        env.update_time();
+        env.time
+    })
+}
+
+/// Default `time` CSR frequency, matching QEMU's virt machine.
+pub const DEFAULT_TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// Virtual instruction rate used for deterministic time.
+const DETERMINISTIC_IPS: u128 = 1_000_000_000;
+
+/// Source of the `time` CSR.
+///
+/// In real-time mode ticks follow host wall-clock time since
+/// the clock was created. In deterministic mode they are
+/// derived from retired instructions at one instruction per
+/// nanosecond, so runs are exactly reproducible.
+#[derive(Debug, Clone, Copy)]
+pub struct GuestClock {
+    freq: u64,
+    deterministic: bool,
+    start: Instant,
+    /// Real-time ticks before `start`.
+    offset: u64,
+}
+
+impl GuestClock {
+    pub fn new(freq: u64, deterministic: bool) -> Self {
+        Self {
+            freq,
+            deterministic,
+            start: Instant::now(),
+            offset: 0,
+        }
+    }
+
+    /// Continue a real-time clock that stood at `ticks`, as
+    /// when resuming a saved run.
+    pub fn resumed_at(mut self, ticks: u64) -> Self {
+        self.offset = ticks;
+        self
+    }
+
+    /// Timebase frequency in Hz.
+    pub fn freq(&self) -> u64 {
+        self.freq
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Current tick count given `insns` retired instructions.
+    pub fn ticks(&self, insns: u64) -> u64 {
+        let ticks = if self.deterministic {
+            insns as u128 * self.freq as u128 / DETERMINISTIC_IPS
+        } else {
+            let ns = self.start.elapsed().as_nanos();
+            self.offset as u128 + ns * self.freq as u128 / 1_000_000_000
+        };
+        ticks as u64
+    }
+}
+
+impl Default for GuestClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEBASE_FREQ, false)
+    }
 }
 
 impl Default for RiscvCpu {
@@ -0,0 +1,216 @@
+//! RISC-V user-level CSRs — read/write IR generation.
+//!
+//! The `csrr*` handlers in `trans.rs` go through these; a CSR
+//! missing here makes the instruction illegal (`EXCP_UNDEF`).
+
+use super::cpu::{
+    helper_rdtime, CYCLE_OFFSET, FFLAGS_OFFSET, FRM_OFFSET, UCAUSE_OFFSET,
+    UEPC_OFFSET, UIE_OFFSET, UIP_OFFSET, USCRATCH_OFFSET, USTATUS_OFFSET,
+    UTVAL_OFFSET, UTVEC_OFFSET,
+};
+use super::fpu;
+use super::RiscvDisasContext;
+use tcg_core::context::Context;
+use tcg_core::tb::DisasJumpType;
+use tcg_core::types::Type;
+use tcg_core::TempIdx;
+
+// CSR numbers (user-level).
+const CSR_USTATUS: i64 = 0x000;
+const CSR_FFLAGS: i64 = 0x001;
+const CSR_FRM: i64 = 0x002;
+const CSR_FCSR: i64 = 0x003;
+const CSR_UIE: i64 = 0x004;
+const CSR_UTVEC: i64 = 0x005;
+const CSR_USCRATCH: i64 = 0x040;
+const CSR_UEPC: i64 = 0x041;
+const CSR_UCAUSE: i64 = 0x042;
+const CSR_UTVAL: i64 = 0x043;
+const CSR_UIP: i64 = 0x044;
+const CSR_CYCLE: i64 = 0xC00;
+const CSR_TIME: i64 = 0xC01;
+const CSR_INSTRET: i64 = 0xC02;
+
+impl RiscvDisasContext {
+    /// Value of `csr`, or `None` if the frontend lacks it.
+    pub(super) fn gen_csr_read(
+        &self,
+        ir: &mut Context,
+        csr: i64,
+    ) -> Option<TempIdx> {
+        match csr {
+            CSR_FFLAGS => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, FFLAGS_OFFSET);
+                let mask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
+                let out = ir.new_temp(Type::I64);
+                ir.gen_and(Type::I64, out, v, mask);
+                Some(out)
+            }
+            CSR_FRM => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, FRM_OFFSET);
+                let mask = ir.new_const(Type::I64, fpu::FRM_MASK);
+                let out = ir.new_temp(Type::I64);
+                ir.gen_and(Type::I64, out, v, mask);
+                Some(out)
+            }
+            CSR_FCSR => {
+                let fflags = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, fflags, self.env, FFLAGS_OFFSET);
+                let fmask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
+                ir.gen_and(Type::I64, fflags, fflags, fmask);
+                let frm = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, frm, self.env, FRM_OFFSET);
+                let rmask = ir.new_const(Type::I64, fpu::FRM_MASK);
+                ir.gen_and(Type::I64, frm, frm, rmask);
+                let shift = ir.new_const(Type::I64, 5);
+                let frm_shift = ir.new_temp(Type::I64);
+                ir.gen_shl(Type::I64, frm_shift, frm, shift);
+                let out = ir.new_temp(Type::I64);
+                ir.gen_or(Type::I64, out, fflags, frm_shift);
+                Some(out)
+            }
+            CSR_USTATUS => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, USTATUS_OFFSET);
+                Some(v)
+            }
+            CSR_UIE => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UIE_OFFSET);
+                Some(v)
+            }
+            CSR_UTVEC => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UTVEC_OFFSET);
+                Some(v)
+            }
+            CSR_USCRATCH => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, USCRATCH_OFFSET);
+                Some(v)
+            }
+            CSR_UEPC => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UEPC_OFFSET);
+                Some(v)
+            }
+            CSR_UCAUSE => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UCAUSE_OFFSET);
+                Some(v)
+            }
+            CSR_UTVAL => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UTVAL_OFFSET);
+                Some(v)
+            }
+            CSR_UIP => {
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, UIP_OFFSET);
+                Some(v)
+            }
+            CSR_CYCLE | CSR_INSTRET => {
+                // Counter reads end the TB, so `cycle` holds every
+                // instruction up to and including this one.
+                let v = ir.new_temp(Type::I64);
+                ir.gen_ld(Type::I64, v, self.env, CYCLE_OFFSET);
+                let one = ir.new_const(Type::I64, 1);
+                ir.gen_sub(Type::I64, v, v, one);
+                Some(v)
+            }
+            CSR_TIME => {
+                let f = helper_rdtime as *const () as usize;
+                Some(self.gen_helper_call(ir, f, &[self.env]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Counter CSR reads end the TB so the TB-entry count is
+    /// exact: `cycle`/`instret` read it, and deterministic
+    /// `time` is derived from it.
+    pub(super) fn gen_csr_end_tb(&mut self, csr: i64) {
+        if self.base.is_jmp == DisasJumpType::Next
+            && matches!(csr, CSR_CYCLE | CSR_INSTRET | CSR_TIME)
+        {
+            self.base.is_jmp = DisasJumpType::TooMany;
+        }
+    }
+
+    /// Store `val` to `csr`; false if it is read-only or
+    /// unknown.
+    pub(super) fn gen_csr_write(
+        &self,
+        ir: &mut Context,
+        csr: i64,
+        val: TempIdx,
+    ) -> bool {
+        match csr {
+            CSR_FFLAGS => {
+                let mask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
+                let v = ir.new_temp(Type::I64);
+                ir.gen_and(Type::I64, v, val, mask);
+                ir.gen_st(Type::I64, v, self.env, FFLAGS_OFFSET);
+                self.gen_set_fs_dirty(ir);
+                true
+            }
+            CSR_FRM => {
+                let mask = ir.new_const(Type::I64, fpu::FRM_MASK);
+                let v = ir.new_temp(Type::I64);
+                ir.gen_and(Type::I64, v, val, mask);
+                ir.gen_st(Type::I64, v, self.env, FRM_OFFSET);
+                self.gen_set_fs_dirty(ir);
+                true
+            }
+            CSR_FCSR => {
+                let fmask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
+                let fflags = ir.new_temp(Type::I64);
+                ir.gen_and(Type::I64, fflags, val, fmask);
+                ir.gen_st(Type::I64, fflags, self.env, FFLAGS_OFFSET);
+                let shift = ir.new_const(Type::I64, 5);
+                let frm = ir.new_temp(Type::I64);
+                ir.gen_shr(Type::I64, frm, val, shift);
+                let rmask = ir.new_const(Type::I64, fpu::FRM_MASK);
+                ir.gen_and(Type::I64, frm, frm, rmask);
+                ir.gen_st(Type::I64, frm, self.env, FRM_OFFSET);
+                self.gen_set_fs_dirty(ir);
+                true
+            }
+            CSR_USTATUS => {
+                ir.gen_st(Type::I64, val, self.env, USTATUS_OFFSET);
+                true
+            }
+            CSR_UIE => {
+                ir.gen_st(Type::I64, val, self.env, UIE_OFFSET);
+                true
+            }
+            CSR_UTVEC => {
+                ir.gen_st(Type::I64, val, self.env, UTVEC_OFFSET);
+                true
+            }
+            CSR_USCRATCH => {
+                ir.gen_st(Type::I64, val, self.env, USCRATCH_OFFSET);
+                true
+            }
+            CSR_UEPC => {
+                ir.gen_st(Type::I64, val, self.env, UEPC_OFFSET);
+                true
+            }
+            CSR_UCAUSE => {
+                ir.gen_st(Type::I64, val, self.env, UCAUSE_OFFSET);
+                true
+            }
+            CSR_UTVAL => {
+                ir.gen_st(Type::I64, val, self.env, UTVAL_OFFSET);
+                true
+            }
+            CSR_UIP => {
+                ir.gen_st(Type::I64, val, self.env, UIP_OFFSET);
+                true
+            }
+            _ => false,
+        }
+    }
+}
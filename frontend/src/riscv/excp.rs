@@ -0,0 +1,31 @@
+//! RISC-V exception codes, in the frontend range of
+//! `tcg_core::excp`.
+
+use tcg_core::excp::{ExcpRegistry, EXCP_FRONTEND_BASE};
+
+pub const EXCP_ECALL: u32 = EXCP_FRONTEND_BASE;
+pub const EXCP_EBREAK: u32 = EXCP_FRONTEND_BASE + 1;
+pub const EXCP_UNDEF: u32 = EXCP_FRONTEND_BASE + 2;
+/// Misaligned load or store; the address is in `utval`.
+pub const EXCP_LOAD_MISALIGNED: u32 = EXCP_FRONTEND_BASE + 3;
+pub const EXCP_STORE_MISALIGNED: u32 = EXCP_FRONTEND_BASE + 4;
+/// Jump or taken branch to a target that is not a multiple of
+/// the instruction alignment; the target is in `utval`.
+pub const EXCP_FETCH_MISALIGNED: u32 = EXCP_FRONTEND_BASE + 5;
+
+/// Name the RISC-V codes in `reg`.
+pub fn register(reg: &mut ExcpRegistry) {
+    reg.register(EXCP_ECALL, "ecall");
+    reg.register(EXCP_EBREAK, "ebreak");
+    reg.register(EXCP_UNDEF, "illegal instruction");
+    reg.register(EXCP_LOAD_MISALIGNED, "load misaligned");
+    reg.register(EXCP_STORE_MISALIGNED, "store misaligned");
+    reg.register(EXCP_FETCH_MISALIGNED, "fetch misaligned");
+}
+
+/// A registry holding the RISC-V codes.
+pub fn registry() -> ExcpRegistry {
+    let mut reg = ExcpRegistry::new();
+    register(&mut reg);
+    reg
+}
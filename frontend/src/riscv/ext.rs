@@ -47,6 +47,11 @@ impl MisaExt {
     pub const fn union(self, other: Self) -> Self {
         Self(self.0 | other.0)
     }
+
+    #[inline]
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
 }
 
 // ── Extension configuration ──────────────────────────────────────
@@ -60,18 +65,30 @@ impl MisaExt {
 pub struct RiscvCfg {
     pub misa: MisaExt,
     // Z-extensions (user-mode relevant)
+    pub ext_zicbom: bool,
+    pub ext_zicbop: bool,
+    pub ext_zicboz: bool,
     pub ext_zicsr: bool,
     pub ext_zifencei: bool,
+    pub ext_zihintntl: bool,
     pub ext_zba: bool,
     pub ext_zbb: bool,
     pub ext_zbc: bool,
     pub ext_zbs: bool,
+    /// Misaligned plain loads and stores raise an
+    /// address-misaligned exception instead of completing.
+    /// Atomics must always be aligned.
+    pub strict_align: bool,
+    /// Bytes `cbo.zero` clears, a power of two from 8 to 512;
+    /// 64 is what Linux reports on common hardware.
+    pub cbo_block_size: u32,
 }
 
 // ── Predefined profiles ──────────────────────────────────────────
 
 impl RiscvCfg {
-    /// RV64GC = RV64IMAFDC + Zicsr + Zifencei.
+    /// RV64GC = RV64IMAFDC + Zicsr + Zifencei, plus the cache
+    /// block and NTL hints, which are safe as no-ops.
     pub const RV64IMAFDC: Self = Self {
         misa: MisaExt::from_bits_truncate(
             MisaExt::I.0
@@ -81,15 +98,90 @@ impl RiscvCfg {
                 | MisaExt::D.0
                 | MisaExt::C.0,
         ),
+        ext_zicbom: true,
+        ext_zicbop: true,
+        ext_zicboz: true,
         ext_zicsr: true,
         ext_zifencei: true,
+        ext_zihintntl: true,
         ext_zba: false,
         ext_zbb: false,
         ext_zbc: false,
         ext_zbs: false,
+        strict_align: false,
+        cbo_block_size: 64,
     };
 }
 
+impl RiscvCfg {
+    /// ISA string in canonical order, e.g.
+    /// `rv64imafdc_zicsr_zifencei_zba`.
+    pub fn isa_string(&self) -> String {
+        let mut s = String::from("rv64");
+        for c in "IMAFDC".bytes() {
+            if self.misa.bits() & (1 << (c - b'A')) != 0 {
+                s.push(c.to_ascii_lowercase() as char);
+            }
+        }
+        let zext = [
+            ("zicbom", self.ext_zicbom),
+            ("zicbop", self.ext_zicbop),
+            ("zicboz", self.ext_zicboz),
+            ("zicsr", self.ext_zicsr),
+            ("zifencei", self.ext_zifencei),
+            ("zihintntl", self.ext_zihintntl),
+            ("zba", self.ext_zba),
+            ("zbb", self.ext_zbb),
+            ("zbc", self.ext_zbc),
+            ("zbs", self.ext_zbs),
+        ];
+        for (name, on) in zext {
+            if on {
+                s.push('_');
+                s.push_str(name);
+            }
+        }
+        s
+    }
+
+    /// Turn extension `name` on or off: a MISA letter other than
+    /// `i` (`m`, `c`, ...) or a Z-extension as spelled in the ISA
+    /// string (`zicsr`, `zbb`, ...).  Returns false for any other
+    /// name.
+    pub fn set_ext(&mut self, name: &str, on: bool) -> bool {
+        let letter = match name {
+            "m" => MisaExt::M,
+            "a" => MisaExt::A,
+            "f" => MisaExt::F,
+            "d" => MisaExt::D,
+            "c" => MisaExt::C,
+            _ => {
+                let flag = match name {
+                    "zicbom" => &mut self.ext_zicbom,
+                    "zicbop" => &mut self.ext_zicbop,
+                    "zicboz" => &mut self.ext_zicboz,
+                    "zicsr" => &mut self.ext_zicsr,
+                    "zifencei" => &mut self.ext_zifencei,
+                    "zihintntl" => &mut self.ext_zihintntl,
+                    "zba" => &mut self.ext_zba,
+                    "zbb" => &mut self.ext_zbb,
+                    "zbc" => &mut self.ext_zbc,
+                    "zbs" => &mut self.ext_zbs,
+                    _ => return false,
+                };
+                *flag = on;
+                return true;
+            }
+        };
+        self.misa = if on {
+            self.misa.union(letter)
+        } else {
+            self.misa.difference(letter)
+        };
+        true
+    }
+}
+
 impl Default for RiscvCfg {
     fn default() -> Self {
         Self::RV64IMAFDC
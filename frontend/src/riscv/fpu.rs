@@ -1,5 +1,7 @@
 use super::cpu::RiscvCpu;
+use std::hint::black_box;
 use std::os::raw::c_int;
+use tcg_core::helper;
 
 #[link(name = "m")]
 extern "C" {
@@ -40,7 +42,34 @@ const FE_UPWARD: c_int = 0x0800;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 const FE_TOWARDZERO: c_int = 0x0c00;
 
-#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+// AArch64 FPSR cumulative bits and FPCR.RMode, as in glibc.
+#[cfg(target_arch = "aarch64")]
+const FE_INVALID: c_int = 0x01;
+#[cfg(target_arch = "aarch64")]
+const FE_DIVBYZERO: c_int = 0x02;
+#[cfg(target_arch = "aarch64")]
+const FE_OVERFLOW: c_int = 0x04;
+#[cfg(target_arch = "aarch64")]
+const FE_UNDERFLOW: c_int = 0x08;
+#[cfg(target_arch = "aarch64")]
+const FE_INEXACT: c_int = 0x10;
+#[cfg(target_arch = "aarch64")]
+const FE_ALL_EXCEPT: c_int = 0x1f;
+
+#[cfg(target_arch = "aarch64")]
+const FE_TONEAREST: c_int = 0x00_0000;
+#[cfg(target_arch = "aarch64")]
+const FE_UPWARD: c_int = 0x40_0000;
+#[cfg(target_arch = "aarch64")]
+const FE_DOWNWARD: c_int = 0x80_0000;
+#[cfg(target_arch = "aarch64")]
+const FE_TOWARDZERO: c_int = 0xc0_0000;
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "aarch64"
+)))]
 compile_error!("fenv constants need porting for this host architecture");
 
 pub const FFLAGS_NV: u64 = 1 << 4;
@@ -120,7 +149,11 @@ where
         feclearexcept(FE_ALL_EXCEPT);
         fesetround(map_rm(env, rm));
     }
-    let res = f();
+    // Rust has no FENV_ACCESS: without these barriers the
+    // operation may be hoisted above fesetround() or sunk below
+    // fetestexcept(), losing both rounding mode and flags.
+    let f = black_box(f);
+    let res = black_box(f());
     let raised = unsafe { fetestexcept(FE_ALL_EXCEPT) };
     unsafe {
         fesetround(old_rm);
@@ -142,6 +175,24 @@ fn canonical_nan_f64() -> u64 {
     0x7ff8_0000_0000_0000u64
 }
 
+/// NaN-box an f32 result, replacing any NaN with the canonical
+/// NaN (the host returns its own default NaN or the input's).
+fn f32_result(res: f32) -> u64 {
+    if res.is_nan() {
+        nanbox_f32(canonical_nan_f32())
+    } else {
+        nanbox_f32(res.to_bits())
+    }
+}
+
+fn f64_result(res: f64) -> u64 {
+    if res.is_nan() {
+        canonical_nan_f64()
+    } else {
+        res.to_bits()
+    }
+}
+
 fn read_f32_bits(_env: &mut RiscvCpu, raw: u64) -> u32 {
     if (raw >> 32) as u32 != 0xffff_ffff {
         return canonical_nan_f32();
@@ -364,11 +415,13 @@ pub extern "C" fn helper_fadd_s(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let res = with_fenv(env, rm, || af + bf);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let res = with_fenv(env, rm, || af + bf);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -378,11 +431,13 @@ pub extern "C" fn helper_fsub_s(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let res = with_fenv(env, rm, || af - bf);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let res = with_fenv(env, rm, || af - bf);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -392,11 +447,13 @@ pub extern "C" fn helper_fmul_s(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let res = with_fenv(env, rm, || af * bf);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let res = with_fenv(env, rm, || af * bf);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -406,19 +463,23 @@ pub extern "C" fn helper_fdiv_s(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let res = with_fenv(env, rm, || af / bf);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let res = with_fenv(env, rm, || af / bf);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsqrt_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let res = with_fenv(env, rm, || unsafe { sqrtf(af) });
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let res = with_fenv(env, rm, || unsafe { sqrtf(af) });
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -429,12 +490,14 @@ pub extern "C" fn helper_fmadd_s(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let cf = read_f32(env, c);
-    let res = with_fenv(env, rm, || unsafe { fmaf(af, bf, cf) });
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let cf = read_f32(env, c);
+        let res = with_fenv(env, rm, || unsafe { fmaf(af, bf, cf) });
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -445,12 +508,14 @@ pub extern "C" fn helper_fmsub_s(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let cf = read_f32(env, c);
-    let res = with_fenv(env, rm, || unsafe { fmaf(af, bf, -cf) });
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let cf = read_f32(env, c);
+        let res = with_fenv(env, rm, || unsafe { fmaf(af, bf, -cf) });
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -461,12 +526,14 @@ pub extern "C" fn helper_fnmsub_s(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let cf = read_f32(env, c);
-    let res = with_fenv(env, rm, || unsafe { fmaf(-af, bf, cf) });
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let cf = read_f32(env, c);
+        let res = with_fenv(env, rm, || unsafe { fmaf(-af, bf, cf) });
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -477,159 +544,195 @@ pub extern "C" fn helper_fnmadd_s(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    let bf = read_f32(env, b);
-    let cf = read_f32(env, c);
-    let res = with_fenv(env, rm, || unsafe { fmaf(-af, bf, -cf) });
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let bf = read_f32(env, b);
+        let cf = read_f32(env, c);
+        let res = with_fenv(env, rm, || unsafe { fmaf(-af, bf, -cf) });
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnj_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    let sign = bb & 0x8000_0000;
-    let res = (ab & 0x7fff_ffff) | sign;
-    nanbox_f32(res)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        let sign = bb & 0x8000_0000;
+        let res = (ab & 0x7fff_ffff) | sign;
+        nanbox_f32(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnjn_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    let sign = (!bb) & 0x8000_0000;
-    let res = (ab & 0x7fff_ffff) | sign;
-    nanbox_f32(res)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        let sign = (!bb) & 0x8000_0000;
+        let res = (ab & 0x7fff_ffff) | sign;
+        nanbox_f32(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnjx_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    let sign = (ab ^ bb) & 0x8000_0000;
-    let res = (ab & 0x7fff_ffff) | sign;
-    nanbox_f32(res)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        let sign = (ab ^ bb) & 0x8000_0000;
+        let res = (ab & 0x7fff_ffff) | sign;
+        nanbox_f32(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fmin_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    nanbox_f32(fmin_f32(env, ab, bb))
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        nanbox_f32(fmin_f32(env, ab, bb))
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fmax_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    nanbox_f32(fmax_f32(env, ab, bb))
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        nanbox_f32(fmax_f32(env, ab, bb))
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_feq_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    if is_snan_f32(ab) || is_snan_f32(bb) {
-        set_invalid(env);
-    }
-    if is_nan_f32(ab) || is_nan_f32(bb) {
-        return 0;
-    }
-    (f32::from_bits(ab) == f32::from_bits(bb)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        if is_snan_f32(ab) || is_snan_f32(bb) {
+            set_invalid(env);
+        }
+        if is_nan_f32(ab) || is_nan_f32(bb) {
+            return 0;
+        }
+        (f32::from_bits(ab) == f32::from_bits(bb)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_flt_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    if is_nan_f32(ab) || is_nan_f32(bb) {
-        set_invalid(env);
-        return 0;
-    }
-    (f32::from_bits(ab) < f32::from_bits(bb)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        if is_nan_f32(ab) || is_nan_f32(bb) {
+            set_invalid(env);
+            return 0;
+        }
+        (f32::from_bits(ab) < f32::from_bits(bb)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fle_s(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    let bb = read_f32_bits(env, b);
-    if is_nan_f32(ab) || is_nan_f32(bb) {
-        set_invalid(env);
-        return 0;
-    }
-    (f32::from_bits(ab) <= f32::from_bits(bb)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        let bb = read_f32_bits(env, b);
+        if is_nan_f32(ab) || is_nan_f32(bb) {
+            set_invalid(env);
+            return 0;
+        }
+        (f32::from_bits(ab) <= f32::from_bits(bb)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fclass_s(env: *mut RiscvCpu, a: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let ab = read_f32_bits(env, a);
-    fclass_f32(ab)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let ab = read_f32_bits(env, a);
+        fclass_f32(ab)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_w_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    fcvt_i32(env, af as f64, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        fcvt_i32(env, af as f64, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_wu_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    fcvt_u32(env, af as f64, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        fcvt_u32(env, af as f64, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_l_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    fcvt_i64(env, af as f64, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        fcvt_i64(env, af as f64, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_lu_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a);
-    fcvt_u64(env, af as f64, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        fcvt_u64(env, af as f64, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_s_w(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as i32 as f32);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as i32 as f32);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_s_wu(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as u32 as f32);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as u32 as f32);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_s_l(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as i64 as f32);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as i64 as f32);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_s_lu(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as f32);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as f32);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
@@ -639,11 +742,13 @@ pub extern "C" fn helper_fadd_d(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let res = with_fenv(env, rm, || af + bf);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let res = with_fenv(env, rm, || af + bf);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -653,11 +758,13 @@ pub extern "C" fn helper_fsub_d(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let res = with_fenv(env, rm, || af - bf);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let res = with_fenv(env, rm, || af - bf);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -667,11 +774,13 @@ pub extern "C" fn helper_fmul_d(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let res = with_fenv(env, rm, || af * bf);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let res = with_fenv(env, rm, || af * bf);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -681,19 +790,23 @@ pub extern "C" fn helper_fdiv_d(
     b: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let res = with_fenv(env, rm, || af / bf);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let res = with_fenv(env, rm, || af / bf);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsqrt_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let res = with_fenv(env, rm, || unsafe { sqrt(af) });
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let res = with_fenv(env, rm, || unsafe { sqrt(af) });
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -704,12 +817,14 @@ pub extern "C" fn helper_fmadd_d(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let cf = read_f64(c);
-    let res = with_fenv(env, rm, || unsafe { fma(af, bf, cf) });
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let cf = read_f64(c);
+        let res = with_fenv(env, rm, || unsafe { fma(af, bf, cf) });
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -720,12 +835,14 @@ pub extern "C" fn helper_fmsub_d(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let cf = read_f64(c);
-    let res = with_fenv(env, rm, || unsafe { fma(af, bf, -cf) });
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let cf = read_f64(c);
+        let res = with_fenv(env, rm, || unsafe { fma(af, bf, -cf) });
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -736,12 +853,14 @@ pub extern "C" fn helper_fnmsub_d(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let cf = read_f64(c);
-    let res = with_fenv(env, rm, || unsafe { fma(-af, bf, cf) });
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let cf = read_f64(c);
+        let res = with_fenv(env, rm, || unsafe { fma(-af, bf, cf) });
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
@@ -752,152 +871,192 @@ pub extern "C" fn helper_fnmadd_d(
     c: u64,
     rm: u64,
 ) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let bf = read_f64(b);
-    let cf = read_f64(c);
-    let res = with_fenv(env, rm, || unsafe { fma(-af, bf, -cf) });
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let bf = read_f64(b);
+        let cf = read_f64(c);
+        let res = with_fenv(env, rm, || unsafe { fma(-af, bf, -cf) });
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnj_d(_env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let sign = b & (1u64 << 63);
-    (a & !(1u64 << 63)) | sign
+    helper::guard(|| {
+        let sign = b & (1u64 << 63);
+        (a & !(1u64 << 63)) | sign
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnjn_d(_env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let sign = (!b) & (1u64 << 63);
-    (a & !(1u64 << 63)) | sign
+    helper::guard(|| {
+        let sign = (!b) & (1u64 << 63);
+        (a & !(1u64 << 63)) | sign
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fsgnjx_d(_env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let sign = (a ^ b) & (1u64 << 63);
-    (a & !(1u64 << 63)) | sign
+    helper::guard(|| {
+        let sign = (a ^ b) & (1u64 << 63);
+        (a & !(1u64 << 63)) | sign
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fmin_d(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    fmin_f64(env, a, b)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        fmin_f64(env, a, b)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fmax_d(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    fmax_f64(env, a, b)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        fmax_f64(env, a, b)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_feq_d(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    if is_snan_f64(a) || is_snan_f64(b) {
-        set_invalid(env);
-    }
-    if is_nan_f64(a) || is_nan_f64(b) {
-        return 0;
-    }
-    (f64::from_bits(a) == f64::from_bits(b)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        if is_snan_f64(a) || is_snan_f64(b) {
+            set_invalid(env);
+        }
+        if is_nan_f64(a) || is_nan_f64(b) {
+            return 0;
+        }
+        (f64::from_bits(a) == f64::from_bits(b)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_flt_d(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    if is_nan_f64(a) || is_nan_f64(b) {
-        set_invalid(env);
-        return 0;
-    }
-    (f64::from_bits(a) < f64::from_bits(b)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        if is_nan_f64(a) || is_nan_f64(b) {
+            set_invalid(env);
+            return 0;
+        }
+        (f64::from_bits(a) < f64::from_bits(b)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fle_d(env: *mut RiscvCpu, a: u64, b: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    if is_nan_f64(a) || is_nan_f64(b) {
-        set_invalid(env);
-        return 0;
-    }
-    (f64::from_bits(a) <= f64::from_bits(b)) as u64
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        if is_nan_f64(a) || is_nan_f64(b) {
+            set_invalid(env);
+            return 0;
+        }
+        (f64::from_bits(a) <= f64::from_bits(b)) as u64
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fclass_d(env: *mut RiscvCpu, a: u64) -> u64 {
-    let _ = env;
-    fclass_f64(a)
+    helper::guard(|| {
+        let _ = env;
+        fclass_f64(a)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_w_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    fcvt_i32(env, af, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        fcvt_i32(env, af, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_wu_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    fcvt_u32(env, af, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        fcvt_u32(env, af, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_l_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    fcvt_i64(env, af, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        fcvt_i64(env, af, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_lu_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    fcvt_u64(env, af, rm)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        fcvt_u64(env, af, rm)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_d_w(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as i32 as f64);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as i32 as f64);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_d_wu(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as u32 as f64);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as u32 as f64);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_d_l(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as i64 as f64);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as i64 as f64);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_d_lu(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let res = with_fenv(env, rm, || a as f64);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let res = with_fenv(env, rm, || a as f64);
+        f64_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_s_d(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f64(a);
-    let res = with_fenv(env, rm, || af as f32);
-    nanbox_f32(res.to_bits())
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f64(a);
+        let res = with_fenv(env, rm, || af as f32);
+        f32_result(res)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcvt_d_s(env: *mut RiscvCpu, a: u64, rm: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let af = read_f32(env, a) as f64;
-    let res = with_fenv(env, rm, || af);
-    res.to_bits()
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let af = read_f32(env, a);
+        let res = with_fenv(env, rm, || af as f64);
+        f64_result(res)
+    })
 }
 
 const I32_MIN_F64: f64 = -2147483648.0;
@@ -936,33 +1095,29 @@ fn fcvt_i32(env: &mut RiscvCpu, val: f64, rm: u64) -> u64 {
     (rounded as i32 as i64) as u64
 }
 
+/// Like every 32-bit result on RV64, the unsigned word is
+/// sign-extended into the register.
 fn fcvt_u32(env: &mut RiscvCpu, val: f64, rm: u64) -> u64 {
     if val.is_nan() {
         set_invalid(env);
-        return u32::MAX as u64;
+        return u64::MAX;
     }
     if val.is_infinite() {
         set_invalid(env);
-        return if val.is_sign_negative() {
-            0
-        } else {
-            u32::MAX as u64
-        };
+        return if val.is_sign_negative() { 0 } else { u64::MAX };
     }
-    if val < 0.0 {
+    // Negative values that round to zero are in range (NX only).
+    let (rounded, flags) = with_fenv_flags(env, rm, || unsafe { rint(val) });
+    if flags & FE_INVALID != 0 || rounded < 0.0 {
         set_invalid(env);
         return 0;
     }
-    let (rounded, flags) = with_fenv_flags(env, rm, || unsafe { rint(val) });
-    if flags & FE_INVALID != 0
-        || !rounded.is_finite()
-        || !(0.0..U32_MAX_PLUS1_F64).contains(&rounded)
-    {
+    if !rounded.is_finite() || rounded >= U32_MAX_PLUS1_F64 {
         set_invalid(env);
-        return u32::MAX as u64;
+        return u64::MAX;
     }
     update_fflags(env, map_fenv_flags(flags));
-    rounded as u32 as u64
+    (rounded as u32 as i32 as i64) as u64
 }
 
 fn fcvt_i64(env: &mut RiscvCpu, val: f64, rm: u64) -> u64 {
@@ -1003,15 +1158,13 @@ fn fcvt_u64(env: &mut RiscvCpu, val: f64, rm: u64) -> u64 {
         set_invalid(env);
         return if val.is_sign_negative() { 0 } else { u64::MAX };
     }
-    if val < 0.0 {
+    // Negative values that round to zero are in range (NX only).
+    let (rounded, flags) = with_fenv_flags(env, rm, || unsafe { rint(val) });
+    if flags & FE_INVALID != 0 || rounded < 0.0 {
         set_invalid(env);
         return 0;
     }
-    let (rounded, flags) = with_fenv_flags(env, rm, || unsafe { rint(val) });
-    if flags & FE_INVALID != 0
-        || !rounded.is_finite()
-        || !(0.0..U64_MAX_PLUS1_F64).contains(&rounded)
-    {
+    if !rounded.is_finite() || rounded >= U64_MAX_PLUS1_F64 {
         set_invalid(env);
         return u64::MAX;
     }
@@ -1021,18 +1174,22 @@ fn fcvt_u64(env: &mut RiscvCpu, val: f64, rm: u64) -> u64 {
 
 #[no_mangle]
 pub extern "C" fn helper_fcsr_read(env: *mut RiscvCpu) -> u64 {
-    let env = unsafe { &mut *env };
-    let fflags = env.fflags & FFLAGS_MASK;
-    let frm = env.frm & FRM_MASK;
-    fflags | (frm << FCSR_RD_SHIFT)
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let fflags = env.fflags & FFLAGS_MASK;
+        let frm = env.frm & FRM_MASK;
+        fflags | (frm << FCSR_RD_SHIFT)
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn helper_fcsr_write(env: *mut RiscvCpu, val: u64) -> u64 {
-    let env = unsafe { &mut *env };
-    let old =
-        (env.fflags & FFLAGS_MASK) | ((env.frm & FRM_MASK) << FCSR_RD_SHIFT);
-    env.fflags = val & FFLAGS_MASK;
-    env.frm = (val >> FCSR_RD_SHIFT) & FRM_MASK;
-    old
+    helper::guard(|| {
+        let env = unsafe { &mut *env };
+        let old = (env.fflags & FFLAGS_MASK)
+            | ((env.frm & FRM_MASK) << FCSR_RD_SHIFT);
+        env.fflags = val & FFLAGS_MASK;
+        env.frm = (val >> FCSR_RD_SHIFT) & FRM_MASK;
+        old
+    })
 }
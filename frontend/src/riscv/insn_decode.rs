@@ -2,9 +2,10 @@
 include!(concat!(env!("OUT_DIR"), "/riscv32_decode.rs"));
 
 mod decode16_impl {
-    // Re-import arg structs from parent (32-bit decoder).
-    use super::*;
     include!(concat!(env!("OUT_DIR"), "/riscv16_decode.rs"));
 }
 
-pub use decode16_impl::{decode16, Decode16};
+pub use decode16_impl::{
+    decode16, decode16_meta, pattern_source16, Decode16, CANONICAL_ENCODINGS16,
+    UNIMPLEMENTED_PATTERNS16,
+};
@@ -1,17 +1,29 @@
 //! RISC-V frontend — RV64 user-mode instruction translation.
 
 pub mod cpu;
+mod csr;
+pub mod excp;
 pub mod ext;
 mod fpu;
 #[allow(dead_code)]
 mod insn_decode;
 mod trans;
 
-use crate::{DisasContextBase, DisasJumpType, TranslatorOps};
-use cpu::{gpr_offset, LOAD_RES_OFFSET, LOAD_VAL_OFFSET, NUM_GPRS, PC_OFFSET};
+pub use insn_decode::{
+    insn_len, pattern_source, pattern_source16, CANONICAL_ENCODINGS,
+    CANONICAL_ENCODINGS16, UNIMPLEMENTED_PATTERNS, UNIMPLEMENTED_PATTERNS16,
+};
+
+use crate::{DisasContextBase, TranslatorOps};
+use cpu::{
+    gpr_offset, CYCLE_OFFSET, LOAD_RES_OFFSET, LOAD_VAL_OFFSET, NUM_GPRS,
+    PC_OFFSET,
+};
+use excp::EXCP_UNDEF;
 use ext::RiscvCfg;
-use tcg_core::tb::{EXCP_UNDEF, TB_EXIT_IDX0};
-use tcg_core::{Context, TempIdx, Type};
+use tcg_core::opcode::OPCODE_DEFS;
+use tcg_core::tb::{DisasJumpType, TbExit};
+use tcg_core::{Context, InsnMeta, OpIdx, Opcode, TempIdx, Type};
 
 // ---------------------------------------------------------------
 // Disassembly context
@@ -39,6 +51,49 @@ pub struct RiscvDisasContext {
     pub cur_insn_len: u32,
     /// Pointer to guest code bytes for fetching.
     pub guest_base: *const u8,
+    /// Guest range `[start, end)` whose stores leave the TB
+    /// with `EXIT_MMIO_STORE` instead of writing memory.
+    pub mmio: Option<(u64, u64)>,
+    /// The `add` bumping `cycle` on TB entry; its constant is
+    /// patched with the instruction count in `tb_stop`.
+    icount_op: Option<OpIdx>,
+    /// Per early exit, the `sub` taking back
+    /// the instructions it skips and how many ran before it;
+    /// patched in `tb_stop`.
+    early_exits: Vec<(OpIdx, u32)>,
+    /// Loads and back edge seen so far, for spin detection.
+    spin: SpinScan,
+}
+
+/// Longest TB considered for spin-loop detection.
+pub const SPIN_MAX_INSNS: u32 = 8;
+
+/// What the translated instructions reveal about a possible
+/// busy-wait loop; judged in `tb_stop`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SpinScan {
+    /// `(rs1, imm)` of the first integer load.
+    load: Option<(usize, i64)>,
+    /// Integer loads translated.
+    loads: u32,
+    /// A load used a different address than the first.
+    mixed: bool,
+    /// The TB ended in a conditional branch to its own start.
+    back_edge: bool,
+}
+
+impl SpinScan {
+    pub(crate) fn note_load(&mut self, rs1: usize, imm: i64) {
+        self.loads += 1;
+        match self.load {
+            None => self.load = Some((rs1, imm)),
+            Some(first) => self.mixed |= first != (rs1, imm),
+        }
+    }
+
+    pub(crate) fn note_branch(&mut self, target: u64, pc_first: u64) {
+        self.back_edge = target == pc_first;
+    }
 }
 
 impl RiscvDisasContext {
@@ -53,6 +108,9 @@ impl RiscvDisasContext {
                 is_jmp: DisasJumpType::Next,
                 num_insns: 0,
                 max_insns: 512,
+                pc_page_end: u64::MAX,
+                single_step: false,
+                spin_loop: false,
             },
             cfg,
             env: TempIdx(0),
@@ -63,6 +121,10 @@ impl RiscvDisasContext {
             opcode: 0,
             cur_insn_len: 4,
             guest_base,
+            mmio: None,
+            icount_op: None,
+            early_exits: Vec::new(),
+            spin: SpinScan::default(),
         }
     }
 
@@ -87,6 +149,58 @@ impl RiscvDisasContext {
     }
 }
 
+/// Record which decode pattern, and so which extensions, the
+/// instruction at `pc_next` needs, whether or not `cfg` has them.
+fn record_insn_meta(ctx: &RiscvDisasContext, ir: &mut Context, half: u16) {
+    let meta = if insn_len(half) == 2 {
+        insn_decode::decode16_meta(half)
+    } else {
+        insn_decode::decode_meta(unsafe { ctx.fetch_insn32() })
+    };
+    if let Some((name, exts)) = meta {
+        ir.record_insn_meta(ctx.base.pc_next, InsnMeta { name, exts });
+    }
+}
+
+/// A short TB that re-reads one guest address, writes no
+/// memory, keeps the address register unchanged and branches
+/// back to its start on a condition. The scan sees only integer
+/// loads, so any other guest memory access, helper call or
+/// barrier in the IR rules the TB out.
+fn is_spin_loop(ctx: &RiscvDisasContext, ir: &Context) -> bool {
+    let scan = &ctx.spin;
+    let Some((rs1, _)) = scan.load else {
+        return false;
+    };
+    if !scan.back_edge
+        || scan.mixed
+        || ctx.base.single_step
+        || ctx.base.num_insns > SPIN_MAX_INSNS
+    {
+        return false;
+    }
+    let base = ctx.gpr[rs1];
+    let mut loads = 0;
+    for op in ir.ops() {
+        match op.opc {
+            Opcode::QemuLd => loads += 1,
+            Opcode::QemuSt
+            | Opcode::QemuLd2
+            | Opcode::QemuSt2
+            | Opcode::StVec
+            | Opcode::Call
+            | Opcode::Mb
+            | Opcode::PluginMemCb => return false,
+            _ => {}
+        }
+        let nb_oargs = OPCODE_DEFS[op.opc as usize].nb_oargs as usize;
+        if rs1 != 0 && op.args[..nb_oargs].contains(&base) {
+            return false;
+        }
+    }
+    loads == scan.loads
+}
+
 // ---------------------------------------------------------------
 // TranslatorOps implementation
 // ---------------------------------------------------------------
@@ -98,8 +212,22 @@ impl TranslatorOps for RiscvTranslator {
     type DisasContext = RiscvDisasContext;
 
     fn init_disas_context(ctx: &mut RiscvDisasContext, ir: &mut Context) {
-        // Register the env pointer (fixed to host RBP = reg 5).
-        ctx.env = ir.new_fixed(Type::I64, 5, "env");
+        if ir.nb_globals() != 0 {
+            // Already registered by an earlier TB, in the order
+            // below: env, gpr[0..32], pc, load_res, load_val.
+            ctx.env = TempIdx(0);
+            for i in 0..NUM_GPRS {
+                ctx.gpr[i] = TempIdx(1 + i as u32);
+            }
+            let next = 1 + NUM_GPRS as u32;
+            ctx.pc = TempIdx(next);
+            ctx.load_res = TempIdx(next + 1);
+            ctx.load_val = TempIdx(next + 2);
+            return;
+        }
+
+        // Register the env pointer in the backend's env register.
+        ctx.env = ir.new_fixed(Type::I64, ir.env_reg, "env");
 
         // Register guest GPRs as globals at known offsets.
         for i in 0..NUM_GPRS {
@@ -117,8 +245,14 @@ impl TranslatorOps for RiscvTranslator {
             ir.new_global(Type::I64, ctx.env, LOAD_VAL_OFFSET, "load_val");
     }
 
-    fn tb_start(_ctx: &mut RiscvDisasContext, _ir: &mut Context) {
-        // Nothing special for user-mode.
+    fn tb_start(ctx: &mut RiscvDisasContext, ir: &mut Context) {
+        // cycle += <insns in this TB>, patched in tb_stop.
+        let cycle = ir.new_temp(Type::I64);
+        ir.gen_ld(Type::I64, cycle, ctx.env, CYCLE_OFFSET);
+        let placeholder = ir.new_const(Type::I64, 0);
+        ctx.icount_op = Some(ir.next_op_idx());
+        ir.gen_add(Type::I64, cycle, cycle, placeholder);
+        ir.gen_st(Type::I64, cycle, ctx.env, CYCLE_OFFSET);
     }
 
     fn insn_start(ctx: &mut RiscvDisasContext, ir: &mut Context) {
@@ -129,7 +263,10 @@ impl TranslatorOps for RiscvTranslator {
     fn translate_insn(ctx: &mut RiscvDisasContext, ir: &mut Context) {
         // Fetch 16-bit half-word to determine instruction length.
         let half = unsafe { ctx.fetch_insn16() };
-        let decoded = if half & 0x3 != 0x3 {
+        if ir.insn_meta_enabled() {
+            record_insn_meta(ctx, ir, half);
+        }
+        let decoded = if insn_len(half) == 2 {
             // 16-bit compressed instruction — requires C extension.
             if !ctx.cfg.misa.contains(ext::MisaExt::C) {
                 false
@@ -150,25 +287,42 @@ impl TranslatorOps for RiscvTranslator {
             let pc_val = ctx.base.pc_next;
             let pc_const = ir.new_const(Type::I64, pc_val);
             ir.gen_mov(Type::I64, ctx.pc, pc_const);
-            ir.gen_exit_tb(EXCP_UNDEF);
+            ctx.gen_uncount_rest(ir, ctx.base.num_insns - 1);
+            ir.gen_exit_tb(TbExit::Exception(EXCP_UNDEF));
             ctx.base.is_jmp = DisasJumpType::NoReturn;
         }
 
         ctx.base.pc_next += ctx.cur_insn_len as u64;
+
+        // Leave a 32-bit instruction crossing the page end to
+        // the next TB; only the first instruction may cross.
+        let left = ctx.base.pc_page_end.saturating_sub(ctx.base.pc_next);
+        if ctx.base.is_jmp == DisasJumpType::Next && (2..4).contains(&left) {
+            let half = unsafe { ctx.fetch_insn16() };
+            if insn_len(half) == 4 {
+                ctx.base.is_jmp = DisasJumpType::TooMany;
+            }
+        }
     }
 
     fn tb_stop(ctx: &mut RiscvDisasContext, ir: &mut Context) {
+        if let Some(oi) = ctx.icount_op.take() {
+            let n = ir.new_const(Type::I64, ctx.base.num_insns as u64);
+            ir.op_mut(oi).args[2] = n;
+        }
+        for (oi, retired) in ctx.early_exits.drain(..) {
+            let n = ctx.base.num_insns - retired;
+            let n = ir.new_const(Type::I64, n as u64);
+            ir.op_mut(oi).args[2] = n;
+        }
+        ctx.base.spin_loop = is_spin_loop(ctx, ir);
         match ctx.base.is_jmp {
             DisasJumpType::NoReturn => {
                 // TB already terminated by the instruction.
             }
             DisasJumpType::Next | DisasJumpType::TooMany => {
                 // Fall through: update PC and return chain slot 0.
-                let pc_val = ctx.base.pc_next;
-                let pc_const = ir.new_const(Type::I64, pc_val);
-                ir.gen_mov(Type::I64, ctx.pc, pc_const);
-                ir.gen_goto_tb(0);
-                ir.gen_exit_tb(TB_EXIT_IDX0);
+                ctx.gen_goto_tb(ir, 0, ctx.base.pc_next);
             }
         }
     }
@@ -6,20 +6,21 @@
 //! `BinOp` function pointer.
 
 use super::cpu::{
-    fpr_offset, FFLAGS_OFFSET, FRM_OFFSET, UCAUSE_OFFSET, UEPC_OFFSET,
-    UIE_OFFSET, UIP_OFFSET, USCRATCH_OFFSET, USTATUS_FS_DIRTY, USTATUS_FS_MASK,
-    USTATUS_OFFSET, UTVAL_OFFSET, UTVEC_OFFSET,
+    fpr_offset, CYCLE_OFFSET, EXIT_MMIO_STORE, MMIO_ADDR_OFFSET,
+    MMIO_OP_OFFSET, MMIO_VAL_OFFSET, USTATUS_FS_DIRTY, USTATUS_FS_MASK,
+    USTATUS_OFFSET, UTVAL_OFFSET,
+};
+use super::excp::{
+    EXCP_EBREAK, EXCP_ECALL, EXCP_FETCH_MISALIGNED, EXCP_LOAD_MISALIGNED,
+    EXCP_STORE_MISALIGNED, EXCP_UNDEF,
 };
 use super::ext::MisaExt;
 use super::fpu;
 use super::insn_decode::*;
 use super::RiscvDisasContext;
-use crate::DisasJumpType;
 use tcg_core::context::Context;
-use tcg_core::tb::{
-    EXCP_EBREAK, EXCP_ECALL, EXCP_UNDEF, TB_EXIT_IDX0, TB_EXIT_IDX1,
-    TB_EXIT_NOCHAIN,
-};
+use tcg_core::helper::CALL_NO_PANIC;
+use tcg_core::tb::{DisasJumpType, TbExit};
 use tcg_core::types::{Cond, MemOp, Type};
 use tcg_core::TempIdx;
 
@@ -49,28 +50,13 @@ const TCG_MO_ALL: u32 = 0x0F;
 const TCG_BAR_LDAQ: u32 = 0x10;
 const TCG_BAR_STRL: u32 = 0x20;
 
-// CSR numbers (user-level).
-const CSR_USTATUS: i64 = 0x000;
-const CSR_FFLAGS: i64 = 0x001;
-const CSR_FRM: i64 = 0x002;
-const CSR_FCSR: i64 = 0x003;
-const CSR_UIE: i64 = 0x004;
-const CSR_UTVEC: i64 = 0x005;
-const CSR_USCRATCH: i64 = 0x040;
-const CSR_UEPC: i64 = 0x041;
-const CSR_UCAUSE: i64 = 0x042;
-const CSR_UTVAL: i64 = 0x043;
-const CSR_UIP: i64 = 0x044;
-const CSR_CYCLE: i64 = 0xC00;
-const CSR_TIME: i64 = 0xC01;
-const CSR_INSTRET: i64 = 0xC02;
-
 // ── Helpers ────────────────────────────────────────────────────
 
 impl RiscvDisasContext {
     // -- GPR access ----------------------------------------
 
-    /// Read GPR `idx`; x0 yields a constant zero.
+    /// Read GPR `idx`; x0 yields the TB's one zero constant,
+    /// since `new_const` deduplicates.
     fn gpr_or_zero(&self, ir: &mut Context, idx: i64) -> TempIdx {
         if idx == 0 {
             ir.new_const(Type::I64, 0)
@@ -107,7 +93,7 @@ impl RiscvDisasContext {
 
     // -- FP state helpers -----------------------------------
 
-    fn gen_fp_check(&self, ir: &mut Context) {
+    fn gen_fp_check(&mut self, ir: &mut Context) {
         let status = ir.new_temp(Type::I64);
         ir.gen_ld(Type::I64, status, self.env, USTATUS_OFFSET);
         let mask = ir.new_const(Type::I64, USTATUS_FS_MASK);
@@ -118,11 +104,24 @@ impl RiscvDisasContext {
         ir.gen_brcond(Type::I64, fs, zero, Cond::Ne, ok);
         let pc = ir.new_const(Type::I64, self.base.pc_next);
         ir.gen_mov(Type::I64, self.pc, pc);
-        ir.gen_exit_tb(EXCP_UNDEF);
+        self.gen_uncount_rest(ir, self.base.num_insns - 1);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_UNDEF));
         ir.gen_set_label(ok);
     }
 
-    fn gen_set_fs_dirty(&self, ir: &mut Context) {
+    /// Before an exit that leaves the TB early, take back from
+    /// `cycle` the instructions past the first `retired`, which
+    /// the TB-entry count charged but which did not retire.
+    pub(super) fn gen_uncount_rest(&mut self, ir: &mut Context, retired: u32) {
+        let cycle = ir.new_temp(Type::I64);
+        ir.gen_ld(Type::I64, cycle, self.env, CYCLE_OFFSET);
+        let placeholder = ir.new_const(Type::I64, 0);
+        self.early_exits.push((ir.next_op_idx(), retired));
+        ir.gen_sub(Type::I64, cycle, cycle, placeholder);
+        ir.gen_st(Type::I64, cycle, self.env, CYCLE_OFFSET);
+    }
+
+    pub(super) fn gen_set_fs_dirty(&self, ir: &mut Context) {
         let status = ir.new_temp(Type::I64);
         ir.gen_ld(Type::I64, status, self.env, USTATUS_OFFSET);
         let clear = ir.new_const(Type::I64, !USTATUS_FS_MASK);
@@ -134,26 +133,38 @@ impl RiscvDisasContext {
         ir.gen_st(Type::I64, new_status, self.env, USTATUS_OFFSET);
     }
 
-    fn gen_helper_call(
+    pub(super) fn gen_helper_call(
         &self,
         ir: &mut Context,
         helper: usize,
         args: &[TempIdx],
     ) -> TempIdx {
         let dst = ir.new_temp(Type::I64);
-        ir.gen_call(dst, helper as u64, args);
+        ir.gen_call(dst, helper as u64, 0, args);
         dst
     }
 
-    fn gen_fp_load(
+    /// Call an FPU helper. They are plain arithmetic on host
+    /// floats with no panic path, so no post-call check.
+    fn gen_fpu_call(
         &self,
         ir: &mut Context,
+        helper: usize,
+        args: &[TempIdx],
+    ) -> TempIdx {
+        let dst = ir.new_temp(Type::I64);
+        ir.gen_call(dst, helper as u64, CALL_NO_PANIC, args);
+        dst
+    }
+
+    fn gen_fp_load(
+        &mut self,
+        ir: &mut Context,
         a: &ArgsI,
         memop: MemOp,
         is_single: bool,
     ) -> bool {
         self.gen_fp_check(ir);
-        self.gen_set_fs_dirty(ir);
         let base = self.gpr_or_zero(ir, a.rs1);
         let addr = if a.imm != 0 {
             let imm = ir.new_const(Type::I64, a.imm as u64);
@@ -162,6 +173,9 @@ impl RiscvDisasContext {
         } else {
             base
         };
+        let memop = self.plain(memop);
+        let addr = self.gen_align_check(ir, addr, memop, false);
+        self.gen_set_fs_dirty(ir);
         let val = ir.new_temp(Type::I64);
         ir.gen_qemu_ld(Type::I64, val, addr, memop.bits() as u32);
         if is_single {
@@ -176,7 +190,7 @@ impl RiscvDisasContext {
     }
 
     fn gen_fp_store(
-        &self,
+        &mut self,
         ir: &mut Context,
         a: &ArgsS,
         memop: MemOp,
@@ -191,6 +205,8 @@ impl RiscvDisasContext {
         } else {
             base
         };
+        let memop = self.plain(memop);
+        let addr = self.gen_align_check(ir, addr, memop, true);
         let val = self.fpr_load(ir, a.rs2);
         let store_val = if is_single {
             let lo32 = ir.new_temp(Type::I32);
@@ -201,169 +217,17 @@ impl RiscvDisasContext {
         } else {
             val
         };
-        ir.gen_qemu_st(Type::I64, store_val, addr, memop.bits() as u32);
+        self.gen_guest_st(ir, store_val, addr, memop);
         true
     }
 
-    // -- CSR helpers ----------------------------------------
-
-    fn gen_csr_read(&self, ir: &mut Context, csr: i64) -> Option<TempIdx> {
-        match csr {
-            CSR_FFLAGS => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, FFLAGS_OFFSET);
-                let mask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
-                let out = ir.new_temp(Type::I64);
-                ir.gen_and(Type::I64, out, v, mask);
-                Some(out)
-            }
-            CSR_FRM => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, FRM_OFFSET);
-                let mask = ir.new_const(Type::I64, fpu::FRM_MASK);
-                let out = ir.new_temp(Type::I64);
-                ir.gen_and(Type::I64, out, v, mask);
-                Some(out)
-            }
-            CSR_FCSR => {
-                let fflags = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, fflags, self.env, FFLAGS_OFFSET);
-                let fmask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
-                ir.gen_and(Type::I64, fflags, fflags, fmask);
-                let frm = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, frm, self.env, FRM_OFFSET);
-                let rmask = ir.new_const(Type::I64, fpu::FRM_MASK);
-                ir.gen_and(Type::I64, frm, frm, rmask);
-                let shift = ir.new_const(Type::I64, 5);
-                let frm_shift = ir.new_temp(Type::I64);
-                ir.gen_shl(Type::I64, frm_shift, frm, shift);
-                let out = ir.new_temp(Type::I64);
-                ir.gen_or(Type::I64, out, fflags, frm_shift);
-                Some(out)
-            }
-            CSR_USTATUS => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, USTATUS_OFFSET);
-                Some(v)
-            }
-            CSR_UIE => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UIE_OFFSET);
-                Some(v)
-            }
-            CSR_UTVEC => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UTVEC_OFFSET);
-                Some(v)
-            }
-            CSR_USCRATCH => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, USCRATCH_OFFSET);
-                Some(v)
-            }
-            CSR_UEPC => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UEPC_OFFSET);
-                Some(v)
-            }
-            CSR_UCAUSE => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UCAUSE_OFFSET);
-                Some(v)
-            }
-            CSR_UTVAL => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UTVAL_OFFSET);
-                Some(v)
-            }
-            CSR_UIP => {
-                let v = ir.new_temp(Type::I64);
-                ir.gen_ld(Type::I64, v, self.env, UIP_OFFSET);
-                Some(v)
-            }
-            CSR_CYCLE | CSR_TIME | CSR_INSTRET => {
-                let v = ir.new_const(Type::I64, 0);
-                Some(v)
-            }
-            _ => None,
-        }
-    }
-
-    fn gen_csr_write(&self, ir: &mut Context, csr: i64, val: TempIdx) -> bool {
-        match csr {
-            CSR_FFLAGS => {
-                let mask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
-                let v = ir.new_temp(Type::I64);
-                ir.gen_and(Type::I64, v, val, mask);
-                ir.gen_st(Type::I64, v, self.env, FFLAGS_OFFSET);
-                self.gen_set_fs_dirty(ir);
-                true
-            }
-            CSR_FRM => {
-                let mask = ir.new_const(Type::I64, fpu::FRM_MASK);
-                let v = ir.new_temp(Type::I64);
-                ir.gen_and(Type::I64, v, val, mask);
-                ir.gen_st(Type::I64, v, self.env, FRM_OFFSET);
-                self.gen_set_fs_dirty(ir);
-                true
-            }
-            CSR_FCSR => {
-                let fmask = ir.new_const(Type::I64, fpu::FFLAGS_MASK);
-                let fflags = ir.new_temp(Type::I64);
-                ir.gen_and(Type::I64, fflags, val, fmask);
-                ir.gen_st(Type::I64, fflags, self.env, FFLAGS_OFFSET);
-                let shift = ir.new_const(Type::I64, 5);
-                let frm = ir.new_temp(Type::I64);
-                ir.gen_shr(Type::I64, frm, val, shift);
-                let rmask = ir.new_const(Type::I64, fpu::FRM_MASK);
-                ir.gen_and(Type::I64, frm, frm, rmask);
-                ir.gen_st(Type::I64, frm, self.env, FRM_OFFSET);
-                self.gen_set_fs_dirty(ir);
-                true
-            }
-            CSR_USTATUS => {
-                ir.gen_st(Type::I64, val, self.env, USTATUS_OFFSET);
-                true
-            }
-            CSR_UIE => {
-                ir.gen_st(Type::I64, val, self.env, UIE_OFFSET);
-                true
-            }
-            CSR_UTVEC => {
-                ir.gen_st(Type::I64, val, self.env, UTVEC_OFFSET);
-                true
-            }
-            CSR_USCRATCH => {
-                ir.gen_st(Type::I64, val, self.env, USCRATCH_OFFSET);
-                true
-            }
-            CSR_UEPC => {
-                ir.gen_st(Type::I64, val, self.env, UEPC_OFFSET);
-                true
-            }
-            CSR_UCAUSE => {
-                ir.gen_st(Type::I64, val, self.env, UCAUSE_OFFSET);
-                true
-            }
-            CSR_UTVAL => {
-                ir.gen_st(Type::I64, val, self.env, UTVAL_OFFSET);
-                true
-            }
-            CSR_UIP => {
-                ir.gen_st(Type::I64, val, self.env, UIP_OFFSET);
-                true
-            }
-            CSR_CYCLE | CSR_TIME | CSR_INSTRET => false,
-            _ => false,
-        }
-    }
-
     // -- R-type helpers ------------------------------------
 
     // -- Guest memory helpers --------------------------------
 
     /// Guest load: rd = *(addr), addr = rs1 + imm.
-    fn gen_load(&self, ir: &mut Context, a: &ArgsI, memop: MemOp) -> bool {
+    fn gen_load(&mut self, ir: &mut Context, a: &ArgsI, memop: MemOp) -> bool {
+        self.spin.note_load(a.rs1 as usize, a.imm);
         let base = self.gpr_or_zero(ir, a.rs1);
         let addr = if a.imm != 0 {
             let imm = ir.new_const(Type::I64, a.imm as u64);
@@ -372,6 +236,8 @@ impl RiscvDisasContext {
         } else {
             base
         };
+        let memop = self.plain(memop);
+        let addr = self.gen_align_check(ir, addr, memop, false);
         let dst = ir.new_temp(Type::I64);
         ir.gen_qemu_ld(Type::I64, dst, addr, memop.bits() as u32);
         self.gen_set_gpr(ir, a.rd, dst);
@@ -379,7 +245,7 @@ impl RiscvDisasContext {
     }
 
     /// Guest store: *(addr) = rs2, addr = rs1 + imm.
-    fn gen_store(&self, ir: &mut Context, a: &ArgsS, memop: MemOp) -> bool {
+    fn gen_store(&mut self, ir: &mut Context, a: &ArgsS, memop: MemOp) -> bool {
         let base = self.gpr_or_zero(ir, a.rs1);
         let addr = if a.imm != 0 {
             let imm = ir.new_const(Type::I64, a.imm as u64);
@@ -388,15 +254,106 @@ impl RiscvDisasContext {
         } else {
             base
         };
+        let memop = self.plain(memop);
+        let addr = self.gen_align_check(ir, addr, memop, true);
         let val = self.gpr_or_zero(ir, a.rs2);
-        ir.gen_qemu_st(Type::I64, val, addr, memop.bits() as u32);
+        self.gen_guest_st(ir, val, addr, memop);
         true
     }
 
+    /// `memop` for a plain load or store: aligned under
+    /// `strict_align`, else at any address.
+    fn plain(&self, memop: MemOp) -> MemOp {
+        if self.cfg.strict_align {
+            memop.aligned()
+        } else {
+            memop
+        }
+    }
+
+    /// Unless `addr` has the alignment `memop` requires, raise
+    /// an address-misaligned exception with the address in
+    /// `utval`. Returns `addr` as a temp live past the check.
+    fn gen_align_check(
+        &mut self,
+        ir: &mut Context,
+        addr: TempIdx,
+        memop: MemOp,
+        store: bool,
+    ) -> TempIdx {
+        let align = memop.align_bytes(memop.size_bytes());
+        if align == 1 {
+            return addr;
+        }
+        let a = ir.new_temp_tb(Type::I64);
+        ir.gen_mov(Type::I64, a, addr);
+        let mask = ir.new_const(Type::I64, align as u64 - 1);
+        let low = ir.new_temp(Type::I64);
+        ir.gen_and(Type::I64, low, a, mask);
+        let zero = ir.new_const(Type::I64, 0);
+        let ok = ir.new_label();
+        ir.gen_brcond(Type::I64, low, zero, Cond::Eq, ok);
+        ir.gen_st(Type::I64, a, self.env, UTVAL_OFFSET);
+        let pc = ir.new_const(Type::I64, self.base.pc_next);
+        ir.gen_mov(Type::I64, self.pc, pc);
+        self.gen_uncount_rest(ir, self.base.num_insns - 1);
+        let excp = if store {
+            EXCP_STORE_MISALIGNED
+        } else {
+            EXCP_LOAD_MISALIGNED
+        };
+        ir.gen_exit_tb(TbExit::Exception(excp));
+        ir.gen_set_label(ok);
+        a
+    }
+
+    /// Store `val` to guest `addr`. A store into the MMIO window
+    /// leaves the TB with `EXIT_MMIO_STORE` and the access in
+    /// the `mmio_*` fields instead.
+    fn gen_guest_st(
+        &mut self,
+        ir: &mut Context,
+        val: TempIdx,
+        addr: TempIdx,
+        memop: MemOp,
+    ) {
+        let Some((start, end)) = self.mmio else {
+            ir.gen_qemu_st(Type::I64, val, addr, memop.bits() as u32);
+            return;
+        };
+        // Both are used again past the label.
+        let (v, a) = (ir.new_temp_tb(Type::I64), ir.new_temp_tb(Type::I64));
+        ir.gen_mov(Type::I64, v, val);
+        ir.gen_mov(Type::I64, a, addr);
+        let off = ir.new_temp(Type::I64);
+        let base = ir.new_const(Type::I64, start);
+        ir.gen_sub(Type::I64, off, a, base);
+        let len = ir.new_const(Type::I64, end - start);
+        let ram = ir.new_label();
+        ir.gen_brcond(Type::I64, off, len, Cond::Geu, ram);
+        ir.gen_st(Type::I64, a, self.env, MMIO_ADDR_OFFSET);
+        ir.gen_st(Type::I64, v, self.env, MMIO_VAL_OFFSET);
+        let op = ir.new_const(Type::I64, memop.bits() as u64);
+        ir.gen_st(Type::I64, op, self.env, MMIO_OP_OFFSET);
+        let next = self.base.pc_next + self.cur_insn_len as u64;
+        let pc = ir.new_const(Type::I64, next);
+        ir.gen_mov(Type::I64, self.pc, pc);
+        self.gen_uncount_rest(ir, self.base.num_insns);
+        ir.gen_exit_tb(TbExit::Custom(EXIT_MMIO_STORE));
+        ir.gen_set_label(ram);
+        ir.gen_qemu_st(Type::I64, v, a, memop.bits() as u32);
+    }
+
     // -- R-type ALU helpers ----------------------------------
+    //
+    // The ALU helpers emit nothing for rd=x0: such encodings are
+    // HINTs (canonical NOPs, Zihintntl) with no effect.
 
     /// R-type ALU: `rd = op(rs1, rs2)`.
     fn gen_arith(&self, ir: &mut Context, a: &ArgsR, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let s1 = self.gpr_or_zero(ir, a.rs1);
         let s2 = self.gpr_or_zero(ir, a.rs2);
         let d = ir.new_temp(Type::I64);
@@ -405,8 +362,28 @@ impl RiscvDisasContext {
         true
     }
 
+    /// R-type shift: `rd = op(rs1, rs2 & 63)`. IR shifts are
+    /// undefined for counts of the operand width or more.
+    fn gen_shift(&self, ir: &mut Context, a: &ArgsR, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
+        let s1 = self.gpr_or_zero(ir, a.rs1);
+        let s2 = self.gpr_or_zero(ir, a.rs2);
+        let c63 = ir.new_const(Type::I64, 63);
+        let sh = ir.new_temp(Type::I64);
+        ir.gen_and(Type::I64, sh, s2, c63);
+        let d = ir.new_temp(Type::I64);
+        op(ir, Type::I64, d, s1, sh);
+        self.gen_set_gpr(ir, a.rd, d);
+        true
+    }
+
     /// R-type setcond: `rd = (rs1 cond rs2) ? 1 : 0`.
     fn gen_setcond_rr(&self, ir: &mut Context, a: &ArgsR, cond: Cond) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let s1 = self.gpr_or_zero(ir, a.rs1);
         let s2 = self.gpr_or_zero(ir, a.rs2);
         let d = ir.new_temp(Type::I64);
@@ -419,6 +396,9 @@ impl RiscvDisasContext {
 
     /// I-type ALU: `rd = op(rs1, sext(imm))`.
     fn gen_arith_imm(&self, ir: &mut Context, a: &ArgsI, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let src = self.gpr_or_zero(ir, a.rs1);
         let imm = ir.new_const(Type::I64, a.imm as u64);
         let d = ir.new_temp(Type::I64);
@@ -429,6 +409,9 @@ impl RiscvDisasContext {
 
     /// I-type setcond: `rd = (rs1 cond imm) ? 1 : 0`.
     fn gen_setcond_imm(&self, ir: &mut Context, a: &ArgsI, cond: Cond) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let src = self.gpr_or_zero(ir, a.rs1);
         let imm = ir.new_const(Type::I64, a.imm as u64);
         let d = ir.new_temp(Type::I64);
@@ -446,6 +429,9 @@ impl RiscvDisasContext {
         a: &ArgsShift,
         op: BinOp,
     ) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let src = self.gpr_or_zero(ir, a.rs1);
         let sh = ir.new_const(Type::I64, a.shamt as u64);
         let d = ir.new_temp(Type::I64);
@@ -458,6 +444,9 @@ impl RiscvDisasContext {
 
     /// R-type W: `rd = sext32(op(rs1, rs2))`.
     fn gen_arith_w(&self, ir: &mut Context, a: &ArgsR, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let s1 = self.gpr_or_zero(ir, a.rs1);
         let s2 = self.gpr_or_zero(ir, a.rs2);
         let d = ir.new_temp(Type::I64);
@@ -468,6 +457,9 @@ impl RiscvDisasContext {
 
     /// I-type W: `rd = sext32(op(rs1, imm))`.
     fn gen_arith_imm_w(&self, ir: &mut Context, a: &ArgsI, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let src = self.gpr_or_zero(ir, a.rs1);
         let imm = ir.new_const(Type::I64, a.imm as u64);
         let d = ir.new_temp(Type::I64);
@@ -476,14 +468,20 @@ impl RiscvDisasContext {
         true
     }
 
-    /// R-type shift W: truncate to I32, shift, sext.
+    /// R-type shift W: truncate to I32, shift by `rs2 & 31`,
+    /// sext.
     fn gen_shiftw(&self, ir: &mut Context, a: &ArgsR, op: BinOp) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let s1 = self.gpr_or_zero(ir, a.rs1);
         let s2 = self.gpr_or_zero(ir, a.rs2);
         let a32 = ir.new_temp(Type::I32);
         ir.gen_extrl_i64_i32(a32, s1);
         let b32 = ir.new_temp(Type::I32);
         ir.gen_extrl_i64_i32(b32, s2);
+        let c31 = ir.new_const(Type::I32, 31);
+        ir.gen_and(Type::I32, b32, b32, c31);
         let d32 = ir.new_temp(Type::I32);
         op(ir, Type::I32, d32, a32, b32);
         self.gen_set_gpr_sx32(ir, a.rd, d32);
@@ -497,6 +495,9 @@ impl RiscvDisasContext {
         a: &ArgsShift,
         op: BinOp,
     ) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
         let src = self.gpr_or_zero(ir, a.rs1);
         let s32 = ir.new_temp(Type::I32);
         ir.gen_extrl_i64_i32(s32, src);
@@ -507,6 +508,55 @@ impl RiscvDisasContext {
         true
     }
 
+    // -- Zbb helpers ---------------------------------------
+
+    /// Unary: `rd = f(rs1)`.
+    fn gen_unary(
+        &self,
+        ir: &mut Context,
+        a: &ArgsR2,
+        f: impl FnOnce(&mut Context, TempIdx) -> TempIdx,
+    ) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
+        let src = self.gpr_or_zero(ir, a.rs1);
+        let d = f(ir, src);
+        self.gen_set_gpr(ir, a.rd, d);
+        true
+    }
+
+    /// Unary W: `rd = sext32(f(rs1[31:0]))`, `f` on I32.
+    fn gen_unary_w(
+        &self,
+        ir: &mut Context,
+        a: &ArgsR2,
+        f: impl FnOnce(&mut Context, TempIdx) -> TempIdx,
+    ) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
+        let src = self.gpr_or_zero(ir, a.rs1);
+        let s32 = ir.new_temp(Type::I32);
+        ir.gen_extrl_i64_i32(s32, src);
+        let d32 = f(ir, s32);
+        self.gen_set_gpr_sx32(ir, a.rd, d32);
+        true
+    }
+
+    /// min/max: `rd = (rs1 cond rs2) ? rs1 : rs2`.
+    fn gen_minmax(&self, ir: &mut Context, a: &ArgsR, cond: Cond) -> bool {
+        if a.rd == 0 {
+            return true;
+        }
+        let s1 = self.gpr_or_zero(ir, a.rs1);
+        let s2 = self.gpr_or_zero(ir, a.rs2);
+        let d = ir.new_temp(Type::I64);
+        ir.gen_movcond(Type::I64, d, s1, s2, s1, s2, cond);
+        self.gen_set_gpr(ir, a.rd, d);
+        true
+    }
+
     // -- M-extension helpers (mul/div/rem) -----------------
 
     /// Signed division with RISC-V special-case handling.
@@ -670,8 +720,15 @@ impl RiscvDisasContext {
     // -- Atomic helpers (A extension) ----------------------
 
     /// LR: load-reserved.
-    fn gen_lr(&self, ir: &mut Context, a: &ArgsAtomic, memop: MemOp) -> bool {
+    fn gen_lr(
+        &mut self,
+        ir: &mut Context,
+        a: &ArgsAtomic,
+        memop: MemOp,
+    ) -> bool {
+        let memop = memop.aligned().atomic();
         let addr = self.gpr_or_zero(ir, a.rs1);
+        let addr = self.gen_align_check(ir, addr, memop, false);
         if a.rl != 0 {
             ir.gen_mb(TCG_MO_ALL | TCG_BAR_STRL);
         }
@@ -688,20 +745,43 @@ impl RiscvDisasContext {
 
     /// SC: store-conditional (single-thread simplified).
     ///
-    /// In single-threaded mode, SC always succeeds if there
-    /// is a valid reservation (set by a preceding LR).
-    /// We skip the address comparison since no other thread
-    /// can invalidate the reservation.
-    fn gen_sc(&self, ir: &mut Context, a: &ArgsAtomic, memop: MemOp) -> bool {
+    /// Succeeds when the address matches the reservation of
+    /// the last LR and memory still holds the value it read;
+    /// with no other thread, the check and the store need not
+    /// be one atomic step. The reservation is dropped either
+    /// way.
+    fn gen_sc(
+        &mut self,
+        ir: &mut Context,
+        a: &ArgsAtomic,
+        memop: MemOp,
+    ) -> bool {
+        let memop = memop.aligned().atomic();
         let addr = self.gpr_or_zero(ir, a.rs1);
-
-        // Always succeed: store and set rd = 0.
+        let addr = self.gen_align_check(ir, addr, memop, true);
+        let fail = ir.new_label();
+        let done = ir.new_label();
+        if a.rl != 0 {
+            ir.gen_mb(TCG_MO_ALL | TCG_BAR_STRL);
+        }
+        ir.gen_brcond(Type::I64, addr, self.load_res, Cond::Ne, fail);
+        let cur = ir.new_temp(Type::I64);
+        ir.gen_qemu_ld(Type::I64, cur, addr, memop.bits() as u32);
+        ir.gen_brcond(Type::I64, cur, self.load_val, Cond::Ne, fail);
         let src2 = self.gpr_or_zero(ir, a.rs2);
         ir.gen_qemu_st(Type::I64, src2, addr, memop.bits() as u32);
+        if a.aq != 0 {
+            ir.gen_mb(TCG_MO_ALL | TCG_BAR_LDAQ);
+        }
         let zero = ir.new_const(Type::I64, 0);
         self.gen_set_gpr(ir, a.rd, zero);
+        ir.gen_br(done);
+
+        ir.gen_set_label(fail);
+        let one = ir.new_const(Type::I64, 1);
+        self.gen_set_gpr(ir, a.rd, one);
 
-        // Clear reservation.
+        ir.gen_set_label(done);
         let neg1 = ir.new_const(Type::I64, u64::MAX);
         ir.gen_mov(Type::I64, self.load_res, neg1);
         true
@@ -709,13 +789,15 @@ impl RiscvDisasContext {
 
     /// AMO: atomic read-modify-write (single-thread: ld+op+st).
     fn gen_amo(
-        &self,
+        &mut self,
         ir: &mut Context,
         a: &ArgsAtomic,
         op: BinOp,
         memop: MemOp,
     ) -> bool {
+        let memop = memop.aligned().atomic();
         let addr = self.gpr_or_zero(ir, a.rs1);
+        let addr = self.gen_align_check(ir, addr, memop, true);
         if a.rl != 0 {
             ir.gen_mb(TCG_MO_ALL | TCG_BAR_STRL);
         }
@@ -734,12 +816,14 @@ impl RiscvDisasContext {
 
     /// AMO swap: store rs2, return old value.
     fn gen_amo_swap(
-        &self,
+        &mut self,
         ir: &mut Context,
         a: &ArgsAtomic,
         memop: MemOp,
     ) -> bool {
+        let memop = memop.aligned().atomic();
         let addr = self.gpr_or_zero(ir, a.rs1);
+        let addr = self.gen_align_check(ir, addr, memop, true);
         if a.rl != 0 {
             ir.gen_mb(TCG_MO_ALL | TCG_BAR_STRL);
         }
@@ -756,19 +840,28 @@ impl RiscvDisasContext {
 
     /// AMO min/max: conditional select via movcond.
     fn gen_amo_minmax(
-        &self,
+        &mut self,
         ir: &mut Context,
         a: &ArgsAtomic,
         cond: Cond,
         memop: MemOp,
     ) -> bool {
+        let memop = memop.aligned().atomic();
         let addr = self.gpr_or_zero(ir, a.rs1);
+        let addr = self.gen_align_check(ir, addr, memop, true);
         if a.rl != 0 {
             ir.gen_mb(TCG_MO_ALL | TCG_BAR_STRL);
         }
         let old = ir.new_temp(Type::I64);
         ir.gen_qemu_ld(Type::I64, old, addr, memop.bits() as u32);
-        let src2 = self.gpr_or_zero(ir, a.rs2);
+        let mut src2 = self.gpr_or_zero(ir, a.rs2);
+        if memop.size_bytes() == 4 {
+            // Compare words: `old` was loaded sign-extended, so
+            // extend rs2's low word the same way.
+            let sx = ir.new_temp(Type::I64);
+            ir.gen_ext_i32_i64(sx, src2);
+            src2 = sx;
+        }
         let new = ir.new_temp(Type::I64);
         // new = (old cond src2) ? old : src2
         ir.gen_movcond(Type::I64, new, old, src2, old, src2, cond);
@@ -782,6 +875,41 @@ impl RiscvDisasContext {
 
     // -- Branch helper -------------------------------------
 
+    /// Set PC to `dest` and leave through chain slot `n`.
+    ///
+    /// In single-step mode the TB must not chain, so the exit
+    /// goes back to the exec loop instead.
+    /// Low target bits that must be clear: instructions are
+    /// 2-byte aligned with C, 4-byte aligned without.
+    fn fetch_align_mask(&self) -> u64 {
+        if self.cfg.misa.contains(MisaExt::C) {
+            1
+        } else {
+            3
+        }
+    }
+
+    /// Raise instruction-address-misaligned for a jump to
+    /// `target`, reported at the jump itself.
+    fn gen_fetch_misaligned(&mut self, ir: &mut Context, target: TempIdx) {
+        ir.gen_st(Type::I64, target, self.env, UTVAL_OFFSET);
+        let pc = ir.new_const(Type::I64, self.base.pc_next);
+        ir.gen_mov(Type::I64, self.pc, pc);
+        self.gen_uncount_rest(ir, self.base.num_insns - 1);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_FETCH_MISALIGNED));
+    }
+
+    pub(super) fn gen_goto_tb(&self, ir: &mut Context, n: u32, dest: u64) {
+        let c = ir.new_const(Type::I64, dest);
+        ir.gen_mov(Type::I64, self.pc, c);
+        if self.base.single_step {
+            ir.gen_exit_tb(TbExit::Normal);
+        } else {
+            ir.gen_goto_tb(n);
+            ir.gen_exit_tb(TbExit::Chain(n as usize));
+        }
+    }
+
     /// Conditional branch that terminates the TB.
     fn gen_branch(&mut self, ir: &mut Context, a: &ArgsB, cond: Cond) {
         let src1 = self.gpr_or_zero(ir, a.rs1);
@@ -792,26 +920,54 @@ impl RiscvDisasContext {
 
         // Not taken: PC = next insn, return chain slot 0.
         let next_pc = self.base.pc_next + self.cur_insn_len as u64;
-        let c = ir.new_const(Type::I64, next_pc);
-        ir.gen_mov(Type::I64, self.pc, c);
-        ir.gen_goto_tb(0);
-        ir.gen_exit_tb(TB_EXIT_IDX0);
+        self.gen_goto_tb(ir, 0, next_pc);
 
         // Taken: PC = branch target, return chain slot 1.
         ir.gen_set_label(taken);
         let target = (self.base.pc_next as i64 + a.imm) as u64;
-        let c = ir.new_const(Type::I64, target);
-        ir.gen_mov(Type::I64, self.pc, c);
-        ir.gen_goto_tb(1);
-        ir.gen_exit_tb(TB_EXIT_IDX1);
+        if target & self.fetch_align_mask() != 0 {
+            let t = ir.new_const(Type::I64, target);
+            self.gen_fetch_misaligned(ir, t);
+        } else {
+            self.gen_goto_tb(ir, 1, target);
+            self.spin.note_branch(target, self.base.pc_first);
+        }
 
         self.base.is_jmp = DisasJumpType::NoReturn;
     }
+
+    /// A decoded pattern with no translation yet: log its name
+    /// and `.decode` line, and raise an illegal-instruction
+    /// exception.
+    fn unimplemented_insn(
+        &self,
+        name: &str,
+        insn: u32,
+        source: Option<(&str, u32)>,
+    ) -> bool {
+        let at = source
+            .map(|(file, line)| format!(", {file}:{line}"))
+            .unwrap_or_default();
+        eprintln!(
+            "[tcg] unimplemented instruction {name} ({insn:#x}) \
+             at pc={:#x}{at}",
+            self.base.pc_next
+        );
+        false
+    }
 }
 
 // ── Decode trait implementation ────────────────────────────────
 
 impl Decode<Context> for RiscvDisasContext {
+    fn insn(&self) -> u32 {
+        self.opcode
+    }
+
+    fn unimplemented(&mut self, name: &'static str, insn: u32) -> bool {
+        self.unimplemented_insn(name, insn, pattern_source(name))
+    }
+
     // ── RV32I: Upper immediate ─────────────────────────
 
     fn trans_lui(&mut self, ir: &mut Context, a: &ArgsU) -> bool {
@@ -831,14 +987,16 @@ impl Decode<Context> for RiscvDisasContext {
 
     fn trans_jal(&mut self, ir: &mut Context, a: &ArgsJ) -> bool {
         let link = self.base.pc_next + self.cur_insn_len as u64;
-        let c = ir.new_const(Type::I64, link);
-        self.gen_set_gpr(ir, a.rd, c);
         let target = (self.base.pc_next as i64 + a.imm) as u64;
-        let c = ir.new_const(Type::I64, target);
-        ir.gen_mov(Type::I64, self.pc, c);
-        ir.gen_goto_tb(0);
-        ir.gen_exit_tb(TB_EXIT_IDX0);
         self.base.is_jmp = DisasJumpType::NoReturn;
+        if target & self.fetch_align_mask() != 0 {
+            let t = ir.new_const(Type::I64, target);
+            self.gen_fetch_misaligned(ir, t);
+            return true;
+        }
+        let c = ir.new_const(Type::I64, link);
+        self.gen_set_gpr(ir, a.rd, c);
+        self.gen_goto_tb(ir, 0, target);
         true
     }
 
@@ -846,15 +1004,32 @@ impl Decode<Context> for RiscvDisasContext {
         let link = self.base.pc_next + self.cur_insn_len as u64;
         let src = self.gpr_or_zero(ir, a.rs1);
         let imm = ir.new_const(Type::I64, a.imm as u64);
-        let tmp = ir.new_temp(Type::I64);
+        // Bit 0 is cleared below, so only bit 1 (without C) is
+        // checked, and the target then lives across a branch.
+        let align = self.fetch_align_mask() & !1;
+        let tmp = if align != 0 {
+            ir.new_temp_tb(Type::I64)
+        } else {
+            ir.new_temp(Type::I64)
+        };
         ir.gen_add(Type::I64, tmp, src, imm);
         // Clear bit 0
         let mask = ir.new_const(Type::I64, !1u64);
         ir.gen_and(Type::I64, tmp, tmp, mask);
+        if align != 0 {
+            let m = ir.new_const(Type::I64, align);
+            let low = ir.new_temp(Type::I64);
+            ir.gen_and(Type::I64, low, tmp, m);
+            let zero = ir.new_const(Type::I64, 0);
+            let ok = ir.new_label();
+            ir.gen_brcond(Type::I64, low, zero, Cond::Eq, ok);
+            self.gen_fetch_misaligned(ir, tmp);
+            ir.gen_set_label(ok);
+        }
         let c = ir.new_const(Type::I64, link);
         self.gen_set_gpr(ir, a.rd, c);
         ir.gen_mov(Type::I64, self.pc, tmp);
-        ir.gen_exit_tb(TB_EXIT_NOCHAIN);
+        ir.gen_exit_tb(TbExit::Normal);
         self.base.is_jmp = DisasJumpType::NoReturn;
         true
     }
@@ -958,7 +1133,7 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_arith(ir, a, Context::gen_sub)
     }
     fn trans_sll(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
-        self.gen_arith(ir, a, Context::gen_shl)
+        self.gen_shift(ir, a, Context::gen_shl)
     }
     fn trans_slt(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
         self.gen_setcond_rr(ir, a, Cond::Lt)
@@ -970,10 +1145,10 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_arith(ir, a, Context::gen_xor)
     }
     fn trans_srl(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
-        self.gen_arith(ir, a, Context::gen_shr)
+        self.gen_shift(ir, a, Context::gen_shr)
     }
     fn trans_sra(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
-        self.gen_arith(ir, a, Context::gen_sar)
+        self.gen_shift(ir, a, Context::gen_sar)
     }
     fn trans_or(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
         self.gen_arith(ir, a, Context::gen_or)
@@ -988,10 +1163,74 @@ impl Decode<Context> for RiscvDisasContext {
         true // NOP for user-mode
     }
 
+    // ── Zicbop / Zihintntl: hints ──────────────────────
+    //
+    // Both are HINTs in the base ISA (ORI / ADD to x0), so they
+    // stay no-ops with the extensions disabled.
+
+    fn trans_prefetch_i(&mut self, _ir: &mut Context, _a: &ArgsS) -> bool {
+        true
+    }
+    fn trans_prefetch_r(&mut self, _ir: &mut Context, _a: &ArgsS) -> bool {
+        true
+    }
+    fn trans_prefetch_w(&mut self, _ir: &mut Context, _a: &ArgsS) -> bool {
+        true
+    }
+    fn trans_ntl_p1(&mut self, _ir: &mut Context, _a: &ArgsEmpty) -> bool {
+        true
+    }
+    fn trans_ntl_pall(&mut self, _ir: &mut Context, _a: &ArgsEmpty) -> bool {
+        true
+    }
+    fn trans_ntl_s1(&mut self, _ir: &mut Context, _a: &ArgsEmpty) -> bool {
+        true
+    }
+    fn trans_ntl_all(&mut self, _ir: &mut Context, _a: &ArgsEmpty) -> bool {
+        true
+    }
+
+    // ── Zicbom / Zicboz: cache-block operations ────────
+
+    // Memory is coherent for a user-mode guest.
+    fn trans_cbo_inval(&mut self, _ir: &mut Context, _a: &ArgsCbo) -> bool {
+        require_cfg!(self, ext_zicbom);
+        true
+    }
+    fn trans_cbo_clean(&mut self, _ir: &mut Context, _a: &ArgsCbo) -> bool {
+        require_cfg!(self, ext_zicbom);
+        true
+    }
+    fn trans_cbo_flush(&mut self, _ir: &mut Context, _a: &ArgsCbo) -> bool {
+        require_cfg!(self, ext_zicbom);
+        true
+    }
+
+    /// Zero the `cbo_block_size` block holding `rs1`.
+    fn trans_cbo_zero(&mut self, ir: &mut Context, a: &ArgsCbo) -> bool {
+        require_cfg!(self, ext_zicboz);
+        let size = self.cfg.cbo_block_size as u64;
+        assert!(size.is_power_of_two() && (8..=512).contains(&size));
+        let base = self.gpr_or_zero(ir, a.rs1);
+        let mask = ir.new_const(Type::I64, !(size - 1));
+        let addr = ir.new_temp(Type::I64);
+        ir.gen_and(Type::I64, addr, base, mask);
+        let zero = ir.new_const(Type::I64, 0);
+        let memop = MemOp::uq().bits() as u32;
+        ir.gen_qemu_st(Type::I64, zero, addr, memop);
+        for off in (8..size).step_by(8) {
+            let off = ir.new_const(Type::I64, off);
+            let t = ir.new_temp(Type::I64);
+            ir.gen_add(Type::I64, t, addr, off);
+            ir.gen_qemu_st(Type::I64, zero, t, memop);
+        }
+        true
+    }
+
     fn trans_ecall(&mut self, ir: &mut Context, _a: &ArgsEmpty) -> bool {
         let pc = ir.new_const(Type::I64, self.base.pc_next);
         ir.gen_mov(Type::I64, self.pc, pc);
-        ir.gen_exit_tb(EXCP_ECALL);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_ECALL));
         self.base.is_jmp = DisasJumpType::NoReturn;
         true
     }
@@ -999,7 +1238,7 @@ impl Decode<Context> for RiscvDisasContext {
     fn trans_ebreak(&mut self, ir: &mut Context, _a: &ArgsEmpty) -> bool {
         let pc = ir.new_const(Type::I64, self.base.pc_next);
         ir.gen_mov(Type::I64, self.pc, pc);
-        ir.gen_exit_tb(EXCP_EBREAK);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_EBREAK));
         self.base.is_jmp = DisasJumpType::NoReturn;
         true
     }
@@ -1070,14 +1309,7 @@ impl Decode<Context> for RiscvDisasContext {
         let s2 = self.gpr_or_zero(ir, a.rs2);
         let lo = ir.new_temp(Type::I64);
         let hi = ir.new_temp(Type::I64);
-        ir.gen_mulu2(Type::I64, lo, hi, s1, s2);
-        // Correction: high -= (s1 >> 63) & s2
-        let c63 = ir.new_const(Type::I64, 63);
-        let sign = ir.new_temp(Type::I64);
-        ir.gen_sar(Type::I64, sign, s1, c63);
-        let adj = ir.new_temp(Type::I64);
-        ir.gen_and(Type::I64, adj, sign, s2);
-        ir.gen_sub(Type::I64, hi, hi, adj);
+        ir.gen_mulsu2(Type::I64, lo, hi, s1, s2);
         self.gen_set_gpr(ir, a.rd, hi);
         true
     }
@@ -1140,6 +1372,165 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_divu_remu_w(ir, a, true)
     }
 
+    // ── Zbb: Basic bit manipulation ───────────────────
+
+    fn trans_andn(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_arith(ir, a, Context::gen_andc)
+    }
+    fn trans_orn(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_arith(ir, a, |ir, ty, d, s1, s2| {
+            let t = ir.new_temp(ty);
+            ir.gen_not(ty, t, s2);
+            ir.gen_or(ty, d, s1, t)
+        })
+    }
+    fn trans_xnor(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_arith(ir, a, |ir, ty, d, s1, s2| {
+            ir.gen_xor(ty, d, s1, s2);
+            ir.gen_not(ty, d, d)
+        })
+    }
+    fn trans_min(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_minmax(ir, a, Cond::Lt)
+    }
+    fn trans_minu(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_minmax(ir, a, Cond::Ltu)
+    }
+    fn trans_max(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_minmax(ir, a, Cond::Gt)
+    }
+    fn trans_maxu(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_minmax(ir, a, Cond::Gtu)
+    }
+    fn trans_rol(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shift(ir, a, Context::gen_rotl)
+    }
+    fn trans_ror(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shift(ir, a, Context::gen_rotr)
+    }
+    fn trans_rori(&mut self, ir: &mut Context, a: &ArgsShift) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shift_imm(ir, a, Context::gen_rotr)
+    }
+    fn trans_clz(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let w = ir.new_const(Type::I64, 64);
+            let d = ir.new_temp(Type::I64);
+            ir.gen_clz(Type::I64, d, s, w)
+        })
+    }
+    fn trans_ctz(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let w = ir.new_const(Type::I64, 64);
+            let d = ir.new_temp(Type::I64);
+            ir.gen_ctz(Type::I64, d, s, w)
+        })
+    }
+    fn trans_cpop(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let d = ir.new_temp(Type::I64);
+            ir.gen_ctpop(Type::I64, d, s)
+        })
+    }
+    fn trans_sext_b(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let d = ir.new_temp(Type::I64);
+            ir.gen_sextract(Type::I64, d, s, 0, 8)
+        })
+    }
+    fn trans_sext_h(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let d = ir.new_temp(Type::I64);
+            ir.gen_sextract(Type::I64, d, s, 0, 16)
+        })
+    }
+    fn trans_zext_h(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let d = ir.new_temp(Type::I64);
+            ir.gen_extract(Type::I64, d, s, 0, 16)
+        })
+    }
+    /// Each byte becomes 0xff if non-zero: set its msb if any
+    /// bit is set, move the msb to the lsb, then fill the byte.
+    fn trans_orc_b(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let low7 = ir.new_const(Type::I64, 0x7f7f_7f7f_7f7f_7f7f);
+            let t = ir.new_temp(Type::I64);
+            ir.gen_and(Type::I64, t, s, low7);
+            ir.gen_add(Type::I64, t, t, low7);
+            ir.gen_or(Type::I64, t, t, s);
+            ir.gen_andc(Type::I64, t, t, low7);
+            let c7 = ir.new_const(Type::I64, 7);
+            ir.gen_shr(Type::I64, t, t, c7);
+            let cff = ir.new_const(Type::I64, 0xff);
+            let d = ir.new_temp(Type::I64);
+            ir.gen_mul(Type::I64, d, t, cff)
+        })
+    }
+    fn trans_rev8(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let d = ir.new_temp(Type::I64);
+            ir.gen_bswap64(Type::I64, d, s, 0)
+        })
+    }
+
+    // ── Zbb: W-suffix (RV64) ──────────────────────────
+
+    fn trans_rolw(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shiftw(ir, a, Context::gen_rotl)
+    }
+    fn trans_rorw(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shiftw(ir, a, Context::gen_rotr)
+    }
+    fn trans_roriw(&mut self, ir: &mut Context, a: &ArgsShift) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_shift_imm_w(ir, a, Context::gen_rotr)
+    }
+    fn trans_clzw(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary_w(ir, a, |ir, s| {
+            let w = ir.new_const(Type::I32, 32);
+            let d = ir.new_temp(Type::I32);
+            ir.gen_clz(Type::I32, d, s, w)
+        })
+    }
+    fn trans_ctzw(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary_w(ir, a, |ir, s| {
+            let w = ir.new_const(Type::I32, 32);
+            let d = ir.new_temp(Type::I32);
+            ir.gen_ctz(Type::I32, d, s, w)
+        })
+    }
+    fn trans_cpopw(&mut self, ir: &mut Context, a: &ArgsR2) -> bool {
+        require_cfg!(self, ext_zbb);
+        self.gen_unary(ir, a, |ir, s| {
+            let t = ir.new_temp(Type::I64);
+            ir.gen_ext_u32_i64(t, s);
+            let d = ir.new_temp(Type::I64);
+            ir.gen_ctpop(Type::I64, d, t)
+        })
+    }
+
     // ── RV32A: Atomic ─────────────────────────────────────
 
     fn trans_lr_w(&mut self, ir: &mut Context, a: &ArgsAtomic) -> bool {
@@ -1148,7 +1539,7 @@ impl Decode<Context> for RiscvDisasContext {
     }
     fn trans_sc_w(&mut self, ir: &mut Context, a: &ArgsAtomic) -> bool {
         require_ext!(self, MisaExt::A);
-        self.gen_sc(ir, a, MemOp::ul())
+        self.gen_sc(ir, a, MemOp::sl())
     }
     fn trans_amoswap_w(&mut self, ir: &mut Context, a: &ArgsAtomic) -> bool {
         require_ext!(self, MisaExt::A);
@@ -1247,6 +1638,7 @@ impl Decode<Context> for RiscvDisasContext {
             return false;
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1265,6 +1657,7 @@ impl Decode<Context> for RiscvDisasContext {
             }
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1285,6 +1678,7 @@ impl Decode<Context> for RiscvDisasContext {
             }
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1299,6 +1693,7 @@ impl Decode<Context> for RiscvDisasContext {
             return false;
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1317,6 +1712,7 @@ impl Decode<Context> for RiscvDisasContext {
             }
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1337,6 +1733,7 @@ impl Decode<Context> for RiscvDisasContext {
             }
         }
         self.gen_set_gpr(ir, a.rd, old);
+        self.gen_csr_end_tb(a.csr);
         true
     }
 
@@ -1369,9 +1766,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmadd_s as usize,
+            fpu::helper_fmadd_s as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1385,9 +1782,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmsub_s as usize,
+            fpu::helper_fmsub_s as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1401,9 +1798,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fnmsub_s as usize,
+            fpu::helper_fnmsub_s as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1417,9 +1814,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fnmadd_s as usize,
+            fpu::helper_fnmadd_s as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1435,9 +1832,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fadd_s as usize,
+            fpu::helper_fadd_s as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1450,9 +1847,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsub_s as usize,
+            fpu::helper_fsub_s as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1465,9 +1862,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmul_s as usize,
+            fpu::helper_fmul_s as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1480,9 +1877,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fdiv_s as usize,
+            fpu::helper_fdiv_s as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1494,9 +1891,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsqrt_s as usize,
+            fpu::helper_fsqrt_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1509,9 +1906,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnj_s as usize,
+            fpu::helper_fsgnj_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1523,9 +1920,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnjn_s as usize,
+            fpu::helper_fsgnjn_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1537,9 +1934,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnjx_s as usize,
+            fpu::helper_fsgnjx_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1551,9 +1948,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmin_s as usize,
+            fpu::helper_fmin_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1565,9 +1962,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmax_s as usize,
+            fpu::helper_fmax_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1579,9 +1976,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_feq_s as usize,
+            fpu::helper_feq_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1592,9 +1989,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_flt_s as usize,
+            fpu::helper_flt_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1605,9 +2002,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fle_s as usize,
+            fpu::helper_fle_s as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1618,9 +2015,9 @@ impl Decode<Context> for RiscvDisasContext {
         require_ext!(self, MisaExt::F);
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fclass_s as usize,
+            fpu::helper_fclass_s as *const () as usize,
             &[self.env, rs1],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1634,9 +2031,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_w_s as usize,
+            fpu::helper_fcvt_w_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1647,9 +2044,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_wu_s as usize,
+            fpu::helper_fcvt_wu_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1661,9 +2058,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_s_w as usize,
+            fpu::helper_fcvt_s_w as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1675,9 +2072,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_s_wu as usize,
+            fpu::helper_fcvt_s_wu as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1716,9 +2113,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_l_s as usize,
+            fpu::helper_fcvt_l_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1729,9 +2126,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_lu_s as usize,
+            fpu::helper_fcvt_lu_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1743,9 +2140,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_s_l as usize,
+            fpu::helper_fcvt_s_l as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1757,9 +2154,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_s_lu as usize,
+            fpu::helper_fcvt_s_lu as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1776,9 +2173,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmadd_d as usize,
+            fpu::helper_fmadd_d as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1792,9 +2189,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmsub_d as usize,
+            fpu::helper_fmsub_d as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1808,9 +2205,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fnmsub_d as usize,
+            fpu::helper_fnmsub_d as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1824,9 +2221,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs2 = self.fpr_load(ir, a.rs2);
         let rs3 = self.fpr_load(ir, a.rs3);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fnmadd_d as usize,
+            fpu::helper_fnmadd_d as *const () as usize,
             &[self.env, rs1, rs2, rs3, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1842,9 +2239,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fadd_d as usize,
+            fpu::helper_fadd_d as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1857,9 +2254,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsub_d as usize,
+            fpu::helper_fsub_d as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1872,9 +2269,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmul_d as usize,
+            fpu::helper_fmul_d as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1887,9 +2284,9 @@ impl Decode<Context> for RiscvDisasContext {
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fdiv_d as usize,
+            fpu::helper_fdiv_d as *const () as usize,
             &[self.env, rs1, rs2, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1901,9 +2298,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsqrt_d as usize,
+            fpu::helper_fsqrt_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1916,9 +2313,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnj_d as usize,
+            fpu::helper_fsgnj_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1930,9 +2327,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnjn_d as usize,
+            fpu::helper_fsgnjn_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1944,9 +2341,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fsgnjx_d as usize,
+            fpu::helper_fsgnjx_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1958,9 +2355,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmin_d as usize,
+            fpu::helper_fmin_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1972,9 +2369,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fmax_d as usize,
+            fpu::helper_fmax_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.fpr_store(ir, a.rd, res);
@@ -1986,9 +2383,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_feq_d as usize,
+            fpu::helper_feq_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -1999,9 +2396,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_flt_d as usize,
+            fpu::helper_flt_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2012,9 +2409,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rs2 = self.fpr_load(ir, a.rs2);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fle_d as usize,
+            fpu::helper_fle_d as *const () as usize,
             &[self.env, rs1, rs2],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2025,9 +2422,9 @@ impl Decode<Context> for RiscvDisasContext {
         require_ext!(self, MisaExt::D);
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fclass_d as usize,
+            fpu::helper_fclass_d as *const () as usize,
             &[self.env, rs1],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2042,9 +2439,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_s_d as usize,
+            fpu::helper_fcvt_s_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2056,9 +2453,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_d_s as usize,
+            fpu::helper_fcvt_d_s as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2069,9 +2466,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_w_d as usize,
+            fpu::helper_fcvt_w_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2082,9 +2479,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_wu_d as usize,
+            fpu::helper_fcvt_wu_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2096,9 +2493,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_d_w as usize,
+            fpu::helper_fcvt_d_w as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2110,9 +2507,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_d_wu as usize,
+            fpu::helper_fcvt_d_wu as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2126,9 +2523,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_l_d as usize,
+            fpu::helper_fcvt_l_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2139,9 +2536,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_fp_check(ir);
         let rs1 = self.fpr_load(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_lu_d as usize,
+            fpu::helper_fcvt_lu_d as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.gen_set_gpr(ir, a.rd, res);
@@ -2153,9 +2550,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_d_l as usize,
+            fpu::helper_fcvt_d_l as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2167,9 +2564,9 @@ impl Decode<Context> for RiscvDisasContext {
         self.gen_set_fs_dirty(ir);
         let rs1 = self.gpr_or_zero(ir, a.rs1);
         let rm = ir.new_const(Type::I64, a.rm as u64);
-        let res = self.gen_helper_call(
+        let res = self.gen_fpu_call(
             ir,
-            fpu::helper_fcvt_d_lu as usize,
+            fpu::helper_fcvt_d_lu as *const () as usize,
             &[self.env, rs1, rm],
         );
         self.fpr_store(ir, a.rd, res);
@@ -2199,6 +2596,14 @@ impl Decode<Context> for RiscvDisasContext {
 // equivalents, so we delegate to the Decode impl.
 
 impl Decode16<Context> for RiscvDisasContext {
+    fn insn(&self) -> u32 {
+        self.opcode
+    }
+
+    fn unimplemented(&mut self, name: &'static str, insn: u32) -> bool {
+        self.unimplemented_insn(name, insn, pattern_source16(name))
+    }
+
     fn trans_illegal(&mut self, _ir: &mut Context, _a: &ArgsEmpty) -> bool {
         false
     }
@@ -2314,4 +2719,20 @@ impl Decode16<Context> for RiscvDisasContext {
     fn trans_add(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
         <Self as Decode<Context>>::trans_add(self, ir, a)
     }
+
+    fn trans_ntl_p1(&mut self, ir: &mut Context, a: &ArgsEmpty) -> bool {
+        <Self as Decode<Context>>::trans_ntl_p1(self, ir, a)
+    }
+
+    fn trans_ntl_pall(&mut self, ir: &mut Context, a: &ArgsEmpty) -> bool {
+        <Self as Decode<Context>>::trans_ntl_pall(self, ir, a)
+    }
+
+    fn trans_ntl_s1(&mut self, ir: &mut Context, a: &ArgsEmpty) -> bool {
+        <Self as Decode<Context>>::trans_ntl_s1(self, ir, a)
+    }
+
+    fn trans_ntl_all(&mut self, ir: &mut Context, a: &ArgsEmpty) -> bool {
+        <Self as Decode<Context>>::trans_ntl_all(self, ir, a)
+    }
 }
@@ -0,0 +1,238 @@
+//! In-process fuzzing with snapshot and restore.
+//!
+//! The target is a small hand-assembled RISC-V routine that
+//! reads its input from a guest buffer: it crashes (`ebreak`)
+//! on input starting with "FUZ", spins forever on input
+//! starting with 'H', and exits otherwise. The harness
+//! snapshots the guest once it is set up, then for every input
+//! writes it into the buffer, runs to exit, crash or hang,
+//! keeps the input if it reached new blocks, and restores.
+//!
+//! There is no instruction budget in the exec loop: a hang is
+//! seen only when the spin detector yields.
+//!
+//! Usage: `cargo run --release -p tcg-linux-user --example
+//! snapshot_fuzz [EXECS]`
+
+use std::collections::HashSet;
+use std::env;
+use std::time::Instant;
+
+use tcg_backend::NativeCodeGen;
+use tcg_core::context::Context;
+use tcg_core::tb::{TbExit, TranslationInfo};
+use tcg_exec::coverage::Coverage;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ChainPolicy, ExecEnv, GuestCpu};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{EXCP_EBREAK, EXCP_ECALL};
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
+use tcg_frontend::translator_loop;
+use tcg_linux_user::guest_space::{page_size, GuestSpace};
+
+const CODE: u64 = 0x10000;
+/// Input buffer.
+const INPUT: u64 = 0x20000;
+const INPUT_LEN: usize = 8;
+
+struct FuzzCpu {
+    cpu: RiscvCpu,
+}
+
+impl GuestCpu for FuzzCpu {
+    fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    fn get_flags(&self) -> u32 {
+        0
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
+        let base = self.cpu.guest_base as *const u8;
+        let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
+        d.base.max_insns = max_insns;
+        d.base.set_tb_flags(flags);
+        translator_loop::<RiscvTranslator>(&mut d, ir)
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        &mut self.cpu as *mut RiscvCpu as *mut u8
+    }
+}
+
+fn i_type(op: u32, f3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op
+}
+
+fn b_type(f3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 12 & 1) << 31)
+        | ((imm >> 5 & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (f3 << 12)
+        | ((imm >> 1 & 0xf) << 8)
+        | ((imm >> 11 & 1) << 7)
+        | 0b1100011
+}
+
+fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b0010011, 0b000, rd, rs1, imm)
+}
+
+fn lbu(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b0000011, 0b100, rd, rs1, imm)
+}
+
+fn ld(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0b0000011, 0b011, rd, rs1, imm)
+}
+
+fn beq(rs1: u32, rs2: u32, imm: i32) -> u32 {
+    b_type(0b000, rs1, rs2, imm)
+}
+
+fn bne(rs1: u32, rs2: u32, imm: i32) -> u32 {
+    b_type(0b001, rs1, rs2, imm)
+}
+
+fn target() -> Vec<u32> {
+    const LUI_S0: u32 = INPUT as u32 | 8 << 7 | 0b0110111;
+    vec![
+        LUI_S0,                  // 0: s0 = INPUT
+        lbu(5, 8, 0),            // 4
+        addi(6, 0, b'H' as i32), // 8
+        beq(5, 6, 48),           // 12: -> 60
+        addi(6, 0, b'F' as i32), // 16
+        bne(5, 6, 32),           // 20: -> 52
+        lbu(5, 8, 1),            // 24
+        addi(6, 0, b'U' as i32), // 28
+        bne(5, 6, 20),           // 32: -> 52
+        lbu(5, 8, 2),            // 36
+        addi(6, 0, b'Z' as i32), // 40
+        bne(5, 6, 8),            // 44: -> 52
+        0x0010_0073,             // 48: ebreak
+        addi(17, 0, 93),         // 52: exit
+        0x0000_0073,             // 56: ecall
+        ld(7, 8, 2040),          // 60: spin on a zero word
+        beq(7, 0, -4),           // 64: -> 60
+        0x0000_0073,             // 68
+    ]
+}
+
+enum Outcome {
+    Exit,
+    Crash(u64),
+    Hang,
+}
+
+/// xorshift64, enough to pick mutations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn main() {
+    let execs: u64 = env::args()
+        .nth(1)
+        .map(|s| s.parse().expect("EXECS must be a number"))
+        .unwrap_or(100_000);
+
+    let ps = page_size();
+    let mut space = GuestSpace::new().expect("guest space");
+    let code: Vec<u8> = target().iter().flat_map(|i| i.to_le_bytes()).collect();
+    let rw = libc::PROT_READ | libc::PROT_WRITE;
+    space.mmap_fixed(CODE, ps, rw).expect("map code");
+    unsafe { space.write_bytes(CODE, &code) };
+    space
+        .mprotect(CODE, ps, libc::PROT_READ | libc::PROT_EXEC)
+        .expect("protect code");
+    space.mmap_fixed(INPUT, ps, rw).expect("map input");
+
+    let mut cpu = FuzzCpu {
+        cpu: RiscvCpu::new(),
+    };
+    cpu.cpu.guest_base = space.guest_base() as u64;
+    cpu.cpu.pc = CODE;
+    // Unchained, every block run is counted in the coverage.
+    let mut env = ExecEnv::new(NativeCodeGen::new())
+        .with_chain_policy(ChainPolicy::Never)
+        .with_spin_yield(1000);
+    let snap = env.snapshot(&cpu.cpu, &mut space).expect("snapshot");
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut corpus: Vec<Vec<u8>> = vec![b"aaaa".to_vec()];
+    let mut seen = HashSet::new();
+    let (mut crashes, mut hangs, mut pages) = (0u64, 0u64, 0u64);
+    let mut first_crash = None;
+    let start = Instant::now();
+    for _ in 0..execs {
+        let mut input = corpus[rng.below(corpus.len())].clone();
+        input.resize(INPUT_LEN, 0);
+        let i = rng.below(INPUT_LEN);
+        input[i] = rng.next() as u8;
+
+        unsafe { space.write_bytes(INPUT, &input) };
+        env.per_cpu.coverage = Some(Coverage::new());
+        let outcome = match unsafe { cpu_exec_loop(&mut env, &mut cpu) } {
+            ExitReason::Exit(TbExit::Exception(EXCP_ECALL)) => Outcome::Exit,
+            ExitReason::Exit(TbExit::Exception(EXCP_EBREAK)) => {
+                Outcome::Crash(cpu.cpu.pc)
+            }
+            ExitReason::Yield => Outcome::Hang,
+            r => panic!("unexpected exit {r:?} at pc={:#x}", cpu.cpu.pc),
+        };
+        let blocks = env.per_cpu.coverage.take().unwrap().blocks();
+        let mut new = false;
+        for b in blocks {
+            new |= seen.insert((b.start, b.end));
+        }
+        match outcome {
+            Outcome::Exit => {}
+            Outcome::Crash(pc) => {
+                crashes += 1;
+                first_crash.get_or_insert((input.clone(), pc));
+            }
+            Outcome::Hang => hangs += 1,
+        }
+        if new {
+            corpus.push(input);
+        }
+        let stats = env
+            .restore(&mut cpu.cpu, &mut space, &snap)
+            .expect("restore");
+        pages += stats.pages as u64;
+    }
+    let secs = start.elapsed().as_secs_f64();
+
+    println!("execs:    {execs} ({:.0}/s)", execs as f64 / secs);
+    println!("blocks:   {}", seen.len());
+    println!("corpus:   {}", corpus.len());
+    println!("crashes:  {crashes}");
+    println!("hangs:    {hangs}");
+    println!(
+        "restored: {:.2} pages/exec",
+        pages as f64 / execs.max(1) as f64
+    );
+    if let Some((input, pc)) = first_crash {
+        println!("first crash at pc={pc:#x}: \"{}\"", input.escape_ascii());
+    }
+}
@@ -0,0 +1,889 @@
+//! Checkpoints of a guest run on disk, and resuming from them.
+//!
+//! A checkpoint directory holds a `state` file and the memory
+//! images it names, `mem-<seq>`. The state file has the CPU
+//! registers, the region tree, brk and stack, the guest fds,
+//! signal dispositions and process ids, the random stream of a
+//! deterministic run, and how the run was started. The memory
+//! images form a chain: a full image of every readable region
+//! (all-zero pages left out), then increments holding the
+//! regions mapped or reprotected since the previous checkpoint
+//! and the pages written to, as found by the write tracking
+//! behind snapshots. Every `FULL_EVERY` checkpoints the chain
+//! restarts with a full image and the older images are
+//! deleted.
+//!
+//! Each file is written under a temporary name, synced and
+//! renamed into place, the state file last, so a crash in the
+//! middle of a checkpoint leaves the previous one intact.
+//!
+//! Guest fds are saved by what they refer to. A regular file
+//! or directory is reopened by path with its status flags and
+//! offset; its contents are not saved, so a resumed run sees
+//! writes made after the checkpoint. Emulated devices are
+//! recreated, and a duplicate of inherited stdio is made again
+//! from the resumed process's. Anything else (sockets, pipes,
+//! terminals, deleted files) cannot be reopened: by
+//! `FdPolicy::Refuse` the checkpoint fails and the run goes on,
+//! by `FdPolicy::Drop` the fd is left closed in the resumed
+//! run. Stdio the guest did not replace is the resumed
+//! process's own.
+//!
+//! Translated code is not saved. Time follows retired
+//! instructions in a deterministic run and continues from the
+//! saved `time` CSR otherwise. There is no record/replay log,
+//! so no position in one is saved.
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use tcg_frontend::riscv::cpu::{RiscvCpu, NUM_FPRS, NUM_GPRS};
+
+use crate::guest_space::{page_size, GuestSpace, Layout, Region};
+use crate::machine::EcallMode;
+use crate::process::Process;
+use crate::signal::{GuestSigaction, SignalTable, NSIG};
+use crate::vfs::{Device, Vfs};
+
+/// Name of the state file in a checkpoint directory.
+pub const STATE_FILE: &str = "state";
+
+const STATE_MAGIC: &[u8; 8] = b"TCGCKPT\0";
+const MEM_MAGIC: &[u8; 8] = b"TCGMEM\0\0";
+const VERSION: u32 = 1;
+
+/// Checkpoints from one full memory image to the next.
+pub const FULL_EVERY: usize = 16;
+
+/// CPU words saved after the GPRs and FPRs, `pc` to `time`.
+const CPU_CSRS: usize = 15;
+
+/// Devices by their number in the state file.
+const DEVICES: [Device; 4] =
+    [Device::Null, Device::Zero, Device::Random, Device::URandom];
+
+/// How the run was started; a resumed run keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunInfo {
+    /// Canonical path of the guest ELF or flat image.
+    pub elf_path: String,
+    pub timebase_freq: u64,
+    pub deterministic: bool,
+    pub ecall: EcallMode,
+    pub uart: Option<u64>,
+}
+
+/// What to do with guest fds that cannot be reopened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FdPolicy {
+    /// Fail the checkpoint.
+    #[default]
+    Refuse,
+    /// Leave the fd closed in the resumed run.
+    Drop,
+}
+
+/// The state of a guest run a checkpoint saves.
+pub struct Guest<'a> {
+    pub cpu: &'a RiscvCpu,
+    pub space: &'a mut GuestSpace,
+    pub vfs: &'a Vfs,
+    pub process: &'a Process,
+    pub signals: &'a SignalTable,
+    pub mmap_next: u64,
+    pub run: &'a RunInfo,
+}
+
+/// A checkpoint written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Written {
+    pub seq: u64,
+    /// The memory image is a full one, not an increment.
+    pub full: bool,
+    /// Guest memory bytes in the image.
+    pub mem_bytes: u64,
+}
+
+/// Writes the checkpoints of one run into a directory.
+pub struct Checkpointer {
+    dir: PathBuf,
+    policy: FdPolicy,
+    next_seq: u64,
+    /// Memory images of the latest checkpoint, oldest first.
+    chain: Vec<String>,
+    /// Write tracking id and regions at the latest checkpoint,
+    /// the base of the next increment.
+    prev: Option<(u64, BTreeMap<u64, Region>)>,
+}
+
+impl Checkpointer {
+    /// Write checkpoints into `dir`, creating it if needed. A
+    /// checkpoint already there stays valid until the first
+    /// one written replaces it.
+    pub fn new(dir: &Path, policy: FdPolicy) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut last = 0;
+        for entry in fs::read_dir(dir)? {
+            if let Some(seq) = mem_seq(&entry?.file_name().to_string_lossy()) {
+                last = last.max(seq);
+            }
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            policy,
+            next_seq: last + 1,
+            chain: Vec::new(),
+            prev: None,
+        })
+    }
+
+    /// Save `guest`, which must be stopped at a TB boundary.
+    /// On error the previous checkpoint is left as it was and
+    /// the next one is a full image.
+    pub fn write(&mut self, guest: Guest) -> io::Result<Written> {
+        let mut fds = Vec::new();
+        for fd in guest.vfs.fds() {
+            match save_fd(guest.vfs, fd) {
+                Ok(kind) => fds.push(SavedFd {
+                    fd,
+                    cloexec: is_cloexec(fd),
+                    kind,
+                }),
+                Err(_) if self.policy == FdPolicy::Drop => {}
+                Err(what) => {
+                    return Err(io::Error::other(format!(
+                        "guest fd {fd} is a {what}, which cannot be reopened"
+                    )))
+                }
+            }
+        }
+
+        let seq = self.next_seq;
+        let layout = guest.space.layout();
+        let prev = self.prev.take();
+        let (id, written) =
+            guest.space.track_writes(prev.as_ref().map(|(id, _)| *id))?;
+        let ranges = match (&prev, written) {
+            (Some((_, regions)), Some(written))
+                if self.chain.len() < FULL_EVERY =>
+            {
+                Some(changed_ranges(&layout, regions, &written))
+            }
+            _ => None,
+        };
+        let full = ranges.is_none();
+        let ranges =
+            ranges.unwrap_or_else(|| full_ranges(guest.space, &layout));
+
+        let name = format!("mem-{seq}");
+        let mut mem_bytes = 0;
+        write_atomic(&self.dir, &name, |out| {
+            out.write_all(MEM_MAGIC)?;
+            out.write_all(&seq.to_le_bytes())?;
+            mem_bytes = write_ranges(out, guest.space, &ranges)?;
+            Ok(())
+        })?;
+        self.next_seq += 1;
+
+        let mut chain = if full { Vec::new() } else { self.chain.clone() };
+        chain.push(name);
+        let state = encode_state(seq, &guest, &layout, &chain, &fds);
+        write_atomic(&self.dir, STATE_FILE, |out| out.write_all(&state))?;
+        File::open(&self.dir)?.sync_all()?;
+        self.chain = chain;
+        if full {
+            self.remove_stale()?;
+        }
+        self.prev = Some((id, layout.regions));
+        Ok(Written {
+            seq,
+            full,
+            mem_bytes,
+        })
+    }
+
+    /// Delete images outside the chain and temporary files.
+    fn remove_stale(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stale = name.ends_with(".tmp")
+                || (mem_seq(&name).is_some() && !self.chain.contains(&name));
+            if stale {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A guest run restored from a checkpoint.
+pub struct Resumed {
+    pub seq: u64,
+    pub run: RunInfo,
+    /// `guest_base` points into `space`.
+    pub cpu: RiscvCpu,
+    pub space: GuestSpace,
+    /// Guest fds and random stream; how stdio and statfs look
+    /// is up to the resuming run's configuration.
+    pub vfs: Vfs,
+    pub process: Process,
+    pub signals: SignalTable,
+    pub mmap_next: u64,
+}
+
+/// Restore the checkpoint in `dir`, reopening the guest's fds
+/// at their numbers. Call it before the process opens fds of
+/// its own, which may otherwise be replaced.
+pub fn resume(dir: &Path) -> io::Result<Resumed> {
+    let state = decode_state(&fs::read(dir.join(STATE_FILE))?)?;
+    let space = GuestSpace::from_layout(&state.layout, |space| {
+        for name in &state.chain {
+            load_image(&dir.join(name), space, &state.layout)?;
+        }
+        Ok(())
+    })?;
+    let mut cpu = state.cpu;
+    cpu.guest_base = space.guest_base() as u64;
+
+    let mut fds = Vec::new();
+    for saved in &state.fds {
+        restore_fd(saved).map_err(|e| {
+            io::Error::new(e.kind(), format!("guest fd {}: {e}", saved.fd))
+        })?;
+        let dev = match saved.kind {
+            FdKind::Device(dev) => Some(dev),
+            _ => None,
+        };
+        fds.push((saved.fd, dev));
+    }
+    let mut vfs = Vfs::new(None);
+    vfs.restore(state.entropy, fds);
+
+    let mut signals = SignalTable::new();
+    for (sig, act) in state.signals {
+        signals.set_action(sig, act);
+    }
+    Ok(Resumed {
+        seq: state.seq,
+        run: state.run,
+        cpu,
+        space,
+        vfs,
+        process: Process::new().with_ids(state.ids),
+        signals,
+        mmap_next: state.mmap_next,
+    })
+}
+
+/// Sequence number of memory image `name`.
+fn mem_seq(name: &str) -> Option<u64> {
+    name.strip_prefix("mem-")?.parse().ok()
+}
+
+/// Write `name` in `dir` through a synced temporary file.
+fn write_atomic(
+    dir: &Path,
+    name: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let tmp = dir.join(format!("{name}.tmp"));
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    write(&mut out)?;
+    let file = out.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(name))
+}
+
+// ---------------------------------------------------------------
+// Memory images
+// ---------------------------------------------------------------
+
+/// Mapped, readable regions of `layout`.
+fn readable(layout: &Layout) -> impl Iterator<Item = (u64, &Region)> {
+    layout
+        .regions
+        .iter()
+        .filter(|(_, r)| !r.guard && r.prot & libc::PROT_READ != 0)
+        .map(|(&s, r)| (s, r))
+}
+
+/// Every readable page that is not all zero, in runs.
+fn full_ranges(space: &GuestSpace, layout: &Layout) -> Vec<(u64, u64)> {
+    let ps = page_size() as u64;
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (start, r) in readable(layout) {
+        for page in (start..r.end).step_by(ps as usize) {
+            // SAFETY: the page is mapped readable.
+            let data = unsafe {
+                std::slice::from_raw_parts(space.g2h(page), ps as usize)
+            };
+            if data.iter().all(|&b| b == 0) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.1 == page => last.1 += ps,
+                _ => ranges.push((page, page + ps)),
+            }
+        }
+    }
+    ranges
+}
+
+/// Readable regions not as they were in `prev`, and the
+/// `written` parts of the others.
+fn changed_ranges(
+    layout: &Layout,
+    prev: &BTreeMap<u64, Region>,
+    written: &[(u64, u64)],
+) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    for (start, r) in readable(layout) {
+        if prev.get(&start) != Some(r) {
+            ranges.push((start, r.end));
+            continue;
+        }
+        for &(s, e) in written.iter().filter(|&&(s, e)| s < r.end && e > start)
+        {
+            ranges.push((s.max(start), e.min(r.end)));
+        }
+    }
+    ranges
+}
+
+/// Write `ranges` of `space` as `(start, len, bytes)` records;
+/// returns the bytes of guest memory written.
+fn write_ranges(
+    out: &mut impl Write,
+    space: &GuestSpace,
+    ranges: &[(u64, u64)],
+) -> io::Result<u64> {
+    let mut total = 0;
+    for &(start, end) in ranges {
+        let len = end - start;
+        out.write_all(&start.to_le_bytes())?;
+        out.write_all(&len.to_le_bytes())?;
+        // SAFETY: the range lies in a readable region.
+        let data = unsafe {
+            std::slice::from_raw_parts(space.g2h(start), len as usize)
+        };
+        out.write_all(data)?;
+        total += len;
+    }
+    Ok(total)
+}
+
+/// Copy the records of image `path` into the regions of
+/// `layout`, all writable while the space is filled.
+fn load_image(
+    path: &Path,
+    space: &GuestSpace,
+    layout: &Layout,
+) -> io::Result<()> {
+    let mut r = BufReader::new(File::open(path)?);
+    let mut head = [0u8; 16];
+    r.read_exact(&mut head)?;
+    if &head[..8] != MEM_MAGIC {
+        return Err(corrupt("memory image magic"));
+    }
+    loop {
+        match r.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let start = u64::from_le_bytes(head[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(head[8..].try_into().unwrap());
+        let end = start
+            .checked_add(len)
+            .filter(|&end| space.range_ok(start, end - start))
+            .ok_or_else(|| corrupt("memory image range"))?;
+        let mut data = vec![0u8; len as usize];
+        r.read_exact(&mut data)?;
+        let hit = layout
+            .regions
+            .range(..end)
+            .filter(|(_, reg)| !reg.guard && reg.end > start);
+        for (&s, reg) in hit {
+            let (lo, hi) = (s.max(start), reg.end.min(end));
+            let src = &data[(lo - start) as usize..(hi - start) as usize];
+            // SAFETY: `from_layout` maps every region writable
+            // while it fills the space.
+            unsafe { space.write_bytes(lo, src) };
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------
+// Guest fds
+// ---------------------------------------------------------------
+
+/// What a guest fd is reopened as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FdKind {
+    /// Path, `F_GETFL` status flags and offset.
+    File(PathBuf, i32, u64),
+    Device(Device),
+    /// A duplicate of inherited stdio fd.
+    Stdio(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SavedFd {
+    fd: i32,
+    cloexec: bool,
+    kind: FdKind,
+}
+
+fn fstat(fd: i32) -> Option<libc::stat> {
+    // SAFETY: a zeroed stat is valid for fstat to fill.
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    (unsafe { libc::fstat(fd, &mut st) } == 0).then_some(st)
+}
+
+fn is_cloexec(fd: i32) -> bool {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    flags >= 0 && flags & libc::FD_CLOEXEC != 0
+}
+
+/// How to reopen guest `fd`, or what it is if it cannot be.
+fn save_fd(vfs: &Vfs, fd: i32) -> Result<FdKind, &'static str> {
+    if let Some(dev) = vfs.device(fd as u64) {
+        return Ok(FdKind::Device(dev));
+    }
+    let st = fstat(fd).ok_or("closed descriptor")?;
+    let fmt = st.st_mode & libc::S_IFMT;
+    if fmt == libc::S_IFREG || fmt == libc::S_IFDIR {
+        let path = fs::read_link(format!("/proc/self/fd/{fd}"))
+            .map_err(|_| "file without a path")?;
+        if path.as_os_str().as_bytes().ends_with(b" (deleted)") {
+            return Err("deleted file");
+        }
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        let offset = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+        return Ok(FdKind::File(path, flags, offset.max(0) as u64));
+    }
+    let inherited = (0..=2).filter(|s| !vfs.fds().any(|fd| fd == *s));
+    for s in inherited {
+        if fstat(s)
+            .is_some_and(|o| (o.st_dev, o.st_ino) == (st.st_dev, st.st_ino))
+        {
+            return Ok(FdKind::Stdio(s));
+        }
+    }
+    Err(match fmt {
+        libc::S_IFSOCK => "socket",
+        libc::S_IFIFO => "pipe",
+        libc::S_IFCHR => "character device",
+        _ => "special file",
+    })
+}
+
+/// Open what `saved` refers to at its fd number.
+fn restore_fd(saved: &SavedFd) -> io::Result<()> {
+    let src = match &saved.kind {
+        FdKind::File(path, flags, _) => {
+            let path = CString::new(path.as_os_str().as_bytes())?;
+            let flags = flags & !(libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC)
+                | libc::O_CLOEXEC;
+            unsafe { libc::open(path.as_ptr(), flags) }
+        }
+        FdKind::Device(_) => unsafe {
+            libc::memfd_create(c"tcg-dev".as_ptr(), libc::MFD_CLOEXEC)
+        },
+        FdKind::Stdio(s) => *s,
+    };
+    if src < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let cloexec = if saved.cloexec { libc::O_CLOEXEC } else { 0 };
+    let ret = if src == saved.fd {
+        let flags = if saved.cloexec { libc::FD_CLOEXEC } else { 0 };
+        unsafe { libc::fcntl(src, libc::F_SETFD, flags) }
+    } else {
+        let ret = unsafe { libc::dup3(src, saved.fd, cloexec) };
+        if !matches!(saved.kind, FdKind::Stdio(_)) {
+            unsafe { libc::close(src) };
+        }
+        ret
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let FdKind::File(_, _, offset) = saved.kind {
+        let ret = unsafe {
+            libc::lseek(saved.fd, offset as libc::off_t, libc::SEEK_SET)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------
+// State file
+// ---------------------------------------------------------------
+
+fn corrupt(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt checkpoint: {what}"),
+    )
+}
+
+/// Little-endian encoder of the state file.
+#[derive(Default)]
+struct Enc(Vec<u8>);
+
+impl Enc {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.u64(b.len() as u64);
+        self.0.extend_from_slice(b);
+    }
+
+    fn opt(&mut self, v: Option<u64>) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                self.u64(v);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Dec<'a>(&'a [u8]);
+
+impl<'a> Dec<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.0.len() {
+            return Err(corrupt("truncated state"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let n = self.u64()?;
+        self.take(usize::try_from(n).map_err(|_| corrupt("length"))?)
+    }
+
+    fn opt(&mut self) -> io::Result<Option<u64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.u64()?)),
+            _ => Err(corrupt("option tag")),
+        }
+    }
+
+    /// A count of items at least `size` bytes each.
+    fn count(&mut self, size: usize) -> io::Result<usize> {
+        let n = self.u64()?;
+        if n > (self.0.len() / size) as u64 {
+            return Err(corrupt("count"));
+        }
+        Ok(n as usize)
+    }
+}
+
+fn cpu_words(c: &RiscvCpu) -> Vec<u64> {
+    let mut words = c.gpr.to_vec();
+    words.extend(c.fpr);
+    words.extend([
+        c.pc, c.load_res, c.load_val, c.fflags, c.frm, c.ustatus, c.uie,
+        c.utvec, c.uscratch, c.uepc, c.ucause, c.utval, c.uip, c.cycle, c.time,
+    ]);
+    words
+}
+
+fn cpu_from_words(words: &[u64]) -> io::Result<RiscvCpu> {
+    let (gpr, rest) = words.split_at_checked(NUM_GPRS).unwrap_or_default();
+    let (fpr, rest) = rest.split_at_checked(NUM_FPRS).unwrap_or_default();
+    let csrs: [u64; CPU_CSRS] =
+        rest.try_into().map_err(|_| corrupt("CPU state"))?;
+    let [pc, load_res, load_val, fflags, frm, ustatus, uie, utvec, uscratch, uepc, ucause, utval, uip, cycle, time] =
+        csrs;
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr.copy_from_slice(gpr);
+    cpu.fpr.copy_from_slice(fpr);
+    cpu.pc = pc;
+    cpu.load_res = load_res;
+    cpu.load_val = load_val;
+    cpu.fflags = fflags;
+    cpu.frm = frm;
+    cpu.ustatus = ustatus;
+    cpu.uie = uie;
+    cpu.utvec = utvec;
+    cpu.uscratch = uscratch;
+    cpu.uepc = uepc;
+    cpu.ucause = ucause;
+    cpu.utval = utval;
+    cpu.uip = uip;
+    cpu.cycle = cycle;
+    cpu.time = time;
+    Ok(cpu)
+}
+
+fn encode_state(
+    seq: u64,
+    g: &Guest,
+    layout: &Layout,
+    chain: &[String],
+    fds: &[SavedFd],
+) -> Vec<u8> {
+    let mut e = Enc::default();
+    e.0.extend_from_slice(STATE_MAGIC);
+    e.u32(VERSION);
+    e.u64(seq);
+
+    e.bytes(g.run.elf_path.as_bytes());
+    e.u64(g.run.timebase_freq);
+    e.u8(g.run.deterministic as u8);
+    e.u8(match g.run.ecall {
+        EcallMode::Linux => 0,
+        EcallMode::Sbi => 1,
+    });
+    e.opt(g.run.uart);
+
+    let words = cpu_words(g.cpu);
+    e.u64(words.len() as u64);
+    for w in words {
+        e.u64(w);
+    }
+    e.u64(g.mmap_next);
+
+    e.u64(layout.size as u64);
+    e.u64(layout.brk);
+    e.opt(layout.stack.map(|(start, _)| start));
+    e.opt(layout.stack.map(|(_, end)| end));
+    e.u64(layout.stack_rlimit.0);
+    e.u64(layout.stack_rlimit.1);
+    e.u64(layout.stack_guard as u64);
+    e.u64(layout.membarrier_reg);
+    e.u64(layout.regions.len() as u64);
+    for (&start, r) in &layout.regions {
+        e.u64(start);
+        e.u64(r.end);
+        e.u32(r.prot as u32);
+        e.u8(r.guard as u8);
+    }
+
+    e.u64(chain.len() as u64);
+    for name in chain {
+        e.bytes(name.as_bytes());
+    }
+
+    e.opt(g.vfs.entropy_state());
+    let (pgid, sid, ctty) = g.process.ids();
+    e.u32(pgid);
+    e.u32(sid);
+    e.opt(ctty.map(u64::from));
+    let actions: Vec<(u64, GuestSigaction)> = (1..=NSIG)
+        .filter_map(|sig| Some((sig, g.signals.action(sig)?)))
+        .filter(|(_, act)| *act != GuestSigaction::default())
+        .collect();
+    e.u64(actions.len() as u64);
+    for (sig, act) in actions {
+        e.u64(sig);
+        e.u64(act.handler);
+        e.u64(act.flags);
+        e.u64(act.mask);
+    }
+
+    e.u64(fds.len() as u64);
+    for saved in fds {
+        e.u32(saved.fd as u32);
+        e.u8(saved.cloexec as u8);
+        match &saved.kind {
+            FdKind::File(path, flags, offset) => {
+                e.u8(0);
+                e.bytes(path.as_os_str().as_bytes());
+                e.u32(*flags as u32);
+                e.u64(*offset);
+            }
+            FdKind::Device(dev) => {
+                e.u8(1);
+                e.u8(DEVICES.iter().position(|d| d == dev).unwrap() as u8);
+            }
+            FdKind::Stdio(s) => {
+                e.u8(2);
+                e.u32(*s as u32);
+            }
+        }
+    }
+    e.0
+}
+
+/// Contents of a state file.
+struct State {
+    seq: u64,
+    run: RunInfo,
+    cpu: RiscvCpu,
+    mmap_next: u64,
+    layout: Layout,
+    chain: Vec<String>,
+    entropy: Option<u64>,
+    ids: (u32, u32, Option<u32>),
+    signals: Vec<(u64, GuestSigaction)>,
+    fds: Vec<SavedFd>,
+}
+
+fn decode_state(buf: &[u8]) -> io::Result<State> {
+    let mut d = Dec(buf);
+    if d.take(8)? != STATE_MAGIC {
+        return Err(corrupt("not a checkpoint state file"));
+    }
+    let version = d.u32()?;
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported checkpoint version {version}"),
+        ));
+    }
+    let seq = d.u64()?;
+
+    let elf_path = String::from_utf8(d.bytes()?.to_vec())
+        .map_err(|_| corrupt("ELF path"))?;
+    let timebase_freq = d.u64()?;
+    let deterministic = d.u8()? != 0;
+    let ecall = match d.u8()? {
+        0 => EcallMode::Linux,
+        1 => EcallMode::Sbi,
+        _ => return Err(corrupt("ecall mode")),
+    };
+    let run = RunInfo {
+        elf_path,
+        timebase_freq,
+        deterministic,
+        ecall,
+        uart: d.opt()?,
+    };
+
+    let n = d.count(8)?;
+    let words = (0..n).map(|_| d.u64()).collect::<io::Result<Vec<_>>>()?;
+    let cpu = cpu_from_words(&words)?;
+    let mmap_next = d.u64()?;
+
+    let size = usize::try_from(d.u64()?).map_err(|_| corrupt("size"))?;
+    let brk = d.u64()?;
+    let stack = d.opt()?.zip(d.opt()?);
+    let stack_rlimit = (d.u64()?, d.u64()?);
+    let stack_guard = d.u64()? as usize;
+    let membarrier_reg = d.u64()?;
+    let mut regions = BTreeMap::new();
+    for _ in 0..d.count(21)? {
+        let start = d.u64()?;
+        let end = d.u64()?;
+        let prot = d.u32()? as i32;
+        let guard = d.u8()? != 0;
+        if end <= start || end > size as u64 {
+            return Err(corrupt("region"));
+        }
+        regions.insert(
+            start,
+            Region {
+                end,
+                prot,
+                guard,
+                file: false,
+            },
+        );
+    }
+    let layout = Layout {
+        size,
+        regions,
+        brk,
+        stack,
+        stack_rlimit,
+        stack_guard,
+        membarrier_reg,
+    };
+
+    let mut chain = Vec::new();
+    for _ in 0..d.count(8)? {
+        let name = String::from_utf8(d.bytes()?.to_vec())
+            .ok()
+            .filter(|n| mem_seq(n).is_some())
+            .ok_or_else(|| corrupt("memory image name"))?;
+        chain.push(name);
+    }
+
+    let entropy = d.opt()?;
+    let pgid = d.u32()?;
+    let sid = d.u32()?;
+    let ctty = d.opt()?.map(|v| v as u32);
+    let mut signals = Vec::new();
+    for _ in 0..d.count(32)? {
+        let sig = d.u64()?;
+        let act = GuestSigaction {
+            handler: d.u64()?,
+            flags: d.u64()?,
+            mask: d.u64()?,
+        };
+        signals.push((sig, act));
+    }
+
+    let mut fds = Vec::new();
+    for _ in 0..d.count(6)? {
+        let fd = d.u32()? as i32;
+        let cloexec = d.u8()? != 0;
+        let kind = match d.u8()? {
+            0 => {
+                let path =
+                    PathBuf::from(std::ffi::OsStr::from_bytes(d.bytes()?));
+                FdKind::File(path, d.u32()? as i32, d.u64()?)
+            }
+            1 => FdKind::Device(
+                *DEVICES
+                    .get(d.u8()? as usize)
+                    .ok_or_else(|| corrupt("device"))?,
+            ),
+            2 => FdKind::Stdio(d.u32()? as i32),
+            _ => return Err(corrupt("fd kind")),
+        };
+        fds.push(SavedFd { fd, cloexec, kind });
+    }
+    Ok(State {
+        seq,
+        run,
+        cpu,
+        mmap_next,
+        layout,
+        chain,
+        entropy,
+        ids: (pgid, sid, ctty),
+        signals,
+        fds,
+    })
+}
@@ -0,0 +1,673 @@
+//! Run-time knobs read from the environment and command line.
+//!
+//! Options follow qemu-user: `-name value`, with `--name` and
+//! `--name=value` accepted as well. Parsing stops at `--` or at
+//! the first non-option, the guest ELF; everything after it is
+//! passed to the guest. Flags override `TCG_*` variables.
+
+use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tcg_frontend::riscv::cpu::{GuestClock, DEFAULT_TIMEBASE_FREQ};
+use tcg_frontend::riscv::ext::RiscvCfg;
+
+use tcg_exec::checkpoint::CheckpointInterval;
+
+use crate::checkpoint::FdPolicy;
+use crate::guest_space::{page_size, GUEST_STACK_GUARD};
+use crate::machine::{EcallMode, DEFAULT_LOAD_ADDR, DEFAULT_RAM_SIZE};
+use crate::syscall::SyscallPolicy;
+use crate::vfs::{fs_magic, StatfsView, Vfs};
+
+pub const USAGE: &str = "\
+usage: tcg-riscv64 [options] <elf> [guest args...]
+       tcg-riscv64 [options] -kernel <image> [guest args...]
+       tcg-riscv64 [options] -resume <dir>
+
+Options (qemu-user compatible):
+  -d <items>          Enable log items (comma-separated): strace
+  -D <file>           Write log output to <file> (default: stderr)
+  -strace             Log guest system calls
+  -L <path>           Guest sysroot
+  -E <var>=<value>    Set a guest environment variable
+  -U <var>            Remove a guest environment variable
+  -0 <argv0>          Guest argv[0] (default: <elf>)
+  -seed <n>           Deterministic run with seed <n>
+  -cpu <model>        rv64, then extensions to turn on or off:
+                      rv64,c=off,zbb=on (default rv64imafdc)
+  -p <size>           Guest page size (must match the host's)
+  -g <port>           Wait for gdb on <port> (not supported yet)
+  -h, -help           Show this help
+
+tcg-rs options (also set by the TCG_* variable named):
+  -timebase-freq <hz> rdtime frequency (TCG_TIMEBASE_FREQ)
+  -deterministic      Time from retired insns (TCG_DETERMINISTIC)
+  -stats              Print exec statistics (TCG_STATS)
+  -coverage <file>    Write block coverage (TCG_COVERAGE)
+  -deny-net           Refuse guest sockets (TCG_DENY_NET)
+  -deny-random        Refuse /dev/random and /dev/urandom
+                      (TCG_DENY_RANDOM)
+  -captured-stdio     Guest stdio is never a tty
+                      (TCG_CAPTURED_STDIO)
+  -verify-code <n>    Check TB code every n entries (TCG_VERIFY_CODE)
+  -stack-check        Stop on host stack exhaustion by generated
+                      code, debug builds (TCG_STACK_CHECK)
+  -stack-guard <n>    Guard bytes below the stack, 0 for none
+                      (TCG_STACK_GUARD, default 1 MiB)
+  -warmup-save <file> Save hot TB addresses on exit (TCG_WARMUP_SAVE)
+  -warmup-load <file> Pre-translate TBs saved by -warmup-save
+                      (TCG_WARMUP_LOAD)
+  -metrics <addr>     Serve Prometheus metrics at http://<addr>/metrics,
+                      e.g. 127.0.0.1:9100 (TCG_METRICS)
+  -max-cpu-seconds <s>
+                      Abort after <s> seconds of guest CPU time,
+                      exit status 124 (TCG_MAX_CPU_SECONDS)
+  -max-wall-seconds <s>
+                      Likewise for wall-clock time
+                      (TCG_MAX_WALL_SECONDS)
+  -sysroot-fstype <t> statfs type of files under -L: a name
+                      (tmpfs, ext4, overlay, nfs, ...) or magic
+                      number (TCG_SYSROOT_FSTYPE)
+  -disk-cap <bytes>   statfs reports file systems of at most
+                      <bytes> (TCG_DISK_CAP)
+
+Checkpoints:
+  -checkpoint <dir>   Save the run to <dir> periodically
+                      (TCG_CHECKPOINT)
+  -checkpoint-insns <n>
+                      Every <n> guest instructions
+                      (TCG_CHECKPOINT_INSNS)
+  -checkpoint-seconds <s>
+                      Every <s> seconds of wall-clock time
+                      (TCG_CHECKPOINT_SECONDS, default 60 if no
+                      interval is given)
+  -checkpoint-drop-fds
+                      Leave fds that cannot be reopened (sockets,
+                      pipes) closed on resume instead of skipping
+                      the checkpoint (TCG_CHECKPOINT_DROP_FDS)
+  -resume <dir>       Continue the run saved in <dir>
+
+Flat images (also used for a guest file without ELF magic):
+  -kernel <image>     Run <image> as a flat binary
+  -load-addr <addr>   Load address (default 0x80000000)
+  -entry <addr>       Entry point (default: load address)
+  -ram-size <n>       RAM from the load address, the stack at its
+                      top (default 128 MiB)
+  -ecall <mode>       linux (default) or sbi: putchar and exit
+  -uart <addr>        16550 UART whose output goes to stdout
+
+Every option also accepts a leading `--`; `--` ends options.";
+
+/// Checkpoint interval when none is configured.
+pub const DEFAULT_CHECKPOINT_WALL: Duration = Duration::from_secs(60);
+
+/// Emulator configuration for a single guest run.
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    /// `time` CSR frequency in Hz (`TCG_TIMEBASE_FREQ`).
+    pub timebase_freq: u64,
+    /// Derive `time` from retired instructions instead of host
+    /// wall-clock time (`TCG_DETERMINISTIC`).
+    pub deterministic: bool,
+    /// Print execution statistics on exit (`TCG_STATS`).
+    pub show_stats: bool,
+    /// Write block coverage to this file on exit
+    /// (`TCG_COVERAGE`).
+    pub coverage: Option<PathBuf>,
+    /// Refuse guest socket creation (`TCG_DENY_NET`).
+    pub deny_net: bool,
+    /// Refuse opening the random devices (`TCG_DENY_RANDOM`).
+    pub deny_random: bool,
+    /// Present guest stdio as redirected, so `isatty` is false
+    /// (`TCG_CAPTURED_STDIO`).
+    pub captured_stdio: bool,
+    /// Check TB host code checksums on every Nth TB entry
+    /// (`TCG_VERIFY_CODE=N`; needs the `verify-code` feature).
+    pub verify_code: Option<u64>,
+    /// Check the host stack at TB and helper entries
+    /// (`TCG_STACK_CHECK`; debug builds only).
+    pub stack_check: bool,
+    /// PROT_NONE guard below the guest stack, in bytes
+    /// (`TCG_STACK_GUARD`).
+    pub stack_guard: usize,
+    /// Save the hottest TBs' addresses here on exit
+    /// (`TCG_WARMUP_SAVE`).
+    pub warmup_save: Option<PathBuf>,
+    /// Pre-translate the TBs listed here, if it was saved for
+    /// the same binary and CPU (`TCG_WARMUP_LOAD`).
+    pub warmup_load: Option<PathBuf>,
+    /// Serve live metrics on this address (`TCG_METRICS`;
+    /// needs the `metrics` feature).
+    pub metrics: Option<SocketAddr>,
+    /// Abort after this much CPU time of the guest thread
+    /// (`TCG_MAX_CPU_SECONDS`).
+    pub max_cpu: Option<Duration>,
+    /// Abort after this much wall-clock time
+    /// (`TCG_MAX_WALL_SECONDS`).
+    pub max_wall: Option<Duration>,
+    /// Log guest system calls (`-strace`, `-d strace`).
+    pub strace: bool,
+    /// Log destination instead of stderr (`-D`).
+    pub log_file: Option<PathBuf>,
+    /// Guest sysroot (`-L`).
+    pub sysroot: Option<PathBuf>,
+    /// `f_type` statfs reports for files under the sysroot
+    /// (`TCG_SYSROOT_FSTYPE`).
+    pub sysroot_fstype: Option<i64>,
+    /// Largest file system size statfs reports, in bytes
+    /// (`TCG_DISK_CAP`).
+    pub disk_cap: Option<u64>,
+    /// Checkpoint directory (`TCG_CHECKPOINT`).
+    pub checkpoint: Option<PathBuf>,
+    /// Checkpoint every this many guest instructions
+    /// (`TCG_CHECKPOINT_INSNS`).
+    pub checkpoint_insns: Option<u64>,
+    /// Checkpoint every this much wall-clock time
+    /// (`TCG_CHECKPOINT_SECONDS`).
+    pub checkpoint_wall: Option<Duration>,
+    /// Checkpoint despite fds that cannot be reopened, which
+    /// stay closed on resume (`TCG_CHECKPOINT_DROP_FDS`).
+    pub checkpoint_drop_fds: bool,
+    /// Checkpoint directory to resume from (`-resume`).
+    pub resume: Option<PathBuf>,
+    /// Guest environment additions (`-E`), applied in order.
+    pub env_set: Vec<(String, String)>,
+    /// Guest environment removals (`-U`).
+    pub env_unset: Vec<String>,
+    /// Guest `argv[0]` override (`-0`).
+    pub argv0: Option<String>,
+    /// Seed of a deterministic run (`-seed`).
+    pub seed: Option<u64>,
+    /// gdbstub port (`-g`).
+    pub gdb_port: Option<u16>,
+    /// Guest ISA extensions (`-cpu`).
+    pub cpu: RiscvCfg,
+    /// Load the guest as a flat image (`-kernel`).
+    pub kernel: bool,
+    /// Flat image load address (`-load-addr`).
+    pub load_addr: u64,
+    /// Flat image entry point, the load address if unset
+    /// (`-entry`).
+    pub entry: Option<u64>,
+    /// Flat image RAM size (`-ram-size`).
+    pub ram_size: u64,
+    /// `ecall` handling (`-ecall`).
+    pub ecall: EcallMode,
+    /// UART address (`-uart`).
+    pub uart: Option<u64>,
+}
+
+/// A parsed `tcg-riscv64` command line.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub config: RunConfig,
+    /// Path of the guest ELF as given; empty with `-resume`.
+    pub elf: String,
+    /// Guest argv, `argv[0]` included; empty with `-resume`.
+    pub argv: Vec<String>,
+}
+
+/// Why the command line was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// `-h` / `-help` was given.
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Help => f.write_str(USAGE),
+            ArgError::Invalid(msg) => write!(f, "{msg}\n\n{USAGE}"),
+        }
+    }
+}
+
+fn invalid(msg: String) -> ArgError {
+    ArgError::Invalid(msg)
+}
+
+fn parse_num<T: std::str::FromStr>(opt: &str, s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid {opt}: {s}"))
+}
+
+impl RunConfig {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|k| env::var(k).ok())
+    }
+
+    /// Build the configuration from `TCG_*` variables looked up
+    /// through `var`.
+    pub fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, String> {
+        let mut cfg = Self::default();
+        if let Some(s) = var("TCG_TIMEBASE_FREQ") {
+            cfg.timebase_freq = parse_positive("TCG_TIMEBASE_FREQ", &s)?;
+        }
+        if let Some(s) = var("TCG_VERIFY_CODE") {
+            cfg.verify_code = Some(parse_positive("TCG_VERIFY_CODE", &s)?);
+        }
+        if let Some(s) = var("TCG_STACK_GUARD") {
+            cfg.stack_guard = parse_num("TCG_STACK_GUARD", &s)?;
+        }
+        if let Some(s) = var("TCG_METRICS") {
+            cfg.metrics = Some(parse_num("TCG_METRICS", &s)?);
+        }
+        if let Some(s) = var("TCG_MAX_CPU_SECONDS") {
+            cfg.max_cpu = Some(parse_seconds("TCG_MAX_CPU_SECONDS", &s)?);
+        }
+        if let Some(s) = var("TCG_MAX_WALL_SECONDS") {
+            cfg.max_wall = Some(parse_seconds("TCG_MAX_WALL_SECONDS", &s)?);
+        }
+        if let Some(s) = var("TCG_SYSROOT_FSTYPE") {
+            cfg.sysroot_fstype = Some(parse_fstype("TCG_SYSROOT_FSTYPE", &s)?);
+        }
+        if let Some(s) = var("TCG_DISK_CAP") {
+            cfg.disk_cap = Some(parse_addr("TCG_DISK_CAP", &s)?);
+        }
+        if let Some(s) = var("TCG_CHECKPOINT_INSNS") {
+            cfg.checkpoint_insns =
+                Some(parse_positive("TCG_CHECKPOINT_INSNS", &s)?);
+        }
+        if let Some(s) = var("TCG_CHECKPOINT_SECONDS") {
+            cfg.checkpoint_wall =
+                Some(parse_seconds("TCG_CHECKPOINT_SECONDS", &s)?);
+        }
+        cfg.checkpoint = var("TCG_CHECKPOINT").map(PathBuf::from);
+        cfg.checkpoint_drop_fds = var("TCG_CHECKPOINT_DROP_FDS").is_some();
+        cfg.deterministic = var("TCG_DETERMINISTIC").is_some();
+        cfg.show_stats = var("TCG_STATS").is_some();
+        cfg.coverage = var("TCG_COVERAGE").map(PathBuf::from);
+        cfg.deny_net = var("TCG_DENY_NET").is_some();
+        cfg.deny_random = var("TCG_DENY_RANDOM").is_some();
+        cfg.captured_stdio = var("TCG_CAPTURED_STDIO").is_some();
+        cfg.stack_check = var("TCG_STACK_CHECK").is_some();
+        cfg.warmup_save = var("TCG_WARMUP_SAVE").map(PathBuf::from);
+        cfg.warmup_load = var("TCG_WARMUP_LOAD").map(PathBuf::from);
+        Ok(cfg)
+    }
+
+    pub fn clock(&self) -> GuestClock {
+        GuestClock::new(self.timebase_freq, self.deterministic)
+    }
+
+    pub fn syscall_policy(&self) -> SyscallPolicy {
+        SyscallPolicy {
+            deny_sockets: self.deny_net,
+            deny_random: self.deny_random,
+        }
+    }
+
+    /// Device and stdio state for the guest; random devices are
+    /// seeded when the run is deterministic.
+    pub fn vfs(&self) -> Vfs {
+        let seed = self.deterministic.then(|| self.seed.unwrap_or(0));
+        Vfs::new(seed)
+            .with_captured_stdio(self.captured_stdio)
+            .with_statfs(self.statfs_view())
+    }
+
+    pub fn statfs_view(&self) -> StatfsView {
+        StatfsView {
+            sysroot: self.sysroot.clone().zip(self.sysroot_fstype),
+            disk_cap: self.disk_cap,
+        }
+    }
+
+    /// When to checkpoint, if `-checkpoint` is set; every 60
+    /// seconds unless an interval is given.
+    pub fn checkpoint_interval(&self) -> Option<CheckpointInterval> {
+        self.checkpoint.as_ref()?;
+        let wall = match (self.checkpoint_insns, self.checkpoint_wall) {
+            (None, None) => Some(DEFAULT_CHECKPOINT_WALL),
+            (_, wall) => wall,
+        };
+        Some(CheckpointInterval {
+            insns: self.checkpoint_insns,
+            wall,
+        })
+    }
+
+    pub fn checkpoint_fd_policy(&self) -> FdPolicy {
+        if self.checkpoint_drop_fds {
+            FdPolicy::Drop
+        } else {
+            FdPolicy::Refuse
+        }
+    }
+
+    /// Guest environment: `base` with `-U` removals and `-E`
+    /// additions applied, as `VAR=value` strings.
+    pub fn guest_env(
+        &self,
+        base: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<String> {
+        let mut vars: Vec<(String, String)> = base
+            .into_iter()
+            .filter(|(k, _)| !self.env_unset.contains(k))
+            .collect();
+        for (k, v) in &self.env_set {
+            match vars.iter_mut().find(|(name, _)| name == k) {
+                Some(slot) => slot.1 = v.clone(),
+                None => vars.push((k.clone(), v.clone())),
+            }
+        }
+        vars.into_iter().map(|(k, v)| format!("{k}={v}")).collect()
+    }
+}
+
+/// A guest address, decimal or `0x` hex.
+fn parse_addr(opt: &str, s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+    .ok_or_else(|| format!("invalid {opt}: {s}"))
+}
+
+fn parse_positive(opt: &str, s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid {opt}: {s}")),
+    }
+}
+
+fn parse_fstype(opt: &str, s: &str) -> Result<i64, String> {
+    fs_magic(s).ok_or_else(|| format!("invalid {opt}: {s}"))
+}
+
+/// Positive seconds, fractions allowed.
+/// `-cpu rv64[,<ext>=on|off]...`, extension names as in the
+/// ISA string; `true`/`false` work as in qemu.
+fn parse_cpu(s: &str) -> Result<RiscvCfg, String> {
+    let mut props = s.split(',');
+    let model = props.next().unwrap_or_default();
+    if model != "rv64" {
+        return Err(format!("unsupported -cpu model: {model}"));
+    }
+    let mut cfg = RiscvCfg::default();
+    for prop in props {
+        let (name, on) = match prop.split_once('=') {
+            Some((n, "on" | "true")) => (n, true),
+            Some((n, "off" | "false")) => (n, false),
+            _ => return Err(format!("invalid -cpu property: {prop}")),
+        };
+        if !cfg.set_ext(name, on) {
+            return Err(format!("unknown -cpu extension: {name}"));
+        }
+    }
+    Ok(cfg)
+}
+
+fn parse_seconds(opt: &str, s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs > 0.0 => Duration::try_from_secs_f64(secs)
+            .map_err(|_| format!("invalid {opt}: {s}")),
+        _ => Err(format!("invalid {opt}: {s}")),
+    }
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            timebase_freq: DEFAULT_TIMEBASE_FREQ,
+            deterministic: false,
+            show_stats: false,
+            coverage: None,
+            deny_net: false,
+            deny_random: false,
+            captured_stdio: false,
+            verify_code: None,
+            stack_check: false,
+            stack_guard: GUEST_STACK_GUARD,
+            warmup_save: None,
+            warmup_load: None,
+            metrics: None,
+            max_cpu: None,
+            max_wall: None,
+            strace: false,
+            log_file: None,
+            sysroot: None,
+            sysroot_fstype: None,
+            disk_cap: None,
+            checkpoint: None,
+            checkpoint_insns: None,
+            checkpoint_wall: None,
+            checkpoint_drop_fds: false,
+            resume: None,
+            env_set: Vec::new(),
+            env_unset: Vec::new(),
+            argv0: None,
+            seed: None,
+            gdb_port: None,
+            cpu: RiscvCfg::default(),
+            kernel: false,
+            load_addr: DEFAULT_LOAD_ADDR,
+            entry: None,
+            ram_size: DEFAULT_RAM_SIZE,
+            ecall: EcallMode::Linux,
+            uart: None,
+        }
+    }
+}
+
+/// Parse the command line (without the program name) on top
+/// of `config`, usually `RunConfig::from_env()`.
+pub fn parse_args(
+    args: &[String],
+    mut config: RunConfig,
+) -> Result<Invocation, ArgError> {
+    let mut kernel = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        if arg == "--" {
+            break;
+        }
+        let Some(name) = arg.strip_prefix('-').filter(|n| !n.is_empty()) else {
+            i -= 1;
+            break;
+        };
+        let name = name.strip_prefix('-').unwrap_or(name);
+        let (name, inline) = match name.split_once('=') {
+            Some((n, v)) => (n, Some(v.to_string())),
+            None => (name, None),
+        };
+        let mut value = || -> Result<String, ArgError> {
+            if let Some(v) = inline.clone() {
+                return Ok(v);
+            }
+            let v = args
+                .get(i)
+                .ok_or_else(|| invalid(format!("{arg} needs a value")))?;
+            i += 1;
+            Ok(v.clone())
+        };
+        match name {
+            "h" | "help" => return Err(ArgError::Help),
+            "d" => {
+                for item in value()?.split(',') {
+                    match item {
+                        "strace" => config.strace = true,
+                        other => {
+                            return Err(invalid(format!(
+                                "unsupported log item: {other}"
+                            )))
+                        }
+                    }
+                }
+            }
+            "D" => config.log_file = Some(PathBuf::from(value()?)),
+            "strace" => config.strace = true,
+            "L" => config.sysroot = Some(PathBuf::from(value()?)),
+            "E" => {
+                let v = value()?;
+                let Some((k, v)) = v.split_once('=') else {
+                    return Err(invalid(format!("-E needs VAR=value: {v}")));
+                };
+                config.env_set.push((k.to_string(), v.to_string()));
+            }
+            "U" => config.env_unset.push(value()?),
+            "0" => config.argv0 = Some(value()?),
+            "seed" => {
+                config.seed =
+                    Some(parse_num("-seed", &value()?).map_err(invalid)?);
+                config.deterministic = true;
+            }
+            "p" => {
+                let size: usize =
+                    parse_num("-p", &value()?).map_err(invalid)?;
+                if size != page_size() {
+                    return Err(invalid(format!(
+                        "unsupported page size {size} (host: {})",
+                        page_size()
+                    )));
+                }
+            }
+            "cpu" => config.cpu = parse_cpu(&value()?).map_err(invalid)?,
+            "g" => {
+                config.gdb_port =
+                    Some(parse_num("-g", &value()?).map_err(invalid)?)
+            }
+            "timebase-freq" => {
+                config.timebase_freq =
+                    parse_positive("-timebase-freq", &value()?)
+                        .map_err(invalid)?;
+            }
+            "deterministic" => config.deterministic = true,
+            "stats" => config.show_stats = true,
+            "coverage" => config.coverage = Some(PathBuf::from(value()?)),
+            "deny-net" => config.deny_net = true,
+            "deny-random" => config.deny_random = true,
+            "captured-stdio" => config.captured_stdio = true,
+            "stack-check" => config.stack_check = true,
+            "verify-code" => {
+                config.verify_code = Some(
+                    parse_positive("-verify-code", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "stack-guard" => {
+                config.stack_guard =
+                    parse_num("-stack-guard", &value()?).map_err(invalid)?;
+            }
+            "warmup-save" => {
+                config.warmup_save = Some(PathBuf::from(value()?));
+            }
+            "warmup-load" => {
+                config.warmup_load = Some(PathBuf::from(value()?));
+            }
+            "metrics" => {
+                config.metrics =
+                    Some(parse_num("-metrics", &value()?).map_err(invalid)?);
+            }
+            "max-cpu-seconds" => {
+                config.max_cpu = Some(
+                    parse_seconds("-max-cpu-seconds", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "max-wall-seconds" => {
+                config.max_wall = Some(
+                    parse_seconds("-max-wall-seconds", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "sysroot-fstype" => {
+                config.sysroot_fstype = Some(
+                    parse_fstype("-sysroot-fstype", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "disk-cap" => {
+                config.disk_cap =
+                    Some(parse_addr("-disk-cap", &value()?).map_err(invalid)?);
+            }
+            "checkpoint" => config.checkpoint = Some(PathBuf::from(value()?)),
+            "checkpoint-insns" => {
+                config.checkpoint_insns = Some(
+                    parse_positive("-checkpoint-insns", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "checkpoint-seconds" => {
+                config.checkpoint_wall = Some(
+                    parse_seconds("-checkpoint-seconds", &value()?)
+                        .map_err(invalid)?,
+                );
+            }
+            "checkpoint-drop-fds" => config.checkpoint_drop_fds = true,
+            "resume" => config.resume = Some(PathBuf::from(value()?)),
+            "kernel" => {
+                config.kernel = true;
+                kernel = Some(value()?);
+            }
+            "load-addr" => {
+                config.load_addr =
+                    parse_addr("-load-addr", &value()?).map_err(invalid)?;
+            }
+            "entry" => {
+                config.entry =
+                    Some(parse_addr("-entry", &value()?).map_err(invalid)?);
+            }
+            "ram-size" => {
+                let v = value()?;
+                config.ram_size = parse_addr("-ram-size", &v)
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| {
+                        invalid(format!("invalid -ram-size: {v}"))
+                    })?;
+            }
+            "ecall" => {
+                let v = value()?;
+                config.ecall = EcallMode::parse(&v)
+                    .ok_or_else(|| invalid(format!("invalid -ecall: {v}")))?;
+            }
+            "uart" => {
+                config.uart =
+                    Some(parse_addr("-uart", &value()?).map_err(invalid)?);
+            }
+            _ => return Err(invalid(format!("unknown option: {arg}"))),
+        }
+    }
+
+    if config.sysroot_fstype.is_some() && config.sysroot.is_none() {
+        return Err(invalid("-sysroot-fstype needs -L".to_string()));
+    }
+    let interval = config.checkpoint_insns.is_some()
+        || config.checkpoint_wall.is_some()
+        || config.checkpoint_drop_fds;
+    if interval && config.checkpoint.is_none() {
+        return Err(invalid("checkpoint options need -checkpoint".to_string()));
+    }
+    if config.resume.is_some() {
+        // The checkpoint names the guest and holds its argv.
+        if kernel.is_some() || i < args.len() {
+            return Err(invalid("-resume takes no guest program".to_string()));
+        }
+        return Ok(Invocation {
+            config,
+            elf: String::new(),
+            argv: Vec::new(),
+        });
+    }
+
+    let mut argv = args[i..].to_vec();
+    if let Some(image) = kernel {
+        argv.insert(0, image);
+    }
+    let Some(elf) = argv.first().cloned() else {
+        return Err(invalid("missing guest ELF".to_string()));
+    };
+    if let Some(argv0) = &config.argv0 {
+        argv[0] = argv0.clone();
+    }
+    Ok(Invocation { config, elf, argv })
+}
@@ -0,0 +1,164 @@
+//! Guest coverage reports: raw block ranges and lcov.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use tcg_exec::coverage::{Coverage, CoveredBlock};
+
+/// Maps guest addresses to `(file, line)` source locations.
+pub trait LineResolver {
+    /// Resolve each address; `None` where no line info exists.
+    fn resolve(
+        &mut self,
+        addrs: &[u64],
+    ) -> io::Result<Vec<Option<(String, u32)>>>;
+}
+
+/// Resolver backed by an external `addr2line` process.
+pub struct Addr2Line {
+    tool: String,
+    elf: PathBuf,
+}
+
+impl Addr2Line {
+    /// Find a usable `addr2line` (LLVM first, as it handles
+    /// RISC-V objects on any host).
+    pub fn find(elf: &Path) -> Option<Self> {
+        ["llvm-addr2line", "addr2line"]
+            .into_iter()
+            .find(|tool| {
+                Command::new(tool)
+                    .arg("--version")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .is_ok_and(|s| s.success())
+            })
+            .map(|tool| Self {
+                tool: tool.to_string(),
+                elf: elf.to_path_buf(),
+            })
+    }
+}
+
+impl LineResolver for Addr2Line {
+    fn resolve(
+        &mut self,
+        addrs: &[u64],
+    ) -> io::Result<Vec<Option<(String, u32)>>> {
+        let mut child = Command::new(&self.tool)
+            .arg("-e")
+            .arg(&self.elf)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let input: String = addrs.iter().map(|a| format!("{a:#x}\n")).collect();
+        // Feed stdin from another thread so a full stdout pipe
+        // cannot deadlock us.
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let out = child.wait_with_output()?;
+        writer.join().unwrap()?;
+        let text = String::from_utf8_lossy(&out.stdout);
+        let mut lines: Vec<_> = text.lines().map(parse_addr2line).collect();
+        lines.resize(addrs.len(), None);
+        Ok(lines)
+    }
+}
+
+/// Parse `file:line [(discriminator N)]`; `??` means unknown.
+fn parse_addr2line(s: &str) -> Option<(String, u32)> {
+    let s = s.split(" (discriminator").next()?;
+    let (file, line) = s.rsplit_once(':')?;
+    let line: u32 = line.trim().parse().ok()?;
+    if file == "??" || line == 0 {
+        return None;
+    }
+    Some((file.to_string(), line))
+}
+
+/// Write an lcov tracefile for `blocks`.
+///
+/// Every instruction start of a block is resolved, or every
+/// 2-byte step (the RVC instruction grain) when a block's
+/// boundaries are unknown; a line's count is the highest hit count of any
+/// block touching it. Addresses in `universe` outside every
+/// block are reported with count 0 so genhtml shows them as
+/// uncovered. Returns the number of source files written.
+pub fn write_lcov(
+    blocks: &[CoveredBlock],
+    universe: &[(u64, u64)],
+    resolver: &mut impl LineResolver,
+    w: &mut impl Write,
+) -> io::Result<usize> {
+    let mut addrs: BTreeMap<u64, u64> = BTreeMap::new();
+    for &(start, end) in universe {
+        for a in (start & !1..end).step_by(2) {
+            addrs.insert(a, 0);
+        }
+    }
+    for b in blocks {
+        let mut mark = |a| {
+            let hits = addrs.entry(a).or_insert(0);
+            *hits = (*hits).max(b.hits);
+        };
+        if b.insn_starts.is_empty() {
+            (b.start..b.end).step_by(2).for_each(&mut mark);
+        } else {
+            b.insn_starts.iter().copied().for_each(mark);
+        }
+    }
+
+    let keys: Vec<u64> = addrs.keys().copied().collect();
+    let resolved = resolver.resolve(&keys)?;
+    let mut files: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+    for (loc, hits) in resolved.into_iter().zip(addrs.values()) {
+        let Some((file, line)) = loc else { continue };
+        let count = files.entry(file).or_default().entry(line).or_insert(0);
+        *count = (*count).max(*hits);
+    }
+
+    for (file, lines) in &files {
+        writeln!(w, "TN:")?;
+        writeln!(w, "SF:{file}")?;
+        for (line, hits) in lines {
+            writeln!(w, "DA:{line},{hits}")?;
+        }
+        let hit = lines.values().filter(|&&h| h > 0).count();
+        writeln!(w, "LF:{}", lines.len())?;
+        writeln!(w, "LH:{hit}")?;
+        writeln!(w, "end_of_record")?;
+    }
+    Ok(files.len())
+}
+
+/// Write `<path>` in the raw format and, when the guest has
+/// line info and an `addr2line` is available, `<path>.info`
+/// in lcov format.
+pub fn write_reports(
+    cov: &Coverage,
+    path: &Path,
+    elf: &Path,
+    exec_ranges: &[(u64, u64)],
+) -> io::Result<()> {
+    let mut raw = io::BufWriter::new(fs::File::create(path)?);
+    cov.write_raw(&mut raw)?;
+    raw.flush()?;
+
+    let Some(mut resolver) = Addr2Line::find(elf) else {
+        return Ok(());
+    };
+    let mut lcov = Vec::new();
+    let n = write_lcov(&cov.blocks(), exec_ranges, &mut resolver, &mut lcov)?;
+    if n > 0 {
+        let mut info = path.as_os_str().to_owned();
+        info.push(".info");
+        fs::write(info, lcov)?;
+    }
+    Ok(())
+}
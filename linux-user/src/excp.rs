@@ -0,0 +1,120 @@
+//! RISC-V exceptions in user mode: `ecall` is a Linux system
+//! call (or an SBI call), the rest end the run.
+
+use std::io::{self, Write};
+use std::process;
+
+use tcg_core::excp::CoreExcp;
+use tcg_exec::excp::{ExcpAction, ExcpHandler};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{
+    EXCP_EBREAK, EXCP_ECALL, EXCP_FETCH_MISALIGNED, EXCP_LOAD_MISALIGNED,
+    EXCP_STORE_MISALIGNED, EXCP_UNDEF,
+};
+
+use crate::fault;
+use crate::guest_space::GuestSpace;
+use crate::machine::{sbi_call, EcallMode};
+use crate::process::Process;
+use crate::signal::SignalTable;
+use crate::syscall::{
+    handle_syscall, strace_call, strace_ret, SyscallPolicy, SyscallResult,
+};
+use crate::vfs::Vfs;
+
+/// The emulated process around a guest CPU.
+pub struct LinuxHandler {
+    pub space: GuestSpace,
+    pub vfs: Vfs,
+    pub process: Process,
+    pub signals: SignalTable,
+    pub mmap_next: u64,
+    pub elf_path: String,
+    pub policy: SyscallPolicy,
+    pub ecall: EcallMode,
+    /// Where `-strace` logs system calls, if enabled.
+    pub strace: Option<Box<dyn Write>>,
+}
+
+impl LinuxHandler {
+    fn syscall(&mut self, cpu: &mut RiscvCpu) -> SyscallResult {
+        if let Some(log) = self.strace.as_mut() {
+            let call = strace_call(cpu.gpr[17], &cpu.gpr[10..16]);
+            let _ = write!(log, "{} {call}", process::id());
+        }
+        let result = handle_syscall(
+            &mut self.space,
+            &mut self.vfs,
+            &mut self.process,
+            &mut self.signals,
+            &mut cpu.gpr,
+            &mut self.mmap_next,
+            &self.elf_path,
+            &self.policy,
+        );
+        if let Some(log) = self.strace.as_mut() {
+            let _ = match result {
+                SyscallResult::Continue(ret) => {
+                    writeln!(log, "{}", strace_ret(ret))
+                }
+                SyscallResult::Exit(_) => writeln!(log, " = ?"),
+            };
+        }
+        result
+    }
+}
+
+impl ExcpHandler<RiscvCpu> for LinuxHandler {
+    fn handle(&mut self, cpu: &mut RiscvCpu, code: u32) -> ExcpAction {
+        match code {
+            EXCP_ECALL => {
+                let result = match self.ecall {
+                    EcallMode::Sbi => sbi_call(&cpu.gpr, &mut io::stdout()),
+                    EcallMode::Linux => self.syscall(cpu),
+                };
+                match result {
+                    SyscallResult::Continue(ret) => {
+                        fault::update(&self.space);
+                        cpu.gpr[10] = ret;
+                        cpu.pc += 4; // skip past ECALL
+                        ExcpAction::Resume
+                    }
+                    SyscallResult::Exit(status) => ExcpAction::Exit(status),
+                }
+            }
+            EXCP_EBREAK => {
+                ExcpAction::Fatal(format!("ebreak at pc={:#x}", cpu.pc))
+            }
+            EXCP_UNDEF => ExcpAction::Fatal(format!(
+                "illegal instruction at pc={:#x}",
+                cpu.pc
+            )),
+            EXCP_LOAD_MISALIGNED | EXCP_STORE_MISALIGNED => {
+                ExcpAction::Fatal(format!(
+                    "guest bus error: misaligned access at {:#x} (pc={:#x})",
+                    cpu.utval, cpu.pc
+                ))
+            }
+            EXCP_FETCH_MISALIGNED => ExcpAction::Fatal(format!(
+                "guest bus error: misaligned jump target {:#x} (pc={:#x})",
+                cpu.utval, cpu.pc
+            )),
+            _ => ExcpAction::Fatal(format!(
+                "unhandled exception {code:#x} at pc={:#x}",
+                cpu.pc
+            )),
+        }
+    }
+
+    fn handle_core(
+        &mut self,
+        cpu: &mut RiscvCpu,
+        excp: CoreExcp,
+    ) -> ExcpAction {
+        ExcpAction::Fatal(format!(
+            "{} exception at pc={:#x}",
+            excp.name(),
+            cpu.pc
+        ))
+    }
+}
@@ -0,0 +1,181 @@
+//! Host SIGSEGV/SIGBUS handling for faults on guest memory.
+//!
+//! Guest loads and stores are host accesses at `guest_base +
+//! addr`, so a bad guest access faults inside translated code.
+//! The handler says what the guest did and then lets the signal
+//! kill the process, as an uncaught SIGSEGV would on a real
+//! kernel. Guest signal delivery does not exist yet, so a guest
+//! cannot catch the fault even when it registered a handler.
+
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::guest_space::GuestSpace;
+use crate::snapshot;
+
+/// A faulting guest access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestFault {
+    /// Access to the guard below the stack.
+    StackOverflow(u64),
+    /// Any other access the guest may not make.
+    Segv(u64),
+}
+
+impl GuestFault {
+    /// Faulting guest address.
+    pub fn addr(&self) -> u64 {
+        match *self {
+            GuestFault::StackOverflow(a) | GuestFault::Segv(a) => a,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            GuestFault::StackOverflow(_) => "guest stack overflow",
+            GuestFault::Segv(_) => "guest segmentation fault",
+        }
+    }
+}
+
+/// `si_code` of a guest SIGSEGV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum SegvCode {
+    /// Nothing is mapped at the address.
+    MapErr = 1,
+    /// The mapping forbids the access.
+    AccErr = 2,
+}
+
+/// What the fault handler knows of a guest address space.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultMap {
+    pub base: usize,
+    pub size: usize,
+    /// Stack guard `[start, end)`; empty when there is none.
+    pub guard: (u64, u64),
+}
+
+impl FaultMap {
+    pub fn new(space: &GuestSpace) -> Self {
+        Self {
+            base: space.guest_base() as usize,
+            size: space.size(),
+            guard: space.stack_guard().unwrap_or((0, 0)),
+        }
+    }
+
+    /// Classify a faulting host address; `None` if it lies
+    /// outside the guest space (an emulator bug).
+    pub fn classify(&self, host_addr: usize) -> Option<GuestFault> {
+        let off = host_addr.checked_sub(self.base)?;
+        if off >= self.size {
+            return None;
+        }
+        let addr = off as u64;
+        Some(if (self.guard.0..self.guard.1).contains(&addr) {
+            GuestFault::StackOverflow(addr)
+        } else {
+            GuestFault::Segv(addr)
+        })
+    }
+}
+
+// The handler reads the layout from atomics; it must not touch
+// the region tree, which may be mid-update.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static SIZE: AtomicUsize = AtomicUsize::new(0);
+static GUARD_START: AtomicU64 = AtomicU64::new(0);
+static GUARD_END: AtomicU64 = AtomicU64::new(0);
+
+/// Publish `space`'s layout to the handler. Call again after
+/// the guest changes its mappings.
+pub fn update(space: &GuestSpace) {
+    let map = FaultMap::new(space);
+    BASE.store(map.base, Ordering::Relaxed);
+    SIZE.store(map.size, Ordering::Relaxed);
+    GUARD_START.store(map.guard.0, Ordering::Relaxed);
+    GUARD_END.store(map.guard.1, Ordering::Relaxed);
+}
+
+fn current() -> FaultMap {
+    FaultMap {
+        base: BASE.load(Ordering::Relaxed),
+        size: SIZE.load(Ordering::Relaxed),
+        guard: (
+            GUARD_START.load(Ordering::Relaxed),
+            GUARD_END.load(Ordering::Relaxed),
+        ),
+    }
+}
+
+/// Install the SIGSEGV/SIGBUS handler for `space`.
+pub fn install(space: &GuestSpace) -> io::Result<()> {
+    update(space);
+    install_handler()
+}
+
+/// Install the SIGSEGV/SIGBUS handler without publishing a
+/// layout; it then only serves snapshot write tracking.
+pub(crate) fn install_handler() -> io::Result<()> {
+    for sig in [libc::SIGSEGV, libc::SIGBUS] {
+        // SAFETY: a zeroed sigaction is valid; the fields we
+        // need are filled in below.
+        let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+        sa.sa_sigaction = on_fault as *const () as usize;
+        sa.sa_flags = libc::SA_SIGINFO;
+        // SAFETY: plain libc calls on owned values.
+        let ret = unsafe {
+            libc::sigemptyset(&mut sa.sa_mask);
+            libc::sigaction(sig, &sa, ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Write `<what> at 0x<addr>\n` into `buf` without allocating,
+/// returning its length.
+pub fn format_report(fault: GuestFault, buf: &mut [u8; 64]) -> usize {
+    let mut n = 0;
+    for &b in fault.describe().as_bytes().iter().chain(b" at 0x") {
+        buf[n] = b;
+        n += 1;
+    }
+    let addr = fault.addr();
+    let digits = (64 - addr.leading_zeros()).div_ceil(4).max(1);
+    for i in (0..digits).rev() {
+        buf[n] = b"0123456789abcdef"[(addr >> (i * 4)) as usize & 0xf];
+        n += 1;
+    }
+    buf[n] = b'\n';
+    n + 1
+}
+
+extern "C" fn on_fault(
+    sig: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _uc: *mut libc::c_void,
+) {
+    // SAFETY: SA_SIGINFO handlers get a valid siginfo.
+    let host = unsafe { (*info).si_addr() } as usize;
+    if snapshot::on_write_fault(host) {
+        // A store to a page a snapshot write-protected; it is
+        // writable again.
+        return;
+    }
+    if let Some(fault) = current().classify(host) {
+        let mut buf = [0u8; 64];
+        let n = format_report(fault, &mut buf);
+        // SAFETY: write(2) is async-signal-safe.
+        unsafe { libc::write(2, buf.as_ptr().cast(), n) };
+    }
+    // Returning re-runs the faulting access, which now takes
+    // the default action and kills the process with `sig`.
+    // SAFETY: signal(2) is async-signal-safe.
+    unsafe { libc::signal(sig, libc::SIG_DFL) };
+}
@@ -1,5 +1,12 @@
+use std::collections::BTreeMap;
 use std::io;
 use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tcg_exec::snapshot::{Rollback, SnapshotMemory};
+
+use crate::fault::SegvCode;
+use crate::snapshot::DirtyLog;
 
 /// Guest address space size: 1 GiB.
 const GUEST_SPACE_SIZE: usize = 1 << 30;
@@ -10,14 +17,121 @@ pub const GUEST_STACK_TOP: u64 = 0x3FFF_0000;
 /// Default guest stack size: 8 MiB.
 pub const GUEST_STACK_SIZE: usize = 8 * 1024 * 1024;
 
+/// Default size of the PROT_NONE guard below the stack: 1 MiB,
+/// so a large frame skipping past the stack still lands in it.
+pub const GUEST_STACK_GUARD: usize = 1024 * 1024;
+
+/// Initial RLIMIT_STACK hard limit (RLIM_INFINITY).
+const RLIM_INFINITY: u64 = u64::MAX;
+
+/// `riscv_flush_icache` flag: flush only the calling thread.
+pub const SYS_RISCV_FLUSH_ICACHE_LOCAL: u64 = 1;
+
+pub const MEMBARRIER_CMD_QUERY: u64 = 0;
+pub const MEMBARRIER_CMD_GLOBAL: u64 = 1 << 0;
+pub const MEMBARRIER_CMD_GLOBAL_EXPEDITED: u64 = 1 << 1;
+pub const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED: u64 = 1 << 2;
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED: u64 = 1 << 3;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: u64 = 1 << 4;
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE: u64 = 1 << 5;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE: u64 = 1 << 6;
+pub const MEMBARRIER_CMD_GET_REGISTRATIONS: u64 = 1 << 9;
+
+/// Commands `MEMBARRIER_CMD_QUERY` reports; the RSEQ ones are
+/// left out.
+const MEMBARRIER_SUPPORTED: u64 = MEMBARRIER_CMD_GLOBAL
+    | MEMBARRIER_CMD_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE;
+
+/// A mapped guest region: `[start, end)` with protection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub end: u64,
+    pub prot: i32,
+    /// Stack guard: reserved so nothing is placed there, but
+    /// not accessible and not reported as a mapping.
+    pub guard: bool,
+    /// Backed by a host file rather than anonymous memory.
+    pub file: bool,
+}
+
+/// Store handler of an MMIO range: `(offset, value, size)`.
+pub type MmioStore = Box<dyn FnMut(u64, u64, usize) + Send>;
+
+struct MmioRegion {
+    start: u64,
+    end: u64,
+    store: MmioStore,
+}
+
 /// mmap-based guest address space.
 ///
 /// Reserves a contiguous region of host memory and maps
-/// guest addresses as offsets within it.
+/// guest addresses as offsets within it. Mapped ranges are
+/// tracked in a region tree keyed by start address.
 pub struct GuestSpace {
     base: *mut u8,
     size: usize,
     brk: u64,
+    regions: BTreeMap<u64, Region>,
+    /// Guest ranges whose contents changed behind the
+    /// translator's back; drained by the exec loop to
+    /// invalidate TBs.
+    pending_inval: Vec<(u64, u64)>,
+    /// `MEMBARRIER_CMD_REGISTER_*` commands the process has
+    /// issued, as the kernel tracks them per mm.
+    membarrier_reg: u64,
+    /// Guard size placed below the stack by `map_stack`.
+    stack_guard: usize,
+    /// The stack mapping `[start, end)`, once mapped.
+    stack: Option<(u64, u64)>,
+    /// RLIMIT_STACK as (soft, hard).
+    stack_rlimit: (u64, u64),
+    /// Ranges whose guest stores go to a handler.
+    mmio: Vec<MmioRegion>,
+    /// Write tracking since the latest capture, and its id.
+    dirty: Option<(u64, Box<DirtyLog>)>,
+}
+
+/// Contents and layout of a `GuestSpace` at a capture.
+///
+/// Regions mapped without `PROT_READ` are not copied and come
+/// back zero-filled. MMIO handlers and host fds are not part of
+/// the image.
+pub struct MemSnapshot {
+    id: u64,
+    regions: BTreeMap<u64, Region>,
+    /// Contents of each readable region, by start address.
+    data: BTreeMap<u64, Vec<u8>>,
+    brk: u64,
+    stack: Option<(u64, u64)>,
+    stack_rlimit: (u64, u64),
+    membarrier_reg: u64,
+}
+
+impl MemSnapshot {
+    /// Captured bytes of `[start, end)`, which must lie in one
+    /// region.
+    fn bytes(&self, start: u64, end: u64) -> Option<&[u8]> {
+        let (&s, data) = self.data.range(..=start).next_back()?;
+        data.get((start - s) as usize..(end - s) as usize)
+    }
+}
+
+/// Layout of a `GuestSpace`, as a checkpoint saves it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layout {
+    pub size: usize,
+    pub regions: BTreeMap<u64, Region>,
+    pub brk: u64,
+    pub stack: Option<(u64, u64)>,
+    pub stack_rlimit: (u64, u64),
+    pub stack_guard: usize,
+    pub membarrier_reg: u64,
 }
 
 // SAFETY: GuestSpace owns its mmap'd memory exclusively.
@@ -26,11 +140,16 @@ unsafe impl Send for GuestSpace {}
 impl GuestSpace {
     /// Reserve a 1 GiB guest address space.
     pub fn new() -> io::Result<Self> {
+        Self::new_sized(GUEST_SPACE_SIZE)
+    }
+
+    /// Reserve a `size`-byte guest address space.
+    pub fn new_sized(size: usize) -> io::Result<Self> {
         // SAFETY: PROT_NONE reservation, no file backing.
         let ptr = unsafe {
             libc::mmap(
                 ptr::null_mut(),
-                GUEST_SPACE_SIZE,
+                size,
                 libc::PROT_NONE,
                 libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
                 -1,
@@ -42,11 +161,32 @@ impl GuestSpace {
         }
         Ok(Self {
             base: ptr as *mut u8,
-            size: GUEST_SPACE_SIZE,
+            size,
             brk: 0,
+            regions: BTreeMap::new(),
+            pending_inval: Vec::new(),
+            membarrier_reg: 0,
+            stack_guard: GUEST_STACK_GUARD,
+            stack: None,
+            stack_rlimit: (GUEST_STACK_SIZE as u64, RLIM_INFINITY),
+            mmio: Vec::new(),
+            dirty: None,
         })
     }
 
+    /// Use a `size`-byte stack guard (rounded up to pages; 0
+    /// disables it).
+    pub fn with_stack_guard(mut self, size: usize) -> Self {
+        self.stack_guard = page_align_up(size as u64) as usize;
+        self
+    }
+
+    /// Size of the guest address space in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     /// Translate guest address to host pointer.
     #[inline]
     pub fn g2h(&self, guest_addr: u64) -> *mut u8 {
@@ -83,13 +223,70 @@ impl GuestSpace {
         self.brk = brk;
     }
 
+    /// Check that `[addr, addr + len)` lies inside the guest
+    /// address space.
+    #[inline]
+    pub fn range_ok(&self, addr: u64, len: u64) -> bool {
+        addr.checked_add(len)
+            .is_some_and(|end| end <= self.size as u64)
+    }
+
+    /// Check that every page of `[addr, addr + len)` is mapped
+    /// with all of `prot`, as a syscall touching guest memory
+    /// requires before it may return anything but `EFAULT`.
+    pub fn access_ok(&self, addr: u64, len: u64, prot: i32) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        self.range_ok(addr, len)
+            && self.is_mapped(addr, end)
+            && self
+                .regions
+                .range(..end)
+                .rev()
+                .take_while(|(_, r)| r.end > addr)
+                .all(|(_, r)| r.prot & prot == prot)
+    }
+
     /// Map a fixed region within the guest space.
     pub fn mmap_fixed(
-        &self,
+        &mut self,
         guest_addr: u64,
         size: usize,
         prot: i32,
     ) -> io::Result<()> {
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        self.map(guest_addr, size, prot, flags, -1, 0)
+    }
+
+    /// Map `size` bytes of host file `fd` from `offset` at a fixed
+    /// guest address, shared or private as `flags` says.
+    pub fn mmap_file(
+        &mut self,
+        guest_addr: u64,
+        size: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> io::Result<()> {
+        let share = flags & (libc::MAP_SHARED | libc::MAP_PRIVATE);
+        self.map(guest_addr, size, prot, share, fd, offset)
+    }
+
+    fn map(
+        &mut self,
+        guest_addr: u64,
+        size: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> io::Result<()> {
+        if !self.range_ok(guest_addr, size as u64) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        self.mark_dirty(guest_addr, guest_addr + size as u64);
         let host = self.g2h(guest_addr);
         // SAFETY: within our reserved region.
         let ret = unsafe {
@@ -97,28 +294,296 @@ impl GuestSpace {
                 host as *mut libc::c_void,
                 size,
                 prot,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
-                -1,
-                0,
+                flags | libc::MAP_FIXED,
+                fd,
+                offset,
             )
         };
         if ret == libc::MAP_FAILED {
-            Err(io::Error::last_os_error())
-        } else {
-            Ok(())
+            return Err(io::Error::last_os_error());
+        }
+        let end = guest_addr + size as u64;
+        self.region_remove(guest_addr, end);
+        self.regions.insert(
+            guest_addr,
+            Region {
+                end,
+                prot,
+                guard: false,
+                file: fd >= 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Map a read/write stack of `size` bytes ending at `top`,
+    /// with the stack guard directly below it.
+    pub fn map_stack(&mut self, top: u64, size: usize) -> io::Result<()> {
+        let base = top
+            .checked_sub(size as u64)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOMEM))?;
+        self.mmap_fixed(base, size, libc::PROT_READ | libc::PROT_WRITE)?;
+        self.stack = Some((base, top));
+        let guard = (self.stack_guard as u64).min(base);
+        if guard > 0 {
+            let lo = base - guard;
+            self.reserve(lo, guard as usize)?;
+            self.region_remove(lo, base);
+            self.regions.insert(
+                lo,
+                Region {
+                    end: base,
+                    prot: libc::PROT_NONE,
+                    guard: true,
+                    file: false,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The stack guard range `[start, end)`, if one is mapped.
+    pub fn stack_guard(&self) -> Option<(u64, u64)> {
+        let (base, _) = self.stack?;
+        self.region_at(base.checked_sub(1)?)
+            .filter(|(_, r)| r.guard)
+            .map(|(s, r)| (s, r.end))
+    }
+
+    /// RLIMIT_STACK as (soft, hard).
+    pub fn stack_rlimit(&self) -> (u64, u64) {
+        self.stack_rlimit
+    }
+
+    /// Set RLIMIT_STACK. The live stack mapping is left alone,
+    /// as on Linux; only later reads see the new limit.
+    pub fn set_stack_rlimit(&mut self, cur: u64, max: u64) -> io::Result<()> {
+        if cur > max {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
         }
+        if max > self.stack_rlimit.1 {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        self.stack_rlimit = (cur, max);
+        Ok(())
     }
 
     /// Change protection on a guest region.
     pub fn mprotect(
-        &self,
+        &mut self,
         guest_addr: u64,
         size: usize,
         prot: i32,
     ) -> io::Result<()> {
+        let end = guest_addr + size as u64;
+        if self.overlaps_guard(guest_addr, end) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        self.mark_dirty(guest_addr, end);
         let host = self.g2h(guest_addr);
         let ret =
             unsafe { libc::mprotect(host as *mut libc::c_void, size, prot) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let covered: Vec<(u64, Region)> = self
+            .regions
+            .range(..end)
+            .filter(|(_, r)| r.end > guest_addr)
+            .map(|(&s, &r)| (s, r))
+            .collect();
+        self.region_remove(guest_addr, end);
+        for (s, r) in covered {
+            let (lo, hi) = (s.max(guest_addr), r.end.min(end));
+            self.regions.insert(lo, Region { end: hi, prot, ..r });
+        }
+        Ok(())
+    }
+
+    /// Unmap a guest region, returning it to the PROT_NONE
+    /// reservation.
+    pub fn munmap(&mut self, guest_addr: u64, size: usize) -> io::Result<()> {
+        check_page_range(guest_addr, 0)?;
+        let size = page_align_up(size as u64);
+        if size == 0 || !self.range_ok(guest_addr, size) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        self.reserve(guest_addr, size as usize)?;
+        let end = guest_addr + size;
+        self.region_remove(guest_addr, end);
+        self.pending_inval.push((guest_addr, end));
+        Ok(())
+    }
+
+    /// Forward a madvise() hint for a mapped guest range.
+    ///
+    /// DONTNEED/FREE discard the contents, so any TBs
+    /// translated from the range are queued for invalidation.
+    pub fn madvise(
+        &mut self,
+        guest_addr: u64,
+        len: usize,
+        advice: i32,
+    ) -> io::Result<()> {
+        check_page_range(guest_addr, 0)?;
+        let len = page_align_up(len as u64);
+        if len == 0 {
+            return Ok(());
+        }
+        if !self.range_ok(guest_addr, len) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let end = guest_addr + len;
+        if !self.is_mapped(guest_addr, end) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        match advice {
+            libc::MADV_NORMAL
+            | libc::MADV_RANDOM
+            | libc::MADV_SEQUENTIAL
+            | libc::MADV_WILLNEED
+            | libc::MADV_DONTNEED
+            | libc::MADV_FREE => {}
+            // Other hints (hugepage, dump, fork) have no
+            // observable effect on the guest.
+            _ => return Ok(()),
+        }
+        self.mark_dirty(guest_addr, end);
+        let host = self.g2h(guest_addr) as *mut libc::c_void;
+        // SAFETY: the range is mapped inside our reservation.
+        let ret = unsafe { libc::madvise(host, len as usize, advice) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if advice == libc::MADV_DONTNEED || advice == libc::MADV_FREE {
+            self.pending_inval.push((guest_addr, end));
+        }
+        Ok(())
+    }
+
+    /// Resize (and possibly move) a mapped guest region.
+    ///
+    /// Shrinking and growing anonymous memory into free space
+    /// happen in place. With `MREMAP_MAYMOVE` the mapping is
+    /// moved to `*mmap_next` when it cannot grow in place, and
+    /// file-backed mappings always move so the new pages come
+    /// from the file. Returns the new guest address.
+    pub fn mremap(
+        &mut self,
+        old_addr: u64,
+        old_len: usize,
+        new_len: usize,
+        flags: i32,
+        mmap_next: &mut u64,
+    ) -> io::Result<u64> {
+        check_page_range(old_addr, 0)?;
+        if flags & !libc::MREMAP_MAYMOVE != 0 || new_len == 0 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let old_len = page_align_up(old_len as u64);
+        let new_len = page_align_up(new_len as u64);
+        let old_end = old_addr
+            .checked_add(old_len)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        if old_len == 0 || !self.is_mapped(old_addr, old_end) {
+            return Err(io::Error::from_raw_os_error(libc::EFAULT));
+        }
+        // Like one Linux VMA, the old range must share protection
+        // and backing; a file mapping is never merged, so it has
+        // to lie in a single region.
+        let (first, r) = self.region_at(old_addr).unwrap();
+        let (prot, file) = (r.prot, r.file);
+        let single = if file {
+            r.end >= old_end
+        } else {
+            self.regions
+                .range(first..old_end)
+                .all(|(_, r)| r.prot == prot && !r.file)
+        };
+        if !single {
+            return Err(io::Error::from_raw_os_error(libc::EFAULT));
+        }
+
+        if new_len <= old_len {
+            if new_len < old_len {
+                self.munmap(old_addr + new_len, (old_len - new_len) as usize)?;
+                self.clamp_brk(old_addr, old_addr + new_len, old_end);
+            }
+            return Ok(old_addr);
+        }
+
+        let new_end = old_addr + new_len;
+        if !file
+            && self.range_ok(old_addr, new_len)
+            && self.is_free(old_end, new_end)
+        {
+            self.mmap_fixed(old_end, (new_len - old_len) as usize, prot)?;
+            self.region_merge(old_addr);
+            return Ok(old_addr);
+        }
+        if flags & libc::MREMAP_MAYMOVE == 0 {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+
+        let dst = *mmap_next;
+        if !self.range_ok(dst, new_len) || !self.is_free(dst, dst + new_len) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        self.mark_dirty(old_addr, old_end);
+        // SAFETY: both ranges lie inside our reservation;
+        // MREMAP_FIXED replaces the PROT_NONE pages at dst.
+        let ret = unsafe {
+            libc::mremap(
+                self.g2h(old_addr) as *mut libc::c_void,
+                old_len as usize,
+                new_len as usize,
+                libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED,
+                self.g2h(dst) as *mut libc::c_void,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        *mmap_next += new_len;
+        // The host unmapped the old range; put the
+        // reservation back so it stays ours.
+        self.reserve(old_addr, old_len as usize)?;
+        self.region_remove(old_addr, old_end);
+        self.regions.insert(
+            dst,
+            Region {
+                end: dst + new_len,
+                prot,
+                guard: false,
+                file,
+            },
+        );
+        self.pending_inval.push((old_addr, old_end));
+        self.clamp_brk(old_addr, old_addr, old_end);
+        Ok(dst)
+    }
+
+    /// Flush a mapped guest range to its backing store.
+    pub fn msync(
+        &self,
+        guest_addr: u64,
+        len: usize,
+        flags: i32,
+    ) -> io::Result<()> {
+        check_page_range(guest_addr, 0)?;
+        let len = page_align_up(len as u64);
+        if !self.range_ok(guest_addr, len) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        if !self.is_mapped(guest_addr, guest_addr + len) {
+            return Err(io::Error::from_raw_os_error(libc::ENOMEM));
+        }
+        let host = self.g2h(guest_addr) as *mut libc::c_void;
+        // SAFETY: the range is mapped inside our reservation.
+        let ret = unsafe { libc::msync(host, len as usize, flags) };
         if ret != 0 {
             Err(io::Error::last_os_error())
         } else {
@@ -126,6 +591,269 @@ impl GuestSpace {
         }
     }
 
+    /// `riscv_flush_icache(start, end, flags)`: queue TBs
+    /// translated from `[start, end)` for invalidation.
+    ///
+    /// linux-user runs one vCPU, so a `SYS_RISCV_FLUSH_ICACHE_LOCAL`
+    /// flush is the same as a global one.
+    pub fn flush_icache(
+        &mut self,
+        start: u64,
+        end: u64,
+        flags: u64,
+    ) -> io::Result<()> {
+        if flags & !SYS_RISCV_FLUSH_ICACHE_LOCAL != 0 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        if start < end {
+            self.pending_inval.push((start, end));
+        }
+        Ok(())
+    }
+
+    /// `membarrier(cmd, flags, cpu_id)`.
+    ///
+    /// With one vCPU every barrier is already satisfied; the
+    /// commands only check registration as the kernel does.
+    /// `PRIVATE_EXPEDITED_SYNC_CORE` is the one that promises
+    /// fresh instruction fetch, so it queues every TB for
+    /// invalidation.
+    pub fn membarrier(&mut self, cmd: u64, flags: u64) -> io::Result<u64> {
+        let einval = || io::Error::from_raw_os_error(libc::EINVAL);
+        let eperm = || io::Error::from_raw_os_error(libc::EPERM);
+        if flags != 0 {
+            return Err(einval());
+        }
+        match cmd {
+            MEMBARRIER_CMD_QUERY => Ok(MEMBARRIER_SUPPORTED),
+            MEMBARRIER_CMD_GLOBAL | MEMBARRIER_CMD_GLOBAL_EXPEDITED => Ok(0),
+            MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE => {
+                self.membarrier_reg |= cmd;
+                Ok(0)
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED => {
+                if self.membarrier_reg
+                    & MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+                    == 0
+                {
+                    return Err(eperm());
+                }
+                Ok(0)
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE => {
+                if self.membarrier_reg
+                    & MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE
+                    == 0
+                {
+                    return Err(eperm());
+                }
+                self.pending_inval.push((0, u64::MAX));
+                Ok(0)
+            }
+            MEMBARRIER_CMD_GET_REGISTRATIONS => Ok(self.membarrier_reg),
+            _ => Err(einval()),
+        }
+    }
+
+    /// Look up the region containing `addr`.
+    pub fn region_at(&self, addr: u64) -> Option<(u64, Region)> {
+        self.regions
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, r)| addr < r.end)
+            .map(|(&s, &r)| (s, r))
+    }
+
+    /// `si_code` for a fault at `addr`: `AccErr` inside a guest
+    /// mapping, `MapErr` elsewhere, the stack guard included.
+    pub fn segv_code(&self, addr: u64) -> SegvCode {
+        match self.region_at(addr) {
+            Some((_, r)) if !r.guard => SegvCode::AccErr,
+            _ => SegvCode::MapErr,
+        }
+    }
+
+    /// Send guest stores to `[start, end)` to `store`. Stores
+    /// reach it only from code translated with `mmio_window` as
+    /// the frontend's MMIO window.
+    pub fn register_mmio(&mut self, start: u64, end: u64, store: MmioStore) {
+        self.mmio.push(MmioRegion { start, end, store });
+    }
+
+    /// Smallest range covering every MMIO region.
+    pub fn mmio_window(&self) -> Option<(u64, u64)> {
+        let start = self.mmio.iter().map(|r| r.start).min()?;
+        let end = self.mmio.iter().map(|r| r.end).max()?;
+        Some((start, end))
+    }
+
+    /// Make a `size`-byte guest store of `val` to `addr` that
+    /// left translated code for lying in the MMIO window: its
+    /// region's handler takes it, otherwise it goes to memory.
+    /// Fails if the memory there is not writable.
+    pub fn mmio_store(
+        &mut self,
+        addr: u64,
+        val: u64,
+        size: usize,
+    ) -> Result<(), SegvCode> {
+        if let Some(r) = self
+            .mmio
+            .iter_mut()
+            .find(|r| (r.start..r.end).contains(&addr))
+        {
+            (r.store)(addr - r.start, val, size);
+            return Ok(());
+        }
+        match self.region_at(addr) {
+            Some((_, r))
+                if r.prot & libc::PROT_WRITE != 0
+                    && addr + size as u64 <= r.end =>
+            {
+                // SAFETY: checked writable above.
+                unsafe { self.write_bytes(addr, &val.to_le_bytes()[..size]) };
+                Ok(())
+            }
+            _ => Err(self.segv_code(addr)),
+        }
+    }
+
+    /// Take the guest ranges queued for TB invalidation.
+    pub fn take_invalidations(&mut self) -> Vec<(u64, u64)> {
+        std::mem::take(&mut self.pending_inval)
+    }
+
+    /// `/proc/self/maps`-style listing of the guest mappings.
+    /// The stack guard is left out, showing as a gap.
+    pub fn maps(&self) -> String {
+        let mut out = String::new();
+        for (&start, r) in self.regions.iter().filter(|(_, r)| !r.guard) {
+            let bit = |flag, c| if r.prot & flag != 0 { c } else { '-' };
+            out.push_str(&format!(
+                "{start:08x}-{:08x} {}{}{}p 00000000 00:00 0",
+                r.end,
+                bit(libc::PROT_READ, 'r'),
+                bit(libc::PROT_WRITE, 'w'),
+                bit(libc::PROT_EXEC, 'x'),
+            ));
+            if self.stack.is_some_and(|(_, top)| r.end == top) {
+                out.push_str("    [stack]");
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// True if every page of `[start, end)` is mapped.
+    fn is_mapped(&self, start: u64, end: u64) -> bool {
+        let mut cur = start;
+        while cur < end {
+            match self.region_at(cur) {
+                Some((_, r)) if !r.guard => cur = r.end,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// True if `[start, end)` touches a stack guard.
+    fn overlaps_guard(&self, start: u64, end: u64) -> bool {
+        self.regions
+            .range(..end)
+            .rev()
+            .take_while(|(_, r)| r.end > start)
+            .any(|(_, r)| r.guard)
+    }
+
+    /// True if no page of `[start, end)` is mapped or reserved
+    /// as a guard, so an mmap hint may be placed there.
+    pub fn is_free(&self, start: u64, end: u64) -> bool {
+        self.regions
+            .range(..end)
+            .next_back()
+            .is_none_or(|(_, r)| r.end <= start)
+    }
+
+    /// Drop `[start, end)` from the region tree, splitting
+    /// regions that straddle either boundary.
+    fn region_remove(&mut self, start: u64, end: u64) {
+        let hit: Vec<(u64, Region)> = self
+            .regions
+            .range(..end)
+            .rev()
+            .take_while(|(_, r)| r.end > start)
+            .map(|(&s, &r)| (s, r))
+            .collect();
+        for (s, r) in hit {
+            self.regions.remove(&s);
+            if s < start {
+                self.regions.insert(s, Region { end: start, ..r });
+            }
+            if r.end > end {
+                self.regions.insert(end, r);
+            }
+        }
+    }
+
+    /// Coalesce the region at `start` with its successor if
+    /// they are adjacent anonymous memory of equal protection.
+    fn region_merge(&mut self, start: u64) {
+        let Some(&r) = self.regions.get(&start) else {
+            return;
+        };
+        if let Some(&next) = self.regions.get(&r.end) {
+            if next.prot == r.prot && !next.guard && !r.file && !next.file {
+                self.regions.remove(&r.end);
+                self.regions.insert(start, next);
+            }
+        }
+    }
+
+    /// Clamp brk if the heap tail `[cut, old_end)` of the
+    /// region starting at `start` went away.
+    fn clamp_brk(&mut self, start: u64, cut: u64, old_end: u64) {
+        if self.brk > start && self.brk <= old_end && self.brk > cut {
+            self.brk = cut.max(start);
+        }
+    }
+
+    /// Replace a guest range with the PROT_NONE reservation.
+    fn reserve(&self, guest_addr: u64, size: usize) -> io::Result<()> {
+        self.mark_dirty(guest_addr, guest_addr + size as u64);
+        let host = self.g2h(guest_addr);
+        // SAFETY: within our reserved region.
+        let ret = unsafe {
+            libc::mmap(
+                host as *mut libc::c_void,
+                size,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE
+                    | libc::MAP_ANONYMOUS
+                    | libc::MAP_NORESERVE
+                    | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Count `[start, end)` as written since the latest capture
+    /// and make it writable again, ahead of a change the write
+    /// tracking cannot see: a host syscall filling a guest
+    /// buffer, or a change to the mapping itself.
+    pub(crate) fn mark_dirty(&self, start: u64, end: u64) {
+        if let Some((_, log)) = &self.dirty {
+            log.mark_range(start, end);
+        }
+    }
+
     /// Write bytes at a guest address.
     ///
     /// # Safety
@@ -154,6 +882,260 @@ impl GuestSpace {
     }
 }
 
+impl GuestSpace {
+    pub(crate) fn layout(&self) -> Layout {
+        Layout {
+            size: self.size,
+            regions: self.regions.clone(),
+            brk: self.brk,
+            stack: self.stack,
+            stack_rlimit: self.stack_rlimit,
+            stack_guard: self.stack_guard,
+            membarrier_reg: self.membarrier_reg,
+        }
+    }
+
+    /// A space with `layout`, zero-filled. `fill` writes the
+    /// contents while every region is writable; each then gets
+    /// its protection.
+    pub(crate) fn from_layout(
+        layout: &Layout,
+        fill: impl FnOnce(&GuestSpace) -> io::Result<()>,
+    ) -> io::Result<Self> {
+        let mut space = Self::new_sized(layout.size)?;
+        let rw = libc::PROT_READ | libc::PROT_WRITE;
+        let mapped = || layout.regions.iter().filter(|(_, r)| !r.guard);
+        for (&start, r) in mapped() {
+            if !space.range_ok(start, r.end - start) {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            space.mmap_fixed(start, (r.end - start) as usize, rw)?;
+        }
+        fill(&space)?;
+        for (&start, r) in mapped().filter(|(_, r)| r.prot != rw) {
+            space.mprotect(start, (r.end - start) as usize, r.prot)?;
+        }
+        // The contents were copied, so nothing is file-backed.
+        space.regions = layout
+            .regions
+            .iter()
+            .map(|(&s, &r)| (s, Region { file: false, ..r }))
+            .collect();
+        space.brk = layout.brk;
+        space.stack = layout.stack;
+        space.stack_rlimit = layout.stack_rlimit;
+        space.stack_guard = layout.stack_guard;
+        space.membarrier_reg = layout.membarrier_reg;
+        space.pending_inval.clear();
+        Ok(space)
+    }
+
+    /// Track writes anew, replacing the tracking of any
+    /// capture, and return its id. If the tracking replaced is
+    /// the one `since` returned, also return the ranges written
+    /// under it: the pages stored to, and every range changed
+    /// behind it.
+    pub(crate) fn track_writes(
+        &mut self,
+        since: Option<u64>,
+    ) -> io::Result<(u64, Option<Ranges>)> {
+        let written = match self.dirty.take() {
+            Some((id, log)) if Some(id) == since => {
+                let shift = log.page_shift();
+                let mut ranges: Vec<(u64, u64)> = log
+                    .take_dirty()
+                    .into_iter()
+                    .map(|p| (p << shift, (p + 1) << shift))
+                    .collect();
+                ranges.extend(log.take_touched());
+                Some(merge_ranges(ranges))
+            }
+            _ => None,
+        };
+        let tracked = self
+            .regions
+            .iter()
+            .filter(|(_, r)| !r.guard && r.prot & libc::PROT_WRITE != 0)
+            .map(|(&s, r)| (s, r.end, r.prot))
+            .collect();
+        let id = NEXT_CAPTURE.fetch_add(1, Ordering::Relaxed);
+        self.dirty = Some((id, DirtyLog::new(self.base, self.size, tracked)?));
+        Ok((id, written))
+    }
+}
+
+/// Guest `[start, end)` ranges.
+pub(crate) type Ranges = Vec<(u64, u64)>;
+
+/// Id of the next capture, so a stale image is refused.
+static NEXT_CAPTURE: AtomicU64 = AtomicU64::new(1);
+
+impl SnapshotMemory for GuestSpace {
+    type Image = MemSnapshot;
+
+    /// Copy every readable region and write-protect the
+    /// writable ones. Replaces the tracking of any earlier
+    /// capture.
+    fn capture(&mut self) -> io::Result<MemSnapshot> {
+        self.dirty = None;
+        let mut data = BTreeMap::new();
+        let mut tracked = Vec::new();
+        for (&start, r) in self.regions.iter().filter(|(_, r)| !r.guard) {
+            if r.prot & libc::PROT_READ != 0 {
+                let len = (r.end - start) as usize;
+                // SAFETY: the region is mapped readable.
+                let src =
+                    unsafe { std::slice::from_raw_parts(self.g2h(start), len) };
+                data.insert(start, src.to_vec());
+            }
+            if r.prot & libc::PROT_WRITE != 0 {
+                tracked.push((start, r.end, r.prot));
+            }
+        }
+        let id = NEXT_CAPTURE.fetch_add(1, Ordering::Relaxed);
+        self.dirty = Some((id, DirtyLog::new(self.base, self.size, tracked)?));
+        Ok(MemSnapshot {
+            id,
+            regions: self.regions.clone(),
+            data,
+            brk: self.brk,
+            stack: self.stack,
+            stack_rlimit: self.stack_rlimit,
+            membarrier_reg: self.membarrier_reg,
+        })
+    }
+
+    /// Remap what the guest mapped, unmapped or reprotected
+    /// since the capture, then copy back the pages written
+    /// since the capture or the previous rollback.
+    fn rollback(&mut self, image: &MemSnapshot) -> io::Result<Rollback> {
+        let log = match self.dirty.take() {
+            Some((id, log)) if id == image.id => log,
+            other => {
+                self.dirty = other;
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+        };
+        let result = self.rollback_with(&log, image);
+        self.dirty = Some((image.id, log));
+        result
+    }
+}
+
+impl GuestSpace {
+    fn rollback_with(
+        &mut self,
+        log: &DirtyLog,
+        image: &MemSnapshot,
+    ) -> io::Result<Rollback> {
+        let mut changed = std::mem::take(&mut self.pending_inval);
+
+        let stale: Vec<(u64, u64)> = self
+            .regions
+            .iter()
+            .filter(|&(s, r)| image.regions.get(s) != Some(r))
+            .map(|(&s, r)| (s, r.end))
+            .collect();
+        let lost: Vec<(u64, Region)> = image
+            .regions
+            .iter()
+            .filter(|&(s, r)| self.regions.get(s) != Some(r))
+            .map(|(&s, &r)| (s, r))
+            .collect();
+        for &(start, end) in &stale {
+            self.reserve(start, (end - start) as usize)?;
+            changed.push((start, end));
+        }
+        for &(start, r) in &lost {
+            changed.push((start, r.end));
+            if !r.guard {
+                self.remap_from(log, image, start, r)?;
+            }
+        }
+        self.regions.clone_from(&image.regions);
+
+        // Checkpoints alone use the touched ranges.
+        log.take_touched();
+        let shift = log.page_shift();
+        let in_lost =
+            |addr: u64| lost.iter().any(|&(s, r)| (s..r.end).contains(&addr));
+        let pages: Vec<u64> = log
+            .take_dirty()
+            .into_iter()
+            .filter(|&p| !in_lost(p << shift))
+            .collect();
+        // Copy back runs of adjacent pages within one region.
+        let mut i = 0;
+        while i < pages.len() {
+            let start = pages[i] << shift;
+            let (_, r) = self.region_at(start).expect("tracked page");
+            let mut j = i + 1;
+            while j < pages.len()
+                && pages[j] == pages[j - 1] + 1
+                && pages[j] << shift < r.end
+            {
+                j += 1;
+            }
+            let end = (pages[j - 1] + 1) << shift;
+            let src = image.bytes(start, end).expect("tracked page data");
+            // SAFETY: marking the pages dirty made them writable.
+            unsafe { self.write_bytes(start, src) };
+            log.protect(start, end, r.prot)?;
+            changed.push((start, end));
+            i = j;
+        }
+
+        self.brk = image.brk;
+        self.stack = image.stack;
+        self.stack_rlimit = image.stack_rlimit;
+        self.membarrier_reg = image.membarrier_reg;
+
+        Ok(Rollback {
+            pages: pages.len(),
+            changed: merge_ranges(changed),
+        })
+    }
+
+    /// Map image region `r` at `start` again with its captured
+    /// contents, protecting it if it is tracked.
+    fn remap_from(
+        &self,
+        log: &DirtyLog,
+        image: &MemSnapshot,
+        start: u64,
+        r: Region,
+    ) -> io::Result<()> {
+        let len = (r.end - start) as usize;
+        // SAFETY: within our reserved region.
+        let ret = unsafe {
+            libc::mmap(
+                self.g2h(start) as *mut libc::c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(src) = image.bytes(start, r.end) {
+            // SAFETY: just mapped writable.
+            unsafe { self.write_bytes(start, src) };
+        }
+        if r.prot & libc::PROT_WRITE != 0 {
+            return log.protect(start, r.end, r.prot);
+        }
+        // SAFETY: as above.
+        let ret = unsafe { libc::mprotect(ret, len, r.prot) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
 impl Drop for GuestSpace {
     fn drop(&mut self) {
         if !self.base.is_null() {
@@ -182,3 +1164,26 @@ pub fn page_align_down(addr: u64) -> u64 {
     let ps = page_size() as u64;
     addr & !(ps - 1)
 }
+
+/// Sort `ranges` and coalesce the overlapping or adjacent ones.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// EINVAL unless `addr` and `len` are page aligned.
+fn check_page_range(addr: u64, len: u64) -> io::Result<()> {
+    let ps = page_size() as u64;
+    if !addr.is_multiple_of(ps) || !len.is_multiple_of(ps) {
+        Err(io::Error::from_raw_os_error(libc::EINVAL))
+    } else {
+        Ok(())
+    }
+}
@@ -1,4 +1,16 @@
+pub mod checkpoint;
+pub mod config;
+pub mod coverage;
 pub mod elf;
+pub mod excp;
+pub mod fault;
 pub mod guest_space;
 pub mod loader;
+pub mod machine;
+pub mod process;
+pub mod signal;
+mod snapshot;
+pub mod socket;
 pub mod syscall;
+pub mod vfs;
+pub mod warmup;
@@ -49,6 +49,8 @@ pub struct ElfInfo {
     pub phnum: u16,
     pub sp: u64,
     pub brk: u64,
+    /// Executable segments `[start, end)` as loaded.
+    pub exec_ranges: Vec<(u64, u64)>,
 }
 
 /// Convert ELF p_flags to mmap prot flags.
@@ -99,6 +101,7 @@ pub fn load_elf(
     }
 
     // Load PT_LOAD segments
+    let mut exec_ranges = Vec::new();
     for ph in phdrs {
         if ph.p_type != PT_LOAD {
             continue;
@@ -132,6 +135,10 @@ pub fn load_elf(
             }
         }
 
+        if ph.p_flags & PF_X != 0 {
+            exec_ranges.push((ph.p_vaddr, ph.p_vaddr + ph.p_memsz));
+        }
+
         // Set final permissions
         let prot = elf_to_prot(ph.p_flags);
         if prot != (libc::PROT_READ | libc::PROT_WRITE) {
@@ -168,12 +175,13 @@ pub fn load_elf(
         phnum: ehdr.e_phnum,
         sp,
         brk,
+        exec_ranges,
     })
 }
 
 /// Build initial stack per Linux ABI.
 fn setup_stack(
-    space: &GuestSpace,
+    space: &mut GuestSpace,
     entry: u64,
     phdr_addr: u64,
     phnum: u16,
@@ -182,14 +190,9 @@ fn setup_stack(
     execfn: &str,
 ) -> Result<u64, LoadError> {
     let stack_top = GUEST_STACK_TOP;
-    let stack_base = stack_top - GUEST_STACK_SIZE as u64;
 
-    // Map stack
-    space.mmap_fixed(
-        stack_base,
-        GUEST_STACK_SIZE,
-        libc::PROT_READ | libc::PROT_WRITE,
-    )?;
+    // Map stack, with its guard below
+    space.map_stack(stack_top, GUEST_STACK_SIZE)?;
 
     // Build from top down
     let mut pos = stack_top;
@@ -298,3 +301,47 @@ fn setup_stack(
 
     Ok(sp)
 }
+
+/// Whether `data` starts with the ELF magic.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7fELF")
+}
+
+/// Load a flat image at `load_addr` into RAM `[load_addr,
+/// load_addr + ram_size)`, mapped read/write/execute. The stack
+/// pointer starts at the top of RAM and the break right after
+/// the image; there is no auxv or argument block.
+pub fn load_flat(
+    data: &[u8],
+    space: &mut GuestSpace,
+    load_addr: u64,
+    entry: u64,
+    ram_size: u64,
+) -> Result<ElfInfo, LoadError> {
+    let ram_end = load_addr
+        .checked_add(ram_size)
+        .filter(|&end| space.range_ok(load_addr, ram_size) && end > load_addr)
+        .ok_or(LoadError::SegmentOutOfRange)?;
+    let image_end = load_addr + data.len() as u64;
+    if image_end > ram_end || !(load_addr..ram_end).contains(&entry) {
+        return Err(LoadError::SegmentOutOfRange);
+    }
+    let start = page_align_down(load_addr);
+    space.mmap_fixed(
+        start,
+        (page_align_up(ram_end) - start) as usize,
+        libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+    )?;
+    // SAFETY: mapped writable above.
+    unsafe { space.write_bytes(load_addr, data) };
+    let brk = page_align_up(image_end);
+    space.set_brk(brk);
+    Ok(ElfInfo {
+        entry,
+        phdr_addr: 0,
+        phnum: 0,
+        sp: ram_end & !0xf,
+        brk,
+        exec_ranges: vec![(load_addr, image_end)],
+    })
+}
@@ -0,0 +1,109 @@
+//! Minimal machine model for flat, non-ELF images.
+//!
+//! A bare-metal image runs on the same exec pipeline as a Linux
+//! binary. Only `ecall` handling differs: besides the Linux
+//! system call ABI it can follow an SBI subset enough for
+//! putchar and exit. An optional UART takes byte stores to its
+//! transmit register, through the guest space's MMIO hook.
+
+use std::io::Write;
+
+use crate::guest_space::{page_size, GuestSpace, MmioStore};
+use crate::syscall::SyscallResult;
+
+/// Default load address of a flat image, as on QEMU's virt
+/// machine.
+pub const DEFAULT_LOAD_ADDR: u64 = 0x8000_0000;
+
+/// Default RAM size: 128 MiB from the load address.
+pub const DEFAULT_RAM_SIZE: u64 = 128 * 1024 * 1024;
+
+/// How `ecall` is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EcallMode {
+    /// Linux system calls.
+    #[default]
+    Linux,
+    /// SBI subset, see [`sbi_call`].
+    Sbi,
+}
+
+impl EcallMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "linux" => Some(Self::Linux),
+            "sbi" => Some(Self::Sbi),
+            _ => None,
+        }
+    }
+}
+
+/// Legacy `sbi_console_putchar`.
+pub const SBI_CONSOLE_PUTCHAR: u64 = 0x01;
+/// Legacy `sbi_shutdown`. Here it exits with status `a0`.
+pub const SBI_SHUTDOWN: u64 = 0x08;
+/// System Reset extension (`SRST`).
+pub const SBI_EXT_SRST: u64 = 0x5352_5354;
+
+const SBI_ERR_NOT_SUPPORTED: i64 = -2;
+
+/// Handle an `ecall` under [`EcallMode::Sbi`]: the extension
+/// is in `a7`, arguments from `a0`. `SRST` exits with status 0
+/// for reason "no reason" (`a1 = 0`) and 1 otherwise. Anything
+/// else returns `SBI_ERR_NOT_SUPPORTED`.
+pub fn sbi_call(gpr: &[u64; 32], out: &mut impl Write) -> SyscallResult {
+    let (a0, a1, a7) = (gpr[10], gpr[11], gpr[17]);
+    match a7 {
+        SBI_CONSOLE_PUTCHAR => {
+            let _ = out.write_all(&[a0 as u8]).and_then(|_| out.flush());
+            SyscallResult::Continue(0)
+        }
+        SBI_SHUTDOWN => SyscallResult::Exit(a0 as i32),
+        SBI_EXT_SRST => SyscallResult::Exit((a1 != 0) as i32),
+        _ => SyscallResult::Continue(SBI_ERR_NOT_SUPPORTED as u64),
+    }
+}
+
+/// 16550 registers the UART model uses.
+const UART_THR: u64 = 0;
+const UART_LSR: u64 = 5;
+/// LSR: transmitter empty and idle.
+const UART_LSR_IDLE: u8 = 0x60;
+/// Register bytes of the UART.
+const UART_LEN: u64 = 8;
+
+/// Map a 16550-style UART at `base` whose transmitted bytes go
+/// to `out`. Stores to the transmit register are forwarded,
+/// other register stores are dropped. Loads read plain memory
+/// in which LSR always shows an idle transmitter, so polling
+/// drivers proceed.
+pub fn map_uart(
+    space: &mut GuestSpace,
+    base: u64,
+    out: impl Write + Send + 'static,
+) -> std::io::Result<()> {
+    let page = base & !(page_size() as u64 - 1);
+    if space.region_at(page).is_some() {
+        return Err(std::io::Error::from_raw_os_error(libc::EEXIST));
+    }
+    space.mmap_fixed(page, page_size(), libc::PROT_READ | libc::PROT_WRITE)?;
+    // SAFETY: mapped writable above.
+    unsafe { space.write_bytes(base + UART_LSR, &[UART_LSR_IDLE]) };
+    attach_uart(space, base, out);
+    Ok(())
+}
+
+/// Forward transmit register stores of the UART already mapped
+/// at `base`, e.g. in a resumed guest space, to `out`.
+pub fn attach_uart(
+    space: &mut GuestSpace,
+    base: u64,
+    mut out: impl Write + Send + 'static,
+) {
+    let store: MmioStore = Box::new(move |off, val, _| {
+        if off == UART_THR {
+            let _ = out.write_all(&[val as u8]).and_then(|_| out.flush());
+        }
+    });
+    space.register_mmio(base, base + UART_LEN, store);
+}
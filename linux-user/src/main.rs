@@ -1,24 +1,47 @@
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
-use tcg_backend::X86_64CodeGen;
+use tcg_backend::NativeCodeGen;
 use tcg_core::context::Context;
-use tcg_core::tb::{EXCP_EBREAK, EXCP_ECALL, EXCP_UNDEF};
-use tcg_core::TempIdx;
+use tcg_core::tb::{TbExit, TranslationInfo};
+use tcg_core::types::MemOp;
+use tcg_exec::budget::Budget;
+use tcg_exec::coverage::Coverage;
+use tcg_exec::excp::{dispatch, ExcpAction};
 use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+#[cfg(debug_assertions)]
+use tcg_exec::stack_check;
+use tcg_exec::warmup::pretranslate;
 use tcg_exec::{ExecEnv, GuestCpu};
-use tcg_frontend::riscv::cpu::{RiscvCpu, NUM_GPRS};
+use tcg_frontend::riscv::cpu::{GuestClock, RiscvCpu, EXIT_MMIO_STORE};
+use tcg_frontend::riscv::excp;
 use tcg_frontend::riscv::ext::RiscvCfg;
 use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
-use tcg_frontend::{translator_loop, DisasJumpType, TranslatorOps};
-use tcg_linux_user::guest_space::GuestSpace;
-use tcg_linux_user::loader::{load_elf, ElfInfo};
-use tcg_linux_user::syscall::{handle_syscall, SyscallResult};
+use tcg_frontend::translator_loop;
+use tcg_linux_user::checkpoint::{self, Checkpointer, RunInfo};
+use tcg_linux_user::config::{parse_args, ArgError, Invocation, RunConfig};
+use tcg_linux_user::coverage::write_reports;
+use tcg_linux_user::excp::LinuxHandler;
+use tcg_linux_user::fault;
+use tcg_linux_user::guest_space::{
+    page_align_down, page_align_up, page_size, GuestSpace,
+};
+use tcg_linux_user::loader::{is_elf, load_elf, load_flat, ElfInfo};
+use tcg_linux_user::machine::{attach_uart, map_uart};
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::vfs::Vfs;
+use tcg_linux_user::warmup;
 
 /// Wrapper: RiscvCpu + guest_base for GuestCpu trait.
 struct LinuxCpu {
     cpu: RiscvCpu,
     cfg: RiscvCfg,
+    /// MMIO window of the guest space.
+    mmio: Option<(u64, u64)>,
 }
 
 impl GuestCpu for LinuxCpu {
@@ -30,128 +53,424 @@ impl GuestCpu for LinuxCpu {
         0
     }
 
-    fn gen_code(&mut self, ir: &mut Context, pc: u64, max_insns: u32) -> u32 {
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
         let base = self.cpu.guest_base as *const u8;
-        if ir.nb_globals() == 0 {
-            let mut d = RiscvDisasContext::new(pc, base, self.cfg);
-            d.base.max_insns = max_insns;
-            translator_loop::<RiscvTranslator>(&mut d, ir);
-            d.base.num_insns * 4
-        } else {
-            let mut d = RiscvDisasContext::new(pc, base, self.cfg);
-            d.base.max_insns = max_insns;
-            d.env = TempIdx(0);
-            for i in 0..NUM_GPRS {
-                d.gpr[i] = TempIdx(1 + i as u32);
-            }
-            d.pc = TempIdx(1 + NUM_GPRS as u32);
-            d.load_res = TempIdx(1 + NUM_GPRS as u32 + 1);
-            d.load_val = TempIdx(1 + NUM_GPRS as u32 + 2);
-            RiscvTranslator::tb_start(&mut d, ir);
-            loop {
-                RiscvTranslator::insn_start(&mut d, ir);
-                RiscvTranslator::translate_insn(&mut d, ir);
-                if d.base.is_jmp != DisasJumpType::Next {
-                    break;
-                }
-                if d.base.num_insns >= d.base.max_insns {
-                    d.base.is_jmp = DisasJumpType::TooMany;
-                    break;
-                }
-            }
-            RiscvTranslator::tb_stop(&mut d, ir);
-            d.base.num_insns * 4
-        }
+        let mut d = RiscvDisasContext::new(pc, base, self.cfg);
+        d.base.max_insns = max_insns;
+        // The next page may be unmapped: stop before reading it.
+        d.base.pc_page_end =
+            page_align_down(pc).saturating_add(page_size() as u64);
+        d.mmio = self.mmio;
+        d.base.set_tb_flags(flags);
+        translator_loop::<RiscvTranslator>(&mut d, ir)
     }
 
     fn env_ptr(&mut self) -> *mut u8 {
         &mut self.cpu as *mut RiscvCpu as *mut u8
     }
+
+    fn insns_retired(&self) -> u64 {
+        self.cpu.cycle
+    }
+}
+
+/// Guest state before its first instruction, or at a
+/// checkpoint.
+struct Guest {
+    cpu: RiscvCpu,
+    space: GuestSpace,
+    vfs: Vfs,
+    process: Process,
+    signals: SignalTable,
+    mmap_next: u64,
+    run: RunInfo,
+    /// Executable segments, unknown on resume.
+    exec_ranges: Vec<(u64, u64)>,
+}
+
+fn load_guest(config: &RunConfig, inv: &Invocation) -> Guest {
+    let elf_path =
+        std::fs::canonicalize(&inv.elf).expect("failed to resolve elf path");
+    let elf_path = elf_path.to_str().unwrap();
+    let guest_argv: Vec<&str> = inv.argv.iter().map(|s| s.as_str()).collect();
+    let host_env = env::vars_os().filter_map(|(k, v)| {
+        Some((k.into_string().ok()?, v.into_string().ok()?))
+    });
+    let guest_env = config.guest_env(host_env);
+    let guest_envp: Vec<&str> = guest_env.iter().map(|s| s.as_str()).collect();
+
+    let image = fs::read(elf_path).unwrap_or_else(|e| {
+        eprintln!("{elf_path}: {e}");
+        process::exit(1);
+    });
+    let flat = config.kernel || !is_elf(&image);
+    let space = if flat {
+        let ram_end = config.load_addr.saturating_add(config.ram_size);
+        let uart_end = config.uart.map_or(0, |a| a.saturating_add(0x1000));
+        let size = page_align_up(ram_end.max(uart_end));
+        GuestSpace::new_sized(size as usize)
+    } else {
+        GuestSpace::new()
+    };
+    let mut space = space
+        .expect("failed to create guest space")
+        .with_stack_guard(config.stack_guard);
+    let loaded = if flat {
+        let entry = config.entry.unwrap_or(config.load_addr);
+        load_flat(&image, &mut space, config.load_addr, entry, config.ram_size)
+    } else {
+        load_elf(Path::new(elf_path), &mut space, &guest_argv, &guest_envp)
+    };
+    let info: ElfInfo = loaded.unwrap_or_else(|e| {
+        eprintln!("{elf_path}: failed to load: {e}");
+        process::exit(1);
+    });
+    if let Some(addr) = config.uart {
+        if let Err(e) = map_uart(&mut space, addr, io::stdout()) {
+            eprintln!("-uart {addr:#x}: {e}");
+            process::exit(1);
+        }
+    }
+
+    let mut cpu = RiscvCpu::new();
+    cpu.pc = info.entry;
+    cpu.gpr[2] = info.sp; // SP = x2
+    cpu.guest_base = space.guest_base() as u64;
+    Guest {
+        cpu,
+        space,
+        vfs: config.vfs(),
+        process: Process::new(),
+        signals: SignalTable::new(),
+        // mmap_next starts after brk
+        mmap_next: page_align_up(info.brk) + 0x1000_0000, // 256 MB gap
+        run: RunInfo {
+            elf_path: elf_path.to_string(),
+            timebase_freq: config.timebase_freq,
+            deterministic: config.deterministic,
+            ecall: config.ecall,
+            uart: config.uart,
+        },
+        exec_ranges: info.exec_ranges,
+    }
+}
+
+/// The run saved in `dir`; its clock, ecall mode and UART
+/// override the configuration's.
+fn resume_guest(config: &RunConfig, dir: &Path) -> Guest {
+    let r = checkpoint::resume(dir).unwrap_or_else(|e| {
+        eprintln!("-resume {}: {e}", dir.display());
+        process::exit(1);
+    });
+    let mut space = r.space;
+    if let Some(addr) = r.run.uart {
+        attach_uart(&mut space, addr, io::stdout());
+    }
+    Guest {
+        cpu: r.cpu,
+        space,
+        vfs: r
+            .vfs
+            .with_captured_stdio(config.captured_stdio)
+            .with_statfs(config.statfs_view()),
+        process: r.process,
+        signals: r.signals,
+        mmap_next: r.mmap_next,
+        run: r.run,
+        exec_ranges: Vec::new(),
+    }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("usage: tcg-riscv64 <elf> [args...]");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let base = RunConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("{e}");
         process::exit(1);
+    });
+    let inv = parse_args(&args, base).unwrap_or_else(|e| match e {
+        ArgError::Help => {
+            println!("{e}");
+            process::exit(0);
+        }
+        ArgError::Invalid(_) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    });
+    let mut config = inv.config.clone();
+    if let Some(port) = config.gdb_port {
+        eprintln!("-g {port}: gdbstub is not supported yet");
+        process::exit(1);
+    }
+    if config.resume.is_some()
+        && (config.coverage.is_some()
+            || config.warmup_save.is_some()
+            || config.warmup_load.is_some())
+    {
+        eprintln!("-resume: coverage and warmup ignored");
+        config.coverage = None;
+        config.warmup_save = None;
+        config.warmup_load = None;
     }
 
-    let elf_path =
-        std::fs::canonicalize(&args[1]).expect("failed to resolve elf path");
-    let elf_path = elf_path.to_str().unwrap();
-    let guest_argv: Vec<&str> = args[1..].iter().map(|s| s.as_str()).collect();
+    // Before anything else opens fds: the guest's are reopened
+    // at their own numbers.
+    let guest = match &config.resume {
+        Some(dir) => resume_guest(&config, dir),
+        None => load_guest(&config, &inv),
+    };
+    let Guest {
+        mut cpu,
+        space,
+        vfs,
+        process,
+        signals,
+        mmap_next,
+        run,
+        exec_ranges,
+    } = guest;
+    let elf_path = run.elf_path.as_str();
+    fault::install(&space).expect("failed to install fault handler");
 
-    // Load ELF
-    let mut space = GuestSpace::new().expect("failed to create guest space");
-    let info: ElfInfo =
-        load_elf(std::path::Path::new(elf_path), &mut space, &guest_argv, &[])
-            .expect("failed to load ELF");
+    let log: Box<dyn Write> = match &config.log_file {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|e| {
+            eprintln!("-D {}: {e}", path.display());
+            process::exit(1);
+        })),
+        None => Box::new(io::stderr()),
+    };
 
-    // Set up CPU
+    cpu.clock = GuestClock::new(run.timebase_freq, run.deterministic)
+        .resumed_at(cpu.time);
     let mut lcpu = LinuxCpu {
-        cpu: RiscvCpu::new(),
-        cfg: RiscvCfg::default(),
+        cpu,
+        cfg: config.cpu,
+        mmio: space.mmio_window(),
     };
-    lcpu.cpu.pc = info.entry;
-    lcpu.cpu.gpr[2] = info.sp; // SP = x2
-    lcpu.cpu.guest_base = space.guest_base() as u64;
-
-    // mmap_next starts after brk
-    let mut mmap_next =
-        tcg_linux_user::guest_space::page_align_up(info.brk) + 0x1000_0000; // 256 MB gap
 
     // Run
-    let show_stats = env::var("TCG_STATS").is_ok();
-    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut linux = LinuxHandler {
+        space,
+        vfs,
+        process,
+        signals,
+        mmap_next,
+        elf_path: elf_path.to_string(),
+        policy: config.syscall_policy(),
+        ecall: run.ecall,
+        strace: config.strace.then_some(log),
+    };
+    let mut env =
+        ExecEnv::new(NativeCodeGen::new()).with_exceptions(excp::registry());
+    if let Some(every) = config.verify_code {
+        #[cfg(feature = "verify-code")]
+        {
+            env = env.with_verify_code(every);
+        }
+        #[cfg(not(feature = "verify-code"))]
+        eprintln!("TCG_VERIFY_CODE={every} ignored: built without verify-code");
+    }
+    if config.stack_check {
+        #[cfg(debug_assertions)]
+        {
+            env = env.with_stack_check(stack_check::DEFAULT_RED_ZONE);
+        }
+        #[cfg(not(debug_assertions))]
+        eprintln!("TCG_STACK_CHECK ignored: release build");
+    }
+    if config.show_stats {
+        env = env.with_timing();
+    }
+    if config.coverage.is_some() || config.warmup_save.is_some() {
+        env.per_cpu.coverage = Some(Coverage::new());
+    }
+    let warmup_keys =
+        if config.warmup_save.is_some() || config.warmup_load.is_some() {
+            match warmup::binary_key(Path::new(elf_path)) {
+                Ok(binary) => Some((binary, warmup::cfg_key(&lcpu.cfg))),
+                Err(e) => {
+                    eprintln!("warmup: {elf_path}: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+    if let (Some(path), Some((binary, cfg))) =
+        (&config.warmup_load, warmup_keys)
+    {
+        let loaded = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| warmup::load(&text, binary, cfg, &exec_ranges));
+        match loaded {
+            Ok(tbs) => unsafe {
+                pretranslate(
+                    &env.shared,
+                    &mut env.per_cpu,
+                    &mut lcpu,
+                    &tbs,
+                    warmup::WARMUP_BUDGET,
+                );
+            },
+            Err(e) => eprintln!("warmup: {}: {e}, ignored", path.display()),
+        }
+    }
+    if let Some(addr) = config.metrics {
+        #[cfg(feature = "metrics")]
+        {
+            env = env.with_metrics(addr).unwrap_or_else(|e| {
+                eprintln!("-metrics {addr}: {e}");
+                process::exit(1);
+            });
+        }
+        #[cfg(not(feature = "metrics"))]
+        eprintln!("TCG_METRICS={addr} ignored: built without metrics");
+    }
+    if config.max_cpu.is_some() || config.max_wall.is_some() {
+        env = env.with_budget(Budget {
+            cpu: config.max_cpu,
+            wall: config.max_wall,
+        });
+    }
+    let mut checkpointer = None;
+    if let (Some(dir), Some(interval)) =
+        (&config.checkpoint, config.checkpoint_interval())
+    {
+        let policy = config.checkpoint_fd_policy();
+        match Checkpointer::new(dir, policy) {
+            Ok(ck) => checkpointer = Some(ck),
+            Err(e) => {
+                eprintln!("-checkpoint {}: {e}", dir.display());
+                process::exit(1);
+            }
+        }
+        env = env.with_checkpoint(interval);
+    }
+    let finish = |env: &ExecEnv<NativeCodeGen>| {
+        if config.show_stats {
+            eprint!("{}", env.per_cpu.stats);
+        }
+        if let (Some(path), Some(cov), Some((binary, cfg))) =
+            (&config.warmup_save, &env.per_cpu.coverage, warmup_keys)
+        {
+            let store = &env.shared.tb_store;
+            if let Err(e) = warmup::save(path, cov, store, binary, cfg) {
+                eprintln!("warmup: {}: {e}", path.display());
+            }
+        }
+        if let (Some(path), Some(cov)) =
+            (&config.coverage, &env.per_cpu.coverage)
+        {
+            let elf = Path::new(elf_path);
+            if let Err(e) = write_reports(cov, path, elf, &exec_ranges) {
+                eprintln!("coverage: {}: {e}", path.display());
+            }
+        }
+    };
     loop {
         let reason = unsafe { cpu_exec_loop(&mut env, &mut lcpu) };
         match reason {
-            ExitReason::Exit(v) if v == EXCP_ECALL as usize => {
-                // ECALL
-                match handle_syscall(
-                    &mut space,
-                    &mut lcpu.cpu.gpr,
-                    &mut mmap_next,
-                    elf_path,
-                ) {
-                    SyscallResult::Continue(ret) => {
-                        lcpu.cpu.gpr[10] = ret;
-                        lcpu.cpu.pc += 4; // skip past ECALL
-                    }
-                    SyscallResult::Exit(code) => {
-                        if show_stats {
-                            eprint!("{}", env.per_cpu.stats);
+            ExitReason::Exit(TbExit::Exception(code)) => {
+                linux.process.set_guest_cpu(env.per_cpu.cpu_time);
+                match dispatch(&mut linux, &mut lcpu.cpu, code) {
+                    ExcpAction::Resume => {
+                        for (start, end) in linux.space.take_invalidations() {
+                            let n = env.shared.tb_invalidate_range(start, end);
+                            env.per_cpu.stats.tb_invalidated += n as u64;
                         }
-                        process::exit(code);
+                    }
+                    ExcpAction::Exit(status) => {
+                        finish(&env);
+                        process::exit(status);
+                    }
+                    ExcpAction::Fatal(message) => {
+                        finish(&env);
+                        eprintln!("{message}");
+                        process::exit(1);
                     }
                 }
             }
-            ExitReason::Exit(v) if v == EXCP_EBREAK as usize => {
-                if show_stats {
-                    eprint!("{}", env.per_cpu.stats);
+            ExitReason::Exit(TbExit::Custom(EXIT_MMIO_STORE)) => {
+                let c = &lcpu.cpu;
+                let size = MemOp::new(c.mmio_op as u16).size_bytes() as usize;
+                if linux
+                    .space
+                    .mmio_store(c.mmio_addr, c.mmio_val, size)
+                    .is_err()
+                {
+                    finish(&env);
+                    eprintln!(
+                        "guest segmentation fault at {:#x} (pc={:#x})",
+                        c.mmio_addr, c.pc
+                    );
+                    process::exit(1);
                 }
-                eprintln!("ebreak at pc={:#x}", lcpu.cpu.pc);
-                process::exit(1);
             }
-            ExitReason::Exit(v) if v == EXCP_UNDEF as usize => {
-                if show_stats {
-                    eprint!("{}", env.per_cpu.stats);
-                }
-                eprintln!("illegal instruction at pc={:#x}", lcpu.cpu.pc);
+            ExitReason::UnknownException { code, pc } => {
+                finish(&env);
+                let name = env.shared.excp.as_ref().unwrap().describe(code);
+                eprintln!("{name} at pc={pc:#x}");
                 process::exit(1);
             }
             ExitReason::Exit(v) => {
-                if show_stats {
-                    eprint!("{}", env.per_cpu.stats);
-                }
-                eprintln!("unexpected exit {v}");
+                finish(&env);
+                eprintln!("unexpected exit {v:?}");
+                process::exit(1);
+            }
+            ExitReason::HelperPanic { message, pc } => {
+                finish(&env);
+                eprintln!("helper panic in TB at pc={pc:#x}: {message}");
+                process::exit(1);
+            }
+            ExitReason::TranslateFailed { pc, error } => {
+                finish(&env);
+                eprintln!("translating pc={pc:#x}: {error}");
                 process::exit(1);
             }
+            // Not enabled: with one guest thread nobody else
+            // could release the spin.
+            ExitReason::Yield => {}
             ExitReason::BufferFull => {
-                if show_stats {
-                    eprint!("{}", env.per_cpu.stats);
+                unreachable!("cpu_exec_loop flushes a full code buffer")
+            }
+            ExitReason::BudgetExhausted => {
+                unreachable!("no instruction budget is set")
+            }
+            ExitReason::TimedOut(report) => {
+                finish(&env);
+                eprint!("{report}");
+                process::exit(124);
+            }
+            ExitReason::Checkpoint => {
+                let (Some(ck), Some(dir)) =
+                    (checkpointer.as_mut(), &config.checkpoint)
+                else {
+                    continue;
+                };
+                let guest = checkpoint::Guest {
+                    cpu: &lcpu.cpu,
+                    space: &mut linux.space,
+                    vfs: &linux.vfs,
+                    process: &linux.process,
+                    signals: &linux.signals,
+                    mmap_next: linux.mmap_next,
+                    run: &run,
+                };
+                // The run goes on; the previous checkpoint stays.
+                if let Err(e) = ck.write(guest) {
+                    eprintln!("checkpoint: {}: {e}", dir.display());
                 }
-                eprintln!("code buffer full");
+            }
+            #[cfg(debug_assertions)]
+            ExitReason::StackOverflow(report) => {
+                finish(&env);
+                eprint!("{report}");
                 process::exit(1);
             }
         }
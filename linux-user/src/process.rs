@@ -0,0 +1,291 @@
+//! Process ids, process groups and sessions.
+//!
+//! The emulator runs a single guest process, pid 1 as `getpid`
+//! reports. Like the kernel's first process it starts in
+//! process group and session 0, so it may call `setsid` or make
+//! itself a group leader. Permission checks are those of Linux
+//! reduced to a process without children.
+//!
+//! Inherited host terminals act as the guest's controlling
+//! terminal. Their foreground group is emulated and never
+//! reaches the host, whose terminal belongs to the emulator's
+//! own job; `SIGTTOU`/`SIGTTIN` are never generated.
+//!
+//! `getrusage` and `times` split the emulator thread's CPU
+//! time into the guest's user time, spent running translated
+//! code, and system time, the rest: system calls, translation
+//! and the emulator itself.
+
+use std::time::Duration;
+
+use tcg_exec::budget::thread_cpu_time;
+
+use crate::guest_space::GuestSpace;
+use crate::socket::{copy_from_guest, copy_to_guest};
+use crate::syscall::SyscallResult;
+
+// asm-generic ioctl numbers, shared by riscv64 and x86-64.
+pub const TIOCSCTTY: u64 = 0x540e;
+pub const TIOCGPGRP: u64 = 0x540f;
+pub const TIOCSPGRP: u64 = 0x5410;
+pub const TIOCGSID: u64 = 0x5429;
+
+/// Pid of the guest process.
+pub const GUEST_PID: u32 = 1;
+
+/// Pid of its parent, as for the kernel's first process.
+pub const GUEST_PPID: u32 = 0;
+
+/// `wait4` options the kernel accepts.
+const WNOHANG: u64 = 1;
+const WUNTRACED: u64 = 2;
+const WCONTINUED: u64 = 8;
+const WNOTHREAD: u64 = 0x2000_0000;
+const WALL: u64 = 0x4000_0000;
+const WCLONE: u64 = 0x8000_0000;
+
+/// `getrusage` targets.
+const RUSAGE_SELF: i32 = 0;
+const RUSAGE_CHILDREN: i32 = -1;
+const RUSAGE_THREAD: i32 = 1;
+
+/// `clock_t` ticks per second of `times`, `sysconf(_SC_CLK_TCK)`.
+pub const GUEST_CLK_TCK: u64 = 100;
+
+fn err(e: i32) -> SyscallResult {
+    SyscallResult::Continue((-e as i64) as u64)
+}
+
+/// Ids of the guest process and the state of its controlling
+/// terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pid: u32,
+    pgid: u32,
+    sid: u32,
+    /// Foreground group of the controlling terminal, if the
+    /// process has one.
+    ctty_pgrp: Option<u32>,
+    /// CPU time spent running guest code.
+    guest_cpu: Duration,
+}
+
+impl Default for Process {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn timeval(d: Duration) -> libc::timeval {
+    libc::timeval {
+        tv_sec: d.as_secs() as libc::time_t,
+        tv_usec: d.subsec_micros() as libc::suseconds_t,
+    }
+}
+
+impl Process {
+    pub fn new() -> Self {
+        Self {
+            pid: GUEST_PID,
+            pgid: 0,
+            sid: 0,
+            ctty_pgrp: Some(0),
+            guest_cpu: Duration::ZERO,
+        }
+    }
+
+    /// Process group, session and terminal foreground group,
+    /// as a checkpoint saves them.
+    pub(crate) fn ids(&self) -> (u32, u32, Option<u32>) {
+        (self.pgid, self.sid, self.ctty_pgrp)
+    }
+
+    pub(crate) fn with_ids(mut self, ids: (u32, u32, Option<u32>)) -> Self {
+        (self.pgid, self.sid, self.ctty_pgrp) = ids;
+        self
+    }
+
+    /// Set the CPU time the guest has spent in its own code so
+    /// far, before a system call that may report it.
+    pub fn set_guest_cpu(&mut self, cpu: Duration) {
+        self.guest_cpu = cpu;
+    }
+
+    /// Guest user and system CPU time.
+    fn cpu_times(&self) -> (Duration, Duration) {
+        let user = self.guest_cpu;
+        (user, thread_cpu_time().saturating_sub(user))
+    }
+
+    /// `getrusage(who, usage)`. Counters other than the times
+    /// are the host's for the emulator; the guest has no
+    /// children, so theirs are all zero.
+    pub fn getrusage(
+        &self,
+        space: &GuestSpace,
+        who: u64,
+        addr: u64,
+    ) -> SyscallResult {
+        let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+        match who as i32 {
+            RUSAGE_SELF | RUSAGE_THREAD => {
+                unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut ru) };
+                let (user, sys) = self.cpu_times();
+                ru.ru_utime = timeval(user);
+                ru.ru_stime = timeval(sys);
+            }
+            RUSAGE_CHILDREN => {}
+            _ => return err(libc::EINVAL),
+        }
+        // riscv64 and x86-64 share the layout: two timevals and
+        // 14 longs.
+        let raw = unsafe {
+            std::slice::from_raw_parts(
+                &ru as *const libc::rusage as *const u8,
+                std::mem::size_of::<libc::rusage>(),
+            )
+        };
+        match copy_to_guest(space, addr, raw) {
+            Ok(()) => SyscallResult::Continue(0),
+            Err(e) => err(e),
+        }
+    }
+
+    /// `times(buf)`: returns the clock ticks since an arbitrary
+    /// point, the host's.
+    pub fn times(&self, space: &GuestSpace, addr: u64) -> SyscallResult {
+        let mut host: libc::tms = unsafe { std::mem::zeroed() };
+        let now = unsafe { libc::times(&mut host) };
+        if addr != 0 {
+            let (user, sys) = self.cpu_times();
+            let ticks = |d: Duration| {
+                (d.as_micros() as u64 * GUEST_CLK_TCK / 1_000_000) as i64
+            };
+            let mut raw = [0u8; 32];
+            raw[..8].copy_from_slice(&ticks(user).to_le_bytes());
+            raw[8..16].copy_from_slice(&ticks(sys).to_le_bytes());
+            if let Err(e) = copy_to_guest(space, addr, &raw) {
+                return err(e);
+            }
+        }
+        // USER_HZ is 100 on both.
+        SyscallResult::Continue(now as u64)
+    }
+
+    /// Whether `pid` (0 for the caller) names the guest.
+    fn is_self(&self, pid: u64) -> bool {
+        pid == 0 || pid == self.pid as u64
+    }
+
+    pub fn getpgid(&self, pid: u64) -> SyscallResult {
+        if !self.is_self(pid) {
+            return err(libc::ESRCH);
+        }
+        SyscallResult::Continue(self.pgid as u64)
+    }
+
+    pub fn getsid(&self, pid: u64) -> SyscallResult {
+        if !self.is_self(pid) {
+            return err(libc::ESRCH);
+        }
+        SyscallResult::Continue(self.sid as u64)
+    }
+
+    /// `setpgid(pid, pgid)`: only the guest itself can be moved,
+    /// into a new group of its own or the group it is in.
+    pub fn setpgid(&mut self, pid: u64, pgid: u64) -> SyscallResult {
+        let pgid = pgid as i32;
+        if pgid < 0 {
+            return err(libc::EINVAL);
+        }
+        if !self.is_self(pid) {
+            return err(libc::ESRCH);
+        }
+        if self.sid == self.pid {
+            return err(libc::EPERM);
+        }
+        let pgid = if pgid == 0 { self.pid } else { pgid as u32 };
+        if pgid != self.pid && pgid != self.pgid {
+            return err(libc::EPERM);
+        }
+        self.pgid = pgid;
+        SyscallResult::Continue(0)
+    }
+
+    /// `setsid()`: a new session and group led by the guest,
+    /// without a controlling terminal.
+    pub fn setsid(&mut self) -> SyscallResult {
+        if self.pgid == self.pid {
+            return err(libc::EPERM);
+        }
+        self.pgid = self.pid;
+        self.sid = self.pid;
+        self.ctty_pgrp = None;
+        SyscallResult::Continue(self.sid as u64)
+    }
+
+    /// `wait4(pid, status, options, rusage)`. The guest has no
+    /// children, so every valid target fails with `ECHILD`;
+    /// `pid < -1` waits for group `-pid`, `0` for the caller's
+    /// group.
+    pub fn wait4(&self, pid: u64, options: u64) -> SyscallResult {
+        let valid =
+            WNOHANG | WUNTRACED | WCONTINUED | WNOTHREAD | WALL | WCLONE;
+        if options & !valid != 0 {
+            return err(libc::EINVAL);
+        }
+        if pid as i32 == i32::MIN {
+            return err(libc::ESRCH);
+        }
+        err(libc::ECHILD)
+    }
+
+    /// Job-control ioctl `req` on a guest fd; `tty` tells
+    /// whether the fd is a host terminal.
+    pub fn tty_ioctl(
+        &mut self,
+        space: &GuestSpace,
+        tty: bool,
+        req: u64,
+        arg: u64,
+    ) -> SyscallResult {
+        if !tty {
+            return err(libc::ENOTTY);
+        }
+        if req == TIOCSCTTY {
+            if self.sid != self.pid {
+                return err(libc::EPERM);
+            }
+            self.ctty_pgrp.get_or_insert(self.pgid);
+            return SyscallResult::Continue(0);
+        }
+        let Some(fg) = self.ctty_pgrp else {
+            return err(libc::ENOTTY);
+        };
+        let put = |v: u32| match copy_to_guest(space, arg, &v.to_le_bytes()) {
+            Ok(()) => SyscallResult::Continue(0),
+            Err(e) => err(e),
+        };
+        match req {
+            TIOCGPGRP => put(fg),
+            TIOCGSID => put(self.sid),
+            TIOCSPGRP => {
+                let mut raw = [0u8; 4];
+                if let Err(e) = copy_from_guest(space, arg, &mut raw) {
+                    return err(e);
+                }
+                let pgrp = i32::from_le_bytes(raw);
+                if pgrp < 0 {
+                    return err(libc::EINVAL);
+                }
+                // The guest's group is the only one there is.
+                if pgrp as u32 != self.pgid {
+                    return err(libc::ESRCH);
+                }
+                self.ctty_pgrp = Some(pgrp as u32);
+                SyscallResult::Continue(0)
+            }
+            _ => err(libc::ENOTTY),
+        }
+    }
+}
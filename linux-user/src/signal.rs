@@ -0,0 +1,136 @@
+//! Guest signal dispositions.
+//!
+//! `rt_sigaction` records what the guest installs and reports
+//! it back, so runtimes that query or chain handlers see a
+//! consistent table. Nothing is delivered yet: a host fault on
+//! guest memory still ends the process (see `fault.rs`).
+
+use crate::guest_space::GuestSpace;
+use crate::socket::{copy_from_guest, copy_to_guest};
+use crate::syscall::SyscallResult;
+
+/// Highest signal number, as on Linux.
+pub const NSIG: u64 = 64;
+
+/// riscv64 `struct sigaction` as seen by the kernel: handler,
+/// flags and mask; the architecture has no `sa_restorer`.
+const TARGET_SIGACTION_SIZE: usize = 24;
+
+/// `rt_sigaction`'s `sigsetsize`: one 64-bit word.
+const TARGET_SIGSET_SIZE: u64 = 8;
+
+const SIGKILL: u64 = 9;
+const SIGSTOP: u64 = 19;
+
+fn err(e: i32) -> SyscallResult {
+    SyscallResult::Continue((-e as i64) as u64)
+}
+
+/// One guest signal disposition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GuestSigaction {
+    pub handler: u64,
+    pub flags: u64,
+    pub mask: u64,
+}
+
+impl GuestSigaction {
+    fn from_bytes(raw: &[u8; TARGET_SIGACTION_SIZE]) -> Self {
+        let word = |i: usize| {
+            u64::from_le_bytes(raw[i * 8..i * 8 + 8].try_into().unwrap())
+        };
+        Self {
+            handler: word(0),
+            flags: word(1),
+            mask: word(2),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; TARGET_SIGACTION_SIZE] {
+        let mut out = [0u8; TARGET_SIGACTION_SIZE];
+        for (i, w) in [self.handler, self.flags, self.mask]
+            .into_iter()
+            .enumerate()
+        {
+            out[i * 8..i * 8 + 8].copy_from_slice(&w.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Dispositions of signals 1..=NSIG.
+#[derive(Debug, Clone)]
+pub struct SignalTable {
+    actions: [GuestSigaction; NSIG as usize],
+}
+
+impl SignalTable {
+    pub fn new() -> Self {
+        Self {
+            actions: [GuestSigaction::default(); NSIG as usize],
+        }
+    }
+
+    /// Disposition of signal `sig` (1-based).
+    pub fn action(&self, sig: u64) -> Option<GuestSigaction> {
+        let i = sig.checked_sub(1)?;
+        self.actions.get(i as usize).copied()
+    }
+
+    /// Set the disposition of signal `sig` (1-based), as a
+    /// checkpoint saved it.
+    pub(crate) fn set_action(&mut self, sig: u64, act: GuestSigaction) {
+        if let Some(slot) = sig
+            .checked_sub(1)
+            .and_then(|i| self.actions.get_mut(i as usize))
+        {
+            *slot = act;
+        }
+    }
+
+    /// `rt_sigaction(sig, act, oact, sigsetsize)`.
+    pub fn rt_sigaction(
+        &mut self,
+        space: &GuestSpace,
+        sig: u64,
+        act: u64,
+        oact: u64,
+        sigsetsize: u64,
+    ) -> SyscallResult {
+        if sigsetsize != TARGET_SIGSET_SIZE {
+            return err(libc::EINVAL);
+        }
+        let Some(old) = self.action(sig) else {
+            return err(libc::EINVAL);
+        };
+        if act != 0 && (sig == SIGKILL || sig == SIGSTOP) {
+            return err(libc::EINVAL);
+        }
+        let new = if act != 0 {
+            let mut raw = [0u8; TARGET_SIGACTION_SIZE];
+            if let Err(e) = copy_from_guest(space, act, &mut raw) {
+                return err(e);
+            }
+            Some(GuestSigaction::from_bytes(&raw))
+        } else {
+            None
+        };
+        if oact != 0 {
+            if let Err(e) = copy_to_guest(space, oact, &old.to_bytes()) {
+                return err(e);
+            }
+        }
+        if let Some(mut new) = new {
+            // SIGKILL and SIGSTOP cannot be blocked.
+            new.mask &= !(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+            self.actions[sig as usize - 1] = new;
+        }
+        SyscallResult::Continue(0)
+    }
+}
+
+impl Default for SignalTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,255 @@
+//! Write tracking behind `GuestSpace` snapshots.
+//!
+//! A capture write-protects every writable guest page and
+//! marks it clean. The first store to a clean page faults; the
+//! SIGSEGV handler (see `fault.rs`) marks the page dirty, gives
+//! it back its protection and returns, so the store is retried
+//! and succeeds. A rollback then copies back only the dirty
+//! pages and protects them again.
+//!
+//! The host kernel does not fault on protected pages: it fails
+//! with `EFAULT`. Buffers handed to host syscalls are therefore
+//! marked dirty up front (`GuestSpace::mark_dirty`).
+//!
+//! Checkpoints (see `checkpoint.rs`) use the same log to find
+//! what changed since the previous checkpoint.
+
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+
+use crate::fault;
+use crate::guest_space::page_size;
+
+/// Spaces that can be tracked at the same time.
+const MAX_LOGS: usize = 16;
+
+/// Dirty-page log of one captured guest space.
+pub(crate) struct DirtyLog {
+    base: usize,
+    size: usize,
+    page_shift: u32,
+    /// Tracked regions `(start, end, prot)`, sorted.
+    regions: Box<[(u64, u64, i32)]>,
+    /// One bit per guest page: tracked and still protected.
+    clean: Box<[AtomicU64]>,
+    /// Pages made writable since the capture or the last
+    /// rollback, in the order they were first written.
+    dirty: Box<[AtomicU64]>,
+    ndirty: AtomicUsize,
+    /// Markings between clearing a clean bit and restoring the
+    /// page's protection.
+    busy: AtomicUsize,
+    /// Ranges passed to `mark_range`, which may reach pages
+    /// outside the tracked regions.
+    touched: Mutex<Vec<(u64, u64)>>,
+    slot: usize,
+}
+
+impl DirtyLog {
+    /// Track the writable `regions` (sorted) of the guest space
+    /// at `base`, protecting them.
+    pub(crate) fn new(
+        base: *mut u8,
+        size: usize,
+        regions: Vec<(u64, u64, i32)>,
+    ) -> io::Result<Box<Self>> {
+        static HANDLER: Once = Once::new();
+        let mut installed = Ok(());
+        HANDLER.call_once(|| installed = fault::install_handler());
+        installed?;
+
+        let page_shift = (page_size() as u64).trailing_zeros();
+        let pages = size >> page_shift;
+        let tracked: u64 =
+            regions.iter().map(|&(s, e, _)| (e - s) >> page_shift).sum();
+        let mut log = Box::new(Self {
+            base: base as usize,
+            size,
+            page_shift,
+            regions: regions.into(),
+            clean: (0..pages.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            dirty: (0..tracked).map(|_| AtomicU64::new(0)).collect(),
+            ndirty: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            touched: Mutex::new(Vec::new()),
+            slot: usize::MAX,
+        });
+        log.slot = register(&log)?;
+        for i in 0..log.regions.len() {
+            let (start, end, prot) = log.regions[i];
+            log.protect(start, end, prot)?;
+        }
+        Ok(log)
+    }
+
+    /// Guest pages written since the last call, sorted.
+    pub(crate) fn take_dirty(&self) -> Vec<u64> {
+        let n = self.ndirty.swap(0, Ordering::AcqRel);
+        let mut pages: Vec<u64> = self.dirty[..n]
+            .iter()
+            .map(|p| p.load(Ordering::Relaxed))
+            .collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Ranges passed to `mark_range` since the last call.
+    pub(crate) fn take_touched(&self) -> Vec<(u64, u64)> {
+        std::mem::take(&mut *self.touched.lock().unwrap())
+    }
+
+    pub(crate) fn page_shift(&self) -> u32 {
+        self.page_shift
+    }
+
+    /// Protection of the tracked region holding `addr`.
+    fn prot_at(&self, addr: u64) -> Option<i32> {
+        let i = self.regions.partition_point(|&(_, end, _)| end <= addr);
+        let &(start, _, prot) = self.regions.get(i)?;
+        (start <= addr).then_some(prot)
+    }
+
+    /// Write-protect `[start, end)` of a tracked region with
+    /// protection `prot` and mark its pages clean.
+    pub(crate) fn protect(
+        &self,
+        start: u64,
+        end: u64,
+        prot: i32,
+    ) -> io::Result<()> {
+        let host = (self.base + start as usize) as *mut libc::c_void;
+        let len = (end - start) as usize;
+        // SAFETY: the range lies inside the guest reservation.
+        let ret =
+            unsafe { libc::mprotect(host, len, prot & !libc::PROT_WRITE) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for page in start >> self.page_shift..end >> self.page_shift {
+            let bit = 1 << (page % 64);
+            self.clean[page as usize / 64].fetch_or(bit, Ordering::AcqRel);
+        }
+        Ok(())
+    }
+
+    /// Mark the pages of `[start, end)` dirty and writable.
+    pub(crate) fn mark_range(&self, start: u64, end: u64) {
+        self.touched.lock().unwrap().push((start, end));
+        let i = self.regions.partition_point(|&(_, e, _)| e <= start);
+        for &(s, e, _) in &self.regions[i..] {
+            if s >= end {
+                break;
+            }
+            let lo = s.max(start) >> self.page_shift;
+            let hi =
+                (e.min(end) + (1 << self.page_shift) - 1) >> self.page_shift;
+            for page in lo..hi {
+                self.mark(page);
+            }
+        }
+    }
+
+    /// Mark `page` dirty if it is clean. Returns false if the
+    /// page is neither clean nor being marked by another thread,
+    /// i.e. a fault there is not ours.
+    fn mark(&self, page: u64) -> bool {
+        let addr = page << self.page_shift;
+        let Some(prot) = self.prot_at(addr) else {
+            return false;
+        };
+        let bit = 1 << (page % 64);
+        self.busy.fetch_add(1, Ordering::AcqRel);
+        let was =
+            self.clean[page as usize / 64].fetch_and(!bit, Ordering::AcqRel);
+        if was & bit == 0 {
+            // Already writable, or another thread is about to
+            // make it so.
+            let busy = self.busy.fetch_sub(1, Ordering::AcqRel) > 1;
+            return busy;
+        }
+        let n = self.ndirty.fetch_add(1, Ordering::AcqRel);
+        self.dirty[n].store(page, Ordering::Relaxed);
+        let host = (self.base + addr as usize) as *mut libc::c_void;
+        // SAFETY: the page lies inside the guest reservation;
+        // mprotect(2) is async-signal-safe.
+        unsafe { libc::mprotect(host, 1 << self.page_shift, prot) };
+        self.busy.fetch_sub(1, Ordering::AcqRel);
+        true
+    }
+}
+
+impl Drop for DirtyLog {
+    fn drop(&mut self) {
+        if self.slot != usize::MAX {
+            unregister(self.slot);
+        }
+    }
+}
+
+/// Logs the fault handler may consult, with the host range each
+/// covers so that it never dereferences a log outside its
+/// space.
+struct Slot {
+    start: AtomicUsize,
+    end: AtomicUsize,
+    log: AtomicPtr<DirtyLog>,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    start: AtomicUsize::new(0),
+    end: AtomicUsize::new(0),
+    log: AtomicPtr::new(ptr::null_mut()),
+};
+
+static SLOTS: [Slot; MAX_LOGS] = [EMPTY; MAX_LOGS];
+
+fn register(log: &DirtyLog) -> io::Result<usize> {
+    let ptr = log as *const DirtyLog as *mut DirtyLog;
+    for (i, slot) in SLOTS.iter().enumerate() {
+        if slot
+            .log
+            .compare_exchange(
+                ptr::null_mut(),
+                ptr,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        {
+            slot.start.store(log.base, Ordering::Release);
+            slot.end.store(log.base + log.size, Ordering::Release);
+            return Ok(i);
+        }
+    }
+    Err(io::Error::other("too many guest spaces tracking writes"))
+}
+
+fn unregister(i: usize) {
+    let slot = &SLOTS[i];
+    slot.end.store(0, Ordering::Release);
+    slot.start.store(0, Ordering::Release);
+    slot.log.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Handle a fault at `host` on a page protected by a capture.
+/// Returns true if the access may be retried.
+pub(crate) fn on_write_fault(host: usize) -> bool {
+    for slot in &SLOTS {
+        let start = slot.start.load(Ordering::Acquire);
+        if !(start..slot.end.load(Ordering::Acquire)).contains(&host) {
+            continue;
+        }
+        let log = slot.log.load(Ordering::Acquire);
+        if log.is_null() {
+            return false;
+        }
+        // SAFETY: a log is unregistered before it is freed, and
+        // the space it covers is not dropped while it faults.
+        let log = unsafe { &*log };
+        return log.mark(((host - start) >> log.page_shift) as u64);
+    }
+    false
+}
@@ -0,0 +1,842 @@
+//! Socket syscalls, passed through to host sockets.
+//!
+//! Guest fds are host fds, so sockets take part in dup/close/fcntl
+//! like any other descriptor. riscv64 and x86-64 Linux share the
+//! generic sockaddr, msghdr, cmsghdr and timeval layouts; the work
+//! here is bounds checking guest buffers and translating lengths
+//! and flags explicitly rather than reshaping fields.
+
+use std::mem::size_of;
+
+use crate::guest_space::GuestSpace;
+use crate::syscall::{errno_ret, host_ret, SyscallPolicy, SyscallResult};
+use crate::vfs::Vfs;
+
+// Guest (asm-generic) socket type flags.
+const TARGET_SOCK_TYPE_MASK: u64 = 0xf;
+const TARGET_SOCK_NONBLOCK: u64 = 0o4000;
+const TARGET_SOCK_CLOEXEC: u64 = 0o2000000;
+
+// Guest sockopt levels and names.
+const TARGET_SOL_SOCKET: u64 = 1;
+const TARGET_IPPROTO_TCP: u64 = 6;
+const TARGET_IPPROTO_IPV6: u64 = 41;
+const TARGET_SO_REUSEADDR: u64 = 2;
+const TARGET_SO_TYPE: u64 = 3;
+const TARGET_SO_ERROR: u64 = 4;
+const TARGET_SO_BROADCAST: u64 = 6;
+const TARGET_SO_SNDBUF: u64 = 7;
+const TARGET_SO_RCVBUF: u64 = 8;
+const TARGET_SO_KEEPALIVE: u64 = 9;
+const TARGET_SO_LINGER: u64 = 13;
+const TARGET_SO_REUSEPORT: u64 = 15;
+const TARGET_SO_RCVTIMEO: u64 = 20;
+const TARGET_SO_SNDTIMEO: u64 = 21;
+const TARGET_SO_ACCEPTCONN: u64 = 30;
+const TARGET_SO_PROTOCOL: u64 = 38;
+const TARGET_SO_DOMAIN: u64 = 39;
+const TARGET_TCP_NODELAY: u64 = 1;
+const TARGET_TCP_KEEPIDLE: u64 = 4;
+const TARGET_TCP_KEEPINTVL: u64 = 5;
+const TARGET_TCP_KEEPCNT: u64 = 6;
+const TARGET_IPV6_V6ONLY: u64 = 26;
+
+// Guest control messages.
+const TARGET_SCM_RIGHTS: i32 = 1;
+const CMSG_HDR_LEN: usize = 16;
+
+/// Largest control buffer accepted from the guest.
+const MAX_CONTROL_LEN: usize = 64 * 1024;
+
+/// Guest `struct msghdr` (LP64).
+const MSGHDR_SIZE: u64 = 56;
+
+fn err(e: i32) -> SyscallResult {
+    SyscallResult::Continue((-e as i64) as u64)
+}
+
+// ---------------------------------------------------------------
+// Guest memory access
+// ---------------------------------------------------------------
+
+pub(crate) fn copy_from_guest(
+    space: &GuestSpace,
+    addr: u64,
+    dst: &mut [u8],
+) -> Result<(), i32> {
+    if dst.is_empty() {
+        return Ok(());
+    }
+    if !space.access_ok(addr, dst.len() as u64, libc::PROT_READ) {
+        return Err(libc::EFAULT);
+    }
+    let src = space.g2h(addr);
+    unsafe {
+        std::ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), dst.len());
+    }
+    Ok(())
+}
+
+pub(crate) fn copy_to_guest(
+    space: &GuestSpace,
+    addr: u64,
+    src: &[u8],
+) -> Result<(), i32> {
+    if src.is_empty() {
+        return Ok(());
+    }
+    if !space.access_ok(addr, src.len() as u64, libc::PROT_WRITE) {
+        return Err(libc::EFAULT);
+    }
+    unsafe {
+        space.write_bytes(addr, src);
+    }
+    Ok(())
+}
+
+fn read_u32(space: &GuestSpace, addr: u64) -> Result<u32, i32> {
+    let mut b = [0u8; 4];
+    copy_from_guest(space, addr, &mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(space: &GuestSpace, addr: u64) -> Result<u64, i32> {
+    let mut b = [0u8; 8];
+    copy_from_guest(space, addr, &mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+/// Host pointer to a guest buffer of `len` bytes that the host
+/// will access with `prot`. The host kernel may fill it, so it
+/// counts as written for snapshots.
+pub(crate) fn guest_buf(
+    space: &GuestSpace,
+    addr: u64,
+    len: usize,
+    prot: i32,
+) -> Result<*mut u8, i32> {
+    if len == 0 {
+        return Ok(std::ptr::NonNull::dangling().as_ptr());
+    }
+    if !space.access_ok(addr, len as u64, prot) {
+        return Err(libc::EFAULT);
+    }
+    space.mark_dirty(addr, addr + len as u64);
+    Ok(space.g2h(addr))
+}
+
+fn as_bytes<T>(v: &T) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(v as *const T as *const u8, size_of::<T>())
+    }
+}
+
+fn as_bytes_mut<T>(v: &mut T) -> &mut [u8] {
+    unsafe {
+        std::slice::from_raw_parts_mut(v as *mut T as *mut u8, size_of::<T>())
+    }
+}
+
+// ---------------------------------------------------------------
+// sockaddr translation
+// ---------------------------------------------------------------
+
+/// Copy a guest sockaddr of `len` bytes into host storage.
+fn read_sockaddr(
+    space: &GuestSpace,
+    addr: u64,
+    len: u64,
+) -> Result<(libc::sockaddr_storage, libc::socklen_t), i32> {
+    let len = len as u32;
+    if len as i32 <= 0 || len as usize > size_of::<libc::sockaddr_storage>() {
+        return Err(libc::EINVAL);
+    }
+    let mut st: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    copy_from_guest(space, addr, &mut as_bytes_mut(&mut st)[..len as usize])?;
+    Ok((st, len))
+}
+
+/// Read the guest's `*addrlen` before a call that returns an
+/// address. A null `addr` means the guest does not want it.
+fn read_addrlen(
+    space: &GuestSpace,
+    addr: u64,
+    len_addr: u64,
+) -> Result<u32, i32> {
+    if addr == 0 {
+        return Ok(0);
+    }
+    let len = read_u32(space, len_addr)?;
+    if (len as i32) < 0 {
+        return Err(libc::EINVAL);
+    }
+    Ok(len)
+}
+
+/// Copy a host sockaddr back, truncated to the guest buffer, and
+/// store the full length in `*addrlen` as Linux does.
+fn write_sockaddr(
+    space: &GuestSpace,
+    addr: u64,
+    len_addr: u64,
+    guest_len: u32,
+    st: &libc::sockaddr_storage,
+    actual: libc::socklen_t,
+) -> Result<(), i32> {
+    if addr == 0 {
+        return Ok(());
+    }
+    let n = guest_len.min(actual) as usize;
+    copy_to_guest(space, addr, &as_bytes(st)[..n])?;
+    copy_to_guest(space, len_addr, &actual.to_le_bytes())
+}
+
+fn host_sock_flags(flags: u64) -> Option<i32> {
+    if flags & !(TARGET_SOCK_NONBLOCK | TARGET_SOCK_CLOEXEC) != 0 {
+        return None;
+    }
+    let mut host = 0;
+    if flags & TARGET_SOCK_NONBLOCK != 0 {
+        host |= libc::SOCK_NONBLOCK;
+    }
+    if flags & TARGET_SOCK_CLOEXEC != 0 {
+        host |= libc::SOCK_CLOEXEC;
+    }
+    Some(host)
+}
+
+fn host_sock_type(ty: u64) -> Option<i32> {
+    let base = (ty & TARGET_SOCK_TYPE_MASK) as i32;
+    host_sock_flags(ty & !TARGET_SOCK_TYPE_MASK).map(|f| base | f)
+}
+
+// ---------------------------------------------------------------
+// socket / socketpair / bind / connect / listen / accept4
+// ---------------------------------------------------------------
+
+pub fn do_socket(
+    policy: &SyscallPolicy,
+    domain: u64,
+    ty: u64,
+    proto: u64,
+) -> SyscallResult {
+    if policy.deny_sockets {
+        return err(libc::EACCES);
+    }
+    let Some(ty) = host_sock_type(ty) else {
+        return err(libc::EINVAL);
+    };
+    let fd = unsafe { libc::socket(domain as i32, ty, proto as i32) };
+    host_ret(fd as i64)
+}
+
+pub fn do_socketpair(
+    space: &GuestSpace,
+    policy: &SyscallPolicy,
+    domain: u64,
+    ty: u64,
+    proto: u64,
+    sv_addr: u64,
+) -> SyscallResult {
+    if policy.deny_sockets {
+        return err(libc::EACCES);
+    }
+    let Some(ty) = host_sock_type(ty) else {
+        return err(libc::EINVAL);
+    };
+    if !space.range_ok(sv_addr, 8) {
+        return err(libc::EFAULT);
+    }
+    let mut sv = [0i32; 2];
+    let ret = unsafe {
+        libc::socketpair(domain as i32, ty, proto as i32, sv.as_mut_ptr())
+    };
+    if ret < 0 {
+        return SyscallResult::Continue(errno_ret());
+    }
+    let mut out = [0u8; 8];
+    out[..4].copy_from_slice(&sv[0].to_le_bytes());
+    out[4..].copy_from_slice(&sv[1].to_le_bytes());
+    match copy_to_guest(space, sv_addr, &out) {
+        Ok(()) => SyscallResult::Continue(0),
+        Err(e) => err(e),
+    }
+}
+
+pub fn do_bind(
+    space: &GuestSpace,
+    fd: u64,
+    addr: u64,
+    len: u64,
+) -> SyscallResult {
+    let (st, len) = match read_sockaddr(space, addr, len) {
+        Ok(v) => v,
+        Err(e) => return err(e),
+    };
+    let ret = unsafe {
+        libc::bind(fd as i32, &st as *const _ as *const libc::sockaddr, len)
+    };
+    host_ret(ret as i64)
+}
+
+pub fn do_connect(
+    space: &GuestSpace,
+    fd: u64,
+    addr: u64,
+    len: u64,
+) -> SyscallResult {
+    let (st, len) = match read_sockaddr(space, addr, len) {
+        Ok(v) => v,
+        Err(e) => return err(e),
+    };
+    let ret = unsafe {
+        libc::connect(fd as i32, &st as *const _ as *const libc::sockaddr, len)
+    };
+    host_ret(ret as i64)
+}
+
+pub fn do_listen(fd: u64, backlog: u64) -> SyscallResult {
+    host_ret(unsafe { libc::listen(fd as i32, backlog as i32) } as i64)
+}
+
+pub fn do_accept4(
+    space: &GuestSpace,
+    fd: u64,
+    addr: u64,
+    len_addr: u64,
+    flags: u64,
+) -> SyscallResult {
+    let Some(flags) = host_sock_flags(flags) else {
+        return err(libc::EINVAL);
+    };
+    let guest_len = match read_addrlen(space, addr, len_addr) {
+        Ok(l) => l,
+        Err(e) => return err(e),
+    };
+    let mut st: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::accept4(
+            fd as i32,
+            &mut st as *mut _ as *mut libc::sockaddr,
+            &mut len,
+            flags,
+        )
+    };
+    if ret < 0 {
+        return SyscallResult::Continue(errno_ret());
+    }
+    if let Err(e) = write_sockaddr(space, addr, len_addr, guest_len, &st, len) {
+        unsafe { libc::close(ret) };
+        return err(e);
+    }
+    SyscallResult::Continue(ret as u64)
+}
+
+// ---------------------------------------------------------------
+// getsockname / getpeername / shutdown
+// ---------------------------------------------------------------
+
+type NameFn =
+    unsafe extern "C" fn(i32, *mut libc::sockaddr, *mut libc::socklen_t) -> i32;
+
+fn do_name(
+    space: &GuestSpace,
+    f: NameFn,
+    fd: u64,
+    addr: u64,
+    len_addr: u64,
+) -> SyscallResult {
+    if addr == 0 {
+        return err(libc::EFAULT);
+    }
+    let guest_len = match read_addrlen(space, addr, len_addr) {
+        Ok(l) => l,
+        Err(e) => return err(e),
+    };
+    let mut st: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret = unsafe {
+        f(
+            fd as i32,
+            &mut st as *mut _ as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return SyscallResult::Continue(errno_ret());
+    }
+    match write_sockaddr(space, addr, len_addr, guest_len, &st, len) {
+        Ok(()) => SyscallResult::Continue(0),
+        Err(e) => err(e),
+    }
+}
+
+pub fn do_getsockname(
+    space: &GuestSpace,
+    fd: u64,
+    addr: u64,
+    len_addr: u64,
+) -> SyscallResult {
+    do_name(space, libc::getsockname, fd, addr, len_addr)
+}
+
+pub fn do_getpeername(
+    space: &GuestSpace,
+    fd: u64,
+    addr: u64,
+    len_addr: u64,
+) -> SyscallResult {
+    do_name(space, libc::getpeername, fd, addr, len_addr)
+}
+
+pub fn do_shutdown(fd: u64, how: u64) -> SyscallResult {
+    host_ret(unsafe { libc::shutdown(fd as i32, how as i32) } as i64)
+}
+
+// ---------------------------------------------------------------
+// sendto / recvfrom
+// ---------------------------------------------------------------
+
+pub fn do_sendto(
+    space: &GuestSpace,
+    fd: u64,
+    buf: u64,
+    len: u64,
+    flags: u64,
+    addr: u64,
+    addrlen: u64,
+) -> SyscallResult {
+    let host = match guest_buf(space, buf, len as usize, libc::PROT_READ) {
+        Ok(p) => p,
+        Err(e) => return err(e),
+    };
+    let dest = if addr != 0 {
+        match read_sockaddr(space, addr, addrlen) {
+            Ok(v) => Some(v),
+            Err(e) => return err(e),
+        }
+    } else {
+        None
+    };
+    let (dp, dl) = match &dest {
+        Some((st, l)) => (st as *const _ as *const libc::sockaddr, *l),
+        None => (std::ptr::null(), 0),
+    };
+    let ret = unsafe {
+        libc::sendto(
+            fd as i32,
+            host as *const libc::c_void,
+            len as usize,
+            flags as i32,
+            dp,
+            dl,
+        )
+    };
+    host_ret(ret as i64)
+}
+
+pub fn do_recvfrom(
+    space: &GuestSpace,
+    fd: u64,
+    buf: u64,
+    len: u64,
+    flags: u64,
+    addr: u64,
+    len_addr: u64,
+) -> SyscallResult {
+    let host = match guest_buf(space, buf, len as usize, libc::PROT_WRITE) {
+        Ok(p) => p,
+        Err(e) => return err(e),
+    };
+    let guest_len = match read_addrlen(space, addr, len_addr) {
+        Ok(l) => l,
+        Err(e) => return err(e),
+    };
+    let mut st: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut alen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let (ap, lp) = if addr != 0 {
+        (
+            &mut st as *mut _ as *mut libc::sockaddr,
+            &mut alen as *mut _,
+        )
+    } else {
+        (std::ptr::null_mut(), std::ptr::null_mut())
+    };
+    let ret = unsafe {
+        libc::recvfrom(
+            fd as i32,
+            host as *mut libc::c_void,
+            len as usize,
+            flags as i32,
+            ap,
+            lp,
+        )
+    };
+    if ret < 0 {
+        return SyscallResult::Continue(errno_ret());
+    }
+    match write_sockaddr(space, addr, len_addr, guest_len, &st, alen) {
+        Ok(()) => SyscallResult::Continue(ret as u64),
+        Err(e) => err(e),
+    }
+}
+
+// ---------------------------------------------------------------
+// sendmsg / recvmsg
+// ---------------------------------------------------------------
+
+/// Guest `struct msghdr` fields.
+struct GuestMsg {
+    name: u64,
+    namelen: u32,
+    iov: u64,
+    iovlen: u64,
+    control: u64,
+    controllen: u64,
+}
+
+fn read_msghdr(space: &GuestSpace, addr: u64) -> Result<GuestMsg, i32> {
+    if !space.range_ok(addr, MSGHDR_SIZE) {
+        return Err(libc::EFAULT);
+    }
+    Ok(GuestMsg {
+        name: read_u64(space, addr)?,
+        namelen: read_u32(space, addr + 8)?,
+        iov: read_u64(space, addr + 16)?,
+        iovlen: read_u64(space, addr + 24)?,
+        control: read_u64(space, addr + 32)?,
+        controllen: read_u64(space, addr + 40)?,
+    })
+}
+
+/// Build host iovecs pointing straight into guest memory, each
+/// buffer accessible with `prot`.
+fn read_iovecs(
+    space: &GuestSpace,
+    iov: u64,
+    cnt: u64,
+    prot: i32,
+) -> Result<Vec<libc::iovec>, i32> {
+    if cnt > libc::UIO_MAXIOV as u64 {
+        return Err(libc::EMSGSIZE);
+    }
+    let mut out = Vec::with_capacity(cnt as usize);
+    for i in 0..cnt {
+        let base = read_u64(space, iov + i * 16)?;
+        let len = read_u64(space, iov + i * 16 + 8)? as usize;
+        let host = guest_buf(space, base, len, prot)?;
+        out.push(libc::iovec {
+            iov_base: host as *mut libc::c_void,
+            iov_len: len,
+        });
+    }
+    Ok(out)
+}
+
+/// Host control buffer with cmsghdr alignment.
+fn control_buf(len: u64) -> Result<Vec<u64>, i32> {
+    if len as usize > MAX_CONTROL_LEN {
+        return Err(libc::ENOBUFS);
+    }
+    Ok(vec![0u64; (len as usize).div_ceil(8)])
+}
+
+/// Fds carried by the control messages in `buf`, which may only
+/// be `SCM_RIGHTS`; their payload is host fds already.
+fn scm_rights(buf: &[u8]) -> Result<Vec<i32>, i32> {
+    let mut fds = Vec::new();
+    let mut off = 0;
+    while off + CMSG_HDR_LEN <= buf.len() {
+        let len = u64::from_le_bytes(buf[off..off + 8].try_into().unwrap());
+        let level =
+            i32::from_le_bytes(buf[off + 8..off + 12].try_into().unwrap());
+        let ty =
+            i32::from_le_bytes(buf[off + 12..off + 16].try_into().unwrap());
+        if len < CMSG_HDR_LEN as u64 || off as u64 + len > buf.len() as u64 {
+            return Err(libc::EINVAL);
+        }
+        if level != TARGET_SOL_SOCKET as i32 || ty != TARGET_SCM_RIGHTS {
+            return Err(libc::EINVAL);
+        }
+        let payload = &buf[off + CMSG_HDR_LEN..off + len as usize];
+        fds.extend(
+            payload
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes(b.try_into().unwrap())),
+        );
+        off += (len as usize).next_multiple_of(8);
+    }
+    Ok(fds)
+}
+
+fn bytes_of(v: &mut [u64]) -> &mut [u8] {
+    unsafe {
+        std::slice::from_raw_parts_mut(v.as_mut_ptr() as *mut u8, v.len() * 8)
+    }
+}
+
+pub fn do_sendmsg(
+    space: &GuestSpace,
+    vfs: &Vfs,
+    fd: u64,
+    msg: u64,
+    flags: u64,
+) -> SyscallResult {
+    let r = (|| {
+        let m = read_msghdr(space, msg)?;
+        let name = if m.name != 0 && m.namelen != 0 {
+            Some(read_sockaddr(space, m.name, m.namelen as u64)?)
+        } else {
+            None
+        };
+        let mut iov = read_iovecs(space, m.iov, m.iovlen, libc::PROT_READ)?;
+        let mut control = control_buf(m.controllen)?;
+        let cbytes = &mut bytes_of(&mut control)[..m.controllen as usize];
+        copy_from_guest(space, m.control, cbytes)?;
+        // Only the guest's own fds may be passed on.
+        if !scm_rights(cbytes)?.into_iter().all(|fd| vfs.owns(fd)) {
+            return Err(libc::EBADF);
+        }
+
+        let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+        if let Some((st, len)) = &name {
+            hdr.msg_name = st as *const _ as *mut libc::c_void;
+            hdr.msg_namelen = *len;
+        }
+        hdr.msg_iov = iov.as_mut_ptr();
+        hdr.msg_iovlen = iov.len();
+        if m.controllen != 0 {
+            hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = m.controllen as usize;
+        }
+        let ret = unsafe { libc::sendmsg(fd as i32, &hdr, flags as i32) };
+        Ok(host_ret(ret as i64))
+    })();
+    r.unwrap_or_else(err)
+}
+
+pub fn do_recvmsg(
+    space: &GuestSpace,
+    vfs: &mut Vfs,
+    fd: u64,
+    msg: u64,
+    flags: u64,
+) -> SyscallResult {
+    let r = (|| {
+        let m = read_msghdr(space, msg)?;
+        if (m.namelen as i32) < 0 {
+            return Err(libc::EINVAL);
+        }
+        let mut iov = read_iovecs(space, m.iov, m.iovlen, libc::PROT_WRITE)?;
+        let mut control = control_buf(m.controllen)?;
+        let mut st: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+
+        let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+        if m.name != 0 {
+            hdr.msg_name = &mut st as *mut _ as *mut libc::c_void;
+            hdr.msg_namelen = size_of::<libc::sockaddr_storage>() as u32;
+        }
+        hdr.msg_iov = iov.as_mut_ptr();
+        hdr.msg_iovlen = iov.len();
+        if m.controllen != 0 {
+            hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = m.controllen as usize;
+        }
+        let ret = unsafe { libc::recvmsg(fd as i32, &mut hdr, flags as i32) };
+        if ret < 0 {
+            return Ok(SyscallResult::Continue(errno_ret()));
+        }
+
+        if m.name != 0 {
+            let n = m.namelen.min(hdr.msg_namelen) as usize;
+            copy_to_guest(space, m.name, &as_bytes(&st)[..n])?;
+        }
+        copy_to_guest(space, msg + 8, &hdr.msg_namelen.to_le_bytes())?;
+        let clen = hdr.msg_controllen;
+        let cbytes = &bytes_of(&mut control)[..clen];
+        for fd in scm_rights(cbytes).unwrap_or_default() {
+            vfs.opened(SyscallResult::Continue(fd as u64));
+        }
+        copy_to_guest(space, m.control, cbytes)?;
+        copy_to_guest(space, msg + 40, &(clen as u64).to_le_bytes())?;
+        copy_to_guest(space, msg + 48, &hdr.msg_flags.to_le_bytes())?;
+        Ok(SyscallResult::Continue(ret as u64))
+    })();
+    r.unwrap_or_else(err)
+}
+
+// ---------------------------------------------------------------
+// getsockopt / setsockopt
+// ---------------------------------------------------------------
+
+/// Value layout of a supported socket option.
+#[derive(Clone, Copy)]
+enum OptKind {
+    Int,
+    Linger,
+    Timeval,
+}
+
+/// Map a guest option to the host level/name and its layout.
+/// Options not listed here fail with `ENOPROTOOPT`.
+fn host_sockopt(level: u64, name: u64) -> Option<(i32, i32, OptKind)> {
+    use OptKind::*;
+    let (host_level, host_name, kind) = match (level, name) {
+        (TARGET_SOL_SOCKET, n) => {
+            let (hn, k) = match n {
+                TARGET_SO_REUSEADDR => (libc::SO_REUSEADDR, Int),
+                TARGET_SO_TYPE => (libc::SO_TYPE, Int),
+                TARGET_SO_ERROR => (libc::SO_ERROR, Int),
+                TARGET_SO_BROADCAST => (libc::SO_BROADCAST, Int),
+                TARGET_SO_SNDBUF => (libc::SO_SNDBUF, Int),
+                TARGET_SO_RCVBUF => (libc::SO_RCVBUF, Int),
+                TARGET_SO_KEEPALIVE => (libc::SO_KEEPALIVE, Int),
+                TARGET_SO_LINGER => (libc::SO_LINGER, Linger),
+                TARGET_SO_REUSEPORT => (libc::SO_REUSEPORT, Int),
+                TARGET_SO_RCVTIMEO => (libc::SO_RCVTIMEO, Timeval),
+                TARGET_SO_SNDTIMEO => (libc::SO_SNDTIMEO, Timeval),
+                TARGET_SO_ACCEPTCONN => (libc::SO_ACCEPTCONN, Int),
+                TARGET_SO_PROTOCOL => (libc::SO_PROTOCOL, Int),
+                TARGET_SO_DOMAIN => (libc::SO_DOMAIN, Int),
+                _ => return None,
+            };
+            (libc::SOL_SOCKET, hn, k)
+        }
+        (TARGET_IPPROTO_TCP, n) => {
+            let hn = match n {
+                TARGET_TCP_NODELAY => libc::TCP_NODELAY,
+                TARGET_TCP_KEEPIDLE => libc::TCP_KEEPIDLE,
+                TARGET_TCP_KEEPINTVL => libc::TCP_KEEPINTVL,
+                TARGET_TCP_KEEPCNT => libc::TCP_KEEPCNT,
+                _ => return None,
+            };
+            (libc::IPPROTO_TCP, hn, Int)
+        }
+        (TARGET_IPPROTO_IPV6, TARGET_IPV6_V6ONLY) => {
+            (libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, Int)
+        }
+        _ => return None,
+    };
+    Some((host_level, host_name, kind))
+}
+
+pub fn do_setsockopt(
+    space: &GuestSpace,
+    fd: u64,
+    level: u64,
+    name: u64,
+    val: u64,
+    len: u64,
+) -> SyscallResult {
+    let Some((hl, hn, kind)) = host_sockopt(level, name) else {
+        return err(libc::ENOPROTOOPT);
+    };
+    let len = len as u32 as usize;
+    let r = (|| {
+        let fd = fd as i32;
+        let ret = match kind {
+            OptKind::Int => {
+                if len < 4 {
+                    return Err(libc::EINVAL);
+                }
+                let v = read_u32(space, val)? as libc::c_int;
+                unsafe { setsockopt_raw(fd, hl, hn, &v) }
+            }
+            OptKind::Linger => {
+                if len < 8 {
+                    return Err(libc::EINVAL);
+                }
+                let l = libc::linger {
+                    l_onoff: read_u32(space, val)? as i32,
+                    l_linger: read_u32(space, val + 4)? as i32,
+                };
+                unsafe { setsockopt_raw(fd, hl, hn, &l) }
+            }
+            OptKind::Timeval => {
+                if len < 16 {
+                    return Err(libc::EINVAL);
+                }
+                let tv = libc::timeval {
+                    tv_sec: read_u64(space, val)? as i64,
+                    tv_usec: read_u64(space, val + 8)? as i64,
+                };
+                unsafe { setsockopt_raw(fd, hl, hn, &tv) }
+            }
+        };
+        Ok(host_ret(ret as i64))
+    })();
+    r.unwrap_or_else(err)
+}
+
+unsafe fn setsockopt_raw<T>(fd: i32, level: i32, name: i32, v: &T) -> i32 {
+    libc::setsockopt(
+        fd,
+        level,
+        name,
+        v as *const T as *const libc::c_void,
+        size_of::<T>() as libc::socklen_t,
+    )
+}
+
+unsafe fn getsockopt_raw<T>(fd: i32, level: i32, name: i32, v: &mut T) -> i32 {
+    let mut len = size_of::<T>() as libc::socklen_t;
+    libc::getsockopt(
+        fd,
+        level,
+        name,
+        v as *mut T as *mut libc::c_void,
+        &mut len,
+    )
+}
+
+pub fn do_getsockopt(
+    space: &GuestSpace,
+    fd: u64,
+    level: u64,
+    name: u64,
+    val: u64,
+    len_addr: u64,
+) -> SyscallResult {
+    let Some((hl, hn, kind)) = host_sockopt(level, name) else {
+        return err(libc::ENOPROTOOPT);
+    };
+    let r = (|| {
+        let guest_len = read_u32(space, len_addr)?;
+        if (guest_len as i32) < 0 {
+            return Err(libc::EINVAL);
+        }
+        let fd = fd as i32;
+        let mut out = [0u8; 16];
+        let (ret, size) = match kind {
+            OptKind::Int => {
+                let mut v: libc::c_int = 0;
+                let ret = unsafe { getsockopt_raw(fd, hl, hn, &mut v) };
+                out[..4].copy_from_slice(&v.to_le_bytes());
+                (ret, 4)
+            }
+            OptKind::Linger => {
+                let mut l: libc::linger = unsafe { std::mem::zeroed() };
+                let ret = unsafe { getsockopt_raw(fd, hl, hn, &mut l) };
+                out[..4].copy_from_slice(&l.l_onoff.to_le_bytes());
+                out[4..8].copy_from_slice(&l.l_linger.to_le_bytes());
+                (ret, 8)
+            }
+            OptKind::Timeval => {
+                let mut tv: libc::timeval = unsafe { std::mem::zeroed() };
+                let ret = unsafe { getsockopt_raw(fd, hl, hn, &mut tv) };
+                out[..8].copy_from_slice(&tv.tv_sec.to_le_bytes());
+                out[8..].copy_from_slice(&tv.tv_usec.to_le_bytes());
+                (ret, 16)
+            }
+        };
+        if ret < 0 {
+            return Ok(SyscallResult::Continue(errno_ret()));
+        }
+        let n = (guest_len as usize).min(size);
+        copy_to_guest(space, val, &out[..n])?;
+        copy_to_guest(space, len_addr, &(n as u32).to_le_bytes())?;
+        Ok(SyscallResult::Continue(0))
+    })();
+    r.unwrap_or_else(err)
+}
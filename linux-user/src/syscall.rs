@@ -1,8 +1,22 @@
 use crate::guest_space::GuestSpace;
+use crate::process::{
+    Process, GUEST_PID, GUEST_PPID, TIOCGPGRP, TIOCGSID, TIOCSCTTY, TIOCSPGRP,
+};
+use crate::signal::SignalTable;
+use crate::socket;
+use crate::vfs::{Device, Vfs};
 
 // RISC-V Linux syscall numbers
+const SYS_DUP: u64 = 23;
+const SYS_DUP3: u64 = 24;
+const SYS_FCNTL: u64 = 25;
 const SYS_IOCTL: u64 = 29;
+const SYS_STATFS: u64 = 43;
+const SYS_FSTATFS: u64 = 44;
+const SYS_OPENAT: u64 = 56;
 const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
 const SYS_WRITE: u64 = 64;
 const SYS_WRITEV: u64 = 66;
 const SYS_READLINKAT: u64 = 78;
@@ -16,22 +30,151 @@ const SYS_CLOCK_GETTIME: u64 = 113;
 const SYS_TGKILL: u64 = 131;
 const SYS_RT_SIGACTION: u64 = 134;
 const SYS_RT_SIGPROCMASK: u64 = 135;
+const SYS_TIMES: u64 = 153;
+const SYS_SETPGID: u64 = 154;
+const SYS_GETPGID: u64 = 155;
+const SYS_GETSID: u64 = 156;
+const SYS_SETSID: u64 = 157;
 const SYS_UNAME: u64 = 160;
+const SYS_GETRUSAGE: u64 = 165;
 const SYS_GETPID: u64 = 172;
+const SYS_GETPPID: u64 = 173;
 const SYS_GETTID: u64 = 178;
 const SYS_BRK: u64 = 214;
 const SYS_MUNMAP: u64 = 215;
+const SYS_MREMAP: u64 = 216;
 const SYS_MMAP: u64 = 222;
 const SYS_MPROTECT: u64 = 226;
+const SYS_MSYNC: u64 = 227;
 const SYS_MADVISE: u64 = 233;
+const SYS_SOCKET: u64 = 198;
+const SYS_SOCKETPAIR: u64 = 199;
+const SYS_BIND: u64 = 200;
+const SYS_LISTEN: u64 = 201;
+const SYS_ACCEPT: u64 = 202;
+const SYS_CONNECT: u64 = 203;
+const SYS_GETSOCKNAME: u64 = 204;
+const SYS_GETPEERNAME: u64 = 205;
+const SYS_SENDTO: u64 = 206;
+const SYS_RECVFROM: u64 = 207;
+const SYS_SETSOCKOPT: u64 = 208;
+const SYS_GETSOCKOPT: u64 = 209;
+const SYS_SHUTDOWN: u64 = 210;
+const SYS_SENDMSG: u64 = 211;
+const SYS_RECVMSG: u64 = 212;
+const SYS_ACCEPT4: u64 = 242;
+const SYS_WAIT4: u64 = 260;
 const SYS_RISCV_HWPROBE: u64 = 258;
+const SYS_RISCV_FLUSH_ICACHE: u64 = 259;
 const SYS_PRLIMIT64: u64 = 261;
 const SYS_GETRANDOM: u64 = 278;
+const SYS_MEMBARRIER: u64 = 283;
 const SYS_RSEQ: u64 = 293;
 
 const ENOSYS: u64 = (-38i64) as u64;
-const ENOTTY: u64 = (-25i64) as u64;
+const ENODEV: u64 = (-19i64) as u64;
 const ENOENT: u64 = (-2i64) as u64;
+const EBADF: u64 = (-9i64) as u64;
+
+/// Restrictions applied to guest syscalls for one run.
+#[derive(Debug, Clone, Default)]
+pub struct SyscallPolicy {
+    /// Fail `socket`/`socketpair` with `EACCES`.
+    pub deny_sockets: bool,
+    /// Fail opening `/dev/random` and `/dev/urandom` with
+    /// `EACCES`.
+    pub deny_random: bool,
+}
+
+/// Name of syscall `nr`, for `-strace`.
+pub fn syscall_name(nr: u64) -> Option<&'static str> {
+    Some(match nr {
+        SYS_DUP => "dup",
+        SYS_DUP3 => "dup3",
+        SYS_FCNTL => "fcntl",
+        SYS_IOCTL => "ioctl",
+        SYS_STATFS => "statfs",
+        SYS_FSTATFS => "fstatfs",
+        SYS_OPENAT => "openat",
+        SYS_CLOSE => "close",
+        SYS_LSEEK => "lseek",
+        SYS_READ => "read",
+        SYS_WRITE => "write",
+        SYS_WRITEV => "writev",
+        SYS_READLINKAT => "readlinkat",
+        SYS_FSTAT => "fstat",
+        SYS_EXIT => "exit",
+        SYS_EXIT_GROUP => "exit_group",
+        SYS_SET_TID_ADDRESS => "set_tid_address",
+        SYS_FUTEX => "futex",
+        SYS_SET_ROBUST_LIST => "set_robust_list",
+        SYS_CLOCK_GETTIME => "clock_gettime",
+        SYS_TGKILL => "tgkill",
+        SYS_RT_SIGACTION => "rt_sigaction",
+        SYS_RT_SIGPROCMASK => "rt_sigprocmask",
+        SYS_TIMES => "times",
+        SYS_SETPGID => "setpgid",
+        SYS_GETPGID => "getpgid",
+        SYS_GETSID => "getsid",
+        SYS_SETSID => "setsid",
+        SYS_UNAME => "uname",
+        SYS_GETRUSAGE => "getrusage",
+        SYS_GETPID => "getpid",
+        SYS_GETPPID => "getppid",
+        SYS_GETTID => "gettid",
+        SYS_BRK => "brk",
+        SYS_MUNMAP => "munmap",
+        SYS_MREMAP => "mremap",
+        SYS_MMAP => "mmap",
+        SYS_MPROTECT => "mprotect",
+        SYS_MSYNC => "msync",
+        SYS_MADVISE => "madvise",
+        SYS_SOCKET => "socket",
+        SYS_SOCKETPAIR => "socketpair",
+        SYS_BIND => "bind",
+        SYS_LISTEN => "listen",
+        SYS_ACCEPT => "accept",
+        SYS_CONNECT => "connect",
+        SYS_GETSOCKNAME => "getsockname",
+        SYS_GETPEERNAME => "getpeername",
+        SYS_SENDTO => "sendto",
+        SYS_RECVFROM => "recvfrom",
+        SYS_SETSOCKOPT => "setsockopt",
+        SYS_GETSOCKOPT => "getsockopt",
+        SYS_SHUTDOWN => "shutdown",
+        SYS_SENDMSG => "sendmsg",
+        SYS_RECVMSG => "recvmsg",
+        SYS_ACCEPT4 => "accept4",
+        SYS_WAIT4 => "wait4",
+        SYS_RISCV_HWPROBE => "riscv_hwprobe",
+        SYS_RISCV_FLUSH_ICACHE => "riscv_flush_icache",
+        SYS_PRLIMIT64 => "prlimit64",
+        SYS_GETRANDOM => "getrandom",
+        SYS_MEMBARRIER => "membarrier",
+        SYS_RSEQ => "rseq",
+        _ => return None,
+    })
+}
+
+/// `-strace` text for a call: `name(a0,a1,...)`.
+pub fn strace_call(nr: u64, args: &[u64]) -> String {
+    let args: Vec<String> = args.iter().map(|a| format!("{a:#x}")).collect();
+    match syscall_name(nr) {
+        Some(name) => format!("{name}({})", args.join(",")),
+        None => format!("syscall_{nr}({})", args.join(",")),
+    }
+}
+
+/// `-strace` text for a return value: ` = 3` or
+/// ` = -1 errno=2`.
+pub fn strace_ret(ret: u64) -> String {
+    let v = ret as i64;
+    if (-4095..0).contains(&v) {
+        format!(" = -1 errno={}", -v)
+    } else {
+        format!(" = {v}")
+    }
+}
 
 /// Syscall dispatch result.
 pub enum SyscallResult {
@@ -45,26 +188,49 @@ pub enum SyscallResult {
 ///
 /// `regs` is the full GPR array (x0-x31).
 /// Syscall number in a7 (x17), args in a0-a5 (x10-x15).
+#[allow(clippy::too_many_arguments)]
 pub fn handle_syscall(
     space: &mut GuestSpace,
+    vfs: &mut Vfs,
+    process: &mut Process,
+    signals: &mut SignalTable,
     regs: &mut [u64; 32],
     mmap_next: &mut u64,
     elf_path: &str,
+    policy: &SyscallPolicy,
 ) -> SyscallResult {
     let nr = regs[17]; // a7
     let a0 = regs[10];
     let a1 = regs[11];
     let a2 = regs[12];
     let a3 = regs[13];
-    #[allow(unused_variables)]
     let a4 = regs[14];
+    let a5 = regs[15];
 
     match nr {
+        SYS_OPENAT => vfs.openat(space, policy, a0, a1, a2, a3),
+        SYS_READ => match vfs.device(a0) {
+            Some(dev) => vfs.read(space, dev, a1, a2),
+            None => do_read(space, a0, a1, a2),
+        },
+        SYS_LSEEK if vfs.device(a0).is_some() => SyscallResult::Continue(0),
+        SYS_LSEEK => {
+            host_ret(
+                unsafe { libc::lseek(a0 as i32, a1 as i64, a2 as i32) } as i64
+            )
+        }
+        SYS_WRITE if vfs.device(a0).is_some() => vfs.write(space, a1, a2),
         SYS_WRITE => {
             let fd = a0 as i32;
             let buf = a1;
             let len = a2 as usize;
-            let host_buf = space.g2h(buf);
+            let host_buf =
+                match socket::guest_buf(space, buf, len, libc::PROT_READ) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        return SyscallResult::Continue((-e as i64) as u64)
+                    }
+                };
             let ret = unsafe {
                 libc::write(fd, host_buf as *const libc::c_void, len)
             };
@@ -98,20 +264,52 @@ pub fn handle_syscall(
             }
         }
         SYS_MMAP => {
+            // Private maps of /dev/zero are anonymous memory; no
+            // other device can be mapped.
+            let file = a3 as i32 & libc::MAP_ANONYMOUS == 0
+                && match vfs.device(a4) {
+                    Some(Device::Zero) => false,
+                    None => true,
+                    Some(_) => return SyscallResult::Continue(ENODEV),
+                };
             let addr = a0;
             let len = a1 as usize;
             let prot = a2 as i32;
             let aligned_len =
                 crate::guest_space::page_align_up(len as u64) as usize;
-            let guest_addr = if addr != 0 {
+            let end = |a: u64| a.saturating_add(aligned_len as u64);
+            // Without MAP_FIXED the address is only a hint; never
+            // place a mapping over another one or a stack guard.
+            let guest_addr = if addr != 0
+                && (a3 as i32 & libc::MAP_FIXED != 0
+                    || space.is_free(addr, end(addr)))
+            {
                 addr
             } else {
                 let a = *mmap_next;
+                if !space.is_free(a, end(a)) {
+                    return SyscallResult::Continue((-12i64) as u64);
+                }
                 *mmap_next += aligned_len as u64;
                 a
             };
-            match space.mmap_fixed(guest_addr, aligned_len, prot) {
+            let r = if file {
+                space.mmap_file(
+                    guest_addr,
+                    aligned_len,
+                    prot,
+                    a3 as i32,
+                    a4 as i32,
+                    a5 as i64,
+                )
+            } else {
+                space.mmap_fixed(guest_addr, aligned_len, prot)
+            };
+            match r {
                 Ok(()) => SyscallResult::Continue(guest_addr),
+                Err(e) if file => SyscallResult::Continue(
+                    -(e.raw_os_error().unwrap_or(libc::ENOMEM) as i64) as u64,
+                ),
                 Err(_) => SyscallResult::Continue(
                     (-12i64) as u64, // ENOMEM
                 ),
@@ -126,15 +324,78 @@ pub fn handle_syscall(
                 Err(_) => SyscallResult::Continue((-22i64) as u64),
             }
         }
+        SYS_MUNMAP => io_ret(space.munmap(a0, a1 as usize).map(|()| 0)),
+        SYS_MADVISE => {
+            io_ret(space.madvise(a0, a1 as usize, a2 as i32).map(|()| 0))
+        }
+        SYS_MREMAP => io_ret(space.mremap(
+            a0,
+            a1 as usize,
+            a2 as usize,
+            a3 as i32,
+            mmap_next,
+        )),
+        SYS_MSYNC => {
+            io_ret(space.msync(a0, a1 as usize, a2 as i32).map(|()| 0))
+        }
+        SYS_RISCV_FLUSH_ICACHE => {
+            io_ret(space.flush_icache(a0, a1, a2).map(|()| 0))
+        }
+        SYS_MEMBARRIER => io_ret(space.membarrier(a0, a1)),
         // Stubs that return success
-        SYS_MUNMAP | SYS_SET_ROBUST_LIST | SYS_RT_SIGACTION
-        | SYS_RT_SIGPROCMASK | SYS_MADVISE | SYS_CLOSE => {
+        SYS_RT_SIGACTION => signals.rt_sigaction(space, a0, a1, a2, a3),
+        SYS_SET_ROBUST_LIST | SYS_RT_SIGPROCMASK => SyscallResult::Continue(0),
+        // Guest fds are host fds, but the emulator's own fds are
+        // not the guest's to close, duplicate or replace.
+        SYS_CLOSE | SYS_DUP | SYS_DUP3 | SYS_FCNTL if !vfs.owns(a0 as i32) => {
+            SyscallResult::Continue(EBADF)
+        }
+        // Stdio may be replaced even once closed.
+        SYS_DUP3
+            if a1 > 2 && !vfs.owns(a1 as i32) && host_fd_open(a1 as i32) =>
+        {
+            SyscallResult::Continue(EBADF)
+        }
+        // Stdio is shared with the emulator and never really
+        // closed.
+        SYS_CLOSE if a0 <= 2 => {
+            vfs.forget(a0 as i32);
             SyscallResult::Continue(0)
         }
+        SYS_CLOSE => {
+            let ret = unsafe { libc::close(a0 as i32) };
+            if ret == 0 {
+                vfs.forget(a0 as i32);
+            }
+            host_ret(ret as i64)
+        }
+        SYS_DUP => {
+            let fd = unsafe { libc::dup(a0 as i32) };
+            if fd >= 0 {
+                vfs.dup(a0 as i32, fd);
+            }
+            host_ret(fd as i64)
+        }
+        SYS_DUP3 => {
+            let fd = unsafe { libc::dup3(a0 as i32, a1 as i32, a2 as i32) };
+            if fd >= 0 {
+                vfs.dup(a0 as i32, fd);
+            }
+            host_ret(fd as i64)
+        }
+        SYS_FCNTL => do_fcntl(vfs, a0, a1, a2),
         SYS_SET_TID_ADDRESS => {
-            SyscallResult::Continue(1) // fake TID
+            SyscallResult::Continue(GUEST_PID as u64) // fake TID
         }
-        SYS_GETPID | SYS_GETTID => SyscallResult::Continue(1),
+        SYS_GETPID | SYS_GETTID => SyscallResult::Continue(GUEST_PID as u64),
+        SYS_GETPPID => SyscallResult::Continue(GUEST_PPID as u64),
+        SYS_SETPGID => process.setpgid(a0, a1),
+        SYS_GETPGID => process.getpgid(a0),
+        SYS_GETSID => process.getsid(a0),
+        SYS_SETSID => process.setsid(),
+        SYS_WAIT4 => process.wait4(a0, a2),
+        SYS_TIMES => process.times(space, a0),
+        SYS_GETRUSAGE => process.getrusage(space, a0, a1),
         SYS_GETRANDOM => {
             // Fill buffer with zeros (deterministic)
             let buf = a0;
@@ -156,13 +417,51 @@ pub fn handle_syscall(
                 SyscallResult::Continue(0)
             }
         }
-        SYS_WRITEV => do_writev(space, a0, a1, a2),
-        SYS_IOCTL => SyscallResult::Continue(ENOTTY),
-        SYS_FSTAT => do_fstat(space, a0, a1),
+        SYS_WRITEV => do_writev(space, vfs, a0, a1, a2),
+        SYS_IOCTL
+            if matches!(a1, TIOCSCTTY | TIOCGPGRP | TIOCSPGRP | TIOCGSID) =>
+        {
+            process.tty_ioctl(space, vfs.is_host_tty(a0), a1, a2)
+        }
+        SYS_IOCTL => vfs.ioctl(space, a0, a1, a2),
+        SYS_STATFS => vfs.statfs(space, a0, a1),
+        SYS_FSTATFS => vfs.fstatfs(space, a0, a1),
+        SYS_FSTAT => match vfs.device(a0) {
+            Some(dev) => vfs.fstat(space, dev, a1),
+            None => do_fstat(space, a0, a1),
+        },
         SYS_PRLIMIT64 => do_prlimit64(space, a0, a1, a2, a3),
         SYS_UNAME => do_uname(space, a0),
         SYS_READLINKAT => do_readlinkat(space, a0, a1, a2, a3, elf_path),
         SYS_CLOCK_GETTIME => do_clock_gettime(space, a0, a1),
+        SYS_SOCKET => vfs.opened(socket::do_socket(policy, a0, a1, a2)),
+        SYS_SOCKETPAIR => {
+            let r = socket::do_socketpair(space, policy, a0, a1, a2, a3);
+            let mut sv = [0u8; 8];
+            if matches!(r, SyscallResult::Continue(0))
+                && socket::copy_from_guest(space, a3, &mut sv).is_ok()
+            {
+                for pair in sv.chunks(4) {
+                    let fd = i32::from_le_bytes(pair.try_into().unwrap());
+                    vfs.opened(SyscallResult::Continue(fd as u64));
+                }
+            }
+            r
+        }
+        SYS_BIND => socket::do_bind(space, a0, a1, a2),
+        SYS_LISTEN => socket::do_listen(a0, a1),
+        SYS_ACCEPT => vfs.opened(socket::do_accept4(space, a0, a1, a2, 0)),
+        SYS_ACCEPT4 => vfs.opened(socket::do_accept4(space, a0, a1, a2, a3)),
+        SYS_CONNECT => socket::do_connect(space, a0, a1, a2),
+        SYS_GETSOCKNAME => socket::do_getsockname(space, a0, a1, a2),
+        SYS_GETPEERNAME => socket::do_getpeername(space, a0, a1, a2),
+        SYS_SENDTO => socket::do_sendto(space, a0, a1, a2, a3, a4, a5),
+        SYS_RECVFROM => socket::do_recvfrom(space, a0, a1, a2, a3, a4, a5),
+        SYS_SETSOCKOPT => socket::do_setsockopt(space, a0, a1, a2, a3, a4),
+        SYS_GETSOCKOPT => socket::do_getsockopt(space, a0, a1, a2, a3, a4),
+        SYS_SHUTDOWN => socket::do_shutdown(a0, a1),
+        SYS_SENDMSG => socket::do_sendmsg(space, vfs, a0, a1, a2),
+        SYS_RECVMSG => socket::do_recvmsg(space, vfs, a0, a1, a2),
         _ => {
             eprintln!("[tcg] unknown syscall {nr} → -ENOSYS");
             SyscallResult::Continue(ENOSYS)
@@ -174,17 +473,69 @@ pub fn handle_syscall(
 // Helper: convert libc errno to negative return
 // ---------------------------------------------------------------
 
-fn errno_ret() -> u64 {
+pub(crate) fn errno_ret() -> u64 {
     let e = unsafe { *libc::__errno_location() };
     (-e as i64) as u64
 }
 
+pub(crate) fn host_ret(ret: i64) -> SyscallResult {
+    if ret < 0 {
+        SyscallResult::Continue(errno_ret())
+    } else {
+        SyscallResult::Continue(ret as u64)
+    }
+}
+
+fn io_ret(r: std::io::Result<u64>) -> SyscallResult {
+    match r {
+        Ok(v) => SyscallResult::Continue(v),
+        Err(e) => {
+            let e = e.raw_os_error().unwrap_or(libc::EINVAL);
+            SyscallResult::Continue((-e as i64) as u64)
+        }
+    }
+}
+
+// ---------------------------------------------------------------
+// fcntl(fd, cmd, arg)
+// ---------------------------------------------------------------
+
+/// True if `fd` is open in this process.
+fn host_fd_open(fd: i32) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+}
+
+fn do_fcntl(vfs: &mut Vfs, fd: u64, cmd: u64, arg: u64) -> SyscallResult {
+    // asm-generic command numbers; all take an int argument.
+    const F_DUPFD: u64 = 0;
+    const F_GETFD: u64 = 1;
+    const F_SETFD: u64 = 2;
+    const F_GETFL: u64 = 3;
+    const F_SETFL: u64 = 4;
+    const F_DUPFD_CLOEXEC: u64 = 1030;
+    let host_cmd = match cmd {
+        F_DUPFD => libc::F_DUPFD,
+        F_GETFD => libc::F_GETFD,
+        F_SETFD => libc::F_SETFD,
+        F_GETFL => libc::F_GETFL,
+        F_SETFL => libc::F_SETFL,
+        F_DUPFD_CLOEXEC => libc::F_DUPFD_CLOEXEC,
+        _ => return SyscallResult::Continue((-libc::EINVAL as i64) as u64),
+    };
+    let ret = unsafe { libc::fcntl(fd as i32, host_cmd, arg as i32) };
+    if ret >= 0 && matches!(cmd, F_DUPFD | F_DUPFD_CLOEXEC) {
+        vfs.dup(fd as i32, ret);
+    }
+    host_ret(ret as i64)
+}
+
 // ---------------------------------------------------------------
 // writev(fd, iov, iovcnt)
 // ---------------------------------------------------------------
 
 fn do_writev(
     space: &mut GuestSpace,
+    vfs: &Vfs,
     fd: u64,
     iov_addr: u64,
     iovcnt: u64,
@@ -200,6 +551,10 @@ fn do_writev(
         if len == 0 {
             continue;
         }
+        if vfs.device(fd as u64).is_some() {
+            total += len;
+            continue;
+        }
         let host = space.g2h(base);
         let ret = unsafe { libc::write(fd, host as *const libc::c_void, len) };
         if ret < 0 {
@@ -210,6 +565,19 @@ fn do_writev(
     SyscallResult::Continue(total as u64)
 }
 
+// ---------------------------------------------------------------
+// read(fd, buf, count)
+// ---------------------------------------------------------------
+
+fn do_read(space: &GuestSpace, fd: u64, buf: u64, len: u64) -> SyscallResult {
+    let host =
+        match socket::guest_buf(space, buf, len as usize, libc::PROT_WRITE) {
+            Ok(p) => p,
+            Err(e) => return SyscallResult::Continue((-e as i64) as u64),
+        };
+    host_ret(unsafe { libc::read(fd as i32, host.cast(), len as usize) } as i64)
+}
+
 // ---------------------------------------------------------------
 // fstat(fd, statbuf)
 // ---------------------------------------------------------------
@@ -287,32 +655,35 @@ fn do_prlimit64(
     space: &mut GuestSpace,
     _pid: u64,
     resource: u64,
-    _new_rlim: u64,
+    new_rlim: u64,
     old_rlim: u64,
 ) -> SyscallResult {
     const RLIMIT_STACK: u64 = 3;
-    const RLIM_INFINITY: u64 = u64::MAX;
+    let old = if resource == RLIMIT_STACK {
+        space.stack_rlimit()
+    } else {
+        // Forward to host
+        let mut rl: libc::rlimit = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::getrlimit(resource as libc::__rlimit_resource_t, &mut rl)
+        };
+        if ret < 0 {
+            return SyscallResult::Continue(errno_ret());
+        }
+        (rl.rlim_cur, rl.rlim_max)
+    };
+    if new_rlim != 0 && resource == RLIMIT_STACK {
+        // Only reported back; the stack mapping is unchanged.
+        let (cur, max) =
+            unsafe { (space.read_u64(new_rlim), space.read_u64(new_rlim + 8)) };
+        if let Err(e) = space.set_stack_rlimit(cur, max) {
+            return io_ret(Err(e));
+        }
+    }
     if old_rlim != 0 {
-        let p = space.g2h(old_rlim);
-        if resource == RLIMIT_STACK {
-            // rlim_cur = 8 MB, rlim_max = RLIM_INFINITY
-            unsafe {
-                *(p as *mut u64) = 8 * 1024 * 1024;
-                *(p.add(8) as *mut u64) = RLIM_INFINITY;
-            }
-        } else {
-            // Forward to host
-            let mut rl: libc::rlimit = unsafe { std::mem::zeroed() };
-            let ret = unsafe {
-                libc::getrlimit(resource as libc::__rlimit_resource_t, &mut rl)
-            };
-            if ret < 0 {
-                return SyscallResult::Continue(errno_ret());
-            }
-            unsafe {
-                *(p as *mut u64) = rl.rlim_cur;
-                *(p.add(8) as *mut u64) = rl.rlim_max;
-            }
+        unsafe {
+            space.write_u64(old_rlim, old.0);
+            space.write_u64(old_rlim + 8, old.1);
         }
     }
     SyscallResult::Continue(0)
@@ -360,7 +731,9 @@ fn do_readlinkat(
 ) -> SyscallResult {
     // Read guest path string
     let host_path = space.g2h(path_addr);
-    let path = unsafe { std::ffi::CStr::from_ptr(host_path as *const i8) };
+    let path = unsafe {
+        std::ffi::CStr::from_ptr(host_path as *const std::ffi::c_char)
+    };
     let path_bytes = path.to_bytes();
     if path_bytes == b"/proc/self/exe" {
         let elf = elf_path.as_bytes();
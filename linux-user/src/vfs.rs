@@ -0,0 +1,695 @@
+//! Guest-visible device files and terminal ioctls.
+//!
+//! `openat` of `/dev/null`, `/dev/zero`, `/dev/random` and
+//! `/dev/urandom` never reaches the host `/dev`. The guest gets
+//! a host memfd as a placeholder so fd numbers stay host
+//! allocated like every other descriptor, and [`Vfs`] remembers
+//! which device each one stands for; read, write, mmap, fstat
+//! and ioctl on those fds are answered here.
+//!
+//! The random devices draw from the host unless the run is
+//! deterministic, in which case they replay a seeded stream.
+//! Terminal ioctls on other fds go to the host, translating
+//! between the kernel `struct termios` the guest uses and the
+//! host libc's.
+//!
+//! `statfs` and `fstatfs` answer for the devices and `/proc`
+//! with synthetic superblocks, and otherwise convert the host's
+//! result to the guest layout, applying [`StatfsView`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::guest_space::GuestSpace;
+use crate::socket::{copy_from_guest, copy_to_guest, guest_buf};
+use crate::syscall::{errno_ret, host_ret, SyscallPolicy, SyscallResult};
+
+// asm-generic ioctl numbers, shared by riscv64 and x86-64.
+const TCGETS: u64 = 0x5401;
+const TCSETS: u64 = 0x5402;
+const TCSETSW: u64 = 0x5403;
+const TCSETSF: u64 = 0x5404;
+const TIOCGWINSZ: u64 = 0x5413;
+
+/// Guest `struct termios`: four flag words, `c_line` and
+/// `c_cc[19]`.
+const TARGET_NCCS: usize = 19;
+const TARGET_TERMIOS_SIZE: usize = 16 + 1 + TARGET_NCCS;
+
+const O_CLOEXEC: u64 = 0o2000000;
+
+// Superblock magics (linux/magic.h).
+pub const TMPFS_MAGIC: i64 = 0x0102_1994;
+pub const PROC_SUPER_MAGIC: i64 = 0x9fa0;
+
+/// File system names accepted for a declared `f_type`.
+const FS_MAGICS: [(&str, i64); 8] = [
+    ("tmpfs", TMPFS_MAGIC),
+    ("proc", PROC_SUPER_MAGIC),
+    ("ext4", 0xef53),
+    ("btrfs", 0x9123_683e),
+    ("xfs", 0x5846_5342),
+    ("overlay", 0x794c_7630),
+    ("nfs", 0x6969),
+    ("9p", 0x0102_1997),
+];
+
+/// `f_type` of file system `name`, or a number, decimal or
+/// `0x` hex.
+pub fn fs_magic(name: &str) -> Option<i64> {
+    if let Some(&(_, m)) = FS_MAGICS.iter().find(|(n, _)| *n == name) {
+        return Some(m);
+    }
+    match name.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => name.parse().ok(),
+    }
+}
+
+// `f_flags` bits.
+const ST_NOSUID: i64 = 0x2;
+const ST_NODEV: i64 = 0x4;
+const ST_NOEXEC: i64 = 0x8;
+const ST_VALID: i64 = 0x20;
+const ST_RELATIME: i64 = 0x1000;
+
+/// Size of the guest `struct statfs`.
+pub const GUEST_STATFS_SIZE: usize = 120;
+
+/// Guest `struct statfs`: the asm-generic layout with 64-bit
+/// words, followed by four spare words.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GuestStatfs {
+    pub f_type: i64,
+    pub bsize: i64,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: [i32; 2],
+    pub namelen: i64,
+    pub frsize: i64,
+    pub flags: i64,
+}
+
+impl GuestStatfs {
+    pub fn from_host(st: &libc::statfs) -> Self {
+        // libc hides `f_fsid`'s words and `f_flags` (in
+        // `f_spare`); the x86-64 struct is 15 words.
+        let words: [i64; 15] = unsafe { std::mem::transmute(*st) };
+        let fsid = words[7] as u64;
+        Self {
+            f_type: st.f_type,
+            bsize: st.f_bsize,
+            blocks: st.f_blocks,
+            bfree: st.f_bfree,
+            bavail: st.f_bavail,
+            files: st.f_files,
+            ffree: st.f_ffree,
+            fsid: [fsid as i32, (fsid >> 32) as i32],
+            namelen: st.f_namelen,
+            frsize: st.f_frsize,
+            flags: words[10],
+        }
+    }
+
+    /// A file system without space, as the kernel reports for
+    /// its pseudo file systems.
+    fn pseudo(f_type: i64, flags: i64) -> Self {
+        Self {
+            f_type,
+            bsize: 4096,
+            namelen: 255,
+            frsize: 4096,
+            flags: flags | ST_VALID,
+            ..Self::default()
+        }
+    }
+
+    /// Report at most `cap` bytes, keeping
+    /// `bavail <= bfree <= blocks`.
+    pub fn cap(&mut self, cap: u64) {
+        let unit = if self.frsize > 0 {
+            self.frsize
+        } else {
+            self.bsize
+        };
+        let max = cap / unit.max(1) as u64;
+        self.blocks = self.blocks.min(max);
+        self.bfree = self.bfree.min(self.blocks);
+        self.bavail = self.bavail.min(self.bfree);
+    }
+
+    pub fn to_bytes(&self) -> [u8; GUEST_STATFS_SIZE] {
+        let mut out = [0u8; GUEST_STATFS_SIZE];
+        let words = [
+            self.f_type as u64,
+            self.bsize as u64,
+            self.blocks,
+            self.bfree,
+            self.bavail,
+            self.files,
+            self.ffree,
+        ];
+        for (i, w) in words.into_iter().enumerate() {
+            out[i * 8..i * 8 + 8].copy_from_slice(&w.to_le_bytes());
+        }
+        out[56..60].copy_from_slice(&self.fsid[0].to_le_bytes());
+        out[60..64].copy_from_slice(&self.fsid[1].to_le_bytes());
+        for (i, w) in [self.namelen, self.frsize, self.flags]
+            .into_iter()
+            .enumerate()
+        {
+            out[64 + i * 8..72 + i * 8].copy_from_slice(&w.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// How `statfs` presents host file systems; by default as they
+/// are.
+#[derive(Debug, Clone, Default)]
+pub struct StatfsView {
+    /// Files under this host directory report this `f_type`.
+    pub sysroot: Option<(PathBuf, i64)>,
+    /// Report file systems of at most this many bytes.
+    pub disk_cap: Option<u64>,
+}
+
+fn err(e: i32) -> SyscallResult {
+    SyscallResult::Continue((-e as i64) as u64)
+}
+
+/// An emulated character device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Null,
+    Zero,
+    Random,
+    URandom,
+}
+
+impl Device {
+    pub fn from_path(path: &[u8]) -> Option<Self> {
+        Some(match path {
+            b"/dev/null" => Device::Null,
+            b"/dev/zero" => Device::Zero,
+            b"/dev/random" => Device::Random,
+            b"/dev/urandom" => Device::URandom,
+            _ => return None,
+        })
+    }
+
+    /// Linux `st_rdev`: major 1 ("mem"), per-device minor.
+    pub fn rdev(self) -> u64 {
+        let minor = match self {
+            Device::Null => 3,
+            Device::Zero => 5,
+            Device::Random => 8,
+            Device::URandom => 9,
+        };
+        (1 << 8) | minor
+    }
+
+    fn is_random(self) -> bool {
+        matches!(self, Device::Random | Device::URandom)
+    }
+}
+
+/// Source of bytes for the random devices.
+enum Entropy {
+    Host,
+    /// splitmix64 state.
+    Seeded(u64),
+}
+
+impl Entropy {
+    fn fill(&mut self, buf: &mut [u8]) {
+        match self {
+            Entropy::Host => {
+                let mut done = 0;
+                while done < buf.len() {
+                    let rest = &mut buf[done..];
+                    let n = unsafe {
+                        libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0)
+                    };
+                    if n > 0 {
+                        done += n as usize;
+                    }
+                }
+            }
+            Entropy::Seeded(state) => {
+                for chunk in buf.chunks_mut(8) {
+                    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = *state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// Per-run file state: which fds the guest opened, and which
+/// of them are emulated devices.
+pub struct Vfs {
+    devices: HashMap<i32, Device>,
+    /// Fds the guest opened or duplicated onto, stdio only if
+    /// replaced.
+    fds: BTreeSet<i32>,
+    /// Inherited stdio fds the guest has closed.
+    stdio_closed: [bool; 3],
+    entropy: Entropy,
+    captured_stdio: bool,
+    statfs: StatfsView,
+}
+
+impl Vfs {
+    /// Random devices replay a stream seeded by `seed` when set,
+    /// and read host entropy otherwise.
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            devices: HashMap::new(),
+            fds: BTreeSet::new(),
+            stdio_closed: [false; 3],
+            entropy: match seed {
+                Some(s) => Entropy::Seeded(s),
+                None => Entropy::Host,
+            },
+            captured_stdio: false,
+            statfs: StatfsView::default(),
+        }
+    }
+
+    /// Present fds 0-2 as redirected: terminal ioctls on them
+    /// fail with `ENOTTY` whatever the host fds are.
+    pub fn with_captured_stdio(mut self, captured: bool) -> Self {
+        self.captured_stdio = captured;
+        self
+    }
+
+    /// Present host file systems to `statfs` through `view`. The
+    /// sysroot is resolved now, so it must exist.
+    pub fn with_statfs(mut self, mut view: StatfsView) -> Self {
+        if let Some((dir, _)) = &mut view.sysroot {
+            if let Ok(real) = fs::canonicalize(&*dir) {
+                *dir = real;
+            }
+        }
+        self.statfs = view;
+        self
+    }
+
+    /// Device behind guest `fd`, if it is an emulated one.
+    pub fn device(&self, fd: u64) -> Option<Device> {
+        self.devices.get(&(fd as i32)).copied()
+    }
+
+    /// True if `fd` is open in the guest: opened by it, or
+    /// inherited stdio it has not closed. Other host fds, such
+    /// as the log or a checkpoint file, belong to the emulator.
+    pub fn owns(&self, fd: i32) -> bool {
+        self.fds.contains(&fd)
+            || usize::try_from(fd).is_ok_and(|s| s < 3 && !self.stdio_closed[s])
+    }
+
+    /// Forget `fd`, which the guest closed or replaced.
+    pub fn forget(&mut self, fd: i32) {
+        self.devices.remove(&fd);
+        self.fds.remove(&fd);
+        if let Ok(s @ 0..=2) = usize::try_from(fd) {
+            self.stdio_closed[s] = true;
+        }
+    }
+
+    /// `new` now refers to what `old` does.
+    pub fn dup(&mut self, old: i32, new: i32) {
+        match self.devices.get(&old).copied() {
+            Some(dev) => self.devices.insert(new, dev),
+            None => self.devices.remove(&new),
+        };
+        self.fds.insert(new);
+    }
+
+    /// Count the fd a successful call returned as the guest's.
+    pub fn opened(&mut self, result: SyscallResult) -> SyscallResult {
+        if let SyscallResult::Continue(ret) = result {
+            if (ret as i64) >= 0 {
+                self.fds.insert(ret as i32);
+            }
+        }
+        result
+    }
+
+    /// Fds the guest has open, in order.
+    pub fn fds(&self) -> impl Iterator<Item = i32> + '_ {
+        self.fds.iter().copied()
+    }
+
+    /// State of the seeded random stream, if the run is
+    /// deterministic.
+    pub(crate) fn entropy_state(&self) -> Option<u64> {
+        match self.entropy {
+            Entropy::Host => None,
+            Entropy::Seeded(state) => Some(state),
+        }
+    }
+
+    /// Take over the saved random stream and guest fds, as the
+    /// checkpoint left them.
+    pub(crate) fn restore(
+        &mut self,
+        entropy: Option<u64>,
+        fds: impl IntoIterator<Item = (i32, Option<Device>)>,
+    ) {
+        self.entropy = match entropy {
+            Some(state) => Entropy::Seeded(state),
+            None => Entropy::Host,
+        };
+        for (fd, dev) in fds {
+            self.fds.insert(fd);
+            if let Some(dev) = dev {
+                self.devices.insert(fd, dev);
+            }
+        }
+    }
+
+    // -----------------------------------------------------------
+    // openat / read / write
+    // -----------------------------------------------------------
+
+    pub fn openat(
+        &mut self,
+        space: &GuestSpace,
+        policy: &SyscallPolicy,
+        dirfd: u64,
+        path_addr: u64,
+        flags: u64,
+        mode: u64,
+    ) -> SyscallResult {
+        let Some(path) = read_cstr(space, path_addr) else {
+            return err(libc::EFAULT);
+        };
+        let Some(dev) = Device::from_path(&path) else {
+            let Ok(cpath) = std::ffi::CString::new(path) else {
+                return err(libc::EINVAL);
+            };
+            let fd = unsafe {
+                libc::openat(
+                    dirfd as i32,
+                    cpath.as_ptr(),
+                    flags as i32,
+                    mode as libc::c_uint,
+                )
+            };
+            return self.opened(host_ret(fd as i64));
+        };
+        if dev.is_random() && policy.deny_random {
+            return err(libc::EACCES);
+        }
+        let mfd_flags = if flags & O_CLOEXEC != 0 {
+            libc::MFD_CLOEXEC
+        } else {
+            0
+        };
+        let fd = unsafe { libc::memfd_create(c"tcg-dev".as_ptr(), mfd_flags) };
+        if fd < 0 {
+            return SyscallResult::Continue(errno_ret());
+        }
+        self.devices.insert(fd, dev);
+        self.fds.insert(fd);
+        SyscallResult::Continue(fd as u64)
+    }
+
+    /// `read` on device `dev`.
+    pub fn read(
+        &mut self,
+        space: &GuestSpace,
+        dev: Device,
+        buf: u64,
+        len: u64,
+    ) -> SyscallResult {
+        if dev == Device::Null {
+            return SyscallResult::Continue(0);
+        }
+        let host = match guest_buf(space, buf, len as usize, libc::PROT_WRITE) {
+            Ok(p) => p,
+            Err(e) => return err(e),
+        };
+        let out = unsafe { std::slice::from_raw_parts_mut(host, len as usize) };
+        match dev {
+            Device::Zero => out.fill(0),
+            _ => self.entropy.fill(out),
+        }
+        SyscallResult::Continue(len)
+    }
+
+    /// `write` on a device: every device swallows the data.
+    pub fn write(
+        &self,
+        space: &GuestSpace,
+        buf: u64,
+        len: u64,
+    ) -> SyscallResult {
+        match guest_buf(space, buf, len as usize, libc::PROT_READ) {
+            Ok(_) => SyscallResult::Continue(len),
+            Err(e) => err(e),
+        }
+    }
+
+    // -----------------------------------------------------------
+    // fstat
+    // -----------------------------------------------------------
+
+    /// Fill a guest `struct stat` for device `dev`.
+    pub fn fstat(
+        &self,
+        space: &GuestSpace,
+        dev: Device,
+        buf: u64,
+    ) -> SyscallResult {
+        let mut st = [0u8; 128];
+        st[16..20].copy_from_slice(&0o020666u32.to_le_bytes());
+        st[20..24].copy_from_slice(&1u32.to_le_bytes());
+        st[32..40].copy_from_slice(&dev.rdev().to_le_bytes());
+        st[56..60].copy_from_slice(&4096u32.to_le_bytes());
+        match copy_to_guest(space, buf, &st) {
+            Ok(()) => SyscallResult::Continue(0),
+            Err(e) => err(e),
+        }
+    }
+
+    // -----------------------------------------------------------
+    // statfs / fstatfs
+    // -----------------------------------------------------------
+
+    pub fn statfs(
+        &self,
+        space: &GuestSpace,
+        path_addr: u64,
+        buf: u64,
+    ) -> SyscallResult {
+        let Some(path) = read_cstr(space, path_addr) else {
+            return err(libc::EFAULT);
+        };
+        let st = match pseudo_fs(&path) {
+            Some(st) => st,
+            None => {
+                let Ok(cpath) = std::ffi::CString::new(path.clone()) else {
+                    return err(libc::EINVAL);
+                };
+                let mut host: libc::statfs = unsafe { std::mem::zeroed() };
+                if unsafe { libc::statfs(cpath.as_ptr(), &mut host) } < 0 {
+                    return SyscallResult::Continue(errno_ret());
+                }
+                let host_path = Path::new(OsStr::from_bytes(&path));
+                self.view(GuestStatfs::from_host(&host), host_path)
+            }
+        };
+        write_statfs(space, buf, &st)
+    }
+
+    pub fn fstatfs(
+        &self,
+        space: &GuestSpace,
+        fd: u64,
+        buf: u64,
+    ) -> SyscallResult {
+        if self.device(fd).is_some() {
+            return write_statfs(space, buf, &devtmpfs());
+        }
+        let mut host: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatfs(fd as i32, &mut host) } < 0 {
+            return SyscallResult::Continue(errno_ret());
+        }
+        let link = PathBuf::from(format!("/proc/self/fd/{}", fd as i32));
+        let st = self.view(GuestStatfs::from_host(&host), &link);
+        write_statfs(space, buf, &st)
+    }
+
+    /// Apply the sysroot `f_type` and the disk cap to `st`, the
+    /// file system of host `path`.
+    fn view(&self, mut st: GuestStatfs, path: &Path) -> GuestStatfs {
+        if let Some((root, f_type)) = &self.statfs.sysroot {
+            if fs::canonicalize(path).is_ok_and(|p| p.starts_with(root)) {
+                st.f_type = *f_type;
+            }
+        }
+        if let Some(cap) = self.statfs.disk_cap {
+            st.cap(cap);
+        }
+        st
+    }
+
+    // -----------------------------------------------------------
+    // ioctl
+    // -----------------------------------------------------------
+
+    /// Whether guest `fd` is a terminal on the host.
+    pub fn is_host_tty(&self, fd: u64) -> bool {
+        self.device(fd).is_none()
+            && !(self.captured_stdio && fd <= 2)
+            && unsafe { libc::isatty(fd as i32) } == 1
+    }
+
+    pub fn ioctl(
+        &self,
+        space: &GuestSpace,
+        fd: u64,
+        req: u64,
+        arg: u64,
+    ) -> SyscallResult {
+        if self.device(fd).is_some() || (self.captured_stdio && fd <= 2) {
+            return err(libc::ENOTTY);
+        }
+        let fd = fd as i32;
+        match req {
+            TCGETS => {
+                let mut t: libc::termios = unsafe { std::mem::zeroed() };
+                if unsafe { libc::tcgetattr(fd, &mut t) } < 0 {
+                    return SyscallResult::Continue(errno_ret());
+                }
+                match copy_to_guest(space, arg, &termios_to_target(&t)) {
+                    Ok(()) => SyscallResult::Continue(0),
+                    Err(e) => err(e),
+                }
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let mut raw = [0u8; TARGET_TERMIOS_SIZE];
+                if let Err(e) = copy_from_guest(space, arg, &mut raw) {
+                    return err(e);
+                }
+                let mut t: libc::termios = unsafe { std::mem::zeroed() };
+                if unsafe { libc::tcgetattr(fd, &mut t) } < 0 {
+                    return SyscallResult::Continue(errno_ret());
+                }
+                termios_from_target(&raw, &mut t);
+                let when = match req {
+                    TCSETS => libc::TCSANOW,
+                    TCSETSW => libc::TCSADRAIN,
+                    _ => libc::TCSAFLUSH,
+                };
+                host_ret(unsafe { libc::tcsetattr(fd, when, &t) } as i64)
+            }
+            TIOCGWINSZ => {
+                let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+                if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) } < 0 {
+                    return SyscallResult::Continue(errno_ret());
+                }
+                let mut out = [0u8; 8];
+                for (i, v) in [ws.ws_row, ws.ws_col, ws.ws_xpixel, ws.ws_ypixel]
+                    .into_iter()
+                    .enumerate()
+                {
+                    out[i * 2..i * 2 + 2].copy_from_slice(&v.to_le_bytes());
+                }
+                match copy_to_guest(space, arg, &out) {
+                    Ok(()) => SyscallResult::Continue(0),
+                    Err(e) => err(e),
+                }
+            }
+            _ => err(libc::ENOTTY),
+        }
+    }
+}
+
+/// Host termios → guest `struct termios` bytes.
+pub fn termios_to_target(t: &libc::termios) -> [u8; TARGET_TERMIOS_SIZE] {
+    let mut out = [0u8; TARGET_TERMIOS_SIZE];
+    for (i, flag) in [t.c_iflag, t.c_oflag, t.c_cflag, t.c_lflag]
+        .into_iter()
+        .enumerate()
+    {
+        out[i * 4..i * 4 + 4].copy_from_slice(&flag.to_le_bytes());
+    }
+    out[16] = t.c_line;
+    out[17..].copy_from_slice(&t.c_cc[..TARGET_NCCS]);
+    out
+}
+
+/// Guest `struct termios` bytes → host termios. Line speeds
+/// live in `c_cflag`; the host's separate speed fields are
+/// left as read from the device.
+pub fn termios_from_target(
+    raw: &[u8; TARGET_TERMIOS_SIZE],
+    t: &mut libc::termios,
+) {
+    let word = |i: usize| {
+        u32::from_le_bytes(raw[i * 4..i * 4 + 4].try_into().unwrap())
+    };
+    t.c_iflag = word(0);
+    t.c_oflag = word(1);
+    t.c_cflag = word(2);
+    t.c_lflag = word(3);
+    t.c_line = raw[16];
+    t.c_cc[..TARGET_NCCS].copy_from_slice(&raw[17..]);
+}
+
+/// `/dev`, a tmpfs on Linux, holding the emulated devices.
+fn devtmpfs() -> GuestStatfs {
+    GuestStatfs::pseudo(TMPFS_MAGIC, ST_NOSUID | ST_RELATIME)
+}
+
+/// The superblock the guest sees for `path` when it is one the
+/// emulator answers for rather than the host.
+fn pseudo_fs(path: &[u8]) -> Option<GuestStatfs> {
+    if Device::from_path(path).is_some() {
+        return Some(devtmpfs());
+    }
+    if path == b"/proc" || path.starts_with(b"/proc/") {
+        let flags = ST_NOSUID | ST_NODEV | ST_NOEXEC | ST_RELATIME;
+        return Some(GuestStatfs::pseudo(PROC_SUPER_MAGIC, flags));
+    }
+    None
+}
+
+fn write_statfs(
+    space: &GuestSpace,
+    buf: u64,
+    st: &GuestStatfs,
+) -> SyscallResult {
+    match copy_to_guest(space, buf, &st.to_bytes()) {
+        Ok(()) => SyscallResult::Continue(0),
+        Err(e) => err(e),
+    }
+}
+
+/// NUL-terminated guest string, at most a page long.
+fn read_cstr(space: &GuestSpace, addr: u64) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut b = [0u8; 1];
+    while out.len() < 4096 {
+        copy_from_guest(space, addr + out.len() as u64, &mut b).ok()?;
+        if b[0] == 0 {
+            return Some(out);
+        }
+        out.push(b[0]);
+    }
+    None
+}
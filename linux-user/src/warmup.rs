@@ -0,0 +1,67 @@
+//! Warmup hint files (`-warmup-save`, `-warmup-load`).
+//!
+//! Hints are keyed by a hash of the ELF file and of the CPU
+//! configuration; a file saved for anything else is stale and
+//! ignored. Loaded addresses outside the ELF's executable
+//! segments are dropped, since only those are mapped before the
+//! guest starts.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tcg_exec::coverage::Coverage;
+use tcg_exec::warmup::{fnv1a64, WarmupHints};
+use tcg_exec::TbStore;
+use tcg_frontend::riscv::ext::RiscvCfg;
+
+/// TBs saved per run.
+pub const WARMUP_TBS: usize = 512;
+
+/// Host code bytes pre-translation may emit.
+pub const WARMUP_BUDGET: usize = 2 * 1024 * 1024;
+
+/// Key of the ELF at `elf`.
+pub fn binary_key(elf: &Path) -> io::Result<u64> {
+    Ok(fnv1a64(&fs::read(elf)?))
+}
+
+/// Key of the CPU configuration, covering the ISA extensions.
+pub fn cfg_key(cfg: &RiscvCfg) -> u64 {
+    fnv1a64(format!("{cfg:?}").as_bytes())
+}
+
+/// Write the hottest TBs of `cov` to `path`.
+pub fn save(
+    path: &Path,
+    cov: &Coverage,
+    store: &TbStore,
+    binary: u64,
+    cfg: u64,
+) -> io::Result<()> {
+    let hints = WarmupHints::from_coverage(cov, store, binary, cfg, WARMUP_TBS);
+    let mut out = Vec::new();
+    hints.write(&mut out)?;
+    fs::write(path, out)
+}
+
+/// The `(pc, flags)` entries of the hints in `text` inside
+/// `exec_ranges`, or why the hints cannot be used.
+pub fn load(
+    text: &str,
+    binary: u64,
+    cfg: u64,
+    exec_ranges: &[(u64, u64)],
+) -> Result<Vec<(u64, u32)>, String> {
+    let hints = WarmupHints::parse(text)?;
+    if !hints.matches(binary, cfg) {
+        return Err("stale: saved for another binary or CPU".to_string());
+    }
+    Ok(hints
+        .tbs
+        .into_iter()
+        .filter(|&(pc, _)| {
+            exec_ranges.iter().any(|&(s, e)| (s..e).contains(&pc))
+        })
+        .collect())
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    // Partially implemented decoder for decode::partial.
+    let input = Path::new("fixtures/partial.decode");
+    let impl_src = Path::new("src/decode/partial.rs");
+    println!("cargo::rerun-if-changed={}", input.display());
+    println!("cargo::rerun-if-changed={}", impl_src.display());
+    let source = input.display().to_string();
+    let input = fs::read_to_string(input).expect("read partial.decode");
+    let impl_src = fs::read_to_string(impl_src).expect("read partial.rs");
+    let opts = decode::GenOptions {
+        partial: Some(decode::implemented_trans(&impl_src, "Decode")),
+        source,
+        source_table: true,
+        ..decode::GenOptions::default()
+    };
+    let mut out = Vec::new();
+    decode::generate_with_options(&input, &mut out, &opts)
+        .expect("partial.decode code generation failed");
+    fs::write(Path::new(&out_dir).join("partial_decode.rs"), out)
+        .expect("write partial_decode.rs");
+
+    // A field transformed by a function decode::funcs provides.
+    let input = Path::new("fixtures/funcs.decode");
+    println!("cargo::rerun-if-changed={}", input.display());
+    let input = fs::read_to_string(input).expect("read funcs.decode");
+    let opts = decode::GenOptions {
+        extern_funcs: ["ex_times_4".to_string()].into(),
+        ..decode::GenOptions::default()
+    };
+    let mut out = Vec::new();
+    decode::generate_with_options(&input, &mut out, &opts)
+        .expect("funcs.decode code generation failed");
+    fs::write(Path::new(&out_dir).join("funcs_decode.rs"), out)
+        .expect("write funcs_decode.rs");
+
+    // Patterns sharing a trans_ method, for decode::alias.
+    let input = Path::new("fixtures/alias.decode");
+    println!("cargo::rerun-if-changed={}", input.display());
+    let input = fs::read_to_string(input).expect("read alias.decode");
+    let opts = decode::GenOptions {
+        coverage: true,
+        ..decode::GenOptions::default()
+    };
+    let mut out = Vec::new();
+    decode::generate_with_options(&input, &mut out, &opts)
+        .expect("alias.decode code generation failed");
+    fs::write(Path::new(&out_dir).join("alias_decode.rs"), out)
+        .expect("write alias_decode.rs");
+
+    // The RISC-V decoder with no trans_ implemented, plus the
+    // Insn enum, for decode::tree.
+    let input = Path::new("../frontend/src/riscv/insn32.decode");
+    println!("cargo::rerun-if-changed={}", input.display());
+    let input = fs::read_to_string(input).expect("read insn32.decode");
+    let opts = decode::GenOptions {
+        coverage: true,
+        partial: Some(Default::default()),
+        mixed_width: true,
+        insn_enum: true,
+        ..decode::GenOptions::default()
+    };
+    let mut out = Vec::new();
+    decode::generate_with_options(&input, &mut out, &opts)
+        .expect("insn32.decode code generation failed");
+    fs::write(Path::new(&out_dir).join("riscv32_tree.rs"), out)
+        .expect("write riscv32_tree.rs");
+}
@@ -0,0 +1,45 @@
+// Probe emulated /dev devices and stdout's tty state.
+
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+static int check_zero(void) {
+    int fd = open("/dev/zero", O_RDWR);
+    if (fd < 0) {
+        return 1;
+    }
+    unsigned char *p =
+        mmap(NULL, 8192, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    close(fd);
+    if (p == MAP_FAILED) {
+        return 2;
+    }
+    for (int i = 0; i < 8192; ++i) {
+        if (p[i] != 0) {
+            return 3;
+        }
+    }
+    memset(p, 0x5a, 8192);
+    return p[8191] == 0x5a ? 0 : 4;
+}
+
+int main(void) {
+    unsigned char buf[16];
+    int fd = open("/dev/urandom", O_RDONLY);
+    if (fd < 0 || read(fd, buf, sizeof(buf)) != sizeof(buf)) {
+        return 1;
+    }
+    close(fd);
+    printf("urandom=");
+    for (int i = 0; i < 16; ++i) {
+        printf("%02x", buf[i]);
+    }
+    printf("\nzero=%d\n", check_zero());
+    int null = open("/dev/null", O_WRONLY);
+    printf("null=%zd\n", write(null, buf, sizeof(buf)));
+    printf("tty=%d\n", isatty(STDOUT_FILENO));
+    return 0;
+}
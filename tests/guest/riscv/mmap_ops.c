@@ -0,0 +1,93 @@
+// Exercise mremap, madvise and msync through the linux-user layer.
+
+#define _GNU_SOURCE
+#include <errno.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+static int check_mremap(void) {
+    size_t len = 4096;
+    unsigned char *p = mmap(NULL, len, PROT_READ | PROT_WRITE,
+                            MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED) {
+        return 1;
+    }
+    memset(p, 0x5a, len);
+    for (int i = 0; i < 4; ++i) {
+        size_t new_len = len * 2;
+        unsigned char *q = mremap(p, len, new_len, MREMAP_MAYMOVE);
+        if (q == MAP_FAILED) {
+            return 2;
+        }
+        for (size_t j = 0; j < len; ++j) {
+            if (q[j] != 0x5a) {
+                return 3;
+            }
+        }
+        memset(q + len, 0x5a, new_len - len);
+        p = q;
+        len = new_len;
+    }
+    if (msync(p, len, MS_SYNC) != 0) {
+        return 4;
+    }
+    return 0;
+}
+
+typedef long (*fn_t)(void);
+
+static int check_madvise_code(void) {
+    // li a0, imm; ret
+    uint32_t code1[2] = {0x00100513, 0x00008067};
+    uint32_t code2[2] = {0x00200513, 0x00008067};
+    void *p = mmap(NULL, 4096, PROT_READ | PROT_WRITE | PROT_EXEC,
+                   MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED) {
+        return 1;
+    }
+    memcpy(p, code1, sizeof(code1));
+    __builtin___clear_cache(p, (char *)p + sizeof(code1));
+    if (((fn_t)p)() != 1) {
+        return 2;
+    }
+    if (madvise(p, 4096, MADV_DONTNEED) != 0) {
+        return 3;
+    }
+    if (*(volatile uint32_t *)p != 0) {
+        return 4;
+    }
+    memcpy(p, code2, sizeof(code2));
+    __builtin___clear_cache(p, (char *)p + sizeof(code2));
+    if (((fn_t)p)() != 2) {
+        return 5;
+    }
+    return 0;
+}
+
+static int check_einval(void) {
+    char *p = mmap(NULL, 8192, PROT_READ | PROT_WRITE,
+                   MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED) {
+        return 1;
+    }
+    if (madvise(p + 1, 4096, MADV_DONTNEED) == 0 || errno != EINVAL) {
+        return 2;
+    }
+    if (mremap(p + 1, 4096, 8192, 0) != MAP_FAILED || errno != EINVAL) {
+        return 3;
+    }
+    if (msync(p + 1, 4096, MS_SYNC) == 0 || errno != EINVAL) {
+        return 4;
+    }
+    return 0;
+}
+
+int main(void) {
+    printf("mremap=%d\n", check_mremap());
+    printf("madvise=%d\n", check_madvise_code());
+    printf("einval=%d\n", check_einval());
+    return 0;
+}
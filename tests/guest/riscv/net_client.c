@@ -0,0 +1,113 @@
+// Talk to host-side TCP and UDP servers through the socket syscalls.
+//
+// usage: net_client <tcp_port> <udp_port> <refused_port>
+
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netinet/in.h>
+#include <netinet/tcp.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <unistd.h>
+
+static struct sockaddr_in loopback(int port) {
+    struct sockaddr_in sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sin_family = AF_INET;
+    sa.sin_port = htons(port);
+    sa.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    return sa;
+}
+
+static int tcp_exchange(int port) {
+    int fd = socket(AF_INET, SOCK_STREAM | SOCK_CLOEXEC, 0);
+    if (fd < 0) {
+        return 1;
+    }
+    int one = 1;
+    if (setsockopt(fd, IPPROTO_TCP, TCP_NODELAY, &one, sizeof(one)) != 0) {
+        return 2;
+    }
+    struct timeval tv = {5, 0};
+    if (setsockopt(fd, SOL_SOCKET, SO_RCVTIMEO, &tv, sizeof(tv)) != 0) {
+        return 3;
+    }
+    struct sockaddr_in sa = loopback(port);
+    if (connect(fd, (struct sockaddr *)&sa, sizeof(sa)) != 0) {
+        return 4;
+    }
+    int soerr = -1;
+    socklen_t len = sizeof(soerr);
+    if (getsockopt(fd, SOL_SOCKET, SO_ERROR, &soerr, &len) != 0 || soerr) {
+        return 5;
+    }
+    if (send(fd, "ping!", 5, 0) != 5) {
+        return 6;
+    }
+    char buf[16];
+    size_t got = 0;
+    while (got < 5) {
+        ssize_t n = recv(fd, buf + got, sizeof(buf) - got, 0);
+        if (n <= 0) {
+            return 7;
+        }
+        got += n;
+    }
+    buf[got] = 0;
+    printf("tcp=%s\n", buf);
+    shutdown(fd, SHUT_RDWR);
+    close(fd);
+    return 0;
+}
+
+static int udp_echo(int port) {
+    int fd = socket(AF_INET, SOCK_DGRAM, 0);
+    if (fd < 0) {
+        return 1;
+    }
+    struct sockaddr_in sa = loopback(port);
+    if (sendto(fd, "datagram", 8, 0, (struct sockaddr *)&sa, sizeof(sa)) !=
+        8) {
+        return 2;
+    }
+    char buf[16];
+    struct sockaddr_in from;
+    socklen_t len = sizeof(from);
+    ssize_t n =
+        recvfrom(fd, buf, sizeof(buf) - 1, 0, (struct sockaddr *)&from, &len);
+    if (n != 8 || from.sin_port != sa.sin_port) {
+        return 3;
+    }
+    buf[n] = 0;
+    printf("udp=%s\n", buf);
+    close(fd);
+    return 0;
+}
+
+static int refused(int port) {
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    struct sockaddr_in sa = loopback(port);
+    int r = connect(fd, (struct sockaddr *)&sa, sizeof(sa));
+    printf("refused=%s\n",
+           r < 0 && errno == ECONNREFUSED ? "ECONNREFUSED" : "unexpected");
+    close(fd);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        return 100;
+    }
+    int r = tcp_exchange(atoi(argv[1]));
+    if (r) {
+        return 10 + r;
+    }
+    r = udp_echo(atoi(argv[2]));
+    if (r) {
+        return 20 + r;
+    }
+    return refused(atoi(argv[3]));
+}
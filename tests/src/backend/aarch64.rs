@@ -0,0 +1,461 @@
+use tcg_backend::aarch64::emitter::*;
+use tcg_backend::aarch64::regs::*;
+use tcg_backend::aarch64::Aarch64CodeGen;
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::{translate, TranslateError};
+use tcg_backend::HostCodeGen;
+use tcg_core::temp::TempKind;
+use tcg_core::{Cond, Context, Opcode, RelocKind, TempIdx, Type};
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
+use tcg_frontend::translator_loop;
+
+fn words(buf: &CodeBuffer, start: usize, end: usize) -> Vec<u32> {
+    (start..end).step_by(4).map(|o| buf.read_u32(o)).collect()
+}
+
+fn emitted(f: impl FnOnce(&mut CodeBuffer)) -> Vec<u32> {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    f(&mut buf);
+    words(&buf, 0, buf.offset())
+}
+
+// -- regs tests --
+
+#[test]
+fn areg0_is_callee_saved() {
+    assert_eq!(TCG_AREG0, Reg::X19);
+    assert!(CALLEE_SAVED.contains(&TCG_AREG0));
+    assert!(CALLEE_SAVED.contains(&TCG_GUEST_BASE_REG));
+}
+
+#[test]
+fn reserved_and_allocatable_disjoint() {
+    for r in [TCG_AREG0, TCG_GUEST_BASE_REG, TCG_REG_TMP0, TCG_REG_TMP1] {
+        assert!(RESERVED_REGS.contains(r as u8));
+        assert!(!ALLOCATABLE_REGS.contains(r as u8));
+    }
+    // x18 is the platform register.
+    assert!(!ALLOCATABLE_REGS.contains(18));
+    assert!(ALLOCATABLE_REGS.contains(Reg::X0 as u8));
+    assert!(ALLOCATABLE_REGS.contains(Reg::X27 as u8));
+}
+
+#[test]
+fn call_clobbered_are_caller_saved() {
+    for &r in CALLEE_SAVED {
+        assert!(!CALL_CLOBBERED.contains(r as u8));
+    }
+    for &r in CALL_ARG_REGS {
+        assert!(CALL_CLOBBERED.contains(r as u8));
+    }
+}
+
+#[test]
+fn frame_size_aligned() {
+    assert_eq!(FRAME_SIZE % STACK_ALIGN, 0);
+    assert_eq!(PUSH_SIZE + STACK_ADDEND, FRAME_SIZE);
+    // `sub sp, sp, #STACK_ADDEND` takes a 12-bit immediate.
+    const { assert!(STACK_ADDEND < 4096) };
+    const { assert!(HELPER_PANIC_SLOT < STACK_ADDEND) };
+}
+
+// -- emitter tests --
+
+#[test]
+fn mov_ri_picks_movz_or_movn() {
+    let mov = |is64, rd, val| emitted(|b| emit_mov_ri(b, is64, rd, val));
+    assert_eq!(mov(true, Reg::X0, 0), [0xd2800000]);
+    assert_eq!(mov(true, Reg::X0, u64::MAX), [0x92800000]);
+    assert_eq!(mov(false, Reg::X0, 0xffff_fffe), [0x12800020]);
+    // movn x3, #0xedcb
+    assert_eq!(mov(true, Reg::X3, 0xffff_ffff_ffff_1234), [0x929db963]);
+    // movz x5, #0x5678; movk x5, #0x1234, lsl #16
+    assert_eq!(mov(true, Reg::X5, 0x1234_5678), [0xd28acf05, 0xf2a24685]);
+    // movz w7, #0xbeef, lsl #16
+    assert_eq!(mov(false, Reg::X7, 0xbeef_0000), [0x52b7dde7]);
+}
+
+#[test]
+fn ldst_offset_forms() {
+    // ldr x1, [x19, #8]
+    let w = emitted(|b| emit_ldst(b, LdSt::LdrX, Reg::X1, Reg::X19, 8));
+    assert_eq!(w, [0xf9400661]);
+    // ldur x1, [x19, #-8]
+    let w = emitted(|b| emit_ldst(b, LdSt::LdrX, Reg::X1, Reg::X19, -8));
+    assert_eq!(w, [0xf85f8261]);
+    // ldurh w2, [x3, #3]
+    let w = emitted(|b| emit_ldst(b, LdSt::Ldrh, Reg::X2, Reg::X3, 3));
+    assert_eq!(w, [0x78403062]);
+    // ldrsb x4, [sp, #7]
+    let w = emitted(|b| emit_ldst(b, LdSt::LdrsbX, Reg::X4, Reg::Sp, 7));
+    assert_eq!(w, [0x39801fe4]);
+    // strb w4, [x20, #4095]
+    let w = emitted(|b| emit_ldst(b, LdSt::Strb, Reg::X4, Reg::X20, 4095));
+    assert_eq!(w, [0x393ffe84]);
+    // mov x16, #0x8001; ldr x1, [x19, x16]
+    let w = emitted(|b| emit_ldst(b, LdSt::LdrX, Reg::X1, Reg::X19, 0x8001));
+    assert_eq!(w, [0xd2900030, 0xf8706a61]);
+}
+
+#[test]
+fn branch_and_cond_encodings() {
+    let w = emitted(|b| {
+        b.emit_u32(OPC_NOP);
+        b.emit_u32(OPC_NOP);
+        // b.lt #-8
+        emit_bcond(b, A64Cond::from_tcg(Cond::Lt), 0);
+        // cbz x16, #8
+        emit_cbz(b, Reg::X16, 20);
+        // b #128
+        emit_b(b, 16 + 128);
+        emit_cset(b, false, Reg::X3, A64Cond::Eq);
+        emit_csetm(b, true, Reg::X3, A64Cond::from_tcg(Cond::Gtu));
+        emit_dmb(b);
+    });
+    assert_eq!(
+        &w[2..],
+        [
+            0x54ffffcb, 0xb4000050, 0x14000020, 0x1a9f17e3, 0xda9f93e3,
+            0xd5033bbf,
+        ]
+    );
+}
+
+#[test]
+fn mul_high_and_ctpop_encodings() {
+    let w = emitted(|b| {
+        emit_rrr(b, OPC_SMULH, true, Reg::X16, Reg::X0, Reg::X1);
+        emit_rrr(b, OPC_UMULH, true, Reg::X16, Reg::X0, Reg::X1);
+        emit_rrr(b, OPC_SMULL, true, Reg::X16, Reg::X0, Reg::X1);
+        emit_rrr(b, OPC_UMULL, true, Reg::X16, Reg::X0, Reg::X1);
+        emit_ctpop(b, true, Reg::X0, Reg::X1);
+        emit_ctpop(b, false, Reg::X2, Reg::X3);
+    });
+    assert_eq!(
+        w,
+        [
+            0x9b417c10, 0x9bc17c10, 0x9b217c10, 0x9ba17c10, 0x9e67003f,
+            0x0e205bff, 0x0e31bbff, 0x1e2603e0, 0x1e27007f, 0x0e205bff,
+            0x0e31bbff, 0x1e2603e2,
+        ]
+    );
+}
+
+#[test]
+fn cond_invert_pairs() {
+    for c in [Cond::Eq, Cond::Lt, Cond::Geu, Cond::Gtu, Cond::Le] {
+        let a = A64Cond::from_tcg(c);
+        assert_eq!(a.invert(), A64Cond::from_tcg(c.invert()));
+    }
+}
+
+// -- codegen tests --
+
+fn gen_prologue_epilogue() -> (CodeBuffer, Aarch64CodeGen) {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let mut gen = Aarch64CodeGen::new();
+    gen.emit_prologue(&mut buf);
+    gen.emit_epilogue(&mut buf);
+    (buf, gen)
+}
+
+#[test]
+fn prologue_saves_frame_and_enters_tb() {
+    let (buf, gen) = gen_prologue_epilogue();
+    let w = words(&buf, 0, gen.code_gen_start);
+    assert_eq!(
+        w,
+        [
+            0xa9ba7bfd, // stp x29, x30, [sp, #-96]!
+            0x910003fd, // mov x29, sp
+            0xa90153f3, // stp x19, x20, [sp, #16]
+            0xa9025bf5, // stp x21, x22, [sp, #32]
+            0xa90363f7, // stp x23, x24, [sp, #48]
+            0xa9046bf9, // stp x25, x26, [sp, #64]
+            0xa90573fb, // stp x27, x28, [sp, #80]
+            0xaa0003f3, // mov x19, x0
+            0xf941067c, // ldr x28, [x19, #520]
+            0xd11243ff, // sub sp, sp, #1168
+            0xf90243e2, // str x2, [sp, #1152]
+            0xd61f0020, // br x1
+        ]
+    );
+}
+
+#[test]
+fn epilogue_restores_frame() {
+    let (buf, gen) = gen_prologue_epilogue();
+    assert_eq!(buf.read_u32(gen.epilogue_return_zero_offset), 0x52800000);
+    assert_eq!(gen.tb_ret_offset, gen.epilogue_return_zero_offset + 4);
+    assert_eq!(gen.epilogue_offset(), gen.tb_ret_offset);
+    let w = words(&buf, gen.tb_ret_offset, buf.offset());
+    assert_eq!(
+        w,
+        [
+            0x911243ff, // add sp, sp, #1168
+            0xa94153f3, // ldp x19, x20, [sp, #16]
+            0xa9425bf5, // ldp x21, x22, [sp, #32]
+            0xa94363f7, // ldp x23, x24, [sp, #48]
+            0xa9446bf9, // ldp x25, x26, [sp, #64]
+            0xa94573fb, // ldp x27, x28, [sp, #80]
+            0xa8c67bfd, // ldp x29, x30, [sp], #96
+            0xd65f03c0, // ret
+        ]
+    );
+}
+
+#[test]
+fn helper_panic_stub_branches_to_tb_ret() {
+    let (buf, gen) = gen_prologue_epilogue();
+    let b = gen.epilogue_return_zero_offset - 4;
+    let disp = (gen.tb_ret_offset - b) as u32 >> 2;
+    assert_eq!(buf.read_u32(b), OPC_B | disp);
+}
+
+#[test]
+fn goto_tb_patch_near_and_reset() {
+    let (mut buf, gen) = gen_prologue_epilogue();
+    buf.emit_u32(OPC_NOP);
+    let (jmp, reset) = gen.emit_goto_tb(&mut buf);
+    assert_eq!(jmp % 8, 0);
+    assert_eq!(reset, jmp + 16);
+    assert_eq!(gen.jump_target(&buf, jmp), reset);
+
+    gen.patch_jump(&buf, jmp, gen.code_gen_start);
+    assert_eq!(buf.read_u32(jmp) & 0xfc00_0000, OPC_B);
+    assert_eq!(gen.jump_target(&buf, jmp), gen.code_gen_start);
+
+    gen.patch_jump(&buf, jmp, reset);
+    assert_eq!(buf.read_u32(jmp), OPC_B | 4);
+}
+
+#[test]
+fn goto_tb_patch_far_uses_literal() {
+    // Past the +-128 MiB reach of B.
+    let far = 130 << 20;
+    let mut buf = CodeBuffer::new(far + 4096).unwrap();
+    let gen = Aarch64CodeGen::new();
+    let (jmp, _) = gen.emit_goto_tb(&mut buf);
+    buf.set_offset(far);
+    buf.emit_u32(OPC_RET);
+
+    gen.patch_jump(&buf, jmp, far);
+    // ldr x16, #8; br x16
+    assert_eq!(buf.read_u32(jmp), 0x58000050);
+    assert_eq!(buf.read_u32(jmp + 4), 0xd61f0200);
+    assert_eq!(gen.jump_target(&buf, jmp), far);
+}
+
+#[test]
+fn movi_large_values_share_pool_slot() {
+    let gen = Aarch64CodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let val = 0x1234_5678_9abc_def0;
+    gen.tcg_out_movi(&mut buf, Type::I64, Reg::X3 as u8, val);
+    gen.tcg_out_movi(&mut buf, Type::I64, Reg::X4 as u8, val);
+    // Two halfwords: still MOVZ/MOVK, no pool.
+    gen.tcg_out_movi(&mut buf, Type::I64, Reg::X5 as u8, 0x1_0000_0001);
+    assert_eq!(buf.pool_size(), 8);
+    buf.emit_pool();
+
+    let slot = |at: usize| {
+        let insn = buf.read_u32(at);
+        assert_eq!(insn & 0xff00_0000, OPC_LDR_LIT);
+        at + ((insn >> 5) & 0x7_ffff) as usize * 4
+    };
+    assert_eq!(buf.read_u32(0) & 0x1f, 3);
+    assert_eq!(buf.read_u32(4) & 0x1f, 4);
+    assert_eq!(slot(0), slot(4));
+    assert_eq!(buf.read_u64(slot(0)), val);
+    assert_eq!(mov_ri_len(true, 0x1_0000_0001), 2);
+    assert_eq!(buf.read_u32(8), 0xd2800025, "movz x5, #1");
+}
+
+#[test]
+fn branch_reloc_per_opcode() {
+    let gen = Aarch64CodeGen::new();
+    assert_eq!(gen.branch_reloc(Opcode::Br), RelocKind::Imm26);
+    assert_eq!(gen.branch_reloc(Opcode::BrCond), RelocKind::Imm19);
+}
+
+#[test]
+fn riscv_env_pinned_to_areg0() {
+    // addi x1, x0, 1
+    let code = 0x0010_0093u32.to_le_bytes();
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    assert_eq!(ctx.env_reg, TCG_AREG0 as u8);
+    let mut disas =
+        RiscvDisasContext::new(0, code.as_ptr(), RiscvCfg::default());
+    disas.base.max_insns = 1;
+    translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
+    let env = ctx.temp(TempIdx(0));
+    assert_eq!(env.kind, TempKind::Fixed);
+    assert_eq!(env.reg, Some(TCG_AREG0 as u8));
+    translate(&mut ctx, &backend, &mut buf).unwrap();
+}
+
+/// Add/subtract-with-carry class of `w`, ignoring sf and
+/// registers.
+fn carry_class(w: u32) -> Option<u32> {
+    let opc = w & 0x7fe0_fc00;
+    [OPC_ADDS, OPC_ADC, OPC_ADCS, OPC_SUBS, OPC_SBC, OPC_SBCS]
+        .contains(&opc)
+        .then_some(opc)
+}
+
+#[test]
+fn translate_carry_chain_uses_flags() {
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, TCG_AREG0 as u8, "env");
+    let x: Vec<_> = (1..5)
+        .map(|i| ctx.new_global(Type::I64, env, 8 * i, "x"))
+        .collect();
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_addco(Type::I64, x[0], x[0], x[1]);
+    ctx.gen_addci(Type::I64, x[2], x[2], x[3]);
+    ctx.gen_subbo(Type::I64, x[0], x[0], x[1]);
+    ctx.gen_subbi(Type::I64, x[2], x[2], x[3]);
+    ctx.gen_addc1o(Type::I64, x[0], x[0], x[1]);
+    ctx.gen_addcio(Type::I64, x[2], x[2], x[3]);
+    ctx.gen_subb1o(Type::I64, x[0], x[0], x[1]);
+    ctx.gen_subbio(Type::I64, x[2], x[2], x[3]);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+    let w = words(&buf, start, buf.offset());
+    let classes: Vec<_> = w.iter().filter_map(|&w| carry_class(w)).collect();
+    assert_eq!(
+        classes,
+        [
+            OPC_ADDS, OPC_ADC, OPC_SUBS, OPC_SBC, OPC_SUBS, OPC_ADCS, OPC_ADCS,
+            OPC_ADDS, OPC_SBCS, OPC_SBCS,
+        ]
+    );
+    // addc1o sets C with cmp wzr, wzr; subb1o clears it (a
+    // borrow) with cmn wzr, wzr.
+    assert!(w.contains(&0x6b1f03ff));
+    assert!(w.contains(&0x2b1f03ff));
+}
+
+#[test]
+fn translate_mul2_and_ctpop() {
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, TCG_AREG0 as u8, "env");
+    let x: Vec<_> = (1..5)
+        .map(|i| ctx.new_global(Type::I64, env, 8 * i, "x"))
+        .collect();
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_muls2(Type::I64, x[0], x[1], x[2], x[3]);
+    ctx.gen_mulu2(Type::I64, x[0], x[1], x[2], x[3]);
+    ctx.gen_mulsu2(Type::I64, x[0], x[1], x[2], x[3]);
+    ctx.gen_ctpop(Type::I64, x[2], x[3]);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+    let w = words(&buf, start, buf.offset());
+    let count =
+        |mask: u32, opc: u32| w.iter().filter(|&&w| w & mask == opc).count();
+    assert_eq!(count(0xffe0_fc00, OPC_SMULH), 1);
+    assert_eq!(count(0xffe0_fc00, OPC_UMULH), 2);
+    assert_eq!(count(0xffff_fc00, OPC_CNT_8B), 1);
+    // mulsu2 fixes up the high half with b when a < 0.
+    let csel_lt = OPC_CSEL | SF | (A64Cond::Lt as u32) << 12;
+    assert_eq!(count(0xffe0_fc00, csel_lt), 1);
+}
+
+#[test]
+fn div2_is_refused() {
+    let gen = Aarch64CodeGen::new();
+    assert!(!gen.op_supported(Opcode::DivS2));
+    assert!(!gen.op_supported(Opcode::DivU2));
+    assert!(gen.op_supported(Opcode::MulS2));
+    assert!(gen.op_supported(Opcode::AddCIO));
+
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, TCG_AREG0 as u8, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let x2 = ctx.new_global(Type::I64, env, 16, "x2");
+    let x3 = ctx.new_global(Type::I64, env, 24, "x3");
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_divs2(Type::I64, x1, x2, x1, x2, x3);
+    ctx.gen_exit_tb_raw(0);
+
+    let err = translate(&mut ctx, &backend, &mut buf).unwrap_err();
+    let TranslateError::UnsupportedOp { site } = err.clone() else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!((site.opc, site.pc), (Opcode::DivS2, Some(0x1000)));
+    assert_eq!(
+        err.to_string(),
+        "divs2 at op #1 (guest pc 0x1000): not supported by this host backend"
+    );
+}
+
+#[test]
+fn translate_patches_forward_brcond() {
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, TCG_AREG0 as u8, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let l = ctx.new_label();
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_brcond(Type::I64, x1, zero, Cond::Eq, l);
+    ctx.gen_add(Type::I64, x1, x1, x1);
+    ctx.gen_set_label(l);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+    let w = words(&buf, start, buf.offset());
+    let (i, bcond) = w
+        .iter()
+        .enumerate()
+        .find(|(_, &w)| w & 0xff00_0010 == OPC_BCOND)
+        .expect("no b.cond emitted");
+    assert_eq!(bcond & 0xf, A64Cond::Eq as u32);
+    let disp = ((bcond << 8) as i32 >> 13) as usize;
+    let target = start + 4 * (i + disp);
+    assert!(target < buf.offset());
+    let skipped = &w[i + 1..(target - start) / 4];
+    assert!(
+        skipped.iter().any(|&w| w & 0x7f20_0000 == OPC_ADD),
+        "b.cond should skip the add"
+    );
+}
+
+#[test]
+fn translate_flushes_pool_after_tb() {
+    let (mut buf, backend) = gen_prologue_epilogue();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, TCG_AREG0 as u8, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let x2 = ctx.new_global(Type::I64, env, 16, "x2");
+    let big = ctx.new_const(Type::I64, 0x0123_4567_89ab_cdef);
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, big);
+    ctx.gen_xor(Type::I64, x2, x2, big);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+    assert_eq!(buf.pool_size(), 0);
+    let end = buf.offset();
+    assert_eq!(buf.read_u64(end - 8), 0x0123_4567_89ab_cdef);
+    for at in (start..end - 8).step_by(4) {
+        let insn = buf.read_u32(at);
+        if insn & 0xff00_0000 == OPC_LDR_LIT {
+            let target = at + ((insn >> 5) & 0x7_ffff) as usize * 4;
+            assert_eq!(target, end - 8);
+        }
+    }
+}
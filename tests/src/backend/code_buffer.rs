@@ -1,4 +1,5 @@
-use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::code_buffer::{CodeBuffer, MAX_CODE_BUF_SIZE};
+use tcg_core::RelocKind;
 
 #[test]
 fn test_emit_and_read() {
@@ -24,3 +25,120 @@ fn test_permissions() {
     buf.set_executable().unwrap();
     buf.set_writable().unwrap();
 }
+
+#[test]
+fn test_grow_into_reserve() {
+    let mut buf = CodeBuffer::with_reserve(4096, 64 * 1024).unwrap();
+    let base = buf.base_ptr();
+    assert_eq!(buf.capacity(), 4096);
+    buf.emit_bytes(&[0xAA; 4096]);
+    assert_eq!(buf.remaining(), 0);
+
+    buf.try_grow_in_place(5000).unwrap();
+    assert_eq!(buf.capacity(), 8192);
+    assert_eq!(buf.base_ptr(), base);
+    buf.emit_bytes(&[0xBB; 4096]);
+    assert_eq!(buf.as_slice()[4095], 0xAA);
+    assert_eq!(buf.as_slice()[4096], 0xBB);
+
+    // Shrinking is a no-op.
+    buf.try_grow_in_place(4096).unwrap();
+    assert_eq!(buf.capacity(), 8192);
+
+    buf.try_grow_in_place(64 * 1024).unwrap();
+    assert_eq!(buf.capacity(), 64 * 1024);
+    buf.set_executable().unwrap();
+    buf.set_writable().unwrap();
+}
+
+#[test]
+fn test_grow_fails_when_blocked() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    buf.emit_u32(0x1234_5678);
+    let end = buf.ptr_at(4096) as *mut libc::c_void;
+    // Occupy the page right after the buffer.
+    let guard = unsafe {
+        libc::mmap(
+            end,
+            4096,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+            -1,
+            0,
+        )
+    };
+    let err = buf.try_grow_in_place(8192).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOMEM));
+    assert_eq!(buf.capacity(), 4096);
+    assert_eq!(buf.read_u32(0), 0x1234_5678);
+    if guard == end {
+        unsafe { libc::munmap(guard, 4096) };
+    }
+}
+
+#[test]
+fn test_grow_limit() {
+    let mut buf = CodeBuffer::with_reserve(4096, 8192).unwrap();
+    assert!(buf.try_grow_in_place(MAX_CODE_BUF_SIZE + 1).is_err());
+    assert_eq!(buf.capacity(), 4096);
+}
+
+#[test]
+fn test_intern_const_dedups() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    assert_eq!(buf.intern_const(0x1122_3344_5566_7788), 0);
+    assert_eq!(buf.intern_const(u64::MAX), 8);
+    assert_eq!(buf.intern_const(0x1122_3344_5566_7788), 0);
+    assert_eq!(buf.pool_size(), 16);
+    assert_eq!(buf.offset(), 0, "interning emits nothing");
+}
+
+#[test]
+fn test_emit_pool_patches_rel32() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    // mov rax, [rip + disp32]
+    buf.emit_bytes(&[0x48, 0x8b, 0x05]);
+    let slot = buf.intern_const(0xdead_beef_cafe_f00d);
+    buf.add_pool_reloc(buf.offset(), RelocKind::Rel32, slot);
+    buf.emit_u32(0);
+    buf.emit_pool();
+
+    // Pool starts at the next 8-byte boundary.
+    assert_eq!(buf.offset(), 16);
+    assert_eq!(buf.read_u64(8), 0xdead_beef_cafe_f00d);
+    assert_eq!(buf.read_u32(3), 8 - 7);
+    assert_eq!(buf.pool_size(), 0);
+}
+
+#[test]
+fn test_emit_pool_patches_imm19() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let a = buf.intern_const(1 << 40);
+    let b = buf.intern_const(2 << 40);
+    // ldr x3, <a>; ldr x4, <b>
+    buf.add_pool_reloc(0, RelocKind::Imm19, a);
+    buf.emit_u32(0x5800_0003);
+    buf.add_pool_reloc(4, RelocKind::Imm19, b);
+    buf.emit_u32(0x5800_0004);
+    buf.emit_pool();
+
+    assert_eq!(buf.read_u64(8), 1 << 40);
+    assert_eq!(buf.read_u64(16), 2 << 40);
+    assert_eq!(buf.read_u32(0), 0x5800_0003 | (8 / 4) << 5);
+    assert_eq!(buf.read_u32(4), 0x5800_0004 | (12 / 4) << 5);
+}
+
+#[test]
+fn test_emit_pool_empty_and_discard() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    buf.emit_u8(0xc3);
+    buf.emit_pool();
+    assert_eq!(buf.offset(), 1, "no pool, no padding");
+
+    let slot = buf.intern_const(42);
+    buf.add_pool_reloc(0, RelocKind::Rel32, slot);
+    buf.discard_pool();
+    buf.emit_pool();
+    assert_eq!(buf.offset(), 1);
+    assert_eq!(buf.intern_const(7), 0, "slots restart after discard");
+}
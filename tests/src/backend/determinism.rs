@@ -0,0 +1,213 @@
+//! Reproducibility of emitted host code.
+//!
+//! Register allocation must depend only on the IR: the same
+//! Context translated into a fresh buffer yields the same bytes,
+//! run after run. The fixture's golden hash turns a change in
+//! emitted code into a test failure; update it only in a commit
+//! that intends to change codegen.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::translate;
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::serialize;
+use tcg_core::{Cond, Context, TempIdx, Type};
+
+/// FNV-1a of the TB code emitted for `fixtures/regalloc.tcgir`.
+const GOLDEN_CODE_HASH: u64 = 0xc3e9_abae_d272_e705;
+
+/// Stand-in helper address; never called.
+const FAKE_HELPER: u64 = 0x0000_7f12_3456_7890;
+
+fn fixture_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/regalloc.tcgir")
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+const NAMES: [&str; 20] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11",
+    "x12", "x13", "x14", "x15", "x16", "x17", "x18", "x19",
+];
+
+fn new_ctx(nb_regs: usize) -> (Context, Vec<TempIdx>) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let regs = NAMES[..nb_regs]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| ctx.new_global(Type::I64, env, 8 * i as i64, name))
+        .collect();
+    (ctx, regs)
+}
+
+/// More live values than host registers, plus fixed-register
+/// ops (shift count in RCX, division in RAX:RDX) and a loop.
+fn pressure_tb() -> Context {
+    let (mut ctx, x) = new_ctx(20);
+    let head = ctx.new_label();
+    let one = ctx.new_const(Type::I64, 1);
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_insn_start(0x1000);
+    let t: Vec<TempIdx> = (0..18)
+        .map(|i| {
+            let t = ctx.new_temp(Type::I64);
+            ctx.gen_add(Type::I64, t, x[i], x[i + 1])
+        })
+        .collect();
+    ctx.gen_insn_start(0x1004);
+    ctx.gen_set_label(head);
+    let mut acc = ctx.new_temp(Type::I64);
+    ctx.gen_mul(Type::I64, acc, t[0], t[1]);
+    for (i, &ti) in t.iter().enumerate().skip(2) {
+        let d = ctx.new_temp(Type::I64);
+        acc = match i % 4 {
+            0 => ctx.gen_shl(Type::I64, d, acc, ti),
+            1 => {
+                let rem = ctx.new_temp(Type::I64);
+                ctx.gen_divu2(Type::I64, d, rem, acc, zero, ti);
+                d
+            }
+            2 => ctx.gen_xor(Type::I64, d, acc, ti),
+            _ => ctx.gen_sub(Type::I64, d, acc, ti),
+        };
+    }
+    let sel = ctx.new_temp(Type::I64);
+    ctx.gen_movcond(Type::I64, sel, acc, x[0], t[3], t[7], Cond::Ltu);
+    ctx.gen_mov(Type::I64, x[0], sel);
+    ctx.gen_sub(Type::I64, x[19], x[19], one);
+    ctx.gen_brcond(Type::I64, x[19], one, Cond::Ne, head);
+    ctx.gen_exit_tb_raw(0);
+    ctx
+}
+
+/// Guest memory access, setcond and both chaining slots.
+fn memory_tb() -> Context {
+    let (mut ctx, x) = new_ctx(4);
+    let skip = ctx.new_label();
+    ctx.gen_insn_start(0x2000);
+    let v = ctx.new_temp(Type::I64);
+    ctx.gen_qemu_ld(Type::I64, v, x[1], 3);
+    ctx.gen_insn_start(0x2004);
+    let w = ctx.new_temp(Type::I64);
+    ctx.gen_qemu_ld(Type::I64, w, x[2], 6);
+    let c = ctx.new_temp(Type::I64);
+    ctx.gen_setcond(Type::I64, c, v, w, Cond::Lt);
+    ctx.gen_add(Type::I64, x[3], x[3], c);
+    ctx.gen_insn_start(0x2008);
+    ctx.gen_qemu_st(Type::I64, x[3], x[1], 3);
+    ctx.gen_brcond(Type::I64, v, w, Cond::Eq, skip);
+    ctx.gen_goto_tb(0);
+    ctx.gen_exit_tb_raw(0);
+    ctx.gen_set_label(skip);
+    ctx.gen_goto_tb(1);
+    ctx.gen_exit_tb_raw(1);
+    ctx
+}
+
+/// A helper call with values live across it.
+fn call_tb() -> Context {
+    let (mut ctx, x) = new_ctx(8);
+    ctx.gen_insn_start(0x3000);
+    let live: Vec<TempIdx> = (0..4)
+        .map(|i| {
+            let t = ctx.new_temp(Type::I64);
+            ctx.gen_mul(Type::I64, t, x[i], x[i + 4])
+        })
+        .collect();
+    let r = ctx.new_temp(Type::I64);
+    ctx.gen_call(r, FAKE_HELPER, 0, &[x[0], live[1], x[2]]);
+    for &t in &live {
+        ctx.gen_add(Type::I64, r, r, t);
+    }
+    ctx.gen_mov(Type::I64, x[7], r);
+    ctx.gen_exit_tb_raw(0);
+    ctx
+}
+
+fn fixture_tbs() -> Vec<Context> {
+    vec![pressure_tb(), memory_tb(), call_tb()]
+}
+
+fn fixture_bytes() -> Vec<u8> {
+    let mut data = Vec::new();
+    for tb in fixture_tbs() {
+        serialize::serialize(&tb, &mut data).unwrap();
+    }
+    data
+}
+
+/// Deserialize `data` and translate each TB into a fresh
+/// buffer, returning the code of each.
+fn emit(data: &[u8]) -> Vec<Vec<u8>> {
+    let ctxs = serialize::deserialize(&mut Cursor::new(data)).unwrap();
+    ctxs.into_iter()
+        .map(|mut ctx| {
+            let mut buf = CodeBuffer::new(64 * 1024).unwrap();
+            let mut backend = X86_64CodeGen::new();
+            backend.emit_prologue(&mut buf);
+            backend.emit_epilogue(&mut buf);
+            backend.init_context(&mut ctx);
+            let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+            buf.as_slice()[start..buf.offset()].to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn same_context_same_code() {
+    let data = fixture_bytes();
+    let first = emit(&data);
+    for run in 1..5 {
+        for (i, code) in emit(&data).iter().enumerate() {
+            assert_eq!(*code, first[i], "TB #{i} differs on run {run}");
+        }
+    }
+}
+
+#[test]
+fn fixture_matches_builder() {
+    let data = fixture_bytes();
+    let path = fixture_path();
+    if std::env::var_os("TCG_BLESS").is_some() {
+        fs::write(&path, &data).unwrap();
+    }
+    let committed = fs::read(&path).expect("fixture missing");
+    assert!(
+        committed == data,
+        "{} is stale; regenerate with TCG_BLESS=1",
+        path.display()
+    );
+}
+
+/// The same TBs as written by format version 3, before record
+/// headers; they must still load and emit the same code.
+#[test]
+fn legacy_fixture_emits_same_code() {
+    let path = fixture_path().with_file_name("regalloc-v3.tcgir");
+    let legacy = fs::read(path).expect("fixture missing");
+    assert_eq!(&legacy[..4], b"TCIR");
+    assert_eq!(emit(&legacy), emit(&fixture_bytes()));
+}
+
+#[test]
+fn fixture_code_hash_is_golden() {
+    let data = fs::read(fixture_path()).expect("fixture missing");
+    let tbs = emit(&data);
+    assert_eq!(tbs.len(), 3);
+    let code: Vec<u8> = tbs.concat();
+    let hash = fnv1a(&code);
+    assert_eq!(
+        hash, GOLDEN_CODE_HASH,
+        "emitted code changed (hash {hash:#018x}); if intended, \
+         update GOLDEN_CODE_HASH in the same commit"
+    );
+}
@@ -0,0 +1,282 @@
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::optimize::{optimize_with, OptimizeOptions};
+use tcg_backend::translate::{analyze, analyze_with, codegen};
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::{Cond, Context, Opcode, TempIdx, Type};
+
+const OFF: OptimizeOptions = OptimizeOptions {
+    if_convert: false,
+    if_convert_max_ops: 4,
+    peephole: true,
+};
+
+/// Context with `env` and globals `g[0..4]` at `env + 8 * i`.
+fn setup() -> (Context, TempIdx, [TempIdx; 4]) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let g = [0, 1, 2, 3].map(|i| ctx.new_global(Type::I64, env, 8 * i, "g"));
+    (ctx, env, g)
+}
+
+fn live_ops(ctx: &Context) -> Vec<Opcode> {
+    ctx.ops()
+        .iter()
+        .map(|op| op.opc)
+        .filter(|&opc| opc != Opcode::Nop)
+        .collect()
+}
+
+/// `d = x < 0 ? -x : x`, the fallthrough arm copying `x`.
+fn gen_abs(ctx: &mut Context, d: TempIdx, x: TempIdx) {
+    let zero = ctx.new_const(Type::I64, 0);
+    let (neg, end) = (ctx.new_label(), ctx.new_label());
+    ctx.gen_brcond(Type::I64, x, zero, Cond::Lt, neg);
+    ctx.gen_mov(Type::I64, d, x);
+    ctx.gen_br(end);
+    ctx.gen_set_label(neg);
+    ctx.gen_neg(Type::I64, d, x);
+    ctx.gen_set_label(end);
+}
+
+#[test]
+fn abs_diamond_becomes_movcond() {
+    let (mut ctx, _env, g) = setup();
+    gen_abs(&mut ctx, g[1], g[0]);
+    ctx.gen_exit_tb_raw(0);
+    analyze(&mut ctx);
+    assert_eq!(
+        live_ops(&ctx),
+        [Opcode::Neg, Opcode::MovCond, Opcode::ExitTb]
+    );
+    let sel = ctx
+        .ops()
+        .iter()
+        .find(|op| op.opc == Opcode::MovCond)
+        .unwrap();
+    let neg = ctx.ops().iter().find(|op| op.opc == Opcode::Neg).unwrap();
+    // g1 = g0 < 0 ? neg : g0
+    assert_eq!(sel.args[..5], [g[1], g[0], sel.args[2], neg.args[0], g[0]]);
+    assert_eq!(sel.args[5].0, Cond::Lt as u32);
+}
+
+#[test]
+fn if_convert_off_keeps_branches() {
+    let (mut ctx, _env, g) = setup();
+    gen_abs(&mut ctx, g[1], g[0]);
+    ctx.gen_exit_tb_raw(0);
+    let before = live_ops(&ctx);
+    optimize_with(&mut ctx, &OFF);
+    assert_eq!(live_ops(&ctx), before);
+}
+
+#[test]
+fn diamond_with_store_is_untouched() {
+    let (mut ctx, env, g) = setup();
+    let zero = ctx.new_const(Type::I64, 0);
+    let (neg, end) = (ctx.new_label(), ctx.new_label());
+    ctx.gen_brcond(Type::I64, g[0], zero, Cond::Lt, neg);
+    ctx.gen_mov(Type::I64, g[1], g[0]);
+    ctx.gen_br(end);
+    ctx.gen_set_label(neg);
+    ctx.gen_neg(Type::I64, g[1], g[0]);
+    ctx.gen_st(Type::I64, g[0], env, 32);
+    ctx.gen_set_label(end);
+    ctx.gen_exit_tb_raw(0);
+    let before = live_ops(&ctx);
+    optimize_with(&mut ctx, &OptimizeOptions::default());
+    assert_eq!(live_ops(&ctx), before);
+}
+
+#[test]
+fn arm_limit_and_unequal_dests_are_kept() {
+    // Five ops in one arm, one over the limit.
+    let (mut ctx, _env, g) = setup();
+    let one = ctx.new_const(Type::I64, 1);
+    let (l, m) = (ctx.new_label(), ctx.new_label());
+    ctx.gen_brcond(Type::I64, g[0], g[1], Cond::Eq, l);
+    for _ in 0..5 {
+        ctx.gen_add(Type::I64, g[2], g[2], one);
+    }
+    ctx.gen_br(m);
+    ctx.gen_set_label(l);
+    ctx.gen_mov(Type::I64, g[2], one);
+    ctx.gen_set_label(m);
+    ctx.gen_exit_tb_raw(0);
+    let before = live_ops(&ctx);
+    optimize_with(&mut ctx, &OptimizeOptions::default());
+    assert_eq!(live_ops(&ctx), before);
+
+    // Arms writing different globals.
+    let (mut ctx, _env, g) = setup();
+    let (l, m) = (ctx.new_label(), ctx.new_label());
+    ctx.gen_brcond(Type::I64, g[0], g[1], Cond::Eq, l);
+    ctx.gen_mov(Type::I64, g[2], g[0]);
+    ctx.gen_br(m);
+    ctx.gen_set_label(l);
+    ctx.gen_mov(Type::I64, g[3], g[0]);
+    ctx.gen_set_label(m);
+    ctx.gen_exit_tb_raw(0);
+    let before = live_ops(&ctx);
+    optimize_with(&mut ctx, &OptimizeOptions::default());
+    assert_eq!(live_ops(&ctx), before);
+}
+
+/// Diamond `then`/`else` arms on `g`, taken when `g[0] cond g[1]`.
+fn gen_diamond(
+    ctx: &mut Context,
+    g: [TempIdx; 4],
+    cond: Cond,
+    then: impl FnOnce(&mut Context),
+    fallthrough: impl FnOnce(&mut Context),
+) {
+    let (l, m) = (ctx.new_label(), ctx.new_label());
+    ctx.gen_brcond(Type::I64, g[0], g[1], cond, l);
+    fallthrough(ctx);
+    ctx.gen_br(m);
+    ctx.gen_set_label(l);
+    then(ctx);
+    ctx.gen_set_label(m);
+}
+
+type Shape = (&'static str, fn(&mut Context, [TempIdx; 4]));
+
+fn shapes() -> Vec<Shape> {
+    vec![
+        ("abs", |ctx, g| gen_abs(ctx, g[3], g[0])),
+        ("min", |ctx, g| {
+            gen_diamond(
+                ctx,
+                g,
+                Cond::Lt,
+                |ctx| {
+                    ctx.gen_mov(Type::I64, g[3], g[0]);
+                },
+                |ctx| {
+                    ctx.gen_mov(Type::I64, g[3], g[1]);
+                },
+            );
+        }),
+        // lo = g1, hi = g2: two diamonds back to back.
+        ("clamp", |ctx, g| {
+            gen_diamond(
+                ctx,
+                g,
+                Cond::Lt,
+                |ctx| {
+                    ctx.gen_mov(Type::I64, g[3], g[1]);
+                },
+                |ctx| {
+                    ctx.gen_mov(Type::I64, g[3], g[0]);
+                },
+            );
+            let (l, m) = (ctx.new_label(), ctx.new_label());
+            ctx.gen_brcond(Type::I64, g[3], g[2], Cond::Gt, l);
+            ctx.gen_mov(Type::I64, g[3], g[3]);
+            ctx.gen_br(m);
+            ctx.gen_set_label(l);
+            ctx.gen_mov(Type::I64, g[3], g[2]);
+            ctx.gen_set_label(m);
+        }),
+        // Swap the compared temps, or bump both; needs a copy.
+        ("sort", |ctx, g| {
+            let one = ctx.new_const(Type::I64, 1);
+            gen_diamond(
+                ctx,
+                g,
+                Cond::Gtu,
+                |ctx| {
+                    let t = ctx.new_temp(Type::I64);
+                    ctx.gen_mov(Type::I64, t, g[0]);
+                    ctx.gen_mov(Type::I64, g[0], g[1]);
+                    ctx.gen_mov(Type::I64, g[1], t);
+                },
+                |ctx| {
+                    ctx.gen_add(Type::I64, g[0], g[0], one);
+                    ctx.gen_add(Type::I64, g[1], g[1], one);
+                },
+            );
+        }),
+        // Arms chaining through their own results.
+        ("mix", |ctx, g| {
+            gen_diamond(
+                ctx,
+                g,
+                Cond::TstNe,
+                |ctx| {
+                    let t = ctx.new_temp(Type::I64);
+                    ctx.gen_xor(Type::I64, t, g[0], g[2]);
+                    ctx.gen_shl(Type::I64, g[2], t, g[1]);
+                    ctx.gen_sub(Type::I64, g[3], g[2], g[0]);
+                },
+                |ctx| {
+                    ctx.gen_mul(Type::I64, g[3], g[0], g[1]);
+                    ctx.gen_not(Type::I64, g[2], g[3]);
+                },
+            );
+        }),
+    ]
+}
+
+/// Translate `shape` into its own buffer; returns the buffer and
+/// TB start.
+fn compile(
+    shape: fn(&mut Context, [TempIdx; 4]),
+    opts: &OptimizeOptions,
+) -> (CodeBuffer, usize) {
+    let mut backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let (mut ctx, _env, g) = setup();
+    backend.init_context(&mut ctx);
+    shape(&mut ctx, g);
+    ctx.gen_exit_tb_raw(0);
+    analyze_with(&mut ctx, opts);
+    let converted = !live_ops(&ctx).contains(&Opcode::BrCond);
+    assert_eq!(converted, opts.if_convert);
+    let start = codegen(&mut ctx, &backend, &mut buf).unwrap();
+    (buf, start)
+}
+
+fn exec(buf: &CodeBuffer, start: usize, env: &mut [u64; 4]) {
+    unsafe {
+        let prologue: unsafe extern "C" fn(
+            *mut u8,
+            *const u8,
+            *mut u64,
+        ) -> usize = std::mem::transmute(buf.base_ptr());
+        prologue(
+            env.as_mut_ptr().cast(),
+            buf.ptr_at(start),
+            tcg_core::helper::pending_ptr(),
+        );
+    }
+}
+
+#[test]
+fn converted_shapes_match_branches() {
+    let edges = [0, 1, u64::MAX, 1 << 63, i64::MAX as u64, 63];
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        match seed % 4 {
+            0 => edges[(seed >> 8) as usize % edges.len()],
+            1 => seed >> 56,
+            _ => seed,
+        }
+    };
+    for (name, shape) in shapes() {
+        let (on, on_start) = compile(shape, &OptimizeOptions::default());
+        let (off, off_start) = compile(shape, &OFF);
+        for _ in 0..500 {
+            let input = [next(), next(), next(), next()];
+            let (mut a, mut b) = (input, input);
+            exec(&on, on_start, &mut a);
+            exec(&off, off_start, &mut b);
+            assert_eq!(a, b, "{name} {input:x?}");
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use tcg_backend::liveness::{liveness_analysis, pressure_report};
+use tcg_backend::x86_64::regs::ALLOCATABLE_REGS;
+use tcg_backend::x86_64::Reg;
+use tcg_core::types::Type;
+use tcg_core::{Context, Opcode, TempIdx};
+
+fn ctx_with_global() -> (Context, TempIdx) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let g = ctx.new_global(Type::I64, env, 0, "g");
+    (ctx, g)
+}
+
+/// a, b overlap with their sum; the peak is at the add.
+#[test]
+fn pressure_peak_and_location() {
+    let (mut ctx, g) = ctx_with_global();
+    let a = ctx.new_temp(Type::I64);
+    let b = ctx.new_temp(Type::I64);
+    let c = ctx.new_temp(Type::I64);
+    let k1 = ctx.new_const(Type::I64, 1);
+    let k2 = ctx.new_const(Type::I64, 2);
+    ctx.gen_mov(Type::I64, a, k1); // op 0: {a}
+    ctx.gen_mov(Type::I64, b, k2); // op 1: {a, b}
+    ctx.gen_add(Type::I64, c, a, b); // op 2: {a, b, c}
+                                     // Globals stay resident until killed.
+    ctx.gen_add(Type::I64, g, c, c); // op 3: {c, g}
+    ctx.gen_exit_tb_raw(0); // op 4: {g}
+
+    liveness_analysis(&mut ctx);
+    let r = pressure_report(&ctx, ALLOCATABLE_REGS.count());
+    assert_eq!(r.max_live, 3);
+    assert_eq!(r.max_op, Some(2));
+    assert_eq!(r.max_live_of(Type::I64), 3);
+    assert_eq!(r.max_live_of(Type::I32), 0);
+    assert_eq!(r.timeline, vec![1, 2, 3, 2, 1]);
+    assert_eq!(r.spill_sites, 0);
+}
+
+/// Types are tracked separately.
+#[test]
+fn pressure_split_by_type() {
+    let (mut ctx, g) = ctx_with_global();
+    let a = ctx.new_temp(Type::I32);
+    let b = ctx.new_temp(Type::I32);
+    let w = ctx.new_temp(Type::I64);
+    ctx.gen_mov(Type::I32, a, b);
+    ctx.gen_ext_i32_i64(w, a);
+    ctx.gen_mov(Type::I64, g, w);
+    ctx.gen_exit_tb_raw(0);
+
+    liveness_analysis(&mut ctx);
+    let r = pressure_report(&ctx, ALLOCATABLE_REGS.count());
+    assert_eq!(r.max_live_of(Type::I32), 2);
+    assert_eq!(r.max_live_of(Type::I64), 2);
+}
+
+/// Each half of an I128 temp holds one register, like an I64.
+#[test]
+fn pressure_counts_i128_halves() {
+    let build = |wide: bool| {
+        let (mut ctx, g) = ctx_with_global();
+        let (a, b) = if wide {
+            let (a, b) = (ctx.new_temp_i128(), ctx.new_temp_i128());
+            (ctx.i128_halves(a), ctx.i128_halves(b))
+        } else {
+            let mut t = || ctx.new_temp(Type::I64);
+            ((t(), t()), (t(), t()))
+        };
+        ctx.gen_mov(Type::I64, a.0, g);
+        ctx.gen_mov(Type::I64, a.1, g);
+        ctx.gen_addco(Type::I64, b.0, a.0, a.0);
+        ctx.gen_addci(Type::I64, b.1, a.1, a.1);
+        ctx.gen_xor(Type::I64, g, b.0, b.1);
+        ctx.gen_exit_tb_raw(0);
+        liveness_analysis(&mut ctx);
+        pressure_report(&ctx, ALLOCATABLE_REGS.count())
+    };
+    let r = build(true);
+    assert_eq!(r, build(false));
+    assert_eq!(r.max_live_of(Type::I128), 0);
+}
+
+/// More simultaneously live temps than allocatable registers
+/// yields guaranteed spill sites.
+#[test]
+fn pressure_spill_sites() {
+    let (mut ctx, g) = ctx_with_global();
+    let nregs = ALLOCATABLE_REGS.count() as usize;
+    let n = nregs + 4;
+    let temps: Vec<TempIdx> = (0..n).map(|_| ctx.new_temp(Type::I64)).collect();
+    for (i, &t) in temps.iter().enumerate() {
+        let k = ctx.new_const(Type::I64, i as u64);
+        ctx.gen_mov(Type::I64, t, k);
+    }
+    let acc = ctx.new_temp(Type::I64);
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_mov(Type::I64, acc, zero);
+    for &t in &temps {
+        ctx.gen_add(Type::I64, acc, acc, t);
+    }
+    ctx.gen_mov(Type::I64, g, acc);
+    ctx.gen_exit_tb_raw(0);
+
+    liveness_analysis(&mut ctx);
+    let r = pressure_report(&ctx, ALLOCATABLE_REGS.count());
+    assert_eq!(r.max_live as usize, n + 1);
+    assert_eq!(r.max_op, Some(n));
+    assert!(r.spill_sites > 0);
+    assert_eq!(r.timeline.len(), ctx.num_ops());
+
+    // The count follows the host's register file.
+    let r = pressure_report(&ctx, n as u32 + 1);
+    assert_eq!(r.spill_sites, 0);
+}
+
+/// Pure ops that only feed dead EBB temps become nops; guest
+/// loads and writes to globals stay.
+#[test]
+fn dead_pure_ops_removed() {
+    let (mut ctx, g) = ctx_with_global();
+    let a = ctx.new_temp(Type::I64);
+    let b = ctx.new_temp(Type::I64);
+    let v = ctx.new_temp(Type::I64);
+    let k = ctx.new_const(Type::I64, 4);
+    ctx.gen_add(Type::I64, a, g, k); // op 0: feeds only b
+    ctx.gen_shl(Type::I64, b, a, k); // op 1: dead
+    ctx.gen_qemu_ld(Type::I64, v, g, 0); // op 2: may fault
+    ctx.gen_mov(Type::I64, g, k); // op 3: global
+    ctx.gen_exit_tb_raw(0);
+
+    liveness_analysis(&mut ctx);
+    let opcs: Vec<Opcode> = ctx.ops().iter().map(|op| op.opc).collect();
+    assert_eq!(
+        opcs,
+        [
+            Opcode::Nop,
+            Opcode::Nop,
+            Opcode::QemuLd,
+            Opcode::Mov,
+            Opcode::ExitTb
+        ]
+    );
+}
+
+/// TB temps no op reads are dropped with the ops feeding them,
+/// even when a loop reads one before the op writing it; TB temps
+/// read past a label stay.
+#[test]
+fn unread_tb_temps_removed() {
+    let (mut ctx, g) = ctx_with_global();
+    let t = ctx.new_temp_tb(Type::I64);
+    let u = ctx.new_temp_tb(Type::I64);
+    let e = ctx.new_temp(Type::I64);
+    let k = ctx.new_const(Type::I64, 4);
+    let l = ctx.new_label();
+    ctx.gen_mov(Type::I64, u, g); // op 0: read past the label
+    ctx.gen_set_label(l); // op 1
+    ctx.gen_add(Type::I64, e, t, k); // op 2: dead, last read of t
+    ctx.gen_mov(Type::I64, t, u); // op 3: t is never read again
+    ctx.gen_sub(Type::I64, g, g, u); // op 4
+    ctx.gen_brcond(Type::I64, g, k, tcg_core::Cond::Ne, l); // op 5
+    ctx.gen_exit_tb_raw(0);
+
+    liveness_analysis(&mut ctx);
+    let opcs: Vec<Opcode> = ctx.ops().iter().map(|op| op.opc).collect();
+    assert_eq!(
+        opcs,
+        [
+            Opcode::Mov,
+            Opcode::SetLabel,
+            Opcode::Nop,
+            Opcode::Nop,
+            Opcode::Sub,
+            Opcode::BrCond,
+            Opcode::ExitTb
+        ]
+    );
+}
@@ -1,2 +1,10 @@
+mod aarch64;
 mod code_buffer;
+mod determinism;
+mod if_convert;
+mod liveness;
+mod optimize;
+mod peephole;
+mod regalloc;
+mod translate;
 mod x86_64;
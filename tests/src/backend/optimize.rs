@@ -0,0 +1,402 @@
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::{analyze, translate};
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::{Context, Opcode, TempIdx, TempKind, Type};
+
+/// Context with `env` and two globals `a`, `b`.
+fn setup() -> (Context, TempIdx, TempIdx, TempIdx) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let a = ctx.new_global(Type::I64, env, 0, "a");
+    let b = ctx.new_global(Type::I64, env, 8, "b");
+    (ctx, env, a, b)
+}
+
+/// Opcodes left after `analyze()`, nops dropped.
+fn live_ops(ctx: &Context) -> Vec<Opcode> {
+    ctx.ops()
+        .iter()
+        .map(|op| op.opc)
+        .filter(|&opc| opc != Opcode::Nop)
+        .collect()
+}
+
+/// `ld (a + 8)` twice, as a guest load pair off one base.
+fn gen_two_loads(
+    ctx: &mut Context,
+    base: TempIdx,
+    dst: TempIdx,
+    between: impl FnOnce(&mut Context),
+) {
+    let k = ctx.new_const(Type::I64, 8);
+    let t1 = ctx.new_temp(Type::I64);
+    ctx.gen_add(Type::I64, t1, base, k);
+    let v1 = ctx.new_temp(Type::I64);
+    ctx.gen_qemu_ld(Type::I64, v1, t1, 0);
+    between(ctx);
+    let k = ctx.new_const(Type::I64, 8);
+    let t2 = ctx.new_temp(Type::I64);
+    ctx.gen_add(Type::I64, t2, base, k);
+    let v2 = ctx.new_temp(Type::I64);
+    ctx.gen_qemu_ld(Type::I64, v2, t2, 0);
+    ctx.gen_add(Type::I64, dst, v1, v2);
+    ctx.gen_exit_tb_raw(0);
+}
+
+#[test]
+fn cse_collapses_identical_adds() {
+    let (mut ctx, env, a, b) = setup();
+    gen_two_loads(&mut ctx, a, b, |ctx| {
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_ld(Type::I64, t, env, 16);
+        ctx.gen_st(Type::I64, t, env, 24);
+    });
+    analyze(&mut ctx);
+    assert_eq!(
+        live_ops(&ctx),
+        [
+            Opcode::Add,
+            Opcode::QemuLd,
+            Opcode::Ld,
+            Opcode::St,
+            Opcode::QemuLd,
+            Opcode::Add,
+            Opcode::ExitTb,
+        ]
+    );
+    // Both loads read the surviving address temp.
+    let loads: Vec<_> = ctx
+        .ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::QemuLd)
+        .map(|op| op.args[1])
+        .collect();
+    assert_eq!(loads[0], loads[1]);
+}
+
+#[test]
+fn cse_respects_redefined_base() {
+    let (mut ctx, _env, a, b) = setup();
+    gen_two_loads(&mut ctx, a, b, |ctx| {
+        let one = ctx.new_const(Type::I64, 1);
+        ctx.gen_add(Type::I64, a, a, one);
+    });
+    analyze(&mut ctx);
+    let adds = live_ops(&ctx)
+        .into_iter()
+        .filter(|&opc| opc == Opcode::Add)
+        .count();
+    assert_eq!(adds, 4);
+}
+
+#[test]
+fn cse_matches_commuted_operands() {
+    let (mut ctx, _env, a, b) = setup();
+    let t1 = ctx.new_temp(Type::I64);
+    let t2 = ctx.new_temp(Type::I64);
+    ctx.gen_xor(Type::I64, t1, a, b);
+    ctx.gen_xor(Type::I64, t2, b, a);
+    ctx.gen_mul(Type::I64, b, t1, t2);
+    ctx.gen_exit_tb_raw(0);
+    analyze(&mut ctx);
+    assert_eq!(live_ops(&ctx), [Opcode::Xor, Opcode::Mul, Opcode::ExitTb]);
+}
+
+/// Available expressions do not survive a label or a call.
+#[test]
+fn cse_stops_at_label_and_call() {
+    extern "C" fn helper_nop(_env: *mut u8) -> u64 {
+        0
+    }
+    for boundary in ["label", "call"] {
+        let (mut ctx, env, a, b) = setup();
+        gen_two_loads(&mut ctx, a, b, |ctx| {
+            if boundary == "label" {
+                let l = ctx.new_label();
+                ctx.gen_set_label(l);
+            } else {
+                let r = ctx.new_temp(Type::I64);
+                ctx.gen_call(r, helper_nop as *const () as u64, 0, &[env]);
+            }
+        });
+        analyze(&mut ctx);
+        let adds = live_ops(&ctx)
+            .into_iter()
+            .filter(|&opc| opc == Opcode::Add)
+            .count();
+        assert_eq!(adds, 3, "{boundary}");
+    }
+}
+
+/// Non-commutative ops keep operand order in the key.
+#[test]
+fn cse_keeps_sub_operand_order() {
+    let (mut ctx, _env, a, b) = setup();
+    let t1 = ctx.new_temp(Type::I64);
+    let t2 = ctx.new_temp(Type::I64);
+    ctx.gen_sub(Type::I64, t1, a, b);
+    ctx.gen_sub(Type::I64, t2, b, a);
+    ctx.gen_add(Type::I64, b, t1, t2);
+    ctx.gen_exit_tb_raw(0);
+    analyze(&mut ctx);
+    assert_eq!(
+        live_ops(&ctx),
+        [Opcode::Sub, Opcode::Sub, Opcode::Add, Opcode::ExitTb]
+    );
+}
+
+/// Opcode and args of each op, for comparing IR before and after.
+fn snapshot(ctx: &Context) -> Vec<(Opcode, Vec<TempIdx>)> {
+    ctx.ops()
+        .iter()
+        .map(|op| (op.opc, op.args[..op.nargs as usize].to_vec()))
+        .collect()
+}
+
+/// The optimizer skips TBs with nothing to rewrite; a TB with a
+/// single opportunity of each kind must still be rewritten.
+#[test]
+fn lone_opportunity_is_taken() {
+    type Gen = fn(&mut Context, TempIdx, TempIdx);
+    let cases: [(&str, Gen); 7] = [
+        ("copy", |ctx, a, b| {
+            let t = ctx.new_temp(Type::I64);
+            ctx.gen_mov(Type::I64, t, a);
+            ctx.gen_shl(Type::I64, b, t, b);
+        }),
+        ("add 0", |ctx, a, b| {
+            let k = ctx.new_const(Type::I64, 0);
+            ctx.gen_add(Type::I64, b, a, k);
+        }),
+        ("mul 1", |ctx, a, b| {
+            let k = ctx.new_const(Type::I64, 1);
+            ctx.gen_mul(Type::I64, b, a, k);
+        }),
+        ("i32 and -1", |ctx, _, _| {
+            let t = ctx.new_temp(Type::I32);
+            let k = ctx.new_const(Type::I32, 0xffff_ffff);
+            ctx.gen_and(Type::I32, t, t, k);
+        }),
+        ("x0 | x", |ctx, a, b| {
+            let zero = ctx.new_const(Type::I64, 0);
+            ctx.gen_or(Type::I64, b, zero, a);
+        }),
+        ("xor x, x", |ctx, a, b| {
+            ctx.gen_xor(Type::I64, b, a, a);
+        }),
+        ("const brcond", |ctx, _, _| {
+            let l = ctx.new_label();
+            let k1 = ctx.new_const(Type::I64, 3);
+            let k2 = ctx.new_const(Type::I64, 4);
+            ctx.gen_brcond(Type::I64, k1, k2, tcg_core::Cond::Eq, l);
+            ctx.gen_set_label(l);
+        }),
+    ];
+    for (name, gen) in cases {
+        let (mut ctx, _env, a, b) = setup();
+        gen(&mut ctx, a, b);
+        ctx.gen_exit_tb_raw(0);
+        let before = snapshot(&ctx);
+        tcg_backend::optimize::optimize(&mut ctx);
+        assert_ne!(snapshot(&ctx), before, "{name}");
+    }
+}
+
+/// Constants the folders have no rule for, a `mov` nobody reads
+/// and distinct pure ops leave nothing to rewrite.
+#[test]
+fn no_opportunity_leaves_ir_unchanged() {
+    let (mut ctx, _env, a, b) = setup();
+    let k = ctx.new_const(Type::I64, 8);
+    let t = ctx.new_temp(Type::I64);
+    ctx.gen_add(Type::I64, t, a, k);
+    let v = ctx.new_temp(Type::I64);
+    ctx.gen_qemu_ld(Type::I64, v, t, 3);
+    ctx.gen_sub(Type::I64, b, b, v);
+    let pc = ctx.new_const(Type::I64, 0x1000);
+    ctx.gen_mov(Type::I64, a, pc);
+    ctx.gen_exit_tb_raw(0);
+    let before = snapshot(&ctx);
+    tcg_backend::optimize::optimize(&mut ctx);
+    assert_eq!(snapshot(&ctx), before);
+}
+
+/// `(8 + 4) * 3` into `b`, plus `x0 | a` as RISC-V's `mv` via
+/// `or` reads it.
+fn gen_const_chain(ctx: &mut Context, a: TempIdx, b: TempIdx) {
+    let k8 = ctx.new_const(Type::I64, 8);
+    let k4 = ctx.new_const(Type::I64, 4);
+    let t1 = ctx.new_temp(Type::I64);
+    ctx.gen_add(Type::I64, t1, k8, k4);
+    let k3 = ctx.new_const(Type::I64, 3);
+    let t2 = ctx.new_temp(Type::I64);
+    ctx.gen_mul(Type::I64, t2, t1, k3);
+    let zero = ctx.new_const(Type::I64, 0);
+    let t3 = ctx.new_temp(Type::I64);
+    ctx.gen_or(Type::I64, t3, zero, a);
+    ctx.gen_add(Type::I64, b, t2, t3);
+    ctx.gen_exit_tb_raw(0);
+}
+
+#[test]
+fn const_chain_folds_to_one_const() {
+    let (mut ctx, _env, a, b) = setup();
+    gen_const_chain(&mut ctx, a, b);
+    analyze(&mut ctx);
+    // The add and mul fold away; the x0 operand turns the or
+    // into a copy of `a`, which the add reads directly.
+    assert_eq!(live_ops(&ctx), [Opcode::Add, Opcode::ExitTb]);
+    let add = ctx.ops().iter().find(|op| op.opc == Opcode::Add).unwrap();
+    assert_eq!(add.args[0], b);
+    let k = ctx.temp(add.args[1]);
+    assert_eq!((k.kind, k.val), (TempKind::Const, 36));
+    assert_eq!(add.args[2], a);
+}
+
+/// A value known on one path into a label, or before a call,
+/// is not known after it.
+#[test]
+fn const_info_ends_at_label_and_call() {
+    let (mut ctx, _env, a, b) = setup();
+    let k1 = ctx.new_const(Type::I64, 1);
+    let k2 = ctx.new_const(Type::I64, 2);
+    let l = ctx.new_label();
+    ctx.gen_mov(Type::I64, b, k1);
+    ctx.gen_brcond(Type::I64, a, k1, tcg_core::Cond::Eq, l);
+    ctx.gen_mov(Type::I64, b, k2);
+    ctx.gen_set_label(l);
+    ctx.gen_add(Type::I64, a, b, k1);
+    ctx.gen_mov(Type::I64, b, k2);
+    let t = ctx.new_temp(Type::I64);
+    ctx.gen_call(t, 0x1000, 0, &[]);
+    ctx.gen_add(Type::I64, a, b, k1);
+    ctx.gen_exit_tb_raw(0);
+    tcg_backend::optimize::optimize(&mut ctx);
+    let adds: Vec<_> = ctx
+        .ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::Add)
+        .map(|op| op.args[1])
+        .collect();
+    assert_eq!(adds, [b, b]);
+}
+
+/// Translate `gen` into a TB and run it with globals `regs`.
+fn run(gen: fn(&mut Context, TempIdx, TempIdx), regs: &mut [u64; 2]) {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let mut backend = X86_64CodeGen::new();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let a = ctx.new_global(Type::I64, env, 0, "a");
+    let b = ctx.new_global(Type::I64, env, 8, "b");
+    gen(&mut ctx, a, b);
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+
+    unsafe {
+        let prologue: unsafe extern "C" fn(
+            *mut u8,
+            *const u8,
+            *mut u64,
+        ) -> usize = std::mem::transmute(buf.base_ptr());
+        prologue(
+            regs.as_mut_ptr().cast(),
+            buf.ptr_at(start),
+            tcg_core::helper::pending_ptr(),
+        );
+    }
+}
+
+#[test]
+fn const_chain_executes() {
+    let mut regs = [5, 0];
+    run(gen_const_chain, &mut regs);
+    assert_eq!(regs, [5, 41]);
+}
+
+/// A chain of copies of `a`, some made by simplification, then
+/// `a = a + 1` and `b = t4 * t2` from copies taken before it.
+fn gen_copy_chain(ctx: &mut Context, a: TempIdx, b: TempIdx) {
+    let [t1, t2, t3, t4, t5] = [(); 5].map(|_| ctx.new_temp(Type::I64));
+    ctx.gen_mov(Type::I64, t1, a);
+    ctx.gen_mov(Type::I64, t2, t1);
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_add(Type::I64, t3, t2, zero);
+    ctx.gen_and(Type::I64, t4, t3, t3);
+    let one = ctx.new_const(Type::I64, 1);
+    ctx.gen_add(Type::I64, t5, t4, one);
+    ctx.gen_mov(Type::I64, a, t5);
+    ctx.gen_mul(Type::I64, b, t4, t2);
+    ctx.gen_exit_tb_raw(0);
+}
+
+#[test]
+fn copy_chain_collapses() {
+    let (mut ctx, _env, a, b) = setup();
+    gen_copy_chain(&mut ctx, a, b);
+    let before = live_ops(&ctx).len();
+    analyze(&mut ctx);
+    // Everything reads `a` until the store to it; t2 and t4 are
+    // still read after it, so only their movs survive.
+    assert_eq!(
+        live_ops(&ctx),
+        [
+            Opcode::Mov,
+            Opcode::Mov,
+            Opcode::Add,
+            Opcode::Mov,
+            Opcode::Mul,
+            Opcode::ExitTb,
+        ]
+    );
+    assert_eq!(before - live_ops(&ctx).len(), 2);
+    let ops: Vec<_> = ctx
+        .ops()
+        .iter()
+        .filter(|op| op.opc != Opcode::Nop)
+        .collect();
+    assert_eq!(&ops[0].args[..2], [ops[4].args[2], a]);
+    assert_eq!(&ops[1].args[..2], [ops[4].args[1], a]);
+    assert_eq!(ops[2].args[1], a);
+    assert_eq!(ops[3].args[0], a);
+}
+
+#[test]
+fn copy_chain_executes() {
+    let mut regs = [7, 0];
+    run(gen_copy_chain, &mut regs);
+    assert_eq!(regs, [8, 49]);
+}
+
+/// `b = a << 3`, plus a dummy add and mul into TB temps nothing
+/// reads when `dummy` is set.
+fn gen_shift(ctx: &mut Context, a: TempIdx, b: TempIdx, dummy: bool) {
+    let k3 = ctx.new_const(Type::I64, 3);
+    let t = ctx.new_temp(Type::I64);
+    ctx.gen_shl(Type::I64, t, a, k3);
+    if dummy {
+        let [d1, d2] = [(); 2].map(|_| ctx.new_temp_tb(Type::I64));
+        ctx.gen_add(Type::I64, d1, t, a);
+        ctx.gen_mul(Type::I64, d2, d1, t);
+    }
+    ctx.gen_mov(Type::I64, b, t);
+    ctx.gen_exit_tb_raw(0);
+}
+
+#[test]
+fn dead_dummy_ops_dropped() {
+    let (mut ctx, _env, a, b) = setup();
+    gen_shift(&mut ctx, a, b, true);
+    analyze(&mut ctx);
+    assert_eq!(live_ops(&ctx), [Opcode::Shl, Opcode::Mov, Opcode::ExitTb]);
+
+    let mut with = [5, 0];
+    run(|ctx, a, b| gen_shift(ctx, a, b, true), &mut with);
+    let mut without = [5, 0];
+    run(|ctx, a, b| gen_shift(ctx, a, b, false), &mut without);
+    assert_eq!(with, without);
+    assert_eq!(with, [5, 40]);
+}
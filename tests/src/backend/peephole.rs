@@ -0,0 +1,151 @@
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::optimize::OptimizeOptions;
+use tcg_backend::peephole::peephole;
+use tcg_backend::translate::{analyze_with, codegen};
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::{Cond, Context, Opcode, TempIdx, Type};
+
+const OFF: OptimizeOptions = OptimizeOptions {
+    if_convert: true,
+    if_convert_max_ops: 4,
+    peephole: false,
+};
+
+/// Context with `env` and globals `g[0..4]` at `env + 8 * i`.
+fn setup() -> (Context, [TempIdx; 4]) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let g = [0, 1, 2, 3].map(|i| ctx.new_global(Type::I64, env, 8 * i, "g"));
+    (ctx, g)
+}
+
+fn live_ops(ctx: &Context) -> Vec<Opcode> {
+    ctx.ops()
+        .iter()
+        .map(|op| op.opc)
+        .filter(|&opc| opc != Opcode::Nop)
+        .collect()
+}
+
+/// `g3 = g1; g3 += g2`.
+fn mov_add(ctx: &mut Context, g: [TempIdx; 4]) {
+    ctx.gen_mov(Type::I64, g[3], g[1]);
+    ctx.gen_add(Type::I64, g[3], g[3], g[2]);
+}
+
+/// `g3 = g1 - g2 == 0; g0 = 0 != g2 - g1`.
+fn sub_setcond(ctx: &mut Context, g: [TempIdx; 4]) {
+    let zero = ctx.new_const(Type::I64, 0);
+    let (t, u) = (ctx.new_temp(Type::I64), ctx.new_temp(Type::I64));
+    ctx.gen_sub(Type::I64, t, g[1], g[2]);
+    ctx.gen_setcond(Type::I64, g[3], t, zero, Cond::Eq);
+    ctx.gen_sub(Type::I64, u, g[2], g[1]);
+    ctx.gen_setcond(Type::I64, g[0], zero, u, Cond::Ne);
+}
+
+/// Translate `shape` into its own buffer; returns the buffer, the
+/// TB start and the live ops codegen saw.
+fn compile(
+    shape: fn(&mut Context, [TempIdx; 4]),
+    opts: &OptimizeOptions,
+) -> (CodeBuffer, usize, Vec<Opcode>) {
+    let mut backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let (mut ctx, g) = setup();
+    backend.init_context(&mut ctx);
+    shape(&mut ctx, g);
+    ctx.gen_exit_tb_raw(0);
+    analyze_with(&mut ctx, opts);
+    let ops = live_ops(&ctx);
+    let start = codegen(&mut ctx, &backend, &mut buf).unwrap();
+    (buf, start, ops)
+}
+
+fn exec(buf: &CodeBuffer, start: usize, env: &mut [u64; 4]) {
+    unsafe {
+        let prologue: unsafe extern "C" fn(
+            *mut u8,
+            *const u8,
+            *mut u64,
+        ) -> usize = std::mem::transmute(buf.base_ptr());
+        prologue(
+            env.as_mut_ptr().cast(),
+            buf.ptr_at(start),
+            tcg_core::helper::pending_ptr(),
+        );
+    }
+}
+
+/// `shape` gives the same results with and without the pass, in
+/// less code with it; returns the fused live ops.
+fn check_fused(shape: fn(&mut Context, [TempIdx; 4])) -> Vec<Opcode> {
+    let (on, on_start, ops) = compile(shape, &OptimizeOptions::default());
+    let (off, off_start, _) = compile(shape, &OFF);
+    let inputs = [[0, 5, 5, 0], [0, 5, 7, 0], [9, u64::MAX, 1, 3]];
+    for input in inputs {
+        let (mut a, mut b) = (input, input);
+        exec(&on, on_start, &mut a);
+        exec(&off, off_start, &mut b);
+        assert_eq!(a, b, "{input:x?}");
+    }
+    let (on_size, off_size) =
+        (on.offset() - on_start, off.offset() - off_start);
+    assert!(on_size < off_size, "fused {on_size}B, unfused {off_size}B");
+    ops
+}
+
+#[test]
+fn mov_into_overwritten_global_is_fused() {
+    let ops = check_fused(mov_add);
+    assert_eq!(ops, [Opcode::Add, Opcode::ExitTb]);
+}
+
+#[test]
+fn sub_feeding_setcond_zero_is_fused() {
+    let ops = check_fused(sub_setcond);
+    assert_eq!(ops, [Opcode::SetCond, Opcode::SetCond, Opcode::ExitTb]);
+}
+
+/// The difference is read again after the `setcond`, so the
+/// `sub` stays.
+#[test]
+fn sub_read_later_is_kept() {
+    let (mut ctx, g) = setup();
+    let zero = ctx.new_const(Type::I64, 0);
+    let t = ctx.new_temp(Type::I64);
+    ctx.gen_sub(Type::I64, t, g[1], g[2]);
+    ctx.gen_setcond(Type::I64, g[3], t, zero, Cond::Eq);
+    ctx.gen_mov(Type::I64, g[0], t);
+    peephole(&mut ctx);
+    assert_eq!(live_ops(&ctx), [Opcode::Sub, Opcode::SetCond, Opcode::Mov]);
+}
+
+/// Only `eq`/`ne` against zero mean `a == b`/`a != b`.
+#[test]
+fn sub_setcond_lt_is_kept() {
+    let (mut ctx, g) = setup();
+    let zero = ctx.new_const(Type::I64, 0);
+    let t = ctx.new_temp(Type::I64);
+    ctx.gen_sub(Type::I64, t, g[1], g[2]);
+    ctx.gen_setcond(Type::I64, g[3], t, zero, Cond::Lt);
+    peephole(&mut ctx);
+    assert_eq!(live_ops(&ctx), [Opcode::Sub, Opcode::SetCond]);
+}
+
+/// Only adjacent ops fuse; an `insn_start` between them is a
+/// guest instruction boundary.
+#[test]
+fn mov_across_insn_start_is_kept() {
+    let (mut ctx, g) = setup();
+    ctx.gen_mov(Type::I64, g[3], g[1]);
+    ctx.gen_insn_start(0x1004);
+    ctx.gen_add(Type::I64, g[3], g[3], g[2]);
+    peephole(&mut ctx);
+    assert_eq!(
+        live_ops(&ctx),
+        [Opcode::Mov, Opcode::InsnStart, Opcode::Add]
+    );
+}
@@ -0,0 +1,68 @@
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::regalloc::RegAllocMode;
+use tcg_backend::translate::translate_and_execute_with;
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::HostCodeGen;
+use tcg_core::types::Type;
+use tcg_core::Context;
+
+/// Ten temps, each the sum of two globals, all live until a
+/// second pass combines neighbours. Together with the globals
+/// loaded along the way they outnumber the host registers.
+fn ten_temp_tb(ctx: &mut Context) {
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let x: Vec<_> = (0..32)
+        .map(|i| ctx.new_global(Type::I64, env, i * 8, "x"))
+        .collect();
+    let t: Vec<_> = (0..10).map(|_| ctx.new_temp(Type::I64)).collect();
+    ctx.gen_insn_start(0x1000);
+    for i in 0..10 {
+        ctx.gen_add(Type::I64, t[i], x[i], x[i + 10]);
+    }
+    for i in 0..10 {
+        ctx.gen_add(Type::I64, x[20 + i], t[i], t[(i + 1) % 10]);
+    }
+    ctx.gen_exit_tb_raw(0);
+}
+
+/// Host code bytes for `ten_temp_tb` under `mode`, and the
+/// guest registers after running it.
+fn run_ten_temp_tb(mode: RegAllocMode) -> (usize, [u64; 32]) {
+    let mut backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    ten_temp_tb(&mut ctx);
+
+    let mut regs: [u64; 32] = std::array::from_fn(|i| i as u64 * 3);
+    let start = buf.offset();
+    let exit = unsafe {
+        translate_and_execute_with(
+            &mut ctx,
+            &backend,
+            &mut buf,
+            regs.as_mut_ptr() as *mut u8,
+            mode,
+        )
+    };
+    assert_eq!(exit, 0);
+    let size = buf.offset() - start;
+    (size, regs)
+}
+
+#[test]
+fn furthest_use_spills_less_than_greedy() {
+    let (greedy, want) = run_ten_temp_tb(RegAllocMode::Greedy);
+    let (furthest, got) = run_ten_temp_tb(RegAllocMode::FurthestUse);
+    assert_eq!(got, want);
+    for i in 0..10 {
+        let t = |i: usize| 3 * (i + i + 10) as u64;
+        assert_eq!(got[20 + i], t(i) + t((i + 1) % 10));
+    }
+    assert!(
+        furthest < greedy,
+        "furthest-use {furthest}B, greedy {greedy}B"
+    );
+}
@@ -0,0 +1,286 @@
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::{
+    disassemble, translate, translate_and_disassemble,
+};
+use tcg_backend::x86_64::{Reg, X86_64CodeGen};
+use tcg_backend::{HostCodeGen, TranslateError};
+use tcg_core::helper::CALL_NO_PANIC;
+use tcg_core::{Cond, Context, MemOp, OpIdx, Opcode, TempIdx, Type};
+
+fn setup() -> (X86_64CodeGen, CodeBuffer, Context, TempIdx) {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let mut backend = X86_64CodeGen::new();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    (backend, buf, ctx, x1)
+}
+
+#[test]
+fn unbound_label_reports_op_and_guest_pc() {
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let l = ctx.new_label();
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_insn_start(0x1_0000);
+    ctx.gen_insn_start(0x1_04a8);
+    ctx.gen_brcond(Type::I64, x1, zero, Cond::Eq, l);
+    ctx.gen_exit_tb_raw(0);
+
+    let err = translate(&mut ctx, &backend, &mut buf).unwrap_err();
+    let TranslateError::UnboundLabel { label, site } = err.clone() else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(label, l);
+    assert_eq!(site.opc, Opcode::BrCond);
+    assert_eq!(site.op, OpIdx(2));
+    assert_eq!(site.pc, Some(0x1_04a8));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "unresolved label L{l} used by brcond at op #2 (guest pc 0x104a8)"
+        )
+    );
+
+    let pending: Vec<u32> = ctx.unresolved_labels().map(|l| l.id).collect();
+    assert_eq!(pending, [l]);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn invalid_ir_is_rejected_before_codegen() {
+    use tcg_core::IrError;
+
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let tmp = ctx.new_temp(Type::I64);
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, tmp);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = buf.offset();
+    let err = translate(&mut ctx, &backend, &mut buf).unwrap_err();
+    let TranslateError::InvalidIr(IrError::ReadBeforeWrite { site, temp }) =
+        err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!((site.op, temp), (OpIdx(1), tmp.0));
+    assert_eq!(buf.offset(), start);
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "invalid IR: add at op #1 (guest pc 0x1000): temp {} read \
+             before it is written",
+            tmp.0
+        )
+    );
+}
+
+#[test]
+fn double_bind_reports_both_sites() {
+    let (backend, mut buf, mut ctx, _) = setup();
+    let l = ctx.new_label();
+    ctx.gen_insn_start(0x2000);
+    ctx.gen_set_label(l);
+    ctx.gen_insn_start(0x2004);
+    ctx.gen_set_label(l);
+    ctx.gen_exit_tb_raw(0);
+
+    let err = translate(&mut ctx, &backend, &mut buf).unwrap_err();
+    let TranslateError::LabelRebound { first, second, .. } = err.clone() else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!((first.op, first.pc), (OpIdx(1), Some(0x2000)));
+    assert_eq!((second.op, second.pc), (OpIdx(3), Some(0x2004)));
+    let msg = err.to_string();
+    assert!(msg.contains(&format!("label L{l} bound twice")), "{msg}");
+    assert!(
+        msg.contains("set_label at op #1 (guest pc 0x2000)"),
+        "{msg}"
+    );
+    assert!(
+        msg.contains("set_label at op #3 (guest pc 0x2004)"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn resolved_labels_leave_nothing_pending() {
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let fwd = ctx.new_label();
+    let back = ctx.new_label();
+    let zero = ctx.new_const(Type::I64, 0);
+    ctx.gen_insn_start(0x3000);
+    ctx.gen_set_label(back);
+    ctx.gen_brcond(Type::I64, x1, zero, Cond::Eq, fwd);
+    ctx.gen_brcond(Type::I64, x1, zero, Cond::Ltu, back);
+    ctx.gen_set_label(fwd);
+    ctx.gen_exit_tb_raw(0);
+
+    translate(&mut ctx, &backend, &mut buf).unwrap();
+    assert_eq!(ctx.unresolved_labels().count(), 0);
+    assert!(ctx.label(fwd).bound_at.is_some());
+}
+
+/// Translate a one-block loop: `x1 += 1` until it equals 10.
+/// Returns the loop-head label offset and the code size.
+fn translate_loop(backend: X86_64CodeGen) -> (usize, usize) {
+    let (_, _, mut ctx, x1) = setup();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let mut backend = backend;
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+    buf.emit_u8(0x90);
+    let head = ctx.new_label();
+    let one = ctx.new_const(Type::I64, 1);
+    let ten = ctx.new_const(Type::I64, 10);
+    ctx.gen_insn_start(0x4000);
+    ctx.gen_set_label(head);
+    ctx.gen_add(Type::I64, x1, x1, one);
+    ctx.gen_brcond(Type::I64, x1, ten, Cond::Ne, head);
+    ctx.gen_exit_tb_raw(0);
+
+    let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+    (ctx.label(head).value, buf.offset() - start)
+}
+
+#[test]
+fn loop_head_alignment_respects_budget() {
+    let (plain_head, plain_size) = translate_loop(X86_64CodeGen::new());
+    let pad = plain_head.next_multiple_of(16) - plain_head;
+    assert_ne!(pad, 0, "test needs an unaligned loop head");
+
+    let (head, size) =
+        translate_loop(X86_64CodeGen::new().with_loop_align(16, 15));
+    assert_eq!(head % 16, 0);
+    assert_eq!(size, plain_size + pad);
+
+    // Over budget: no padding.
+    let (head, size) =
+        translate_loop(X86_64CodeGen::new().with_loop_align(16, pad - 1));
+    assert_eq!((head, size), (plain_head, plain_size));
+}
+
+/// Every exit a frontend can emit comes back from generated code
+/// as itself, with the source TB on the chainable ones.
+#[test]
+fn exit_tb_roundtrips_through_generated_code() {
+    use tcg_core::tb::TbExit;
+    use tcg_frontend::riscv::excp::EXCP_UNDEF;
+    let exits = [
+        TbExit::Chain(0),
+        TbExit::Chain(1),
+        TbExit::Normal,
+        TbExit::Exception(EXCP_UNDEF),
+        TbExit::Custom(0x1234),
+    ];
+    for exit in exits {
+        let (backend, mut buf, mut ctx, _) = setup();
+        ctx.tb_idx = 42;
+        ctx.gen_insn_start(0x1000);
+        ctx.gen_exit_tb(exit);
+        let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+
+        let mut env = [0u64; 8];
+        let raw = unsafe {
+            let prologue: unsafe extern "C" fn(
+                *mut u8,
+                *const u8,
+                *mut u64,
+            ) -> usize = std::mem::transmute(buf.base_ptr());
+            prologue(
+                env.as_mut_ptr().cast(),
+                buf.ptr_at(start),
+                tcg_core::helper::pending_ptr(),
+            )
+        };
+        let src = exit.chains().then_some(42);
+        assert_eq!(TbExit::decode(raw), (src, exit), "{raw:#x}");
+    }
+}
+
+#[test]
+fn atomic_access_within_host_width() {
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let v = ctx.new_temp(Type::I64);
+    let memop = MemOp::uq().aligned().atomic().bits() as u32;
+    ctx.gen_insn_start(0x3000);
+    ctx.gen_qemu_ld(Type::I64, v, x1, memop);
+    ctx.gen_qemu_st(Type::I64, v, x1, memop);
+    ctx.gen_exit_tb_raw(0);
+    assert_eq!(backend.max_atomic_bytes(), 8);
+    translate(&mut ctx, &backend, &mut buf).unwrap();
+}
+
+#[test]
+fn atomic_access_wider_than_host_is_refused() {
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let (lo, hi) = (ctx.new_temp(Type::I64), ctx.new_temp(Type::I64));
+    let memop = MemOp::uq().aligned().atomic().bits() as u32;
+    ctx.gen_insn_start(0x3000);
+    ctx.gen_insn_start(0x3004);
+    ctx.gen_qemu_ld2(Type::I64, lo, hi, x1, memop);
+    ctx.gen_exit_tb_raw(0);
+
+    let err = translate(&mut ctx, &backend, &mut buf).unwrap_err();
+    let TranslateError::UnsupportedAtomic { bytes, max, site } = err.clone()
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!((bytes, max), (16, 8));
+    assert_eq!((site.opc, site.pc), (Opcode::QemuLd2, Some(0x3004)));
+    assert_eq!(
+        err.to_string(),
+        "atomic 16-byte access by qemu_ld2 at op #2 (guest pc 0x3004): \
+         host atomics are at most 8 bytes"
+    );
+}
+
+#[test]
+fn no_panic_call_omits_pending_check() {
+    let size = |flags| {
+        let (backend, mut buf, mut ctx, x1) = setup();
+        let env = TempIdx(0);
+        ctx.gen_insn_start(0x1_0000);
+        ctx.gen_call(x1, 0x1234_5678, flags, &[env]);
+        ctx.gen_exit_tb_raw(0);
+        let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+        buf.offset() - start
+    };
+    assert!(size(CALL_NO_PANIC) < size(0));
+}
+
+#[test]
+fn add_tb_disassembles_to_mov_add_ret_shape() {
+    let (backend, mut buf, mut ctx, x1) = setup();
+    let x2 = ctx.new_global(Type::I64, TempIdx(0), 16, "x2");
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, x2);
+    ctx.gen_exit_tb_raw(0);
+
+    let (start, insns) =
+        translate_and_disassemble(&mut ctx, &backend, &mut buf).unwrap();
+    assert_eq!(insns[0].0, start);
+    assert!(insns.windows(2).all(|w| w[0].0 < w[1].0));
+    // The add lands in a fresh register, hence lea.
+    let ret = backend.tb_ret_offset;
+    let text: Vec<&str> = insns.iter().map(|(_, t)| t.as_str()).collect();
+    assert_eq!(
+        text,
+        [
+            "mov rax, qword ptr [rbp+0x8]",
+            "mov rcx, qword ptr [rbp+0x10]",
+            "lea rdx, [rax+rcx]",
+            "mov qword ptr [rbp+0x8], rdx",
+            "movabs rax, 0x100000000",
+            &format!("jmp {ret:#x}"),
+        ]
+    );
+
+    // The jump lands on the epilogue, which returns.
+    let exit = disassemble(&backend, &buf, ret, start);
+    assert_eq!(exit[0].1, "add rsp, 0x488");
+    assert_eq!(exit.last().unwrap().1, "ret");
+}
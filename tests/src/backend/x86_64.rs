@@ -42,7 +42,7 @@ fn frame_size_aligned() {
 
 #[test]
 fn stack_addend_positive() {
-    assert!(STACK_ADDEND > 0);
+    const { assert!(STACK_ADDEND > 0) };
     // After pushes + sub, total should be FRAME_SIZE
     assert_eq!(PUSH_SIZE + STACK_ADDEND, FRAME_SIZE);
 }
@@ -184,6 +184,59 @@ fn exit_tb_nonzero() {
     assert_eq!(code[0], 0xB8, "exit_tb(nonzero) should emit mov eax, imm32");
 }
 
+#[test]
+fn exit_tb_zero_is_bare_jump() {
+    let (mut buf, gen) = gen_prologue_epilogue();
+    let exit_offset = buf.offset();
+    gen.emit_exit_tb(&mut buf, 0);
+    let code = &buf.as_slice()[exit_offset..];
+    assert_eq!(code.len(), 5, "exit_tb(0) should be a lone jmp rel32");
+    let disp = i32::from_le_bytes(code[1..5].try_into().unwrap());
+    let target = (exit_offset + 5) as i64 + disp as i64;
+    assert_eq!(target as usize, gen.epilogue_return_zero_offset);
+}
+
+#[test]
+fn exit_tb_wide_uses_movabs() {
+    let (mut buf, gen) = gen_prologue_epilogue();
+    let exit_offset = buf.offset();
+    gen.emit_exit_tb(&mut buf, 0xffff_ffff);
+    // mov eax, imm32 still covers the full u32 range.
+    assert_eq!(buf.as_slice()[exit_offset..].len(), 10);
+    assert_eq!(buf.as_slice()[exit_offset], 0xB8);
+
+    let exit_offset = buf.offset();
+    gen.emit_exit_tb(&mut buf, (3 << 32) | 1);
+    let code = &buf.as_slice()[exit_offset..];
+    // movabs rax, imm64 = 48 B8 imm64, then jmp rel32.
+    assert_eq!(&code[..2], [0x48, 0xB8]);
+    assert_eq!(code.len(), 15);
+}
+
+/// Raw `rax` seen by the caller after running `exit_tb(val)`.
+fn run_exit_tb(val: u64) -> usize {
+    let (mut buf, gen) = gen_prologue_epilogue();
+    let tb_start = buf.offset();
+    gen.emit_exit_tb(&mut buf, val);
+    let mut env = [0u64; 128];
+    let prologue: unsafe extern "C" fn(*mut u8, *const u8, *mut u64) -> usize =
+        unsafe { std::mem::transmute(buf.base_ptr()) };
+    unsafe {
+        prologue(
+            env.as_mut_ptr().cast(),
+            buf.ptr_at(tb_start),
+            tcg_core::helper::pending_ptr(),
+        )
+    }
+}
+
+#[test]
+fn exit_tb_values_round_trip() {
+    for val in [0, 1, 0x1234, 0xffff_ffff, (7 << 32) | 2, u64::MAX] {
+        assert_eq!(run_exit_tb(val) as u64, val, "{val:#x}");
+    }
+}
+
 #[test]
 fn goto_tb_alignment_padding() {
     let mut buf = CodeBuffer::new(4096).unwrap();
@@ -466,6 +519,15 @@ fn mov_ri_zero() {
     assert_eq!(code, [0x31, 0xC0]);
 }
 
+#[test]
+fn mov_ri_keep_flags_zero() {
+    // mov r9d, 0 => 41 B9 00 00 00 00, not xor
+    let code = emit_bytes(|b| emit_mov_ri_keep_flags(b, true, Reg::R9, 0));
+    assert_eq!(code, [0x41, 0xB9, 0x00, 0x00, 0x00, 0x00]);
+    let code = emit_bytes(|b| emit_mov_ri_keep_flags(b, true, Reg::Rax, 7));
+    assert_eq!(code, emit_bytes(|b| emit_mov_ri(b, true, Reg::Rax, 7)));
+}
+
 #[test]
 fn mov_ri_u32() {
     // mov eax, 0x1234 => B8 34 12 00 00
@@ -673,13 +735,13 @@ fn idiv_64() {
 
 #[test]
 fn cdq_test() {
-    let code = emit_bytes(|b| emit_cdq(b));
+    let code = emit_bytes(emit_cdq);
     assert_eq!(code, [0x99]);
 }
 
 #[test]
 fn cqo_test() {
-    let code = emit_bytes(|b| emit_cqo(b));
+    let code = emit_bytes(emit_cqo);
     assert_eq!(code, [0x48, 0x99]);
 }
 
@@ -988,19 +1050,19 @@ fn push_imm32() {
 
 #[test]
 fn ret_test() {
-    let code = emit_bytes(|b| emit_ret(b));
+    let code = emit_bytes(emit_ret);
     assert_eq!(code, [0xC3]);
 }
 
 #[test]
 fn mfence_test() {
-    let code = emit_bytes(|b| emit_mfence(b));
+    let code = emit_bytes(emit_mfence);
     assert_eq!(code, [0x0F, 0xAE, 0xF0]);
 }
 
 #[test]
 fn ud2_test() {
-    let code = emit_bytes(|b| emit_ud2(b));
+    let code = emit_bytes(emit_ud2);
     assert_eq!(code, [0x0F, 0x0B]);
 }
 
@@ -1030,6 +1092,22 @@ fn nop_8() {
     assert_eq!(code[1], 0x1F);
 }
 
+#[test]
+fn emit_align_pads_with_nops() {
+    let backend = X86_64CodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    buf.emit_u8(0xC3);
+    assert_eq!(buf.padding_to(16), 15);
+    assert_eq!(backend.emit_align(&mut buf, 16), 15);
+    assert_eq!(buf.offset(), 16);
+    // 15 bytes = one 8-byte NOP + one 7-byte NOP.
+    assert_eq!(&buf.as_slice()[1..4], [0x0F, 0x1F, 0x84]);
+    assert_eq!(&buf.as_slice()[9..12], [0x0F, 0x1F, 0x80]);
+    assert_eq!(backend.emit_align(&mut buf, 16), 0);
+    assert_eq!(backend.emit_align(&mut buf, 1), 0);
+    assert_eq!(buf.offset(), 16);
+}
+
 #[test]
 fn inc_32() {
     // inc eax => FF C0
@@ -1093,10 +1171,7 @@ fn modrm_offset_rsp_sib() {
         emit_modrm_offset(b, OPC_MOVL_GvEv, Reg::Rax, Reg::Rsp, 0)
     });
     // Should have SIB byte 0x24
-    assert!(
-        code.iter().any(|&x| x == 0x24),
-        "RSP base should have SIB byte"
-    );
+    assert!(code.contains(&0x24), "RSP base should have SIB byte");
 }
 
 #[test]
@@ -1838,6 +1913,112 @@ fn codegen_setcond_movzx_dil() {
     );
 }
 
+/// Byte and word ops as emitted by `tcg_out_op`, for a low, a
+/// REX-only byte (sil/dil) and an extended register.
+fn byte_op(
+    opc: Opcode,
+    ty: Type,
+    oregs: &[Reg],
+    iregs: &[Reg],
+    cargs: &[u32],
+) -> Vec<u8> {
+    let o: Vec<u8> = oregs.iter().map(|&r| r as u8).collect();
+    let i: Vec<u8> = iregs.iter().map(|&r| r as u8).collect();
+    emit_tcg_op_bytes(Op::new(OpIdx(0), opc, ty), &o, &i, cargs)
+}
+
+#[test]
+fn codegen_ext8u_source_regs() {
+    let ext8u = |d, s| byte_op(Opcode::Extract, Type::I32, &[d], &[s], &[0, 8]);
+    // movzbl eax, cl
+    assert_eq!(ext8u(Reg::Rax, Reg::Rcx), [0x0F, 0xB6, 0xC1]);
+    // movzbl eax, sil (not dh)
+    assert_eq!(ext8u(Reg::Rax, Reg::Rsi), [0x40, 0x0F, 0xB6, 0xC6]);
+    // movzbl edi, dil
+    assert_eq!(ext8u(Reg::Rdi, Reg::Rdi), [0x40, 0x0F, 0xB6, 0xFF]);
+    // movzbl r8d, sil
+    assert_eq!(ext8u(Reg::R8, Reg::Rsi), [0x44, 0x0F, 0xB6, 0xC6]);
+    // movzbl eax, r9b
+    assert_eq!(ext8u(Reg::Rax, Reg::R9), [0x41, 0x0F, 0xB6, 0xC1]);
+}
+
+#[test]
+fn codegen_ext8s_source_regs() {
+    let ext8s = |ty, d, s| byte_op(Opcode::SExtract, ty, &[d], &[s], &[0, 8]);
+    // movsbl eax, cl
+    assert_eq!(ext8s(Type::I32, Reg::Rax, Reg::Rcx), [0x0F, 0xBE, 0xC1]);
+    // movsbl eax, dil (not bh)
+    assert_eq!(
+        ext8s(Type::I32, Reg::Rax, Reg::Rdi),
+        [0x40, 0x0F, 0xBE, 0xC7]
+    );
+    // movsbl ecx, r10b
+    assert_eq!(
+        ext8s(Type::I32, Reg::Rcx, Reg::R10),
+        [0x41, 0x0F, 0xBE, 0xCA]
+    );
+    // movsbq rax, sil
+    assert_eq!(
+        ext8s(Type::I64, Reg::Rax, Reg::Rsi),
+        [0x48, 0x0F, 0xBE, 0xC6]
+    );
+}
+
+#[test]
+fn codegen_st8_st16_source_regs() {
+    let st = |opc, src| byte_op(opc, Type::I64, &[], &[src, Reg::Rbp], &[0x10]);
+    // mov [rbp+0x10], dl
+    assert_eq!(st(Opcode::St8, Reg::Rdx), [0x88, 0x55, 0x10]);
+    // mov [rbp+0x10], sil
+    assert_eq!(st(Opcode::St8, Reg::Rsi), [0x40, 0x88, 0x75, 0x10]);
+    // mov [rbp+0x10], dil
+    assert_eq!(st(Opcode::St8, Reg::Rdi), [0x40, 0x88, 0x7D, 0x10]);
+    // mov [rbp+0x10], r9b
+    assert_eq!(st(Opcode::St8, Reg::R9), [0x44, 0x88, 0x4D, 0x10]);
+    // mov [rbp+0x10], si: 66 before any REX
+    assert_eq!(st(Opcode::St16, Reg::Rsi), [0x66, 0x89, 0x75, 0x10]);
+    // mov [rbp+0x10], r10w
+    assert_eq!(st(Opcode::St16, Reg::R10), [0x66, 0x44, 0x89, 0x55, 0x10]);
+}
+
+#[test]
+fn codegen_ld8s_ld16s_dest_regs() {
+    let ld = |opc, ty, d| byte_op(opc, ty, &[d], &[Reg::Rbp], &[0x10]);
+    // movsbl esi, [rbp+0x10]
+    assert_eq!(
+        ld(Opcode::Ld8S, Type::I32, Reg::Rsi),
+        [0x0F, 0xBE, 0x75, 0x10]
+    );
+    // movsbq rdi, [rbp+0x10]
+    assert_eq!(
+        ld(Opcode::Ld8S, Type::I64, Reg::Rdi),
+        [0x48, 0x0F, 0xBE, 0x7D, 0x10]
+    );
+    // movswl r11d, [rbp+0x10]
+    assert_eq!(
+        ld(Opcode::Ld16S, Type::I32, Reg::R11),
+        [0x44, 0x0F, 0xBF, 0x5D, 0x10]
+    );
+    // movswq rsi, [rbp+0x10]
+    assert_eq!(
+        ld(Opcode::Ld16S, Type::I64, Reg::Rsi),
+        [0x48, 0x0F, 0xBF, 0x75, 0x10]
+    );
+}
+
+#[test]
+fn codegen_deposit_low_byte_word() {
+    let dep = |d, s, len| {
+        byte_op(Opcode::Deposit, Type::I64, &[d], &[d, s], &[0, len])
+    };
+    // mov dil, sil
+    assert_eq!(dep(Reg::Rdi, Reg::Rsi, 8), [0x40, 0x88, 0xF7]);
+    // mov cl, al
+    assert_eq!(dep(Reg::Rcx, Reg::Rax, 8), [0x88, 0xC1]);
+    // mov di, r8w
+    assert_eq!(dep(Reg::Rdi, Reg::R8, 16), [0x66, 0x44, 0x89, 0xC7]);
+}
+
 emit_case!(movzx_sil_reg, [0x40, 0x0F, 0xB6, 0xC6], |b| emit_movzx(
     b,
     OPC_MOVZBL | P_REXB_RM,
@@ -2034,3 +2215,65 @@ jcc_case!(jcc_ja_opcode, X86Cond::Ja, 0x87);
 jcc_case!(jcc_jl_opcode, X86Cond::Jl, 0x8C);
 jcc_case!(jcc_jge_opcode, X86Cond::Jge, 0x8D);
 jcc_case!(jcc_jg_opcode, X86Cond::Jg, 0x8F);
+
+#[test]
+fn codegen_mulsu2() {
+    let (rax, rdx) = (Reg::Rax, Reg::Rdx);
+    let mulsu2 =
+        |a, b| byte_op(Opcode::MulSU2, Type::I64, &[rax, rdx], &[a, b], &[]);
+    assert_eq!(
+        mulsu2(Reg::Rcx, Reg::R9),
+        [
+            0x4C, 0x89, 0xC8, // mov rax, r9
+            0x48, 0xF7, 0xE1, // mul rcx
+            0x48, 0x85, 0xC9, // test rcx, rcx
+            0x0F, 0x89, 0x03, 0x00, 0x00, 0x00, // jns +3
+            0x49, 0x2B, 0xD1, // sub rdx, r9
+        ]
+    );
+}
+
+// -- Disassembler tests --
+
+fn disas_all(buf: &CodeBuffer) -> Vec<String> {
+    let gen = X86_64CodeGen::new();
+    let code = buf.as_slice();
+    let mut off = 0;
+    let mut out = Vec::new();
+    while off < code.len() {
+        let (text, len) = gen.disas_insn(off as u64, &code[off..]);
+        assert!(len > 0);
+        out.push(text);
+        off += len;
+    }
+    out
+}
+
+#[test]
+fn disas_emitter_sequence() {
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    emit_load_sib(&mut buf, true, Reg::Rax, Reg::R14, Reg::Rcx, 3, -8);
+    emit_andn(&mut buf, true, Reg::R8, Reg::Rax, Reg::Rbx);
+    emit_setcc(&mut buf, X86Cond::Jl, Reg::Rsi);
+    emit_mov_ri(&mut buf, true, Reg::Rdx, 0x1234_5678_9abc);
+    emit_jcc(&mut buf, X86Cond::Je, 0x100);
+    emit_bswap(&mut buf, false, Reg::R9);
+    assert_eq!(
+        disas_all(&buf),
+        [
+            "mov rax, qword ptr [r14+rcx*8-0x8]",
+            "andn r8, rax, rbx",
+            "setl sil",
+            "movabs rdx, 0x123456789abc",
+            "je 0x100",
+            "bswap r9d",
+        ]
+    );
+}
+
+#[test]
+fn disas_unknown_byte() {
+    let gen = X86_64CodeGen::new();
+    assert_eq!(gen.disas_insn(0, &[0x06]), (".byte 0x06".to_string(), 1));
+    assert_eq!(gen.disas_insn(0, &[]).1, 0);
+}
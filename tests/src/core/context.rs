@@ -2,7 +2,7 @@ use tcg_core::context::Context;
 use tcg_core::op::{Op, OpIdx};
 use tcg_core::opcode::Opcode;
 use tcg_core::temp::{TempIdx, TempKind};
-use tcg_core::types::{RegSet, Type};
+use tcg_core::types::{MemOp, RegSet, Type};
 
 #[test]
 fn context_new_temp() {
@@ -24,6 +24,42 @@ fn context_new_temp_tb() {
     assert_eq!(ctx.temp(t).kind, TempKind::Tb);
 }
 
+#[test]
+fn context_temp_accessors() {
+    let mut ctx = Context::new();
+    let t = ctx.new_temp_tb(Type::I32);
+    let c = ctx.new_const(Type::I64, 42);
+    assert_eq!(ctx.temp_kind(t), TempKind::Tb);
+    assert_eq!(ctx.temp_type(t), Type::I32);
+    assert_eq!(ctx.temp_const_val(t), None);
+    assert_eq!(ctx.temp_kind(c), TempKind::Const);
+    assert_eq!(ctx.temp_type(c), Type::I64);
+    assert_eq!(ctx.temp_const_val(c), Some(42));
+}
+
+#[test]
+fn context_new_temp_i128() {
+    let mut ctx = Context::new();
+    ctx.new_temp(Type::I32);
+    let t = ctx.new_temp_i128();
+    let (lo, hi) = ctx.i128_halves(t);
+    assert_eq!((lo, hi), (TempIdx(1), TempIdx(2)));
+    assert_eq!(ctx.nb_temps(), 3);
+    for h in [lo, hi] {
+        assert_eq!(ctx.temp(h).ty, Type::I64);
+        assert_eq!(ctx.temp(h).base_type, Type::I128);
+        assert_eq!(ctx.temp(h).kind, TempKind::Ebb);
+    }
+}
+
+#[test]
+#[should_panic(expected = "temp 0 is not 128-bit")]
+fn context_i128_halves_rejects_i64() {
+    let mut ctx = Context::new();
+    let t = ctx.new_temp(Type::I64);
+    ctx.i128_halves(t);
+}
+
 #[test]
 fn context_const_dedup() {
     let mut ctx = Context::new();
@@ -57,6 +93,24 @@ fn context_globals() {
     assert_eq!(ctx.nb_temps(), 4);
 }
 
+/// Constants are shared within a TB, not across a reset.
+#[test]
+fn context_reset_clears_const_table() {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    ctx.new_temp(Type::I64);
+    let zeros: Vec<TempIdx> =
+        (0..50).map(|_| ctx.new_const(Type::I64, 0)).collect();
+    assert!(zeros.iter().all(|&z| z == zeros[0]));
+    assert_eq!(ctx.nb_temps(), 3);
+
+    ctx.reset();
+    let z = ctx.new_const(Type::I64, 0);
+    assert_eq!(z, TempIdx(1));
+    assert_eq!(ctx.temp_const_val(z), Some(0));
+    assert_ne!(z, env);
+}
+
 #[test]
 fn context_reset_preserves_globals() {
     let mut ctx = Context::new();
@@ -83,6 +137,20 @@ fn context_reset_preserves_globals() {
     assert!(ctx.labels().is_empty());
 }
 
+#[test]
+fn context_reset_keeps_op_capacity() {
+    let mut ctx = Context::with_capacity(8, 4);
+    assert!(ctx.op_capacity() >= 8);
+    for _ in 0..100 {
+        let idx = ctx.next_op_idx();
+        ctx.emit_op(Op::new(idx, Opcode::Nop, Type::I32));
+    }
+    let grown = ctx.op_capacity();
+    assert!(grown >= 100);
+    ctx.reset();
+    assert_eq!(ctx.op_capacity(), grown);
+}
+
 #[test]
 fn context_emit_ops() {
     let mut ctx = Context::new();
@@ -142,3 +210,83 @@ fn context_global_after_local_panics() {
     ctx.new_temp(Type::I32); // local
     ctx.new_global(Type::I64, env, 0, "x"); // should panic
 }
+
+#[test]
+fn context_insn_meta_side_table() {
+    let meta = tcg_core::InsnMeta {
+        name: "mul",
+        exts: &["M"],
+    };
+    let mut ctx = Context::new();
+    ctx.record_insn_meta(0x1000, meta);
+    assert!(ctx.insn_meta().is_none(), "off by default");
+
+    ctx.enable_insn_meta();
+    ctx.record_insn_meta(0x1000, meta);
+    assert_eq!(ctx.insn_meta().unwrap()[&0x1000], meta);
+
+    // Entries are per TB; recording stays on.
+    ctx.reset();
+    assert!(ctx.insn_meta().unwrap().is_empty());
+    assert!(ctx.insn_meta_enabled());
+}
+
+#[test]
+fn context_exit_tb_typed_code() {
+    use tcg_core::tb::{TbExit, TB_EXIT_CUSTOM};
+    use tcg_frontend::riscv::excp::EXCP_EBREAK;
+    let mut ctx = Context::new();
+    ctx.gen_exit_tb(TbExit::Exception(EXCP_EBREAK));
+    ctx.gen_exit_tb(TbExit::Custom(7));
+    ctx.gen_exit_tb_raw(1);
+    let codes: Vec<u32> = ctx.ops().iter().map(|op| op.cargs()[0].0).collect();
+    assert_eq!(codes, [EXCP_EBREAK, TB_EXIT_CUSTOM + 7, 1]);
+}
+
+#[test]
+#[should_panic(expected = "is reserved")]
+fn context_exit_tb_raw_rejects_chain_marker() {
+    Context::new().gen_exit_tb_raw(1 << 32);
+}
+
+#[test]
+#[should_panic(expected = "is reserved")]
+fn context_exit_tb_raw_rejects_helper_panic() {
+    Context::new().gen_exit_tb_raw(u32::MAX as u64);
+}
+
+#[test]
+fn qemu_ld_accepts_atomic_aligned() {
+    let mut ctx = Context::new();
+    let (d, a) = (ctx.new_temp(Type::I64), ctx.new_temp(Type::I64));
+    let memop = MemOp::uq().aligned().atomic();
+    ctx.gen_qemu_ld(Type::I64, d, a, memop.bits() as u32);
+    let memop = MemOp::new(MemOp::SIZE_32 | MemOp::ALIGN_8).atomic();
+    ctx.gen_qemu_st(Type::I64, d, a, memop.bits() as u32);
+}
+
+#[test]
+#[should_panic(expected = "atomic 8-byte access may be misaligned")]
+fn qemu_ld_rejects_unaligned_atomic() {
+    let mut ctx = Context::new();
+    let (d, a) = (ctx.new_temp(Type::I64), ctx.new_temp(Type::I64));
+    let memop = MemOp::uq().atomic();
+    ctx.gen_qemu_ld(Type::I64, d, a, memop.bits() as u32);
+}
+
+#[test]
+#[should_panic(expected = "atomic 16-byte access may be misaligned")]
+fn qemu_ld2_atomic_needs_pair_alignment() {
+    let mut ctx = Context::new();
+    let t = [0; 3].map(|_| ctx.new_temp(Type::I64));
+    let memop = MemOp::new(MemOp::SIZE_64 | MemOp::ALIGN_8).atomic();
+    ctx.gen_qemu_ld2(Type::I64, t[0], t[1], t[2], memop.bits() as u32);
+}
+
+#[test]
+#[should_panic(expected = "8-byte access into I32")]
+fn qemu_st_rejects_oversized_access() {
+    let mut ctx = Context::new();
+    let (v, a) = (ctx.new_temp(Type::I32), ctx.new_temp(Type::I64));
+    ctx.gen_qemu_st(Type::I32, v, a, MemOp::uq().bits() as u32);
+}
@@ -0,0 +1,158 @@
+use serde_json::Value;
+use tcg_backend::liveness::{liveness_analysis, pressure_report};
+use tcg_core::context::Context;
+use tcg_core::dump::{dump_ops, dump_ops_json, dump_ops_json_with};
+use tcg_core::tb::TbExit;
+use tcg_core::types::{Cond, Type};
+
+fn nums(v: &Value) -> Vec<u64> {
+    v.as_array()
+        .unwrap()
+        .iter()
+        .map(|n| n.as_u64().unwrap())
+        .collect()
+}
+
+fn strs(v: &Value) -> Vec<&str> {
+    v.as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s.as_str().unwrap())
+        .collect()
+}
+
+/// x1 = x1 + 42; if x1 < 0 skip to the exit; x1 = -x1.
+fn small_tb() -> Context {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let tmp = ctx.new_temp(Type::I64);
+    let c42 = ctx.new_const(Type::I64, 42);
+    let zero = ctx.new_const(Type::I64, 0);
+    let done = ctx.new_label();
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, tmp, x1, c42);
+    ctx.gen_mov(Type::I64, x1, tmp);
+    ctx.gen_brcond(Type::I64, x1, zero, Cond::Lt, done);
+    ctx.gen_neg(Type::I64, x1, x1);
+    ctx.gen_set_label(done);
+    ctx.gen_exit_tb(TbExit::Normal);
+    ctx
+}
+
+#[test]
+fn test_dump_json_round_trip() {
+    let ctx = small_tb();
+    let mut out = Vec::new();
+    dump_ops_json(&ctx, &mut out, None).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+    assert!(json.get("pressure").is_none());
+
+    let ops = json["ops"].as_array().unwrap();
+    assert_eq!(ops.len(), ctx.ops().len());
+    for (o, op) in ops.iter().zip(ctx.ops()) {
+        let args: Vec<u64> = op.oargs().iter().map(|a| a.0 as u64).collect();
+        assert_eq!(nums(&o["oargs"]), args);
+        let args: Vec<u64> = op.iargs().iter().map(|a| a.0 as u64).collect();
+        assert_eq!(nums(&o["iargs"]), args);
+    }
+    let names: Vec<&str> =
+        ops.iter().map(|o| o["opc"].as_str().unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "insn_start",
+            "add_i64",
+            "mov_i64",
+            "brcond_i64",
+            "neg_i64",
+            "set_label",
+            "exit_tb"
+        ]
+    );
+    assert_eq!(ops[0]["pc"], 0x1000);
+    assert!(ops[0].get("guest_asm").is_none());
+    assert!(ops[1].get("pc").is_none());
+    assert_eq!(strs(&ops[1]["onames"]), ["tmp0"]);
+    assert_eq!(strs(&ops[1]["inames"]), ["x1", "$0x2a"]);
+    let br = &ops[3];
+    assert_eq!(br["type"], "i64");
+    assert_eq!(nums(&br["labels"]), [0]);
+    assert_eq!(nums(&br["cargs"]), [Cond::Lt as u64, 0]);
+    assert_eq!(nums(&ops[5]["labels"]), [0]);
+    assert!(nums(&ops[1]["labels"]).is_empty());
+
+    let temps = json["temps"].as_array().unwrap();
+    assert_eq!(temps.len(), ctx.nb_temps() as usize);
+    let kinds: Vec<&str> =
+        temps.iter().map(|t| t["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["Fixed", "Global", "Temp", "Const", "Const"]);
+    assert_eq!(temps[0]["reg"], 5);
+    assert_eq!(temps[1]["name"], "x1");
+    assert_eq!(temps[1]["offset"], 8);
+    assert_eq!(temps[1]["base"], 0);
+    assert_eq!(temps[2]["scope"], "ebb");
+    assert_eq!(temps[3]["value"], 42);
+    assert_eq!(temps[3]["type"], "i64");
+
+    // The text dump is unaffected.
+    let mut text = Vec::new();
+    dump_ops(&ctx, &mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains(" brcond_i64 x1, $0x0, lt, L0\n"), "{text}");
+}
+
+#[test]
+fn test_dump_json_guest_asm() {
+    let mut ctx = small_tb();
+    ctx.gen_insn_start(0x1004);
+    let mut out = Vec::new();
+    dump_ops_json_with(&ctx, &mut out, None, |pc, w| {
+        writeln!(w, "  insn @ \"{pc:#x}\"")
+    })
+    .unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+
+    let starts: Vec<&Value> = json["ops"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|o| o["opc"] == "insn_start")
+        .collect();
+    assert_eq!(starts.len(), 2);
+    assert_eq!(starts[0]["guest_asm"], "insn @ \"0x1000\"");
+    assert_eq!(starts[1]["pc"], 0x1004);
+    assert_eq!(starts[1]["guest_asm"], "insn @ \"0x1004\"");
+}
+
+#[test]
+fn test_dump_json_pressure() {
+    let mut ctx = small_tb();
+    liveness_analysis(&mut ctx);
+    let report = pressure_report(&ctx, 1);
+    let mut out = Vec::new();
+    dump_ops_json(&ctx, &mut out, Some(&report)).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+
+    let p = &json["pressure"];
+    assert_eq!(p["max_live"], report.max_live);
+    assert_eq!(p["max_op"], report.max_op.unwrap());
+    assert_eq!(p["spill_sites"], report.spill_sites);
+    let timeline: Vec<u64> =
+        report.timeline.iter().map(|&n| n as u64).collect();
+    assert_eq!(nums(&p["timeline"]), timeline);
+    assert_eq!(timeline.len(), json["ops"].as_array().unwrap().len());
+    // x1 and tmp are both resident at the add and the mov,
+    // one more than the single register.
+    assert_eq!(p["max_live_by_type"]["i64"], 2);
+    assert_eq!(p["max_live_by_type"]["i32"], 0);
+    assert_eq!(p["max_live_by_type"].as_object().unwrap().len(), 6);
+    assert_eq!(report.spill_sites, 2);
+
+    let empty = Context::new();
+    let report = pressure_report(&empty, 1);
+    let mut out = Vec::new();
+    dump_ops_json(&empty, &mut out, Some(&report)).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+    assert!(json["pressure"]["max_op"].is_null());
+}
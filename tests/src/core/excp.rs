@@ -0,0 +1,94 @@
+use tcg_core::excp::*;
+use tcg_core::tb::{TB_EXIT_CUSTOM, TB_EXIT_MAX};
+use tcg_frontend::riscv::excp::{self as rv, EXCP_EBREAK, EXCP_ECALL};
+
+#[test]
+fn registry_round_trips_names_and_codes() {
+    let mut reg = ExcpRegistry::new();
+    reg.register(EXCP_FRONTEND_BASE + 7, "seven");
+    reg.register(EXCP_FRONTEND_BASE, "zero");
+    assert_eq!(reg.name(EXCP_FRONTEND_BASE + 7), Some("seven"));
+    assert_eq!(reg.code("seven"), Some(EXCP_FRONTEND_BASE + 7));
+    assert_eq!(reg.code("zero"), Some(EXCP_FRONTEND_BASE));
+    assert_eq!(reg.name(EXCP_FRONTEND_BASE + 1), None);
+    assert_eq!(reg.code("one"), None);
+    assert!(!reg.contains(EXCP_FRONTEND_BASE + 1));
+}
+
+#[test]
+fn registry_names_core_codes() {
+    let reg = ExcpRegistry::new();
+    for c in [
+        CoreExcp::Debug,
+        CoreExcp::Watchpoint,
+        CoreExcp::Interrupted,
+        CoreExcp::Fault,
+    ] {
+        assert!(c.code() >= EXCP_CORE_BASE && c.code() < TB_EXIT_CUSTOM);
+        assert_eq!(CoreExcp::from_code(c.code()), Some(c));
+        assert_eq!(reg.name(c.code()), Some(c.name()));
+        assert_eq!(reg.code(c.name()), Some(c.code()));
+    }
+}
+
+#[test]
+fn classify_codes() {
+    assert_eq!(EXCP_FRONTEND_BASE, TB_EXIT_MAX);
+    assert_eq!(ExcpClass::of(EXCP_ECALL), ExcpClass::Frontend(EXCP_ECALL));
+    assert_eq!(
+        ExcpClass::of(CoreExcp::Fault.code()),
+        ExcpClass::Core(CoreExcp::Fault)
+    );
+    let spare = TB_EXIT_CUSTOM - 1;
+    assert_eq!(ExcpClass::of(spare), ExcpClass::Reserved(spare));
+}
+
+#[test]
+fn describe_names_the_raw_value() {
+    let reg = rv::registry();
+    assert_eq!(reg.describe(EXCP_EBREAK).to_string(), "ebreak (0x4)");
+    assert_eq!(
+        reg.describe(0x1234).to_string(),
+        "unregistered exception 0x1234"
+    );
+}
+
+/// The RISC-V codes kept their values from before the registry.
+#[test]
+fn riscv_codes_keep_their_values() {
+    let reg = rv::registry();
+    let codes = [
+        ("ecall", 3),
+        ("ebreak", 4),
+        ("illegal instruction", 5),
+        ("load misaligned", 6),
+        ("store misaligned", 7),
+        ("fetch misaligned", 8),
+    ];
+    for (name, code) in codes {
+        assert_eq!(reg.code(name), Some(code), "{name}");
+    }
+    assert_eq!(rv::EXCP_STORE_MISALIGNED, 7);
+}
+
+#[test]
+#[should_panic(expected = "outside the frontend range")]
+fn register_rejects_core_codes() {
+    ExcpRegistry::new().register(CoreExcp::Debug.code(), "mine");
+}
+
+#[test]
+#[should_panic(expected = "registered twice")]
+fn register_rejects_duplicate_codes() {
+    let mut reg = ExcpRegistry::new();
+    reg.register(EXCP_FRONTEND_BASE, "a");
+    reg.register(EXCP_FRONTEND_BASE, "b");
+}
+
+#[test]
+#[should_panic(expected = "registered twice")]
+fn register_rejects_duplicate_names() {
+    let mut reg = ExcpRegistry::new();
+    reg.register(EXCP_FRONTEND_BASE, "a");
+    reg.register(EXCP_FRONTEND_BASE + 1, "a");
+}
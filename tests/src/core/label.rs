@@ -1,4 +1,13 @@
 use tcg_core::label::*;
+use tcg_core::{OpIdx, Opcode};
+
+fn site(op: u32) -> LabelSite {
+    LabelSite {
+        op: OpIdx(op),
+        opc: Opcode::Br,
+        pc: None,
+    }
+}
 
 #[test]
 fn label_new() {
@@ -12,8 +21,8 @@ fn label_new() {
 #[test]
 fn label_add_use() {
     let mut l = Label::new(1);
-    l.add_use(100, RelocKind::Rel32);
-    l.add_use(200, RelocKind::Rel32);
+    l.add_use(100, RelocKind::Rel32, site(0));
+    l.add_use(200, RelocKind::Rel32, site(0));
     assert_eq!(l.uses.len(), 2);
     assert_eq!(l.uses[0].offset, 100);
     assert_eq!(l.uses[1].offset, 200);
@@ -23,7 +32,7 @@ fn label_add_use() {
 #[test]
 fn label_resolve() {
     let mut l = Label::new(2);
-    l.add_use(50, RelocKind::Rel32);
+    l.add_use(50, RelocKind::Rel32, site(0));
     assert!(l.has_pending_uses());
 
     l.set_value(300);
@@ -44,7 +53,42 @@ fn label_reloc_kind() {
     let u = LabelUse {
         offset: 42,
         kind: RelocKind::Rel32,
+        site: site(7),
     };
     assert_eq!(u.kind, RelocKind::Rel32);
     assert_eq!(u.offset, 42);
+    assert_eq!(u.site.op, OpIdx(7));
+}
+
+#[test]
+fn label_rel32_range() {
+    assert!(RelocKind::Rel32.fits(i32::MAX as i64));
+    assert!(RelocKind::Rel32.fits(i32::MIN as i64));
+    assert!(!RelocKind::Rel32.fits(i32::MAX as i64 + 1));
+    assert!(!RelocKind::Rel32.fits(i32::MIN as i64 - 1));
+}
+
+#[test]
+fn label_a64_branch_range() {
+    // B: +-128 MiB in words, from the branch itself.
+    assert_eq!(RelocKind::Imm26.disp(0x100, 0x80), -0x80);
+    assert_eq!(RelocKind::Rel32.disp(0x100, 0x80), -0x84);
+    assert!(RelocKind::Imm26.fits((1 << 27) - 4));
+    assert!(RelocKind::Imm26.fits(-(1 << 27)));
+    assert!(!RelocKind::Imm26.fits(1 << 27));
+    assert!(!RelocKind::Imm26.fits(-(1 << 27) - 4));
+    assert!(!RelocKind::Imm26.fits(2));
+    // B.cond: +-1 MiB.
+    assert!(RelocKind::Imm19.fits((1 << 20) - 4));
+    assert!(RelocKind::Imm19.fits(-(1 << 20)));
+    assert!(!RelocKind::Imm19.fits(1 << 20));
+}
+
+#[test]
+fn label_site_display() {
+    let mut s = site(42);
+    s.opc = Opcode::BrCond;
+    assert_eq!(s.to_string(), "brcond at op #42");
+    s.pc = Some(0x104a8);
+    assert_eq!(s.to_string(), "brcond at op #42 (guest pc 0x104a8)");
 }
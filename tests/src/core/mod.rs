@@ -1,4 +1,6 @@
 mod context;
+mod dump;
+mod excp;
 mod label;
 mod op;
 mod opcode;
@@ -7,3 +9,4 @@ mod serialize;
 mod tb;
 mod temp;
 mod types;
+mod verify;
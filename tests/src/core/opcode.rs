@@ -225,7 +225,14 @@ fn opcode_def_full_coverage() {
         int,
     );
     assert_group(&mut seen, &[Opcode::DivS2, Opcode::DivU2], 2, 3, 0, int);
-    assert_group(&mut seen, &[Opcode::MulS2, Opcode::MulU2], 2, 2, 0, int);
+    assert_group(
+        &mut seen,
+        &[Opcode::MulS2, Opcode::MulU2, Opcode::MulSU2],
+        2,
+        2,
+        0,
+        int,
+    );
     assert_group(
         &mut seen,
         &[Opcode::AddCO, Opcode::AddC1O, Opcode::SubBO, Opcode::SubB1O],
@@ -322,7 +329,7 @@ fn opcode_def_full_coverage() {
     assert_group(&mut seen, &[Opcode::GotoPtr], 0, 1, 0, bx_be);
     assert_group(&mut seen, &[Opcode::Mb, Opcode::PluginCb], 0, 0, 1, np);
 
-    assert_group(&mut seen, &[Opcode::Call], 1, 6, 2, cc_np);
+    assert_group(&mut seen, &[Opcode::Call], 1, 6, 3, cc_np);
     assert_group(&mut seen, &[Opcode::PluginMemCb], 0, 1, 1, np);
     assert_group(&mut seen, &[Opcode::Nop], 0, 0, 0, np);
     assert_group(&mut seen, &[Opcode::Discard], 1, 0, 0, np);
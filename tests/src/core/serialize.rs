@@ -3,7 +3,9 @@ use std::io::Cursor;
 use tcg_core::context::Context;
 use tcg_core::op::Op;
 use tcg_core::opcode::Opcode;
-use tcg_core::serialize;
+use tcg_core::serialize::{
+    self, DeserializeError, IrMeta, IrRecord, MetaWarning,
+};
 use tcg_core::temp::TempIdx;
 use tcg_core::types::Type;
 
@@ -259,15 +261,17 @@ fn serialize_multiple_tbs() {
 #[test]
 fn serialize_op_params() {
     let mut ctx = Context::new();
-    let _env = ctx.new_fixed(Type::I64, 5, "env");
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let x2 = ctx.new_global(Type::I64, env, 16, "x2");
 
     let idx = ctx.next_op_idx();
     let mut op = Op::new(idx, Opcode::Extract, Type::I64);
     op.param1 = 7;
     op.param2 = 3;
     op.nargs = 4;
-    op.args[0] = TempIdx(1);
-    op.args[1] = TempIdx(2);
+    op.args[0] = x1;
+    op.args[1] = x2;
     op.args[2] = TempIdx(8); // pos
     op.args[3] = TempIdx(16); // len
     ctx.emit_op(op);
@@ -307,7 +311,7 @@ fn deserialize_bad_magic() {
         b"BAAD\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00";
     let mut cursor = Cursor::new(&data[..]);
     let result = serialize::deserialize(&mut cursor);
-    assert!(result.is_err());
+    assert!(matches!(result, Err(DeserializeError::BadMagic)));
 }
 
 // -- Deserialize: empty file --
@@ -320,3 +324,400 @@ fn deserialize_empty_file() {
         serialize::deserialize(&mut cursor).expect("empty file should be OK");
     assert!(result.is_empty());
 }
+
+// -- Metadata --
+
+/// A TB of three guest instructions at 0x1000..=0x1008.
+fn three_insn_tb() -> Context {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    for pc in [0x1000, 0x1004, 0x1008] {
+        ctx.gen_insn_start(pc);
+        ctx.gen_add(Type::I64, x1, x1, x1);
+    }
+    ctx.gen_exit_tb_raw(0);
+    ctx
+}
+
+fn meta(arch: &str, isa: &str) -> IrMeta {
+    IrMeta {
+        arch: arch.to_string(),
+        pc_range: None,
+        cfg_hash: IrMeta::hash_cfg(isa),
+        producer: "tcg-irdump 0.1.0".to_string(),
+        extra: vec![("isa".to_string(), isa.to_string())],
+    }
+}
+
+fn records_of(parts: &[(&Context, Option<&IrMeta>)]) -> Vec<IrRecord> {
+    let mut buf = Vec::new();
+    for (ctx, m) in parts {
+        match m {
+            Some(m) => serialize::serialize_with_meta(ctx, m, &mut buf),
+            None => serialize::serialize(ctx, &mut buf),
+        }
+        .expect("serialize failed");
+    }
+    serialize::deserialize_records(&mut Cursor::new(&buf))
+        .expect("deserialize failed")
+}
+
+#[test]
+fn serialize_meta_round_trip() {
+    let ctx = three_insn_tb();
+    let m = meta("riscv64", "rv64imafdc_zicsr_zifencei");
+    let recs = records_of(&[(&ctx, Some(&m))]);
+    assert_eq!(recs.len(), 1);
+    let got = recs[0].meta.as_ref().expect("metadata");
+    assert_eq!(got.arch, "riscv64");
+    assert_eq!(got.cfg_hash, m.cfg_hash);
+    assert_eq!(got.producer, m.producer);
+    assert_eq!(got.extra, m.extra);
+    assert_eq!(recs[0].ctx.num_ops(), ctx.num_ops());
+
+    let plain = records_of(&[(&ctx, None)]);
+    assert!(plain[0].meta.is_none());
+}
+
+#[test]
+fn serialize_meta_pc_range() {
+    let ctx = three_insn_tb();
+    assert_eq!(serialize::pc_range(&ctx), Some((0x1000, 0x1008)));
+    assert_eq!(serialize::pc_range(&Context::new()), None);
+
+    // The caller's range is replaced by the computed one.
+    let mut m = meta("riscv64", "rv64i");
+    m.pc_range = Some((0, 0));
+    let recs = records_of(&[(&ctx, Some(&m))]);
+    let got = recs[0].meta.as_ref().unwrap();
+    assert_eq!(got.pc_range, Some((0x1000, 0x1008)));
+    assert!(got.to_string().contains("pc 0x1000..=0x1008"));
+}
+
+#[test]
+fn serialize_meta_rejects_oversized_fields() {
+    use std::io::ErrorKind;
+
+    let ctx = three_insn_tb();
+    let write = |m: &IrMeta| {
+        serialize::serialize_with_meta(&ctx, m, &mut Vec::new())
+            .map_err(|e| e.kind())
+    };
+
+    let mut m = meta("riscv64", "rv64i");
+    m.producer = "x".repeat(u16::MAX as usize);
+    assert_eq!(write(&m), Ok(()));
+    m.producer.push('x');
+    assert_eq!(write(&m), Err(ErrorKind::InvalidInput));
+
+    let mut m = meta("riscv64", "rv64i");
+    m.extra = vec![(String::new(), String::new()); u16::MAX as usize + 1];
+    assert_eq!(write(&m), Err(ErrorKind::InvalidInput));
+}
+
+#[test]
+fn serialize_meta_mixed_file_warns() {
+    let ctx = three_insn_tb();
+    let a = meta("riscv64", "rv64imafdc_zicsr_zifencei");
+    let b = meta("riscv64", "rv64i");
+    let recs = records_of(&[(&ctx, Some(&a)), (&ctx, Some(&a))]);
+    assert!(serialize::check_records(&recs, Some("riscv64")).is_empty());
+
+    let recs = records_of(&[(&ctx, Some(&a)), (&ctx, Some(&b)), (&ctx, None)]);
+    let warnings = serialize::check_records(&recs, None);
+    assert_eq!(warnings.len(), 2);
+    assert!(matches!(
+        warnings[0],
+        MetaWarning::Inconsistent {
+            tb: 1,
+            field: "cfg",
+            ..
+        }
+    ));
+    assert_eq!(warnings[1], MetaWarning::Missing { tb: 2 });
+
+    let warnings = serialize::check_records(&recs[..1], Some("x86_64"));
+    assert_eq!(
+        warnings[0].to_string(),
+        "TB #0: arch riscv64, expected x86_64"
+    );
+}
+
+// -- Validation on load --
+
+#[test]
+fn deserialize_rejects_invalid_ir() {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    ctx.gen_add(Type::I64, x1, x1, TempIdx(7));
+    let mut buf = Vec::new();
+    serialize::serialize(&ctx, &mut buf).unwrap();
+
+    let Err(e) = serialize::deserialize(&mut Cursor::new(&buf)) else {
+        panic!("corrupt IR loaded");
+    };
+    assert!(matches!(e, DeserializeError::InvalidIr { tb: 0, .. }));
+    assert_eq!(
+        e.to_string(),
+        "TB #0: invalid IR: add at op #0: temp 7 out of range"
+    );
+}
+
+#[test]
+fn deserialize_corrupt_bytes_do_not_panic() {
+    let mut ctx = three_insn_tb();
+    let l = ctx.new_label();
+    ctx.gen_br(l);
+    ctx.gen_set_label(l);
+    let mut buf = Vec::new();
+    serialize::serialize(&ctx, &mut buf).unwrap();
+
+    for i in 0..buf.len() {
+        for byte in [0x00, 0x7F, 0xFF] {
+            let mut bad = buf.clone();
+            bad[i] = byte;
+            let _ = serialize::deserialize(&mut Cursor::new(&bad));
+        }
+        let _ = serialize::deserialize(&mut Cursor::new(&buf[..i]));
+    }
+}
+
+// -- Format: headers and round-trip guarantees --
+
+/// Offsets of file and record header fields.
+const VERSION_AT: usize = 5;
+const BOM_AT: usize = 7;
+const TB_COUNT_AT: usize = 11;
+const PC_AT: usize = 31;
+const RECORD_AT: usize = 39;
+
+/// A valid TB using every opcode, with a temp of each kind.
+fn every_opcode_tb() -> Context {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let g64 = ctx.new_global(Type::I64, env, 8, "x1");
+    let g32 = ctx.new_global(Type::I32, env, 16, "w1");
+    ctx.gen_insn_start(0x8000_0000_1000);
+    let tb = ctx.new_temp_tb(Type::I64);
+    let c = ctx.new_const(Type::I64, 0x1234);
+    ctx.gen_add(Type::I64, tb, g64, c);
+    // Constant args are all 0, which is also this label's id.
+    let label = ctx.new_label();
+    assert_eq!(label, 0);
+    for v in 0..Opcode::Count as u8 {
+        let opc = Opcode::from_u8(v).unwrap();
+        if opc == Opcode::SetLabel {
+            continue;
+        }
+        let def = opc.def();
+        let ty = opc.fixed_type().unwrap_or(Type::I64);
+        let input = match opc {
+            Opcode::ExtrlI64I32 | Opcode::ExtrhI64I32 => g64,
+            _ if ty == Type::I32 => g32,
+            _ => g64,
+        };
+        let mut op = Op::new(ctx.next_op_idx(), opc, ty);
+        op.nargs = def.nb_args();
+        op.param1 = v;
+        for i in 0..def.nb_oargs as usize {
+            op.args[i] = ctx.new_temp(ty);
+        }
+        let inputs =
+            def.nb_oargs as usize..(def.nb_oargs + def.nb_iargs) as usize;
+        for i in inputs {
+            op.args[i] = input;
+        }
+        ctx.emit_op(op);
+    }
+    ctx.gen_set_label(label);
+    ctx
+}
+
+/// Assert `a` and `b` hold the same temps and ops.
+fn assert_same(a: &Context, b: &Context) {
+    assert_eq!(a.nb_globals(), b.nb_globals());
+    assert_eq!(a.nb_temps(), b.nb_temps());
+    for (x, y) in a.temps().iter().zip(b.temps()) {
+        let key = |t: &tcg_core::Temp| {
+            (
+                t.kind,
+                t.ty,
+                t.base_type,
+                t.reg,
+                t.val,
+                t.mem_base,
+                t.mem_offset,
+                t.name,
+            )
+        };
+        assert_eq!(key(x), key(y), "temp {}", x.idx.0);
+    }
+    assert_eq!(a.num_ops(), b.num_ops());
+    for (x, y) in a.ops().iter().zip(b.ops()) {
+        let key = |op: &Op| {
+            (
+                op.opc,
+                op.op_type,
+                op.param1,
+                op.param2,
+                op.args[..op.nargs as usize].to_vec(),
+            )
+        };
+        assert_eq!(key(x), key(y), "op {}", x.idx.0);
+    }
+}
+
+#[test]
+fn serialize_every_opcode_round_trip() {
+    let ctx = every_opcode_tb();
+    ctx.validate().expect("builder emits valid IR");
+    let mut seen = vec![false; Opcode::Count as usize];
+    for op in ctx.ops() {
+        seen[op.opc as usize] = true;
+    }
+    assert!(seen.iter().all(|&s| s));
+
+    let mut buf = Vec::new();
+    serialize::serialize(&ctx, &mut buf).unwrap();
+    let recs = serialize::deserialize_records(&mut Cursor::new(&buf))
+        .expect("deserialize failed");
+    assert_eq!(recs.len(), 1);
+    assert_eq!(recs[0].pc, Some(0x8000_0000_1000));
+    assert_same(&ctx, &recs[0].ctx);
+
+    let mut again = Vec::new();
+    serialize::serialize(&recs[0].ctx, &mut again).unwrap();
+    assert_eq!(again, buf);
+}
+
+#[test]
+fn serialize_writes_file_and_record_headers() {
+    let ctx = three_insn_tb();
+    let mut buf = Vec::new();
+    serialize::serialize(&ctx, &mut buf).unwrap();
+    let u32_at =
+        |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+    assert_eq!(&buf[..VERSION_AT], b"TCGIR");
+    assert_eq!(buf[VERSION_AT..BOM_AT], 4u16.to_le_bytes());
+    assert_eq!(buf[BOM_AT..BOM_AT + 2], [0xFF, 0xFE]);
+    assert_eq!(u32_at(TB_COUNT_AT), 1);
+    assert_eq!(u32_at(TB_COUNT_AT + 4), ctx.num_ops() as u32);
+    assert_eq!(u32_at(TB_COUNT_AT + 8), ctx.nb_temps());
+    assert_eq!(u32_at(TB_COUNT_AT + 12), ctx.nb_globals());
+    assert_eq!(u32_at(TB_COUNT_AT + 16), 0);
+    assert_eq!(buf[PC_AT..RECORD_AT], 0x1000u64.to_le_bytes());
+
+    // No insn_start: the pc is all ones.
+    let mut buf = Vec::new();
+    serialize::serialize(&Context::new(), &mut buf).unwrap();
+    assert_eq!(buf[PC_AT..RECORD_AT], [0xFF; 8]);
+    let recs = serialize::deserialize_records(&mut Cursor::new(&buf))
+        .expect("deserialize failed");
+    assert_eq!(recs[0].pc, None);
+}
+
+#[test]
+fn deserialize_rejects_truncated_tb() {
+    let ctx = three_insn_tb();
+    let mut one = Vec::new();
+    serialize::serialize(&ctx, &mut one).unwrap();
+    let mut two = one.clone();
+    serialize::serialize(&ctx, &mut two).unwrap();
+
+    for cut in 1..one.len() {
+        let e = serialize::deserialize(&mut Cursor::new(&one[..cut]))
+            .err()
+            .unwrap_or_else(|| panic!("cut at {cut} loaded"));
+        if cut < TB_COUNT_AT + 4 {
+            assert!(matches!(e, DeserializeError::TruncatedHeader), "{e}");
+        } else {
+            assert!(matches!(e, DeserializeError::Truncated { tb: 0 }));
+        }
+    }
+    for cut in one.len() + RECORD_AT..two.len() {
+        let Err(e) = serialize::deserialize(&mut Cursor::new(&two[..cut]))
+        else {
+            panic!("cut at {cut} loaded a partial TB");
+        };
+        assert!(matches!(e, DeserializeError::Truncated { tb: 1 }));
+        assert_eq!(e.to_string(), "TB #1: file truncated");
+    }
+
+    // The file header promises a second TB that never comes.
+    let mut short = one.clone();
+    short[TB_COUNT_AT] = 2;
+    let Err(e) = serialize::deserialize(&mut Cursor::new(&short)) else {
+        panic!("missing TB not noticed");
+    };
+    assert!(matches!(e, DeserializeError::Truncated { tb: 1 }));
+}
+
+/// Files from an older format, with either magic, are refused
+/// up front rather than parsed as the current layout.
+#[test]
+fn deserialize_rejects_older_versions() {
+    let mut v1 = b"TCGIR".to_vec();
+    v1.extend_from_slice(&1u16.to_le_bytes());
+    v1.extend_from_slice(&[0xAB; 64]);
+    let e = serialize::deserialize(&mut Cursor::new(&v1)).err().unwrap();
+    assert!(matches!(e, DeserializeError::UnsupportedVersion(1)));
+    assert_eq!(
+        e.to_string(),
+        "unsupported .tcgir version 1 (this build reads 4 and legacy 3)"
+    );
+
+    let mut old = b"TCIR".to_vec();
+    old.extend_from_slice(&2u16.to_le_bytes());
+    old.extend_from_slice(&[0; 16]);
+    let e = serialize::deserialize(&mut Cursor::new(&old))
+        .err()
+        .unwrap();
+    assert!(matches!(e, DeserializeError::UnsupportedVersion(2)));
+}
+
+#[test]
+fn deserialize_checks_header_fields() {
+    let ctx = three_insn_tb();
+    let mut buf = Vec::new();
+    serialize::serialize(&ctx, &mut buf).unwrap();
+    let load = |bytes: &[u8]| {
+        serialize::deserialize(&mut Cursor::new(bytes))
+            .err()
+            .expect("bad header accepted")
+    };
+
+    let mut bad = buf.clone();
+    bad[VERSION_AT] = 5;
+    assert!(matches!(
+        load(&bad),
+        DeserializeError::UnsupportedVersion(5)
+    ));
+
+    let mut bad = buf.clone();
+    bad.swap(BOM_AT, BOM_AT + 1);
+    let e = load(&bad);
+    assert!(matches!(e, DeserializeError::ByteOrder(0xFFFE)));
+    assert_eq!(e.to_string(), "file is big-endian");
+
+    let mut bad = buf.clone();
+    bad[PC_AT] = 0x04;
+    let e = load(&bad);
+    assert!(matches!(
+        e,
+        DeserializeError::PcMismatch {
+            tb: 0,
+            header: Some(0x1004),
+            found: Some(0x1000),
+        }
+    ));
+
+    // A global counted as a local.
+    let mut bad = buf.clone();
+    bad[TB_COUNT_AT + 12] -= 1;
+    assert!(matches!(
+        load(&bad),
+        DeserializeError::Corrupt { tb: 0, .. }
+    ));
+}
@@ -1,4 +1,5 @@
 use tcg_core::tb::*;
+use tcg_frontend::riscv::excp::{EXCP_ECALL, EXCP_UNDEF};
 
 #[test]
 fn tb_new() {
@@ -119,3 +120,92 @@ fn jump_cache_collision() {
     // pc1's entry was overwritten
     assert_eq!(cache.lookup(pc1), Some(2));
 }
+
+#[test]
+fn insn_starts_round_trip() {
+    let pure = [0, 4, 8, 12];
+    let starts = InsnStarts::from_offsets(pure);
+    assert_eq!(starts.offsets().collect::<Vec<_>>(), pure);
+    assert_eq!(starts.encoded_len(), 4);
+
+    let mixed = [0, 2, 6, 8, 10];
+    let starts = InsnStarts::from_offsets(mixed);
+    assert_eq!(starts.offsets().collect::<Vec<_>>(), mixed);
+    assert_eq!(starts.encoded_len(), 5);
+}
+
+/// A fused op spanning 255 bytes or more takes the u16 form.
+#[test]
+fn insn_starts_wide_delta() {
+    let fused = [0, 4, 4 + 255, 4 + 255 + 0x1234, 4 + 255 + 0x1234 + 2];
+    let starts = InsnStarts::from_offsets(fused);
+    assert_eq!(starts.offsets().collect::<Vec<_>>(), fused);
+    assert_eq!(starts.encoded_len(), 1 + 1 + 3 + 3 + 1);
+}
+
+#[test]
+fn tb_insn_boundaries() {
+    let mut tb = TranslationBlock::new(0x1000, 0, 0);
+    assert_eq!(tb.insn_starts().count(), 0);
+    assert!(!tb.contains_insn_at(0x1000));
+
+    tb.size = 10;
+    tb.insn_offsets = InsnStarts::from_offsets([0, 2, 6, 8]);
+    let starts: Vec<u64> = tb.insn_starts().collect();
+    assert_eq!(starts, [0x1000, 0x1002, 0x1006, 0x1008]);
+    assert!(tb.contains_insn_at(0x1006));
+    assert!(!tb.contains_insn_at(0x1004));
+    assert!(!tb.contains_insn_at(0xffe));
+    assert_eq!(tb.insn_containing(0x1004), Some(0x1002));
+    assert_eq!(tb.insn_containing(0x1009), Some(0x1008));
+    assert_eq!(tb.insn_containing(0x100a), None);
+}
+
+const EXITS: [TbExit; 9] = [
+    TbExit::Chain(0),
+    TbExit::Chain(1),
+    TbExit::Normal,
+    TbExit::Exception(EXCP_ECALL),
+    TbExit::Exception(TB_EXIT_CUSTOM - 1),
+    TbExit::Custom(0),
+    TbExit::Custom(TB_EXIT_STACK_OVERFLOW - TB_EXIT_CUSTOM - 1),
+    TbExit::StackOverflow,
+    TbExit::HelperPanic,
+];
+
+#[test]
+fn test_tb_exit_code_roundtrip() {
+    for exit in EXITS {
+        assert_eq!(TbExit::from_code(exit.code()), exit);
+    }
+    assert_eq!(TbExit::Chain(1).code(), TB_EXIT_IDX1);
+    assert_eq!(TbExit::Normal.code(), TB_EXIT_NOCHAIN);
+    assert_eq!(TbExit::Exception(EXCP_UNDEF).code(), EXCP_UNDEF);
+    assert_eq!(TbExit::Custom(5).code(), TB_EXIT_CUSTOM + 5);
+}
+
+#[test]
+fn test_tb_exit_encode_roundtrip() {
+    for tb in [0, 1, 0x7654, u32::MAX - 1] {
+        for exit in EXITS {
+            let raw = TbExit::encode(tb, exit.code()) as usize;
+            let src = exit.chains().then_some(tb as usize);
+            assert_eq!(TbExit::decode(raw), (src, exit), "{raw:#x}");
+        }
+    }
+    // Only chainable exits carry the TB.
+    assert_eq!(TbExit::encode(3, EXCP_ECALL), EXCP_ECALL as u64);
+    assert_eq!(TbExit::encode(3, TB_EXIT_NOCHAIN), (4 << 32) | 2);
+}
+
+#[test]
+#[should_panic(expected = "outside the exception range")]
+fn test_tb_exit_exception_in_chain_range() {
+    TbExit::Exception(TB_EXIT_NOCHAIN).code();
+}
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn test_tb_exit_custom_overflow() {
+    TbExit::Custom(TB_EXIT_STACK_OVERFLOW - TB_EXIT_CUSTOM).code();
+}
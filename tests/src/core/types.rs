@@ -126,6 +126,22 @@ fn memop_bswap() {
     assert!(!op.is_signed());
 }
 
+#[test]
+fn memop_align_and_atom() {
+    let plain = MemOp::ul();
+    assert_eq!(plain.align_bytes(4), 1);
+    assert!(!plain.is_atomic());
+    let amo = MemOp::sl().aligned().atomic();
+    assert_eq!(amo.align_bytes(4), 4);
+    assert!(amo.is_atomic() && amo.is_signed());
+    assert_eq!(amo.size_bytes(), 4);
+    let explicit = MemOp::new(MemOp::SIZE_64 | MemOp::ALIGN_16);
+    assert_eq!(explicit.align_bytes(8), 16);
+    assert_eq!(explicit.aligned().align_bytes(8), 8);
+    // Two-register ops align to the whole pair.
+    assert_eq!(MemOp::uq().aligned().align_bytes(16), 16);
+}
+
 #[test]
 fn regset_basic() {
     let empty = RegSet::EMPTY;
@@ -0,0 +1,187 @@
+use tcg_core::context::Context;
+use tcg_core::tb::TbExit;
+use tcg_core::temp::TempIdx;
+use tcg_core::types::{Cond, Type};
+use tcg_core::verify;
+
+/// A context with an env pointer and one global, `x1`.
+fn setup() -> (Context, TempIdx) {
+    let mut ctx = Context::new();
+    let env = ctx.new_fixed(Type::I64, 5, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    (ctx, x1)
+}
+
+#[test]
+fn test_verify_accepts_well_formed() {
+    let (mut ctx, x1) = setup();
+    let tmp = ctx.new_temp(Type::I64);
+    let lo = ctx.new_temp(Type::I32);
+    let c = ctx.new_const(Type::I64, 42);
+    let done = ctx.new_label();
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, tmp, x1, c);
+    ctx.gen_extrl_i64_i32(lo, tmp);
+    ctx.gen_ext_i32_i64(x1, lo);
+    ctx.gen_brcond(Type::I64, x1, c, Cond::Lt, done);
+    ctx.gen_mov(Type::I64, x1, tmp);
+    ctx.gen_set_label(done);
+    ctx.gen_exit_tb(TbExit::Normal);
+    assert_eq!(verify(&ctx), Ok(()));
+}
+
+#[test]
+fn test_verify_use_before_def() {
+    let (mut ctx, x1) = setup();
+    let tmp = ctx.new_temp(Type::I64);
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, tmp);
+    ctx.gen_mov(Type::I64, tmp, x1);
+    ctx.gen_mov(Type::I64, x1, tmp);
+    assert_eq!(
+        verify(&ctx),
+        Err(vec![format!(
+            "add at op #1 (guest pc 0x1000): temp {} read before it is \
+             written",
+            tmp.0
+        )])
+    );
+}
+
+#[test]
+fn test_verify_type_mismatch() {
+    let (mut ctx, x1) = setup();
+    let t32 = ctx.new_temp(Type::I32);
+    ctx.gen_add(Type::I32, t32, x1, x1);
+    ctx.gen_extrl_i64_i32(x1, t32);
+    assert_eq!(
+        verify(&ctx),
+        Err(vec![
+            format!("add at op #0: temp {} is I64, expected I32", x1.0),
+            format!("add at op #0: temp {} is I64, expected I32", x1.0),
+            format!(
+                "extrl_i64_i32 at op #1: temp {} is I64, expected I32",
+                x1.0
+            ),
+            format!(
+                "extrl_i64_i32 at op #1: temp {} is I32, expected I64",
+                t32.0
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_verify_label_set_twice() {
+    let (mut ctx, _) = setup();
+    let l = ctx.new_label();
+    ctx.gen_set_label(l);
+    ctx.gen_br(l);
+    ctx.gen_set_label(l);
+    assert_eq!(
+        verify(&ctx),
+        Err(vec![format!(
+            "set_label at op #2: label L{l} already set by set_label at \
+             op #0"
+        )])
+    );
+}
+
+#[test]
+fn test_verify_label_never_set() {
+    let (mut ctx, x1) = setup();
+    let l = ctx.new_label();
+    let c = ctx.new_const(Type::I64, 0);
+    ctx.gen_brcond(Type::I64, x1, c, Cond::Eq, l);
+    ctx.gen_br(l);
+    ctx.gen_exit_tb(TbExit::Normal);
+    assert_eq!(
+        verify(&ctx),
+        Err(vec![
+            format!("brcond at op #0: label L{l} is never set"),
+            format!("br at op #1: label L{l} is never set"),
+        ])
+    );
+}
+
+#[test]
+fn test_validate_reports_first_error() {
+    use tcg_core::label::LabelSite;
+    use tcg_core::opcode::Opcode;
+    use tcg_core::{IrError, OpIdx};
+
+    let site = |op, opc, pc| LabelSite {
+        op: OpIdx(op),
+        opc,
+        pc,
+    };
+
+    let (mut ctx, x1) = setup();
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, TempIdx(99));
+    assert_eq!(
+        ctx.validate(),
+        Err(IrError::UnknownTemp {
+            site: site(1, Opcode::Add, Some(0x1000)),
+            temp: 99,
+        })
+    );
+
+    let (mut ctx, x1) = setup();
+    let tmp = ctx.new_temp(Type::I64);
+    ctx.gen_mov(Type::I64, x1, tmp);
+    ctx.gen_add(Type::I64, x1, x1, TempIdx(99));
+    assert_eq!(
+        ctx.validate(),
+        Err(IrError::ReadBeforeWrite {
+            site: site(0, Opcode::Mov, None),
+            temp: tmp.0,
+        })
+    );
+
+    let (mut ctx, x1) = setup();
+    let t32 = ctx.new_temp(Type::I32);
+    ctx.gen_mov(Type::I32, t32, x1);
+    assert_eq!(
+        ctx.validate(),
+        Err(IrError::TypeMismatch {
+            site: site(0, Opcode::Mov, None),
+            temp: x1.0,
+            expected: Type::I32,
+            found: Type::I64,
+        })
+    );
+
+    let (mut ctx, _) = setup();
+    let l = ctx.new_label();
+    ctx.gen_set_label(l);
+    ctx.gen_set_label(l);
+    assert_eq!(
+        ctx.validate(),
+        Err(IrError::DuplicateLabel {
+            label: l,
+            first: site(0, Opcode::SetLabel, None),
+            second: site(1, Opcode::SetLabel, None),
+        })
+    );
+
+    let (mut ctx, _) = setup();
+    let l = ctx.new_label();
+    ctx.gen_br(l);
+    let err = ctx.validate().unwrap_err();
+    assert_eq!(
+        err,
+        IrError::UnsetLabel {
+            label: l,
+            site: site(0, Opcode::Br, None),
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        format!("br at op #0: label L{l} is never set")
+    );
+
+    let (mut ctx, x1) = setup();
+    ctx.gen_mov(Type::I64, x1, x1);
+    assert_eq!(ctx.validate(), Ok(()));
+}
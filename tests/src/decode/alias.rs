@@ -0,0 +1,44 @@
+//! Patterns that share one `trans_` method through `!trans=`
+//! (see `tests/build.rs`).
+
+// Only part of the generated API is exercised.
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/alias_decode.rs"));
+}
+
+use generated::*;
+
+/// Implements the single method the three patterns share.
+#[derive(Default)]
+struct Adds(Vec<(i64, i64, i64)>);
+
+impl Decode<()> for Adds {
+    fn trans_add(&mut self, _ir: &mut (), a: &ArgsR) -> bool {
+        self.0.push((a.rd, a.rs1, a.rs2));
+        true
+    }
+}
+
+#[test]
+fn test_aliases_reach_shared_trans() {
+    let mut d = Adds::default();
+    for insn in [
+        0x0031_00b3, // add x1, x2, x3
+        0x0030_10b3, // mv x1, x3
+        0x0001_20b3, // addi0 x1, x2
+    ] {
+        assert!(decode(&mut d, &mut (), insn), "{insn:#010x}");
+    }
+    assert_eq!(d.0, [(1, 2, 3), (1, 0, 3), (1, 2, 0)]);
+    // Each keeps its own name.
+    assert_eq!(decode_meta(0x0030_10b3).unwrap().0, "mv");
+}
+
+#[test]
+fn test_trait_declares_shared_trans_once() {
+    let code = include_str!(concat!(env!("OUT_DIR"), "/alias_decode.rs"));
+    assert_eq!(code.matches("fn trans_add(&mut self, ir").count(), 1);
+    assert!(!code.contains("trans_mv"));
+    assert_eq!(code.matches("return ctx.trans_add(ir, &a);").count(), 3);
+}
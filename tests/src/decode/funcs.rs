@@ -0,0 +1,34 @@
+//! A decoder whose field uses a `!function=` the including
+//! module provides (see `tests/build.rs`).
+
+// Only part of the generated API is exercised.
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/funcs_decode.rs"));
+
+    fn ex_times_4(x: i64) -> i64 {
+        x * 4
+    }
+}
+
+use generated::*;
+
+#[derive(Default)]
+struct Imm(Option<(i64, i64)>);
+
+impl Decode<()> for Imm {
+    fn trans_ldx(&mut self, _ir: &mut (), a: &ArgsI) -> bool {
+        self.0 = Some((a.imm, a.rd));
+        true
+    }
+}
+
+#[test]
+fn test_extern_func_applied() {
+    for (imm, want) in [(3, 12), (-1, -4), (-2048, -8192)] {
+        let insn = ((imm as u32) << 20) | 5 << 7 | 0x300b;
+        let mut d = Imm::default();
+        assert!(decode(&mut d, &mut (), insn));
+        assert_eq!(d.0, Some((want, 5)), "{imm}");
+    }
+}
@@ -1,3 +1,8 @@
+mod alias;
+mod funcs;
+mod partial;
+mod tree;
+
 use decode::*;
 
 fn parse(input: &str) -> Result<Parsed, String> {
@@ -83,8 +88,8 @@ fn bit_pattern_inline_fields() {
     let r = parse_bit_tokens(&toks, 32).unwrap();
     assert_eq!(r.fixedmask, 0x0000_707f);
     assert_eq!(r.fixedbits, 0x0000_000f);
-    assert_eq!(r.inline_fields["pred"], (24, 4));
-    assert_eq!(r.inline_fields["succ"], (20, 4));
+    assert_eq!(r.inline_fields["pred"], (24, 4, false));
+    assert_eq!(r.inline_fields["succ"], (20, 4, false));
 }
 
 #[test]
@@ -186,7 +191,7 @@ fn merge_continuations_basic() {
 }
 
 #[test]
-fn parse_group_braces_ignored() {
+fn parse_group_braces() {
     let input = "\
 %rd 7:5
 &r rd
@@ -198,10 +203,14 @@ fn parse_group_braces_ignored() {
     let p = parse(input).unwrap();
     assert_eq!(p.patterns.len(), 1);
     assert_eq!(p.patterns[0].name, "add");
+    assert_eq!(p.groups.len(), 1);
+    assert!(p.groups[0].overlap);
+    assert_eq!(p.groups[0].line, 4);
+    assert_eq!(p.groups[0].members, [GroupMember::Pattern(0)]);
 }
 
 #[test]
-fn parse_group_brackets_ignored() {
+fn parse_group_brackets() {
     let input = "\
 %rd 7:5
 &r rd
@@ -212,6 +221,70 @@ fn parse_group_brackets_ignored() {
 ";
     let p = parse(input).unwrap();
     assert_eq!(p.patterns.len(), 1);
+    assert!(!p.groups[0].overlap);
+    assert_eq!(p.patterns[0].groups, [0]);
+}
+
+#[test]
+fn parse_nested_groups() {
+    let input = "\
+a 0000000 ..... ..... 000 ..... 0110011
+{
+  b 0000000 00000 ..... 001 ..... 0110011
+  [
+    c 0000000 ..... 00000 001 ..... 0110011
+    {
+      d 0000001 ..... ..... 001 ..... 0110011
+    }
+  ]
+  e 0000000 ..... ..... 001 ..... 0110011
+}
+";
+    let p = parse(input).unwrap();
+    let names: Vec<_> = p.patterns.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["a", "b", "c", "d", "e"]);
+    use GroupMember::{Group as G, Pattern as P};
+    assert_eq!(p.groups[0].members, [P(1), G(1), P(4)]);
+    assert_eq!(p.groups[1].members, [P(2), G(2)]);
+    assert_eq!(p.groups[2].members, [P(3)]);
+    assert_eq!(
+        p.groups
+            .iter()
+            .map(|g| (g.overlap, g.line))
+            .collect::<Vec<_>>(),
+        [(true, 2), (false, 4), (true, 6)]
+    );
+    assert!(p.patterns[0].groups.is_empty());
+    assert_eq!(p.patterns[3].groups, [0, 1, 2]);
+    // b and c overlap, but the { } around both orders them.
+    validate(&p).unwrap();
+}
+
+#[test]
+fn generate_overlap_group_in_order() {
+    // c.addi16sp is c.lui with rd = 2, so it must be tried first.
+    let input = "\
+%rd 7:5
+&u imm rd
+&i imm rs1 rd
+{
+  addi  011 . 00010 ..... 01 &i imm=0 rs1=2 rd=2
+  lui   011 . ..... ..... 01 &u imm=0 %rd
+}
+addi  000 . ..... ..... 01 &i imm=0 rs1=0 %rd
+";
+    let p = parse_with_width(input, 16).unwrap();
+    validate(&p).unwrap();
+    let mut out = Vec::new();
+    generate_with_width(input, &mut out, 16).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    let body = &code[code.find("pub fn decode16<").unwrap()..];
+    let body = &body[..body.find("\n}\n").unwrap()];
+    let addi16sp = body.find("if insn & 0xef83 == 0x6101").unwrap();
+    let lui = body.find("if insn & 0xe003 == 0x6001").unwrap();
+    assert!(addi16sp < lui, "{body}");
+    // Nothing else is tested between the two.
+    assert_eq!(body[addi16sp..lui].matches("if insn").count(), 1, "{body}");
 }
 
 #[test]
@@ -226,6 +299,85 @@ fn continuation_in_format() {
     assert!(p.argsets.contains_key("shift"));
 }
 
+// ── Source lines ─────────────────────────────────────────────
+
+/// Known layout: a continued format and pattern, and patterns
+/// inside groups.
+fn lines_decode() -> &'static str {
+    "\
+# line 1
+%rd     7:5
+%rs1    15:5
+
+&r   rd rs1
+@r   ....... ..... ..... ... ..... ....... \\
+     &r %rd %rs1
+
+{
+  add  0000000 ..... ..... 000 ..... 0110011 @r
+  [
+    sub  0100000 ..... \\
+         ..... 000 ..... 0110011 @r
+  ]
+}
+add  0000001 ..... ..... 000 ..... 0110011 @r
+xor  0000000 ..... ..... 100 ..... 0110011 @r
+"
+}
+
+#[test]
+fn parse_records_source_lines() {
+    let p = parse(lines_decode()).unwrap();
+    assert_eq!(p.fields["rd"].line, 2);
+    assert_eq!(p.fields["rs1"].line, 3);
+    let lines: Vec<(&str, u32)> = p
+        .patterns
+        .iter()
+        .map(|p| (p.name.as_str(), p.line))
+        .collect();
+    assert_eq!(lines, [("add", 10), ("sub", 12), ("add", 16), ("xor", 17)]);
+}
+
+#[test]
+fn parse_error_cites_physical_line() {
+    let input = "\
+%rd 7:5
+&r rd
+@r ....... ..... ..... ... \\
+   ..... ....... &r %rd
+add 0000000 ..... ..... 000 ..... 0110011 @nonexistent
+";
+    let e = parse(input).err().unwrap();
+    assert!(e.starts_with("line 5: "), "{e}");
+}
+
+#[test]
+fn generate_source_comments_and_table() {
+    let opts = GenOptions {
+        source: "lines.decode".to_string(),
+        source_table: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(lines_decode(), &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("// lines.decode:2: %rd\nfn extract_rd("));
+    let (_, after) = code.split_once("// lines.decode:12: sub\n").unwrap();
+    let arm = after.lines().next().unwrap();
+    assert!(arm.contains(" if insn & 0xfe00707f == 0x40000033"), "{arm}");
+    assert!(code.contains("    // lines.decode:16: add\n"));
+    // A name defined twice maps to its first definition.
+    assert!(code.contains("\"add\" => Some((\"lines.decode\", 10)),"));
+    assert!(!code.contains("\"add\" => Some((\"lines.decode\", 16)),"));
+    assert!(code.contains("\"xor\" => Some((\"lines.decode\", 17)),"));
+
+    let mut out = Vec::new();
+    generate(lines_decode(), &mut out).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("// <input>:12: sub"));
+    assert!(!code.contains("fn pattern_source"));
+}
+
 // ── Full parse ───────────────────────────────────────────────
 
 fn mini_decode() -> &'static str {
@@ -272,7 +424,7 @@ fn parse_riscv32_decode() {
     let input =
         std::fs::read_to_string("../frontend/src/riscv/insn32.decode").unwrap();
     let p = parse(&input).unwrap();
-    assert_eq!(p.patterns.len(), 155);
+    assert_eq!(p.patterns.len(), 190);
     assert!(p.fields.contains_key("imm_b"));
     assert!(p.fields.contains_key("imm_j"));
     assert!(p.argsets.contains_key("r"));
@@ -306,6 +458,92 @@ add 0000000 ..... ..... 000 ..... 0110011 @nonexistent
     assert!(parse(input).is_err());
 }
 
+#[test]
+fn parse_const_attrs() {
+    let input = "\
+&i imm rd
+a ................. 000 rd:5 0010011 &i imm=0x80000000
+b ................. 001 rd:5 0010011 &i imm=-5
+c ................. 010 rd:5 0010011 &i imm=0x123456789
+d ................. 011 rd:5 0010011 &i imm=0xffffffffffffffff
+e ................. 100 rd:5 0010011 &i imm=-9223372036854775808
+";
+    let p = parse(input).unwrap();
+    let consts: Vec<i64> = p
+        .patterns
+        .iter()
+        .map(|p| match p.field_map["imm"] {
+            FieldMapping::Const(c) => c,
+            ref m => panic!("unexpected mapping {m:?}"),
+        })
+        .collect();
+    assert_eq!(consts, [0x8000_0000, -5, 0x1_2345_6789, -1, i64::MIN]);
+
+    for (attr, bad) in [
+        ("imm=0xzz", "\"0xzz\""),
+        ("imm=9223372036854775808", "\"9223372036854775808\""),
+        ("imm=", "\"\""),
+    ] {
+        let input = format!(
+            "&i imm rd\na ................. 000 rd:5 0010011 &i {attr}\n"
+        );
+        let e = parse(&input).err().unwrap();
+        assert!(e.starts_with(&format!("line 2: {attr}: ")), "{e}");
+        assert!(e.ends_with(&format!("got {bad}")), "{e}");
+    }
+}
+
+#[test]
+fn parse_func_attr() {
+    let input = "\
+&i imm rd
+a ................. 000 rd:5 0010011 &i imm=!function=ex_shift_2
+";
+    let p = parse(input).unwrap();
+    assert!(matches!(
+        &p.patterns[0].field_map["imm"],
+        FieldMapping::Func(f) if f == "ex_shift_2"
+    ));
+    let input =
+        "&i imm rd\na ................. 000 rd:5 0010011 &i imm=!function=2x\n";
+    let e = parse(input).err().unwrap();
+    assert_eq!(e, "line 2: imm=!function=2x: bad !function name: \"2x\"");
+}
+
+#[test]
+fn trans_alias_errors() {
+    let check = |input: &str, want: &str| {
+        let e = parse(input).and_then(|p| validate(&p)).unwrap_err();
+        assert_eq!(e, want);
+    };
+    let head = "&r rd rs1 rs2\n&i imm rs1 rd\n";
+    check(
+        &format!("{head}mv ................. 000 rd:5 0110011 &r !trans=3\n"),
+        "line 3: bad !trans= in pattern mv: \"3\"",
+    );
+    check(
+        &format!(
+            "{head}mv ................. 000 rd:5 0110011 &r \
+             !trans=add !trans=or\n"
+        ),
+        "line 3: more than one !trans= in pattern mv",
+    );
+    check(
+        &format!(
+            "{head}add ................. 000 rd:5 0110011 &r\n\
+             li  ................. 001 rd:5 0110011 &i !trans=add\n"
+        ),
+        "line 4: li: trans_add takes ArgsR (line 3), not ArgsI",
+    );
+    check(
+        &format!(
+            "{head}mv ................. 000 rd:5 0110011 &r !trans=add\n\
+             mv ................. 001 rd:5 0110011 &r\n"
+        ),
+        "line 4: mv calls trans_mv, but line 3 calls trans_add",
+    );
+}
+
 // ── Format inheritance ───────────────────────────────────────
 
 #[test]
@@ -414,6 +652,13 @@ fn pattern_masks_shift() {
     let sraiw = find("sraiw");
     assert_eq!(sraiw.fixedmask, 0xfe00_707f);
     assert_eq!(sraiw.fixedbits, 0x4000_501b);
+    // shamt[5] (bit 25) is fixed in the W forms: shamt >= 32 is
+    // reserved and must not decode.
+    for name in ["slliw", "srliw", "sraiw"] {
+        let mask = find(name).fixedmask;
+        assert_eq!(mask, 0xfe00_707f, "{name}");
+        assert_eq!(find(name).fixedbits & (1 << 25), 0, "{name}");
+    }
 }
 
 // ── Extract function correctness ─────────────────────────────
@@ -651,7 +896,7 @@ fn generate_riscv32_decode() {
     let mut out = Vec::new();
     generate(&input, &mut out).unwrap();
     let code = String::from_utf8(out).unwrap();
-    assert_eq!(code.matches("fn trans_").count(), 155);
+    assert_eq!(code.matches("fn trans_").count(), 190);
     assert!(code.contains("fn trans_lui("));
     assert!(code.contains("fn trans_jal("));
     assert!(code.contains("fn trans_mul("));
@@ -717,6 +962,44 @@ addi ............ ..... 000 ..... 0010011 @i
     assert!(code.contains("fn trans_addi("));
 }
 
+#[test]
+fn extern_argset_shared_across_files() {
+    let input32 = "\
+&r rd rs1 rs2
+&i imm rs1 rd
+add 0000000 rs2:5 rs1:5 000 rd:5 0110011 &r
+addi imm:12 rs1:5 000 rd:5 0010011 &i
+";
+    let input16 = "\
+&r rd rs1 rs2 !extern
+&i imm rs1 rd !extern
+c_add 1001 rd:5 rs2:5 10 &r rs1=%rd
+%rd 7:5
+";
+    let mut out32 = Vec::new();
+    generate(input32, &mut out32).unwrap();
+    let opts = GenOptions {
+        width: 16,
+        extern_args: Some("super".to_string()),
+        ..GenOptions::default()
+    };
+    let mut out16 = Vec::new();
+    generate_with_options(input16, &mut out16, &opts).unwrap();
+    let (code32, code16) = (
+        String::from_utf8(out32).unwrap(),
+        String::from_utf8(out16).unwrap(),
+    );
+    let both = format!("{code32}{code16}");
+    assert_eq!(both.matches("struct ArgsR ").count(), 1, "{both}");
+    assert!(code32.contains("pub struct ArgsR {"));
+    // Only the argsets a pattern takes are imported.
+    assert!(code16.contains("\nuse super::ArgsR;\n"), "{code16}");
+    assert!(!code16.contains("ArgsI"), "{code16}");
+    assert!(
+        code16.contains("fn trans_c_add(&mut self, ir: &mut Ir, a: &ArgsR)")
+    );
+}
+
 // ── 16-bit width ─────────────────────────────────────────────
 
 #[test]
@@ -790,6 +1073,48 @@ fn func_sreg_register() {
     assert!(code.contains("[8,9,18,19,20,21,22,23]"));
 }
 
+#[test]
+fn func_user_defined() {
+    let gen = |input: &str, funcs: &[&str]| {
+        let opts = GenOptions {
+            extern_funcs: funcs.iter().map(|f| f.to_string()).collect(),
+            ..GenOptions::default()
+        };
+        let mut out = Vec::new();
+        generate_with_options(input, &mut out, &opts)
+            .map(|_| String::from_utf8(out).unwrap())
+    };
+    let input = "%imm 20:s12 !function=ex_mything\n";
+    let code = gen(input, &["ex_mything"]).unwrap();
+    assert!(code.contains("    ex_mything(val as i64)\n}"), "{code}");
+    assert!(!code.contains("unknown func"));
+
+    let input = "%imm 25:s7 7:5 !function=_ex_split\n";
+    let code = gen(input, &["_ex_split"]).unwrap();
+    assert!(code.contains("    _ex_split(val)\n}"), "{code}");
+
+    // Undeclared, it is refused up front.
+    let input = "# custom\n%imm 20:s12 !function=ex_mything\n";
+    let e = gen(input, &["ex_other"]).unwrap_err();
+    assert_eq!(
+        e,
+        "line 2: %imm: unknown !function=ex_mything; \
+         declare it in GenOptions::extern_funcs"
+    );
+    // The built-in ones need no declaration.
+    gen("%imm 20:12 !function=ex_shift_3\n", &[]).unwrap();
+}
+
+#[test]
+fn func_bad_name() {
+    for func in ["", "1st", "ex-shift", "ex_shift(1)"] {
+        let e = parse_field(&format!("%imm 20:12 !function={func}"))
+            .err()
+            .unwrap_or_else(|| panic!("{func}"));
+        assert_eq!(e, format!("bad !function name: {func:?}"));
+    }
+}
+
 // ── Full insn16.decode parse ─────────────────────────────────
 
 #[test]
@@ -843,7 +1168,7 @@ fn extract_imm_ci() {
     let imm5 = ((insn >> 12) & 1) as i64;
     let imm4_0 = ((insn >> 2) & 0x1f) as i64;
     let raw = (imm5 << 5) | imm4_0;
-    let sext = ((raw << 58) as i64) >> 58; // sign-extend 6-bit
+    let sext = (raw << 58) >> 58; // sign-extend 6-bit
     assert_eq!(sext, -1);
 }
 
@@ -882,7 +1207,7 @@ fn extract_imm_cb() {
     // Test: offset = -2 (0b1111111111111110 in 9-bit sext)
     let offset: i64 = -2;
     assert_eq!(offset & 1, 0); // must be even
-    assert!(offset >= -256 && offset < 256);
+    assert!((-256..256).contains(&offset));
 }
 
 #[test]
@@ -891,7 +1216,7 @@ fn extract_imm_cj() {
     // 12-bit signed immediate
     let offset: i64 = -2;
     assert_eq!(offset & 1, 0);
-    assert!(offset >= -2048 && offset < 2048);
+    assert!((-2048..2048).contains(&offset));
 }
 
 #[test]
@@ -1061,3 +1386,753 @@ fn generate_16bit_trait_dedup() {
         assert!(seen.insert(name), "duplicate trait method: {name}");
     }
 }
+
+// ── Decode coverage ──────────────────────────────────────────
+
+/// Name of the first pattern matching `insn`, as `decode` would
+/// dispatch.
+fn first_match(p: &Parsed, insn: u32) -> Option<&str> {
+    p.patterns
+        .iter()
+        .find(|pat| insn & pat.fixedmask == pat.fixedbits)
+        .map(|pat| pat.name.as_str())
+}
+
+#[test]
+fn fill_value_in_range() {
+    assert_eq!(fill_value(1, 1, false), 1);
+    assert_eq!(fill_value(2, 5, false), 2);
+    assert_eq!(fill_value(9, 3, false), 2);
+    assert_eq!(fill_value(1, 1, true), -1);
+    assert_eq!(fill_value(3, 4, true), -3);
+    assert_eq!(fill_value(9, 4, true), -1);
+}
+
+#[test]
+fn parse_signed_inline_field() {
+    assert!(is_inline_field("imm:s12"));
+    assert!(!is_inline_field("imm:s"));
+    let p = parse("foo imm:s12 ..... 000 rd:5 0010011").unwrap();
+    match &p.patterns[0].field_map["imm"] {
+        FieldMapping::Inline { pos, len, signed } => {
+            assert_eq!((*pos, *len, *signed), (20, 12, true));
+        }
+        m => panic!("unexpected mapping {m:?}"),
+    }
+}
+
+#[test]
+fn generate_wide_const_and_func_attrs() {
+    let input = "\
+&i imm rd
+a ................. 000 rd:5 0010011 &i imm=0x80000000
+b ................. 001 rd:5 0010011 &i imm=-0x1000000000
+c ................. 010 rd:5 0010011 &i imm=!function=ex_shift_2
+d ................. 011 rd:5 0010011 &i imm=!function=ex_times_4
+";
+    let opts = GenOptions {
+        extern_funcs: ["ex_times_4".to_string()].into(),
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(input, &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    for imm in [
+        "imm: 2147483648_i64,",
+        "imm: -68719476736_i64,",
+        "imm: (0) << 2,",
+        "imm: ex_times_4(0),",
+    ] {
+        assert!(code.contains(imm), "{imm}\n{code}");
+    }
+
+    let e = generate(input, &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        e,
+        "line 5: imm=!function=ex_times_4: unknown !function; declare it \
+         in GenOptions::extern_funcs"
+    );
+}
+
+#[test]
+fn generate_signed_inline_field() {
+    let input = "\
+&i imm rd
+foo imm:s5 ....... ..... 000 rd:5 0010011 &i
+bar imm:5 ....... ..... 001 rd:5 0010011 &i
+";
+    let mut out = Vec::new();
+    generate(input, &mut out).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    let signed = "imm: ((insn as i32) >> 27) as i64,";
+    let unsigned = "imm: ((insn >> 27) & 0x1f) as i64,";
+    assert!(code.contains(signed), "{code}");
+    assert!(code.contains(unsigned), "{code}");
+
+    let input = "\
+&c imm
+baz 010 . imm:s5 ..... 01 &c
+";
+    let mut out = Vec::new();
+    generate_with_width(input, &mut out, 16).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    let signed = "imm: (((insn as i16) << 4) >> 11) as i64,";
+    assert!(code.contains(signed), "{code}");
+
+    // The same expressions on a word with all five bits set.
+    let insn: u32 = 0xf800_0013;
+    assert_eq!(((insn as i32) >> 27) as i64, -1);
+    assert_eq!(((insn >> 27) & 0x1f) as i64, 31);
+}
+
+#[test]
+fn canonical_signed_inline_field() {
+    // imm (field 1, signed) gets -1, rd (field 2) gets 2.
+    let p = parse("foo imm:s12 ..... 000 rd:5 0010011").unwrap();
+    let insn = canonical_encoding(&p, 0, 32).unwrap();
+    assert_eq!(insn, 0xfff0_0113);
+}
+
+#[test]
+fn canonical_multi_segment_field() {
+    let input = "\
+%imm_s 25:s7 7:5
+%rs1 15:5
+%rs2 20:5
+&s imm rs1 rs2
+@s ....... ..... ..... ... ..... ....... &s imm=%imm_s %rs2 %rs1
+sd ....... ..... ..... 011 ..... 0100011 @s
+";
+    let p = parse(input).unwrap();
+    let insn = canonical_encoding(&p, 0, 32).unwrap();
+    // imm = -1 fills both segments; rs1 = 2, rs2 = 3.
+    assert_eq!(insn, 0xfe00_0000 | 3 << 20 | 2 << 15 | 0x3 << 12 | 0xfa3);
+}
+
+#[test]
+fn canonical_avoids_earlier_overlap() {
+    let input = "\
+%rs1 15:5
+%rd 7:5
+{
+  special ............ 00010 000 ..... 0010011 %rd
+  general ............ ..... 000 ..... 0010011 %rs1 %rd
+}
+";
+    let p = parse(input).unwrap();
+    assert!(patterns_overlap(&p.patterns[0], &p.patterns[1]));
+    // The fill (rd = 1, rs1 = 2) would hit `special`; the
+    // lowest bit it fixes and `general` leaves free is flipped.
+    let insn = canonical_encoding(&p, 1, 32).unwrap();
+    assert_eq!(insn, 3 << 15 | 1 << 7 | 0x13);
+    assert_eq!(first_match(&p, insn), Some("general"));
+    let special = canonical_encoding(&p, 0, 32).unwrap();
+    assert_eq!(first_match(&p, special), Some("special"));
+}
+
+#[test]
+fn canonical_reports_shadowed_pattern() {
+    let input = "\
+{
+  general ............ ..... 000 ..... 0010011
+  special ............ 00010 000 ..... 0010011
+}
+";
+    let p = parse(input).unwrap();
+    let err = canonical_encoding(&p, 1, 32).unwrap_err();
+    assert!(err.contains("special is shadowed by general"), "{err}");
+}
+
+#[test]
+fn canonical_rvc_group() {
+    let p = rvc_parsed();
+    // c.lui's fill (rd = 2) collides with c.addi16sp.
+    let idx = p.patterns.iter().position(|x| x.name == "lui").unwrap();
+    let insn = canonical_encoding(&p, idx, 16).unwrap();
+    assert!(insn <= 0xffff);
+    assert_eq!(first_match(&p, insn), Some("lui"));
+    assert_ne!((insn >> 7) & 0x1f, 2);
+}
+
+#[test]
+fn canonical_encodings_reach_own_pattern() {
+    for (file, width) in [("insn32", 32), ("insn16", 16)] {
+        let path = format!("../frontend/src/riscv/{file}.decode");
+        let input = std::fs::read_to_string(path).unwrap();
+        let p = parse_with_width(&input, width).unwrap();
+        for (i, pat) in p.patterns.iter().enumerate() {
+            if let Ok(insn) = canonical_encoding(&p, i, width) {
+                let hit = p
+                    .patterns
+                    .iter()
+                    .position(|x| insn & x.fixedmask == x.fixedbits);
+                assert_eq!(hit, Some(i), "{file}: {} {insn:#x}", pat.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn generate_coverage_table() {
+    let input = "\
+{
+  special ............ 00010 000 ..... 0010011
+  general ............ ..... 000 ..... 0010011
+}
+";
+    let mut out = Vec::new();
+    generate(input, &mut out).unwrap();
+    let plain = String::from_utf8(out).unwrap();
+    assert!(!plain.contains("CANONICAL_ENCODINGS"));
+
+    let opts = GenOptions {
+        coverage: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(input, &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("pub const CANONICAL_ENCODINGS: &[(&str, u32)]"));
+    assert!(code.contains("(\"special\", 0x00010013)"));
+    assert!(code.contains("(\"general\", 0x00000013)"));
+    assert!(code.contains("#[cfg(test)]\nmod decode_coverage {"));
+    assert!(code.contains("impl Decode<()> for Recorder {"));
+}
+
+#[test]
+fn generate_coverage_lists_unreachable() {
+    let input =
+        std::fs::read_to_string("../frontend/src/riscv/insn16.decode").unwrap();
+    let opts = GenOptions {
+        width: 16,
+        coverage: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(&input, &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("CANONICAL_ENCODINGS16"));
+    assert!(code.contains("mod decode16_coverage {"));
+    assert!(code.contains("// unreachable: pattern c_flw is shadowed by ld"));
+}
+
+#[test]
+fn implemented_trans_per_trait() {
+    let src = "\
+impl Decode<Context> for Cpu {
+    fn trans_add(&mut self, ir: &mut Context, a: &ArgsR) -> bool {
+        true
+    }
+    // fn trans_sub is not here yet
+    fn trans_c_addi(&mut self, ir: &mut Context, a: &ArgsI) -> bool {
+        true
+    }
+}
+
+impl Cpu {
+    fn trans_helper(&self) {}
+}
+
+impl Decode16<Context> for Cpu {
+    fn trans_addi(&mut self, ir: &mut Context, a: &ArgsI) -> bool {
+        true
+    }
+}
+";
+    let names = |t| implemented_trans(src, t).into_iter().collect::<Vec<_>>();
+    assert_eq!(names("Decode"), ["add", "c_addi"]);
+    assert_eq!(names("Decode16"), ["addi"]);
+}
+
+// ── Extension tags ──────────────────────────────────────────
+
+#[test]
+fn parse_ext_attr() {
+    let input = "\
+&r rd rs1 rs2
+@r ....... ..... ..... ... ..... ....... &r %rs2 %rs1 %rd
+%rs2 20:5
+%rs1 15:5
+%rd 7:5
+mul  0000001 ..... ..... 000 ..... 0110011 @r !ext=M
+fld  ............ ..... 011 ..... 0000111 !ext=C,D
+add  0000000 ..... ..... 000 ..... 0110011
+";
+    let p = parse(input).unwrap();
+    assert_eq!(p.patterns[0].exts, ["M"]);
+    assert_eq!(p.patterns[1].exts, ["C", "D"]);
+    assert!(p.patterns[2].exts.is_empty());
+    assert!(p.patterns[0].field_map.contains_key("rd"));
+}
+
+#[test]
+fn parse_empty_ext_rejected() {
+    let Err(err) = parse("nop 00000000000000000000000000010011 !ext=\n") else {
+        panic!("empty !ext= accepted");
+    };
+    assert!(err.contains("empty !ext="), "{err}");
+}
+
+#[test]
+fn generate_decode_meta() {
+    let input = "\
+{
+  special ............ 00010 000 ..... 0010011 !ext=Zfoo
+  general ............ ..... 000 ..... 0010011 !ext=I
+}
+";
+    let mut out = Vec::new();
+    generate(input, &mut out).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains(
+        "pub fn decode_meta(insn: u32) \
+         -> Option<(&'static str, &'static [&'static str])> {"
+    ));
+    let special = code.find("Some((\"special\", &[\"Zfoo\"]))").unwrap();
+    let general = code.find("Some((\"general\", &[\"I\"]))").unwrap();
+    assert!(special < general, "meta must follow dispatch order");
+
+    let mut out = Vec::new();
+    generate_with_width("nop 0000000000000001 !ext=C\n", &mut out, 16).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("pub fn decode16_meta(insn: u16)"));
+}
+
+#[test]
+fn riscv_patterns_all_tagged() {
+    for (file, width) in [("insn32", 32), ("insn16", 16)] {
+        let path = format!("../frontend/src/riscv/{file}.decode");
+        let input = std::fs::read_to_string(path).unwrap();
+        let p = parse_with_width(&input, width).unwrap();
+        for pat in &p.patterns {
+            assert!(!pat.exts.is_empty(), "{file}: {} has no !ext=", pat.name);
+        }
+    }
+}
+
+// ── Reserved values ─────────────────────────────────────────
+
+const RESERVED_INPUT: &str = "\
+%rs1 15:5
+%rd 7:5
+%rm 12:3
+&r2_rm rd rs1 rm
+@r2_rm ....... ..... ..... ... ..... ....... &r2_rm %rs1 %rm %rd \\
+       !reserved rm=5,6
+fsqrt 0101100 00000 ..... ... ..... 1010011 @r2_rm
+fence fm:4 pred:4 succ:4 ..... 000 ..... 0001111 !reserved fm=1-7,9-15
+";
+
+#[test]
+fn parse_reserved_list_and_range() {
+    let p = parse(RESERVED_INPUT).unwrap();
+    assert_eq!(
+        p.patterns[0].reserved,
+        [Reserved {
+            field: "rm".to_string(),
+            ranges: vec![(5, 5), (6, 6)],
+        }]
+    );
+    let fm = &p.patterns[1].reserved[0];
+    assert_eq!(fm.field, "fm");
+    assert_eq!(fm.ranges, [(1, 7), (9, 15)]);
+    assert!(!fm.contains(0) && fm.contains(1) && fm.contains(7));
+    assert!(!fm.contains(8) && fm.contains(9) && fm.contains(15));
+}
+
+#[test]
+fn parse_reserved_negative_values() {
+    let p = parse("foo imm:s12 ..... 000 rd:5 0010011 !reserved imm=-3--1,7\n")
+        .unwrap();
+    assert_eq!(p.patterns[0].reserved[0].ranges, [(-3, -1), (7, 7)]);
+}
+
+#[test]
+fn parse_reserved_rejected() {
+    for (attr, want) in [
+        ("!reserved", "without a field"),
+        ("!reserved rd", "bad !reserved"),
+        ("!reserved rd=", "bad !reserved"),
+        ("!reserved rd=3-1", "bad !reserved"),
+        ("!reserved rd=x", "bad !reserved"),
+        ("!reserved rs2=1", "not a plain field"),
+        ("!reserved imm=1", "not a plain field"),
+    ] {
+        let input = format!(
+            "%imm 20:12 !function=ex_shift_1\n\
+             foo ............ ..... 000 rd:5 0010011 imm=%imm {attr}\n"
+        );
+        let err = parse(&input).err().unwrap_or_else(|| panic!("{attr}"));
+        assert!(err.contains(want), "{attr}: {err}");
+    }
+}
+
+#[test]
+fn generate_reserved_checks() {
+    let mut out = Vec::new();
+    generate(RESERVED_INPUT, &mut out).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    let rm = "insn & 0xfff0007f == 0x58000053 \
+              && !matches!(extract_rm(insn), 5 | 6)";
+    let fm = "insn & 0x0000707f == 0x0000000f \
+              && !matches!(((insn >> 28) & 0xf) as i64, 1..=7 | 9..=15)";
+    // In decode(), guarded arms of the match on the opcode; one
+    // too long for a line wraps the way rustfmt does.
+    let arm = format!("        0x53 if {rm} => {{\n");
+    assert_eq!(code.matches(&arm).count(), 1, "{code}");
+    let (bits, rest) = fm.split_once(" && ").unwrap();
+    let arm = format!(
+        "        0xf if {bits}\n            && {rest} =>\n        {{\n"
+    );
+    assert_eq!(code.matches(&arm).count(), 1, "{code}");
+    // In decode_meta(), an if-chain.
+    for cond in [rm, fm] {
+        let chain = format!("    if {cond} {{\n");
+        assert_eq!(code.matches(&chain).count(), 1, "{code}");
+    }
+}
+
+#[test]
+fn pattern_matches_skips_reserved() {
+    let p = parse(RESERVED_INPUT).unwrap();
+    let fsqrt = &p.patterns[0];
+    for rm in 0..8 {
+        let insn = 0x5800_0053 | rm << 12;
+        assert_eq!(pattern_matches(&p, fsqrt, insn), rm != 5 && rm != 6);
+    }
+    let fence = &p.patterns[1];
+    for fm in 0..16 {
+        let insn = 0x0330_000f | fm << 28;
+        assert_eq!(pattern_matches(&p, fence, insn), fm == 0 || fm == 8);
+    }
+}
+
+#[test]
+fn canonical_avoids_reserved() {
+    // rm (field 2) would get 2 and fm (field 1) 1.
+    let input = "\
+%rs1 15:5
+%rd 7:5
+%rm 12:3
+foo ....... ..... ..... ... ..... 1010011 %rd %rm !reserved rm=2-6
+fence fm:4 pred:4 succ:4 ..... 000 ..... 0001111 !reserved fm=1-7
+";
+    let p = parse(input).unwrap();
+    let foo = canonical_encoding(&p, 0, 32).unwrap();
+    assert_eq!(foo >> 12 & 7, 7);
+    assert!(pattern_matches(&p, &p.patterns[0], foo));
+    let fence = canonical_encoding(&p, 1, 32).unwrap();
+    assert_eq!(fence >> 28, 8);
+
+    let p =
+        parse("foo ............ ..... rm:3 ..... 1010011 !reserved rm=0-7\n");
+    let err = canonical_encoding(&p.unwrap(), 0, 32).unwrap_err();
+    assert!(err.contains("no canonical encoding"), "{err}");
+}
+
+#[test]
+fn riscv_fp_rm_reserved() {
+    let p = riscv_parsed();
+    let with_rm: Vec<_> = p
+        .patterns
+        .iter()
+        .filter(|pat| pat.field_map.contains_key("rm"))
+        .collect();
+    assert!(with_rm.len() > 30);
+    for pat in with_rm {
+        assert_eq!(pat.reserved.len(), 1, "{}", pat.name);
+        assert_eq!(pat.reserved[0].ranges, [(5, 5), (6, 6)], "{}", pat.name);
+    }
+}
+
+// ── Overlap validation ──────────────────────────────────────
+
+const OVERLAP_HEAD: &str = "\
+%rd 7:5
+%rs1 15:5
+";
+
+#[test]
+fn validate_rejects_overlap() {
+    let input = format!(
+        "{OVERLAP_HEAD}\
+         addi ............ ..... 000 ..... 0010011 %rs1 %rd
+         nop 000000000000 00000 000 00000 0010011
+"
+    );
+    let p = parse(&input).unwrap();
+    let e = validate(&p).unwrap_err();
+    assert_eq!(
+        e,
+        "line 4: pattern nop overlaps addi (line 3), both match 0x13"
+    );
+    // generate() refuses it too.
+    let e = generate(&input, &mut Vec::new()).unwrap_err();
+    assert!(e.contains("nop overlaps addi"), "{e}");
+}
+
+#[test]
+fn validate_allows_overlap_group() {
+    let input = format!(
+        "{OVERLAP_HEAD}\
+         {{
+           nop 000000000000 00000 000 00000 0010011
+           addi ............ ..... 000 ..... 0010011 %rs1 %rd
+         }}
+         andi ............ ..... 111 ..... 0010011 %rs1 %rd
+"
+    );
+    let p = parse(&input).unwrap();
+    assert_eq!(p.patterns[0].groups, [0]);
+    assert!(p.groups[0].overlap);
+    assert!(p.patterns[2].groups.is_empty());
+    validate(&p).unwrap();
+}
+
+#[test]
+fn validate_rejects_overlap_across_groups() {
+    // Only a shared `{ }` orders two patterns.
+    let input = format!(
+        "{OVERLAP_HEAD}\
+         {{
+           addi ............ ..... 000 ..... 0010011 %rs1 %rd
+         }}
+         {{
+           nop 000000000000 00000 000 00000 0010011
+         }}
+"
+    );
+    let e = validate(&parse(&input).unwrap()).unwrap_err();
+    assert!(e.contains("nop overlaps addi"), "{e}");
+
+    // Nor does a `[ ]` inside one.
+    let input = format!(
+        "{OVERLAP_HEAD}\
+         {{
+           [
+             addi ............ ..... 000 ..... 0010011 %rs1 %rd
+             nop 000000000000 00000 000 00000 0010011
+           ]
+           andi ............ ..... 111 ..... 0010011 %rs1 %rd
+         }}
+"
+    );
+    let p = parse(&input).unwrap();
+    assert_eq!(p.patterns[0].groups, [0, 1]);
+    assert!(!p.groups[1].overlap);
+    let e = validate(&p).unwrap_err();
+    assert!(e.ends_with("in the [ ] group at line 4"), "{e}");
+}
+
+#[test]
+fn parse_unbalanced_groups() {
+    for (input, want) in [
+        ("{\n]\n", "line 2: unbalanced ']'"),
+        ("}\n", "line 1: unbalanced '}'"),
+        ("{\n[\n]\n", "line 1: group is never closed"),
+    ] {
+        assert_eq!(parse(input).err().as_deref(), Some(want), "{input}");
+    }
+}
+
+#[test]
+fn validate_riscv_decode_files() {
+    for (path, width) in [
+        ("../frontend/src/riscv/insn32.decode", 32),
+        ("../frontend/src/riscv/insn16.decode", 16),
+    ] {
+        let input = std::fs::read_to_string(path).unwrap();
+        let p = parse_with_width(&input, width).unwrap();
+        validate(&p).unwrap_or_else(|e| panic!("{path}: {e}"));
+    }
+}
+
+// ── Instruction length ──────────────────────────────────────
+
+#[test]
+fn generate_insn_len() {
+    let input = "\
+lw  ............ ..... 010 ..... 0000011
+sw  ....... ..... ..... 010 ..... 0100011
+";
+    let gen = |mixed_width| {
+        let opts = GenOptions {
+            mixed_width,
+            ..GenOptions::default()
+        };
+        let mut out = Vec::new();
+        generate_with_options(input, &mut out, &opts).map(|_| {
+            let code = String::from_utf8(out).unwrap();
+            let start = code.find("pub fn insn_len(").unwrap();
+            code[start..].split("\n}\n").next().unwrap().to_string()
+        })
+    };
+    // lw and sw agree on funct3 and the opcode but for bit 5.
+    let f = gen(true).unwrap();
+    assert!(f.contains("if first_halfword & 0x705f == 0x2003 {"), "{f}");
+    // A 32-bit-only decoder always says 4.
+    let f = gen(false).unwrap();
+    assert!(f.ends_with("    let _ = first_halfword;\n    4"), "{f}");
+
+    let input = "\
+lw  ............ ..... 010 ..... 0000011
+foo ............ ..... 101 ..... 1111100
+";
+    let opts = GenOptions {
+        mixed_width: true,
+        ..GenOptions::default()
+    };
+    let e = generate_with_options(input, &mut Vec::new(), &opts).unwrap_err();
+    assert!(e.contains("no common low bits"), "{e}");
+
+    // The 16-bit decoder has none.
+    let mut out = Vec::new();
+    generate_with_width("c_nop 000 0 00000 00000 01\n", &mut out, 16).unwrap();
+    assert!(!String::from_utf8(out).unwrap().contains("fn insn_len"));
+}
+
+// ── Output options ──────────────────────────────────────────
+
+#[test]
+fn generate_custom_names() {
+    let opts = GenOptions {
+        coverage: true,
+        trait_name: Some("Rv32".to_string()),
+        fn_name: Some("rv32_decode".to_string()),
+        args_vis: "pub(crate)".to_string(),
+        derive_eq: true,
+        allow: vec!["dead_code".to_string(), "clippy::all".to_string()],
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(mini_decode(), &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    let allow = "#[allow(dead_code, clippy::all)]\n";
+    assert!(code.contains(&format!("{allow}pub trait Rv32<Ir> {{")));
+    assert!(code.contains(&format!(
+        "{allow}pub fn rv32_decode<Ir, T: Rv32<Ir>>(ctx: &mut T,"
+    )));
+    assert!(code.contains(&format!("{allow}pub fn rv32_decode_meta(")));
+    assert!(code.contains(&format!("{allow}mod rv32_decode_coverage {{")));
+    assert!(code.contains("    impl Rv32<()> for Recorder {"));
+    assert!(code.contains("rv32_decode(&mut r, &mut (), insn)"));
+    assert!(code.contains(&format!(
+        "{allow}#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]\n\
+         pub(crate) struct ArgsR {{\n    pub(crate) rd: i64,"
+    )));
+    assert!(!code.contains("Decode<"), "{code}");
+    assert!(!code.contains("fn decode"), "{code}");
+
+    // Private args structs.
+    let opts = GenOptions {
+        args_vis: String::new(),
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(mini_decode(), &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains("\nstruct ArgsR {\n    rd: i64,"), "{code}");
+    assert!(!code.contains("#[allow("));
+}
+
+#[test]
+fn generate_insn_enum() {
+    let opts = GenOptions {
+        fn_name: Some("rv32_decode".to_string()),
+        derive_eq: true,
+        insn_enum: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(mini_decode(), &mut out, &opts).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(code.contains(
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n\
+         pub enum Insn {\n    Add(ArgsR),\n    Addi(ArgsI),\n}"
+    ));
+    assert!(code
+        .contains("pub fn rv32_decode_to_insn(insn: u32) -> Option<Insn> {"));
+    assert!(code.contains("return Some(Insn::Addi(a));"));
+
+    // Off by default.
+    let mut out = Vec::new();
+    generate(mini_decode(), &mut out).unwrap();
+    let code = String::from_utf8(out).unwrap();
+    assert!(!code.contains("Insn"));
+}
+
+#[test]
+fn generate_insn_enum_variant_clash() {
+    let input = "\
+&r   rd
+fence_i  ................. 001 rd:5 0001111 &r
+fenceI   ................. 010 rd:5 0001111 &r
+";
+    let opts = GenOptions {
+        insn_enum: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    let err = generate_with_options(input, &mut out, &opts).unwrap_err();
+    assert_eq!(
+        err,
+        "line 3: fenceI: variant FenceI already taken by fence_i (line 2)"
+    );
+    // Only the enum needs distinct variants.
+    let mut out = Vec::new();
+    generate(input, &mut out).unwrap();
+}
+
+fn riscv32_frontend_output() -> String {
+    let input =
+        std::fs::read_to_string("../frontend/src/riscv/insn32.decode").unwrap();
+    let opts = GenOptions {
+        coverage: true,
+        partial: Some(Default::default()),
+        source: "insn32.decode".to_string(),
+        source_table: true,
+        mixed_width: true,
+        insn_enum: true,
+        ..GenOptions::default()
+    };
+    let mut out = Vec::new();
+    generate_with_options(&input, &mut out, &opts).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn generate_deterministic() {
+    let code = riscv32_frontend_output();
+    assert_eq!(code, riscv32_frontend_output());
+    // Trait methods follow the source order.
+    let lui = code.find("fn trans_lui(").unwrap();
+    let auipc = code.find("fn trans_auipc(").unwrap();
+    let jal = code.find("fn trans_jal(").unwrap();
+    assert!(lui < auipc && auipc < jal);
+}
+
+#[test]
+fn generate_rustfmt_clean() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // Outside the workspace, so rustfmt takes its defaults as it
+    // would for a build script's OUT_DIR.
+    let path = std::env::temp_dir()
+        .join(format!("decode_fmt_{}.rs", std::process::id()));
+    std::fs::write(&path, riscv32_frontend_output()).unwrap();
+    let child = Command::new("rustfmt")
+        .args(["--edition", "2021", "--check"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn();
+    let Ok(child) = child else {
+        eprintln!("rustfmt not found, skipping");
+        let _ = std::fs::remove_file(&path);
+        return;
+    };
+    let out = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&path);
+    std::io::stderr().write_all(&out.stdout).unwrap();
+    assert!(out.status.success(), "generated code is not rustfmt-clean");
+}
@@ -0,0 +1,94 @@
+//! Partial decoders: patterns without a `trans_` dispatch to the
+//! `unimplemented()` hook (see `tests/build.rs`).
+
+// Only part of the generated API is exercised.
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/partial_decode.rs"));
+}
+
+use generated::*;
+
+/// Records every dispatch; implements `addi` and `sub` only.
+#[derive(Default)]
+struct Partial {
+    insn: u32,
+    done: Vec<(&'static str, i64)>,
+    missing: Vec<(&'static str, u32)>,
+}
+
+impl Decode<()> for Partial {
+    fn insn(&self) -> u32 {
+        self.insn
+    }
+
+    fn unimplemented(&mut self, name: &'static str, insn: u32) -> bool {
+        self.missing.push((name, insn));
+        false
+    }
+
+    fn trans_addi(&mut self, _ir: &mut (), a: &ArgsI) -> bool {
+        self.done.push(("addi", a.imm));
+        true
+    }
+
+    fn trans_sub(&mut self, _ir: &mut (), a: &ArgsR) -> bool {
+        self.done.push(("sub", a.rs2));
+        true
+    }
+}
+
+fn run(insn: u32) -> (bool, Partial) {
+    let mut p = Partial {
+        insn,
+        ..Partial::default()
+    };
+    let ok = decode(&mut p, &mut (), insn);
+    (ok, p)
+}
+
+#[test]
+fn test_partial_implemented_dispatch() {
+    // addi x1, x2, -3
+    let (ok, p) = run(0xffd1_0093);
+    assert!(ok);
+    assert_eq!(p.done, [("addi", -3)]);
+    assert!(p.missing.is_empty());
+
+    // sub x1, x2, x3
+    let (ok, p) = run(0x4031_00b3);
+    assert!(ok);
+    assert_eq!(p.done, [("sub", 3)]);
+    assert!(p.missing.is_empty());
+}
+
+#[test]
+fn test_partial_unimplemented_hook() {
+    for (insn, name) in [
+        (0x0051_2093, "slti"),
+        (0x0031_00b3, "add"),
+        (0x0ff0_000f, "fence"),
+    ] {
+        let (ok, p) = run(insn);
+        assert!(!ok, "{name}");
+        assert!(p.done.is_empty(), "{name}");
+        assert_eq!(p.missing, [(name, insn)]);
+    }
+    // Not a pattern at all: no hook call.
+    let (ok, p) = run(0);
+    assert!(!ok);
+    assert!(p.missing.is_empty());
+}
+
+#[test]
+fn test_partial_unimplemented_list() {
+    assert_eq!(UNIMPLEMENTED_PATTERNS, ["slti", "add", "fence"]);
+}
+
+#[test]
+fn test_partial_pattern_source() {
+    let src = "fixtures/partial.decode";
+    assert_eq!(pattern_source("addi"), Some((src, 8)));
+    assert_eq!(pattern_source("fence"), Some((src, 12)));
+    assert_eq!(pattern_source("nop"), None);
+}
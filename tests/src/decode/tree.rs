@@ -0,0 +1,166 @@
+//! The decision tree `decode()` is generated as: every word
+//! reaches the pattern the if-chain in `decode_meta()` picks,
+//! and the tree stays shallow.
+
+// Only part of the generated API is exercised.
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/riscv32_tree.rs"));
+}
+
+use generated::*;
+
+/// Records the patterns dispatch reaches; implements none.
+#[derive(Default)]
+struct Recorder {
+    insn: u32,
+    hits: Vec<&'static str>,
+}
+
+impl Decode<()> for Recorder {
+    fn insn(&self) -> u32 {
+        self.insn
+    }
+
+    fn unimplemented(&mut self, name: &'static str, _insn: u32) -> bool {
+        self.hits.push(name);
+        true
+    }
+}
+
+fn dispatch(insn: u32) -> Vec<&'static str> {
+    let mut r = Recorder {
+        insn,
+        ..Recorder::default()
+    };
+    let ok = decode(&mut r, &mut (), insn);
+    assert_eq!(ok, !r.hits.is_empty(), "{insn:#010x}");
+    r.hits
+}
+
+#[test]
+fn test_tree_resolves_common_insns() {
+    for (insn, name) in [
+        (0x0031_00b3, "add"), // add x1, x2, x3
+        (0x4031_00b3, "sub"), // sub x1, x2, x3
+        (0x0231_00b3, "mul"), // mul x1, x2, x3
+        (0x0000_0073, "ecall"),
+        (0x0051_1093, "slli"), // slli x1, x2, 5
+    ] {
+        assert_eq!(dispatch(insn), [name], "{insn:#010x}");
+    }
+}
+
+#[test]
+fn test_tree_matches_linear_order() {
+    let mut words: Vec<u32> =
+        CANONICAL_ENCODINGS.iter().map(|&(_, insn)| insn).collect();
+    // Plus a deterministic spread of arbitrary words.
+    let mut x = 0x2545_f491_u32;
+    for _ in 0..100_000 {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        words.push(x);
+        words.push(x | 0x3);
+    }
+    for insn in words {
+        let want: Vec<_> = decode_meta(insn).map(|m| m.0).into_iter().collect();
+        assert_eq!(dispatch(insn), want, "{insn:#010x}");
+    }
+}
+
+/// Nesting of `match` and the longest if-chain in `decode()`.
+fn tree_shape(code: &str) -> (usize, usize) {
+    let start = code.find("pub fn decode<").unwrap();
+    let end = start + code[start..].find("\n}\n").unwrap();
+    let (mut depth, mut chain) = (0, 0);
+    // The current run of leaf if-tests: their indent and count.
+    let mut run = (0, 0);
+    for line in code[start..end].lines() {
+        let ind = line.len() - line.trim_start().len();
+        let line = line.trim_start();
+        if line.starts_with("match ") {
+            // Each level indents by 8: the match, then its arms.
+            depth = depth.max(ind / 8 + 1);
+        }
+        if line.starts_with("if insn") {
+            run = if run.0 == ind {
+                (ind, run.1 + 1)
+            } else {
+                (ind, 1)
+            };
+            chain = chain.max(run.1);
+        } else if ind < run.0 {
+            run = (0, 0);
+        }
+    }
+    (depth, chain)
+}
+
+#[test]
+fn test_tree_depth_bounded() {
+    let code = include_str!(concat!(env!("OUT_DIR"), "/riscv32_tree.rs"));
+    let patterns = CANONICAL_ENCODINGS.len();
+    let (depth, chain) = tree_shape(code);
+    // opcode, funct3, then funct7 and the like, then the rs2
+    // selector of Zbb's unary forms, then at most a short chain
+    // of overlapping patterns: a handful of tests for any word,
+    // where the if-chain took up to one per pattern.
+    assert!(depth <= 4, "depth {depth}");
+    assert!(chain <= 5, "chain {chain}");
+    assert!(depth + chain < patterns / 10, "{depth} + {chain}");
+}
+
+#[test]
+fn test_insn_len_mixed_width() {
+    // c.addi, c.lw, c.jr, then addi and ecall.
+    for (half, len) in [
+        (0x0505, 2),
+        (0x4188, 2),
+        (0x8082, 2),
+        (0x0093, 4),
+        (0x0073, 4),
+    ] {
+        assert_eq!(insn_len(half), len, "{half:#06x}");
+    }
+    for &(name, insn) in CANONICAL_ENCODINGS {
+        assert_eq!(insn_len(insn as u16), 4, "{name}");
+    }
+}
+
+#[test]
+fn test_decode_to_insn_fields() {
+    // add x1, x2, x3
+    let Some(Insn::Add(a)) = decode_to_insn(0x0031_00b3) else {
+        panic!("add");
+    };
+    assert_eq!((a.rd, a.rs1, a.rs2), (1, 2, 3));
+    // addi x5, x6, -1
+    let Some(Insn::Addi(a)) = decode_to_insn(0xfff3_0293) else {
+        panic!("addi");
+    };
+    assert_eq!((a.rd, a.rs1, a.imm), (5, 6, -1));
+    // beq x1, x2, -8
+    let Some(Insn::Beq(a)) = decode_to_insn(0xfe20_8ce3) else {
+        panic!("beq");
+    };
+    assert_eq!((a.rs1, a.rs2, a.imm), (1, 2, -8));
+    // jal x1, 2048
+    let Some(Insn::Jal(a)) = decode_to_insn(0x0010_00ef) else {
+        panic!("jal");
+    };
+    assert_eq!((a.rd, a.imm), (1, 2048));
+    assert!(matches!(decode_to_insn(0x0000_0073), Some(Insn::Ecall(_))));
+    assert!(decode_to_insn(0).is_none());
+}
+
+#[test]
+fn test_decode_to_insn_matches_dispatch() {
+    for &(name, insn) in CANONICAL_ENCODINGS {
+        let got = decode_to_insn(insn).map(|i| format!("{i:?}"));
+        let want = decode::to_camel(name);
+        let ok = got.is_some_and(|g| g.starts_with(&format!("{want}(")));
+        assert!(ok, "{name}");
+    }
+}
@@ -0,0 +1,102 @@
+//! CPU and wall-clock budgets of the exec loop.
+
+use std::time::{Duration, Instant};
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::budget::{thread_cpu_time, Budget, Overrun};
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, bne, ecall, jal, TestCpu};
+
+/// Counts in x1 forever; the back edge is chained.
+fn forever() -> Vec<u32> {
+    vec![addi(1, 1, 1), jal(0, -4)]
+}
+
+fn run_forever(budget: Budget) -> (ExitReason, Duration) {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_budget(budget);
+    let mut t = TestCpu::new(&forever());
+    let start = Instant::now();
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert!(env.per_cpu.stats.chain_patched > 0);
+    assert!(t.cpu.gpr[1] > 0);
+    (r, start.elapsed())
+}
+
+#[test]
+fn test_cpu_budget_stops_chained_loop() {
+    let limit = Duration::from_millis(200);
+    let (r, elapsed) = run_forever(Budget {
+        cpu: Some(limit),
+        wall: None,
+    });
+    let ExitReason::TimedOut(report) = r else {
+        panic!("{r:?}");
+    };
+    assert_eq!(report.overrun, Overrun::Cpu);
+    assert_eq!(report.limit, limit);
+    assert!(report.thread_cpu >= limit, "{report}");
+    assert!(report.loop_cpu <= report.thread_cpu, "{report}");
+    assert!(report.pc < 8, "{report}");
+    assert_eq!(report.recent.last(), Some(&report.pc));
+    assert!(report.insns > 0);
+    assert!(elapsed < Duration::from_secs(10), "{elapsed:?}");
+    let text = report.to_string();
+    assert!(text.starts_with("CPU time limit of 0.200s"), "{text}");
+}
+
+#[test]
+fn test_wall_budget_stops_chained_loop() {
+    let limit = Duration::from_millis(100);
+    let (r, elapsed) = run_forever(Budget {
+        cpu: None,
+        wall: Some(limit),
+    });
+    let ExitReason::TimedOut(report) = r else {
+        panic!("{r:?}");
+    };
+    assert_eq!(report.overrun, Overrun::Wall);
+    assert!(report.wall >= limit, "{report}");
+    assert!(elapsed >= limit);
+    assert!(elapsed < Duration::from_secs(10), "{elapsed:?}");
+}
+
+/// A guest finishing within its budget is not disturbed, and
+/// the loop's CPU time is no more than the thread used.
+#[test]
+fn test_budget_not_reached() {
+    let code = vec![addi(1, 1, 1), bne(1, 3, -4), ecall()];
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_budget(Budget {
+        cpu: Some(Duration::from_secs(60)),
+        wall: Some(Duration::from_secs(60)),
+    });
+    let mut t = TestCpu::new(&code);
+    t.cpu.gpr[3] = 1_000_000;
+    let before = thread_cpu_time();
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    let used = thread_cpu_time() - before;
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], 1_000_000);
+    let cpu = env.per_cpu.cpu_time;
+    assert!(cpu > Duration::ZERO && cpu <= used, "{cpu:?} > {used:?}");
+    assert_eq!(env.per_cpu.budget.as_ref().unwrap().overrun(), None);
+}
+
+/// Without a budget the loop still accounts its CPU time.
+#[test]
+fn test_cpu_time_accumulates() {
+    let code = vec![addi(1, 1, 1), bne(1, 3, -4), ecall()];
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut t = TestCpu::new(&code);
+    t.cpu.gpr[3] = 100_000;
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+    let first = env.per_cpu.cpu_time;
+    assert!(first > Duration::ZERO);
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert!(env.per_cpu.cpu_time > first);
+}
@@ -0,0 +1,117 @@
+//! Strict chain check tests.
+
+use tcg_backend::{HostCodeGen, X86_64CodeGen};
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{add, addi, bne, ecall, jal, TestCpu};
+
+/// sum(1..=10) in a loop, then call-style jump to the exit.
+fn loop_code() -> Vec<u32> {
+    vec![
+        addi(1, 1, 1),
+        add(2, 2, 1),
+        bne(1, 3, -8),
+        jal(0, 8),
+        ecall(),
+        ecall(),
+    ]
+}
+
+fn run(env: &mut ExecEnv<X86_64CodeGen>, t: &mut TestCpu) -> [u64; 32] {
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    t.cpu.gpr[2] = 0;
+    t.cpu.gpr[3] = 10;
+    let r = unsafe { cpu_exec_loop(env, t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    t.cpu.gpr
+}
+
+/// Slot of `src` chained to `dst`.
+fn chained_slot(env: &ExecEnv<X86_64CodeGen>, src: usize, dst: usize) -> usize {
+    let jmp = env.shared.tb_store.get(src).jmp.lock().unwrap();
+    (0..2)
+        .find(|&s| jmp.jmp_dest[s] == Some(dst))
+        .expect("not chained")
+}
+
+#[test]
+fn test_chain_check_clean_run() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_chain_check();
+    let mut t = TestCpu::new(&loop_code());
+    let strict = run(&mut env, &mut t);
+    let again = run(&mut env, &mut t);
+    assert!(env.per_cpu.stats.chain_patched > 0);
+    let check = env.shared.chain_check.as_ref().unwrap();
+    assert_eq!(check.divergences(), []);
+
+    let mut plain = ExecEnv::new(X86_64CodeGen::new());
+    let expected = run(&mut plain, &mut TestCpu::new(&loop_code()));
+    assert_eq!(strict, expected);
+    assert_eq!(again, expected);
+    assert_eq!(strict[2], 55);
+}
+
+/// The loop's back edge patched to jump to the exit TB: the
+/// graph still says the loop, so the jump is caught before the
+/// loop is re-entered and the guest result stays correct.
+#[test]
+fn test_chain_check_catches_wrong_patch() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_chain_check();
+    let mut t = TestCpu::new(&loop_code());
+    run(&mut env, &mut t);
+
+    let store = &env.shared.tb_store;
+    let head = store.lookup(0, 0).unwrap();
+    let exit = store.lookup(12, 0).unwrap();
+    let slot = chained_slot(&env, head, head);
+    let jmp = store.get(head).jmp_insn_offset[slot].unwrap() as usize;
+    let wrong = store.get(exit).host_offset;
+    let code = env.shared.code_buf();
+    env.shared.backend.patch_jump(code, jmp, wrong);
+    assert_eq!(env.shared.backend.jump_target(code, jmp), wrong);
+
+    assert_eq!(run(&mut env, &mut t)[2], 55);
+    let check = env.shared.chain_check.as_ref().unwrap();
+    let d = check.divergences();
+    assert_eq!(d.len(), 1, "{d:?}");
+    assert_eq!((d[0].src, d[0].slot), (head, slot));
+    assert_eq!(d[0].taken, Some(exit));
+    assert_eq!(d[0].expected, Some(head));
+    let report = d[0].to_string();
+    assert!(report.contains("pc 0x0,"), "{report}");
+    assert!(report.contains("pc 0xc,"), "{report}");
+    assert!(report.contains(&format!("host {wrong:#x}")), "{report}");
+    assert!(env.shared.tb_store.get(head).is_invalid());
+
+    // Retranslated and rechained cleanly.
+    assert_eq!(run(&mut env, &mut t)[2], 55);
+    let check = env.shared.chain_check.as_ref().unwrap();
+    assert_eq!(check.divergences().len(), 1);
+}
+
+/// A chained target killed without unlinking its incoming
+/// jumps is a stale chain.
+#[test]
+fn test_chain_check_catches_stale_chain() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_chain_check();
+    let mut t = TestCpu::new(&loop_code());
+    run(&mut env, &mut t);
+
+    let store = &env.shared.tb_store;
+    let head = store.lookup(0, 0).unwrap();
+    let tail = store.lookup(12, 0).unwrap();
+    let slot = chained_slot(&env, head, tail);
+    store.get(tail).mark_dead();
+
+    assert_eq!(run(&mut env, &mut t)[2], 55);
+    let d = env.shared.chain_check.as_ref().unwrap().divergences();
+    assert_eq!(d.len(), 1, "{d:?}");
+    assert_eq!((d[0].src, d[0].slot), (head, slot));
+    assert_eq!(d[0].taken, Some(tail));
+    assert_eq!(d[0].what, "chained to an invalid TB");
+    assert!(d[0].to_string().contains("invalid)"));
+}
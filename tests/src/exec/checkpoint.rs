@@ -0,0 +1,372 @@
+//! Checkpoint requests from the exec loop, and a guest run
+//! checkpointed to disk and resumed by a new instance.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::budget::Budget;
+use tcg_exec::checkpoint::CheckpointInterval;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{EXCP_EBREAK, EXCP_ECALL};
+use tcg_linux_user::checkpoint::{
+    self, Checkpointer, FdPolicy, Resumed, RunInfo,
+};
+use tcg_linux_user::guest_space::{page_size, GuestSpace};
+use tcg_linux_user::machine::EcallMode;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
+
+use super::snapshot::SpaceCpu;
+use super::{
+    add, addi, beq, bne, ebreak, ecall, jal, ld, lui, sd, slli, sub, TestCpu,
+};
+
+fn counting_loop() -> TestCpu {
+    TestCpu::new(&[addi(1, 1, 1), jal(0, -4)])
+}
+
+/// A chained loop stops once per instruction interval.
+#[test]
+fn test_checkpoint_insn_interval() {
+    const EVERY: u64 = 5_000_000;
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_checkpoint(
+        CheckpointInterval {
+            insns: Some(EVERY),
+            wall: None,
+        },
+    );
+    let mut t = counting_loop();
+    let mut last = 0;
+    for _ in 0..3 {
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Checkpoint);
+        assert!(t.cpu.cycle - last >= EVERY, "{} {last}", t.cpu.cycle);
+        last = t.cpu.cycle;
+    }
+    assert_eq!(env.per_cpu.checkpoint.as_ref().unwrap().taken(), 3);
+    assert!(env.per_cpu.stats.chain_patched > 0);
+}
+
+#[test]
+fn test_checkpoint_wall_interval() {
+    const EVERY: Duration = Duration::from_millis(30);
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_checkpoint(
+        CheckpointInterval {
+            insns: None,
+            wall: Some(EVERY),
+        },
+    );
+    let mut t = counting_loop();
+    for _ in 0..2 {
+        let start = Instant::now();
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Checkpoint);
+        assert!(start.elapsed() >= EVERY - Duration::from_millis(1));
+    }
+}
+
+/// Requests that find no interval passed are dropped.
+#[test]
+fn test_checkpoint_not_due() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_budget(Budget {
+            cpu: None,
+            wall: Some(Duration::from_millis(350)),
+        })
+        .with_checkpoint(CheckpointInterval {
+            insns: Some(1 << 50),
+            wall: None,
+        });
+    let mut t = counting_loop();
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert!(matches!(r, ExitReason::TimedOut(_)), "{r:?}");
+    assert_eq!(env.per_cpu.checkpoint.as_ref().unwrap().taken(), 0);
+}
+
+const CODE: u64 = 0x10000;
+const DATA: u64 = 0x20000;
+const IN_PATH: i32 = 0x100;
+const OUT_PATH: i32 = 0x200;
+const BUF: i32 = 0x300;
+/// Iterations of the busy loop after each chunk.
+const DELAY: i32 = 0x10_0000;
+/// Input fd.
+const S2: u32 = 18;
+/// Output fd.
+const S3: u32 = 19;
+/// Checksum.
+const S4: u32 = 20;
+/// Bytes read.
+const S5: u32 = 21;
+
+/// Read the input file 8 bytes at a time; after each chunk,
+/// fold it into a checksum, append the checksum to the output
+/// file and spin a while. Stop with ebreak at end of file.
+fn file_target() -> Vec<u32> {
+    vec![
+        lui(8, DATA as i32),   // 0: s0 = DATA
+        addi(10, 0, -100),     // 4: AT_FDCWD
+        addi(11, 8, IN_PATH),  // 8
+        addi(12, 0, 0),        // 12: O_RDONLY
+        addi(17, 0, 56),       // 16: openat
+        ecall(),               // 20
+        addi(S2, 10, 0),       // 24
+        addi(10, 0, -100),     // 28
+        addi(11, 8, OUT_PATH), // 32
+        addi(12, 0, 0o1101),   // 36: O_WRONLY | O_CREAT | O_TRUNC
+        addi(13, 0, 0o644),    // 40
+        addi(17, 0, 56),       // 44: openat
+        ecall(),               // 48
+        addi(S3, 10, 0),       // 52
+        addi(10, S2, 0),       // 56: loop
+        addi(11, 8, BUF),      // 60
+        addi(12, 0, 8),        // 64
+        addi(17, 0, 63),       // 68: read
+        ecall(),               // 72
+        beq(10, 0, 64),        // 76: -> 140
+        add(S5, S5, 10),       // 80
+        ld(5, 8, BUF),         // 84
+        slli(6, S4, 5),        // 88
+        sub(S4, 6, S4),        // 92: s4 *= 31
+        add(S4, S4, 5),        // 96
+        sd(S4, 8, BUF + 8),    // 100
+        addi(10, S3, 0),       // 104
+        addi(11, 8, BUF + 8),  // 108
+        addi(12, 0, 8),        // 112
+        addi(17, 0, 64),       // 116: write
+        ecall(),               // 120
+        lui(7, DELAY),         // 124
+        addi(7, 7, -1),        // 128
+        bne(7, 0, -4),         // 132
+        jal(0, -80),           // 136: -> 56
+        ebreak(),              // 140
+    ]
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(tag: &str) -> Self {
+        let pid = std::process::id();
+        let dir = std::env::temp_dir().join(format!("tcg_ckpt_{tag}_{pid}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run_info() -> RunInfo {
+    RunInfo {
+        elf_path: "/target".to_string(),
+        timebase_freq: 10_000_000,
+        deterministic: false,
+        ecall: EcallMode::Linux,
+        uart: None,
+    }
+}
+
+fn cstr(path: &Path) -> Vec<u8> {
+    let mut s = path.to_str().unwrap().as_bytes().to_vec();
+    s.push(0);
+    s
+}
+
+/// A guest process around the exec loop, as in `tcg-riscv64`.
+struct Machine {
+    env: ExecEnv<X86_64CodeGen>,
+    cpu: SpaceCpu,
+    space: GuestSpace,
+    vfs: Vfs,
+    process: Process,
+    signals: SignalTable,
+    mmap_next: u64,
+    run: RunInfo,
+}
+
+impl Machine {
+    fn new(input: &Path, output: &Path, every: Option<Duration>) -> Self {
+        let ps = page_size();
+        let rw = libc::PROT_READ | libc::PROT_WRITE;
+        let mut space = GuestSpace::new().unwrap();
+        let code: Vec<u8> =
+            file_target().iter().flat_map(|i| i.to_le_bytes()).collect();
+        space.mmap_fixed(CODE, ps, rw).unwrap();
+        unsafe { space.write_bytes(CODE, &code) };
+        space
+            .mprotect(CODE, ps, libc::PROT_READ | libc::PROT_EXEC)
+            .unwrap();
+        space.mmap_fixed(DATA, ps, rw).unwrap();
+        unsafe {
+            space.write_bytes(DATA + IN_PATH as u64, &cstr(input));
+            space.write_bytes(DATA + OUT_PATH as u64, &cstr(output));
+        }
+        let mut cpu = RiscvCpu::new();
+        cpu.pc = CODE;
+        cpu.guest_base = space.guest_base() as u64;
+        Self {
+            env: env(every),
+            cpu: SpaceCpu { cpu },
+            space,
+            vfs: Vfs::new(None),
+            process: Process::new(),
+            signals: SignalTable::new(),
+            mmap_next: 0x4000_0000,
+            run: run_info(),
+        }
+    }
+
+    fn resumed(r: Resumed, every: Option<Duration>) -> Self {
+        Self {
+            env: env(every),
+            cpu: SpaceCpu { cpu: r.cpu },
+            space: r.space,
+            vfs: r.vfs,
+            process: r.process,
+            signals: r.signals,
+            mmap_next: r.mmap_next,
+            run: r.run,
+        }
+    }
+
+    /// Run until the guest stops, true, or until `ck` has
+    /// written `stop_at` checkpoints.
+    fn drive(
+        &mut self,
+        mut ck: Option<&mut Checkpointer>,
+        stop_at: u64,
+    ) -> bool {
+        let mut written = 0;
+        loop {
+            let r = unsafe { cpu_exec_loop(&mut self.env, &mut self.cpu) };
+            match r {
+                ExitReason::Exit(TbExit::Exception(EXCP_ECALL)) => {
+                    let result = handle_syscall(
+                        &mut self.space,
+                        &mut self.vfs,
+                        &mut self.process,
+                        &mut self.signals,
+                        &mut self.cpu.cpu.gpr,
+                        &mut self.mmap_next,
+                        &self.run.elf_path,
+                        &SyscallPolicy::default(),
+                    );
+                    let SyscallResult::Continue(ret) = result else {
+                        panic!("guest exited");
+                    };
+                    self.cpu.cpu.gpr[10] = ret;
+                    self.cpu.cpu.pc += 4;
+                }
+                ExitReason::Exit(TbExit::Exception(EXCP_EBREAK)) => {
+                    return true
+                }
+                ExitReason::Checkpoint => {
+                    let ck = ck.as_deref_mut().unwrap();
+                    ck.write(checkpoint::Guest {
+                        cpu: &self.cpu.cpu,
+                        space: &mut self.space,
+                        vfs: &self.vfs,
+                        process: &self.process,
+                        signals: &self.signals,
+                        mmap_next: self.mmap_next,
+                        run: &self.run,
+                    })
+                    .unwrap();
+                    written += 1;
+                    if written == stop_at {
+                        return false;
+                    }
+                }
+                r => panic!("{r:?}"),
+            }
+        }
+    }
+
+    fn fds(&self) -> (i32, i32) {
+        let gpr = &self.cpu.cpu.gpr;
+        (gpr[S2 as usize] as i32, gpr[S3 as usize] as i32)
+    }
+}
+
+fn env(every: Option<Duration>) -> ExecEnv<X86_64CodeGen> {
+    let env = ExecEnv::new(X86_64CodeGen::new());
+    match every {
+        Some(wall) => env.with_checkpoint(CheckpointInterval {
+            insns: None,
+            wall: Some(wall),
+        }),
+        None => env,
+    }
+}
+
+fn offset(fd: i32) -> i64 {
+    unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) }
+}
+
+/// A run stopped after its second checkpoint, as if the
+/// emulator had died, and resumed by a fresh instance ends
+/// like an uninterrupted one: same checksum, same output, and
+/// the file offsets of the checkpoint.
+#[test]
+fn test_checkpoint_resume_matches_uninterrupted_run() {
+    let dir = TempDir::new("resume");
+    let input = dir.0.join("input");
+    let words: Vec<u8> = (1..=48u64)
+        .flat_map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes())
+        .collect();
+    fs::write(&input, &words).unwrap();
+
+    let mut m = Machine::new(&input, &dir.0.join("out-a"), None);
+    assert!(m.drive(None, 0));
+    let want_sum = m.cpu.cpu.gpr[S4 as usize];
+    assert_eq!(m.cpu.cpu.gpr[S5 as usize], words.len() as u64);
+    let (a, b) = m.fds();
+    unsafe {
+        libc::close(a);
+        libc::close(b);
+    }
+    let want = fs::read(dir.0.join("out-a")).unwrap();
+    assert_eq!(want.len(), words.len());
+
+    let every = Some(Duration::from_millis(5));
+    let ckdir = dir.0.join("ckpt");
+    let out = dir.0.join("out-b");
+    let mut m = Machine::new(&input, &out, every);
+    let mut ck = Checkpointer::new(&ckdir, FdPolicy::Refuse).unwrap();
+    assert!(!m.drive(Some(&mut ck), 2), "done before two checkpoints");
+    let (in_fd, out_fd) = m.fds();
+    let at = (offset(in_fd), offset(out_fd));
+    assert!(at.0 > 0 && at.0 < words.len() as i64, "{at:?}");
+    drop(m);
+    drop(ck);
+    // The dead instance's fds are still open; rewound, they
+    // would show if resuming kept them rather than reopening.
+    unsafe {
+        libc::lseek(in_fd, 0, libc::SEEK_SET);
+        libc::lseek(out_fd, 0, libc::SEEK_SET);
+    }
+
+    let r = checkpoint::resume(&ckdir).unwrap();
+    assert_eq!(r.seq, 2);
+    assert_eq!((offset(in_fd), offset(out_fd)), at);
+    let mut m = Machine::resumed(r, None);
+    assert!(m.drive(None, 0));
+    unsafe {
+        libc::close(in_fd);
+        libc::close(out_fd);
+    }
+    assert_eq!(m.cpu.cpu.gpr[S4 as usize], want_sum);
+    assert_eq!(fs::read(&out).unwrap(), want);
+}
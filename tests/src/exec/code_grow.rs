@@ -0,0 +1,147 @@
+//! Growing the code buffer in place when it fills up, and
+//! flushing it when it cannot grow.
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::coverage::Coverage;
+use tcg_exec::exec_loop::{cpu_exec_loop, cpu_exec_loop_mt, ExitReason};
+use tcg_exec::{ExecEnv, PerCpuState};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, ecall, jal, TestCpu};
+
+/// Blocks translated by `chain_code`.
+const BLOCKS: usize = 600;
+
+/// `BLOCKS` one-TB blocks of `addi x1, x1, 1; jal x0, +4`,
+/// then ecall: far more host code than one page holds.
+fn chain_code() -> Vec<u32> {
+    let mut code = Vec::new();
+    for _ in 0..BLOCKS {
+        code.push(addi(1, 1, 1));
+        code.push(jal(0, 4));
+    }
+    code.push(ecall());
+    code
+}
+
+fn small_env(buf: CodeBuffer) -> ExecEnv<X86_64CodeGen> {
+    ExecEnv::with_code_buf(X86_64CodeGen::new(), buf)
+}
+
+#[test]
+fn test_code_buf_grows_instead_of_filling() {
+    let buf = CodeBuffer::with_reserve(8192, 1 << 20).unwrap();
+    let base = buf.base_ptr();
+    let mut env = small_env(buf);
+    let mut t = TestCpu::new(&chain_code());
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], BLOCKS as u64);
+    assert_eq!(env.shared.tb_store.len(), BLOCKS + 1);
+
+    let stats = &env.per_cpu.stats;
+    assert!(stats.code_grow > 0);
+    assert_eq!(stats.code_full, 0);
+    let code = env.shared.code_buf();
+    assert!(code.capacity() > 8192);
+    assert_eq!(code.base_ptr(), base, "buffer moved");
+
+    // TBs translated before the first grow still run.
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], BLOCKS as u64);
+    assert_eq!(env.per_cpu.stats.translate, BLOCKS as u64 + 1);
+}
+
+/// The multi-thread loop cannot know other vCPUs are out of
+/// their TBs, so it leaves the flush to its caller.
+#[test]
+fn test_code_buf_full_when_growth_impossible() {
+    let buf = CodeBuffer::new(8192).unwrap();
+    let mut env = small_env(buf).with_code_buf_limit(8192);
+    let mut t = TestCpu::new(&chain_code());
+
+    let r = unsafe { cpu_exec_loop_mt(&env.shared, &mut env.per_cpu, &mut t) };
+    assert_eq!(r, ExitReason::BufferFull);
+    let stats = &env.per_cpu.stats;
+    assert_eq!(stats.code_grow, 0);
+    assert_eq!(stats.code_full, 1);
+    assert_eq!(stats.code_flush, 0);
+    assert_eq!(env.shared.code_buf().capacity(), 8192);
+    // Everything translated before filling up ran correctly.
+    assert_eq!(t.cpu.gpr[1], env.shared.tb_store.len() as u64);
+
+    // Flushed by the caller, the loop picks up where it left.
+    let start = env.shared.code_gen_start;
+    unsafe { env.shared.flush_code_cache() };
+    assert!(env.shared.tb_store.is_empty());
+    assert_eq!(env.shared.code_buf().offset(), start);
+    let r = unsafe { cpu_exec_loop_mt(&env.shared, &mut env.per_cpu, &mut t) };
+    assert_eq!(r, ExitReason::BufferFull);
+    assert_eq!(t.cpu.gpr[1], env.per_cpu.stats.translate - 2);
+}
+
+#[test]
+fn test_code_buf_flushed_when_growth_impossible() {
+    let buf = CodeBuffer::new(8192).unwrap();
+    let mut env = small_env(buf).with_code_buf_limit(8192);
+    env.per_cpu.coverage = Some(Coverage::new());
+    let mut t = TestCpu::new(&chain_code());
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], BLOCKS as u64);
+    let stats = &env.per_cpu.stats;
+    assert!(stats.code_flush > 1, "{} flushes", stats.code_flush);
+    assert_eq!(stats.code_full, stats.code_flush);
+    assert_eq!(stats.translate, BLOCKS as u64 + 1 + stats.code_flush);
+    assert_eq!(env.shared.flushes(), stats.code_flush);
+    assert!(env.shared.tb_store.len() < BLOCKS);
+    assert_eq!(env.shared.code_buf().capacity(), 8192);
+    // Blocks from before each flush keep their coverage.
+    let blocks = env.per_cpu.coverage.as_ref().unwrap().blocks();
+    assert_eq!(blocks.len(), BLOCKS + 1);
+
+    // Chains into flushed code were dropped with it.
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], BLOCKS as u64);
+}
+
+/// vCPUs keep running and chaining, reading the buffer
+/// unlocked, while whichever translates grows it under them.
+#[test]
+fn test_code_buf_grows_under_running_vcpus() {
+    let buf = CodeBuffer::with_reserve(8192, 1 << 20).unwrap();
+    let env = small_env(buf);
+    let shared = &env.shared;
+
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(move || {
+                let mut t = TestCpu::new(&chain_code());
+                let mut per_cpu = PerCpuState::new();
+                for _ in 0..3 {
+                    t.cpu.pc = 0;
+                    t.cpu.gpr[1] = 0;
+                    let r = unsafe {
+                        cpu_exec_loop_mt(shared, &mut per_cpu, &mut t)
+                    };
+                    assert_eq!(
+                        r,
+                        ExitReason::Exit(TbExit::Exception(EXCP_ECALL))
+                    );
+                    assert_eq!(t.cpu.gpr[1], BLOCKS as u64);
+                }
+            });
+        }
+    });
+    assert!(shared.code_buf().capacity() > 8192);
+}
@@ -0,0 +1,82 @@
+//! The shared IR context is reused across TBs without
+//! reallocating.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::context::Context;
+use tcg_core::tb::{TbExit, TranslationInfo};
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ExecEnv, GuestCpu};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, ecall, jal, TestCpu};
+
+/// `TestCpu` that records the IR context's op capacity before
+/// and after the frontend fills it, and its global count.
+struct Probe {
+    t: TestCpu,
+    seen: Vec<(usize, usize, u32)>,
+}
+
+impl GuestCpu for Probe {
+    fn get_pc(&self) -> u64 {
+        self.t.get_pc()
+    }
+
+    fn get_flags(&self) -> u32 {
+        self.t.get_flags()
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
+        let before = ir.op_capacity();
+        let info = self.t.gen_code(ir, pc, flags, max_insns);
+        self.seen.push((before, ir.op_capacity(), ir.nb_globals()));
+        info
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        self.t.env_ptr()
+    }
+
+    fn insns_retired(&self) -> u64 {
+        self.t.insns_retired()
+    }
+}
+
+/// 1000 TBs of 1 to 16 instructions: after the first few, no
+/// translation reallocates the op vector, and the globals the
+/// first TB registered are still the only ones.
+#[test]
+fn test_ir_context_reused_across_tbs() {
+    let mut insns = Vec::new();
+    for i in 0..1000 {
+        for _ in 0..i % 16 {
+            insns.push(addi(5, 5, 1));
+        }
+        insns.push(jal(0, 4));
+    }
+    insns.push(ecall());
+    let mut p = Probe {
+        t: TestCpu::new(&insns),
+        seen: Vec::new(),
+    };
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut p) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(p.t.cpu.gpr[5], (0..1000).map(|i| i % 16).sum::<u64>());
+
+    assert_eq!(p.seen.len(), 1001);
+    let warm = p.seen[16];
+    assert_eq!(warm.0, warm.1);
+    assert!(warm.2 > 0);
+    assert!(
+        p.seen[16..].iter().all(|&s| s == warm),
+        "{:?}",
+        &p.seen[16..]
+    );
+}
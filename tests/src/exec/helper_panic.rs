@@ -0,0 +1,166 @@
+//! Panics in helpers surface as `ExitReason::HelperPanic`.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::context::Context;
+use tcg_core::helper::{self, CALL_NO_PANIC};
+use tcg_core::tb::{DisasJumpType, TbExit, TranslationInfo};
+use tcg_core::{TempIdx, Type};
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ExecEnv, GuestCpu};
+use tcg_frontend::riscv::cpu::{RiscvCpu, PC_OFFSET};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+/// Block that calls `helper_boom`, then `helper_bump`.
+const BOOM_PC: u64 = 0x1000;
+/// Block that calls only `helper_bump`.
+const BUMP_PC: u64 = 0x2000;
+/// Block that calls only `helper_boom`, flagged as unable to
+/// panic.
+const NOCHECK_PC: u64 = 0x3000;
+
+extern "C" fn helper_boom(_env: *mut RiscvCpu) -> u64 {
+    helper::guard(|| -> u64 { panic!("helper exploded: {}", 42) })
+}
+
+extern "C" fn helper_bump(env: *mut RiscvCpu) -> u64 {
+    helper::guard(|| {
+        unsafe { (*env).gpr[1] += 1 };
+        0
+    })
+}
+
+/// One-instruction blocks built directly from IR; each ends
+/// with `pc += 4` and an ECALL exit.
+struct CallCpu {
+    cpu: RiscvCpu,
+    env: TempIdx,
+    pc: TempIdx,
+}
+
+impl CallCpu {
+    fn new(pc: u64) -> Self {
+        let mut cpu = RiscvCpu::new();
+        cpu.pc = pc;
+        Self {
+            cpu,
+            env: TempIdx(0),
+            pc: TempIdx(0),
+        }
+    }
+}
+
+impl GuestCpu for CallCpu {
+    fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    fn get_flags(&self) -> u32 {
+        0
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        _flags: u32,
+        _max_insns: u32,
+    ) -> TranslationInfo {
+        if ir.nb_globals() == 0 {
+            self.env = ir.new_fixed(Type::I64, ir.env_reg, "env");
+            self.pc = ir.new_global(Type::I64, self.env, PC_OFFSET, "pc");
+        }
+        ir.gen_insn_start(pc);
+        let dst = ir.new_temp(Type::I64);
+        if pc == BOOM_PC {
+            ir.gen_call(dst, helper_boom as *const () as u64, 0, &[self.env]);
+        }
+        if pc == NOCHECK_PC {
+            let f = helper_boom as *const () as u64;
+            ir.gen_call(dst, f, CALL_NO_PANIC, &[self.env]);
+        } else {
+            ir.gen_call(dst, helper_bump as *const () as u64, 0, &[self.env]);
+        }
+        let next = ir.new_const(Type::I64, pc + 4);
+        ir.gen_mov(Type::I64, self.pc, next);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_ECALL));
+        TranslationInfo {
+            guest_len_bytes: 4,
+            guest_insns: 1,
+            is_jmp: DisasJumpType::NoReturn,
+            first_pc: pc,
+            next_pc: pc + 4,
+            spin_loop: false,
+        }
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        &mut self.cpu as *mut RiscvCpu as *mut u8
+    }
+}
+
+#[test]
+fn test_helper_panic_exits_tb() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut c = CallCpu::new(BOOM_PC);
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert_eq!(
+        r,
+        ExitReason::HelperPanic {
+            message: "helper exploded: 42".to_string(),
+            pc: BOOM_PC,
+        }
+    );
+    // The TB stopped right after the panicking call.
+    assert_eq!(c.cpu.gpr[1], 0);
+    assert_eq!(c.cpu.pc, BOOM_PC);
+    assert_eq!(helper::take_panic(), None);
+}
+
+#[test]
+fn test_exec_continues_after_helper_panic() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut c = CallCpu::new(BOOM_PC);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert!(matches!(r, ExitReason::HelperPanic { .. }));
+
+    c.cpu.pc = BUMP_PC;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(c.cpu.gpr[1], 1);
+    assert_eq!(c.cpu.pc, BUMP_PC + 4);
+}
+
+#[test]
+fn test_unchecked_helper_panic_found_at_tb_exit() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut c = CallCpu::new(NOCHECK_PC);
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    // No check after the call: the TB ran to its end.
+    assert_eq!(c.cpu.pc, NOCHECK_PC + 4);
+    assert_eq!(
+        r,
+        ExitReason::HelperPanic {
+            message: "helper exploded: 42".to_string(),
+            pc: NOCHECK_PC + 4,
+        }
+    );
+    assert_eq!(helper::take_panic(), None);
+}
+
+#[test]
+fn test_guard_passes_result_through() {
+    assert_eq!(helper::guard(|| 7u64), 7);
+    assert_eq!(helper::take_panic(), None);
+}
+
+#[test]
+fn test_guard_records_panic() {
+    let r: u64 = helper::guard(|| panic!("direct"));
+    assert_eq!(r, 0);
+    let p = helper::take_panic().unwrap();
+    assert_eq!(p.message, "direct");
+    assert_eq!(p.host_ret, 0, "not called from generated code");
+    assert_eq!(helper::take_panic(), None);
+}
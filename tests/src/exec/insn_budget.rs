@@ -0,0 +1,77 @@
+//! Instruction budget: the exec loop stops after exactly the
+//! budgeted number of retired guest instructions.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, bne, ecall, TestCpu};
+
+/// `addi x<n>, x0, n` for n in 1..=10, then ecall.
+fn straight_line() -> Vec<u32> {
+    let mut insns: Vec<u32> = (1..=10).map(|n| addi(n, 0, n as i32)).collect();
+    insns.push(ecall());
+    insns
+}
+
+#[test]
+fn test_budget_stops_mid_tb() {
+    let mut t = TestCpu::new(&straight_line());
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    env.per_cpu.insn_budget = Some(7);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::BudgetExhausted);
+    assert_eq!(t.cpu.pc, 28);
+    assert_eq!(t.cpu.cycle, 7);
+    for n in 1..=10 {
+        let want = if n <= 7 { n as u64 } else { 0 };
+        assert_eq!(t.cpu.gpr[n], want, "x{n}");
+    }
+
+    // The rest runs from where the budget stopped.
+    env.per_cpu.insn_budget = None;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[10], 10);
+    assert_eq!(t.cpu.cycle, 11);
+}
+
+/// Stepping in slices of 3 visits the same states as one run.
+#[test]
+fn test_budget_in_slices() {
+    let mut t = TestCpu::new(&straight_line());
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    for slice in 1..=3 {
+        env.per_cpu.insn_budget = Some(3);
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::BudgetExhausted);
+        assert_eq!(t.cpu.cycle, 3 * slice);
+        assert_eq!(t.cpu.pc, 4 * 3 * slice);
+    }
+}
+
+/// Branching back does not chain past the budget.
+#[test]
+fn test_budget_in_loop() {
+    // 0: addi x1, x1, 1; 4: bne x1, x2, -4; 8: ecall
+    let mut t = TestCpu::new(&[addi(1, 1, 1), bne(1, 2, -4), ecall()]);
+    t.cpu.gpr[2] = 100;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    env.per_cpu.insn_budget = Some(25);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::BudgetExhausted);
+    assert_eq!((t.cpu.gpr[1], t.cpu.pc, t.cpu.cycle), (13, 4, 25));
+    assert_eq!(env.per_cpu.stats.chain_patched, 0);
+}
+
+#[test]
+fn test_zero_budget_runs_nothing() {
+    let mut t = TestCpu::new(&straight_line());
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    env.per_cpu.insn_budget = Some(0);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::BudgetExhausted);
+    assert_eq!((t.cpu.pc, t.cpu.cycle), (0, 0));
+}
@@ -0,0 +1,97 @@
+//! Per-TB guest instruction boundaries.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ExecEnv, MidInsn};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, c_addi, c_li, ecall, jal, Parcel, TestCpu};
+
+fn run(t: &mut TestCpu) -> ExecEnv<X86_64CodeGen> {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    env
+}
+
+fn starts(env: &ExecEnv<X86_64CodeGen>, pc: u64) -> Vec<u64> {
+    let store = &env.shared.tb_store;
+    store
+        .get(store.lookup(pc, 0).unwrap())
+        .insn_starts()
+        .collect()
+}
+
+#[test]
+fn test_insn_starts_pure_32bit() {
+    let mut t =
+        TestCpu::new(&[addi(1, 0, 1), addi(1, 1, 2), addi(1, 1, 3), ecall()]);
+    let env = run(&mut t);
+    assert_eq!(starts(&env, 0), [0, 4, 8, 12]);
+    assert_eq!(env.per_cpu.stats.insn_starts_bytes, 4);
+
+    let store = &env.shared.tb_store;
+    let tb = store.get(store.lookup(0, 0).unwrap());
+    assert!(tb.contains_insn_at(8));
+    assert!(!tb.contains_insn_at(10));
+}
+
+#[test]
+fn test_insn_starts_mixed_rvc() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        C(c_li(1, 1)),
+        W(addi(1, 1, 2)),
+        C(c_addi(1, 3)),
+        W(ecall()),
+    ]);
+    let env = run(&mut t);
+    assert_eq!(starts(&env, 0), [0, 2, 6, 8]);
+    assert_eq!(t.cpu.gpr[1], 6);
+}
+
+/// A breakpoint must sit on an instruction start; inside a
+/// 4-byte instruction it is rejected with the real start.
+#[test]
+fn test_breakpoint_mid_insn_rejected() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        C(c_li(1, 1)),
+        W(addi(1, 1, 2)),
+        C(c_addi(1, 3)),
+        W(ecall()),
+    ]);
+    let env = run(&mut t);
+    let store = &env.shared.tb_store;
+    for pc in [0, 2, 6, 8, 0x1000] {
+        assert_eq!(store.check_insn_boundary(pc), Ok(()), "pc {pc:#x}");
+    }
+    let err = store.check_insn_boundary(4).unwrap_err();
+    assert_eq!(err, MidInsn { pc: 4, insn: 2 });
+    assert_eq!(
+        err.to_string(),
+        "0x4 is not an instruction boundary (inside the instruction at 0x2)"
+    );
+    assert!(store.check_insn_boundary(10).is_err());
+}
+
+/// Data between two TBs on the same page (e.g. a JIT's literal
+/// pool) can be rewritten without killing either TB.
+#[test]
+fn test_invalidate_spares_code_around_data() {
+    let mut t = TestCpu::new(&[
+        addi(1, 0, 1),
+        jal(0, 12),
+        0xdead_beef,
+        0xdead_beef,
+        addi(1, 1, 2),
+        ecall(),
+    ]);
+    let env = run(&mut t);
+    assert_eq!(starts(&env, 0), [0, 4]);
+    assert_eq!(starts(&env, 16), [16, 20]);
+    assert_eq!(env.shared.tb_invalidate_range(8, 16), 0);
+    assert_eq!(env.shared.tb_invalidate_range(15, 17), 1);
+    assert_eq!(env.shared.tb_store.check_insn_boundary(8), Ok(()));
+}
@@ -0,0 +1,189 @@
+//! Prometheus metrics served while the exec loop runs.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::budget::Budget;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ChainPolicy, ExecEnv};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, bne, ecall, jal, TestCpu};
+
+/// Send `request` to `addr`; returns the status line and body.
+fn http(addr: SocketAddr, request: &str) -> (String, String) {
+    let mut s = TcpStream::connect(addr).unwrap();
+    s.write_all(request.as_bytes()).unwrap();
+    let mut resp = String::new();
+    s.read_to_string(&mut resp).unwrap();
+    let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, body.to_string())
+}
+
+fn scrape(addr: SocketAddr) -> String {
+    let (status, body) = http(addr, "GET /metrics HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    body
+}
+
+/// Value of the sample `series`, e.g. `tcg_tbs{state="valid"}`.
+fn value(text: &str, series: &str) -> f64 {
+    text.lines()
+        .find_map(|l| l.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {series} in\n{text}"))
+        .parse()
+        .unwrap()
+}
+
+const FAMILIES: &[&str] = &[
+    "tcg_tbs gauge",
+    "tcg_tb_hash_buckets gauge",
+    "tcg_code_buffer_bytes gauge",
+    "tcg_code_buffer_utilization gauge",
+    "tcg_loop_iterations_total counter",
+    "tcg_guest_insns_total counter",
+    "tcg_translated_insns_total counter",
+    "tcg_translated_bytes_total counter",
+    "tcg_tb_lookups_total counter",
+    "tcg_tb_exits_total counter",
+    "tcg_chain_refused_total counter",
+    "tcg_tb_invalidated_total counter",
+    "tcg_code_buffer_full_total counter",
+    "tcg_code_cache_flushes_total counter",
+    "tcg_cpu_seconds_total counter",
+    "tcg_phase_seconds_total counter",
+    "tcg_guest_mips gauge",
+];
+
+/// Two scrapes of a vCPU counting in an unchained loop on
+/// another thread see it make progress.
+#[test]
+fn test_metrics_scrape_during_run() {
+    let (tx, rx) = mpsc::channel();
+    let vcpu = thread::spawn(move || {
+        let mut env = ExecEnv::new(X86_64CodeGen::new())
+            .with_chain_policy(ChainPolicy::Never)
+            .with_timing()
+            .with_metrics("127.0.0.1:0")
+            .unwrap()
+            .with_budget(Budget {
+                cpu: None,
+                wall: Some(Duration::from_millis(1500)),
+            });
+        tx.send(env.metrics.as_ref().unwrap().local_addr()).unwrap();
+        let mut t = TestCpu::new(&[addi(1, 1, 1), jal(0, -4)]);
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert!(matches!(r, ExitReason::TimedOut(_)), "{r:?}");
+    });
+    let addr = rx.recv().unwrap();
+
+    thread::sleep(Duration::from_millis(300));
+    let first = scrape(addr);
+    thread::sleep(Duration::from_millis(400));
+    let second = scrape(addr);
+    vcpu.join().unwrap();
+
+    for family in FAMILIES {
+        assert!(second.contains(&format!("# TYPE {family}\n")), "{family}");
+    }
+    let insns = "tcg_guest_insns_total{cpu=\"0\"}";
+    let iters = "tcg_loop_iterations_total{cpu=\"0\"}";
+    assert!(value(&first, insns) > 0.0);
+    assert!(value(&second, insns) > value(&first, insns));
+    assert!(value(&second, iters) > value(&first, iters));
+    assert!(value(&second, "tcg_guest_mips{cpu=\"0\"}") > 0.0);
+    assert!(value(&second, "tcg_tbs{state=\"valid\"}") >= 1.0);
+    assert!(
+        value(
+            &second,
+            "tcg_tb_lookups_total{cpu=\"0\",result=\"translate\"}"
+        ) >= 1.0
+    );
+    // Chain exits, each refused by the policy.
+    let exits = "tcg_tb_exits_total{cpu=\"0\",kind=\"chain_0\"}";
+    let refused = "tcg_chain_refused_total{cpu=\"0\",reason=\"never\"}";
+    assert!(value(&second, exits) > 0.0);
+    assert_eq!(value(&second, refused), value(&second, exits));
+    assert!(
+        value(
+            &second,
+            "tcg_phase_seconds_total{cpu=\"0\",phase=\"execute\"}"
+        ) > 0.0
+    );
+    let util = value(&second, "tcg_code_buffer_utilization");
+    assert!(util > 0.0 && util < 1.0, "{util}");
+    assert!(
+        value(&second, "tcg_code_buffer_bytes{kind=\"tbs\"}")
+            < value(&second, "tcg_code_buffer_bytes{kind=\"used\"}")
+    );
+}
+
+/// Leaving the loop publishes the final counts; the server
+/// stops with the environment.
+#[test]
+fn test_metrics_final_counts() {
+    let code = vec![addi(1, 1, 1), bne(1, 3, -4), ecall()];
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_metrics("127.0.0.1:0")
+        .unwrap();
+    let addr = env.metrics.as_ref().unwrap().local_addr();
+    let mut t = TestCpu::new(&code);
+    t.cpu.gpr[3] = 1000;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+
+    let text = scrape(addr);
+    let stats = &env.per_cpu.stats;
+    assert_eq!(
+        value(&text, "tcg_guest_insns_total{cpu=\"0\"}"),
+        stats.insns as f64
+    );
+    assert_eq!(
+        value(&text, "tcg_chain_patched_total{cpu=\"0\"}"),
+        stats.chain_patched as f64
+    );
+    assert_eq!(value(&text, "tcg_tbs{state=\"valid\"}"), 2.0);
+
+    drop(env);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+/// A scrape does not wait for a translation in progress.
+#[test]
+fn test_metrics_scrape_during_translation() {
+    let env = ExecEnv::new(X86_64CodeGen::new())
+        .with_metrics("127.0.0.1:0")
+        .unwrap();
+    let addr = env.metrics.as_ref().unwrap().local_addr();
+    let used = env.shared.code_buf().offset() as f64;
+
+    let _translating = env.shared.translate_lock.lock().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(scrape(addr)).unwrap());
+    let text = rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("scrape blocked on translate_lock");
+    assert_eq!(value(&text, "tcg_code_buffer_bytes{kind=\"used\"}"), used);
+}
+
+#[test]
+fn test_metrics_other_requests() {
+    let env = ExecEnv::new(X86_64CodeGen::new())
+        .with_metrics("127.0.0.1:0")
+        .unwrap();
+    let addr = env.metrics.as_ref().unwrap().local_addr();
+    let (status, _) = http(addr, "GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    let (status, _) = http(addr, "POST /metrics HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    // No vCPU has run yet.
+    let text = scrape(addr);
+    assert_eq!(value(&text, "tcg_guest_insns_total{cpu=\"0\"}"), 0.0);
+    assert_eq!(value(&text, "tcg_guest_mips{cpu=\"0\"}"), 0.0);
+}
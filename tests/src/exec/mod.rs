@@ -1,22 +1,47 @@
 //! Integration tests for the tcg-exec execution loop.
 
+mod budget;
+mod chain_check;
+mod checkpoint;
+mod code_grow;
+mod ctx_reuse;
+mod helper_panic;
+mod insn_budget;
+mod insn_starts;
+mod metrics;
 mod mttcg;
+mod snapshot;
+mod spin;
+#[cfg(debug_assertions)]
+mod stack_check;
+mod timing;
+mod translate_fail;
+mod verify;
+mod warmup;
 
+use tcg_backend::regalloc::RegAllocMode;
+use tcg_backend::x86_64::regs::ALLOCATABLE_REGS;
 use tcg_backend::X86_64CodeGen;
 use tcg_core::context::Context;
-use tcg_core::tb::{EXCP_EBREAK, EXCP_ECALL};
-use tcg_core::TempIdx;
+use tcg_core::excp::ExcpRegistry;
+use tcg_core::tb::{TbExit, TranslationInfo, TB_FLAG_SINGLE_STEP};
+use tcg_exec::coverage::{Coverage, CoveredBlock};
 use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
-use tcg_exec::{ExecEnv, GuestCpu};
-use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_exec::{ChainPolicy, ExecEnv, GuestCpu, JumpPatch};
+use tcg_frontend::riscv::cpu::{GuestClock, RiscvCpu};
+use tcg_frontend::riscv::excp::{EXCP_EBREAK, EXCP_ECALL};
 use tcg_frontend::riscv::ext::RiscvCfg;
 use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
-use tcg_frontend::{translator_loop, DisasJumpType, TranslatorOps};
+use tcg_frontend::translator_loop;
 
 /// Test wrapper: RiscvCpu + guest code buffer.
 struct TestCpu {
     cpu: RiscvCpu,
     code: Vec<u8>,
+    flags: u32,
+    /// Shortest instruction in `code`; bounds how many
+    /// instructions a TB may take before running off the end.
+    min_insn_len: u64,
 }
 
 impl TestCpu {
@@ -26,11 +51,34 @@ impl TestCpu {
         Self {
             cpu: RiscvCpu::new(),
             code,
+            flags: 0,
+            min_insn_len: 4,
+        }
+    }
+
+    /// Code mixing 16-bit (RVC) and 32-bit instructions.
+    fn from_parcels(parcels: &[Parcel]) -> Self {
+        let mut code = Vec::new();
+        for p in parcels {
+            match *p {
+                Parcel::C(h) => code.extend_from_slice(&h.to_le_bytes()),
+                Parcel::W(w) => code.extend_from_slice(&w.to_le_bytes()),
+            }
+        }
+        Self {
+            code,
+            min_insn_len: 2,
+            ..Self::new(&[])
         }
     }
 }
 
-const NUM_GPRS: usize = 32;
+/// One instruction of a mixed-width program.
+#[derive(Clone, Copy)]
+enum Parcel {
+    C(u16),
+    W(u32),
+}
 
 impl GuestCpu for TestCpu {
     fn get_pc(&self) -> u64 {
@@ -38,51 +86,31 @@ impl GuestCpu for TestCpu {
     }
 
     fn get_flags(&self) -> u32 {
-        0
+        self.flags
     }
 
-    fn gen_code(&mut self, ir: &mut Context, pc: u64, max_insns: u32) -> u32 {
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
         let base = self.code.as_ptr();
-        let avail = (self.code.len() as u64 - pc) / 4;
-        let limit = max_insns.min(avail as u32);
-
-        if ir.nb_globals() == 0 {
-            // First call: register globals via translator_loop
-            let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
-            d.base.max_insns = limit;
-            translator_loop::<RiscvTranslator>(&mut d, ir);
-            d.base.num_insns * 4
-        } else {
-            // Reuse existing globals (same order as
-            // init_disas_context: env, gpr[0..32], pc)
-            let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
-            d.base.max_insns = limit;
-            d.env = TempIdx(0);
-            for i in 0..NUM_GPRS {
-                d.gpr[i] = TempIdx(1 + i as u32);
-            }
-            d.pc = TempIdx(1 + NUM_GPRS as u32);
-            // Run translation loop without init
-            RiscvTranslator::tb_start(&mut d, ir);
-            loop {
-                RiscvTranslator::insn_start(&mut d, ir);
-                RiscvTranslator::translate_insn(&mut d, ir);
-                if d.base.is_jmp != DisasJumpType::Next {
-                    break;
-                }
-                if d.base.num_insns >= d.base.max_insns {
-                    d.base.is_jmp = DisasJumpType::TooMany;
-                    break;
-                }
-            }
-            RiscvTranslator::tb_stop(&mut d, ir);
-            d.base.num_insns * 4
-        }
+        let avail = (self.code.len() as u64 - pc) / self.min_insn_len;
+        let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
+        d.base.max_insns = max_insns.min(avail as u32);
+        d.base.set_tb_flags(flags);
+        translator_loop::<RiscvTranslator>(&mut d, ir)
     }
 
     fn env_ptr(&mut self) -> *mut u8 {
         &mut self.cpu as *mut RiscvCpu as *mut u8
     }
+
+    fn insns_retired(&self) -> u64 {
+        self.cpu.cycle
+    }
 }
 
 // ── RISC-V instruction encoding helpers ─────────────────────
@@ -116,6 +144,15 @@ fn rv_b(imm: i32, rs2: u32, rs1: u32, f3: u32) -> u32 {
         | 0b1100011
 }
 
+fn rv_s(imm: i32, rs2: u32, rs1: u32, f3: u32) -> u32 {
+    let i = imm as u32;
+    ((i >> 5) & 0x7F) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | f3 << 12
+        | (i & 0x1F) << 7
+        | 0b0100011
+}
 fn rv_j(imm: i32, rd: u32) -> u32 {
     let i = imm as u32;
     let b20 = (i >> 20) & 1;
@@ -167,9 +204,28 @@ fn bge(rs1: u32, rs2: u32, imm: i32) -> u32 {
 fn ecall() -> u32 {
     0x0000_0073
 }
+fn ld(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b011, rd, 0b0000011)
+}
+fn sd(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    rv_s(imm, rs2, rs1, 0b011)
+}
 fn ebreak() -> u32 {
     0x0010_0073
 }
+fn sub(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(0b0100000, rs2, rs1, 0b000, rd, 0b0110011)
+}
+/// csrrs rd, csr, x0
+fn csrr(rd: u32, csr: i32) -> u32 {
+    rv_i(csr, 0, 0b010, rd, 0b1110011)
+}
+fn rdcycle(rd: u32) -> u32 {
+    csrr(rd, 0xC00)
+}
+fn rdtime(rd: u32) -> u32 {
+    csrr(rd, 0xC01)
+}
 
 // ── Helper ──────────────────────────────────────────────────
 
@@ -180,7 +236,7 @@ fn run(insns: &[u32], setup: impl FnOnce(&mut TestCpu)) -> TestCpu {
     let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
     assert_eq!(
         r,
-        ExitReason::Exit(EXCP_ECALL as usize),
+        ExitReason::Exit(TbExit::Exception(EXCP_ECALL)),
         "expected ecall exit"
     );
     t
@@ -194,7 +250,7 @@ fn run_env(
     setup(&mut t);
     let mut env = ExecEnv::new(X86_64CodeGen::new());
     let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
-    assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
     (t, env)
 }
 
@@ -223,7 +279,7 @@ fn test_exec_loop_cache_hit() {
     let mut env = ExecEnv::new(X86_64CodeGen::new());
 
     let r1 = unsafe { cpu_exec_loop(&mut env, &mut t) };
-    assert_eq!(r1, ExitReason::Exit(EXCP_ECALL as usize));
+    assert_eq!(r1, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
     assert_eq!(t.cpu.gpr[1], 5);
     assert_eq!(env.shared.tb_store.len(), 1);
 
@@ -231,7 +287,7 @@ fn test_exec_loop_cache_hit() {
     t.cpu.pc = 0;
     t.cpu.gpr[1] = 0;
     let r2 = unsafe { cpu_exec_loop(&mut env, &mut t) };
-    assert_eq!(r2, ExitReason::Exit(EXCP_ECALL as usize));
+    assert_eq!(r2, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
     assert_eq!(t.cpu.gpr[1], 5);
     assert_eq!(env.shared.tb_store.len(), 1);
 }
@@ -254,6 +310,844 @@ fn test_exec_loop_sum() {
     assert_eq!(t.cpu.gpr[2], 15); // 1+2+3+4+5
 }
 
+/// Invalidating a guest range forces retranslation of code
+/// that changed underneath a cached TB.
+#[test]
+fn test_tb_invalidate_range_retranslates() {
+    let mut t = TestCpu::new(&[addi(1, 0, 1), ecall()]);
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[1], 1);
+
+    // Rewrite the code; the stale TB still runs.
+    t.code[..4].copy_from_slice(&addi(1, 0, 2).to_le_bytes());
+    t.cpu.pc = 0;
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(t.cpu.gpr[1], 1);
+
+    // Unrelated range: nothing invalidated.
+    assert_eq!(env.shared.tb_invalidate_range(0x1000, 0x2000), 0);
+    assert_eq!(env.shared.tb_invalidate_range(0, 4), 1);
+    t.cpu.pc = 0;
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(t.cpu.gpr[1], 2);
+    assert_eq!(env.shared.tb_store.len(), 2);
+}
+
+/// TBs are indexed by guest page: invalidating one page leaves
+/// the TBs on other pages alone and drops the dead TB from its
+/// page list.
+///
+///   0x0:    addi x1, x1, 1
+///   0x4:    jal  x0, +0xffc  → 0x1000
+///   0x1000: addi x2, x2, 1
+///   0x1004: ecall
+#[test]
+fn test_tb_invalidate_range_by_page() {
+    let mut insns = vec![addi(0, 0, 0); 0x1000 / 4];
+    insns[0] = addi(1, 1, 1);
+    insns[1] = jal(0, 0xffc);
+    insns.extend([addi(2, 2, 1), ecall()]);
+    let mut t = TestCpu::new(&insns);
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+
+    let store = &env.shared.tb_store;
+    let (a, b) = (
+        store.lookup(0, 0).unwrap(),
+        store.lookup(0x1000, 0).unwrap(),
+    );
+    assert_eq!(store.tbs_on_page(0), vec![a]);
+    assert_eq!(store.tbs_on_page(0x1004), vec![b]);
+    assert!(store.tbs_on_page(0x2000).is_empty());
+
+    assert_eq!(env.shared.tb_invalidate_range(0x1000, 0x1004), 1);
+    assert!(store.get(b).is_invalid());
+    assert!(!store.get(a).is_invalid());
+    assert!(store.tbs_on_page(0x1000).is_empty());
+    assert_eq!(env.shared.tb_invalidate_range(0x1000, 0x2000), 0);
+    assert_eq!(env.shared.tb_invalidate_ranges(&[(0, 1), (4, 8)]), 1);
+    assert!(store.tbs_on_page(0).is_empty());
+}
+
+/// Invalidating a chained-into TB resets the jumps into it,
+/// so the chained loop picks up the rewritten code.
+///
+///   0x0: addi x1, x1, 1
+///   0x4: jal  x0, +4     → 0x8, chained
+///   0x8: addi x2, x2, 1
+///   0xc: blt  x2, x3, -12 → 0x0, chained
+///   0x10: ecall
+#[test]
+fn test_tb_invalidate_unchains_sources() {
+    let mut t = TestCpu::new(&[
+        addi(1, 1, 1),
+        jal(0, 4),
+        addi(2, 2, 1),
+        blt(2, 3, -12),
+        ecall(),
+    ]);
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    t.cpu.gpr[3] = 10;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[1], t.cpu.gpr[2]), (10, 10));
+
+    let store = &env.shared.tb_store;
+    let (a, b) = (store.lookup(0, 0).unwrap(), store.lookup(8, 0).unwrap());
+    assert_eq!(store.get(a).jmp.lock().unwrap().jmp_dest[0], Some(b));
+
+    t.code[8..12].copy_from_slice(&addi(2, 2, 2).to_le_bytes());
+    assert_eq!(env.shared.tb_invalidate_range(8, 12), 1);
+    assert_eq!(store.get(a).jmp.lock().unwrap().jmp_dest[0], None);
+
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    t.cpu.gpr[2] = 0;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[1], t.cpu.gpr[2]), (5, 10));
+}
+
+// ── TB byte length ──────────────────────────────────────────
+
+fn c_li(rd: u32, imm: u32) -> u16 {
+    (0b010 << 13 | (imm >> 5 & 1) << 12 | rd << 7 | (imm & 0x1f) << 2 | 0b01)
+        as u16
+}
+fn c_addi(rd: u32, imm: u32) -> u16 {
+    ((imm >> 5 & 1) << 12 | rd << 7 | (imm & 0x1f) << 2 | 0b01) as u16
+}
+fn c_ebreak() -> u16 {
+    0x9002
+}
+
+/// Run `t` from pc 0 and return the size and icount of the TB
+/// starting at `pc`.
+fn tb_extent(t: &mut TestCpu, pc: u64) -> (u32, u32) {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    unsafe { cpu_exec_loop(&mut env, t) };
+    let store = &env.shared.tb_store;
+    let tb = store.get(store.lookup(pc, t.flags).expect("TB at pc"));
+    (tb.size, tb.icount as u32)
+}
+
+#[test]
+fn test_tb_size_pure_rvc() {
+    use Parcel::C;
+    let mut t = TestCpu::from_parcels(&[
+        C(c_li(1, 1)),
+        C(c_addi(1, 2)),
+        C(c_addi(1, 3)),
+        C(c_ebreak()),
+    ]);
+    assert_eq!(tb_extent(&mut t, 0), (8, 4));
+    assert_eq!(t.cpu.gpr[1], 6);
+}
+
+#[test]
+fn test_tb_size_mixed_widths() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        C(c_li(1, 1)),
+        W(addi(1, 1, 2)),
+        C(c_addi(1, 3)),
+        W(ecall()),
+    ]);
+    assert_eq!(tb_extent(&mut t, 0), (12, 4));
+    assert_eq!(t.cpu.gpr[1], 6);
+}
+
+#[test]
+fn test_tb_size_single_step_rvc() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        C(c_li(1, 1)),
+        W(addi(1, 1, 2)),
+        C(c_addi(1, 3)),
+        W(ecall()),
+    ]);
+    t.flags = TB_FLAG_SINGLE_STEP;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    let store = &env.shared.tb_store;
+    for (pc, size) in [(0, 2), (2, 4), (6, 2), (8, 4)] {
+        let tb = store.get(store.lookup(pc, TB_FLAG_SINGLE_STEP).unwrap());
+        assert_eq!((tb.size, tb.icount), (size, 1), "pc {pc:#x}");
+    }
+    assert_eq!(env.per_cpu.stats.insns_translated, 4);
+    assert_eq!(env.per_cpu.stats.bytes_translated, 12);
+}
+
+/// A TB ending in a 2-byte instruction covers exactly its
+/// bytes: a write to that instruction invalidates it, a write
+/// just past it does not.
+#[test]
+fn test_tb_invalidate_rvc_tail() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        W(addi(1, 0, 1)),
+        C(c_addi(1, 2)),
+        C(c_ebreak()),
+        W(addi(2, 0, 7)),
+    ]);
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_EBREAK)));
+    assert_eq!(t.cpu.gpr[1], 3);
+    assert_eq!(env.shared.tb_invalidate_range(8, 12), 0);
+
+    t.code[4..6].copy_from_slice(&c_addi(1, 5).to_le_bytes());
+    assert_eq!(env.shared.tb_invalidate_range(6, 8), 1);
+    t.cpu.pc = 0;
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(t.cpu.gpr[1], 6);
+    assert_eq!(env.shared.tb_store.len(), 2);
+}
+
+// ── Value numbering ─────────────────────────────────────────
+
+/// Two read-modify-write passes over fields of the struct at
+/// `x8`, with field offsets `offs`. Data follows the code.
+fn struct_rmw(offs: [i32; 6]) -> TestCpu {
+    let mut t = TestCpu::new(&[
+        ld(10, 8, offs[0]),
+        ld(11, 8, offs[1]),
+        add(10, 10, 11),
+        sd(10, 8, offs[2]),
+        ld(12, 8, offs[3]),
+        ld(13, 8, offs[4]),
+        add(12, 12, 13),
+        sd(12, 8, offs[5]),
+        ecall(),
+    ]);
+    let data = t.code.len().next_multiple_of(8);
+    t.code.resize(data + 64, 0);
+    for (i, v) in (0..8u64).map(|i| (i, i * 10 + 5)) {
+        let at = data + i as usize * 8;
+        t.code[at..at + 8].copy_from_slice(&v.to_le_bytes());
+    }
+    t.cpu.gpr[8] = data as u64;
+    t.cpu.guest_base = t.code.as_ptr() as u64;
+    t
+}
+
+fn run_host_size(t: &mut TestCpu) -> usize {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    let store = &env.shared.tb_store;
+    store.get(store.lookup(0, 0).unwrap()).host_size
+}
+
+/// Repeated `x8 + off` addresses share one computation: the
+/// TB is smaller than the same code over distinct offsets,
+/// and computes the same thing.
+#[test]
+fn test_cse_shrinks_struct_access() {
+    let mut same = struct_rmw([8, 16, 8, 8, 16, 16]);
+    let mut distinct = struct_rmw([8, 16, 24, 32, 40, 48]);
+    let same_size = run_host_size(&mut same);
+    let distinct_size = run_host_size(&mut distinct);
+    assert!(same_size < distinct_size, "{same_size} >= {distinct_size}");
+
+    // Field at offset `off` starts as 5 + off * 10 / 8.
+    let data = same.cpu.gpr[8] as usize;
+    let field = |t: &TestCpu, off: usize| {
+        let b = &t.code[data + off..data + off + 8];
+        u64::from_le_bytes(b.try_into().unwrap())
+    };
+    assert_eq!(same.cpu.gpr[10..14], [40, 25, 65, 25]);
+    assert_eq!(field(&same, 8), 40);
+    assert_eq!(field(&same, 16), 65);
+    assert_eq!(distinct.cpu.gpr[10..14], [40, 25, 100, 55]);
+    assert_eq!(field(&distinct, 24), 40);
+    assert_eq!(field(&distinct, 48), 100);
+}
+
+// ── Chaining policy ─────────────────────────────────────────
+
+/// Loop bouncing between two guest pages, `x3` iterations.
+///
+///   0x0000: addi x1, x1, 1
+///   0x0004: jal  x0, 0x1000     → page 1
+///   0x1000: addi x2, x2, <step>
+///   0x1004: bge  x2, x3, +8     → 0x100c
+///   0x1008: jal  x0, -0x1008    → page 0
+///   0x100c: ecall
+fn two_page_code(step: i32) -> Vec<u32> {
+    let mut insns = vec![0u32; 0x1010 / 4];
+    insns[0] = addi(1, 1, 1);
+    insns[1] = jal(0, 0x1000 - 4);
+    insns[0x1000 / 4] = addi(2, 2, step);
+    insns[0x1004 / 4] = bge(2, 3, 8);
+    insns[0x1008 / 4] = jal(0, -0x1008);
+    insns[0x100c / 4] = ecall();
+    insns
+}
+
+fn run_two_page(
+    env: &mut ExecEnv<X86_64CodeGen>,
+    t: &mut TestCpu,
+) -> (u64, u64) {
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    t.cpu.gpr[2] = 0;
+    t.cpu.gpr[3] = 10;
+    let r = unsafe { cpu_exec_loop(env, t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    (t.cpu.gpr[1], t.cpu.gpr[2])
+}
+
+fn chain_policy_env(policy: ChainPolicy) -> (ExecEnv<X86_64CodeGen>, TestCpu) {
+    let env = ExecEnv::new(X86_64CodeGen::new()).with_chain_policy(policy);
+    (env, TestCpu::new(&two_page_code(1)))
+}
+
+/// Every policy runs the program correctly; only the amount
+/// of chaining differs.
+#[test]
+fn test_chain_policy_always_and_never() {
+    let (mut env, mut t) = chain_policy_env(ChainPolicy::Always);
+    assert_eq!(run_two_page(&mut env, &mut t), (10, 10));
+    let stats = &env.per_cpu.stats;
+    assert!(stats.chain_patched >= 3);
+    assert_eq!(stats.chain_refused_page, 0);
+    assert_eq!(stats.chain_refused_never, 0);
+
+    let (mut env, mut t) = chain_policy_env(ChainPolicy::Never);
+    assert_eq!(run_two_page(&mut env, &mut t), (10, 10));
+    let stats = &env.per_cpu.stats;
+    assert_eq!(stats.chain_patched, 0);
+    assert!(stats.chain_refused_never > 0);
+}
+
+/// A conditional branch chains both of its exits: the
+/// fall-through through `goto_tb` slot 0, the taken edge
+/// through slot 1.
+///
+///   0:  addi x1, x1, 1
+///   4:  andi x5, x1, 1
+///   8:  bne  x5, x0, 8   → 16 on odd x1
+///   12: addi x2, x2, 1
+///   16: blt  x1, x3, -16 → 0
+///   20: ecall
+#[test]
+fn test_branch_chains_both_exits() {
+    let andi = rv_i(1, 1, 0b111, 5, 0b0010011);
+    let insns = [
+        addi(1, 1, 1),
+        andi,
+        bne(5, 0, 8),
+        addi(2, 2, 1),
+        blt(1, 3, -16),
+        ecall(),
+    ];
+    let mut t = TestCpu::new(&insns);
+    t.cpu.gpr[3] = 10;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[1], t.cpu.gpr[2]), (10, 5));
+
+    let store = &env.shared.tb_store;
+    let head = store.lookup(0, 0).unwrap();
+    let fall = store.lookup(12, 0).unwrap();
+    let taken = store.lookup(16, 0).unwrap();
+    let jmp = store.get(head).jmp.lock().unwrap();
+    assert_eq!(jmp.jmp_dest, [Some(fall), Some(taken)]);
+
+    let stats = &env.per_cpu.stats;
+    assert!(stats.chain_exit[0] > 0 && stats.chain_exit[1] > 0);
+    assert!(stats.chain_patched >= 2);
+    assert_eq!(env.per_cpu.jump_cache.lookup(12), Some(fall));
+    assert_eq!(env.per_cpu.jump_cache.lookup(16), Some(taken));
+}
+
+/// Indirect jumps leave the TB without chaining; with two
+/// alternating targets the per-TB exit cache misses every time,
+/// and the per-CPU jump cache resolves the target instead of
+/// the hash table.
+///
+///   0:  addi x10, x10, 1
+///   4:  andi x5, x10, 1
+///   8:  slli x5, x5, 3
+///   12: addi x5, x5, 24
+///   16: jalr x1, 0(x5)   → 24 or 32
+///   20: ecall
+///   24: jal  x0, 16      → 40
+///   28: ecall
+///   32: jal  x0, 8       → 40
+///   36: ecall
+///   40: blt  x10, x11, -40 → 0
+///   44: ecall
+#[test]
+fn test_indirect_jump_hits_jump_cache() {
+    let andi = rv_i(1, 10, 0b111, 5, 0b0010011);
+    let insns = [
+        addi(10, 10, 1),
+        andi,
+        slli(5, 5, 3),
+        addi(5, 5, 24),
+        jalr(1, 5, 0),
+        ecall(),
+        jal(0, 16),
+        ecall(),
+        jal(0, 8),
+        ecall(),
+        blt(10, 11, -40),
+        ecall(),
+    ];
+    let mut t = TestCpu::new(&insns);
+    t.cpu.gpr[11] = 100;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[10], t.cpu.pc), (100, 44));
+
+    let stats = &env.per_cpu.stats;
+    assert!(stats.jc_hit >= 95, "{stats}");
+    assert!(stats.ht_hit + stats.translate <= 5, "{stats}");
+}
+
+/// C.BNEZ rs1', offset
+fn c_bnez(rs1p: u32, off: i32) -> u16 {
+    let o = off as u32;
+    (0b111 << 13
+        | (o >> 8 & 1) << 12
+        | (o >> 3 & 3) << 10
+        | rs1p << 7
+        | (o >> 6 & 3) << 5
+        | (o >> 1 & 3) << 3
+        | (o >> 5 & 1) << 2
+        | 0b01) as u16
+}
+
+/// The compressed branches end the TB the same way: c.bnez
+/// exits through both slots and the loop result is unchanged.
+///
+///   0: c.addi x9, 1
+///   2: c.addi x8, -1
+///   4: c.bnez x8, -4 → 0
+///   6: ecall
+#[test]
+fn test_compressed_branch_chains_both_exits() {
+    use Parcel::{C, W};
+    let mut t = TestCpu::from_parcels(&[
+        C(c_addi(9, 1)),
+        C(c_addi(8, 0x3f)),
+        C(c_bnez(0, -4)),
+        W(ecall()),
+    ]);
+    t.cpu.gpr[8] = 5;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[8], t.cpu.gpr[9]), (0, 5));
+
+    let stats = &env.per_cpu.stats;
+    assert!(stats.chain_exit[0] > 0 && stats.chain_exit[1] > 0);
+    let store = &env.shared.tb_store;
+    let head = store.lookup(0, 0).unwrap();
+    let jmp = store.get(head).jmp.lock().unwrap();
+    assert_eq!(jmp.jmp_dest, [store.lookup(6, 0), Some(head)]);
+}
+
+/// SamePageOnly chains within page 1 but leaves both
+/// cross-page exits unchained, so remapping page 1 takes
+/// effect even for jumps coming from page 0.
+#[test]
+fn test_chain_policy_same_page_only() {
+    let (mut env, mut t) = chain_policy_env(ChainPolicy::SamePageOnly);
+    assert_eq!(run_two_page(&mut env, &mut t), (10, 10));
+    let stats = &env.per_cpu.stats;
+    assert!(stats.chain_patched > 0);
+    assert!(stats.chain_refused_page > 0);
+
+    let store = &env.shared.tb_store;
+    for pc in [0, 0x1008] {
+        let idx = store.lookup(pc, 0).unwrap();
+        let jmp = store.get(idx).jmp.lock().unwrap();
+        assert_eq!(jmp.jmp_dest, [None, None], "pc {pc:#x} chained");
+    }
+
+    // Remap page 1 with a different step.
+    let step2 = addi(2, 2, 2).to_le_bytes();
+    t.code[0x1000..0x1004].copy_from_slice(&step2);
+    assert!(env.shared.tb_invalidate_range(0x1000, 0x2000) > 0);
+    assert_eq!(run_two_page(&mut env, &mut t), (5, 10));
+}
+
+/// A TB starting on page 0 but ending on page 1 shares no
+/// page set with a TB wholly on page 0, so SamePageOnly
+/// chains neither direction.
+///
+///   0x000: jal  x0, 0xff8
+///   0xff8: addi x2, x2, 1
+///   0xffc: addi x1, x1, 1
+///   0x1000: blt x2, x3, -0x1000 → 0x0
+///   0x1004: ecall
+#[test]
+fn test_chain_policy_same_page_checks_last_page() {
+    let mut insns = vec![0u32; 0x1008 / 4];
+    insns[0] = jal(0, 0xff8);
+    insns[0xff8 / 4] = addi(2, 2, 1);
+    insns[0xffc / 4] = addi(1, 1, 1);
+    insns[0x1000 / 4] = blt(2, 3, -0x1000);
+    insns[0x1004 / 4] = ecall();
+    let mut t = TestCpu::new(&insns);
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_chain_policy(ChainPolicy::SamePageOnly);
+    t.cpu.gpr[3] = 10;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!((t.cpu.gpr[1], t.cpu.gpr[2]), (10, 10));
+
+    let stats = &env.per_cpu.stats;
+    assert_eq!(stats.chain_patched, 0);
+    assert!(stats.chain_refused_page >= 2);
+    let store = &env.shared.tb_store;
+    let span = store.get(store.lookup(0xff8, 0).unwrap());
+    assert_eq!((span.first_page(), span.last_page()), (0, 0x1000));
+    for pc in [0, 0xff8] {
+        let idx = store.lookup(pc, 0).unwrap();
+        let jmp = store.get(idx).jmp.lock().unwrap();
+        assert_eq!(jmp.jmp_dest, [None, None], "pc {pc:#x} chained");
+    }
+}
+
+/// Two threads chaining A→B and B→A at once take the two
+/// `jmp` locks in the same order and both edges land.
+///
+///   0x0: jal x0, 8          → slot 0 to 0x8
+///   0x4: ecall
+///   0x8: bne x1, x0, -8     → slot 1 to 0x0
+///   0xc: ecall
+#[test]
+fn test_chain_both_directions_concurrently() {
+    use std::sync::Barrier;
+
+    let insns = [jal(0, 8), ecall(), bne(1, 0, -8), ecall()];
+    let mut t = TestCpu::new(&insns);
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_chain_policy(ChainPolicy::Never);
+
+    for _ in 0..500 {
+        t.cpu.pc = 0;
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+
+        let shared = &env.shared;
+        let a = shared.tb_store.lookup(0, 0).unwrap();
+        let b = shared.tb_store.lookup(8, 0).unwrap();
+        let barrier = Barrier::new(2);
+        let chain = |src, slot, dst| {
+            barrier.wait();
+            shared.tb_store.add_jump(
+                src,
+                slot,
+                dst,
+                shared.code_buf(),
+                &shared.backend,
+            )
+        };
+        let (ab, ba) = std::thread::scope(|s| {
+            let ab = s.spawn(|| chain(a, 0, b));
+            let ba = s.spawn(|| chain(b, 1, a));
+            (ab.join().unwrap(), ba.join().unwrap())
+        });
+        assert_eq!((ab, ba), (JumpPatch::Patched, JumpPatch::Patched));
+
+        let (a_tb, b_tb) = (shared.tb_store.get(a), shared.tb_store.get(b));
+        assert_eq!(a_tb.jmp.lock().unwrap().jmp_dest[0], Some(b));
+        assert_eq!(b_tb.jmp.lock().unwrap().jmp_dest[1], Some(a));
+        assert_eq!(a_tb.jmp.lock().unwrap().jmp_list, [(b, 1)]);
+        assert_eq!(b_tb.jmp.lock().unwrap().jmp_list, [(a, 0)]);
+
+        shared.tb_invalidate_range(0, 0x10);
+    }
+}
+
+/// Chaining racing with invalidation of the target never
+/// leaves a jump into a dead TB.
+#[test]
+fn test_chain_vs_invalidate_race() {
+    use std::sync::Barrier;
+
+    // 0x0: jal x0, 4 (goto_tb slot 0 → 0x4)
+    // 0x4: ecall
+    let mut t = TestCpu::new(&[jal(0, 4), ecall()]);
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_chain_policy(ChainPolicy::Never);
+
+    for _ in 0..200 {
+        t.cpu.pc = 0;
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+
+        let shared = &env.shared;
+        let src = shared.tb_store.lookup(0, 0).unwrap();
+        let dst = shared.tb_store.lookup(4, 0).unwrap();
+        let barrier = Barrier::new(2);
+        std::thread::scope(|s| {
+            let chainer = s.spawn(|| {
+                barrier.wait();
+                shared.tb_store.add_jump(
+                    src,
+                    0,
+                    dst,
+                    shared.code_buf(),
+                    &shared.backend,
+                )
+            });
+            barrier.wait();
+            shared.tb_invalidate_range(4, 8);
+            // Either order is legal: the chain lands first and is
+            // unlinked, or the target is already dead.
+            let patch = chainer.join().unwrap();
+            assert!(matches!(
+                patch,
+                JumpPatch::Patched | JumpPatch::TargetDead
+            ));
+        });
+
+        let src_tb = shared.tb_store.get(src);
+        let dst_tb = shared.tb_store.get(dst);
+        assert!(dst_tb.is_invalid());
+        assert_eq!(src_tb.jmp.lock().unwrap().jmp_dest[0], None);
+        assert!(dst_tb.jmp.lock().unwrap().jmp_list.is_empty());
+        let off = src_tb.jmp_insn_offset[0].unwrap() as usize;
+        let disp = shared.code_buf().read_u32(off + 1) as i32;
+        let target = (off as i64 + 5 + disp as i64) as usize;
+        assert_ne!(target, dst_tb.host_offset);
+
+        shared.tb_invalidate_range(0, 4);
+    }
+}
+
+// ── Coverage ────────────────────────────────────────────────
+
+/// Run a branch over one instruction with coverage enabled.
+///
+///   0x0: beq  x1, x0, +8   → 0x8
+///   0x4: addi x2, x2, 1
+///   0x8: addi x3, x3, 1
+///   0xc: ecall
+fn branch_coverage(x1: u64) -> Vec<CoveredBlock> {
+    let insns = [beq(1, 0, 8), addi(2, 2, 1), addi(3, 3, 1), ecall()];
+    let mut t = TestCpu::new(&insns);
+    t.cpu.gpr[1] = x1;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    env.per_cpu.coverage = Some(Coverage::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    env.per_cpu.coverage.unwrap().blocks()
+}
+
+fn block(start: u64, end: u64, insns: u32) -> CoveredBlock {
+    CoveredBlock {
+        start,
+        end,
+        insns,
+        insn_starts: (start..end).step_by(4).collect(),
+        hits: 1,
+    }
+}
+
+#[test]
+fn test_coverage_branch_taken() {
+    assert_eq!(
+        branch_coverage(0),
+        [block(0x0, 0x4, 1), block(0x8, 0x10, 2)]
+    );
+}
+
+#[test]
+fn test_coverage_branch_not_taken() {
+    assert_eq!(
+        branch_coverage(1),
+        [block(0x0, 0x4, 1), block(0x4, 0x10, 3)]
+    );
+}
+
+/// Loop blocks accumulate hits; the raw dump lists them sorted.
+#[test]
+fn test_coverage_raw_format() {
+    let insns = [addi(1, 1, 1), bne(1, 3, -4), ecall()];
+    let mut t = TestCpu::new(&insns);
+    t.cpu.gpr[3] = 3;
+    let mut env = ExecEnv::new(X86_64CodeGen::new())
+        .with_chain_policy(ChainPolicy::Never);
+    env.per_cpu.coverage = Some(Coverage::new());
+    unsafe { cpu_exec_loop(&mut env, &mut t) };
+
+    let mut out = Vec::new();
+    let cov = env.per_cpu.coverage.as_ref().unwrap();
+    cov.write_raw(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let body: Vec<&str> =
+        text.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(body, ["0x0 0x8 2 3", "0x8 0xc 1 1"]);
+}
+
+/// Straight-line code cycling through 16 GPRs, keeping more
+/// globals resident than there are host registers so a single
+/// TB keeps evicting and reloading them.
+fn pressure_insns() -> Vec<u32> {
+    let mut insns = Vec::new();
+    for _ in 0..4 {
+        for r in 1..16 {
+            insns.push(add(r, r, r + 1));
+        }
+    }
+    insns.push(ecall());
+    insns
+}
+
+fn run_pressure(
+    limit: Option<u32>,
+    mode: RegAllocMode,
+) -> (TestCpu, ExecEnv<X86_64CodeGen>) {
+    let mut t = TestCpu::new(&pressure_insns());
+    for r in 1..32 {
+        t.cpu.gpr[r] = r as u64;
+    }
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_regalloc(mode);
+    if let Some(limit) = limit {
+        env = env.with_pressure_limit(limit);
+    }
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    (t, env)
+}
+
+/// A pressure limit splits the TB, keeps results identical and
+/// emits less host code than the spill-heavy single TB.
+#[test]
+fn test_pressure_limit_splits_tb() {
+    let greedy = RegAllocMode::Greedy;
+    let (base, base_env) = run_pressure(None, greedy);
+    let (split, split_env) =
+        run_pressure(Some(ALLOCATABLE_REGS.count()), greedy);
+
+    assert_eq!(base_env.shared.tb_store.len(), 1);
+    assert!(split_env.shared.tb_store.len() > 1);
+    assert!(split_env.per_cpu.stats.pressure_split > 0);
+    assert_eq!(base.cpu.gpr, split.cpu.gpr);
+
+    let host_bytes = |env: &ExecEnv<X86_64CodeGen>| -> usize {
+        (0..env.shared.tb_store.len())
+            .map(|i| env.shared.tb_store.get(i).host_size)
+            .sum()
+    };
+    assert!(host_bytes(&split_env) < host_bytes(&base_env));
+}
+
+/// Spilling by furthest next read keeps the globals the next
+/// instructions use resident: the same TB, the same results,
+/// less host code.
+#[test]
+fn test_furthest_use_shrinks_pressure_tb() {
+    let (greedy, greedy_env) = run_pressure(None, RegAllocMode::Greedy);
+    let (furthest, furthest_env) =
+        run_pressure(None, RegAllocMode::FurthestUse);
+    assert_eq!(furthest_env.shared.tb_store.len(), 1);
+    assert_eq!(furthest.cpu.gpr, greedy.cpu.gpr);
+    let host_size =
+        |env: &ExecEnv<X86_64CodeGen>| env.shared.tb_store.get(0).host_size;
+    assert!(host_size(&furthest_env) < host_size(&greedy_env));
+}
+
+// ── Counter CSRs ────────────────────────────────────────────
+
+/// rdcycle deltas count exactly the instructions in between,
+/// and the final count matches ExecStats.
+#[test]
+fn test_rdcycle_counts_straight_line() {
+    let (t, env) = run_env(
+        &[
+            rdcycle(1),
+            addi(5, 5, 1),
+            addi(5, 5, 1),
+            addi(5, 5, 1),
+            addi(5, 5, 1),
+            addi(5, 5, 1),
+            rdcycle(2),
+            ecall(),
+        ],
+        |_| {},
+    );
+    assert_eq!(t.cpu.gpr[1], 0);
+    assert_eq!(t.cpu.gpr[2] - t.cpu.gpr[1], 6);
+    assert_eq!(env.per_cpu.stats.insns, 8);
+    assert_eq!(t.cpu.cycle, 8);
+}
+
+/// Delay loop: spin until `x4` ticks of rdtime have elapsed.
+///
+///   PC=0:  rdtime x1
+///   PC=4:  rdtime x2
+///   PC=8:  sub    x3, x2, x1
+///   PC=12: blt    x3, x4, -8
+///   PC=16: ecall
+fn rdtime_delay(clock: GuestClock, ticks: u64) -> TestCpu {
+    let insns = [rdtime(1), rdtime(2), sub(3, 2, 1), blt(3, 4, -8), ecall()];
+    let mut t = TestCpu::new(&insns);
+    t.cpu.clock = clock;
+    t.cpu.gpr[4] = ticks;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    t
+}
+
+/// In real-time mode rdtime tracks host time at the timebase
+/// frequency.
+#[test]
+fn test_rdtime_real_time() {
+    const FREQ: u64 = 10_000_000;
+    let delay = std::time::Duration::from_millis(50);
+    let ticks = FREQ / 1000 * delay.as_millis() as u64;
+
+    let start = std::time::Instant::now();
+    let t = rdtime_delay(GuestClock::new(FREQ, false), ticks);
+    let host = start.elapsed();
+
+    let guest = t.cpu.gpr[2] - t.cpu.gpr[1];
+    assert!(guest >= ticks);
+    assert!(host >= delay, "host {host:?} < {delay:?}");
+    assert!(host < delay * 20, "host {host:?} too slow");
+}
+
+/// TB exits do not read the clock; only rdtime does.
+#[test]
+fn test_time_untouched_without_rdtime() {
+    let (t, _) = run_env(&[addi(1, 0, 1), addi(2, 0, 2), ecall()], |t| {
+        t.cpu.clock = GuestClock::new(1_000_000_000, true);
+    });
+    assert_eq!(t.cpu.cycle, 3);
+    assert_eq!(t.cpu.time, 0);
+}
+
+/// In deterministic mode rdtime follows retired instructions,
+/// so two runs agree exactly.
+#[test]
+fn test_rdtime_deterministic() {
+    let clock = || GuestClock::new(1_000_000_000, true);
+    let a = rdtime_delay(clock(), 1000);
+    let b = rdtime_delay(clock(), 1000);
+    assert_eq!(a.cpu.gpr, b.cpu.gpr);
+    assert_eq!(a.cpu.cycle, b.cpu.cycle);
+    // 1 GHz timebase: one tick per instruction. The last rdtime
+    // saw all but the sub, blt and ecall after it.
+    assert_eq!(a.cpu.gpr[2], a.cpu.time);
+    assert_eq!(a.cpu.time, a.cpu.cycle - 3);
+    assert!(a.cpu.gpr[3] >= 1000);
+}
+
 // ── New multi-TB tests ──────────────────────────────────────
 
 /// Countdown: x1 starts at N, decrements to 0, then exits.
@@ -637,7 +1531,7 @@ fn test_ebreak_exit_code() {
     let mut t = TestCpu::new(&insns);
     let mut env = ExecEnv::new(X86_64CodeGen::new());
     let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
-    assert_eq!(r, ExitReason::Exit(EXCP_EBREAK as usize));
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_EBREAK)));
     assert_eq!(t.cpu.gpr[1], 77);
 }
 
@@ -715,3 +1609,153 @@ fn test_multi_branch_targets() {
                                  // Multiple TBs from different branch targets
     assert!(env.shared.tb_store.len() >= 4);
 }
+
+// ── TB alignment ────────────────────────────────────────────
+
+/// Run `insns` with TB entry points aligned to `align` bytes and
+/// check every TB actually starts on that boundary.
+fn run_aligned(
+    insns: &[u32],
+    align: usize,
+    setup: impl FnOnce(&mut TestCpu),
+) -> TestCpu {
+    let mut t = TestCpu::new(insns);
+    setup(&mut t);
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_tb_align(align);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    let store = &env.shared.tb_store;
+    for idx in 0..store.len() {
+        let off = store.get(idx).host_offset;
+        assert_eq!(off % align, 0, "TB {idx} at {off:#x}, align {align}");
+    }
+    if align == 1 {
+        assert_eq!(env.per_cpu.stats.align_pad, 0);
+    }
+    t
+}
+
+#[test]
+fn test_tb_align_matrix() {
+    for align in [1, 64] {
+        let t = run_aligned(
+            &[addi(1, 1, 1), add(2, 2, 1), bne(1, 3, -8), ecall()],
+            align,
+            |t| t.cpu.gpr[3] = 100,
+        );
+        assert_eq!(t.cpu.gpr[2], 5050, "sum, align {align}");
+
+        let t = run_aligned(
+            &[
+                add(3, 1, 2),
+                add(1, 2, 0),
+                add(2, 3, 0),
+                addi(4, 4, -1),
+                bne(4, 0, -16),
+                ecall(),
+            ],
+            align,
+            |t| {
+                t.cpu.gpr[2] = 1;
+                t.cpu.gpr[4] = 9;
+            },
+        );
+        assert_eq!(t.cpu.gpr[2], 55, "fib, align {align}");
+
+        let t = run_aligned(
+            &[
+                addi(5, 5, 1),
+                addi(2, 2, 1),
+                bne(2, 4, -8),
+                addi(2, 0, 0),
+                addi(1, 1, 1),
+                bne(1, 3, -20),
+                ecall(),
+            ],
+            align,
+            |t| {
+                t.cpu.gpr[3] = 4;
+                t.cpu.gpr[4] = 3;
+            },
+        );
+        assert_eq!(t.cpu.gpr[5], 12, "nested, align {align}");
+
+        let t = run_aligned(
+            &[
+                addi(1, 0, 3),
+                jal(10, 8),
+                ecall(),
+                addi(2, 1, 4),
+                jalr(0, 10, 0),
+            ],
+            align,
+            |_| {},
+        );
+        assert_eq!(t.cpu.gpr[2], 7, "call/return, align {align}");
+    }
+}
+
+#[test]
+fn test_tb_align_default() {
+    let (_, env) =
+        run_env(&[addi(1, 0, 1), jal(0, 8), ecall(), ecall()], |_| {});
+    assert_eq!(env.shared.tb_align, tcg_exec::DEFAULT_TB_ALIGN);
+    let store = &env.shared.tb_store;
+    assert_eq!(store.len(), 2);
+    for idx in 0..store.len() {
+        assert_eq!(store.get(idx).host_offset % 16, 0);
+    }
+}
+
+// ── Single-step TBs ─────────────────────────────────────────
+
+#[test]
+fn test_single_step_tbs_coexist() {
+    let insns = [addi(1, 0, 1), addi(2, 1, 2), beq(0, 0, 8), ecall(), ecall()];
+    let (mut t, mut env) = run_env(&insns, |_| {});
+    assert_eq!(t.cpu.gpr[2], 3);
+    let normal = env.shared.tb_store.lookup(0, 0).expect("normal TB");
+    assert_eq!(env.shared.tb_store.get(normal).icount, 3);
+
+    t.cpu.gpr[1] = 0;
+    t.cpu.gpr[2] = 0;
+    t.cpu.pc = 0;
+    t.flags = TB_FLAG_SINGLE_STEP;
+    let patched = env.per_cpu.stats.chain_patched;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[2], 3);
+
+    let store = &env.shared.tb_store;
+    let step = store.lookup(0, TB_FLAG_SINGLE_STEP).expect("step TB");
+    assert_ne!(step, normal);
+    assert_eq!(store.lookup(0, 0), Some(normal));
+    for pc in [0, 4, 8] {
+        let idx = store.lookup(pc, TB_FLAG_SINGLE_STEP).unwrap();
+        let tb = store.get(idx);
+        assert_eq!(tb.icount, 1, "pc {pc:#x}");
+        assert_eq!(tb.jmp_insn_offset, [None, None], "pc {pc:#x}");
+    }
+    assert_eq!(env.per_cpu.stats.chain_patched, patched);
+}
+
+/// With a registry installed, a code it does not name leaves
+/// the loop as `UnknownException`; named codes exit as before.
+#[test]
+fn test_unregistered_exception_exits_loop() {
+    let mut reg = ExcpRegistry::new();
+    reg.register(EXCP_ECALL, "ecall");
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_exceptions(reg);
+    let mut t = TestCpu::new(&[addi(1, 0, 1), ecall(), ebreak()]);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+
+    t.cpu.pc += 4;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+    let ExitReason::UnknownException { code, pc } = r else {
+        panic!("unexpected {r:?}");
+    };
+    assert_eq!((code, pc), (EXCP_EBREAK, t.cpu.pc));
+    let reg = env.shared.excp.as_ref().unwrap();
+    assert_eq!(reg.describe(code).to_string(), "unregistered exception 0x4");
+}
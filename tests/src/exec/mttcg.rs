@@ -1,37 +1,23 @@
 //! Multi-threaded TCG (MTTCG) concurrent execution tests.
 
-use std::sync::Arc;
 use std::thread;
 
 use tcg_backend::X86_64CodeGen;
 use tcg_core::context::Context;
-use tcg_core::tb::EXCP_ECALL;
-use tcg_core::TempIdx;
+use tcg_core::tb::{TbExit, TranslationInfo};
 use tcg_exec::exec_loop::{cpu_exec_loop_mt, ExitReason};
-use tcg_exec::{ExecEnv, GuestCpu, PerCpuState, SharedState};
+use tcg_exec::{ExecEnv, GuestCpu, PerCpuState};
 use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
 use tcg_frontend::riscv::ext::RiscvCfg;
 use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
-use tcg_frontend::{translator_loop, DisasJumpType, TranslatorOps};
-
-const NUM_GPRS: usize = 32;
+use tcg_frontend::translator_loop;
 
 struct TestCpu {
     cpu: RiscvCpu,
     code: Vec<u8>,
 }
 
-impl TestCpu {
-    fn new(insns: &[u32]) -> Self {
-        let code: Vec<u8> =
-            insns.iter().flat_map(|i| i.to_le_bytes()).collect();
-        Self {
-            cpu: RiscvCpu::new(),
-            code,
-        }
-    }
-}
-
 impl GuestCpu for TestCpu {
     fn get_pc(&self) -> u64 {
         self.cpu.pc
@@ -39,39 +25,19 @@ impl GuestCpu for TestCpu {
     fn get_flags(&self) -> u32 {
         0
     }
-    fn gen_code(&mut self, ir: &mut Context, pc: u64, max_insns: u32) -> u32 {
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
         let base = self.code.as_ptr();
         let avail = (self.code.len() as u64 - pc) / 4;
-        let limit = max_insns.min(avail as u32);
-
-        if ir.nb_globals() == 0 {
-            let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
-            d.base.max_insns = limit;
-            translator_loop::<RiscvTranslator>(&mut d, ir);
-            d.base.num_insns * 4
-        } else {
-            let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
-            d.base.max_insns = limit;
-            d.env = TempIdx(0);
-            for i in 0..NUM_GPRS {
-                d.gpr[i] = TempIdx(1 + i as u32);
-            }
-            d.pc = TempIdx(1 + NUM_GPRS as u32);
-            RiscvTranslator::tb_start(&mut d, ir);
-            loop {
-                RiscvTranslator::insn_start(&mut d, ir);
-                RiscvTranslator::translate_insn(&mut d, ir);
-                if d.base.is_jmp != DisasJumpType::Next {
-                    break;
-                }
-                if d.base.num_insns >= d.base.max_insns {
-                    d.base.is_jmp = DisasJumpType::TooMany;
-                    break;
-                }
-            }
-            RiscvTranslator::tb_stop(&mut d, ir);
-            d.base.num_insns * 4
-        }
+        let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
+        d.base.max_insns = max_insns.min(avail as u32);
+        d.base.set_tb_flags(flags);
+        translator_loop::<RiscvTranslator>(&mut d, ir)
     }
 
     fn env_ptr(&mut self) -> *mut u8 {
@@ -120,10 +86,7 @@ fn ecall() -> u32 {
 }
 
 fn new_per_cpu() -> PerCpuState {
-    PerCpuState {
-        jump_cache: tcg_core::tb::JumpCache::new(),
-        stats: tcg_exec::ExecStats::default(),
-    }
+    PerCpuState::new()
 }
 
 /// Two vCPU threads each run an independent sum loop on
@@ -148,7 +111,7 @@ fn test_mt_sum_loop() {
         cpu.cpu.gpr[3] = 100; // sum 1..=100
         let mut pc = new_per_cpu();
         let r = unsafe { cpu_exec_loop_mt(&shared1, &mut pc, &mut cpu) };
-        assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
         assert_eq!(cpu.cpu.gpr[2], 5050);
     });
 
@@ -162,7 +125,7 @@ fn test_mt_sum_loop() {
         cpu.cpu.gpr[3] = 200; // sum 1..=200
         let mut pc = new_per_cpu();
         let r = unsafe { cpu_exec_loop_mt(&shared2, &mut pc, &mut cpu) };
-        assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
         assert_eq!(cpu.cpu.gpr[2], 20100);
     });
 
@@ -191,7 +154,7 @@ fn test_shared_tb_cache() {
             };
             let mut pc = new_per_cpu();
             let r = unsafe { cpu_exec_loop_mt(&s, &mut pc, &mut cpu) };
-            assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+            assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
             assert_eq!(cpu.cpu.gpr[1], 42);
         }));
     }
@@ -238,7 +201,7 @@ fn test_concurrent_tb_lookup() {
             };
             let mut pc = new_per_cpu();
             let r = unsafe { cpu_exec_loop_mt(&s, &mut pc, &mut cpu) };
-            assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+            assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
         }));
     }
     for h in handles {
@@ -270,7 +233,7 @@ fn test_concurrent_chaining() {
             cpu.cpu.gpr[3] = 50 + i as u64;
             let mut pc = new_per_cpu();
             let r = unsafe { cpu_exec_loop_mt(&s, &mut pc, &mut cpu) };
-            assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+            assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
             assert_eq!(cpu.cpu.gpr[1], 50 + i as u64);
         }));
     }
@@ -302,7 +265,7 @@ fn test_concurrent_translation() {
             cpu.cpu.gpr[3] = 10 * (i + 1) as u64;
             let mut pc = new_per_cpu();
             let r = unsafe { cpu_exec_loop_mt(&s, &mut pc, &mut cpu) };
-            assert_eq!(r, ExitReason::Exit(EXCP_ECALL as usize));
+            assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
             let n = cpu.cpu.gpr[3];
             let expected = n * (n + 1) / 2;
             assert_eq!(cpu.cpu.gpr[2], expected);
@@ -0,0 +1,196 @@
+//! Snapshot and restore of a guest running in a `GuestSpace`.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::context::Context;
+use tcg_core::tb::{TbExit, TranslationInfo};
+use tcg_exec::coverage::{Coverage, CoveredBlock};
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::snapshot::{EmuSnapshot, RestoreStats};
+use tcg_exec::{ChainPolicy, ExecEnv, GuestCpu};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{EXCP_EBREAK, EXCP_ECALL};
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
+use tcg_frontend::translator_loop;
+use tcg_linux_user::guest_space::{page_size, GuestSpace, MemSnapshot};
+
+use super::{add, addi, beq, bne, ebreak, ecall, ld, lui, sd, slli};
+
+const CODE: u64 = 0x10000;
+const DATA: u64 = 0x20000;
+/// Runs so far, at `DATA + COUNTER`.
+const COUNTER: i32 = 0x400;
+/// Input sum that makes the target crash.
+const CRASH: i32 = 1337;
+
+/// RiscvCpu fetching from the space its `guest_base` points at.
+pub(super) struct SpaceCpu {
+    pub(super) cpu: RiscvCpu,
+}
+
+impl GuestCpu for SpaceCpu {
+    fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    fn get_flags(&self) -> u32 {
+        0
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        flags: u32,
+        max_insns: u32,
+    ) -> TranslationInfo {
+        let base = self.cpu.guest_base as *const u8;
+        let mut d = RiscvDisasContext::new(pc, base, RiscvCfg::default());
+        d.base.max_insns = max_insns;
+        d.base.set_tb_flags(flags);
+        translator_loop::<RiscvTranslator>(&mut d, ir)
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        &mut self.cpu as *mut RiscvCpu as *mut u8
+    }
+}
+
+/// Sum the input words, bump the run counter, and exit with
+/// `sum + (runs << 32)`; crash with ebreak on a sum of `CRASH`.
+/// `counter_base` holds the counter's page: `DATA`, or `CODE`
+/// to write next to the code.
+fn target(counter_base: u32) -> Vec<u32> {
+    vec![
+        lui(8, DATA as i32),         // 0: s0 = DATA
+        lui(9, counter_base as i32), // 4: s1 = counter page
+        ld(5, 8, 0),                 // 8: t0 = words
+        addi(6, 8, 8),               // 12: t1 = input
+        addi(10, 0, 0),              // 16: a0 = 0
+        beq(5, 0, 24),               // 20: -> 44
+        ld(7, 6, 0),                 // 24: loop
+        add(10, 10, 7),              // 28
+        addi(6, 6, 8),               // 32
+        addi(5, 5, -1),              // 36
+        bne(5, 0, -16),              // 40: -> 24
+        addi(7, 0, CRASH),           // 44
+        beq(10, 7, 32),              // 48: -> 80
+        ld(7, 9, COUNTER),           // 52
+        addi(7, 7, 1),               // 56
+        sd(7, 9, COUNTER),           // 60
+        slli(7, 7, 32),              // 64
+        add(10, 10, 7),              // 68
+        addi(17, 0, 93),             // 72: exit
+        ecall(),                     // 76
+        ebreak(),                    // 80: crash
+    ]
+}
+
+struct Guest {
+    env: ExecEnv<X86_64CodeGen>,
+    cpu: SpaceCpu,
+    space: GuestSpace,
+    snap: EmuSnapshot<RiscvCpu, MemSnapshot>,
+}
+
+impl Guest {
+    fn new(code_prot: i32, counter_base: u32) -> Self {
+        let ps = page_size();
+        let mut space = GuestSpace::new().unwrap();
+        let code: Vec<u8> = target(counter_base)
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        let rw = libc::PROT_READ | libc::PROT_WRITE;
+        space.mmap_fixed(CODE, ps, rw).unwrap();
+        unsafe { space.write_bytes(CODE, &code) };
+        space.mprotect(CODE, ps, code_prot).unwrap();
+        space.mmap_fixed(DATA, ps, rw).unwrap();
+
+        let mut cpu = SpaceCpu {
+            cpu: RiscvCpu::new(),
+        };
+        cpu.cpu.guest_base = space.guest_base() as u64;
+        cpu.cpu.pc = CODE;
+        // Unchained, every TB run passes the loop and is counted
+        // in the coverage.
+        let env = ExecEnv::new(X86_64CodeGen::new())
+            .with_chain_policy(ChainPolicy::Never);
+        let snap = env.snapshot(&cpu.cpu, &mut space).unwrap();
+        Self {
+            env,
+            cpu,
+            space,
+            snap,
+        }
+    }
+
+    /// Run `input` from the snapshot, then restore it.
+    fn run(
+        &mut self,
+        input: &[u64],
+    ) -> (ExitReason, u64, Vec<CoveredBlock>, RestoreStats) {
+        let mut bytes = (input.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(input.iter().flat_map(|w| w.to_le_bytes()));
+        unsafe { self.space.write_bytes(DATA, &bytes) };
+        self.env.per_cpu.coverage = Some(Coverage::new());
+        let r = unsafe { cpu_exec_loop(&mut self.env, &mut self.cpu) };
+        let a0 = self.cpu.cpu.gpr[10];
+        let cov = self.env.per_cpu.coverage.take().unwrap().blocks();
+        let stats = self
+            .env
+            .restore(&mut self.cpu.cpu, &mut self.space, &self.snap)
+            .unwrap();
+        (r, a0, cov, stats)
+    }
+}
+
+const EXIT: ExitReason = ExitReason::Exit(TbExit::Exception(EXCP_ECALL));
+
+#[test]
+fn test_restore_replays_identical_runs() {
+    let mut g = Guest::new(libc::PROT_READ | libc::PROT_EXEC, DATA as u32);
+    let inputs: [&[u64]; 3] = [&[1, 2, 3], &[40, 2], &[1000, 337]];
+    let first: Vec<_> = inputs.iter().map(|i| g.run(i)).collect();
+    // The counter is back at zero before every run.
+    assert_eq!(first[0].0, EXIT);
+    assert_eq!(first[0].1, 6 + (1 << 32));
+    assert_eq!(first[1].1, 42 + (1 << 32));
+    assert_eq!(first[2].0, ExitReason::Exit(TbExit::Exception(EXCP_EBREAK)));
+    assert_ne!(first[0].2, first[2].2, "the crash path covers other code");
+
+    let translated = g.env.shared.tb_store.len();
+    for _ in 0..3 {
+        for (input, want) in inputs.iter().zip(&first) {
+            let got = g.run(input);
+            assert_eq!(got.0, want.0);
+            assert_eq!(got.1, want.1);
+            assert_eq!(got.2, want.2);
+        }
+    }
+    // Only data pages were written: the TBs all survive.
+    assert_eq!(g.env.shared.tb_store.len(), translated);
+    assert_eq!(g.env.per_cpu.stats.tb_invalidated, 0);
+    assert_eq!(first[0].3.pages, 1);
+    assert_eq!(first[0].3.tb_invalidated, 0);
+    assert_eq!(g.cpu.cpu.pc, CODE);
+}
+
+/// TBs translated from a page written after the snapshot are
+/// dropped on restore; the rest of the cache is kept.
+#[test]
+fn test_restore_invalidates_tbs_of_written_pages() {
+    let rwx = libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC;
+    let mut g = Guest::new(rwx, CODE as u32);
+    let (r, a0, _, stats) = g.run(&[5]);
+    assert_eq!((r, a0), (EXIT, 5 + (1 << 32)));
+    // The data page and the code page with the counter.
+    assert_eq!(stats.pages, 2);
+    assert!(stats.tb_invalidated > 0);
+    let (r, a0, _, _) = g.run(&[5]);
+    assert_eq!((r, a0), (EXIT, 5 + (1 << 32)));
+    assert_eq!(
+        g.env.per_cpu.stats.tb_invalidated,
+        2 * stats.tb_invalidated as u64
+    );
+}
@@ -0,0 +1,124 @@
+//! Spin-loop detection and the exec loop's yield hook.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, cpu_exec_loop_mt, ExitReason};
+use tcg_exec::{ExecEnv, PerCpuState};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, beq, bne, ecall, ld, sd, TestCpu};
+
+/// Spin-marked TB runs allowed before the exec loop yields.
+const SPIN_K: u32 = 16;
+
+/// Iterations of vCPU1's work loop.
+const WORK: u64 = 1000;
+
+/// vCPU0 at 0 spins until the flag at `a0` is set; vCPU1 at
+/// `WORKER_PC` counts to `WORK`, then sets the flag.
+fn flag_program() -> Vec<u32> {
+    vec![
+        ld(5, 10, 0),  // 0: ld t0, 0(a0)
+        beq(5, 0, -4), // 4: beqz t0, 0
+        ecall(),       // 8
+        addi(5, 5, 1), // 12: addi t0, t0, 1
+        bne(5, 6, -4), // 16: bne t0, t1, 12
+        addi(7, 0, 1), // 20: li t2, 1
+        sd(7, 10, 0),  // 24: sd t2, 0(a0)
+        ecall(),       // 28
+    ]
+}
+
+const WORKER_PC: u64 = 12;
+
+fn vcpu(flag: &mut u64, pc: u64) -> TestCpu {
+    let mut t = TestCpu::new(&flag_program());
+    t.cpu.guest_base = flag as *mut u64 as u64;
+    t.cpu.pc = pc;
+    t.cpu.gpr[6] = WORK;
+    t
+}
+
+#[test]
+fn test_spin_yield_round_robin() {
+    let mut flag = 0u64;
+    let mut cpus = [vcpu(&mut flag, 0), vcpu(&mut flag, WORKER_PC)];
+    let env = ExecEnv::new(X86_64CodeGen::new()).with_spin_yield(SPIN_K);
+    let mut per_cpu = [PerCpuState::new(), PerCpuState::new()];
+
+    // Round-robin: run each vCPU until it yields or ecalls.
+    let mut done = [false; 2];
+    let mut turns = 0;
+    while done != [true; 2] {
+        for i in 0..2 {
+            if done[i] {
+                continue;
+            }
+            let r = unsafe {
+                cpu_exec_loop_mt(&env.shared, &mut per_cpu[i], &mut cpus[i])
+            };
+            match r {
+                ExitReason::Yield => {}
+                ExitReason::Exit(TbExit::Exception(EXCP_ECALL)) => {
+                    done[i] = true;
+                }
+                r => panic!("vCPU{i}: {r:?}"),
+            }
+        }
+        turns += 1;
+        assert!(turns < 10, "no progress after {turns} turns");
+    }
+
+    assert_eq!(flag, 1);
+    assert_eq!(cpus[0].cpu.gpr[5], 1);
+    assert_eq!(cpus[0].cpu.pc, 8);
+    assert_eq!(cpus[1].cpu.gpr[5], WORK);
+
+    // K runs before the yield, one more to see the flag, then
+    // the ecall.
+    let spins = (cpus[0].cpu.cycle - 1) / 2;
+    assert!(spins <= SPIN_K as u64 + 1, "{spins} spin iterations");
+    assert_eq!(per_cpu[0].stats.spin_yield, 1);
+    assert!(per_cpu[0].stats.chain_refused_spin > 0);
+    assert_eq!(per_cpu[1].stats.spin_yield, 0);
+}
+
+#[test]
+fn test_spin_loop_marking() {
+    let mut flag = 1u64;
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    for pc in [0, WORKER_PC] {
+        let mut t = vcpu(&mut flag, pc);
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    }
+    let store = &env.shared.tb_store;
+    let spin = |pc| store.get(store.lookup(pc, 0).unwrap()).spin_loop;
+    assert!(spin(0), "load-and-branch-back loop");
+    assert!(!spin(WORKER_PC), "computational loop has no load");
+    assert!(!spin(20), "straight-line code");
+}
+
+/// Loops that write memory or move the loaded address are
+/// making progress, not waiting.
+#[test]
+fn test_spin_loop_rejects_progress() {
+    let cases: [&[u32]; 3] = [
+        // Store in the loop body.
+        &[ld(5, 10, 0), sd(5, 10, 8), beq(5, 0, -8), ecall()],
+        // Address register advances.
+        &[ld(5, 10, 0), addi(10, 10, 8), beq(5, 0, -8), ecall()],
+        // Two different addresses.
+        &[ld(5, 10, 0), ld(7, 10, 8), beq(5, 7, -8), ecall()],
+    ];
+    for code in cases {
+        let mut data = [1u64, 0, 0, 0];
+        let mut t = TestCpu::new(code);
+        t.cpu.guest_base = data.as_mut_ptr() as u64;
+        let mut env = ExecEnv::new(X86_64CodeGen::new());
+        let r = unsafe { cpu_exec_loop(&mut env, &mut t) };
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+        let store = &env.shared.tb_store;
+        assert!(!store.get(store.lookup(0, 0).unwrap()).spin_loop);
+    }
+}
@@ -0,0 +1,230 @@
+//! Host stack checks (debug builds): recursion through helpers
+//! stops with `ExitReason::StackOverflow` before it runs off the
+//! host stack.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use tcg_backend::code_buffer::CodeBuffer;
+use tcg_backend::translate::translate;
+use tcg_backend::x86_64::Reg;
+use tcg_backend::{HostCodeGen, X86_64CodeGen};
+use tcg_core::context::Context;
+use tcg_core::helper::{self, BREADCRUMB_LEN};
+use tcg_core::tb::{DisasJumpType, TbExit, TranslationInfo};
+use tcg_core::{TempIdx, Type};
+use tcg_exec::exec_loop::{cpu_exec_loop, cpu_exec_loop_mt, ExitReason};
+use tcg_exec::stack_check::{StackOverflowReport, DEFAULT_RED_ZONE};
+use tcg_exec::{ExecEnv, GuestCpu, PerCpuState, SharedState};
+use tcg_frontend::riscv::cpu::{RiscvCpu, PC_OFFSET};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+/// Block whose helper re-enters the exec loop at this block.
+const REENTER_PC: u64 = 0x1000;
+/// Block whose helper recurses through `helper::guard`.
+const RECURSE_PC: u64 = 0x2000;
+
+thread_local! {
+    static SHARED: RefCell<Option<Arc<SharedState<X86_64CodeGen>>>> =
+        const { RefCell::new(None) };
+    /// Report of the innermost loop that stopped.
+    static INNER: RefCell<Option<StackOverflowReport>> =
+        const { RefCell::new(None) };
+}
+
+extern "C" fn helper_reenter(env: *mut RiscvCpu) -> u64 {
+    helper::guard(|| {
+        let shared = SHARED.with(|s| s.borrow().clone()).unwrap();
+        let mut per_cpu = PerCpuState::new();
+        let mut c = CallCpu::new(REENTER_PC);
+        let r = unsafe { cpu_exec_loop_mt(&shared, &mut per_cpu, &mut c) };
+        match r {
+            ExitReason::StackOverflow(report) => {
+                INNER.with(|i| *i.borrow_mut() = Some(report));
+            }
+            r => assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL))),
+        }
+        // Nesting depth below this level, plus one.
+        unsafe { (*env).gpr[2] = c.cpu.gpr[2] + 1 };
+        0
+    })
+}
+
+extern "C" fn helper_recurse(env: *mut RiscvCpu) -> u64 {
+    recurse(env, 1)
+}
+
+fn recurse(env: *mut RiscvCpu, depth: u64) -> u64 {
+    helper::guard(|| {
+        unsafe { (*env).gpr[1] = depth };
+        recurse(env, depth + 1)
+    })
+}
+
+/// One-instruction blocks built directly from IR: a helper
+/// call chosen by pc, then `pc += 4` and an ECALL exit.
+struct CallCpu {
+    cpu: RiscvCpu,
+    env: TempIdx,
+    pc: TempIdx,
+}
+
+impl CallCpu {
+    fn new(pc: u64) -> Self {
+        let mut cpu = RiscvCpu::new();
+        cpu.pc = pc;
+        Self {
+            cpu,
+            env: TempIdx(0),
+            pc: TempIdx(0),
+        }
+    }
+}
+
+impl GuestCpu for CallCpu {
+    fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    fn get_flags(&self) -> u32 {
+        0
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        _flags: u32,
+        _max_insns: u32,
+    ) -> TranslationInfo {
+        if ir.nb_globals() == 0 {
+            self.env = ir.new_fixed(Type::I64, ir.env_reg, "env");
+            self.pc = ir.new_global(Type::I64, self.env, PC_OFFSET, "pc");
+        }
+        ir.gen_insn_start(pc);
+        let dst = ir.new_temp(Type::I64);
+        let helper = match pc {
+            REENTER_PC => helper_reenter as *const () as u64,
+            _ => helper_recurse as *const () as u64,
+        };
+        ir.gen_call(dst, helper, 0, &[self.env]);
+        let next = ir.new_const(Type::I64, pc + 4);
+        ir.gen_mov(Type::I64, self.pc, next);
+        ir.gen_exit_tb(TbExit::Exception(EXCP_ECALL));
+        TranslationInfo {
+            guest_len_bytes: 4,
+            guest_insns: 1,
+            is_jmp: DisasJumpType::NoReturn,
+            first_pc: pc,
+            next_pc: pc + 4,
+            spin_loop: false,
+        }
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        &mut self.cpu as *mut RiscvCpu as *mut u8
+    }
+}
+
+#[test]
+fn test_reentrant_helper_stops_at_red_zone() {
+    let mut env =
+        ExecEnv::new(X86_64CodeGen::new()).with_stack_check(DEFAULT_RED_ZONE);
+    SHARED.with(|s| *s.borrow_mut() = Some(Arc::clone(&env.shared)));
+    let mut c = CallCpu::new(REENTER_PC);
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    SHARED.with(|s| s.borrow_mut().take());
+    // Every level unwound cleanly back to the outermost loop.
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(c.cpu.pc, REENTER_PC + 4);
+    let levels = c.cpu.gpr[2];
+    assert!(levels > 8, "only {levels} nested loops");
+    assert_eq!(helper::stack_limit(), 0, "limit not restored");
+
+    // The innermost loop tripped either entering the TB or in
+    // the guard of its helper call, depending on where the red
+    // zone starts.
+    let report = INNER.with(|i| i.borrow_mut().take()).unwrap();
+    assert_eq!(report.pc, REENTER_PC);
+    assert_ne!(report.limit, 0);
+    if let Some(b) = report.helper {
+        assert_eq!(b.helper.file(), file!());
+        assert!(b.sp < report.limit);
+    }
+    assert_eq!(report.breadcrumbs.len(), BREADCRUMB_LEN);
+    for b in &report.breadcrumbs {
+        assert_eq!(b.helper.file(), file!());
+    }
+    assert!(report.breadcrumbs.windows(2).all(|w| w[1].sp < w[0].sp));
+    assert!(report.to_string().contains("host stack overflow"));
+}
+
+#[test]
+fn test_recursive_helper_trips_in_guard() {
+    let mut env =
+        ExecEnv::new(X86_64CodeGen::new()).with_stack_check(DEFAULT_RED_ZONE);
+    let mut c = CallCpu::new(RECURSE_PC);
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    let ExitReason::StackOverflow(report) = r else {
+        panic!("unexpected exit {r:?}");
+    };
+    assert_eq!(report.pc, RECURSE_PC);
+    let b = report.helper.expect("tripped at TB entry");
+    assert_eq!(b.helper.file(), file!());
+    assert!(b.sp < report.limit);
+    assert_eq!(report.breadcrumbs.last(), Some(&b));
+    assert!(report.to_string().starts_with(&format!(
+        "host stack overflow in helper at {} (pc={RECURSE_PC:#x})",
+        b.helper
+    )));
+    // The TB stopped after the call; the recursion went deep.
+    assert_eq!(c.cpu.pc, RECURSE_PC);
+    assert!(c.cpu.gpr[1] > 100, "depth {}", c.cpu.gpr[1]);
+    assert_eq!(helper::take_panic(), None);
+    assert_eq!(helper::take_stack_breach(), None);
+    assert_eq!(helper::stack_limit(), 0);
+}
+
+/// IR for a block that only exits.
+fn exit_block(backend: &X86_64CodeGen) -> Context {
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let env = ctx.new_fixed(Type::I64, Reg::Rbp as u8, "env");
+    let x1 = ctx.new_global(Type::I64, env, 8, "x1");
+    let one = ctx.new_const(Type::I64, 1);
+    ctx.gen_insn_start(0x1000);
+    ctx.gen_add(Type::I64, x1, x1, one);
+    ctx.gen_exit_tb(TbExit::Exception(EXCP_ECALL));
+    ctx
+}
+
+/// A TB translated with the check is the check followed by
+/// exactly the code translated without it; without the check,
+/// as in release builds, nothing is added.
+#[test]
+fn test_stack_check_only_prepends_entry_check() {
+    let translate_at = |check: bool, pad: usize| {
+        let mut buf = CodeBuffer::new(4096).unwrap();
+        let mut backend = X86_64CodeGen::new();
+        backend.set_stack_check(check);
+        backend.emit_prologue(&mut buf);
+        backend.emit_epilogue(&mut buf);
+        buf.emit_bytes(&vec![0x90; pad]);
+        let mut ctx = exit_block(&backend);
+        let start = translate(&mut ctx, &backend, &mut buf).unwrap();
+        buf.as_slice()[start..buf.offset()].to_vec()
+    };
+    let checked = translate_at(true, 0);
+    let plain = translate_at(false, 0);
+    let check_len = checked.len() - plain.len();
+    assert_eq!(check_len, 28);
+    let shifted = translate_at(false, check_len);
+    assert_eq!(checked[check_len..], shifted[..]);
+
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    let backend = X86_64CodeGen::new();
+    backend.emit_stack_check(&mut buf);
+    assert_eq!(buf.offset(), 0);
+}
@@ -0,0 +1,96 @@
+//! Exec-loop phase timing.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::timing::PhaseTimes;
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, bne, ecall, TestCpu};
+
+/// Two counting loops separated by an ecall, whose handling
+/// sleeps like a slow system call.
+fn run_mixed(env: &mut ExecEnv<X86_64CodeGen>) -> Duration {
+    let mut t = TestCpu::new(&[
+        addi(1, 1, 1),
+        bne(1, 3, -4),
+        ecall(),
+        addi(2, 2, 1),
+        bne(2, 3, -4),
+        ecall(),
+    ]);
+    t.cpu.gpr[3] = 100_000;
+    let start = Instant::now();
+    for call in 0..2 {
+        if call > 0 {
+            thread::sleep(Duration::from_millis(2));
+        }
+        let r = unsafe { cpu_exec_loop(env, &mut t) };
+        assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+        t.cpu.pc += 4;
+    }
+    let wall = start.elapsed();
+    assert_eq!((t.cpu.gpr[1], t.cpu.gpr[2]), (100_000, 100_000));
+    wall
+}
+
+#[test]
+fn test_phase_times_cover_wall_time() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_timing();
+    let wall = run_mixed(&mut env);
+    let time = env.per_cpu.stats.time;
+
+    assert!(time.lookup > Duration::ZERO);
+    assert!(time.translate > Duration::ZERO);
+    assert!(time.execute > Duration::ZERO);
+    // The sleep falls between the two loop calls.
+    assert!(time.outside >= Duration::from_millis(2), "{time:?}");
+    // The phases tile the span from the first loop entry to
+    // the last exit, which the test's clock brackets.
+    let total = time.total();
+    assert!(total <= wall, "{total:?} > {wall:?}");
+    let slack = wall - total;
+    assert!(slack < Duration::from_millis(1) + wall / 20, "{time:?}");
+    assert!(time.reads > 0);
+    assert!(time.overhead() < total);
+}
+
+#[test]
+fn test_timing_disabled_reads_nothing() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    run_mixed(&mut env);
+    assert_eq!(env.per_cpu.stats.time, PhaseTimes::default());
+    assert!(!env.per_cpu.stats.to_string().contains("--- Time ---"));
+}
+
+#[test]
+fn test_timing_report_formats() {
+    let time = PhaseTimes {
+        lookup: Duration::from_millis(1),
+        translate: Duration::from_millis(2),
+        execute: Duration::from_millis(6),
+        outside: Duration::from_millis(1),
+        reads: 100,
+        read_cost: Duration::from_nanos(20),
+    };
+    assert_eq!(time.total(), Duration::from_millis(10));
+    assert_eq!(time.overhead(), Duration::from_micros(2));
+    assert_eq!(
+        time.to_json(),
+        "{\"lookup_ns\":1000000,\"lookup_pct\":10.00,\
+         \"translate_ns\":2000000,\"translate_pct\":20.00,\
+         \"execute_ns\":6000000,\"execute_pct\":60.00,\
+         \"outside_ns\":1000000,\"outside_pct\":10.00,\
+         \"total_ns\":10000000,\"reads\":100,\"overhead_ns\":2000}"
+    );
+    let text = time.to_string();
+    assert!(
+        text.contains("execute:          6.000 ms (60.0%)"),
+        "{text}"
+    );
+    assert!(text.contains("total:           10.000 ms"), "{text}");
+}
@@ -0,0 +1,125 @@
+//! Backend errors surface as `ExitReason::TranslateFailed`.
+
+use tcg_backend::{TranslateError, X86_64CodeGen};
+use tcg_core::context::Context;
+use tcg_core::tb::{DisasJumpType, TbExit, TranslationInfo};
+use tcg_core::{Cond, TempIdx, Type};
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::{ExecEnv, GuestCpu};
+use tcg_frontend::riscv::cpu::{RiscvCpu, PC_OFFSET};
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+/// Block branching to a label it never sets.
+const BAD_PC: u64 = 0x1000;
+/// Block with a forward branch over one op.
+const GOOD_PC: u64 = 0x2000;
+
+/// One-instruction blocks built directly from IR; each ends
+/// with `pc += 4` and an ECALL exit.
+struct LabelCpu {
+    cpu: RiscvCpu,
+    env: TempIdx,
+    pc: TempIdx,
+}
+
+impl LabelCpu {
+    fn new(pc: u64) -> Self {
+        let mut cpu = RiscvCpu::new();
+        cpu.pc = pc;
+        Self {
+            cpu,
+            env: TempIdx(0),
+            pc: TempIdx(0),
+        }
+    }
+}
+
+impl GuestCpu for LabelCpu {
+    fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    fn get_flags(&self) -> u32 {
+        0
+    }
+
+    fn gen_code(
+        &mut self,
+        ir: &mut Context,
+        pc: u64,
+        _flags: u32,
+        _max_insns: u32,
+    ) -> TranslationInfo {
+        if ir.nb_globals() == 0 {
+            self.env = ir.new_fixed(Type::I64, ir.env_reg, "env");
+            self.pc = ir.new_global(Type::I64, self.env, PC_OFFSET, "pc");
+        }
+        ir.gen_insn_start(pc);
+        let next = ir.new_const(Type::I64, pc + 4);
+        let skip = ir.new_label();
+        ir.gen_brcond(Type::I64, self.pc, next, Cond::Eq, skip);
+        ir.gen_mov(Type::I64, self.pc, next);
+        if pc != BAD_PC {
+            ir.gen_set_label(skip);
+        }
+        ir.gen_exit_tb(TbExit::Exception(EXCP_ECALL));
+        TranslationInfo {
+            guest_len_bytes: 4,
+            guest_insns: 1,
+            is_jmp: DisasJumpType::NoReturn,
+            first_pc: pc,
+            next_pc: pc + 4,
+            spin_loop: false,
+        }
+    }
+
+    fn env_ptr(&mut self) -> *mut u8 {
+        &mut self.cpu as *mut RiscvCpu as *mut u8
+    }
+}
+
+#[test]
+fn test_translate_error_exits_loop() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut c = LabelCpu::new(BAD_PC);
+    let before = env.shared.code_buf().offset();
+
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    let ExitReason::TranslateFailed { pc, error } = r else {
+        panic!("unexpected exit {r:?}");
+    };
+    assert_eq!(pc, BAD_PC);
+    assert!(matches!(error, TranslateError::UnboundLabel { .. }));
+    // Nothing of the failed TB is kept.
+    assert_eq!(env.shared.code_buf().offset(), before);
+    assert_eq!(env.shared.tb_store.lookup(BAD_PC, 0), None);
+    assert_eq!(c.cpu.pc, BAD_PC);
+}
+
+/// The next translation starts from a clean slate: no label
+/// or code of the failed attempt leaks into it, and the bad
+/// pc fails the same way again.
+#[test]
+fn test_translate_after_error() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut c = LabelCpu::new(BAD_PC);
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert!(matches!(r, ExitReason::TranslateFailed { .. }));
+
+    c.cpu.pc = GOOD_PC;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(c.cpu.pc, GOOD_PC + 4);
+    let good = env.shared.tb_store.lookup(GOOD_PC, 0).unwrap();
+    assert!(!env.shared.tb_store.get(good).is_invalid());
+
+    c.cpu.pc = BAD_PC;
+    let r = unsafe { cpu_exec_loop(&mut env, &mut c) };
+    assert!(matches!(
+        r,
+        ExitReason::TranslateFailed {
+            pc: BAD_PC,
+            error: TranslateError::UnboundLabel { .. }
+        }
+    ));
+}
@@ -0,0 +1,95 @@
+//! Code verifier (`verify-code` feature) tests.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::verify::crc32;
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{add, addi, bne, ecall, jal, TestCpu};
+
+/// sum(1..=10) in a loop, then call-style jump to the exit.
+fn loop_code() -> Vec<u32> {
+    vec![
+        addi(1, 1, 1),
+        add(2, 2, 1),
+        bne(1, 3, -8),
+        jal(0, 8),
+        ecall(),
+        ecall(),
+    ]
+}
+
+fn run(env: &mut ExecEnv<X86_64CodeGen>, t: &mut TestCpu) {
+    t.cpu.pc = 0;
+    t.cpu.gpr[1] = 0;
+    t.cpu.gpr[2] = 0;
+    t.cpu.gpr[3] = 10;
+    let r = unsafe { cpu_exec_loop(env, t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    assert_eq!(t.cpu.gpr[2], 55);
+}
+
+fn panic_message(e: Box<dyn std::any::Any + Send>) -> String {
+    match e.downcast::<String>() {
+        Ok(s) => *s,
+        Err(e) => e.downcast::<&str>().map(|s| s.to_string()).unwrap(),
+    }
+}
+
+#[test]
+fn test_crc32_known_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn test_verify_code_chaining_no_false_positive() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_verify_code(1);
+    let mut t = TestCpu::new(&loop_code());
+    run(&mut env, &mut t);
+    run(&mut env, &mut t);
+    assert!(env.per_cpu.stats.chain_patched > 0);
+    let patches = |env: &ExecEnv<X86_64CodeGen>| {
+        let v = env.shared.tb_store.verifier().unwrap();
+        v.recent_patches().len() as u64
+    };
+    assert_eq!(patches(&env), env.per_cpu.stats.chain_patched);
+
+    // Unchaining on invalidation is a legitimate patch too.
+    assert!(env.shared.tb_invalidate_range(8, 16) > 0);
+    run(&mut env, &mut t);
+    assert!(patches(&env) > env.per_cpu.stats.chain_patched);
+}
+
+#[test]
+fn test_verify_code_catches_corruption() {
+    let mut env = ExecEnv::new(X86_64CodeGen::new()).with_verify_code(1);
+    let mut t = TestCpu::new(&loop_code());
+    run(&mut env, &mut t);
+
+    let store = &env.shared.tb_store;
+    let victim = store.lookup(0, 0).expect("entry TB");
+    let tb = store.get(victim);
+    let (start, off) = (tb.host_offset, tb.host_offset + tb.host_size / 2);
+    let code = env.shared.code_buf();
+    let byte = code.as_slice()[off];
+    code.patch_u8(off, !byte);
+
+    let err = catch_unwind(AssertUnwindSafe(|| run(&mut env, &mut t)))
+        .expect_err("corruption not detected");
+    let msg = panic_message(err);
+    assert!(msg.contains(&format!("TB #{victim} (pc 0x0")), "{msg}");
+    let rel = off - start;
+    assert!(
+        msg.contains(&format!(
+            "+{rel:#x} ({off:#x}): expected {byte:02x}, found {:02x}",
+            !byte
+        )),
+        "{msg}"
+    );
+    assert!(msg.contains("recent patches:\n  TB #"), "{msg}");
+}
@@ -0,0 +1,103 @@
+//! Cross-run warmup: save hot TBs, pre-translate them next run.
+
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::TbExit;
+use tcg_exec::coverage::Coverage;
+use tcg_exec::exec_loop::{cpu_exec_loop, ExitReason};
+use tcg_exec::warmup::{pretranslate, WarmupHints};
+use tcg_exec::ExecEnv;
+use tcg_frontend::riscv::excp::EXCP_ECALL;
+
+use super::{addi, beq, bne, ecall, TestCpu};
+
+/// Counts x5 to 100 in a loop split over two TBs; four TBs run.
+fn workload() -> TestCpu {
+    TestCpu::new(&[
+        addi(5, 0, 0),   // 0
+        addi(6, 0, 100), // 4
+        addi(5, 5, 1),   // 8: loop
+        addi(7, 5, 0),   // 12
+        beq(7, 6, 16),   // 16: -> 32
+        addi(11, 11, 1), // 20
+        bne(5, 6, -16),  // 24: -> 8
+        ecall(),         // 28
+        ecall(),         // 32
+    ])
+}
+
+fn run(env: &mut ExecEnv<X86_64CodeGen>) -> TestCpu {
+    let mut t = workload();
+    let r = unsafe { cpu_exec_loop(env, &mut t) };
+    assert_eq!(r, ExitReason::Exit(TbExit::Exception(EXCP_ECALL)));
+    t
+}
+
+fn recorded_hints() -> (WarmupHints, u64, TestCpu) {
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    env.per_cpu.coverage = Some(Coverage::new());
+    let t = run(&mut env);
+    let cov = env.per_cpu.coverage.as_ref().unwrap();
+    let hints =
+        WarmupHints::from_coverage(cov, &env.shared.tb_store, 0xb1, 0xc2, 64);
+    (hints, env.per_cpu.stats.translate, t)
+}
+
+#[test]
+fn test_warmup_save_then_load() {
+    let (hints, translated, first) = recorded_hints();
+    assert_eq!(translated, 4);
+    assert_eq!(hints.tbs.len(), 4);
+
+    let mut text = Vec::new();
+    hints.write(&mut text).unwrap();
+    let loaded = WarmupHints::parse(std::str::from_utf8(&text).unwrap());
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded, hints);
+    assert!(loaded.matches(0xb1, 0xc2));
+
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut t = workload();
+    let n = unsafe {
+        pretranslate(
+            &env.shared,
+            &mut env.per_cpu,
+            &mut t,
+            &loaded.tbs,
+            usize::MAX,
+        )
+    };
+    assert_eq!(n as u64, translated);
+    let second = run(&mut env);
+
+    let stats = &env.per_cpu.stats;
+    assert_eq!(stats.translate, 0);
+    assert_eq!(stats.warmup_tbs, translated);
+    assert_eq!(stats.warmup_used, translated);
+    assert_eq!(second.cpu.gpr, first.cpu.gpr);
+    assert_eq!(second.cpu.pc, first.cpu.pc);
+    assert!(stats.to_string().contains("--- Warmup ---"));
+}
+
+#[test]
+fn test_warmup_budget_and_duplicates() {
+    let (hints, _, _) = recorded_hints();
+    let mut env = ExecEnv::new(X86_64CodeGen::new());
+    let mut t = workload();
+    let pre = |env: &mut ExecEnv<X86_64CodeGen>, t: &mut TestCpu, budget| unsafe {
+        pretranslate(&env.shared, &mut env.per_cpu, t, &hints.tbs, budget)
+    };
+    // A one-byte budget is spent by the first TB.
+    assert_eq!(pre(&mut env, &mut t, 1), 1);
+    // Already-cached entries are skipped, not retranslated.
+    assert_eq!(pre(&mut env, &mut t, usize::MAX), hints.tbs.len() - 1);
+    assert_eq!(pre(&mut env, &mut t, usize::MAX), 0);
+    assert_eq!(env.shared.tb_store.len(), hints.tbs.len());
+}
+
+#[test]
+fn test_warmup_parse_rejects_garbage() {
+    assert!(WarmupHints::parse("").is_err());
+    assert!(WarmupHints::parse("# tcg-rs warmup v1\nbinary 12\n").is_err());
+    let bad = "# tcg-rs warmup v1\nbinary 0x1\ncfg 0x2\n0x10 zz\n";
+    assert!(WarmupHints::parse(bad).unwrap_err().contains("line 4"));
+}
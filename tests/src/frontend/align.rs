@@ -0,0 +1,206 @@
+//! Alignment of guest accesses: plain accesses are free unless
+//! `strict_align`; atomics always trap when misaligned.  Jump
+//! targets must be 2-byte aligned, 4-byte without C.
+
+use tcg_core::tb::TbExit;
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{
+    EXCP_FETCH_MISALIGNED, EXCP_LOAD_MISALIGNED, EXCP_STORE_MISALIGNED,
+};
+use tcg_frontend::riscv::ext::{MisaExt, RiscvCfg};
+
+use super::{
+    addi, beq, bne, jal, jalr, lr_w, run_rv_insns, run_rv_insns_with_cfg, rv_i,
+    rv_r, OP_AMO,
+};
+
+fn lw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b010, rd, 0b0000011)
+}
+
+fn ld(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b011, rd, 0b0000011)
+}
+
+fn sw(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (0b010 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0b0100011
+}
+
+fn amoadd_d(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(0, rs2, rs1, 0b011, rd, OP_AMO)
+}
+
+fn strict() -> RiscvCfg {
+    RiscvCfg {
+        strict_align: true,
+        ..RiscvCfg::default()
+    }
+}
+
+fn cpu_with(mem: &mut [u8; 0x200]) -> RiscvCpu {
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    cpu
+}
+
+fn excp(n: u32) -> usize {
+    TbExit::Exception(n).code() as usize
+}
+
+/// x1 = `addr`, then `access`, then x3 = 1.
+fn program(access: u32, addr: i32) -> [u32; 3] {
+    [addi(1, 0, addr), access, addi(3, 0, 1)]
+}
+
+#[test]
+fn test_misaligned_load_lenient() {
+    let mut mem = [0u8; 0x200];
+    mem[0x101..0x105].copy_from_slice(&0x8765_4321u32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = program(lw(2, 1, 0), 0x101);
+    run_rv_insns_with_cfg(&mut cpu, &code, RiscvCfg::default());
+    assert_eq!(cpu.gpr[2], 0xffff_ffff_8765_4321);
+    assert_eq!(cpu.gpr[3], 1);
+}
+
+#[test]
+fn test_misaligned_load_strict() {
+    let mut mem = [0u8; 0x200];
+    mem[0x101..0x105].copy_from_slice(&0x8765_4321u32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = program(lw(2, 1, 0), 0x101);
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, strict());
+    assert_eq!(exit, excp(EXCP_LOAD_MISALIGNED));
+    assert_eq!(cpu.utval, 0x101);
+    // At the load, which did not happen.
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(cpu.gpr[2], 0);
+    assert_eq!(cpu.gpr[3], 0);
+    // Only the addi retired.
+    assert_eq!(cpu.cycle, 1);
+}
+
+#[test]
+fn test_misaligned_store_strict() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    cpu.gpr[2] = 0x41;
+    let code = program(sw(2, 1, 2), 0x100);
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, strict());
+    assert_eq!(exit, excp(EXCP_STORE_MISALIGNED));
+    assert_eq!(cpu.utval, 0x102);
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(mem[0x102], 0);
+}
+
+#[test]
+fn test_aligned_access_strict() {
+    let mut mem = [0u8; 0x200];
+    mem[0x108..0x110].copy_from_slice(&0x1122_3344_5566_7788u64.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    run_rv_insns_with_cfg(&mut cpu, &program(ld(2, 1, 8), 0x100), strict());
+    assert_eq!(cpu.gpr[2], 0x1122_3344_5566_7788);
+    assert_eq!(cpu.gpr[3], 1);
+}
+
+#[test]
+fn test_amo_d_aligned() {
+    let mut mem = [0u8; 0x200];
+    mem[0x108..0x110].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    cpu.gpr[2] = 3;
+    run_rv_insns_with_cfg(
+        &mut cpu,
+        &program(amoadd_d(4, 1, 2), 0x108),
+        RiscvCfg::default(),
+    );
+    assert_eq!(cpu.gpr[4], u64::MAX - 1);
+    assert_eq!(u64::from_le_bytes(mem[0x108..0x110].try_into().unwrap()), 1);
+    assert_eq!(cpu.gpr[3], 1);
+}
+
+/// Atomics trap when misaligned even without `strict_align`.
+#[test]
+fn test_misaligned_atomics_trap() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    cpu.gpr[2] = 3;
+    let code = program(amoadd_d(4, 1, 2), 0x104);
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, RiscvCfg::default());
+    assert_eq!(exit, excp(EXCP_STORE_MISALIGNED));
+    assert_eq!(cpu.utval, 0x104);
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(mem[0x104], 0);
+
+    let mut cpu = cpu_with(&mut mem);
+    let code = program(lr_w(4, 1), 0x102);
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, RiscvCfg::default());
+    assert_eq!(exit, excp(EXCP_LOAD_MISALIGNED));
+    assert_eq!(cpu.utval, 0x102);
+    assert_eq!(cpu.load_res, u64::MAX);
+}
+
+fn no_c() -> RiscvCfg {
+    let mut cfg = RiscvCfg::default();
+    cfg.misa = cfg.misa.difference(MisaExt::C);
+    cfg
+}
+
+/// Without C, a jalr target with bit 1 set traps at the jalr
+/// and leaves rd unwritten; bit 0 is cleared as usual.
+#[test]
+fn test_jalr_misaligned_target_without_c() {
+    let mut cpu = RiscvCpu::new();
+    let code = [addi(1, 0, 0x103), jalr(5, 1, 0), addi(3, 0, 1)];
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, no_c());
+    assert_eq!(exit, excp(EXCP_FETCH_MISALIGNED));
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(cpu.utval, 0x102);
+    assert_eq!((cpu.gpr[5], cpu.gpr[3]), (0, 0));
+
+    let mut cpu = RiscvCpu::new();
+    let code = [addi(1, 0, 0x101), jalr(5, 1, 0)];
+    run_rv_insns_with_cfg(&mut cpu, &code, no_c());
+    assert_eq!((cpu.pc, cpu.gpr[5]), (0x100, 8));
+}
+
+/// With C any jalr target works once bit 0 is cleared.
+#[test]
+fn test_jalr_odd_target_with_c() {
+    let mut cpu = RiscvCpu::new();
+    run_rv_insns(&mut cpu, &[addi(1, 0, 0x103), jalr(5, 1, 0)]);
+    assert_eq!((cpu.pc, cpu.gpr[5]), (0x102, 8));
+}
+
+#[test]
+fn test_jal_to_halfword_target() {
+    let mut cpu = RiscvCpu::new();
+    run_rv_insns(&mut cpu, &[jal(1, 6)]);
+    assert_eq!((cpu.pc, cpu.gpr[1]), (6, 4));
+
+    let mut cpu = RiscvCpu::new();
+    let exit = run_rv_insns_with_cfg(&mut cpu, &[jal(1, 6)], no_c());
+    assert_eq!(exit, excp(EXCP_FETCH_MISALIGNED));
+    assert_eq!((cpu.pc, cpu.utval, cpu.gpr[1]), (0, 6, 0));
+}
+
+/// A branch to a misaligned target traps only when taken.
+#[test]
+fn test_branch_misaligned_target_without_c() {
+    let mut cpu = RiscvCpu::new();
+    let code = [addi(1, 0, 1), beq(1, 0, 6)];
+    run_rv_insns_with_cfg(&mut cpu, &code, no_c());
+    assert_eq!(cpu.pc, 8);
+
+    let mut cpu = RiscvCpu::new();
+    let code = [addi(1, 0, 1), bne(1, 0, 6)];
+    let exit = run_rv_insns_with_cfg(&mut cpu, &code, no_c());
+    assert_eq!(exit, excp(EXCP_FETCH_MISALIGNED));
+    assert_eq!((cpu.pc, cpu.utval, cpu.gpr[1]), (4, 10, 1));
+}
@@ -0,0 +1,291 @@
+//! A extension: LR/SC reservations and AMO read-modify-write
+//! through guest memory.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+
+use super::{addi, lr_w, run_rv_insns, rv_i, rv_r, OP_AMO};
+
+const LR: u32 = 0b00010;
+const SC: u32 = 0b00011;
+const AMOSWAP: u32 = 0b00001;
+const AMOADD: u32 = 0b00000;
+const AMOXOR: u32 = 0b00100;
+const AMOAND: u32 = 0b01100;
+const AMOOR: u32 = 0b01000;
+const AMOMIN: u32 = 0b10000;
+const AMOMAX: u32 = 0b10100;
+const AMOMINU: u32 = 0b11000;
+const AMOMAXU: u32 = 0b11100;
+
+const W: u32 = 0b010;
+const D: u32 = 0b011;
+
+fn amo(
+    funct5: u32,
+    aq: u32,
+    rl: u32,
+    w: u32,
+    rd: u32,
+    rs1: u32,
+    rs2: u32,
+) -> u32 {
+    rv_r(funct5 << 2 | aq << 1 | rl, rs2, rs1, w, rd, OP_AMO)
+}
+
+fn sc_w(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    amo(SC, 0, 0, W, rd, rs1, rs2)
+}
+
+fn sw(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (0b010 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0b0100011
+}
+
+fn lw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b010, rd, 0b0000011)
+}
+
+fn cpu_with(mem: &mut [u8; 0x200]) -> RiscvCpu {
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    cpu
+}
+
+fn word(mem: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(mem[at..at + 4].try_into().unwrap())
+}
+
+#[test]
+fn test_lr_modify_sc_succeeds() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x104].copy_from_slice(&41u32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = [addi(1, 0, 0x100), lr_w(2, 1), addi(2, 2, 1), sc_w(3, 1, 2)];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 0, "sc should succeed");
+    assert_eq!(cpu.load_res, u64::MAX, "sc drops the reservation");
+    assert_eq!(word(&mem, 0x100), 42);
+}
+
+#[test]
+fn test_sc_fails_after_reservation_clobbered() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x104].copy_from_slice(&7u32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    // The first sc consumes the reservation; the second fails.
+    let code = [
+        addi(1, 0, 0x100),
+        lr_w(2, 1),
+        addi(2, 0, 8),
+        sc_w(3, 1, 2),
+        addi(2, 0, 9),
+        sc_w(4, 1, 2),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 0);
+    assert_eq!(cpu.gpr[4], 1, "sc without a reservation must fail");
+    assert_eq!(word(&mem, 0x100), 8);
+}
+
+#[test]
+fn test_sc_fails_on_other_address() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    let code = [
+        addi(1, 0, 0x100),
+        lr_w(2, 1),
+        addi(4, 1, 4),
+        addi(2, 0, 5),
+        sc_w(3, 4, 2),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 1);
+    assert_eq!(word(&mem, 0x104), 0, "failed sc must not store");
+}
+
+#[test]
+fn test_sc_fails_when_value_changed() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    let code = [
+        addi(1, 0, 0x100),
+        lr_w(2, 1),
+        addi(5, 0, 3),
+        sw(5, 1, 0),
+        addi(2, 0, 5),
+        sc_w(3, 1, 2),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 1);
+    assert_eq!(word(&mem, 0x100), 3);
+}
+
+#[test]
+fn test_sc_w_matches_negative_word() {
+    // lr.w sign-extends; sc.w must compare the same way.
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x104].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = [addi(1, 0, 0x100), lr_w(2, 1), sc_w(3, 1, 0)];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[2], 0xffff_ffff_8000_0000);
+    assert_eq!(cpu.gpr[3], 0);
+    assert_eq!(word(&mem, 0x100), 0);
+}
+
+#[test]
+fn test_amoadd_w_sign_extends_old_value() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x104].copy_from_slice(&0xffff_fffeu32.to_le_bytes());
+    mem[0x104..0x108].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = [
+        addi(1, 0, 0x100),
+        addi(2, 0, 1),
+        amo(AMOADD, 0, 0, W, 3, 1, 2),
+        addi(1, 1, 4),
+        amo(AMOADD, 1, 1, W, 4, 1, 2),
+        lw(5, 1, 0),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_ffff_fffe);
+    assert_eq!(word(&mem, 0x100), 0xffff_ffff);
+    assert_eq!(cpu.gpr[4], 0x7fff_ffff);
+    assert_eq!(cpu.gpr[5], 0xffff_ffff_8000_0000);
+}
+
+#[test]
+fn test_x0_destination_still_accesses_memory() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x108].copy_from_slice(&10u64.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    // amoadd.d x0 writes memory; lr.d x0 still reserves.
+    let code = [
+        addi(1, 0, 0x100),
+        addi(2, 0, 5),
+        amo(AMOADD, 0, 0, D, 0, 1, 2),
+        amo(LR, 1, 1, D, 0, 1, 0),
+        amo(SC, 0, 1, D, 3, 1, 2),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[0], 0);
+    assert_eq!(cpu.gpr[3], 0);
+    assert_eq!(&mem[0x100..0x108], &5u64.to_le_bytes());
+}
+
+#[test]
+fn test_amo_min_maxu_d() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x108].copy_from_slice(&(-3i64).to_le_bytes());
+    mem[0x108..0x110].copy_from_slice(&3u64.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    let code = [
+        addi(1, 0, 0x100),
+        addi(2, 0, -1),
+        amo(AMOMIN, 0, 0, D, 3, 1, 2),
+        addi(1, 1, 8),
+        amo(AMOMAXU, 0, 0, D, 4, 1, 2),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], -3i64 as u64);
+    assert_eq!(&mem[0x100..0x108], &(-3i64).to_le_bytes());
+    assert_eq!(cpu.gpr[4], 3);
+    assert_eq!(&mem[0x108..0x110], &u64::MAX.to_le_bytes());
+}
+
+#[test]
+fn test_amoswap_d() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x108].copy_from_slice(&0x1122_3344_5566_7788u64.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    cpu.gpr[2] = 0x8000_0000_0000_0001;
+    let code = [addi(1, 0, 0x100), amo(AMOSWAP, 1, 1, D, 3, 1, 2)];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[3], 0x1122_3344_5566_7788);
+    assert_eq!(&mem[0x100..0x108], &0x8000_0000_0000_0001u64.to_le_bytes());
+}
+
+/// Memory result of AMO `funct5` on old value `a` and rs2 `b`,
+/// as 64-bit operands.
+fn amo_ref(funct5: u32, a: i64, b: i64) -> i64 {
+    match funct5 {
+        AMOSWAP => b,
+        AMOADD => a.wrapping_add(b),
+        AMOXOR => a ^ b,
+        AMOAND => a & b,
+        AMOOR => a | b,
+        AMOMIN => a.min(b),
+        AMOMAX => a.max(b),
+        AMOMINU => (a as u64).min(b as u64) as i64,
+        AMOMAXU => (a as u64).max(b as u64) as i64,
+        _ => unreachable!(),
+    }
+}
+
+/// Every AMO at both widths: rd gets the old value (sign-
+/// extended for .w), memory the result, neighbours untouched.
+#[test]
+fn test_amo_ops_against_reference() {
+    const OPS: [u32; 9] = [
+        AMOSWAP, AMOADD, AMOXOR, AMOAND, AMOOR, AMOMIN, AMOMAX, AMOMINU,
+        AMOMAXU,
+    ];
+    const VALUES: [i64; 4] = [-5, 3, i64::MIN + 7, 0x7fff_ffff];
+    for op in OPS {
+        for a in VALUES {
+            for b in VALUES {
+                for (w, bytes) in [(W, 4), (D, 8)] {
+                    let mut mem = [0xa5u8; 0x200];
+                    mem[0x100..0x108].copy_from_slice(&a.to_le_bytes());
+                    let mut cpu = cpu_with(&mut mem);
+                    cpu.gpr[2] = b as u64;
+                    let code = [addi(1, 0, 0x100), amo(op, 0, 0, w, 3, 1, 2)];
+                    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+
+                    let (old, want) = if bytes == 4 {
+                        let (a, b) = (a as i32 as i64, b as i32 as i64);
+                        (a, amo_ref(op, a, b) as u32 as u64)
+                    } else {
+                        (a, amo_ref(op, a, b) as u64)
+                    };
+                    let at = &mem[0x100..0x100 + bytes];
+                    let mut got = [0u8; 8];
+                    got[..bytes].copy_from_slice(at);
+                    let ctx = format!("op {op:#07b} w {w} a {a:#x} b {b:#x}");
+                    assert_eq!(cpu.gpr[3], old as u64, "{ctx}");
+                    assert_eq!(u64::from_le_bytes(got), want, "{ctx}");
+                    if bytes == 4 {
+                        assert_eq!(&mem[0x104..0x108], &a.to_le_bytes()[4..]);
+                    }
+                    assert_eq!(mem[0x108], 0xa5, "{ctx}");
+                }
+            }
+        }
+    }
+}
+
+/// amomin.w/amomaxu.w compare rs2's low word, not all of rs2.
+#[test]
+fn test_amo_minmax_w_ignore_upper_rs2() {
+    let mut mem = [0u8; 0x200];
+    mem[0x100..0x104].copy_from_slice(&(-5i32).to_le_bytes());
+    mem[0x104..0x108].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    let mut cpu = cpu_with(&mut mem);
+    // Negative as a doubleword, 7 as a word; huge unsigned, 1.
+    cpu.gpr[2] = 0x8000_0000_0000_0007;
+    cpu.gpr[4] = 0xffff_ffff_0000_0001;
+    let code = [
+        addi(1, 0, 0x100),
+        amo(AMOMIN, 0, 0, W, 3, 1, 2),
+        addi(1, 1, 4),
+        amo(AMOMAXU, 0, 0, W, 5, 1, 4),
+    ];
+    assert_eq!(run_rv_insns(&mut cpu, &code), 0);
+    assert_eq!(word(&mem, 0x100), -5i32 as u32);
+    assert_eq!(word(&mem, 0x104), 0x7fff_ffff);
+}
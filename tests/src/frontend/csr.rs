@@ -0,0 +1,149 @@
+//! Zicsr: register and immediate forms, the rules for which
+//! operands suppress the write, and CSRs the frontend lacks.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_UNDEF;
+
+use super::{csrrc, csrrs, csrrw, run_rv, run_rv_insns, OP_SYSTEM};
+
+const CSR_FFLAGS: u32 = 0x001;
+const CSR_FRM: u32 = 0x002;
+const CSR_FCSR: u32 = 0x003;
+const CSR_USCRATCH: u32 = 0x040;
+const CSR_CYCLE: u32 = 0xc00;
+const CSR_INSTRET: u32 = 0xc02;
+
+fn csr_imm(f3: u32, rd: u32, uimm: u32, csr: u32) -> u32 {
+    (csr << 20) | (uimm << 15) | (f3 << 12) | (rd << 7) | OP_SYSTEM
+}
+
+fn csrrwi(rd: u32, uimm: u32, csr: u32) -> u32 {
+    csr_imm(0b101, rd, uimm, csr)
+}
+
+fn csrrsi(rd: u32, uimm: u32, csr: u32) -> u32 {
+    csr_imm(0b110, rd, uimm, csr)
+}
+
+fn csrrci(rd: u32, uimm: u32, csr: u32) -> u32 {
+    csr_imm(0b111, rd, uimm, csr)
+}
+
+#[test]
+fn test_csrrwi_fcsr() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fflags = 0x3;
+    cpu.frm = 0x4;
+    run_rv_insns(
+        &mut cpu,
+        &[csrrwi(1, 0x15, CSR_FCSR), csrrs(2, 0, CSR_FCSR)],
+    );
+    assert_eq!(cpu.gpr[1], 0x4 << 5 | 0x3);
+    // A 5-bit immediate reaches fflags only; frm is cleared.
+    assert_eq!(cpu.gpr[2], 0x15);
+    assert_eq!((cpu.fflags, cpu.frm), (0x15, 0));
+}
+
+#[test]
+fn test_csrrs_reads_back_and_sets() {
+    let mut cpu = RiscvCpu::new();
+    cpu.uscratch = 0x1234_0000_0000_0001;
+    cpu.gpr[3] = 0xf0;
+    run_rv_insns(
+        &mut cpu,
+        &[
+            csrrs(1, 0, CSR_USCRATCH),
+            csrrs(2, 3, CSR_USCRATCH),
+            csrrsi(4, 0x6, CSR_USCRATCH),
+        ],
+    );
+    assert_eq!(cpu.gpr[1], 0x1234_0000_0000_0001);
+    assert_eq!(cpu.gpr[2], 0x1234_0000_0000_0001);
+    assert_eq!(cpu.gpr[4], 0x1234_0000_0000_00f1);
+    assert_eq!(cpu.uscratch, 0x1234_0000_0000_00f7);
+}
+
+#[test]
+fn test_csrrc_and_csrrci_clear() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fflags = 0x1f;
+    cpu.gpr[2] = 0x3;
+    run_rv_insns(
+        &mut cpu,
+        &[csrrc(1, 2, CSR_FFLAGS), csrrci(3, 0x10, CSR_FFLAGS)],
+    );
+    assert_eq!(cpu.gpr[1], 0x1f);
+    assert_eq!(cpu.gpr[3], 0x1c);
+    assert_eq!(cpu.fflags, 0x0c);
+}
+
+/// With rs1 = x0 (or a zero immediate) csrrs/csrrc do not
+/// write, so they may read a read-only counter.
+#[test]
+fn test_rs1_x0_does_not_write() {
+    for insn in [
+        csrrs(1, 0, CSR_CYCLE),
+        csrrc(1, 0, CSR_INSTRET),
+        csrrsi(1, 0, CSR_CYCLE),
+        csrrci(1, 0, CSR_INSTRET),
+    ] {
+        let mut cpu = RiscvCpu::new();
+        cpu.cycle = 5;
+        assert_eq!(run_rv(&mut cpu, insn), 0, "{insn:#010x}");
+        assert_eq!(cpu.gpr[1], 5, "{insn:#010x}");
+    }
+
+    let mut cpu = RiscvCpu::new();
+    cpu.frm = 0x2;
+    run_rv_insns(&mut cpu, &[csrrs(0, 0, CSR_FRM), csrrc(1, 0, CSR_FRM)]);
+    assert_eq!((cpu.gpr[1], cpu.frm), (0x2, 0x2));
+}
+
+/// csrrw always writes, even from x0, so it faults on a
+/// read-only counter.
+#[test]
+fn test_write_to_read_only_csr_is_undef() {
+    for insn in [
+        csrrw(0, 0, CSR_CYCLE),
+        csrrwi(1, 0, CSR_INSTRET),
+        csrrs(1, 2, CSR_CYCLE),
+    ] {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = 77;
+        cpu.gpr[2] = 1;
+        assert_eq!(run_rv(&mut cpu, insn), EXCP_UNDEF as usize);
+        assert_eq!(cpu.gpr[1], 77, "{insn:#010x} wrote rd");
+    }
+}
+
+#[test]
+fn test_unknown_csr_is_undef() {
+    for csr in [0x7c0, 0x300, 0xfff] {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = 77;
+        let exit = run_rv(&mut cpu, csrrs(1, 0, csr));
+        assert_eq!(exit, EXCP_UNDEF as usize, "csr {csr:#x}");
+        assert_eq!(cpu.gpr[1], 77);
+        assert_eq!(cpu.pc, 0);
+    }
+}
+
+#[test]
+fn test_frm_reads_back_after_write() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fflags = 0x5;
+    run_rv_insns(&mut cpu, &[csrrwi(0, 0x3, CSR_FRM), csrrs(1, 0, CSR_FRM)]);
+    assert_eq!(cpu.gpr[1], 0x3);
+    assert_eq!((cpu.frm, cpu.fflags), (0x3, 0x5));
+}
+
+/// Each counter read retires an instruction, so a second read
+/// sees a larger value.
+#[test]
+fn test_cycle_advances_between_reads() {
+    let mut cpu = RiscvCpu::new();
+    assert_eq!(run_rv(&mut cpu, csrrs(1, 0, CSR_CYCLE)), 0);
+    let first = cpu.gpr[1];
+    assert_eq!(run_rv(&mut cpu, csrrs(1, 0, CSR_CYCLE)), 0);
+    assert!(cpu.gpr[1] > first, "{} -> {}", first, cpu.gpr[1]);
+}
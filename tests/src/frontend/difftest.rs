@@ -212,8 +212,8 @@ fn gen_alu_asm(test: &AluTest) -> String {
     // Test instruction
     asm.push_str(&format!("    {}\n", test.asm));
     // Save all registers
-    for i in 0..32 {
-        asm.push_str(&format!("    sd {}, {}(gp)\n", REG_NAME[i], i * 8));
+    for (i, name) in REG_NAME.iter().enumerate() {
+        asm.push_str(&format!("    sd {}, {}(gp)\n", name, i * 8));
     }
     // write(1, save_area, 256)
     asm.push_str(
@@ -250,8 +250,8 @@ fn gen_branch_asm(test: &BranchTest) -> String {
          2:\n",
         test.mnemonic
     ));
-    for i in 0..32 {
-        asm.push_str(&format!("    sd {}, {}(gp)\n", REG_NAME[i], i * 8));
+    for (i, name) in REG_NAME.iter().enumerate() {
+        asm.push_str(&format!("    sd {}, {}(gp)\n", name, i * 8));
     }
     asm.push_str(
         "    li a7, 64\n\
@@ -321,9 +321,9 @@ fn run_qemu(asm_src: &str) -> [u64; 32] {
 
     // Parse register dump
     let mut regs = [0u64; 32];
-    for i in 0..32 {
+    for (i, reg) in regs.iter_mut().enumerate() {
         let off = i * 8;
-        regs[i] =
+        *reg =
             u64::from_le_bytes(qemu.stdout[off..off + 8].try_into().unwrap());
     }
 
@@ -445,7 +445,7 @@ fn itype_test(
 ) -> AluTest {
     AluTest {
         name,
-        asm: format!("{}", mnemonic),
+        asm: mnemonic.to_string(),
         insn,
         init: vec![(5, v1)],
         check_reg: 7,
@@ -0,0 +1,163 @@
+//! Division and remainder against reference math, including the
+//! cases RISC-V defines instead of trapping: divide by zero and
+//! signed overflow.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+
+use super::{addi, run_rv, run_rv_insns, rv_r, OP_M_FUNCT7, OP_REG, OP_REG32};
+
+const VALUES: [u64; 9] = [
+    0,
+    1,
+    7,
+    u64::MAX,     // -1
+    u64::MAX - 6, // -7
+    i64::MIN as u64,
+    i64::MAX as u64,
+    0x8000_0000,           // i32::MIN, zero-extended
+    0xffff_ffff_8000_0000, // i32::MIN, sign-extended
+];
+
+fn m(f3: u32, op: u32) -> impl Fn(u32, u32, u32) -> u32 {
+    move |rd, rs1, rs2| rv_r(OP_M_FUNCT7, rs2, rs1, f3, rd, op)
+}
+
+/// Run `insn(3, 1, 2)` with x1 = `a`, x2 = `b`; returns x3.
+fn run(insn: &impl Fn(u32, u32, u32) -> u32, a: u64, b: u64) -> u64 {
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = a;
+    cpu.gpr[2] = b;
+    run_rv(&mut cpu, insn(3, 1, 2));
+    cpu.gpr[3]
+}
+
+fn div_ref(a: u64, b: u64) -> u64 {
+    let (a, b) = (a as i64, b as i64);
+    match b {
+        0 => u64::MAX,
+        _ => a.wrapping_div(b) as u64,
+    }
+}
+
+fn rem_ref(a: u64, b: u64) -> u64 {
+    let (a, b) = (a as i64, b as i64);
+    match b {
+        0 => a as u64,
+        _ => a.wrapping_rem(b) as u64,
+    }
+}
+
+fn divu_ref(a: u64, b: u64) -> u64 {
+    a.checked_div(b).unwrap_or(u64::MAX)
+}
+
+fn remu_ref(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        a % b
+    }
+}
+
+fn divw_ref(a: u64, b: u64) -> u64 {
+    let (a, b) = (a as i32, b as i32);
+    let q = if b == 0 { -1 } else { a.wrapping_div(b) };
+    q as i64 as u64
+}
+
+fn remw_ref(a: u64, b: u64) -> u64 {
+    let (a, b) = (a as i32, b as i32);
+    let r = if b == 0 { a } else { a.wrapping_rem(b) };
+    r as i64 as u64
+}
+
+fn divuw_ref(a: u64, b: u64) -> u64 {
+    let q = (a as u32).checked_div(b as u32).unwrap_or(u32::MAX);
+    q as i32 as i64 as u64
+}
+
+fn remuw_ref(a: u64, b: u64) -> u64 {
+    let (a, b) = (a as u32, b as u32);
+    let r = if b == 0 { a } else { a % b };
+    r as i32 as i64 as u64
+}
+
+fn check(
+    name: &str,
+    insn: impl Fn(u32, u32, u32) -> u32,
+    want: impl Fn(u64, u64) -> u64,
+) {
+    for a in VALUES {
+        for b in VALUES {
+            assert_eq!(run(&insn, a, b), want(a, b), "{name} {a:#x}, {b:#x}");
+        }
+    }
+}
+
+#[test]
+fn test_div_rem() {
+    check("div", m(0b100, OP_REG), div_ref);
+    check("divu", m(0b101, OP_REG), divu_ref);
+    check("rem", m(0b110, OP_REG), rem_ref);
+    check("remu", m(0b111, OP_REG), remu_ref);
+}
+
+#[test]
+fn test_div_rem_w() {
+    check("divw", m(0b100, OP_REG32), divw_ref);
+    check("divuw", m(0b101, OP_REG32), divuw_ref);
+    check("remw", m(0b110, OP_REG32), remw_ref);
+    check("remuw", m(0b111, OP_REG32), remuw_ref);
+}
+
+#[test]
+fn test_div_by_zero() {
+    let div = m(0b100, OP_REG);
+    let rem = m(0b110, OP_REG);
+    assert_eq!(run(&div, 42, 0), u64::MAX);
+    assert_eq!(run(&m(0b101, OP_REG), 42, 0), u64::MAX);
+    assert_eq!(run(&rem, -42i64 as u64, 0), -42i64 as u64);
+    assert_eq!(run(&m(0b111, OP_REG), 42, 0), 42);
+    // divuw by zero is all ones in 32 bits, sign-extended.
+    assert_eq!(run(&m(0b101, OP_REG32), 42, 0), u64::MAX);
+}
+
+#[test]
+fn test_signed_overflow() {
+    let min = i64::MIN as u64;
+    assert_eq!(run(&m(0b100, OP_REG), min, u64::MAX), min);
+    assert_eq!(run(&m(0b110, OP_REG), min, u64::MAX), 0);
+    let min32 = 0x8000_0000;
+    let divw = run(&m(0b100, OP_REG32), min32, u64::MAX);
+    assert_eq!(divw, 0xffff_ffff_8000_0000);
+    assert_eq!(run(&m(0b110, OP_REG32), min32, u64::MAX), 0);
+}
+
+#[test]
+fn test_remw_sign_extends() {
+    // 0xffff_fff9 % 0x10 in 32 bits: -7 % 16 = -7.
+    let r = run(&m(0b110, OP_REG32), 0x1_ffff_fff9, 0x10);
+    assert_eq!(r, -7i64 as u64);
+    // remuw: 0x8000_0005 % 0x10 = 5; 0x8000_0000 % 3 = 2.
+    assert_eq!(run(&m(0b111, OP_REG32), 0x8000_0005, 0x10), 5);
+    let r = run(&m(0b111, OP_REG32), 0xffff_ffff, 0x1_0000_0000);
+    assert_eq!(r, u64::MAX, "divisor's low word is 0: rem = dividend");
+}
+
+#[test]
+fn test_div_rem_sequence() {
+    // x1 = -100, x2 = 7: q = -14, r = -2; x5 = q * 7 + r.
+    let mut cpu = RiscvCpu::new();
+    let code = [
+        addi(1, 0, -100),
+        addi(2, 0, 7),
+        m(0b100, OP_REG)(3, 1, 2),
+        m(0b110, OP_REG)(4, 1, 2),
+        rv_r(OP_M_FUNCT7, 2, 3, 0b000, 5, OP_REG),
+        rv_r(0, 4, 5, 0b000, 5, OP_REG),
+    ];
+    run_rv_insns(&mut cpu, &code);
+    assert_eq!(cpu.gpr[3], -14i64 as u64);
+    assert_eq!(cpu.gpr[4], -2i64 as u64);
+    assert_eq!(cpu.gpr[5], -100i64 as u64);
+}
@@ -0,0 +1,332 @@
+//! RV64D: double-precision arithmetic, conversions, moves,
+//! compares and sign injection, and how D values interact with
+//! NaN-boxed single-precision ones.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+
+use super::{
+    fadd_d, fadd_s, fcvt_d_s, fdiv_d, fmul_d, fsqrt_d, nanbox, run_rv,
+    run_rv_insns, rv_i, rv_r, rv_r4, DZ, NV, NX, OP_FMADD, OP_FMSUB, OP_FNMADD,
+    OP_FNMSUB, OP_FP,
+};
+
+const FMT_D: u32 = 0b01;
+
+fn fld(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b011, rd, 0b0000111)
+}
+fn fsd(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (0b011 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0b0100111
+}
+fn fsub_d(rd: u32, rs1: u32, rs2: u32, rm: u32) -> u32 {
+    rv_r(0b0000101, rs2, rs1, rm, rd, OP_FP)
+}
+fn fsgnj_d(f3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(0b0010001, rs2, rs1, f3, rd, OP_FP)
+}
+fn fminmax_d(f3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(0b0010101, rs2, rs1, f3, rd, OP_FP)
+}
+fn fcmp_d(f3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(0b1010001, rs2, rs1, f3, rd, OP_FP)
+}
+/// fcvt.{w,wu,l,lu}.d for `kind` 0..=3.
+fn fcvt_int_d(kind: u32, rd: u32, rs1: u32, rm: u32) -> u32 {
+    rv_r(0b1100001, kind, rs1, rm, rd, OP_FP)
+}
+/// fcvt.d.{w,wu,l,lu} for `kind` 0..=3.
+fn fcvt_d_int(kind: u32, rd: u32, rs1: u32, rm: u32) -> u32 {
+    rv_r(0b1101001, kind, rs1, rm, rd, OP_FP)
+}
+fn fcvt_s_d(rd: u32, rs1: u32, rm: u32) -> u32 {
+    rv_r(0b0100000, 1, rs1, rm, rd, OP_FP)
+}
+fn fmv_x_d(rd: u32, rs1: u32) -> u32 {
+    rv_r(0b1110001, 0, rs1, 0b000, rd, OP_FP)
+}
+fn fmv_d_x(rd: u32, rs1: u32) -> u32 {
+    rv_r(0b1111001, 0, rs1, 0b000, rd, OP_FP)
+}
+
+const W: u32 = 0;
+const WU: u32 = 1;
+const L: u32 = 2;
+const LU: u32 = 3;
+
+const RNE: u32 = 0b000;
+const RTZ: u32 = 0b001;
+const RDN: u32 = 0b010;
+const RUP: u32 = 0b011;
+const RMM: u32 = 0b100;
+const DYN: u32 = 0b111;
+const CANONICAL_NAN_D: u64 = 0x7ff8_0000_0000_0000;
+const CANONICAL_NAN_S: u32 = 0x7fc0_0000;
+
+fn d(v: f64) -> u64 {
+    v.to_bits()
+}
+
+/// Run `insn` with f1, f2, f3 = `a`, `b`, `c`; returns f4.
+fn run3(insn: u32, a: f64, b: f64, c: f64) -> u64 {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = d(a);
+    cpu.fpr[2] = d(b);
+    cpu.fpr[3] = d(c);
+    run_rv(&mut cpu, insn);
+    cpu.fpr[4]
+}
+
+#[test]
+fn test_fadd_d() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = 0x3ff0_0000_0000_0000; // 1.0
+    cpu.fpr[2] = 0x4000_0000_0000_0000; // 2.0
+    run_rv(&mut cpu, fadd_d(3, 1, 2, RNE));
+    assert_eq!(cpu.fpr[3], 0x4008_0000_0000_0000); // 3.0
+    assert_eq!(cpu.fflags, 0);
+}
+
+#[test]
+fn test_arith_d() {
+    assert_eq!(run3(fadd_d(4, 1, 2, 0), 1.5, 2.25, 0.0), d(3.75));
+    assert_eq!(run3(fsub_d(4, 1, 2, 0), 1.5, 2.25, 0.0), d(-0.75));
+    assert_eq!(run3(fmul_d(4, 1, 2, 0), 1.5, -4.0, 0.0), d(-6.0));
+    assert_eq!(run3(fdiv_d(4, 1, 2, 0), 1.0, 8.0, 0.0), d(0.125));
+    assert_eq!(run3(fsqrt_d(4, 1, 0), 2.0, 0.0, 0.0), d(2f64.sqrt()));
+    // Full double precision, not rounded through f32.
+    assert_eq!(run3(fadd_d(4, 1, 2, 0), 0.1, 0.2, 0.0), d(0.1 + 0.2));
+}
+
+/// 1/3 is inexact and just above the truncated quotient, so
+/// only rounding up (and down, for -1/3) changes the last bit.
+#[test]
+fn test_fdiv_d_rounding_modes() {
+    const THIRD: u64 = 0x3fd5_5555_5555_5555;
+    for (rm, pos, neg) in [
+        (RNE, THIRD, THIRD),
+        (RTZ, THIRD, THIRD),
+        (RDN, THIRD, THIRD + 1),
+        (RUP, THIRD + 1, THIRD),
+        (RMM, THIRD, THIRD),
+    ] {
+        assert_eq!(run3(fdiv_d(4, 1, 2, rm), 1.0, 3.0, 0.0), pos, "rm {rm}");
+        let got = run3(fdiv_d(4, 1, 2, rm), -1.0, 3.0, 0.0);
+        assert_eq!(got, neg | 1 << 63, "rm {rm}");
+    }
+
+    // DYN takes the mode from frm.
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = d(1.0);
+    cpu.fpr[2] = d(3.0);
+    cpu.frm = RUP as u64;
+    run_rv(&mut cpu, fdiv_d(4, 1, 2, DYN));
+    assert_eq!(cpu.fpr[4], THIRD + 1);
+}
+
+/// fcvt.s.d rounds to single precision in the requested mode.
+#[test]
+fn test_fcvt_s_d_rounding() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = d(1.0 / 3.0);
+    run_rv_insns(&mut cpu, &[fcvt_s_d(2, 1, RTZ), fcvt_s_d(3, 1, RUP)]);
+    assert_eq!(cpu.fpr[2], nanbox(0x3eaa_aaaa));
+    assert_eq!(cpu.fpr[3], nanbox(0x3eaa_aaab));
+    assert_eq!(cpu.fflags, NX);
+}
+
+#[test]
+fn test_fflags_d() {
+    let flags = |insn: u32, a: f64, b: f64| {
+        let mut cpu = RiscvCpu::new();
+        cpu.fpr[1] = d(a);
+        cpu.fpr[2] = d(b);
+        run_rv(&mut cpu, insn);
+        (cpu.fpr[4], cpu.fflags)
+    };
+    assert_eq!(
+        flags(fdiv_d(4, 1, 2, RNE), 1.0, 0.0),
+        (d(f64::INFINITY), DZ)
+    );
+    assert_eq!(flags(fdiv_d(4, 1, 2, RNE), 0.0, 0.0), (CANONICAL_NAN_D, NV));
+    assert_eq!(flags(fsqrt_d(4, 1, RNE), -1.0, 0.0), (CANONICAL_NAN_D, NV));
+    assert_eq!(flags(fadd_d(4, 1, 2, RNE), 0.1, 0.2).1, NX);
+    assert_eq!(flags(fmul_d(4, 1, 2, RNE), 1.5, 2.0), (d(3.0), 0));
+}
+
+// a=2.0, b=3.0, c=1.0:
+//   FMADD:  fma(a,b,c)    =  7.0
+//   FMSUB:  fma(a,b,-c)   =  5.0
+//   FNMSUB: fma(-a,b,c)   = -5.0
+//   FNMADD: fma(-a,b,-c)  = -7.0
+#[test]
+fn test_fma_family_d() {
+    for (op, want) in [
+        (OP_FMADD, 7.0),
+        (OP_FMSUB, 5.0),
+        (OP_FNMSUB, -5.0),
+        (OP_FNMADD, -7.0),
+    ] {
+        let insn = rv_r4(3, FMT_D, 2, 1, 0, 4, op);
+        assert_eq!(run3(insn, 2.0, 3.0, 1.0), d(want), "op {op:#09b}");
+    }
+    // Fused: one rounding, unlike mul then add.
+    let a = 1.0 + f64::EPSILON;
+    let insn = rv_r4(3, FMT_D, 2, 1, 0, 4, OP_FMSUB);
+    let got = f64::from_bits(run3(insn, a, a, a * a));
+    assert_eq!(got, a.mul_add(a, -(a * a)));
+    assert_ne!(got, 0.0);
+}
+
+/// Run `insn` with x1 = f1 = `x`; returns (x2, f2).
+fn cvt(insn: u32, x: u64) -> (u64, u64) {
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = x;
+    cpu.fpr[1] = x;
+    run_rv(&mut cpu, insn);
+    (cpu.gpr[2], cpu.fpr[2])
+}
+
+#[test]
+fn test_fcvt_d_from_int() {
+    let to_d = |kind, x: u64| cvt(fcvt_d_int(kind, 2, 1, 0), x).1;
+    assert_eq!(to_d(W, -5i64 as u64), d(-5.0));
+    // .w reads only the low word.
+    assert_eq!(to_d(W, 0x1_0000_0007), d(7.0));
+    assert_eq!(to_d(WU, 0xffff_ffff), d(4294967295.0));
+    assert_eq!(to_d(L, i64::MIN as u64), d(i64::MIN as f64));
+    assert_eq!(to_d(LU, u64::MAX), d(u64::MAX as f64));
+}
+
+#[test]
+fn test_fcvt_int_from_d() {
+    let to_int = |kind, rm, v: f64| cvt(fcvt_int_d(kind, 2, 1, rm), d(v)).0;
+    // .w results are sign-extended into the 64-bit GPR, .wu
+    // ones included.
+    assert_eq!(to_int(W, RTZ, -5.75), -5i64 as u64);
+    assert_eq!(to_int(WU, RTZ, 3e9), 3_000_000_000u32 as i32 as u64);
+    assert_eq!(to_int(L, RTZ, -1e18), -1_000_000_000_000_000_000i64 as u64);
+    assert_eq!(to_int(LU, RTZ, 1.5e19), 15_000_000_000_000_000_000);
+    // Round to nearest even.
+    assert_eq!(to_int(W, 0, 2.5), 2);
+    assert_eq!(to_int(W, 0, 3.5), 4);
+    // Out of range saturates; NaN gives the maximum.
+    assert_eq!(to_int(W, RTZ, 1e10), i32::MAX as u64);
+    assert_eq!(to_int(W, RTZ, -1e10), i32::MIN as i64 as u64);
+    assert_eq!(to_int(WU, RTZ, -1.0), 0);
+    assert_eq!(to_int(WU, RTZ, 1e10), u64::MAX);
+    assert_eq!(to_int(WU, RTZ, f64::NAN), u64::MAX);
+    assert_eq!(to_int(L, RTZ, f64::NAN), i64::MAX as u64);
+    assert_eq!(to_int(LU, RTZ, f64::INFINITY), u64::MAX);
+}
+
+#[test]
+fn test_fcvt_round_trip_d() {
+    for v in [0i64, 1, -1, 123_456_789, -(1 << 53), i32::MIN as i64] {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = v as u64;
+        run_rv_insns(
+            &mut cpu,
+            &[fcvt_d_int(L, 1, 1, 0), fcvt_int_d(L, 2, 1, RTZ)],
+        );
+        assert_eq!(cpu.fpr[1], d(v as f64));
+        assert_eq!(cpu.gpr[2], v as u64);
+    }
+}
+
+/// fmv moves bits untouched, NaN payloads included.
+#[test]
+fn test_fmv_d_bits() {
+    let payload = 0x7ff4_0000_dead_beef;
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = payload;
+    run_rv_insns(&mut cpu, &[fmv_d_x(1, 1), fmv_x_d(2, 1)]);
+    assert_eq!(cpu.fpr[1], payload);
+    assert_eq!(cpu.gpr[2], payload);
+}
+
+#[test]
+fn test_compare_d() {
+    let cmp = |f3, a: f64, b: f64| {
+        let mut cpu = RiscvCpu::new();
+        cpu.fpr[1] = d(a);
+        cpu.fpr[2] = d(b);
+        cpu.gpr[3] = 99;
+        run_rv(&mut cpu, fcmp_d(f3, 3, 1, 2));
+        cpu.gpr[3]
+    };
+    let (feq, flt, fle) = (0b010, 0b001, 0b000);
+    assert_eq!(cmp(feq, 1.0, 1.0), 1);
+    assert_eq!(cmp(feq, 0.0, -0.0), 1);
+    assert_eq!(cmp(flt, -1.0, 1.0), 1);
+    assert_eq!(cmp(flt, 1.0, 1.0), 0);
+    assert_eq!(cmp(fle, 1.0, 1.0), 1);
+    for f3 in [feq, flt, fle] {
+        assert_eq!(cmp(f3, f64::NAN, 1.0), 0);
+    }
+}
+
+#[test]
+fn test_fsgnj_d() {
+    let (j, jn, jx) = (0b000, 0b001, 0b010);
+    let sgn = |f3, a: f64, b: f64| {
+        f64::from_bits(run3(fsgnj_d(f3, 4, 1, 2), a, b, 0.0))
+    };
+    assert_eq!(sgn(j, 3.0, -1.0), -3.0);
+    assert_eq!(sgn(jn, 3.0, -1.0), 3.0);
+    assert_eq!(sgn(jx, -3.0, -1.0), 3.0);
+    assert_eq!(sgn(jx, -3.0, 1.0), -3.0);
+}
+
+#[test]
+fn test_fmin_fmax_d() {
+    let (min, max) = (0b000, 0b001);
+    let mm = |f3, a: f64, b: f64| run3(fminmax_d(f3, 4, 1, 2), a, b, 0.0);
+    assert_eq!(mm(min, 1.0, -2.0), d(-2.0));
+    assert_eq!(mm(max, 1.0, -2.0), d(1.0));
+    // -0.0 orders below +0.0.
+    assert_eq!(mm(min, 0.0, -0.0), d(-0.0));
+    assert_eq!(mm(max, -0.0, 0.0), d(0.0));
+    // One NaN: the other operand; both: canonical NaN.
+    assert_eq!(mm(min, f64::NAN, 5.0), d(5.0));
+    assert_eq!(mm(max, 5.0, f64::NAN), d(5.0));
+    assert_eq!(mm(max, f64::NAN, f64::NAN), CANONICAL_NAN_D);
+}
+
+#[test]
+fn test_fld_fsd_round_trip() {
+    let mut mem = [0u8; 0x40];
+    mem[0x10..0x18].copy_from_slice(&d(-2.5).to_le_bytes());
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    cpu.gpr[1] = 0x10;
+    run_rv_insns(&mut cpu, &[fld(1, 1, 0), fadd_d(2, 1, 1, 0), fsd(2, 1, 8)]);
+    assert_eq!(cpu.fpr[1], d(-2.5));
+    assert_eq!(&mem[0x18..0x20], &d(-5.0).to_le_bytes());
+}
+
+/// D results fill the FPR; S results are NaN-boxed, and an S
+/// operand that is not NaN-boxed reads as the canonical NaN.
+#[test]
+fn test_nanboxing_between_s_and_d() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = d(1.5);
+    cpu.fpr[2] = nanbox(0x4020_0000); // 2.5f
+    run_rv_insns(
+        &mut cpu,
+        &[
+            fcvt_s_d(3, 1, 0),
+            fcvt_d_s(4, 2),
+            // f1 holds a double: not a valid single.
+            fadd_s(5, 1, 2, 0),
+        ],
+    );
+    assert_eq!(cpu.fpr[3], nanbox(1.5f32.to_bits()));
+    assert_eq!(cpu.fpr[4], d(2.5));
+    assert_eq!(cpu.fpr[5], nanbox(CANONICAL_NAN_S));
+}
@@ -0,0 +1,39 @@
+//! Every frontend helper runs its body under `helper::guard`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn rust_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            rust_files(&path, out);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            out.push(path);
+        }
+    }
+}
+
+#[test]
+fn every_helper_is_guarded() {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("../frontend/src");
+    let mut files = Vec::new();
+    rust_files(&src, &mut files);
+    let mut seen = 0;
+    for file in files {
+        let text = fs::read_to_string(&file).unwrap();
+        for (at, _) in text.match_indices("extern \"C\" fn helper_") {
+            let decl = &text[at..];
+            let name =
+                decl["extern \"C\" fn ".len()..].split('(').next().unwrap();
+            let body = decl[decl.find('{').unwrap() + 1..].trim_start();
+            assert!(
+                body.starts_with("helper::guard("),
+                "{}: {name} is not wrapped in helper::guard",
+                file.display()
+            );
+            seen += 1;
+        }
+    }
+    assert!(seen > 50, "found only {seen} helpers");
+}
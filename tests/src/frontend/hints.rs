@@ -0,0 +1,185 @@
+//! HINT encodings (Zicbop prefetches, Zihintntl, canonical NOPs)
+//! and the Zicbom/Zicboz cache-block operations.
+
+use tcg_backend::{HostCodeGen, X86_64CodeGen};
+use tcg_core::insn_ops::insn_ops;
+use tcg_core::tb::TbExit;
+use tcg_core::Context;
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_UNDEF;
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
+use tcg_frontend::translator_loop;
+
+use super::{
+    add, addi, c_add, c_addi, count_insns, fence, ori, run_rv_bytes,
+    run_rv_insns_with_cfg, rv_i, rv_r, slli, OP_IMM,
+};
+
+const OP_MISC_MEM: u32 = 0b0001111;
+
+fn prefetch(kind: u32, rs1: u32, off: i32) -> u32 {
+    rv_i(off | kind as i32, rs1, 0b110, 0, OP_IMM)
+}
+
+fn cbo(op: u32, rs1: u32) -> u32 {
+    rv_i(op as i32, rs1, 0b010, 0, OP_MISC_MEM)
+}
+
+const CBO_INVAL: u32 = 0;
+const CBO_CLEAN: u32 = 1;
+const CBO_FLUSH: u32 = 2;
+const CBO_ZERO: u32 = 4;
+
+/// Every 32-bit hint, with its name.
+fn hints32() -> Vec<(&'static str, u32)> {
+    vec![
+        ("prefetch.i", prefetch(0, 3, 0x40)),
+        ("prefetch.r", prefetch(1, 3, -0x20)),
+        ("prefetch.w", prefetch(3, 3, 0)),
+        ("ntl.p1", add(0, 0, 2)),
+        ("ntl.pall", add(0, 0, 3)),
+        ("ntl.s1", add(0, 0, 4)),
+        ("ntl.all", add(0, 0, 5)),
+        ("nop", addi(0, 0, 0)),
+        ("addi x0", addi(0, 3, 7)),
+        ("ori x0", ori(0, 3, 1)),
+        ("add x0", add(0, 3, 4)),
+        ("slli x0", slli(0, 3, 4)),
+        ("sub x0", rv_r(0b0100000, 4, 3, 0b000, 0, 0b0110011)),
+        ("addw x0", rv_r(0, 4, 3, 0b000, 0, 0b0111011)),
+        ("fence", fence()),
+        ("cbo.inval", cbo(CBO_INVAL, 3)),
+        ("cbo.clean", cbo(CBO_CLEAN, 3)),
+        ("cbo.flush", cbo(CBO_FLUSH, 3)),
+    ]
+}
+
+/// Every 16-bit hint, with its name.
+fn hints16() -> Vec<(&'static str, u16)> {
+    vec![
+        ("c.ntl.p1", c_add(0, 2)),
+        ("c.ntl.pall", c_add(0, 3)),
+        ("c.ntl.s1", c_add(0, 4)),
+        ("c.ntl.all", c_add(0, 5)),
+        ("c.nop", c_addi(0, 0)),
+        ("c.add x0", c_add(0, 9)),
+    ]
+}
+
+/// `x1 += 5`, `hint`, `x2 = x1 + 1`.
+fn around(hint: &[u8]) -> Vec<u8> {
+    let mut code = addi(1, 1, 5).to_le_bytes().to_vec();
+    code.extend_from_slice(hint);
+    code.extend_from_slice(&addi(2, 1, 1).to_le_bytes());
+    code
+}
+
+fn translate_bytes(code: &[u8]) -> Context {
+    let backend = X86_64CodeGen::new();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let mut disas =
+        RiscvDisasContext::new(0, code.as_ptr(), RiscvCfg::default());
+    disas.base.max_insns = count_insns(code);
+    translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
+    ctx
+}
+
+fn check_hint(name: &str, hint: &[u8]) {
+    let code = around(hint);
+    let ctx = translate_bytes(&code);
+    let tb = insn_ops(&ctx);
+    let pcs: Vec<u64> = tb.insns.iter().map(|i| i.pc).collect();
+    let second = 4 + hint.len() as u64;
+    assert_eq!(pcs, [0, 4, second], "{name}");
+    assert!(tb.insns[1].ops.is_empty(), "{name}: {:?}", tb.insns[1]);
+
+    let mut cpu = RiscvCpu::new();
+    for i in 1..32 {
+        cpu.gpr[i] = 0x1111 * i as u64;
+    }
+    let mut want = cpu.gpr;
+    want[1] += 5;
+    want[2] = want[1] + 1;
+    run_rv_bytes(&mut cpu, &code);
+    assert_eq!(cpu.gpr, want, "{name}");
+    assert_eq!(cpu.pc, code.len() as u64, "{name}");
+}
+
+#[test]
+fn test_hints_emit_no_ir() {
+    for (name, hint) in hints32() {
+        check_hint(name, &hint.to_le_bytes());
+    }
+    for (name, hint) in hints16() {
+        check_hint(name, &hint.to_le_bytes());
+    }
+}
+
+/// Disabled, the hints are still the base-ISA HINTs they
+/// encode; the cache-block operations become illegal.
+#[test]
+fn test_hints_with_extensions_disabled() {
+    let cfg = RiscvCfg {
+        ext_zicbom: false,
+        ext_zicbop: false,
+        ext_zicboz: false,
+        ext_zihintntl: false,
+        ..RiscvCfg::default()
+    };
+    for insn in [prefetch(1, 3, 0x40), add(0, 0, 2)] {
+        let mut cpu = RiscvCpu::new();
+        let code = [insn, addi(1, 0, 1)];
+        run_rv_insns_with_cfg(&mut cpu, &code, cfg);
+        assert_eq!(cpu.gpr[1], 1);
+    }
+    for op in [CBO_INVAL, CBO_CLEAN, CBO_FLUSH, CBO_ZERO] {
+        let mut cpu = RiscvCpu::new();
+        let exit = run_rv_insns_with_cfg(&mut cpu, &[cbo(op, 3)], cfg);
+        assert_eq!(exit, TbExit::Exception(EXCP_UNDEF).code() as usize);
+    }
+}
+
+/// Runs `x1 = addr`, `cbo.zero (x1)`, `x3 = 1` over a page of
+/// 0xff bytes and returns the page.
+fn cbo_zero_at(addr: i32, cfg: RiscvCfg) -> [u8; 0x200] {
+    let mut mem = [0xffu8; 0x200];
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    let code = [addi(1, 0, addr), cbo(CBO_ZERO, 1), addi(3, 0, 1)];
+    run_rv_insns_with_cfg(&mut cpu, &code, cfg);
+    assert_eq!(cpu.gpr[3], 1);
+    assert_eq!(cpu.gpr[1], addr as u64);
+    mem
+}
+
+fn assert_zeroed(mem: &[u8; 0x200], block: std::ops::Range<usize>) {
+    for (i, &b) in mem.iter().enumerate() {
+        let want = if block.contains(&i) { 0 } else { 0xff };
+        assert_eq!(b, want, "byte {i:#x}");
+    }
+}
+
+#[test]
+fn test_cbo_zero_aligned() {
+    let mem = cbo_zero_at(0x100, RiscvCfg::default());
+    assert_zeroed(&mem, 0x100..0x140);
+}
+
+/// A mid-block address zeroes the whole block holding it.
+#[test]
+fn test_cbo_zero_mid_block() {
+    let mem = cbo_zero_at(0x12b, RiscvCfg::default());
+    assert_zeroed(&mem, 0x100..0x140);
+}
+
+#[test]
+fn test_cbo_zero_block_size() {
+    let cfg = RiscvCfg {
+        cbo_block_size: 16,
+        ..RiscvCfg::default()
+    };
+    let mem = cbo_zero_at(0x10c, cfg);
+    assert_zeroed(&mem, 0x100..0x110);
+}
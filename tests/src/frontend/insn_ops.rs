@@ -0,0 +1,58 @@
+//! Attribution of IR ops to guest instructions.
+
+use tcg_core::insn_ops::insn_ops;
+use tcg_core::{Context, Opcode};
+
+use super::{add, addi, beq, sraiw, translate_ir};
+
+fn opcodes(ctx: &Context, range: std::ops::Range<usize>) -> Vec<Opcode> {
+    ctx.ops()[range].iter().map(|op| op.opc).collect()
+}
+
+/// A branch ends the TB with its own exits: they stay with the
+/// branch but are counted apart, and the epilogue is empty.
+#[test]
+fn test_insn_ops_mixed_tb() {
+    let ctx = translate_ir(
+        &[addi(1, 1, 5), sraiw(2, 1, 3), add(3, 1, 2), beq(3, 0, 16)],
+        0,
+    );
+    let tb = insn_ops(&ctx);
+    let pcs: Vec<u64> = tb.insns.iter().map(|i| i.pc).collect();
+    assert_eq!(pcs, [0, 4, 8, 12]);
+    assert_eq!(tb.start.end, tb.insns[0].ops.start - 1);
+    for w in tb.insns.windows(2) {
+        // Only the next insn_start in between.
+        assert_eq!(w[0].ops.end + 1, w[1].ops.start);
+    }
+    assert_eq!(
+        opcodes(&ctx, tb.insns[0].ops.clone()),
+        [Opcode::Add, Opcode::Mov]
+    );
+    assert_eq!(tb.insns[0].exit, 0);
+    assert_eq!(tb.insns[1].body(), tb.insns[1].ops.len());
+
+    let beq = &tb.insns[3];
+    let ops = opcodes(&ctx, beq.ops.clone());
+    assert_eq!(ops.first(), Some(&Opcode::BrCond));
+    assert_eq!(beq.exit, 4, "{ops:?}");
+    assert_eq!(beq.body(), ops.len() - 4);
+    assert!(tb.stop.is_empty());
+    assert_eq!(beq.ops.end, ctx.ops().len());
+}
+
+/// A TB cut by the instruction limit falls through in the
+/// epilogue, which is not charged to its last instruction.
+#[test]
+fn test_insn_ops_fall_through_epilogue() {
+    let ctx = translate_ir(&[addi(1, 1, 5), addi(2, 2, 7)], 0);
+    let tb = insn_ops(&ctx);
+    assert_eq!(tb.insns.len(), 2);
+    assert_eq!(tb.insns[0].body(), tb.insns[1].body());
+    assert_eq!(tb.insns[1].exit, 0);
+    assert_eq!(tb.insns[1].ops.end, tb.stop.start);
+    assert_eq!(
+        opcodes(&ctx, tb.stop.clone()),
+        [Opcode::Mov, Opcode::GotoTb, Opcode::ExitTb]
+    );
+}
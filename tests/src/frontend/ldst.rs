@@ -0,0 +1,164 @@
+//! RV64I loads and stores through guest memory: widths, sign
+//! and zero extension into the 64-bit GPR.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+
+use super::{addi, lui, run_rv_insns, rv_i};
+
+const OP_LOAD: u32 = 0b0000011;
+const OP_STORE: u32 = 0b0100011;
+
+const LB: u32 = 0b000;
+const LH: u32 = 0b001;
+const LW: u32 = 0b010;
+const LD: u32 = 0b011;
+const LBU: u32 = 0b100;
+const LHU: u32 = 0b101;
+const LWU: u32 = 0b110;
+
+const SB: u32 = 0b000;
+const SH: u32 = 0b001;
+const SW: u32 = 0b010;
+const SD: u32 = 0b011;
+
+fn load(f3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, f3, rd, OP_LOAD)
+}
+
+fn store(f3: u32, rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (f3 << 12)
+        | ((imm & 0x1f) << 7)
+        | OP_STORE
+}
+
+/// Run `code` at guest address 0 with `data` placed right after
+/// it in the same guest image; x1 holds the address of `data`.
+/// Returns the CPU and the image's data part afterwards.
+fn run_with_data(code: &[u32], data: &[u8]) -> (RiscvCpu, Vec<u8>) {
+    let data_at = (code.len() as i32 + 1) * 4;
+    let mut insns = vec![addi(1, 0, data_at)];
+    insns.extend_from_slice(code);
+
+    let mut image: Vec<u8> =
+        insns.iter().flat_map(|i| i.to_le_bytes()).collect();
+    image.extend_from_slice(data);
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = image.as_mut_ptr() as u64;
+    assert_eq!(run_rv_insns(&mut cpu, &insns), 0);
+    let data = image.split_off(data_at as usize);
+    (cpu, data)
+}
+
+/// Load `bytes` with `f3` at offset 0 and return rd.
+fn load_from(f3: u32, bytes: &[u8]) -> u64 {
+    let (cpu, _) = run_with_data(&[load(f3, 2, 1, 0)], bytes);
+    cpu.gpr[2]
+}
+
+#[test]
+fn test_lb_lbu_extension() {
+    assert_eq!(load_from(LB, &[0x80]), 0xffff_ffff_ffff_ff80);
+    assert_eq!(load_from(LBU, &[0x80]), 0x80);
+    assert_eq!(load_from(LB, &[0x7f]), 0x7f);
+}
+
+#[test]
+fn test_lh_lhu_extension() {
+    let bytes = 0x8001u16.to_le_bytes();
+    assert_eq!(load_from(LH, &bytes), 0xffff_ffff_ffff_8001);
+    assert_eq!(load_from(LHU, &bytes), 0x8001);
+}
+
+#[test]
+fn test_lw_sign_extends_lwu_zero_extends() {
+    let bytes = 0x8000_0001u32.to_le_bytes();
+    assert_eq!(load_from(LW, &bytes), 0xffff_ffff_8000_0001);
+    assert_eq!(load_from(LWU, &bytes), 0x8000_0001);
+    let bytes = 0x7fff_ffffu32.to_le_bytes();
+    assert_eq!(load_from(LW, &bytes), 0x7fff_ffff);
+}
+
+#[test]
+fn test_ld_full_width() {
+    let v = 0x8123_4567_89ab_cdefu64;
+    assert_eq!(load_from(LD, &v.to_le_bytes()), v);
+}
+
+#[test]
+fn test_loads_are_little_endian_with_offset() {
+    let data = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+    let code = [load(LBU, 2, 1, 3), load(LHU, 3, 1, 2), load(LWU, 4, 1, 4)];
+    let (cpu, _) = run_with_data(&code, &data);
+    assert_eq!(cpu.gpr[2], 0x44);
+    assert_eq!(cpu.gpr[3], 0x4433);
+    assert_eq!(cpu.gpr[4], 0x8877_6655);
+}
+
+/// Stores write only their width; the surrounding bytes keep
+/// their value.
+#[test]
+fn test_store_widths() {
+    let code = [
+        addi(2, 0, -1), // x2 = all ones
+        store(SB, 2, 1, 0),
+        store(SH, 2, 1, 2),
+        store(SW, 2, 1, 4),
+        store(SD, 2, 1, 8),
+    ];
+    let (_, data) = run_with_data(&code, &[0; 24]);
+    assert_eq!(
+        data,
+        [
+            0xff, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+            0, 0, 0, 0, 0, 0, 0, 0,
+        ]
+    );
+}
+
+#[test]
+fn test_sb_lh_lw_ld_round_trip() {
+    let code = [
+        addi(2, 0, -0x80), // x2 = ...ff80
+        store(SB, 2, 1, 0),
+        store(SB, 2, 1, 1),
+        load(LH, 3, 1, 0),             // 0x8080 sign-extended
+        lui(4, 0x8000_0000u32 as i32), // x4 = 0xffff_ffff_8000_0000
+        store(SW, 4, 1, 4),
+        load(LW, 5, 1, 4),
+        load(LD, 6, 1, 0),
+        store(SD, 6, 1, 8),
+        load(LWU, 7, 1, 12),
+    ];
+    let (cpu, data) = run_with_data(&code, &[0; 16]);
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_ffff_8080);
+    assert_eq!(cpu.gpr[5], 0xffff_ffff_8000_0000);
+    assert_eq!(cpu.gpr[6], 0x8000_0000_0000_8080);
+    assert_eq!(cpu.gpr[7], 0x8000_0000);
+    assert_eq!(data[8..16], data[0..8]);
+}
+
+/// Negative offsets address below the base register.
+#[test]
+fn test_negative_offset() {
+    let code = [
+        addi(1, 1, 8),
+        addi(2, 0, 0x5a),
+        store(SB, 2, 1, -8),
+        load(LBU, 3, 1, -8),
+    ];
+    let (cpu, data) = run_with_data(&code, &[0; 8]);
+    assert_eq!(cpu.gpr[3], 0x5a);
+    assert_eq!(data[0], 0x5a);
+}
+
+/// A load into x0 is performed but does not write the register.
+#[test]
+fn test_load_to_x0_discarded() {
+    let (cpu, _) = run_with_data(&[load(LD, 0, 1, 0)], &[0xff; 8]);
+    assert_eq!(cpu.gpr[0], 0);
+}
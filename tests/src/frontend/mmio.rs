@@ -0,0 +1,77 @@
+//! Stores into the MMIO window leave the TB instead of writing
+//! guest memory.
+
+use tcg_core::tb::TbExit;
+use tcg_core::types::MemOp;
+use tcg_frontend::riscv::cpu::{RiscvCpu, EXIT_MMIO_STORE};
+
+use super::{addi, run_rv_insns, run_rv_insns_with};
+
+const WINDOW: (u64, u64) = (0x100, 0x108);
+
+fn s_type(imm: i32, rs2: u32, rs1: u32, f3: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (f3 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0b0100011
+}
+
+fn sb(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    s_type(imm, rs2, rs1, 0b000)
+}
+
+fn sd(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    s_type(imm, rs2, rs1, 0b011)
+}
+
+/// x1 = `addr`, x2 = 0x41, then `store`, then x3 = 1.
+fn program(store: u32, addr: i32) -> [u32; 4] {
+    [addi(1, 0, addr), addi(2, 0, 0x41), store, addi(3, 0, 1)]
+}
+
+fn cpu_with(mem: &mut [u8; 0x200]) -> RiscvCpu {
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    cpu
+}
+
+#[test]
+fn test_mmio_store_exits() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    let exit = run_rv_insns_with(&mut cpu, &program(sb(2, 1, 4), 0x100), |d| {
+        d.mmio = Some(WINDOW)
+    });
+    assert_eq!(exit, TbExit::Custom(EXIT_MMIO_STORE).code() as usize);
+    assert_eq!(cpu.mmio_addr, 0x104);
+    assert_eq!(cpu.mmio_val, 0x41);
+    assert_eq!(MemOp::new(cpu.mmio_op as u16).size_bytes(), 1);
+    // Past the store, which was not made; nothing after it ran.
+    assert_eq!(cpu.pc, 12);
+    assert_eq!(mem[0x104], 0);
+    assert_eq!(cpu.gpr[3], 0);
+    // The store retires once the caller makes it.
+    assert_eq!(cpu.cycle, 3);
+}
+
+#[test]
+fn test_store_outside_mmio_window() {
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    // Just past the window.
+    run_rv_insns_with(&mut cpu, &program(sd(2, 1, 0), 0x108), |d| {
+        d.mmio = Some(WINDOW)
+    });
+    assert_eq!(mem[0x108], 0x41);
+    assert_eq!(cpu.gpr[3], 1);
+
+    // Without a window the same address is plain memory.
+    let mut mem = [0u8; 0x200];
+    let mut cpu = cpu_with(&mut mem);
+    run_rv_insns(&mut cpu, &program(sb(2, 1, 4), 0x100));
+    assert_eq!(mem[0x104], 0x41);
+    assert_eq!(cpu.gpr[3], 1);
+}
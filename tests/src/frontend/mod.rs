@@ -2,15 +2,32 @@
 //! run them through the full frontend→backend pipeline, and verify
 //! the resulting CPU state.
 
+mod align;
+mod atomic;
+mod coverage;
+mod csr;
 mod difftest;
+mod div;
+mod fpu_d;
+mod helpers;
+mod hints;
+mod insn_ops;
+mod ldst;
+mod mmio;
+mod mulh;
+mod page_end;
+mod reserved;
+mod shifts;
+mod zbb;
 
 use tcg_backend::code_buffer::CodeBuffer;
 use tcg_backend::translate::translate_and_execute;
 use tcg_backend::HostCodeGen;
 use tcg_backend::X86_64CodeGen;
-use tcg_core::tb::{EXCP_EBREAK, EXCP_ECALL, EXCP_UNDEF};
-use tcg_core::Context;
+use tcg_core::tb::{TB_EXIT_NOCHAIN, TB_FLAG_SINGLE_STEP};
+use tcg_core::{Context, Opcode, TempIdx, Type};
 use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{EXCP_EBREAK, EXCP_ECALL, EXCP_UNDEF};
 use tcg_frontend::riscv::ext::{MisaExt, RiscvCfg};
 use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
 use tcg_frontend::translator_loop;
@@ -199,6 +216,15 @@ const OP_M_FUNCT7: u32 = 0b0000001;
 fn mul(rd: u32, rs1: u32, rs2: u32) -> u32 {
     rv_r(OP_M_FUNCT7, rs2, rs1, 0b000, rd, OP_REG)
 }
+fn mulh(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(OP_M_FUNCT7, rs2, rs1, 0b001, rd, OP_REG)
+}
+fn mulhsu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(OP_M_FUNCT7, rs2, rs1, 0b010, rd, OP_REG)
+}
+fn mulhu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    rv_r(OP_M_FUNCT7, rs2, rs1, 0b011, rd, OP_REG)
+}
 fn div_rv(rd: u32, rs1: u32, rs2: u32) -> u32 {
     rv_r(OP_M_FUNCT7, rs2, rs1, 0b100, rd, OP_REG)
 }
@@ -220,6 +246,12 @@ const OP_SYSTEM: u32 = 0b1110011;
 fn csrrw(rd: u32, rs1: u32, csr: u32) -> u32 {
     (csr << 20) | (rs1 << 15) | (0b001 << 12) | (rd << 7) | OP_SYSTEM
 }
+fn csrrs(rd: u32, rs1: u32, csr: u32) -> u32 {
+    (csr << 20) | (rs1 << 15) | (0b010 << 12) | (rd << 7) | OP_SYSTEM
+}
+fn csrrc(rd: u32, rs1: u32, csr: u32) -> u32 {
+    (csr << 20) | (rs1 << 15) | (0b011 << 12) | (rd << 7) | OP_SYSTEM
+}
 
 // ── Test runner ───────────────────────────────────────────────
 
@@ -239,6 +271,16 @@ fn run_rv_insns_with_cfg(
     cpu: &mut RiscvCpu,
     insns: &[u32],
     cfg: RiscvCfg,
+) -> usize {
+    run_rv_insns_with(cpu, insns, |d| d.cfg = cfg)
+}
+
+/// Like `run_rv_insns`, letting `setup` adjust the disassembly
+/// context first.
+fn run_rv_insns_with(
+    cpu: &mut RiscvCpu,
+    insns: &[u32],
+    setup: impl FnOnce(&mut RiscvDisasContext),
 ) -> usize {
     let code: Vec<u8> = insns.iter().flat_map(|i| i.to_le_bytes()).collect();
     let guest_base = code.as_ptr();
@@ -251,8 +293,9 @@ fn run_rv_insns_with_cfg(
     let mut ctx = Context::new();
     backend.init_context(&mut ctx);
 
-    let mut disas = RiscvDisasContext::new(0, guest_base, cfg);
+    let mut disas = RiscvDisasContext::new(0, guest_base, RiscvCfg::default());
     disas.base.max_insns = insns.len() as u32;
+    setup(&mut disas);
     translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
 
     unsafe {
@@ -1065,6 +1108,30 @@ fn fcvt_s_w(rd: u32, rs1: u32, rm: u32) -> u32 {
     rv_r(0b1101000, 0, rs1, rm, rd, OP_FP)
 }
 
+fn fdiv_s(rd: u32, rs1: u32, rs2: u32, rm: u32) -> u32 {
+    rv_r(0b0001100, rs2, rs1, rm, rd, OP_FP)
+}
+fn fadd_d(rd: u32, rs1: u32, rs2: u32, rm: u32) -> u32 {
+    rv_r(0b0000001, rs2, rs1, rm, rd, OP_FP)
+}
+fn fmul_d(rd: u32, rs1: u32, rs2: u32, rm: u32) -> u32 {
+    rv_r(0b0001001, rs2, rs1, rm, rd, OP_FP)
+}
+fn fdiv_d(rd: u32, rs1: u32, rs2: u32, rm: u32) -> u32 {
+    rv_r(0b0001101, rs2, rs1, rm, rd, OP_FP)
+}
+fn fsqrt_d(rd: u32, rs1: u32, rm: u32) -> u32 {
+    rv_r(0b0101101, 0, rs1, rm, rd, OP_FP)
+}
+/// FCVT.WU.S rd, rs1, rm — convert f32 to unsigned i32
+fn fcvt_wu_s(rd: u32, rs1: u32, rm: u32) -> u32 {
+    rv_r(0b1100000, 1, rs1, rm, rd, OP_FP)
+}
+/// FCVT.D.S rd, rs1 — widen f32 to f64
+fn fcvt_d_s(rd: u32, rs1: u32) -> u32 {
+    rv_r(0b0100001, 0, rs1, 0, rd, OP_FP)
+}
+
 // ── Byte-level test runner ───────────────────────────────────
 
 /// Count instructions in a raw byte stream (mixed 16/32-bit).
@@ -1086,6 +1153,15 @@ fn count_insns(code: &[u8]) -> u32 {
 /// Translate and execute a raw byte stream of RISC-V
 /// instructions (supports mixed 16/32-bit).
 fn run_rv_bytes(cpu: &mut RiscvCpu, code: &[u8]) -> usize {
+    run_rv_bytes_with_cfg(cpu, code, RiscvCfg::default())
+}
+
+/// Like `run_rv_bytes` but with a custom extension config.
+fn run_rv_bytes_with_cfg(
+    cpu: &mut RiscvCpu,
+    code: &[u8],
+    cfg: RiscvCfg,
+) -> usize {
     let guest_base = code.as_ptr();
 
     let mut backend = X86_64CodeGen::new();
@@ -1097,7 +1173,7 @@ fn run_rv_bytes(cpu: &mut RiscvCpu, code: &[u8]) -> usize {
     backend.init_context(&mut ctx);
 
     let n = count_insns(code);
-    let mut disas = RiscvDisasContext::new(0, guest_base, RiscvCfg::default());
+    let mut disas = RiscvDisasContext::new(0, guest_base, cfg);
     disas.base.max_insns = n;
     translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
 
@@ -1119,29 +1195,7 @@ fn run_rvc(cpu: &mut RiscvCpu, insn: u16) -> usize {
 
 /// Like `run_rvc` but with a custom extension config.
 fn run_rvc_with_cfg(cpu: &mut RiscvCpu, insn: u16, cfg: RiscvCfg) -> usize {
-    let code = insn.to_le_bytes();
-    let guest_base = code.as_ptr();
-
-    let mut backend = X86_64CodeGen::new();
-    let mut buf = CodeBuffer::new(4096).unwrap();
-    backend.emit_prologue(&mut buf);
-    backend.emit_epilogue(&mut buf);
-
-    let mut ctx = Context::new();
-    backend.init_context(&mut ctx);
-
-    let mut disas = RiscvDisasContext::new(0, guest_base, cfg);
-    disas.base.max_insns = 1;
-    translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
-
-    unsafe {
-        translate_and_execute(
-            &mut ctx,
-            &backend,
-            &mut buf,
-            cpu as *mut RiscvCpu as *mut u8,
-        )
-    }
+    run_rv_bytes_with_cfg(cpu, &insn.to_le_bytes(), cfg)
 }
 
 // ── RVC execution tests ──────────────────────────────────────
@@ -1478,18 +1532,211 @@ fn test_fcvt_fadd_sequence() {
     assert_eq!(cpu.fpr[3], nanbox(0x41f0_0000));
 }
 
+// ── RV32F/RV64D: accrued exception flags ───────────────────
+
+const CSR_FFLAGS: u32 = 0x001;
+const CSR_FRM: u32 = 0x002;
+const CSR_FCSR: u32 = 0x003;
+const NV: u64 = 1 << 4;
+const DZ: u64 = 1 << 3;
+const OF: u64 = 1 << 2;
+const UF: u64 = 1 << 1;
+const NX: u64 = 1 << 0;
+const RM_RTZ: u32 = 1;
+const RM_RUP: u32 = 3;
+const RM_DYN: u32 = 7;
+
+/// Run `insn` on f1 = `a`, f2 = `b` and read fflags back with
+/// `csrrs x1, fflags, x0`; returns (f3, fflags).
+fn fflags_of(insn: u32, a: f64, b: f64) -> (u64, u64) {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = a.to_bits();
+    cpu.fpr[2] = b.to_bits();
+    run_rv_insns(&mut cpu, &[insn, csrrs(1, 0, CSR_FFLAGS)]);
+    assert_eq!(cpu.gpr[1], cpu.fflags);
+    (cpu.fpr[3], cpu.gpr[1])
+}
+
+#[test]
+fn test_fflags_inexact() {
+    let (res, flags) = fflags_of(fadd_d(3, 1, 2, 0), 0.1, 0.2);
+    assert_eq!(res, (0.1f64 + 0.2).to_bits());
+    assert_eq!(flags, NX);
+}
+
+#[test]
+fn test_fflags_exact_sets_nothing() {
+    let (res, flags) = fflags_of(fadd_d(3, 1, 2, 0), 0.5, 0.25);
+    assert_eq!(res, 0.75f64.to_bits());
+    assert_eq!(flags, 0);
+}
+
+#[test]
+fn test_fflags_underflow() {
+    // DBL_MIN / 2 is an exact subnormal, which does not raise UF;
+    // DBL_MIN / 3 is tiny and inexact.
+    let (_, flags) = fflags_of(fdiv_d(3, 1, 2, 0), f64::MIN_POSITIVE, 2.0);
+    assert_eq!(flags, 0);
+    let (_, flags) = fflags_of(fdiv_d(3, 1, 2, 0), f64::MIN_POSITIVE, 3.0);
+    assert_eq!(flags, UF | NX);
+}
+
+#[test]
+fn test_fflags_overflow() {
+    let (res, flags) = fflags_of(fmul_d(3, 1, 2, 0), f64::MAX, 2.0);
+    assert_eq!(res, f64::INFINITY.to_bits());
+    assert_eq!(flags, OF | NX);
+}
+
+#[test]
+fn test_fflags_invalid_gives_canonical_nan() {
+    let (res, flags) = fflags_of(fdiv_d(3, 1, 2, 0), 0.0, 0.0);
+    assert_eq!(res, 0x7ff8_0000_0000_0000);
+    assert_eq!(flags, NV);
+    let (res, flags) = fflags_of(fsqrt_d(3, 1, 0), -1.0, 0.0);
+    assert_eq!(res, 0x7ff8_0000_0000_0000);
+    assert_eq!(flags, NV);
+}
+
+#[test]
+fn test_fflags_divide_by_zero() {
+    let (res, flags) = fflags_of(fdiv_d(3, 1, 2, 0), 1.0, 0.0);
+    assert_eq!(res, f64::INFINITY.to_bits());
+    assert_eq!(flags, DZ);
+}
+
+#[test]
+fn test_fflags_single_precision() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = nanbox(1.0f32.to_bits());
+    cpu.fpr[2] = nanbox(0.0f32.to_bits());
+    run_rv(&mut cpu, fdiv_s(3, 1, 2, 0));
+    assert_eq!(cpu.fpr[3], nanbox(f32::INFINITY.to_bits()));
+    assert_eq!(cpu.fflags, DZ);
+
+    cpu.fpr[1] = nanbox(0.0f32.to_bits());
+    run_rv(&mut cpu, fdiv_s(3, 1, 2, 0));
+    assert_eq!(cpu.fpr[3], nanbox(0x7fc0_0000));
+    assert_eq!(cpu.fflags, DZ | NV);
+}
+
+#[test]
+fn test_fflags_accumulate_and_clear() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = 1.0f64.to_bits();
+    cpu.fpr[2] = 0.0f64.to_bits();
+    cpu.fpr[4] = f64::MAX.to_bits();
+    cpu.fpr[5] = 2.0f64.to_bits();
+    cpu.gpr[2] = DZ | OF;
+    run_rv_insns(
+        &mut cpu,
+        &[
+            fdiv_d(3, 1, 2, 0),
+            fmul_d(3, 4, 5, 0),
+            csrrs(1, 0, CSR_FFLAGS),
+            // Clear DZ and OF, returning the old value.
+            csrrc(3, 2, CSR_FFLAGS),
+            csrrs(4, 0, CSR_FFLAGS),
+        ],
+    );
+    assert_eq!(cpu.gpr[1], DZ | OF | NX);
+    assert_eq!(cpu.gpr[3], DZ | OF | NX);
+    assert_eq!(cpu.gpr[4], NX);
+
+    // fcsr = frm << 5 | fflags; writing it replaces both.
+    cpu.gpr[2] = (RM_RTZ as u64) << 5 | NV;
+    run_rv_insns(
+        &mut cpu,
+        &[
+            csrrw(1, 2, CSR_FCSR),
+            csrrs(3, 0, CSR_FRM),
+            csrrs(4, 0, CSR_FFLAGS),
+        ],
+    );
+    assert_eq!(cpu.gpr[1], NX);
+    assert_eq!(cpu.gpr[3], RM_RTZ as u64);
+    assert_eq!(cpu.gpr[4], NV);
+}
+
+#[test]
+fn test_frm_dynamic_rounding() {
+    let third = |frm: u32| {
+        let mut cpu = RiscvCpu::new();
+        cpu.fpr[1] = 1.0f64.to_bits();
+        cpu.fpr[2] = 3.0f64.to_bits();
+        cpu.gpr[2] = frm as u64;
+        run_rv_insns(
+            &mut cpu,
+            &[csrrw(0, 2, CSR_FRM), fdiv_d(3, 1, 2, RM_DYN)],
+        );
+        assert_eq!(cpu.fflags, NX);
+        cpu.fpr[3]
+    };
+    assert_eq!(third(0), 0x3fd5_5555_5555_5555);
+    assert_eq!(third(RM_RTZ), 0x3fd5_5555_5555_5555);
+    assert_eq!(third(RM_RUP), 0x3fd5_5555_5555_5556);
+
+    // A static rm overrides frm.
+    let mut cpu = RiscvCpu::new();
+    cpu.frm = RM_RTZ as u64;
+    cpu.fpr[1] = 1.0f64.to_bits();
+    cpu.fpr[2] = 3.0f64.to_bits();
+    run_rv(&mut cpu, fdiv_d(3, 1, 2, RM_RUP));
+    assert_eq!(cpu.fpr[3], 0x3fd5_5555_5555_5556);
+}
+
+#[test]
+fn test_fcvt_wu_small_negative_is_inexact() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = nanbox((-0.5f32).to_bits());
+    run_rv(&mut cpu, fcvt_wu_s(1, 1, RM_RTZ));
+    assert_eq!(cpu.gpr[1], 0);
+    assert_eq!(cpu.fflags, NX);
+
+    cpu.fflags = 0;
+    cpu.fpr[1] = nanbox((-1.0f32).to_bits());
+    run_rv(&mut cpu, fcvt_wu_s(1, 1, RM_RTZ));
+    assert_eq!(cpu.gpr[1], 0);
+    assert_eq!(cpu.fflags, NV);
+}
+
+/// fcvt.wu.s sign-extends its word like every .w result.
+#[test]
+fn test_fcvt_wu_s_sign_extends() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = nanbox(3e9f32.to_bits());
+    run_rv(&mut cpu, fcvt_wu_s(1, 1, RM_RTZ));
+    assert_eq!(cpu.gpr[1], 3_000_000_000u32 as i32 as u64);
+    assert_eq!(cpu.fflags, 0);
+}
+
+#[test]
+fn test_fcvt_d_s_snan() {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = nanbox(0x7f80_0001);
+    run_rv(&mut cpu, fcvt_d_s(2, 1));
+    assert_eq!(cpu.fpr[2], 0x7ff8_0000_0000_0000);
+    assert_eq!(cpu.fflags, NV);
+}
+
 // ── Extension profile tests ─────────────────────────────────
 
 /// Helper: RV64I-only config (no M/A/F/D/C).
 fn cfg_rv64i_only() -> RiscvCfg {
     RiscvCfg {
         misa: MisaExt::I,
+        ext_zicbom: false,
+        ext_zicbop: false,
+        ext_zicboz: false,
         ext_zicsr: false,
         ext_zifencei: false,
+        ext_zihintntl: false,
         ext_zba: false,
         ext_zbb: false,
         ext_zbc: false,
         ext_zbs: false,
+        strict_align: false,
+        cbo_block_size: 64,
     }
 }
 
@@ -1574,6 +1821,20 @@ fn test_ext_fadd_s_rejected_without_f() {
     assert_eq!(exit, EXCP_UNDEF as usize);
 }
 
+/// D gates separately from F: with only D off, `fadd.d`
+/// traps and `fadd.s` still runs.
+#[test]
+fn test_ext_fadd_d_rejected_without_d() {
+    let mut cfg = RiscvCfg::default();
+    assert!(cfg.set_ext("d", false));
+    let mut cpu = RiscvCpu::new();
+    let exit = run_rv_with_cfg(&mut cpu, fadd_d(1, 2, 3, 0), cfg);
+    assert_eq!(exit, EXCP_UNDEF as usize);
+    assert_eq!(cpu.pc, 0);
+    let mut cpu = RiscvCpu::new();
+    assert_eq!(run_rv_with_cfg(&mut cpu, fadd_s(1, 2, 3, 0), cfg), 0);
+}
+
 #[test]
 fn test_ext_c_insn_rejected_without_c() {
     let mut cpu = RiscvCpu::new();
@@ -1585,3 +1846,137 @@ fn test_ext_c_insn_rejected_without_c() {
     let exit = run_rvc_with_cfg(&mut cpu, c_li(1, 42), cfg);
     assert_eq!(exit, EXCP_UNDEF as usize);
 }
+
+/// The UNDEF exit leaves pc at the rejected instruction, with
+/// the ones before it retired.
+#[test]
+fn test_ext_mul_undef_pc_without_m() {
+    let mut cpu = RiscvCpu::new();
+    let insns = [addi(2, 0, 6), mul(1, 2, 2), addi(3, 0, 1)];
+    let exit = run_rv_insns_with_cfg(&mut cpu, &insns, cfg_rv64i_only());
+    assert_eq!(exit, EXCP_UNDEF as usize);
+    assert_eq!(cpu.pc, 4);
+    assert_eq!((cpu.gpr[1], cpu.gpr[2], cpu.gpr[3]), (0, 6, 0));
+}
+
+#[test]
+fn test_ext_c_addi_undef_pc_without_c() {
+    let mut cpu = RiscvCpu::new();
+    let mut code = addi(1, 0, 5).to_le_bytes().to_vec();
+    code.extend_from_slice(&c_addi(1, 3).to_le_bytes());
+    let exit = run_rv_bytes_with_cfg(&mut cpu, &code, cfg_rv64i_only());
+    assert_eq!(exit, EXCP_UNDEF as usize);
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(cpu.gpr[1], 5);
+
+    // The same bytes with C on run to the end.
+    let mut cpu = RiscvCpu::new();
+    assert_eq!(run_rv_bytes(&mut cpu, &code), 0);
+    assert_eq!(cpu.gpr[1], 8);
+}
+
+// ── Single-step translation ──────────────────────────────────
+
+/// Translate `insns` at pc 0 under TB `flags`, returning the IR.
+fn translate_ir(insns: &[u32], flags: u32) -> Context {
+    let code: Vec<u8> = insns.iter().flat_map(|i| i.to_le_bytes()).collect();
+    let backend = X86_64CodeGen::new();
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let mut disas =
+        RiscvDisasContext::new(0, code.as_ptr(), RiscvCfg::default());
+    disas.base.max_insns = insns.len() as u32;
+    disas.base.set_tb_flags(flags);
+    translator_loop::<RiscvTranslator>(&mut disas, &mut ctx);
+    ctx
+}
+
+fn count_ops(ctx: &Context, opc: Opcode) -> usize {
+    ctx.ops().iter().filter(|op| op.opc == opc).count()
+}
+
+/// The `exit_tb` values emitted in `ctx`.
+fn exit_codes(ctx: &Context) -> Vec<u32> {
+    ctx.ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::ExitTb)
+        .map(|op| op.cargs()[0].0)
+        .collect()
+}
+
+/// Every x0 read in a TB shares one zero constant.
+#[test]
+fn test_x0_reads_share_one_const() {
+    let insns: Vec<u32> = (1..=8)
+        .map(|rd| add(rd, 0, 0))
+        .chain((9..=16).map(|rd| xor(rd, 0, rd - 8)))
+        .collect();
+    let ctx = translate_ir(&insns, 0);
+    let zeros = (0..ctx.nb_temps())
+        .map(TempIdx)
+        .filter(|&t| ctx.temp_const_val(t) == Some(0))
+        .filter(|&t| ctx.temp_type(t) == Type::I64)
+        .count();
+    assert_eq!(zeros, 1);
+
+    let mut cpu = RiscvCpu::new();
+    for r in 1..=16 {
+        cpu.gpr[r] = 100 + r as u64;
+    }
+    run_rv_insns(&mut cpu, &insns);
+    for r in 1..=8 {
+        assert_eq!(cpu.gpr[r], 0, "x{r}");
+        assert_eq!(cpu.gpr[r + 8], 0, "x{}", r + 8);
+    }
+}
+
+/// Constants moved into the `pc` global in `ctx`.
+fn pc_stores(ctx: &Context) -> Vec<u64> {
+    ctx.ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::Mov)
+        .filter(|op| ctx.temp(op.args[0]).name == Some("pc"))
+        .map(|op| ctx.temp(op.args[1]))
+        .filter(|t| t.is_const())
+        .map(|t| t.val)
+        .collect()
+}
+
+#[test]
+fn test_single_step_straight_line() {
+    let insns = [addi(1, 0, 1), addi(2, 1, 2)];
+    let normal = translate_ir(&insns, 0);
+    assert_eq!(count_ops(&normal, Opcode::InsnStart), 2);
+    assert_eq!(count_ops(&normal, Opcode::GotoTb), 1);
+
+    let step = translate_ir(&insns, TB_FLAG_SINGLE_STEP);
+    assert_eq!(count_ops(&step, Opcode::InsnStart), 1);
+    assert_eq!(count_ops(&step, Opcode::GotoTb), 0);
+    assert_eq!(exit_codes(&step), vec![TB_EXIT_NOCHAIN]);
+    assert_eq!(pc_stores(&step), vec![4]);
+}
+
+#[test]
+fn test_single_step_branch_does_not_chain() {
+    let normal = translate_ir(&[beq(0, 0, 8)], 0);
+    assert_eq!(count_ops(&normal, Opcode::GotoTb), 2);
+
+    let step = translate_ir(&[beq(0, 0, 8)], TB_FLAG_SINGLE_STEP);
+    assert_eq!(count_ops(&step, Opcode::GotoTb), 0);
+    assert_eq!(exit_codes(&step), vec![TB_EXIT_NOCHAIN; 2]);
+    assert_eq!(pc_stores(&step), vec![4, 8]);
+}
+
+#[test]
+fn test_cfg_isa_string() {
+    assert_eq!(
+        RiscvCfg::default().isa_string(),
+        "rv64imafdc_zicbom_zicbop_zicboz_zicsr_zifencei_zihintntl"
+    );
+    let cfg = RiscvCfg {
+        misa: MisaExt::I.union(MisaExt::M),
+        ext_zba: true,
+        ..cfg_rv64i_only()
+    };
+    assert_eq!(cfg.isa_string(), "rv64im_zba");
+}
@@ -0,0 +1,125 @@
+//! High-half multiplies against i128/u128 reference math, and
+//! the IR they translate to.
+
+use tcg_backend::translate::analyze;
+use tcg_core::{Context, Opcode};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+
+use super::{count_ops, mulh, mulhsu, mulhu, run_rv, translate_ir};
+
+const VALUES: [u64; 7] = [
+    0,
+    1,
+    u64::MAX, // -1
+    i64::MIN as u64,
+    i64::MAX as u64,
+    u64::MAX,
+    0x8000_0000_0000_0001,
+];
+
+/// Run `insn(3, 1, 2)` with x1 = `a`, x2 = `b`; returns x3.
+fn mul_high(insn: fn(u32, u32, u32) -> u32, a: u64, b: u64) -> u64 {
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = a;
+    cpu.gpr[2] = b;
+    run_rv(&mut cpu, insn(3, 1, 2));
+    assert_eq!((cpu.gpr[1], cpu.gpr[2]), (a, b));
+    cpu.gpr[3]
+}
+
+fn hi(v: i128) -> u64 {
+    (v >> 64) as u64
+}
+
+#[test]
+fn test_mulh_boundaries() {
+    for a in VALUES {
+        for b in VALUES {
+            let want = hi(a as i64 as i128 * b as i64 as i128);
+            assert_eq!(mul_high(mulh, a, b), want, "mulh {a:#x}, {b:#x}");
+        }
+    }
+}
+
+#[test]
+fn test_mulhu_boundaries() {
+    for a in VALUES {
+        for b in VALUES {
+            let want = ((a as u128 * b as u128) >> 64) as u64;
+            assert_eq!(mul_high(mulhu, a, b), want, "mulhu {a:#x}, {b:#x}");
+        }
+    }
+}
+
+#[test]
+fn test_mulhsu_boundaries() {
+    for a in VALUES {
+        for b in VALUES {
+            let want = hi(a as i64 as i128 * b as i128);
+            assert_eq!(mul_high(mulhsu, a, b), want, "mulhsu {a:#x}, {b:#x}");
+        }
+    }
+}
+
+/// Both operands in one register, and rd aliasing a source.
+#[test]
+fn test_mulh_register_aliasing() {
+    for v in VALUES {
+        for (insn, want) in [
+            (
+                mulh as fn(u32, u32, u32) -> u32,
+                hi(v as i64 as i128 * v as i64 as i128),
+            ),
+            (mulhu, ((v as u128 * v as u128) >> 64) as u64),
+            (mulhsu, hi(v as i64 as i128 * v as i128)),
+        ] {
+            let mut cpu = RiscvCpu::new();
+            cpu.gpr[1] = v;
+            run_rv(&mut cpu, insn(1, 1, 1));
+            assert_eq!(cpu.gpr[1], want, "{:#x} with {v:#x}", insn(1, 1, 1));
+        }
+    }
+}
+
+/// Ops left after liveness, and whether the low half of the
+/// only double-width multiply is dead.
+fn live_mul(insn: u32) -> (Vec<Opcode>, bool) {
+    let mut ctx: Context = translate_ir(&[insn], 0);
+    analyze(&mut ctx);
+    let arith: Vec<Opcode> = ctx
+        .ops()
+        .iter()
+        .map(|op| op.opc)
+        .filter(|&opc| {
+            !matches!(
+                opc,
+                Opcode::Nop
+                    | Opcode::InsnStart
+                    | Opcode::Mov
+                    | Opcode::Ld
+                    | Opcode::St
+                    | Opcode::ExitTb
+                    | Opcode::GotoTb
+                    // Instruction count.
+                    | Opcode::Add
+            )
+        })
+        .collect();
+    let mul = ctx.ops().iter().find(|op| {
+        matches!(op.opc, Opcode::MulS2 | Opcode::MulU2 | Opcode::MulSU2)
+    });
+    (arith, mul.unwrap().life.is_dead(0))
+}
+
+/// One double-width multiply each, with only the high half
+/// consumed: no sign fixup ops and no register kept for the
+/// low half.
+#[test]
+fn test_mulh_family_single_op() {
+    assert_eq!(live_mul(mulh(3, 1, 2)), (vec![Opcode::MulS2], true));
+    assert_eq!(live_mul(mulhu(3, 1, 2)), (vec![Opcode::MulU2], true));
+    assert_eq!(live_mul(mulhsu(3, 1, 2)), (vec![Opcode::MulSU2], true));
+    let ctx = translate_ir(&[mulhsu(3, 1, 2)], 0);
+    assert_eq!(count_ops(&ctx, Opcode::MulU2), 0);
+    assert_eq!(count_ops(&ctx, Opcode::Sar), 0);
+}
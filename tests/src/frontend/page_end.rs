@@ -0,0 +1,100 @@
+//! TBs stop at `pc_page_end`: code placed right before an
+//! inaccessible page translates without touching it.
+
+use tcg_backend::HostCodeGen;
+use tcg_backend::X86_64CodeGen;
+use tcg_core::tb::{DisasJumpType, TranslationInfo};
+use tcg_core::Context;
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
+use tcg_frontend::translator_loop;
+
+use super::{addi, c_addi};
+
+/// A readable page followed by a `PROT_NONE` one.
+struct GuardedPage {
+    base: *mut u8,
+    size: usize,
+}
+
+impl GuardedPage {
+    fn new() -> Self {
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                2 * size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        let guard = unsafe { base.cast::<u8>().add(size) };
+        let rc = unsafe { libc::mprotect(guard.cast(), size, libc::PROT_NONE) };
+        assert_eq!(rc, 0);
+        GuardedPage {
+            base: base.cast(),
+            size,
+        }
+    }
+
+    /// Copy `code` to the end of the readable page and return
+    /// its guest pc (the page starts at pc 0).
+    fn place(&self, code: &[u8]) -> u64 {
+        let off = self.size - code.len();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                code.as_ptr(),
+                self.base.add(off),
+                code.len(),
+            );
+        }
+        off as u64
+    }
+
+    fn translate(&self, pc: u64) -> TranslationInfo {
+        let backend = X86_64CodeGen::new();
+        let mut ctx = Context::new();
+        backend.init_context(&mut ctx);
+        let mut d = RiscvDisasContext::new(pc, self.base, RiscvCfg::default());
+        d.base.pc_page_end = self.size as u64;
+        translator_loop::<RiscvTranslator>(&mut d, &mut ctx)
+    }
+}
+
+impl Drop for GuardedPage {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.cast(), 2 * self.size) };
+    }
+}
+
+#[test]
+fn test_tb_stops_at_page_end() {
+    let page = GuardedPage::new();
+    let code: Vec<u8> = [addi(1, 1, 1), addi(2, 2, 2), addi(3, 3, 3)]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+    let pc = page.place(&code);
+    let info = page.translate(pc);
+    assert_eq!(info.guest_insns, 3);
+    assert_eq!(info.next_pc, page.size as u64);
+    assert_eq!(info.is_jmp, DisasJumpType::TooMany);
+}
+
+/// A 32-bit instruction whose upper half lies past the page
+/// end is left for the next TB, unread.
+#[test]
+fn test_tb_stops_before_insn_crossing_page_end() {
+    let page = GuardedPage::new();
+    let mut code = addi(1, 1, 1).to_le_bytes().to_vec();
+    code.extend(c_addi(2, 1).to_le_bytes());
+    code.extend(&addi(3, 3, 3).to_le_bytes()[..2]);
+    let pc = page.place(&code);
+    let info = page.translate(pc);
+    assert_eq!(info.guest_insns, 2);
+    assert_eq!(info.next_pc, page.size as u64 - 2);
+    assert_eq!(info.is_jmp, DisasJumpType::TooMany);
+}
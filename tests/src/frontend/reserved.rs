@@ -0,0 +1,102 @@
+//! Encodings the decode files mark `!reserved`, and their legal
+//! neighbours.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_UNDEF;
+
+use super::{
+    addi, fadd_d, fadd_s, fcvt_s_w, fence, fmadd_s, fsqrt_d, lr_w, nanbox,
+    run_rv_insns, rv_r, OP_AMO,
+};
+
+type FpOp = (&'static str, fn(u32) -> u32, u64);
+
+/// One FP encoder per `rm`-carrying format, with the result it
+/// leaves in f3 for f1 = 1.0, f2 = 2.0 and x1 = 4.
+fn fp_ops() -> Vec<FpOp> {
+    vec![
+        ("fadd.s", |rm| fadd_s(3, 1, 2, rm), nanbox(0x4040_0000)),
+        ("fmadd.s", |rm| fmadd_s(3, 1, 2, 1, rm), nanbox(0x4040_0000)),
+        ("fcvt.s.w", |rm| fcvt_s_w(3, 1, rm), nanbox(0x4080_0000)),
+        ("fadd.d", |rm| fadd_d(3, 4, 5, rm), 0x4008_0000_0000_0000),
+        ("fsqrt.d", |rm| fsqrt_d(3, 6, rm), 0x4000_0000_0000_0000),
+    ]
+}
+
+fn fp_cpu() -> RiscvCpu {
+    let mut cpu = RiscvCpu::new();
+    cpu.fpr[1] = nanbox(0x3f80_0000);
+    cpu.fpr[2] = nanbox(0x4000_0000);
+    cpu.fpr[4] = 0x3ff0_0000_0000_0000;
+    cpu.fpr[5] = 0x4000_0000_0000_0000;
+    cpu.fpr[6] = 0x4010_0000_0000_0000;
+    cpu.gpr[1] = 4;
+    cpu
+}
+
+#[test]
+fn test_fp_reserved_rm_undef() {
+    for (name, op, _) in fp_ops() {
+        for rm in [5, 6] {
+            let mut cpu = fp_cpu();
+            let exit = run_rv_insns(&mut cpu, &[op(rm), addi(9, 0, 1)]);
+            assert_eq!(exit, EXCP_UNDEF as usize, "{name} rm={rm}");
+            assert_eq!(cpu.fpr[3], 0, "{name} rm={rm}");
+            assert_eq!(cpu.gpr[9], 0, "{name} rm={rm}");
+            assert_eq!(cpu.fflags, 0, "{name} rm={rm}");
+        }
+    }
+}
+
+#[test]
+fn test_fp_legal_rm() {
+    for (name, op, want) in fp_ops() {
+        for rm in [0, 1, 2, 3, 4, 7] {
+            let mut cpu = fp_cpu();
+            let exit = run_rv_insns(&mut cpu, &[op(rm), addi(9, 0, 1)]);
+            assert_ne!(exit, EXCP_UNDEF as usize, "{name} rm={rm}");
+            assert_eq!(cpu.fpr[3], want, "{name} rm={rm}");
+            assert_eq!(cpu.gpr[9], 1, "{name} rm={rm}");
+        }
+    }
+}
+
+/// LR fixes rs2 to zero in its encoding.
+#[test]
+fn test_lr_rs2_nonzero_undef() {
+    let mut mem = [0u8; 0x20];
+    mem[0x10..0x14].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    for rs2 in [0, 1, 0x10] {
+        let mut cpu = RiscvCpu::new();
+        cpu.guest_base = mem.as_mut_ptr() as u64;
+        cpu.gpr[2] = 0x10;
+        let insn = lr_w(1, 2) | rs2 << 20;
+        let exit = run_rv_insns(&mut cpu, &[insn]);
+        if rs2 == 0 {
+            assert_ne!(exit, EXCP_UNDEF as usize);
+            assert_eq!(cpu.gpr[1], 0x1234_5678);
+        } else {
+            assert_eq!(exit, EXCP_UNDEF as usize, "rs2={rs2}");
+            assert_eq!(cpu.gpr[1], 0, "rs2={rs2}");
+        }
+    }
+    // SC differs from LR only in funct5; rs2 is its source.
+    let sc_w = rv_r(0b00011 << 2, 3, 2, 0b010, 1, OP_AMO);
+    let mut cpu = RiscvCpu::new();
+    cpu.guest_base = mem.as_mut_ptr() as u64;
+    cpu.gpr[2] = 0x10;
+    assert_ne!(run_rv_insns(&mut cpu, &[sc_w]), EXCP_UNDEF as usize);
+}
+
+/// The base ISA runs reserved FENCE `fm` values as a plain
+/// fence rather than trapping, so fence is not annotated.
+#[test]
+fn test_fence_reserved_fm_is_fence() {
+    for fm in [0b0000, 0b0101, 0b1000, 0b1111] {
+        let mut cpu = RiscvCpu::new();
+        let code = [fence() | fm << 28, addi(9, 0, 1)];
+        let exit = run_rv_insns(&mut cpu, &code);
+        assert_ne!(exit, EXCP_UNDEF as usize, "fm={fm:#06b}");
+        assert_eq!(cpu.gpr[9], 1, "fm={fm:#06b}");
+    }
+}
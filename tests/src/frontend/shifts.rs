@@ -0,0 +1,125 @@
+//! Shift-count semantics: W forms use the low 5 bits of rs2,
+//! 64-bit forms the low 6; W immediates with shamt[5] set are
+//! reserved; shifts and W ops writing x0 are HINTs.
+
+use tcg_core::{Context, Opcode};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_UNDEF;
+
+use super::{
+    add, addi, addiw, run_rv, run_rv_insns, sll, slli, slliw, sllw, sra, sraiw,
+    sraw, srl, srliw, srlw, translate_ir,
+};
+
+const VALUES: [u64; 5] = [
+    0,
+    1,
+    0x8000_0000,
+    0xdead_beef_1234_5678,
+    0xffff_ffff_ffff_fffe,
+];
+
+const COUNTS: [u64; 6] = [0, 1, 31, 33, 0xffff_ffe3, u64::MAX];
+
+fn sext32(v: u32) -> u64 {
+    v as i32 as i64 as u64
+}
+
+/// Run `insn(3, 1, 2)` with x1 = `v`, x2 = `sh`; returns x3.
+fn shift(insn: fn(u32, u32, u32) -> u32, v: u64, sh: u64) -> u64 {
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = v;
+    cpu.gpr[2] = sh;
+    run_rv(&mut cpu, insn(3, 1, 2));
+    cpu.gpr[3]
+}
+
+#[test]
+fn test_shiftw_uses_low_5_bits() {
+    // sllw with rs2 = 33 shifts by 1.
+    assert_eq!(shift(sllw, 0x4000_0001, 33), 0xffff_ffff_8000_0002);
+    // sraw with rs2 = 0xFFFF_FFE3 shifts by 3.
+    assert_eq!(shift(sraw, 0x8000_0000, 0xffff_ffe3), 0xffff_ffff_f000_0000);
+    for v in VALUES {
+        for sh in COUNTS {
+            let (x, n) = (v as u32, sh as u32 & 31);
+            let ctx = format!("{v:#x}, {sh:#x}");
+            assert_eq!(shift(sllw, v, sh), sext32(x << n), "sllw {ctx}");
+            assert_eq!(shift(srlw, v, sh), sext32(x >> n), "srlw {ctx}");
+            let want = ((x as i32) >> n) as u32;
+            assert_eq!(shift(sraw, v, sh), sext32(want), "sraw {ctx}");
+        }
+    }
+}
+
+#[test]
+fn test_shift_uses_low_6_bits() {
+    assert_eq!(shift(sll, 1, 65), 2);
+    for v in VALUES {
+        for sh in COUNTS {
+            let n = sh as u32 & 63;
+            let ctx = format!("{v:#x}, {sh:#x}");
+            assert_eq!(shift(sll, v, sh), v << n, "sll {ctx}");
+            assert_eq!(shift(srl, v, sh), v >> n, "srl {ctx}");
+            let want = ((v as i64) >> n) as u64;
+            assert_eq!(shift(sra, v, sh), want, "sra {ctx}");
+        }
+    }
+}
+
+/// Constant masks applied by `And` ops in `ctx`.
+fn and_masks(ctx: &Context) -> Vec<u64> {
+    ctx.ops()
+        .iter()
+        .filter(|op| op.opc == Opcode::And)
+        .map(|op| ctx.temp(op.args[2]))
+        .filter(|t| t.is_const())
+        .map(|t| t.val)
+        .collect()
+}
+
+#[test]
+fn test_shift_count_masked_in_ir() {
+    for insn in [sllw, srlw, sraw] {
+        assert_eq!(and_masks(&translate_ir(&[insn(3, 1, 2)], 0)), [31]);
+    }
+    for insn in [sll, srl, sra] {
+        assert_eq!(and_masks(&translate_ir(&[insn(3, 1, 2)], 0)), [63]);
+    }
+}
+
+#[test]
+fn test_shiftw_imm_shamt5_reserved() {
+    for insn in [slliw, srliw, sraiw] {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = 1;
+        let exit = run_rv(&mut cpu, insn(3, 1, 32));
+        assert_eq!(exit, EXCP_UNDEF as usize, "{:#x}", insn(3, 1, 32));
+        assert_eq!(cpu.gpr[3], 0);
+        // shamt = 31 is the largest valid count.
+        let exit = run_rv(&mut cpu, insn(3, 1, 31));
+        assert_ne!(exit, EXCP_UNDEF as usize);
+    }
+}
+
+#[test]
+fn test_hints_do_not_trap() {
+    let hints = [
+        addiw(0, 1, 5),
+        slliw(0, 1, 3),
+        sllw(0, 1, 2),
+        slli(0, 1, 40),
+        add(0, 1, 2),
+    ];
+    for hint in hints {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = 0x1234;
+        cpu.gpr[2] = 7;
+        // The instruction after the HINT still runs.
+        let exit = run_rv_insns(&mut cpu, &[hint, addi(4, 0, 9)]);
+        assert_ne!(exit, EXCP_UNDEF as usize, "{hint:#x}");
+        assert_eq!(cpu.gpr[0], 0, "{hint:#x}");
+        assert_eq!(cpu.gpr[4], 9, "{hint:#x}");
+        assert_eq!(cpu.pc, 8, "{hint:#x}");
+    }
+}
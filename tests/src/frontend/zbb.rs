@@ -0,0 +1,265 @@
+//! Zbb basic bit manipulation: off by default, and each form
+//! against a host reference once enabled.
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::EXCP_UNDEF;
+use tcg_frontend::riscv::ext::RiscvCfg;
+
+use super::{
+    run_rv, run_rv_with_cfg, rv_i, rv_r, OP_IMM, OP_IMM32, OP_REG, OP_REG32,
+};
+
+const VALUES: [u64; 7] = [
+    0,
+    1,
+    0x80,
+    0x8000_0000,
+    0x0000_ff00_0000_0010,
+    0xdead_beef_1234_5678,
+    u64::MAX,
+];
+
+fn cfg_zbb() -> RiscvCfg {
+    RiscvCfg {
+        ext_zbb: true,
+        ..RiscvCfg::default()
+    }
+}
+
+fn sext32(v: u32) -> u64 {
+    v as i32 as i64 as u64
+}
+
+/// Unary form with the given rs2 selector: `rd=3, rs1=1`.
+fn unary(f7: u32, sel: u32, f3: u32, op: u32) -> u32 {
+    rv_r(f7, sel, 1, f3, 3, op)
+}
+
+fn clz() -> u32 {
+    unary(0b0110000, 0b00000, 0b001, OP_IMM)
+}
+fn ctz() -> u32 {
+    unary(0b0110000, 0b00001, 0b001, OP_IMM)
+}
+fn cpop() -> u32 {
+    unary(0b0110000, 0b00010, 0b001, OP_IMM)
+}
+fn sext_b() -> u32 {
+    unary(0b0110000, 0b00100, 0b001, OP_IMM)
+}
+fn sext_h() -> u32 {
+    unary(0b0110000, 0b00101, 0b001, OP_IMM)
+}
+fn orc_b() -> u32 {
+    unary(0b0010100, 0b00111, 0b101, OP_IMM)
+}
+fn rev8() -> u32 {
+    unary(0b0110101, 0b11000, 0b101, OP_IMM)
+}
+fn zext_h() -> u32 {
+    unary(0b0000100, 0b00000, 0b100, OP_REG32)
+}
+fn clzw() -> u32 {
+    unary(0b0110000, 0b00000, 0b001, OP_IMM32)
+}
+fn ctzw() -> u32 {
+    unary(0b0110000, 0b00001, 0b001, OP_IMM32)
+}
+fn cpopw() -> u32 {
+    unary(0b0110000, 0b00010, 0b001, OP_IMM32)
+}
+
+/// Binary form: `rd=3, rs1=1, rs2=2`.
+fn binary(f7: u32, f3: u32, op: u32) -> u32 {
+    rv_r(f7, 2, 1, f3, 3, op)
+}
+
+fn andn() -> u32 {
+    binary(0b0100000, 0b111, OP_REG)
+}
+fn orn() -> u32 {
+    binary(0b0100000, 0b110, OP_REG)
+}
+fn xnor() -> u32 {
+    binary(0b0100000, 0b100, OP_REG)
+}
+fn min() -> u32 {
+    binary(0b0000101, 0b100, OP_REG)
+}
+fn minu() -> u32 {
+    binary(0b0000101, 0b101, OP_REG)
+}
+fn max() -> u32 {
+    binary(0b0000101, 0b110, OP_REG)
+}
+fn maxu() -> u32 {
+    binary(0b0000101, 0b111, OP_REG)
+}
+fn rol() -> u32 {
+    binary(0b0110000, 0b001, OP_REG)
+}
+fn ror() -> u32 {
+    binary(0b0110000, 0b101, OP_REG)
+}
+fn rolw() -> u32 {
+    binary(0b0110000, 0b001, OP_REG32)
+}
+fn rorw() -> u32 {
+    binary(0b0110000, 0b101, OP_REG32)
+}
+
+fn rori(shamt: u32) -> u32 {
+    rv_i((0b011000 << 6 | shamt) as i32, 1, 0b101, 3, OP_IMM)
+}
+fn roriw(shamt: u32) -> u32 {
+    rv_i((0b0110000 << 5 | shamt) as i32, 1, 0b101, 3, OP_IMM32)
+}
+
+/// Run `insn` with Zbb on, x1 = `a`, x2 = `b`; returns x3.
+fn exec(insn: u32, a: u64, b: u64) -> u64 {
+    let mut cpu = RiscvCpu::new();
+    cpu.gpr[1] = a;
+    cpu.gpr[2] = b;
+    assert_eq!(run_rv_with_cfg(&mut cpu, insn, cfg_zbb()), 0);
+    cpu.gpr[3]
+}
+
+fn check_unary(name: &str, insn: u32, reference: fn(u64) -> u64) {
+    for a in VALUES {
+        assert_eq!(exec(insn, a, 0), reference(a), "{name} {a:#x}");
+    }
+}
+
+fn check_binary(name: &str, insn: u32, reference: fn(u64, u64) -> u64) {
+    for a in VALUES {
+        for b in VALUES.iter().chain(&[63, 64, 0xffff_ffe1]) {
+            let b = *b;
+            assert_eq!(
+                exec(insn, a, b),
+                reference(a, b),
+                "{name} {a:#x}, {b:#x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_clz() {
+    check_unary("clz", clz(), |a| a.leading_zeros() as u64);
+    assert_eq!(exec(clz(), 0, 0), 64);
+    assert_eq!(exec(clz(), 1, 0), 63);
+    check_unary("clzw", clzw(), |a| (a as u32).leading_zeros() as u64);
+    assert_eq!(exec(clzw(), 0xffff_ffff_0000_0000, 0), 32);
+}
+
+#[test]
+fn test_ctz_cpop() {
+    check_unary("ctz", ctz(), |a| a.trailing_zeros() as u64);
+    check_unary("ctzw", ctzw(), |a| (a as u32).trailing_zeros() as u64);
+    check_unary("cpop", cpop(), |a| a.count_ones() as u64);
+    check_unary("cpopw", cpopw(), |a| (a as u32).count_ones() as u64);
+}
+
+#[test]
+fn test_max() {
+    check_binary("max", max(), |a, b| (a as i64).max(b as i64) as u64);
+    check_binary("maxu", maxu(), |a, b| a.max(b));
+    check_binary("min", min(), |a, b| (a as i64).min(b as i64) as u64);
+    check_binary("minu", minu(), |a, b| a.min(b));
+    assert_eq!(exec(max(), u64::MAX, 1), 1);
+    assert_eq!(exec(maxu(), u64::MAX, 1), u64::MAX);
+}
+
+#[test]
+fn test_andn() {
+    check_binary("andn", andn(), |a, b| a & !b);
+    check_binary("orn", orn(), |a, b| a | !b);
+    check_binary("xnor", xnor(), |a, b| !(a ^ b));
+    assert_eq!(exec(andn(), 0xff, 0x0f), 0xf0);
+}
+
+#[test]
+fn test_rev8() {
+    check_unary("rev8", rev8(), u64::swap_bytes);
+    assert_eq!(
+        exec(rev8(), 0x0102_0304_0506_0708, 0),
+        0x0807_0605_0403_0201
+    );
+}
+
+#[test]
+fn test_orc_b() {
+    check_unary("orc.b", orc_b(), |a| {
+        let bytes = a.to_le_bytes().map(|b| if b != 0 { 0xff } else { 0 });
+        u64::from_le_bytes(bytes)
+    });
+    assert_eq!(
+        exec(orc_b(), 0x0080_0001_0000_7f00, 0),
+        0x00ff_00ff_0000_ff00
+    );
+}
+
+#[test]
+fn test_sext_zext() {
+    check_unary("sext.b", sext_b(), |a| a as i8 as i64 as u64);
+    check_unary("sext.h", sext_h(), |a| a as i16 as i64 as u64);
+    check_unary("zext.h", zext_h(), |a| a as u16 as u64);
+}
+
+#[test]
+fn test_rotates() {
+    check_binary("rol", rol(), |a, b| a.rotate_left(b as u32 & 63));
+    check_binary("ror", ror(), |a, b| a.rotate_right(b as u32 & 63));
+    check_binary("rolw", rolw(), |a, b| {
+        sext32((a as u32).rotate_left(b as u32 & 31))
+    });
+    check_binary("rorw", rorw(), |a, b| {
+        sext32((a as u32).rotate_right(b as u32 & 31))
+    });
+    for sh in [0, 1, 31, 32, 63] {
+        let a = 0xdead_beef_1234_5678u64;
+        assert_eq!(exec(rori(sh), a, 0), a.rotate_right(sh), "rori {sh}");
+        if sh < 32 {
+            let want = sext32((a as u32).rotate_right(sh));
+            assert_eq!(exec(roriw(sh), a, 0), want, "roriw {sh}");
+        }
+    }
+}
+
+/// Zbb is off by default: every form is an illegal instruction
+/// and leaves rd alone.
+#[test]
+fn test_zbb_off_by_default() {
+    for insn in [
+        clz(),
+        ctz(),
+        cpop(),
+        sext_b(),
+        sext_h(),
+        orc_b(),
+        rev8(),
+        zext_h(),
+        clzw(),
+        ctzw(),
+        cpopw(),
+        andn(),
+        orn(),
+        xnor(),
+        min(),
+        minu(),
+        max(),
+        maxu(),
+        rol(),
+        ror(),
+        rolw(),
+        rorw(),
+        rori(3),
+        roriw(3),
+    ] {
+        let mut cpu = RiscvCpu::new();
+        cpu.gpr[1] = 0x1234;
+        cpu.gpr[3] = 77;
+        assert_eq!(run_rv(&mut cpu, insn), EXCP_UNDEF as usize, "{insn:#010x}");
+        assert_eq!(cpu.gpr[3], 77, "{insn:#010x}");
+    }
+}
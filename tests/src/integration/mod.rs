@@ -1,11 +1,15 @@
 use tcg_backend::code_buffer::CodeBuffer;
-use tcg_backend::translate::translate_and_execute;
+use tcg_backend::regalloc::RegAllocMode;
+use tcg_backend::translate::{
+    translate_and_execute, translate_and_execute_with,
+};
 use tcg_backend::HostCodeGen;
-use tcg_backend::X86_64CodeGen;
+use tcg_backend::NativeCodeGen;
 use tcg_core::types::Type;
 use tcg_core::{Context, Op, Opcode, TempIdx};
 
 /// Minimal RISC-V CPU state for testing.
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C)]
 struct RiscvCpuState {
     regs: [u64; 32], // x0-x31, offset 0..256
@@ -22,6 +26,7 @@ impl RiscvCpuState {
 }
 
 /// RISC-V CPU state with a small memory window for load/store tests.
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C)]
 struct RiscvCpuStateMem {
     regs: [u64; 32],
@@ -39,12 +44,11 @@ impl RiscvCpuStateMem {
     }
 }
 
-/// Register globals for RISC-V x0-x31 and pc.
+/// Register globals for RISC-V x0-x31 and pc, with env in the
+/// register the backend's `init_context` chose.
 /// Returns (env_temp, reg_temps[0..32], pc_temp).
 fn setup_riscv_globals(ctx: &mut Context) -> (TempIdx, [TempIdx; 32], TempIdx) {
-    // env pointer is a fixed temp in RBP
-    let env =
-        ctx.new_fixed(Type::I64, tcg_backend::x86_64::Reg::Rbp as u8, "env");
+    let env = ctx.new_fixed(Type::I64, ctx.env_reg, "env");
 
     // x0-x31 as globals backed by RiscvCpuState.regs
     let mut reg_temps = [TempIdx(0); 32];
@@ -68,29 +72,42 @@ fn setup_riscv_globals(ctx: &mut Context) -> (TempIdx, [TempIdx; 32], TempIdx) {
     (env, reg_temps, pc)
 }
 
+/// Run the TB `build` emits on the host's backend with each
+/// register allocator, checking they leave the same state and
+/// exit code.
 fn run_riscv_tb<S, F>(cpu: &mut S, build: F) -> usize
 where
-    F: FnOnce(&mut Context, TempIdx, [TempIdx; 32], TempIdx),
+    S: Clone + PartialEq + std::fmt::Debug,
+    F: Fn(&mut Context, TempIdx, [TempIdx; 32], TempIdx),
 {
-    let mut backend = X86_64CodeGen::new();
-    let mut buf = CodeBuffer::new(4096).unwrap();
-    backend.emit_prologue(&mut buf);
-    backend.emit_epilogue(&mut buf);
-
-    let mut ctx = Context::new();
-    backend.init_context(&mut ctx);
-    let (env, regs, pc) = setup_riscv_globals(&mut ctx);
-
-    build(&mut ctx, env, regs, pc);
-
-    unsafe {
-        translate_and_execute(
-            &mut ctx,
-            &backend,
-            &mut buf,
-            cpu as *mut S as *mut u8,
-        )
-    }
+    let mut backend = NativeCodeGen::new();
+    let mut run = |mode, cpu: &mut S| {
+        let mut buf = CodeBuffer::new(4096).unwrap();
+        backend.emit_prologue(&mut buf);
+        backend.emit_epilogue(&mut buf);
+
+        let mut ctx = Context::new();
+        backend.init_context(&mut ctx);
+        let (env, regs, pc) = setup_riscv_globals(&mut ctx);
+
+        build(&mut ctx, env, regs, pc);
+
+        unsafe {
+            translate_and_execute_with(
+                &mut ctx,
+                &backend,
+                &mut buf,
+                cpu as *mut S as *mut u8,
+                mode,
+            )
+        }
+    };
+    let mut greedy = cpu.clone();
+    let want = run(RegAllocMode::Greedy, &mut greedy);
+    let exit = run(RegAllocMode::FurthestUse, cpu);
+    assert_eq!(exit, want, "furthest-use exit differs");
+    assert_eq!(*cpu, greedy, "furthest-use state differs");
+    exit
 }
 
 fn split_u128(val: u128) -> (u64, u64) {
@@ -114,7 +131,7 @@ macro_rules! riscv_bin_case {
                 ctx.gen_insn_start(0x4000);
                 ctx.$op(Type::I64, tmp, regs[1], regs[2]);
                 ctx.gen_mov(Type::I64, regs[3], tmp);
-                ctx.gen_exit_tb(0);
+                ctx.gen_exit_tb_raw(0);
             });
 
             assert_eq!(exit_val, 0);
@@ -136,7 +153,7 @@ macro_rules! riscv_shift_case {
                 ctx.gen_insn_start(0x4100);
                 ctx.$op(Type::I64, tmp, regs[1], regs[2]);
                 ctx.gen_mov(Type::I64, regs[3], tmp);
-                ctx.gen_exit_tb(0);
+                ctx.gen_exit_tb_raw(0);
             });
 
             assert_eq!(exit_val, 0);
@@ -158,7 +175,7 @@ macro_rules! riscv_setcond_case {
                 ctx.gen_insn_start(0x4200);
                 ctx.gen_setcond(Type::I64, tmp, regs[1], regs[2], $cond);
                 ctx.gen_mov(Type::I64, regs[3], tmp);
-                ctx.gen_exit_tb(0);
+                ctx.gen_exit_tb_raw(0);
             });
 
             assert_eq!(exit_val, 0);
@@ -194,7 +211,7 @@ macro_rules! riscv_branch_case {
                 ctx.gen_mov(Type::I64, regs[3], t_taken);
 
                 ctx.gen_set_label(label_end);
-                ctx.gen_exit_tb(0);
+                ctx.gen_exit_tb_raw(0);
             });
 
             assert_eq!(exit_val, 0);
@@ -221,7 +238,7 @@ macro_rules! riscv_mem_case {
                 ctx.gen_st(Type::I64, t_val, env, mem_offset);
                 ctx.gen_ld(Type::I64, t_load, env, mem_offset);
                 ctx.gen_mov(Type::I64, regs[4], t_load);
-                ctx.gen_exit_tb(0);
+                ctx.gen_exit_tb_raw(0);
             });
 
             assert_eq!(exit_val, 0);
@@ -238,7 +255,7 @@ macro_rules! riscv_mem_case {
 /// Test: ADDI x1, x0, 42 → verify cpu.regs[1] == 42
 #[test]
 fn test_addi_x1_x0_42() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
 
     // Emit prologue + epilogue
@@ -261,7 +278,7 @@ fn test_addi_x1_x0_42() {
     ctx.gen_mov(Type::I64, regs[1], tmp);
 
     // Exit TB
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     // Execute
     let mut cpu = RiscvCpuState::new();
@@ -283,7 +300,7 @@ fn test_addi_x1_x0_42() {
 /// Test: ADD x3, x1, x2 → verify x3 == x1 + x2
 #[test]
 fn test_add_x3_x1_x2() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -296,7 +313,7 @@ fn test_add_x3_x1_x2() {
     let tmp = ctx.new_temp(Type::I64);
     ctx.gen_add(Type::I64, tmp, regs[1], regs[2]);
     ctx.gen_mov(Type::I64, regs[3], tmp);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.regs[1] = 100;
@@ -322,7 +339,7 @@ struct ShiftCpuState {
 
 #[test]
 fn test_shift_out_rcx_count_non_rcx() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -330,8 +347,7 @@ fn test_shift_out_rcx_count_non_rcx() {
     let mut ctx = Context::new();
     backend.init_context(&mut ctx);
 
-    let env =
-        ctx.new_fixed(Type::I64, tcg_backend::x86_64::Reg::Rbp as u8, "env");
+    let env = ctx.new_fixed(Type::I64, ctx.env_reg, "env");
 
     let c1 = ctx.new_const(Type::I64, 1);
     let cval = ctx.new_const(Type::I64, 0x10);
@@ -350,7 +366,7 @@ fn test_shift_out_rcx_count_non_rcx() {
     ctx.gen_shl(Type::I64, t_out, t_val, t_cnt);
     ctx.gen_add(Type::I64, t_dummy, t_hold, t_cnt);
     ctx.gen_st(Type::I64, t_out, env, 0);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = ShiftCpuState { out: 0 };
     let exit_val = unsafe {
@@ -369,7 +385,7 @@ fn test_shift_out_rcx_count_non_rcx() {
 /// Test: combine AND/XOR/OR/ADD in one TB (AND, XOR, OR, ADD).
 #[test]
 fn test_alu_mix_and_or_xor_add() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -389,7 +405,7 @@ fn test_alu_mix_and_or_xor_add() {
     ctx.gen_or(Type::I64, t_or, t_and, t_xor);
     ctx.gen_add(Type::I64, t_add, t_or, t_and);
     ctx.gen_mov(Type::I64, regs[5], t_add);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.regs[1] = 0x0F0F;
@@ -418,7 +434,7 @@ fn test_alu_mix_and_or_xor_add() {
 /// Test: MUL/ADD/NEG/NOT chain in one TB.
 #[test]
 fn test_mul_add_neg_not_chain() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -439,7 +455,7 @@ fn test_mul_add_neg_not_chain() {
     ctx.gen_not(Type::I64, t_not, t_neg);
     ctx.gen_mov(Type::I64, regs[6], t_neg);
     ctx.gen_mov(Type::I64, regs[7], t_not);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.regs[1] = 6;
@@ -468,7 +484,7 @@ fn test_mul_add_neg_not_chain() {
 /// Test: SLT/SLTU using SetCond for signed and unsigned compares.
 #[test]
 fn test_slt_sltu() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -487,7 +503,7 @@ fn test_slt_sltu() {
     ctx.gen_setcond(Type::I64, t_sltu, a, b, tcg_core::Cond::Ltu);
     ctx.gen_mov(Type::I64, regs[8], t_slt);
     ctx.gen_mov(Type::I64, regs[9], t_sltu);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
 
@@ -508,7 +524,7 @@ fn test_slt_sltu() {
 /// Test: AUIPC/LUI style sequences using pc + imm and imm << 12.
 #[test]
 fn test_auipc_lui() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -526,7 +542,7 @@ fn test_auipc_lui() {
     ctx.gen_add(Type::I64, t_auipc, pc, cimm);
     ctx.gen_mov(Type::I64, regs[10], t_auipc);
     ctx.gen_mov(Type::I64, regs[11], cimm);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.pc = 0x1000;
@@ -548,7 +564,7 @@ fn test_auipc_lui() {
 /// Test: store/load via env base, then move back to a register.
 #[test]
 fn test_load_store_64() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -568,7 +584,7 @@ fn test_load_store_64() {
     ctx.gen_st(Type::I64, t_val, env, mem_offset);
     ctx.gen_ld(Type::I64, t_load, env, mem_offset);
     ctx.gen_mov(Type::I64, regs[12], t_load);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuStateMem::new();
 
@@ -590,7 +606,7 @@ fn test_load_store_64() {
 /// Test: signed vs unsigned branches with two compare paths.
 #[test]
 fn test_signed_unsigned_branches() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -645,7 +661,7 @@ fn test_signed_unsigned_branches() {
     ctx.gen_mov(Type::I64, t3, imm3);
     ctx.gen_mov(Type::I64, regs[14], t3);
     ctx.gen_set_label(label_unsigned_end);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.regs[1] = 0xFFFF_FFFF_FFFF_FFFF;
@@ -668,7 +684,7 @@ fn test_signed_unsigned_branches() {
 /// Test: SUB x3, x1, x2
 #[test]
 fn test_sub_x3_x1_x2() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -681,7 +697,7 @@ fn test_sub_x3_x1_x2() {
     let tmp = ctx.new_temp(Type::I64);
     ctx.gen_sub(Type::I64, tmp, regs[1], regs[2]);
     ctx.gen_mov(Type::I64, regs[3], tmp);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     let mut cpu = RiscvCpuState::new();
     cpu.regs[1] = 500;
@@ -703,7 +719,7 @@ fn test_sub_x3_x1_x2() {
 /// Test: BEQ branch taken
 #[test]
 fn test_beq_taken() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -734,7 +750,7 @@ fn test_beq_taken() {
     ctx.gen_mov(Type::I64, regs[3], tmp2);
 
     ctx.gen_set_label(label_end);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     // x1 == x2 → should take equal path
     let mut cpu = RiscvCpuState::new();
@@ -757,7 +773,7 @@ fn test_beq_taken() {
 /// Test: BEQ branch not taken
 #[test]
 fn test_beq_not_taken() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -787,7 +803,7 @@ fn test_beq_not_taken() {
     ctx.gen_mov(Type::I64, regs[3], tmp2);
 
     ctx.gen_set_label(label_end);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     // x1 != x2 → should take not-equal path
     let mut cpu = RiscvCpuState::new();
@@ -879,7 +895,7 @@ fn test_exec_alu_shift_cond_mov() {
         ctx.gen_setcond(Type::I64, t_sc, regs[4], regs[5], tcg_core::Cond::Lt);
         ctx.gen_mov(Type::I64, regs[22], t_sc);
 
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     let sh = (shift & 63) as u32;
@@ -969,7 +985,7 @@ fn test_exec_mem_and_ext() {
         ctx.gen_extrl_i64_i32(t_extrl, c_i64);
         ctx.gen_st32(Type::I32, t_extrl, env, mem_offset + 48);
 
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1003,6 +1019,96 @@ fn test_exec_mem_and_ext() {
     );
 }
 
+/// Run byte and word loads, stores and extensions of
+/// `mem[0..8]` with `pad` more temps live across them, so that
+/// their operands land in successively higher host registers.
+fn run_byte_ops_under_pressure(pad: usize) -> RiscvCpuStateMem {
+    let mut cpu = RiscvCpuStateMem::new();
+    cpu.mem[0..8].copy_from_slice(&0x0123_4567_89AB_CD80u64.to_le_bytes());
+    cpu.mem[32..40].fill(0xFF);
+    for i in 0..pad {
+        cpu.regs[i + 1] = 1 << i;
+    }
+
+    run_riscv_tb(&mut cpu, |ctx, env, regs, _pc| {
+        let m = std::mem::offset_of!(RiscvCpuStateMem, mem) as i64;
+        ctx.gen_insn_start(0x5200);
+        let pads: Vec<TempIdx> = (0..pad)
+            .map(|i| {
+                let t = ctx.new_temp(Type::I64);
+                ctx.gen_ld(Type::I64, t, env, (i as i64 + 1) * 8)
+            })
+            .collect();
+
+        // Extensions first, while the temps after `v` still get
+        // registers below r8.
+        let v = ctx.new_temp(Type::I64);
+        ctx.gen_ld(Type::I64, v, env, m);
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_extract(Type::I64, t, v, 0, 8);
+        ctx.gen_mov(Type::I64, regs[23], t);
+        let v32 = ctx.new_temp(Type::I32);
+        ctx.gen_extrl_i64_i32(v32, v);
+        let t = ctx.new_temp(Type::I32);
+        ctx.gen_sextract(Type::I32, t, v32, 0, 8);
+        ctx.gen_st32(Type::I32, t, env, m + 24);
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_sextract(Type::I64, t, v, 0, 8);
+        ctx.gen_mov(Type::I64, regs[22], t);
+
+        ctx.gen_st8(Type::I64, v, env, m + 8);
+        ctx.gen_st16(Type::I64, v, env, m + 16);
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_ld8s(Type::I64, t, env, m);
+        ctx.gen_mov(Type::I64, regs[20], t);
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_ld16s(Type::I64, t, env, m);
+        ctx.gen_mov(Type::I64, regs[21], t);
+        let t = ctx.new_temp(Type::I32);
+        ctx.gen_ld16s(Type::I32, t, env, m);
+        ctx.gen_st32(Type::I32, t, env, m + 28);
+
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_ld(Type::I64, t, env, m + 32);
+        ctx.gen_deposit(Type::I64, t, t, v, 0, 8);
+        ctx.gen_st(Type::I64, t, env, m + 32);
+
+        if let Some((&first, rest)) = pads.split_first() {
+            for &p in rest {
+                ctx.gen_add(Type::I64, first, first, p);
+            }
+            ctx.gen_mov(Type::I64, regs[31], first);
+        }
+        ctx.gen_exit_tb_raw(0);
+    });
+    cpu
+}
+
+#[test]
+fn test_exec_byte_ops_in_high_regs() {
+    // Past the 13 allocatable registers the pads spill.
+    for pad in 0..=14 {
+        let cpu = run_byte_ops_under_pressure(pad);
+        let mem = &cpu.mem;
+        let word =
+            |o: usize| u32::from_le_bytes(mem[o..o + 4].try_into().unwrap());
+        assert_eq!(mem[8..10], [0x80, 0], "st8, pad {pad}");
+        assert_eq!(mem[16..19], [0x80, 0xCD, 0], "st16, pad {pad}");
+        assert_eq!(cpu.regs[20], 0xFFFF_FFFF_FFFF_FF80, "ld8s, pad {pad}");
+        assert_eq!(cpu.regs[21], 0xFFFF_FFFF_FFFF_CD80, "ld16s, pad {pad}");
+        assert_eq!(cpu.regs[22], 0xFFFF_FFFF_FFFF_FF80, "ext8s, pad {pad}");
+        assert_eq!(cpu.regs[23], 0x80, "ext8u, pad {pad}");
+        assert_eq!(word(24), 0xFFFF_FF80, "ext8s_i32, pad {pad}");
+        assert_eq!(word(28), 0xFFFF_CD80, "ld16s_i32, pad {pad}");
+        assert_eq!(
+            u64::from_le_bytes(mem[32..40].try_into().unwrap()),
+            0xFFFF_FFFF_FFFF_FF80,
+            "deposit8, pad {pad}"
+        );
+        assert_eq!(cpu.regs[31], (1 << pad) - 1, "pads, pad {pad}");
+    }
+}
+
 #[test]
 fn test_exec_control_flow_ops() {
     let mut cpu = RiscvCpuState::new();
@@ -1040,7 +1146,7 @@ fn test_exec_control_flow_ops() {
 
         ctx.gen_goto_tb(0);
 
-        ctx.gen_exit_tb(0x1234);
+        ctx.gen_exit_tb_raw(0x1234);
     });
 
     assert_eq!(exit_val, 0x1234);
@@ -1048,6 +1154,100 @@ fn test_exec_control_flow_ops() {
     assert_eq!(cpu.regs[11], 1);
 }
 
+/// A join label reached both by falling through and by a jump,
+/// with the global written in a different register on each path.
+#[test]
+fn test_exec_global_read_after_join() {
+    for (a, b) in [(1u64, 2u64), (2, 1)] {
+        let mut cpu = RiscvCpuState::new();
+        cpu.regs[1] = a;
+        cpu.regs[2] = b;
+
+        run_riscv_tb(&mut cpu, |ctx, env, regs, _pc| {
+            let one = ctx.new_const(Type::I64, 1);
+            let taken = ctx.new_label();
+            let join = ctx.new_label();
+            ctx.gen_insn_start(0x5300);
+            ctx.gen_brcond(
+                Type::I64,
+                regs[1],
+                regs[2],
+                tcg_core::Cond::Lt,
+                taken,
+            );
+            ctx.gen_mov(Type::I64, regs[3], regs[1]);
+            ctx.gen_br(join);
+            ctx.gen_set_label(taken);
+            ctx.gen_mov(Type::I64, regs[3], regs[2]);
+            // Keeps the diamond from being if-converted.
+            ctx.gen_st(Type::I64, regs[3], env, 5 * 8);
+            ctx.gen_set_label(join);
+            ctx.gen_add(Type::I64, regs[4], regs[3], one);
+            ctx.gen_exit_tb_raw(0);
+        });
+
+        assert_eq!(cpu.regs[3], 2, "{a} {b}");
+        assert_eq!(cpu.regs[4], 3, "{a} {b}");
+    }
+}
+
+/// A constant first loaded on the fallthrough path is reused
+/// after a label that the jump reaches without loading it.
+#[test]
+fn test_exec_const_reuse_after_label() {
+    const K: u64 = 0x1234_5678_9abc;
+    for (a, b) in [(1u64, 2u64), (2, 1)] {
+        let mut cpu = RiscvCpuState::new();
+        cpu.regs[1] = a;
+        cpu.regs[2] = b;
+
+        run_riscv_tb(&mut cpu, |ctx, env, regs, _pc| {
+            let k = ctx.new_const(Type::I64, K);
+            let taken = ctx.new_label();
+            let join = ctx.new_label();
+            ctx.gen_insn_start(0x5380);
+            ctx.gen_brcond(
+                Type::I64,
+                regs[1],
+                regs[2],
+                tcg_core::Cond::Lt,
+                taken,
+            );
+            ctx.gen_sub(Type::I64, regs[3], regs[1], k);
+            ctx.gen_br(join);
+            ctx.gen_set_label(taken);
+            ctx.gen_mov(Type::I64, regs[3], regs[2]);
+            ctx.gen_st(Type::I64, regs[3], env, 5 * 8);
+            ctx.gen_set_label(join);
+            ctx.gen_add(Type::I64, regs[4], regs[3], k);
+            ctx.gen_exit_tb_raw(0);
+        });
+
+        let r3 = if a < b { b } else { a.wrapping_sub(K) };
+        assert_eq!(cpu.regs[3], r3, "{a} {b}");
+        assert_eq!(cpu.regs[4], r3.wrapping_add(K), "{a} {b}");
+    }
+}
+
+/// Swap through a temp: the temp copied x1 before x1 changed.
+#[test]
+fn test_exec_swap_through_temp() {
+    let mut cpu = RiscvCpuState::new();
+    cpu.regs[1] = 1;
+    cpu.regs[2] = 2;
+
+    run_riscv_tb(&mut cpu, |ctx, _env, regs, _pc| {
+        let t = ctx.new_temp(Type::I64);
+        ctx.gen_insn_start(0x5400);
+        ctx.gen_mov(Type::I64, t, regs[1]);
+        ctx.gen_mov(Type::I64, regs[1], regs[2]);
+        ctx.gen_mov(Type::I64, regs[2], t);
+        ctx.gen_exit_tb_raw(0);
+    });
+
+    assert_eq!((cpu.regs[1], cpu.regs[2]), (2, 1));
+}
+
 #[test]
 fn test_exec_rotate_and_bitfield_ops() {
     let mut cpu = RiscvCpuState::new();
@@ -1100,7 +1300,7 @@ fn test_exec_rotate_and_bitfield_ops() {
         ctx.gen_mov(Type::I64, regs[15], t_deposit8);
         ctx.gen_mov(Type::I64, regs[16], t_deposit16);
         ctx.gen_mov(Type::I64, regs[17], t_extract2);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     let expected_rotl = a.rotate_left(shift as u32);
@@ -1125,6 +1325,7 @@ fn test_exec_rotate_and_bitfield_ops() {
 }
 
 #[test]
+#[cfg(target_arch = "x86_64")]
 fn test_exec_andc() {
     if !std::is_x86_feature_detected!("bmi1") {
         return;
@@ -1141,7 +1342,7 @@ fn test_exec_andc() {
         ctx.gen_insn_start(0x5310);
         ctx.gen_andc(Type::I64, t_andc, c_a, c_b);
         ctx.gen_mov(Type::I64, regs[10], t_andc);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1171,7 +1372,7 @@ fn test_exec_bswap_ops() {
         ctx.gen_mov(Type::I64, regs[10], t_bswap16);
         ctx.gen_mov(Type::I64, regs[11], t_bswap32);
         ctx.gen_mov(Type::I64, regs[12], t_bswap64);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     let expected_bswap16 = 0xB2A1u64;
@@ -1185,6 +1386,7 @@ fn test_exec_bswap_ops() {
 }
 
 #[test]
+#[cfg(target_arch = "x86_64")]
 fn test_exec_clz_ctz_ctpop() {
     if !std::is_x86_feature_detected!("lzcnt")
         || !std::is_x86_feature_detected!("bmi1")
@@ -1214,7 +1416,7 @@ fn test_exec_clz_ctz_ctpop() {
         ctx.gen_mov(Type::I64, regs[10], t_clz);
         ctx.gen_mov(Type::I64, regs[11], t_ctz);
         ctx.gen_mov(Type::I64, regs[12], t_pop);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     let expected_clz = val_clz.leading_zeros() as u64;
@@ -1246,7 +1448,7 @@ fn test_exec_muls2() {
         ctx.gen_muls2(Type::I64, t_muls_lo, t_muls_hi, c_a_s, c_b_s);
         ctx.gen_mov(Type::I64, regs[10], t_muls_lo);
         ctx.gen_mov(Type::I64, regs[11], t_muls_hi);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1273,7 +1475,7 @@ fn test_exec_mulu2() {
         ctx.gen_mulu2(Type::I64, t_mulu_lo, t_mulu_hi, c_a_u, c_b_u);
         ctx.gen_mov(Type::I64, regs[10], t_mulu_lo);
         ctx.gen_mov(Type::I64, regs[11], t_mulu_hi);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1281,7 +1483,43 @@ fn test_exec_mulu2() {
     assert_eq!(cpu.regs[11], mulu_hi);
 }
 
+/// Both halves live, operands read from globals that stay
+/// live across the sign fixup.
+#[test]
+fn test_exec_mulsu2() {
+    let pairs: [(i64, u64); 4] = [
+        (-3, 5),
+        (-3, u64::MAX),
+        (i64::MIN, 0x8000_0000_0000_0001),
+        (i64::MAX, u64::MAX),
+    ];
+    for (a, b) in pairs {
+        let mut cpu = RiscvCpuState::new();
+        cpu.regs[1] = a as u64;
+        cpu.regs[2] = b;
+        let (lo, hi) = split_i128(a as i128 * b as i128);
+
+        let exit_val = run_riscv_tb(&mut cpu, |ctx, _env, regs, _pc| {
+            let t_lo = ctx.new_temp(Type::I64);
+            let t_hi = ctx.new_temp(Type::I64);
+
+            ctx.gen_insn_start(0x5344);
+            ctx.gen_mulsu2(Type::I64, t_lo, t_hi, regs[1], regs[2]);
+            ctx.gen_mov(Type::I64, regs[10], t_lo);
+            ctx.gen_mov(Type::I64, regs[11], t_hi);
+            ctx.gen_exit_tb_raw(0);
+        });
+
+        assert_eq!(exit_val, 0);
+        assert_eq!(cpu.regs[10], lo, "{a} * {b:#x}");
+        assert_eq!(cpu.regs[11], hi, "{a} * {b:#x}");
+        assert_eq!((cpu.regs[1], cpu.regs[2]), (a as u64, b));
+    }
+}
+
+// AArch64 has no div2 lowering.
 #[test]
+#[cfg(target_arch = "x86_64")]
 fn test_exec_divs2() {
     let mut cpu = RiscvCpuState::new();
     let divs_al: i64 = 100;
@@ -1312,7 +1550,7 @@ fn test_exec_divs2() {
         );
         ctx.gen_mov(Type::I64, regs[10], t_divs_lo);
         ctx.gen_mov(Type::I64, regs[11], t_divs_hi);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1320,7 +1558,9 @@ fn test_exec_divs2() {
     assert_eq!(cpu.regs[11], divs_r_hi);
 }
 
+// AArch64 has no div2 lowering.
 #[test]
+#[cfg(target_arch = "x86_64")]
 fn test_exec_divu2() {
     let mut cpu = RiscvCpuState::new();
     let divu_al: u64 = 0x1_0000_0000;
@@ -1351,7 +1591,7 @@ fn test_exec_divu2() {
         );
         ctx.gen_mov(Type::I64, regs[10], t_divu_lo);
         ctx.gen_mov(Type::I64, regs[11], t_divu_hi);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1414,7 +1654,7 @@ fn test_exec_carry_borrow_ops() {
         ctx.gen_subb1o(Type::I64, t_subb1o, c_five, c_three);
         ctx.gen_mov(Type::I64, regs[20], t_subb1o);
 
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1431,6 +1671,83 @@ fn test_exec_carry_borrow_ops() {
     assert_eq!(cpu.regs[20], 1);
 }
 
+/// A constant zero loaded between a carry-out op and its
+/// carry-in reader must not clobber the carry.
+#[test]
+fn test_exec_carry_survives_const_zero() {
+    let mut cpu = RiscvCpuState::new();
+    cpu.regs[10] = u64::MAX;
+    cpu.regs[11] = 5;
+
+    run_riscv_tb(&mut cpu, |ctx, _env, regs, _pc| {
+        let zero = ctx.new_const(Type::I64, 0);
+        let lo = ctx.new_temp(Type::I64);
+        let hi = ctx.new_temp(Type::I64);
+        ctx.gen_insn_start(0x5500);
+        ctx.gen_addco(Type::I64, lo, regs[10], regs[10]);
+        ctx.gen_addci(Type::I64, hi, regs[11], zero);
+        ctx.gen_mov(Type::I64, regs[12], lo);
+        ctx.gen_mov(Type::I64, regs[13], hi);
+        ctx.gen_exit_tb_raw(0);
+    });
+
+    assert_eq!(cpu.regs[12], u64::MAX - 1);
+    assert_eq!(cpu.regs[13], 6);
+}
+
+#[test]
+fn test_exec_i128_ops() {
+    let mut cpu = RiscvCpuState::new();
+    let a: u128 = 0x1_ffff_ffff_ffff_ffff;
+    let b: u128 = 0x8000_0000_0000_0000_0000_0000_0000_0001;
+    (cpu.regs[10], cpu.regs[11]) = split_u128(a);
+    (cpu.regs[12], cpu.regs[13]) = split_u128(b);
+
+    run_riscv_tb(&mut cpu, |ctx, env, regs, _pc| {
+        let off = |r: usize| ctx.temp(regs[r]).mem_offset;
+        let [oa, ob, add, sub, and, or, xor, mov, carry] =
+            [10, 12, 14, 16, 18, 20, 22, 24, 26].map(off);
+        assert_eq!(off(11), oa + 8);
+        let t_a = ctx.new_temp_i128();
+        let t_b = ctx.new_temp_i128();
+        let t_d = ctx.new_temp_i128();
+        ctx.gen_insn_start(0x5400);
+        ctx.gen_ld_i128(t_a, env, oa);
+        ctx.gen_ld_i128(t_b, env, ob);
+        ctx.gen_add_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, add);
+        ctx.gen_sub_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, sub);
+        ctx.gen_and_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, and);
+        ctx.gen_or_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, or);
+        ctx.gen_xor_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, xor);
+        ctx.gen_mov_i128(t_d, t_a);
+        ctx.gen_st_i128(t_d, env, mov);
+
+        // A constant-zero high half must keep the carry.
+        let (lo, hi) = ctx.i128_halves(t_b);
+        let zero = ctx.new_const(Type::I64, 0);
+        let one = ctx.new_const(Type::I64, 1);
+        ctx.gen_mov(Type::I64, lo, one);
+        ctx.gen_mov(Type::I64, hi, zero);
+        ctx.gen_add_i128(t_d, t_a, t_b);
+        ctx.gen_st_i128(t_d, env, carry);
+        ctx.gen_exit_tb_raw(0);
+    });
+
+    let pair = |r: usize| (cpu.regs[r], cpu.regs[r + 1]);
+    assert_eq!(pair(14), split_u128(a.wrapping_add(b)));
+    assert_eq!(pair(16), split_u128(a.wrapping_sub(b)));
+    assert_eq!(pair(18), split_u128(a & b));
+    assert_eq!(pair(20), split_u128(a | b));
+    assert_eq!(pair(22), split_u128(a ^ b));
+    assert_eq!(pair(24), split_u128(a));
+    assert_eq!(pair(26), split_u128(a + 1));
+}
+
 #[test]
 fn test_exec_negsetcond_movcond() {
     let mut cpu = RiscvCpuState::new();
@@ -1475,7 +1792,7 @@ fn test_exec_negsetcond_movcond() {
         );
         ctx.gen_mov(Type::I64, regs[13], t_mov_false);
 
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1498,7 +1815,7 @@ fn test_exec_extrh_i64_i32() {
         ctx.gen_insn_start(0x5370);
         ctx.gen_extrh_i64_i32(t_extrh, c_val);
         ctx.gen_st32(Type::I32, t_extrh, env, mem_offset);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1510,7 +1827,7 @@ fn test_exec_extrh_i64_i32() {
 
 #[test]
 fn test_exec_goto_ptr() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -1529,7 +1846,7 @@ fn test_exec_goto_ptr() {
     ctx.gen_ld(Type::I64, t_ptr, env, mem_offset);
     ctx.gen_goto_ptr(t_ptr);
     ctx.gen_st(Type::I64, c_after, env, mem_offset + 16);
-    ctx.gen_exit_tb(0x9999);
+    ctx.gen_exit_tb_raw(0x9999);
 
     let mut cpu = RiscvCpuStateMem::new();
     let target = buf.ptr_at(backend.epilogue_return_zero_offset) as u64;
@@ -1555,10 +1872,48 @@ fn test_exec_goto_ptr() {
     );
 }
 
+/// A `goto_ptr` input that stays live is synced before the jump
+/// and not assumed to survive it.
+#[test]
+fn test_exec_goto_ptr_live_input() {
+    let mut backend = NativeCodeGen::new();
+    let mut buf = CodeBuffer::new(4096).unwrap();
+    backend.emit_prologue(&mut buf);
+    backend.emit_epilogue(&mut buf);
+
+    let mut ctx = Context::new();
+    backend.init_context(&mut ctx);
+    let (env, regs, _pc) = setup_riscv_globals(&mut ctx);
+    let mem_offset = std::mem::offset_of!(RiscvCpuStateMem, mem) as i64;
+
+    ctx.gen_insn_start(0x5400);
+    ctx.gen_ld(Type::I64, regs[5], env, mem_offset);
+    ctx.gen_goto_ptr(regs[5]);
+    ctx.gen_st(Type::I64, regs[5], env, mem_offset + 8);
+    ctx.gen_exit_tb_raw(0x9999);
+
+    let mut cpu = RiscvCpuStateMem::new();
+    let target = buf.ptr_at(backend.epilogue_return_zero_offset) as u64;
+    cpu.mem[0..8].copy_from_slice(&target.to_le_bytes());
+
+    let exit_val = unsafe {
+        translate_and_execute(
+            &mut ctx,
+            &backend,
+            &mut buf,
+            &mut cpu as *mut RiscvCpuStateMem as *mut u8,
+        )
+    };
+
+    assert_eq!(exit_val, 0);
+    assert_eq!(cpu.regs[5], target);
+    assert_eq!(u64::from_le_bytes(cpu.mem[8..16].try_into().unwrap()), 0);
+}
+
 /// Test: compute sum 1..5 using a loop
 #[test]
 fn test_sum_loop() {
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(4096).unwrap();
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
@@ -1592,7 +1947,7 @@ fn test_sum_loop() {
     ctx.gen_brcond(Type::I64, regs[2], regs[3], tcg_core::Cond::Le, label_loop);
 
     ctx.gen_set_label(label_end);
-    ctx.gen_exit_tb(0);
+    ctx.gen_exit_tb_raw(0);
 
     // sum = 0, counter = 1, limit = 5
     // Expected: 1+2+3+4+5 = 15
@@ -1947,7 +2302,7 @@ fn test_complex_addi_andi_slli() {
         ctx.gen_and(Type::I64, t_and, t_add, mask);
         ctx.gen_shl(Type::I64, t_shl, t_and, shamt);
         ctx.gen_mov(Type::I64, regs[5], t_shl);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -1972,7 +2327,7 @@ fn test_complex_mul_add_xor() {
         ctx.gen_add(Type::I64, t_add, t_mul, regs[3]);
         ctx.gen_xor(Type::I64, t_xor, t_add, regs[4]);
         ctx.gen_mov(Type::I64, regs[6], t_xor);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2019,7 +2374,7 @@ fn test_complex_slt_branch_select() {
         ctx.gen_mov(Type::I64, regs[7], t_yes);
 
         ctx.gen_set_label(label_end);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2042,7 +2397,7 @@ fn test_complex_auipc_addi() {
         ctx.gen_add(Type::I64, t_auipc, pc, imm);
         ctx.gen_add(Type::I64, t_add, t_auipc, addi);
         ctx.gen_mov(Type::I64, regs[8], t_add);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2067,7 +2422,7 @@ fn test_complex_bitfield_extract() {
         ctx.gen_shr(Type::I64, t_shr, regs[1], shamt);
         ctx.gen_and(Type::I64, t_and, t_shr, mask);
         ctx.gen_mov(Type::I64, regs[9], t_and);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2090,7 +2445,7 @@ fn test_complex_load_add_store() {
         ctx.gen_add(Type::I64, t_add, t_load, c_add);
         ctx.gen_st(Type::I64, t_add, env, mem_offset + 8);
         ctx.gen_mov(Type::I64, regs[10], t_add);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2117,7 +2472,7 @@ fn test_complex_shift_or() {
         ctx.gen_shr(Type::I64, t_shr, regs[3], regs[4]);
         ctx.gen_or(Type::I64, t_or, t_shl, t_shr);
         ctx.gen_mov(Type::I64, regs[11], t_or);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2142,7 +2497,7 @@ fn test_complex_xor_sub_and() {
         ctx.gen_and(Type::I64, t_and, regs[3], regs[4]);
         ctx.gen_sub(Type::I64, t_sub, t_xor, t_and);
         ctx.gen_mov(Type::I64, regs[12], t_sub);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2183,7 +2538,7 @@ fn test_complex_branch_fallthrough() {
         ctx.gen_mov(Type::I64, regs[13], t2);
 
         ctx.gen_set_label(label_end);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2205,7 +2560,7 @@ fn test_complex_pc_relative_mask() {
         ctx.gen_add(Type::I64, t_add, pc, imm);
         ctx.gen_and(Type::I64, t_and, t_add, mask);
         ctx.gen_mov(Type::I64, regs[14], t_and);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2222,7 +2577,7 @@ fn test_neg_basic() {
         ctx.gen_insn_start(0x5100);
         ctx.gen_neg(Type::I64, t_neg, regs[1]);
         ctx.gen_mov(Type::I64, regs[15], t_neg);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2239,7 +2594,7 @@ fn test_not_basic() {
         ctx.gen_insn_start(0x5110);
         ctx.gen_not(Type::I64, t_not, regs[1]);
         ctx.gen_mov(Type::I64, regs[16], t_not);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2255,7 +2610,7 @@ fn test_mov_chain() {
         ctx.gen_insn_start(0x5120);
         ctx.gen_mov(Type::I64, regs[2], regs[1]);
         ctx.gen_mov(Type::I64, regs[3], regs[2]);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2289,7 +2644,7 @@ fn test_brcond_on_temp_eq() {
         ctx.gen_mov(Type::I64, t_out, c1);
         ctx.gen_mov(Type::I64, regs[4], t_out);
         ctx.gen_set_label(label_end);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2316,7 +2671,7 @@ fn test_countdown_loop_sum() {
         ctx.gen_sub(Type::I64, t_cnt, regs[1], c1);
         ctx.gen_mov(Type::I64, regs[1], t_cnt);
         ctx.gen_brcond(Type::I64, regs[1], c0, tcg_core::Cond::Ne, label_loop);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2343,7 +2698,7 @@ fn test_mem_store_overwrite() {
         ctx.gen_st(Type::I64, t2, env, mem_offset);
         ctx.gen_ld(Type::I64, t_load, env, mem_offset);
         ctx.gen_mov(Type::I64, regs[1], t_load);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2369,7 +2724,7 @@ fn test_mem_load_add_sum() {
         ctx.gen_ld(Type::I64, t1, env, mem_offset + 8);
         ctx.gen_add(Type::I64, t_sum, t0, t1);
         ctx.gen_mov(Type::I64, regs[2], t_sum);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2391,7 +2746,7 @@ fn test_shift_count_computed() {
         ctx.gen_add(Type::I64, t_cnt, regs[2], c1);
         ctx.gen_shl(Type::I64, t_out, regs[1], t_cnt);
         ctx.gen_mov(Type::I64, regs[5], t_out);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
@@ -2413,9 +2768,35 @@ fn test_mul_sub_mix() {
         ctx.gen_mul(Type::I64, t_mul, regs[1], regs[2]);
         ctx.gen_sub(Type::I64, t_sub, t_mul, regs[3]);
         ctx.gen_mov(Type::I64, regs[6], t_sub);
-        ctx.gen_exit_tb(0);
+        ctx.gen_exit_tb_raw(0);
     });
 
     assert_eq!(exit_val, 0);
     assert_eq!(cpu.regs[6], (9u64 * 7u64).wrapping_sub(10u64));
 }
+
+/// In-place `add t, t, c` where `t` stays live: the output
+/// lands in a new register and must still read the old `t`.
+#[test]
+fn test_inplace_add_of_loaded_temp() {
+    let mut cpu = RiscvCpuStateMem::new();
+    cpu.mem[0..8].copy_from_slice(&41u64.to_le_bytes());
+
+    let exit_val = run_riscv_tb(&mut cpu, |ctx, env, regs, _pc| {
+        let mem_offset = std::mem::offset_of!(RiscvCpuStateMem, mem) as i64;
+        let t = ctx.new_temp(Type::I64);
+        let c1 = ctx.new_const(Type::I64, 1u64);
+
+        ctx.gen_insn_start(0x5190);
+        ctx.gen_ld(Type::I64, t, env, mem_offset);
+        ctx.gen_add(Type::I64, t, t, c1);
+        ctx.gen_st(Type::I64, t, env, mem_offset);
+        ctx.gen_mov(Type::I64, regs[7], c1);
+        ctx.gen_exit_tb_raw(0);
+    });
+
+    assert_eq!(exit_val, 0);
+    let stored = u64::from_le_bytes(cpu.mem[0..8].try_into().unwrap());
+    assert_eq!(stored, 42);
+    assert_eq!(cpu.regs[7], 1);
+}
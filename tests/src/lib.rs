@@ -1,3 +1,7 @@
+// Instruction encodings in tests group bits by field, not nibble,
+// and keep no-op shifts for symmetry with neighbouring fields.
+#![allow(clippy::unusual_byte_groupings, clippy::identity_op)]
+
 #[cfg(test)]
 mod backend;
 #[cfg(test)]
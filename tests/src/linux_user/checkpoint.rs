@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_linux_user::checkpoint::{
+    resume, Checkpointer, FdPolicy, Guest, RunInfo, Written, FULL_EVERY,
+};
+use tcg_linux_user::guest_space::{page_size, GuestSpace};
+use tcg_linux_user::machine::EcallMode;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::SyscallResult;
+use tcg_linux_user::vfs::Vfs;
+
+const RW: i32 = libc::PROT_READ | libc::PROT_WRITE;
+const MEM: u64 = 0x10000;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(tag: &str) -> Self {
+        let pid = std::process::id();
+        let dir = std::env::temp_dir().join(format!("tcg_ckpt_{tag}_{pid}"));
+        let _ = fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn fill(space: &GuestSpace, addr: u64, len: usize, byte: u8) {
+    unsafe { space.write_bytes(addr, &vec![byte; len]) };
+}
+
+fn read(space: &GuestSpace, addr: u64, len: usize) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(space.g2h(addr), len) }.to_vec()
+}
+
+/// Guest state that is not memory.
+struct Rest {
+    cpu: RiscvCpu,
+    vfs: Vfs,
+    process: Process,
+    signals: SignalTable,
+    run: RunInfo,
+}
+
+impl Rest {
+    fn new() -> Self {
+        let mut cpu = RiscvCpu::new();
+        cpu.pc = MEM;
+        cpu.gpr[5] = 0x1234;
+        cpu.fpr[3] = 0x4000_0000_0000_0000;
+        cpu.time = 777;
+        Self {
+            cpu,
+            vfs: Vfs::new(Some(9)),
+            process: Process::new(),
+            signals: SignalTable::new(),
+            run: RunInfo {
+                elf_path: "/bin/guest".to_string(),
+                timebase_freq: 1_000_000,
+                deterministic: true,
+                ecall: EcallMode::Sbi,
+                uart: Some(0x1000_0000),
+            },
+        }
+    }
+
+    fn write(
+        &self,
+        ck: &mut Checkpointer,
+        space: &mut GuestSpace,
+    ) -> std::io::Result<Written> {
+        ck.write(Guest {
+            cpu: &self.cpu,
+            space,
+            vfs: &self.vfs,
+            process: &self.process,
+            signals: &self.signals,
+            mmap_next: 0x5000_0000,
+            run: &self.run,
+        })
+    }
+}
+
+fn mem_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|n| n != "state")
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_checkpoint_chain_restores_memory() {
+    let ps = page_size();
+    let dir = TempDir::new("chain");
+    let rest = Rest::new();
+    let mut space = GuestSpace::new().unwrap();
+    let (a, b, c) = (MEM, MEM + 0x10000, MEM + 0x20000);
+    space.mmap_fixed(a, 4 * ps, RW).unwrap();
+    fill(&space, a, 4 * ps, 0xaa);
+    space.mmap_fixed(b, 2 * ps, RW).unwrap();
+    fill(&space, b, ps, 0xbb);
+    space.set_brk(0x8000);
+
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Refuse).unwrap();
+    let w = rest.write(&mut ck, &mut space).unwrap();
+    assert!(w.full);
+    // The untouched page of b is all zero and left out.
+    assert_eq!(w.mem_bytes, 5 * ps as u64);
+
+    // One page written: the increment holds just it.
+    fill(&space, a + ps as u64 + 8, 8, 0x11);
+    let w = rest.write(&mut ck, &mut space).unwrap();
+    assert!(!w.full);
+    assert_eq!(w.mem_bytes, ps as u64);
+
+    // Changed regions go in whole: a, shrunk, b and c.
+    space.munmap(a + 3 * ps as u64, ps).unwrap();
+    space.mprotect(b, 2 * ps, libc::PROT_READ).unwrap();
+    space.mmap_fixed(c, ps, RW).unwrap();
+    fill(&space, c, 16, 0xcc);
+    space
+        .mmap_fixed(c + 0x1000_0000, ps, libc::PROT_NONE)
+        .unwrap();
+    let w = rest.write(&mut ck, &mut space).unwrap();
+    assert!(!w.full);
+    assert_eq!(w.mem_bytes, 6 * ps as u64);
+    assert_eq!(mem_files(&dir.0), ["mem-1", "mem-2", "mem-3"]);
+    let want = space.maps();
+    let want_a = read(&space, a, 3 * ps);
+    drop(space);
+
+    let r = resume(&dir.0).unwrap();
+    assert_eq!(r.seq, 3);
+    assert_eq!(r.space.maps(), want);
+    assert_eq!(r.space.brk(), 0x8000);
+    assert_eq!(read(&r.space, a, 3 * ps), want_a);
+    assert_eq!(read(&r.space, b, ps), vec![0xbb; ps]);
+    assert_eq!(read(&r.space, b + ps as u64, ps), vec![0; ps]);
+    assert_eq!(read(&r.space, c, 16), [0xcc; 16]);
+    assert_eq!(r.cpu.pc, MEM);
+    assert_eq!(r.cpu.gpr[5], 0x1234);
+    assert_eq!(r.cpu.fpr[3], 0x4000_0000_0000_0000);
+    assert_eq!(r.cpu.time, 777);
+    assert_eq!(r.cpu.guest_base, r.space.guest_base() as u64);
+    assert_eq!(r.mmap_next, 0x5000_0000);
+    assert_eq!(r.run, rest.run);
+}
+
+/// Every `FULL_EVERY` checkpoints a full image replaces the
+/// chain, and a new writer goes on numbering after the old.
+#[test]
+fn test_checkpoint_chain_restarts() {
+    let ps = page_size();
+    let dir = TempDir::new("restart");
+    let rest = Rest::new();
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, ps, RW).unwrap();
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Refuse).unwrap();
+    for i in 0..FULL_EVERY {
+        fill(&space, MEM, 8, i as u8);
+        let w = rest.write(&mut ck, &mut space).unwrap();
+        assert_eq!(w.full, i == 0, "{i}");
+    }
+    assert_eq!(mem_files(&dir.0).len(), FULL_EVERY);
+    fill(&space, MEM, 8, 0x77);
+    let w = rest.write(&mut ck, &mut space).unwrap();
+    assert!(w.full);
+    assert_eq!(mem_files(&dir.0), [format!("mem-{}", FULL_EVERY + 1)]);
+
+    // A new writer starts with a full image.
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Refuse).unwrap();
+    let w = rest.write(&mut ck, &mut space).unwrap();
+    assert!(w.full);
+    assert_eq!(w.seq, FULL_EVERY as u64 + 2);
+    drop(space);
+    let r = resume(&dir.0).unwrap();
+    assert_eq!(read(&r.space, MEM, 8), [0x77; 8]);
+}
+
+#[test]
+fn test_checkpoint_socket_refused_or_dropped() {
+    let ps = page_size();
+    let dir = TempDir::new("socket");
+    let mut rest = Rest::new();
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, ps, RW).unwrap();
+    let sock = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+    assert!(sock >= 0);
+    rest.vfs.opened(SyscallResult::Continue(sock as u64));
+
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Refuse).unwrap();
+    let e = rest.write(&mut ck, &mut space).unwrap_err();
+    assert!(e.to_string().contains("is a socket"), "{e}");
+    assert!(!dir.0.join("state").exists());
+
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Drop).unwrap();
+    rest.write(&mut ck, &mut space).unwrap();
+    drop(space);
+    let r = resume(&dir.0).unwrap();
+    assert_eq!(r.vfs.fds().count(), 0);
+    unsafe { libc::close(sock) };
+}
+
+#[test]
+fn test_checkpoint_corrupt_state() {
+    let dir = TempDir::new("corrupt");
+    let rest = Rest::new();
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, page_size(), RW).unwrap();
+    let mut ck = Checkpointer::new(&dir.0, FdPolicy::Refuse).unwrap();
+    rest.write(&mut ck, &mut space).unwrap();
+    drop(space);
+
+    let state = dir.0.join("state");
+    let full = fs::read(&state).unwrap();
+    fs::write(&state, &full[..full.len() / 2]).unwrap();
+    let e = resume(&dir.0).err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    fs::write(&state, b"garbage").unwrap();
+    assert!(resume(&dir.0).is_err());
+}
@@ -0,0 +1,419 @@
+//! Command-line and environment configuration tests.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tcg_exec::checkpoint::CheckpointInterval;
+use tcg_frontend::riscv::ext::{MisaExt, RiscvCfg};
+use tcg_linux_user::checkpoint::FdPolicy;
+use tcg_linux_user::config::{
+    parse_args, ArgError, Invocation, RunConfig, DEFAULT_CHECKPOINT_WALL,
+};
+use tcg_linux_user::guest_space::{page_size, GUEST_STACK_GUARD};
+use tcg_linux_user::machine::{EcallMode, DEFAULT_LOAD_ADDR};
+use tcg_linux_user::syscall::{strace_call, strace_ret};
+
+fn parse(args: &[&str]) -> Result<Invocation, ArgError> {
+    let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+    parse_args(&args, RunConfig::default())
+}
+
+fn config(args: &[&str]) -> RunConfig {
+    parse(args).expect("parse failed").config
+}
+
+fn invalid(args: &[&str]) -> String {
+    match parse(args) {
+        Err(ArgError::Invalid(msg)) => msg,
+        other => panic!("{args:?}: expected error, got {other:?}"),
+    }
+}
+
+#[test]
+fn bare_elf() {
+    let inv = parse(&["prog"]).unwrap();
+    assert_eq!(inv.elf, "prog");
+    assert_eq!(inv.argv, ["prog"]);
+    assert!(!inv.config.strace);
+    assert!(inv.config.env_set.is_empty());
+}
+
+#[test]
+fn log_items() {
+    assert!(config(&["-d", "strace", "prog"]).strace);
+    assert!(invalid(&["-d", "strace,in_asm", "prog"]).contains("in_asm"));
+    let cfg = config(&["-D", "/tmp/log", "prog"]);
+    assert_eq!(cfg.log_file, Some(PathBuf::from("/tmp/log")));
+}
+
+#[test]
+fn strace_and_long_forms() {
+    assert!(config(&["-strace", "prog"]).strace);
+    assert!(config(&["--strace", "prog"]).strace);
+    let cfg = config(&["--D=/tmp/log", "prog"]);
+    assert_eq!(cfg.log_file, Some(PathBuf::from("/tmp/log")));
+}
+
+#[test]
+fn sysroot() {
+    let cfg = config(&["-L", "/opt/riscv/sysroot", "prog"]);
+    assert_eq!(cfg.sysroot, Some(PathBuf::from("/opt/riscv/sysroot")));
+}
+
+#[test]
+fn env_set_and_unset() {
+    let cfg = config(&["-E", "A=1", "-E", "B=x=y", "-U", "HOME", "prog"]);
+    let base = [
+        ("HOME".to_string(), "/root".to_string()),
+        ("A".to_string(), "0".to_string()),
+    ];
+    assert_eq!(cfg.guest_env(base), ["A=1", "B=x=y"]);
+    assert!(invalid(&["-E", "NOVALUE", "prog"]).contains("VAR=value"));
+}
+
+#[test]
+fn argv0() {
+    let inv = parse(&["-0", "busybox", "/bin/ls", "-l"]).unwrap();
+    assert_eq!(inv.elf, "/bin/ls");
+    assert_eq!(inv.argv, ["busybox", "-l"]);
+}
+
+#[test]
+fn seed_implies_deterministic() {
+    let cfg = config(&["-seed", "42", "prog"]);
+    assert_eq!(cfg.seed, Some(42));
+    assert!(cfg.deterministic);
+    assert!(invalid(&["-seed", "x", "prog"]).contains("-seed"));
+}
+
+#[test]
+fn page_size_must_match_host() {
+    let host = page_size().to_string();
+    assert!(parse(&["-p", &host, "prog"]).is_ok());
+    let other = (page_size() * 2).to_string();
+    assert!(invalid(&["-p", &other, "prog"]).contains("page size"));
+}
+
+#[test]
+fn gdb_port() {
+    assert_eq!(config(&["-g", "1234", "prog"]).gdb_port, Some(1234));
+    assert!(invalid(&["-g", "99999", "prog"]).contains("-g"));
+}
+
+#[test]
+fn cpu_extensions() {
+    let isa = |args: &[&str]| config(args).cpu.isa_string();
+    assert_eq!(isa(&["prog"]), RiscvCfg::default().isa_string());
+    assert_eq!(
+        isa(&["-cpu", "rv64,c=off,m=false,zbb=on", "prog"]),
+        "rv64iafd_zicbom_zicbop_zicboz_zicsr_zifencei_zihintntl_zbb"
+    );
+    assert!(!config(&["-cpu", "rv64,c=off", "prog"])
+        .cpu
+        .misa
+        .contains(MisaExt::C));
+    assert!(invalid(&["-cpu", "rv32", "prog"]).contains("model"));
+    assert!(invalid(&["-cpu", "rv64,i=off", "prog"]).contains("i"));
+    assert!(invalid(&["-cpu", "rv64,zbb", "prog"]).contains("zbb"));
+    assert!(invalid(&["-cpu", "rv64,c=maybe", "prog"]).contains("c=maybe"));
+}
+
+#[test]
+fn tcg_options() {
+    let cfg = config(&[
+        "--timebase-freq=1000",
+        "-deterministic",
+        "-stats",
+        "-coverage",
+        "out.cov",
+        "-deny-net",
+        "-verify-code",
+        "8",
+        "-warmup-save",
+        "hot.txt",
+        "prog",
+    ]);
+    assert_eq!(cfg.timebase_freq, 1000);
+    assert!(cfg.deterministic && cfg.show_stats && cfg.deny_net);
+    assert_eq!(cfg.coverage, Some(PathBuf::from("out.cov")));
+    assert_eq!(cfg.verify_code, Some(8));
+    assert_eq!(cfg.warmup_save, Some(PathBuf::from("hot.txt")));
+    assert_eq!(cfg.warmup_load, None);
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_WARMUP_LOAD").then(|| "hot.txt".to_string())
+    })
+    .unwrap();
+    assert_eq!(env.warmup_load, Some(PathBuf::from("hot.txt")));
+    assert!(invalid(&["-verify-code", "0", "prog"]).contains("verify-code"));
+}
+
+#[test]
+fn stack_guard() {
+    assert_eq!(config(&["prog"]).stack_guard, GUEST_STACK_GUARD);
+    assert_eq!(config(&["-stack-guard", "0", "prog"]).stack_guard, 0);
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_STACK_GUARD").then(|| "65536".to_string())
+    })
+    .unwrap();
+    assert_eq!(env.stack_guard, 65536);
+    assert!(invalid(&["-stack-guard", "big", "prog"]).contains("stack-guard"));
+}
+
+#[test]
+fn time_limits() {
+    let cfg = config(&["prog"]);
+    assert_eq!((cfg.max_cpu, cfg.max_wall), (None, None));
+    let cfg =
+        config(&["-max-cpu-seconds", "1.5", "--max-wall-seconds=30", "prog"]);
+    assert_eq!(cfg.max_cpu, Some(Duration::from_millis(1500)));
+    assert_eq!(cfg.max_wall, Some(Duration::from_secs(30)));
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_MAX_CPU_SECONDS").then(|| "0.25".to_string())
+    })
+    .unwrap();
+    assert_eq!(env.max_cpu, Some(Duration::from_millis(250)));
+    for bad in ["0", "-1", "soon", "inf", "NaN"] {
+        let msg = invalid(&["-max-cpu-seconds", bad, "prog"]);
+        assert!(msg.contains("max-cpu-seconds"), "{bad}: {msg}");
+    }
+    assert!(RunConfig::from_vars(|k| {
+        (k == "TCG_MAX_WALL_SECONDS").then(|| "0".to_string())
+    })
+    .is_err());
+}
+
+#[test]
+fn statfs_options() {
+    let cfg = config(&["prog"]);
+    assert_eq!((cfg.sysroot_fstype, cfg.disk_cap), (None, None));
+    let cfg = config(&[
+        "-L",
+        "/sysroot",
+        "-sysroot-fstype",
+        "overlay",
+        "--disk-cap=0x100000",
+        "prog",
+    ]);
+    assert_eq!(cfg.sysroot_fstype, Some(0x794c_7630));
+    assert_eq!(cfg.disk_cap, Some(1 << 20));
+    let cfg = config(&["-L", "/s", "-sysroot-fstype", "0x6969", "prog"]);
+    assert_eq!(cfg.sysroot_fstype, Some(0x6969));
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_SYSROOT_FSTYPE").then(|| "tmpfs".to_string())
+    })
+    .unwrap();
+    assert_eq!(env.sysroot_fstype, Some(0x0102_1994));
+
+    let msg = invalid(&["-L", "/s", "-sysroot-fstype", "zfs?", "prog"]);
+    assert!(msg.contains("invalid -sysroot-fstype: zfs?"), "{msg}");
+    let msg = invalid(&["-sysroot-fstype", "ext4", "prog"]);
+    assert!(msg.contains("-sysroot-fstype needs -L"), "{msg}");
+}
+
+#[test]
+fn device_options() {
+    let cfg = config(&["prog"]);
+    assert!(!cfg.deny_random && !cfg.captured_stdio);
+    assert!(!cfg.syscall_policy().deny_random);
+    let cfg = config(&["-deny-random", "--captured-stdio", "prog"]);
+    assert!(cfg.deny_random && cfg.captured_stdio);
+    assert!(cfg.syscall_policy().deny_random);
+    let env = RunConfig::from_vars(|k| {
+        matches!(k, "TCG_DENY_RANDOM" | "TCG_CAPTURED_STDIO")
+            .then(|| "1".to_string())
+    })
+    .unwrap();
+    assert!(env.deny_random && env.captured_stdio);
+}
+
+#[test]
+fn stack_check_option() {
+    assert!(!config(&["prog"]).stack_check);
+    assert!(config(&["-stack-check", "prog"]).stack_check);
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_STACK_CHECK").then(|| "1".to_string())
+    })
+    .unwrap();
+    assert!(env.stack_check);
+}
+
+#[test]
+fn flat_image_options() {
+    let cfg = config(&["prog"]);
+    assert!(!cfg.kernel);
+    assert_eq!(cfg.load_addr, DEFAULT_LOAD_ADDR);
+    assert_eq!((cfg.entry, cfg.uart), (None, None));
+    assert_eq!(cfg.ecall, EcallMode::Linux);
+
+    let inv = parse(&[
+        "-kernel",
+        "fw.bin",
+        "-load-addr",
+        "0x80200000",
+        "-entry=0x80200100",
+        "-ram-size",
+        "0x100000",
+        "-ecall",
+        "sbi",
+        "-uart",
+        "0x10000000",
+        "arg",
+    ])
+    .unwrap();
+    assert_eq!(inv.elf, "fw.bin");
+    assert_eq!(inv.argv, ["fw.bin", "arg"]);
+    let cfg = inv.config;
+    assert!(cfg.kernel);
+    assert_eq!(cfg.load_addr, 0x8020_0000);
+    assert_eq!(cfg.entry, Some(0x8020_0100));
+    assert_eq!(cfg.ram_size, 0x10_0000);
+    assert_eq!(cfg.ecall, EcallMode::Sbi);
+    assert_eq!(cfg.uart, Some(0x1000_0000));
+
+    assert!(invalid(&["-ecall", "x", "prog"]).contains("-ecall"));
+    assert!(invalid(&["-load-addr", "0xg", "prog"]).contains("-load-addr"));
+    assert!(invalid(&["-ram-size", "0", "prog"]).contains("-ram-size"));
+}
+
+#[test]
+fn rejects_bad_command_lines() {
+    assert!(invalid(&["-frobnicate", "prog"]).contains("unknown option"));
+    assert!(invalid(&["-D"]).contains("needs a value"));
+    assert!(invalid(&["-strace"]).contains("missing guest ELF"));
+    assert_eq!(parse(&["-h"]).unwrap_err(), ArgError::Help);
+    assert_eq!(parse(&["--help", "prog"]).unwrap_err(), ArgError::Help);
+    let usage = ArgError::Invalid("x".into()).to_string();
+    assert!(usage.starts_with("x\n\nusage: tcg-riscv64"));
+}
+
+#[test]
+fn flag_overrides_env() {
+    let vars = |k: &str| match k {
+        "TCG_TIMEBASE_FREQ" => Some("5000".to_string()),
+        "TCG_STATS" => Some("1".to_string()),
+        _ => None,
+    };
+    let base = RunConfig::from_vars(vars).unwrap();
+    assert_eq!(base.timebase_freq, 5000);
+    assert!(base.show_stats);
+
+    let args = ["-timebase-freq".to_string(), "7".into(), "prog".into()];
+    let cfg = parse_args(&args, base.clone()).unwrap().config;
+    assert_eq!(cfg.timebase_freq, 7);
+    assert!(cfg.show_stats);
+
+    let cfg = parse_args(&["prog".to_string()], base).unwrap().config;
+    assert_eq!(cfg.timebase_freq, 5000);
+
+    let bad = |k: &str| (k == "TCG_VERIFY_CODE").then(|| "0".to_string());
+    assert!(RunConfig::from_vars(bad).is_err());
+}
+
+#[test]
+fn dash_args_pass_through() {
+    let inv =
+        parse(&["-strace", "--", "prog", "--verbose", "-d", "x"]).unwrap();
+    assert!(inv.config.strace);
+    assert_eq!(inv.argv, ["prog", "--verbose", "-d", "x"]);
+
+    // Options end at the ELF even without `--`.
+    let inv = parse(&["prog", "-strace", "--", "-x"]).unwrap();
+    assert!(!inv.config.strace);
+    assert_eq!(inv.argv, ["prog", "-strace", "--", "-x"]);
+
+    // `--` lets the ELF itself start with a dash.
+    assert_eq!(parse(&["--", "-prog"]).unwrap().elf, "-prog");
+}
+
+#[test]
+fn strace_format() {
+    assert_eq!(
+        strace_call(64, &[1, 0x4000, 12, 0, 0, 0]),
+        "write(0x1,0x4000,0xc,0x0,0x0,0x0)"
+    );
+    assert_eq!(strace_call(9999, &[0]), "syscall_9999(0x0)");
+    assert_eq!(strace_ret(12), " = 12");
+    assert_eq!(strace_ret((-2i64) as u64), " = -1 errno=2");
+}
+
+#[test]
+fn metrics_option() {
+    assert_eq!(config(&["prog"]).metrics, None);
+    let addr = "127.0.0.1:9100".parse().ok();
+    assert_eq!(
+        config(&["-metrics", "127.0.0.1:9100", "prog"]).metrics,
+        addr
+    );
+    let env = RunConfig::from_vars(|k| {
+        (k == "TCG_METRICS").then(|| "127.0.0.1:9100".to_string())
+    })
+    .unwrap();
+    assert_eq!(env.metrics, addr);
+    let msg = invalid(&["-metrics", "localhost", "prog"]);
+    assert_eq!(msg, "invalid -metrics: localhost");
+}
+
+#[test]
+fn checkpoint_options() {
+    let cfg = config(&["prog"]);
+    assert_eq!(cfg.checkpoint_interval(), None);
+    let cfg = config(&["-checkpoint", "/tmp/ck", "prog"]);
+    assert_eq!(cfg.checkpoint, Some(PathBuf::from("/tmp/ck")));
+    assert_eq!(
+        cfg.checkpoint_interval(),
+        Some(CheckpointInterval {
+            insns: None,
+            wall: Some(DEFAULT_CHECKPOINT_WALL),
+        })
+    );
+    assert_eq!(cfg.checkpoint_fd_policy(), FdPolicy::Refuse);
+    let cfg = config(&[
+        "-checkpoint=/tmp/ck",
+        "-checkpoint-insns",
+        "1000000",
+        "-checkpoint-drop-fds",
+        "prog",
+    ]);
+    assert_eq!(
+        cfg.checkpoint_interval(),
+        Some(CheckpointInterval {
+            insns: Some(1_000_000),
+            wall: None,
+        })
+    );
+    assert_eq!(cfg.checkpoint_fd_policy(), FdPolicy::Drop);
+    let env = RunConfig::from_vars(|k| match k {
+        "TCG_CHECKPOINT" => Some("/tmp/ck".to_string()),
+        "TCG_CHECKPOINT_SECONDS" => Some("2.5".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(
+        env.checkpoint_interval().unwrap().wall,
+        Some(Duration::from_millis(2500))
+    );
+
+    let msg = invalid(&["-checkpoint-seconds", "5", "prog"]);
+    assert_eq!(msg, "checkpoint options need -checkpoint");
+    let msg = invalid(&["-checkpoint", "d", "-checkpoint-insns", "0", "prog"]);
+    assert_eq!(msg, "invalid -checkpoint-insns: 0");
+}
+
+#[test]
+fn resume_option() {
+    let inv = parse(&["-resume", "/tmp/ck", "-stats"]).unwrap();
+    assert_eq!(inv.config.resume, Some(PathBuf::from("/tmp/ck")));
+    assert!(inv.config.show_stats);
+    assert_eq!(inv.elf, "");
+    assert!(inv.argv.is_empty());
+    // A resumed run may go on checkpointing.
+    let inv = parse(&["-resume", "d", "-checkpoint", "d"]).unwrap();
+    assert!(inv.config.checkpoint_interval().is_some());
+    for args in [
+        &["-resume", "d", "prog"][..],
+        &["-resume", "d", "-kernel", "x"],
+    ] {
+        let msg = invalid(args);
+        assert_eq!(msg, "-resume takes no guest program");
+    }
+}
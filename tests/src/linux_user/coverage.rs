@@ -0,0 +1,72 @@
+use std::io;
+
+use tcg_exec::coverage::CoveredBlock;
+use tcg_linux_user::coverage::{write_lcov, LineResolver};
+
+/// Two instructions per source line in `prog.c`; addresses at
+/// or above 0x100 have no line info.
+struct FakeResolver;
+
+impl LineResolver for FakeResolver {
+    fn resolve(
+        &mut self,
+        addrs: &[u64],
+    ) -> io::Result<Vec<Option<(String, u32)>>> {
+        Ok(addrs
+            .iter()
+            .map(|&a| (a < 0x100).then(|| ("prog.c".into(), a as u32 / 8 + 1)))
+            .collect())
+    }
+}
+
+fn lcov(blocks: &[CoveredBlock], universe: &[(u64, u64)]) -> String {
+    let mut out = Vec::new();
+    write_lcov(blocks, universe, &mut FakeResolver, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn lcov_record_is_well_formed() {
+    let blocks = [
+        CoveredBlock {
+            start: 0x0,
+            end: 0x8,
+            insns: 2,
+            insn_starts: vec![0x0, 0x4],
+            hits: 3,
+        },
+        CoveredBlock {
+            start: 0x10,
+            end: 0x14,
+            insns: 1,
+            insn_starts: Vec::new(),
+            hits: 1,
+        },
+    ];
+    let text = lcov(&blocks, &[(0x0, 0x20), (0x100, 0x110)]);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines.first(), Some(&"TN:"));
+    assert_eq!(lines.get(1), Some(&"SF:prog.c"));
+    assert_eq!(lines.last(), Some(&"end_of_record"));
+    let da: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|l| l.starts_with("DA:"))
+        .collect();
+    assert_eq!(da, ["DA:1,3", "DA:2,0", "DA:3,1", "DA:4,0"]);
+    assert!(lines.contains(&"LF:4"));
+    assert!(lines.contains(&"LH:2"));
+}
+
+#[test]
+fn lcov_skips_addresses_without_line_info() {
+    let blocks = [CoveredBlock {
+        start: 0x100,
+        end: 0x108,
+        insns: 2,
+        insn_starts: Vec::new(),
+        hits: 1,
+    }];
+    assert_eq!(lcov(&blocks, &[]), "");
+}
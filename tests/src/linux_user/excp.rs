@@ -0,0 +1,89 @@
+//! RISC-V exceptions handed to the user-mode handler.
+
+use tcg_core::excp::{CoreExcp, EXCP_FRONTEND_BASE};
+use tcg_exec::excp::{dispatch, ExcpAction};
+use tcg_frontend::riscv::cpu::RiscvCpu;
+use tcg_frontend::riscv::excp::{
+    EXCP_EBREAK, EXCP_ECALL, EXCP_FETCH_MISALIGNED, EXCP_LOAD_MISALIGNED,
+    EXCP_UNDEF,
+};
+use tcg_linux_user::excp::LinuxHandler;
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::machine::EcallMode;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::SyscallPolicy;
+use tcg_linux_user::vfs::Vfs;
+
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_GETPID: u64 = 172;
+
+fn handler(ecall: EcallMode) -> LinuxHandler {
+    LinuxHandler {
+        space: GuestSpace::new().unwrap(),
+        vfs: Vfs::new(None),
+        process: Process::new(),
+        signals: SignalTable::new(),
+        mmap_next: 0,
+        elf_path: String::new(),
+        policy: SyscallPolicy::default(),
+        ecall,
+        strace: None,
+    }
+}
+
+fn cpu_at(pc: u64) -> RiscvCpu {
+    let mut cpu = RiscvCpu::new();
+    cpu.pc = pc;
+    cpu
+}
+
+#[test]
+fn ecall_runs_the_syscall_and_resumes() {
+    let mut h = handler(EcallMode::Linux);
+    let mut cpu = cpu_at(0x1000);
+    cpu.gpr[17] = SYS_GETPID;
+    assert_eq!(dispatch(&mut h, &mut cpu, EXCP_ECALL), ExcpAction::Resume);
+    assert_eq!(cpu.gpr[10], 1, "the guest is pid 1");
+    assert_eq!(cpu.pc, 0x1004);
+
+    cpu.gpr[17] = SYS_EXIT_GROUP;
+    cpu.gpr[10] = 3;
+    assert_eq!(dispatch(&mut h, &mut cpu, EXCP_ECALL), ExcpAction::Exit(3));
+}
+
+#[test]
+fn other_exceptions_end_the_run() {
+    let mut h = handler(EcallMode::Linux);
+    let mut cpu = cpu_at(0x2000);
+    let fatal = |h: &mut LinuxHandler, cpu: &mut RiscvCpu, code| match dispatch(
+        h, cpu, code,
+    ) {
+        ExcpAction::Fatal(m) => m,
+        a => panic!("unexpected {a:?}"),
+    };
+    assert_eq!(fatal(&mut h, &mut cpu, EXCP_EBREAK), "ebreak at pc=0x2000");
+    assert_eq!(
+        fatal(&mut h, &mut cpu, EXCP_UNDEF),
+        "illegal instruction at pc=0x2000"
+    );
+    cpu.utval = 0x3001;
+    assert_eq!(
+        fatal(&mut h, &mut cpu, EXCP_LOAD_MISALIGNED),
+        "guest bus error: misaligned access at 0x3001 (pc=0x2000)"
+    );
+    cpu.utval = 0x3002;
+    assert_eq!(
+        fatal(&mut h, &mut cpu, EXCP_FETCH_MISALIGNED),
+        "guest bus error: misaligned jump target 0x3002 (pc=0x2000)"
+    );
+    assert_eq!(
+        fatal(&mut h, &mut cpu, CoreExcp::Fault.code()),
+        "fault exception at pc=0x2000"
+    );
+    assert_eq!(
+        fatal(&mut h, &mut cpu, EXCP_FRONTEND_BASE + 0x40),
+        "unhandled exception 0x43 at pc=0x2000"
+    );
+    assert_eq!(cpu.pc, 0x2000);
+}
@@ -0,0 +1,129 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, Output};
+
+use tcg_linux_user::fault::{format_report, FaultMap, GuestFault, SegvCode};
+use tcg_linux_user::guest_space::{GuestSpace, GUEST_STACK_GUARD};
+
+use super::loader::{make_elf, tempfile};
+use super::runner_bin;
+
+fn words(insns: &[u32]) -> Vec<u8> {
+    insns.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+/// `f: addi sp, sp, -256; sd ra, 0(sp); jal ra, f`
+fn recurse_forever() -> Vec<u8> {
+    words(&[0xf001_0113, 0x0011_3023, 0xff9f_f0ef])
+}
+
+/// Run a hand-assembled guest under `tcg-riscv64 <opts>`.
+fn run_code(code: &[u8], opts: &[&str]) -> Output {
+    let mut elf = tempfile().unwrap();
+    elf.write_all(&make_elf(code)).unwrap();
+    Command::new(runner_bin())
+        .args(opts)
+        .arg(elf.path())
+        .output()
+        .expect("failed to run tcg-riscv64")
+}
+
+fn assert_killed_with(out: &Output, msg: &str) {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.signal(), Some(libc::SIGSEGV), "{stderr}");
+    assert!(stderr.contains(msg), "stderr: {stderr}");
+}
+
+#[test]
+fn test_classify_guard_and_other_faults() {
+    let map = FaultMap {
+        base: 0x7000_0000_0000,
+        size: 1 << 30,
+        guard: (0x3f00_0000, 0x3f10_0000),
+    };
+    let at = |a: usize| map.classify(map.base + a);
+    assert_eq!(
+        at(0x3f0f_fff8),
+        Some(GuestFault::StackOverflow(0x3f0f_fff8))
+    );
+    assert_eq!(
+        at(0x3f00_0000),
+        Some(GuestFault::StackOverflow(0x3f00_0000))
+    );
+    assert_eq!(at(0x3f10_0000), Some(GuestFault::Segv(0x3f10_0000)));
+    assert_eq!(at(0x10), Some(GuestFault::Segv(0x10)));
+    assert_eq!(at(1 << 30), None);
+    assert_eq!(map.classify(0x1000), None);
+}
+
+#[test]
+fn test_fault_map_tracks_stack_guard() {
+    let mut space = GuestSpace::new().unwrap();
+    space.map_stack(0x3000_0000, 0x10_0000).unwrap();
+    let map = FaultMap::new(&space);
+    assert_eq!(map.base, space.guest_base() as usize);
+    assert_eq!(
+        map.guard,
+        (0x2ff0_0000 - GUEST_STACK_GUARD as u64, 0x2ff0_0000)
+    );
+    let host = space.g2h(0x2fef_fff0) as usize;
+    assert_eq!(
+        map.classify(host),
+        Some(GuestFault::StackOverflow(0x2fef_fff0))
+    );
+}
+
+#[test]
+fn test_format_report() {
+    let mut buf = [0u8; 64];
+    let n = format_report(GuestFault::StackOverflow(0x3f6f_fff0), &mut buf);
+    assert_eq!(&buf[..n], b"guest stack overflow at 0x3f6ffff0\n");
+    let n = format_report(GuestFault::Segv(0), &mut buf);
+    assert_eq!(&buf[..n], b"guest segmentation fault at 0x0\n");
+}
+
+#[test]
+fn test_recursive_guest_reports_stack_overflow() {
+    let out = run_code(&recurse_forever(), &[]);
+    assert_killed_with(&out, "guest stack overflow at 0x");
+}
+
+#[test]
+fn test_large_frame_lands_in_guard() {
+    // lui t0, 0x880; sub sp, sp, t0; sd zero, 0(sp):
+    // an 8.5 MiB frame skips the 8 MiB stack entirely.
+    let code = words(&[0x0088_02b7, 0x4051_0133, 0x0001_3023]);
+    let out = run_code(&code, &[]);
+    assert_killed_with(&out, "guest stack overflow at 0x");
+}
+
+#[test]
+fn test_no_guard_is_plain_segfault() {
+    let out = run_code(&recurse_forever(), &["-stack-guard", "0"]);
+    assert_killed_with(&out, "guest segmentation fault at 0x");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!stderr.contains("stack overflow"), "{stderr}");
+}
+
+/// si_code follows the region tree: protected mappings give
+/// SEGV_ACCERR, holes and the stack guard SEGV_MAPERR.
+#[test]
+fn test_segv_code_from_regions() {
+    let mut space = GuestSpace::new()
+        .unwrap()
+        .with_stack_guard(GUEST_STACK_GUARD);
+    space.mmap_fixed(0x10000, 0x2000, libc::PROT_READ).unwrap();
+    space.mprotect(0x11000, 0x1000, libc::PROT_NONE).unwrap();
+    space.map_stack(0x3000_0000, 0x10_0000).unwrap();
+
+    assert_eq!(space.segv_code(0x10008), SegvCode::AccErr);
+    assert_eq!(space.segv_code(0x11000), SegvCode::AccErr);
+    assert_eq!(space.segv_code(0x12000), SegvCode::MapErr);
+    assert_eq!(space.segv_code(0), SegvCode::MapErr);
+    let (guard, _) = space.stack_guard().unwrap();
+    assert_eq!(space.segv_code(guard), SegvCode::MapErr);
+    assert_eq!(space.segv_code(0x3000_0000 - 8), SegvCode::AccErr);
+
+    space.munmap(0x10000, 0x1000).unwrap();
+    assert_eq!(space.segv_code(0x10008), SegvCode::MapErr);
+    assert_eq!(SegvCode::AccErr as i32, 2);
+}
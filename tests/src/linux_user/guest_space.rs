@@ -1,6 +1,15 @@
 use tcg_linux_user::guest_space::{
-    page_align_down, page_align_up, page_size, GuestSpace,
+    page_align_down, page_align_up, page_size, GuestSpace, GUEST_STACK_GUARD,
+    MEMBARRIER_CMD_GET_REGISTRATIONS, MEMBARRIER_CMD_PRIVATE_EXPEDITED,
+    MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE, MEMBARRIER_CMD_QUERY,
+    MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED,
+    MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE,
+    SYS_RISCV_FLUSH_ICACHE_LOCAL,
 };
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
 
 #[test]
 fn test_create_and_drop() {
@@ -19,7 +28,7 @@ fn test_g2h_h2g_roundtrip() {
 
 #[test]
 fn test_mmap_fixed_and_write() {
-    let space = GuestSpace::new().unwrap();
+    let mut space = GuestSpace::new().unwrap();
     let addr: u64 = 0x10000;
     let size = page_size();
     space
@@ -47,3 +56,493 @@ fn test_page_align() {
     assert_eq!(page_align_down(ps - 1), 0);
     assert_eq!(page_align_down(ps), ps);
 }
+
+const RW: i32 = libc::PROT_READ | libc::PROT_WRITE;
+
+fn errno(e: std::io::Error) -> i32 {
+    e.raw_os_error().unwrap()
+}
+
+#[test]
+fn test_mremap_grow_preserves_contents() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let addr: u64 = 0x100000;
+    let mut mmap_next: u64 = 0x200000;
+    space.mmap_fixed(addr, ps, RW).unwrap();
+    unsafe {
+        space.write_bytes(addr, &vec![0xa5; ps]);
+    }
+    // Block in-place growth after the second doubling so the
+    // mapping has to move.
+    space.mmap_fixed(addr + 4 * ps as u64, ps, RW).unwrap();
+
+    let mut cur = addr;
+    let mut len = ps;
+    for _ in 0..4 {
+        let new = space
+            .mremap(cur, len, len * 2, libc::MREMAP_MAYMOVE, &mut mmap_next)
+            .unwrap();
+        let host = space.g2h(new);
+        let data = unsafe { std::slice::from_raw_parts(host, len) };
+        assert!(data.iter().all(|&b| b == 0xa5));
+        unsafe {
+            space.write_bytes(new + len as u64, &vec![0xa5; len]);
+        }
+        cur = new;
+        len *= 2;
+    }
+    assert_ne!(cur, addr);
+    assert_eq!(space.region_at(cur).unwrap().1.end, cur + len as u64);
+    // The old range went back to the reservation.
+    assert!(space.region_at(addr).is_none());
+    assert!(space
+        .take_invalidations()
+        .iter()
+        .any(|&(s, e)| s == addr && e == addr + 4 * ps as u64));
+}
+
+#[test]
+fn test_mremap_no_maymove_enomem() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let mut mmap_next: u64 = 0x200000;
+    space.mmap_fixed(0x100000, ps, RW).unwrap();
+    space.mmap_fixed(0x100000 + ps as u64, ps, RW).unwrap();
+    let e = space
+        .mremap(0x100000, ps, 2 * ps, 0, &mut mmap_next)
+        .unwrap_err();
+    assert_eq!(errno(e), libc::ENOMEM);
+}
+
+#[test]
+fn test_mremap_shrink_clamps_brk() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size() as u64;
+    let mut mmap_next: u64 = 0x200000;
+    space.mmap_fixed(0x100000, 4 * ps as usize, RW).unwrap();
+    space.set_brk(0x100000 + 3 * ps);
+    let r = space
+        .mremap(0x100000, 4 * ps as usize, ps as usize, 0, &mut mmap_next)
+        .unwrap();
+    assert_eq!(r, 0x100000);
+    assert_eq!(space.brk(), 0x100000 + ps);
+    assert_eq!(space.region_at(0x100000).unwrap().1.end, 0x100000 + ps);
+    assert!(space.region_at(0x100000 + ps).is_none());
+}
+
+/// A host file of `pages` pages, page `i` filled with `i + 1`.
+fn page_file(tag: &str, pages: usize) -> std::fs::File {
+    use std::io::Write;
+    let path = std::env::temp_dir()
+        .join(format!("tcg_mremap_{tag}_{}", std::process::id()));
+    let mut f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for i in 0..pages {
+        f.write_all(&vec![i as u8 + 1; page_size()]).unwrap();
+    }
+    f
+}
+
+#[test]
+fn test_mremap_file_grow_moves_and_keeps_contents() {
+    use std::os::fd::AsRawFd;
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let f = page_file("grow", 2);
+    let mut mmap_next: u64 = 0x200000;
+    space
+        .mmap_file(0x100000, ps, RW, libc::MAP_SHARED, f.as_raw_fd(), 0)
+        .unwrap();
+    assert!(space.region_at(0x100000).unwrap().1.file);
+
+    // The space after it is free, but anonymous pages there would
+    // read as zeros instead of the file's second page.
+    let e = space
+        .mremap(0x100000, ps, 2 * ps, 0, &mut mmap_next)
+        .unwrap_err();
+    assert_eq!(errno(e), libc::ENOMEM);
+    let new = space
+        .mremap(0x100000, ps, 2 * ps, libc::MREMAP_MAYMOVE, &mut mmap_next)
+        .unwrap();
+    assert_eq!(new, 0x200000);
+    let host = space.g2h(new);
+    let data = unsafe { std::slice::from_raw_parts(host, 2 * ps) };
+    assert!(data[..ps].iter().all(|&b| b == 1));
+    assert!(data[ps..].iter().all(|&b| b == 2));
+    let (_, r) = space.region_at(new).unwrap();
+    assert!(r.file);
+    assert_eq!(r.end, new + 2 * ps as u64);
+}
+
+#[test]
+fn test_mremap_keeps_prot_and_rejects_mixed_ranges() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let mut mmap_next: u64 = 0x200000;
+    space.mmap_fixed(0x100000, 2 * ps, libc::PROT_READ).unwrap();
+    space
+        .mprotect(0x100000 + ps as u64, ps, libc::PROT_READ | libc::PROT_WRITE)
+        .unwrap();
+    // The old range spans two protections.
+    let e = space
+        .mremap(
+            0x100000,
+            2 * ps,
+            4 * ps,
+            libc::MREMAP_MAYMOVE,
+            &mut mmap_next,
+        )
+        .unwrap_err();
+    assert_eq!(errno(e), libc::EFAULT);
+
+    // Growing the writable page alone extends it writable and
+    // leaves the read-only page as it was.
+    let hi = 0x100000 + ps as u64;
+    assert_eq!(space.mremap(hi, ps, 2 * ps, 0, &mut mmap_next).unwrap(), hi);
+    let (start, r) = space.region_at(hi + ps as u64).unwrap();
+    assert_eq!((start, r.end, r.prot), (hi, hi + 2 * ps as u64, RW));
+    let (_, r) = space.region_at(0x100000).unwrap();
+    assert_eq!((r.end, r.prot), (hi, libc::PROT_READ));
+}
+
+#[test]
+fn test_madvise_dontneed_zeroes_and_invalidates() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let addr: u64 = 0x100000;
+    space.mmap_fixed(addr, ps, RW).unwrap();
+    unsafe {
+        space.write_u64(addr, 0x0010_0513);
+    }
+    space.madvise(addr, ps, libc::MADV_DONTNEED).unwrap();
+    assert_eq!(unsafe { space.read_u64(addr) }, 0);
+    assert_eq!(space.take_invalidations(), vec![(addr, addr + ps as u64)]);
+
+    // Non-destructive hints do not invalidate.
+    space.madvise(addr, ps, libc::MADV_WILLNEED).unwrap();
+    assert!(space.take_invalidations().is_empty());
+}
+
+#[test]
+fn test_madvise_unmapped_enomem() {
+    let mut space = GuestSpace::new().unwrap();
+    let e = space
+        .madvise(0x100000, page_size(), libc::MADV_DONTNEED)
+        .unwrap_err();
+    assert_eq!(errno(e), libc::ENOMEM);
+}
+
+#[test]
+fn test_misaligned_einval() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    let mut mmap_next: u64 = 0x200000;
+    space.mmap_fixed(0x100000, 2 * ps, RW).unwrap();
+    let e = space
+        .madvise(0x100001, ps, libc::MADV_DONTNEED)
+        .unwrap_err();
+    assert_eq!(errno(e), libc::EINVAL);
+    let e = space
+        .mremap(0x100001, ps, 2 * ps, libc::MREMAP_MAYMOVE, &mut mmap_next)
+        .unwrap_err();
+    assert_eq!(errno(e), libc::EINVAL);
+    let e = space.msync(0x100001, ps, libc::MS_SYNC).unwrap_err();
+    assert_eq!(errno(e), libc::EINVAL);
+    let e = space.munmap(0x100001, ps).unwrap_err();
+    assert_eq!(errno(e), libc::EINVAL);
+}
+
+#[test]
+fn test_msync_mapped_ok() {
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size();
+    space.mmap_fixed(0x100000, ps, RW).unwrap();
+    space.msync(0x100000, ps, libc::MS_SYNC).unwrap();
+}
+
+// ── Stack guard ──────────────────────────────────────────────
+
+const STACK_TOP: u64 = 0x3000_0000;
+const STACK_SIZE: usize = 0x10_0000;
+const STACK_BASE: u64 = STACK_TOP - STACK_SIZE as u64;
+
+fn sys(space: &mut GuestSpace, nr: u64, args: &[u64]) -> i64 {
+    let mut regs = [0u64; 32];
+    regs[17] = nr;
+    regs[10..10 + args.len()].copy_from_slice(args);
+    let mut mmap_next = 0x1000_0000;
+    match handle_syscall(
+        space,
+        &mut Vfs::new(None),
+        &mut Process::new(),
+        &mut SignalTable::new(),
+        &mut regs,
+        &mut mmap_next,
+        "",
+        &SyscallPolicy::default(),
+    ) {
+        SyscallResult::Continue(v) => v as i64,
+        SyscallResult::Exit(c) => panic!("unexpected exit {c}"),
+    }
+}
+
+#[test]
+fn test_stack_guard_below_stack() {
+    let mut space = GuestSpace::new().unwrap();
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let guard_start = STACK_BASE - GUEST_STACK_GUARD as u64;
+    assert_eq!(space.stack_guard(), Some((guard_start, STACK_BASE)));
+    let (start, r) = space.region_at(STACK_BASE - 1).unwrap();
+    assert_eq!(start, guard_start);
+    assert!(r.guard);
+    assert_eq!(r.prot, libc::PROT_NONE);
+    assert!(!space.region_at(STACK_BASE).unwrap().1.guard);
+    assert!(!space.is_free(guard_start, guard_start + 0x1000));
+}
+
+#[test]
+fn test_stack_guard_size_configurable() {
+    let mut space = GuestSpace::new().unwrap().with_stack_guard(0x4001);
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let size = page_align_up(0x4001);
+    assert_eq!(space.stack_guard(), Some((STACK_BASE - size, STACK_BASE)));
+
+    let mut space = GuestSpace::new().unwrap().with_stack_guard(0);
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    assert_eq!(space.stack_guard(), None);
+    assert!(space.region_at(STACK_BASE - 1).is_none());
+}
+
+#[test]
+fn test_maps_shows_guard_as_gap() {
+    let mut space = GuestSpace::new().unwrap();
+    space
+        .mmap_fixed(0x10000, page_size(), libc::PROT_READ)
+        .unwrap();
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let maps = space.maps();
+    let lines: Vec<&str> = maps.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!("00010000-{:08x} r--p 00000000 00:00 0", 0x10000 + page_size()),
+            format!(
+                "{STACK_BASE:08x}-{STACK_TOP:08x} rw-p 00000000 00:00 0    [stack]"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_guard_not_mapped_for_guest() {
+    let mut space = GuestSpace::new().unwrap();
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let ps = page_size();
+    let page = STACK_BASE - ps as u64;
+    let e = space.mprotect(page, ps, RW).unwrap_err();
+    assert_eq!(errno(e), libc::ENOMEM);
+    let e = space.madvise(page, ps, libc::MADV_DONTNEED).unwrap_err();
+    assert_eq!(errno(e), libc::ENOMEM);
+    assert!(space.region_at(page).unwrap().1.guard);
+}
+
+#[test]
+fn test_mmap_hint_avoids_guard() {
+    const SYS_MMAP: u64 = 222;
+    let mut space = GuestSpace::new().unwrap();
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let hint = STACK_BASE - 2 * page_size() as u64;
+    let flags = (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS) as u64;
+    let len = page_size() as u64;
+    let got = sys(&mut space, SYS_MMAP, &[hint, len, RW as u64, flags]);
+    assert_eq!(got, 0x1000_0000);
+    assert!(space.stack_guard().is_some());
+
+    // A free hint is honoured.
+    let free = 0x2000_0000;
+    let got = sys(&mut space, SYS_MMAP, &[free, len, RW as u64, flags]);
+    assert_eq!(got, free as i64);
+}
+
+#[test]
+fn test_mmap_file_maps_contents() {
+    use std::os::fd::AsRawFd;
+    const SYS_MMAP: u64 = 222;
+    let mut space = GuestSpace::new().unwrap();
+    let f = page_file("sys", 2);
+    let ps = page_size() as u64;
+    let args = [
+        0,
+        ps,
+        libc::PROT_READ as u64,
+        libc::MAP_PRIVATE as u64,
+        f.as_raw_fd() as u64,
+        ps,
+    ];
+    let got = sys(&mut space, SYS_MMAP, &args) as u64;
+    assert_eq!(got, 0x1000_0000);
+    assert_eq!(unsafe { space.read_u64(got) }, 0x0202_0202_0202_0202);
+    assert!(space.region_at(got).unwrap().1.file);
+
+    let mut bad = args;
+    bad[0] = 0x2000_0000;
+    bad[4] = 9999;
+    assert_eq!(sys(&mut space, SYS_MMAP, &bad), -libc::EBADF as i64);
+}
+
+#[test]
+fn test_prlimit_stack_reflected_in_reads() {
+    const SYS_PRLIMIT64: u64 = 261;
+    const RLIMIT_STACK: u64 = 3;
+    let mut space = GuestSpace::new().unwrap();
+    space.map_stack(STACK_TOP, STACK_SIZE).unwrap();
+    let (new, old) = (STACK_TOP - 0x100, STACK_TOP - 0x80);
+    unsafe {
+        space.write_u64(new, 0x2000);
+        space.write_u64(new + 8, 0x10_0000);
+    }
+    assert_eq!(
+        sys(&mut space, SYS_PRLIMIT64, &[0, RLIMIT_STACK, new, old]),
+        0
+    );
+    assert_eq!(unsafe { space.read_u64(old) }, 8 * 1024 * 1024);
+    assert_eq!(unsafe { space.read_u64(old + 8) }, u64::MAX);
+
+    assert_eq!(
+        sys(&mut space, SYS_PRLIMIT64, &[0, RLIMIT_STACK, 0, old]),
+        0
+    );
+    assert_eq!(unsafe { space.read_u64(old) }, 0x2000);
+    assert_eq!(unsafe { space.read_u64(old + 8) }, 0x10_0000);
+    assert_eq!(space.stack_rlimit(), (0x2000, 0x10_0000));
+
+    // The live stack stays mapped below the new soft limit.
+    unsafe { space.write_u64(STACK_BASE, 0x55) };
+    assert_eq!(unsafe { space.read_u64(STACK_BASE) }, 0x55);
+}
+
+#[test]
+fn test_prlimit_stack_errors() {
+    let mut space = GuestSpace::new().unwrap();
+    let e = space.set_stack_rlimit(0x2000, 0x1000).unwrap_err();
+    assert_eq!(errno(e), libc::EINVAL);
+    space.set_stack_rlimit(0x1000, 0x2000).unwrap();
+    let e = space.set_stack_rlimit(0x1000, 0x4000).unwrap_err();
+    assert_eq!(errno(e), libc::EPERM);
+    assert_eq!(space.stack_rlimit(), (0x1000, 0x2000));
+}
+
+#[test]
+fn test_mmio_store_dispatch() {
+    use std::sync::{Arc, Mutex};
+    use tcg_linux_user::fault::SegvCode;
+
+    let mut space = GuestSpace::new().unwrap();
+    let ps = page_size() as u64;
+    space
+        .mmap_fixed(0x10000, ps as usize, libc::PROT_READ | libc::PROT_WRITE)
+        .unwrap();
+    assert_eq!(space.mmio_window(), None);
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&seen);
+    let store = Box::new(move |off, val, size| {
+        log.lock().unwrap().push((off, val, size));
+    });
+    space.register_mmio(0x10010, 0x10018, store);
+    let noop = Box::new(|_, _, _| {});
+    space.register_mmio(0x10040, 0x10048, noop);
+    assert_eq!(space.mmio_window(), Some((0x10010, 0x10048)));
+
+    space.mmio_store(0x10012, 0xab, 1).unwrap();
+    assert_eq!(*seen.lock().unwrap(), [(2, 0xab, 1)]);
+
+    // Between the regions: ordinary memory.
+    space.mmio_store(0x10020, 0x1122_3344, 4).unwrap();
+    assert_eq!(unsafe { space.read_u64(0x10020) }, 0x1122_3344);
+    assert_eq!(seen.lock().unwrap().len(), 1);
+
+    assert_eq!(space.mmio_store(0x20000, 0, 8), Err(SegvCode::MapErr));
+}
+
+#[test]
+fn test_new_sized_space() {
+    let space = GuestSpace::new_sized(3 << 30).unwrap();
+    assert_eq!(space.size(), 3 << 30);
+    assert!(space.range_ok(0x8000_0000, 0x1000));
+    assert!(!GuestSpace::new().unwrap().range_ok(0x8000_0000, 0x1000));
+}
+
+#[test]
+fn test_flush_icache_queues_range() {
+    let mut space = GuestSpace::new().unwrap();
+    space.flush_icache(0x1000, 0x1040, 0).unwrap();
+    space
+        .flush_icache(0x2000, 0x2004, SYS_RISCV_FLUSH_ICACHE_LOCAL)
+        .unwrap();
+    // Empty range: nothing to do.
+    space.flush_icache(0x3000, 0x3000, 0).unwrap();
+    assert_eq!(
+        space.take_invalidations(),
+        vec![(0x1000, 0x1040), (0x2000, 0x2004)]
+    );
+
+    let e = space.flush_icache(0x1000, 0x1040, 2).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+    assert!(space.take_invalidations().is_empty());
+}
+
+#[test]
+fn test_membarrier_registration() {
+    let mut space = GuestSpace::new().unwrap();
+    let errno = |r: std::io::Result<u64>| r.unwrap_err().raw_os_error();
+
+    let supported = space.membarrier(MEMBARRIER_CMD_QUERY, 0).unwrap();
+    assert_eq!(supported, 0x7f);
+    assert_eq!(
+        errno(space.membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED, 0)),
+        Some(libc::EPERM)
+    );
+    assert_eq!(
+        errno(space.membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE, 0)),
+        Some(libc::EPERM)
+    );
+
+    space
+        .membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED, 0)
+        .unwrap();
+    space
+        .membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED, 0)
+        .unwrap();
+    assert!(space.take_invalidations().is_empty());
+
+    space
+        .membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE, 0)
+        .unwrap();
+    space
+        .membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE, 0)
+        .unwrap();
+    assert_eq!(space.take_invalidations(), vec![(0, u64::MAX)]);
+    assert_eq!(
+        space
+            .membarrier(MEMBARRIER_CMD_GET_REGISTRATIONS, 0)
+            .unwrap(),
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE
+    );
+
+    // Flags, RSEQ commands and unknown commands.
+    assert_eq!(
+        errno(space.membarrier(MEMBARRIER_CMD_QUERY, 1)),
+        Some(libc::EINVAL)
+    );
+    assert_eq!(errno(space.membarrier(1 << 7, 0)), Some(libc::EINVAL));
+    assert_eq!(errno(space.membarrier(3, 0)), Some(libc::EINVAL));
+}
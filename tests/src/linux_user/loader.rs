@@ -11,17 +11,22 @@ use tcg_linux_user::elf::{
 use tcg_linux_user::guest_space::{
     GuestSpace, GUEST_STACK_SIZE, GUEST_STACK_TOP,
 };
-use tcg_linux_user::loader::load_elf;
+use tcg_linux_user::loader::{is_elf, load_elf, load_flat, LoadError};
 
 static COUNTER: AtomicU32 = AtomicU32::new(0);
 
 /// Build a minimal valid RISC-V ELF in memory.
 fn make_minimal_elf() -> Vec<u8> {
+    // Minimal code: RISC-V NOP (addi x0,x0,0)
+    make_elf(&[0x13, 0x00, 0x00, 0x00])
+}
+
+/// Build a RISC-V ELF with one PT_LOAD segment holding `code`,
+/// entered at its first byte.
+pub(crate) fn make_elf(code: &[u8]) -> Vec<u8> {
     let ehdr_sz = mem::size_of::<Elf64Ehdr>();
     let phdr_sz = mem::size_of::<Elf64Phdr>();
     let code_offset = ehdr_sz + phdr_sz;
-    // Minimal code: RISC-V NOP (addi x0,x0,0)
-    let code: [u8; 4] = [0x13, 0x00, 0x00, 0x00];
     let file_size = code_offset + code.len();
     let load_vaddr: u64 = 0x10000;
 
@@ -72,23 +77,23 @@ fn make_minimal_elf() -> Vec<u8> {
     buf[ph_off + 48..ph_off + 56].copy_from_slice(&4096u64.to_le_bytes());
 
     // Code
-    buf[code_offset..code_offset + code.len()].copy_from_slice(&code);
+    buf[code_offset..code_offset + code.len()].copy_from_slice(code);
 
     buf
 }
 
 /// Simple temp file helper.
-struct TempFile {
+pub(crate) struct TempFile {
     path: std::path::PathBuf,
     file: fs::File,
 }
 
 impl TempFile {
-    fn path(&self) -> &Path {
+    pub(crate) fn path(&self) -> &Path {
         &self.path
     }
 
-    fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
         self.file.write_all(data)?;
         self.file.flush()
     }
@@ -100,7 +105,7 @@ impl Drop for TempFile {
     }
 }
 
-fn tempfile() -> std::io::Result<TempFile> {
+pub(crate) fn tempfile() -> std::io::Result<TempFile> {
     let pid = std::process::id();
     let n = COUNTER.fetch_add(1, Ordering::Relaxed);
     let path =
@@ -196,3 +201,35 @@ fn test_stack_layout() {
         assert!(execfn.ends_with(".bin"));
     }
 }
+
+#[test]
+fn test_load_flat_image() {
+    let image = [0x13, 0, 0, 0, 0x73, 0, 0, 0]; // nop; ecall
+    assert!(!is_elf(&image));
+    assert!(is_elf(&make_minimal_elf()));
+
+    let mut space = GuestSpace::new().unwrap();
+    let info = load_flat(&image, &mut space, 0x100_0000, 0x100_0004, 0x10000)
+        .expect("load flat image");
+    assert_eq!(info.entry, 0x100_0004);
+    assert_eq!(info.sp, 0x101_0000);
+    assert_eq!(info.exec_ranges, [(0x100_0000, 0x100_0008)]);
+    assert_eq!(unsafe { space.read_u64(0x100_0000) }, 0x73_0000_0013);
+    // All of RAM is mapped, up to the stack top.
+    assert!(space.region_at(info.sp - 1).is_some());
+}
+
+#[test]
+fn test_load_flat_out_of_range() {
+    let image = [0u8; 16];
+    let mut space = GuestSpace::new().unwrap();
+    // RAM past the guest space.
+    let r = load_flat(&image, &mut space, 0x8000_0000, 0x8000_0000, 0x1000);
+    assert!(matches!(r, Err(LoadError::SegmentOutOfRange)));
+    // Image larger than RAM.
+    let r = load_flat(&image, &mut space, 0x10000, 0x10000, 8);
+    assert!(matches!(r, Err(LoadError::SegmentOutOfRange)));
+    // Entry outside RAM.
+    let r = load_flat(&image, &mut space, 0x10000, 0x20000, 0x1000);
+    assert!(matches!(r, Err(LoadError::SegmentOutOfRange)));
+}
@@ -0,0 +1,262 @@
+//! Flat images: SBI calls, the UART and a bare-metal run.
+
+use std::io::Write;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::machine::{
+    map_uart, sbi_call, EcallMode, SBI_CONSOLE_PUTCHAR, SBI_EXT_SRST,
+    SBI_SHUTDOWN,
+};
+use tcg_linux_user::syscall::SyscallResult;
+
+use super::loader::tempfile;
+use super::runner_bin;
+
+fn sbi(a7: u64, a0: u64, a1: u64, out: &mut Vec<u8>) -> SyscallResult {
+    let mut gpr = [0u64; 32];
+    (gpr[10], gpr[11], gpr[17]) = (a0, a1, a7);
+    sbi_call(&gpr, out)
+}
+
+#[test]
+fn test_sbi_calls() {
+    let mut out = Vec::new();
+    assert!(matches!(
+        sbi(SBI_CONSOLE_PUTCHAR, b'x' as u64, 0, &mut out),
+        SyscallResult::Continue(0)
+    ));
+    assert_eq!(out, b"x");
+    assert!(matches!(
+        sbi(SBI_SHUTDOWN, 3, 0, &mut out),
+        SyscallResult::Exit(3)
+    ));
+    assert!(matches!(
+        sbi(SBI_EXT_SRST, 0, 0, &mut out),
+        SyscallResult::Exit(0)
+    ));
+    assert!(matches!(
+        sbi(SBI_EXT_SRST, 0, 1, &mut out),
+        SyscallResult::Exit(1)
+    ));
+    // Base extension: not provided.
+    assert!(matches!(
+        sbi(0x10, 0, 0, &mut out),
+        SyscallResult::Continue(r) if r as i64 == -2
+    ));
+    assert_eq!(EcallMode::parse("sbi"), Some(EcallMode::Sbi));
+    assert_eq!(EcallMode::parse("semihosting"), None);
+}
+
+/// `Write` into a shared buffer.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_uart_transmits_thr_stores() {
+    let mut space = GuestSpace::new().unwrap();
+    let out = Shared::default();
+    map_uart(&mut space, 0x1000_0000, out.clone()).unwrap();
+    assert_eq!(space.mmio_window(), Some((0x1000_0000, 0x1000_0008)));
+    // LSR reads as an idle transmitter.
+    let lsr = unsafe { *space.g2h(0x1000_0005) };
+    assert_eq!(lsr & 0x60, 0x60);
+
+    for b in b"ok" {
+        space.mmio_store(0x1000_0000, *b as u64, 1).unwrap();
+    }
+    // LCR: ignored.
+    space.mmio_store(0x1000_0003, 0x03, 1).unwrap();
+    assert_eq!(*out.0.lock().unwrap(), b"ok");
+    assert_eq!(unsafe { *space.g2h(0x1000_0005) }, lsr);
+
+    // A second device on the same page is refused.
+    assert!(map_uart(&mut space, 0x1000_0100, out).is_err());
+}
+
+// -- Bare-metal run --------------------------------------------
+
+fn rv_i(imm: i32, rs1: u32, f3: u32, rd: u32, op: u32) -> u32 {
+    ((imm as u32) << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op
+}
+
+fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b000, rd, 0b0010011)
+}
+
+fn lbu(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b100, rd, 0b0000011)
+}
+
+fn sb(rs2: u32, rs1: u32) -> u32 {
+    (rs2 << 20) | (rs1 << 15) | 0b0100011
+}
+
+fn lui(rd: u32, imm: u32) -> u32 {
+    (imm << 12) | (rd << 7) | 0b0110111
+}
+
+fn auipc(rd: u32) -> u32 {
+    (rd << 7) | 0b0010111
+}
+
+fn beqz(rs1: u32, imm: u32) -> u32 {
+    // imm[4:1] and imm[11] only: short forward branches.
+    (rs1 << 15) | ((imm & 0x1e) << 7) | 0b1100011
+}
+
+fn j_back(imm: i32) -> u32 {
+    let i = imm as u32;
+    ((i >> 20) & 1) << 31
+        | ((i >> 1) & 0x3ff) << 21
+        | ((i >> 11) & 1) << 20
+        | ((i >> 12) & 0xff) << 12
+        | 0b1101111
+}
+
+fn lw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b010, rd, 0b0000011)
+}
+
+fn sw(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let i = imm as u32;
+    ((i >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (0b010 << 12)
+        | ((i & 0x1f) << 7)
+        | 0b0100011
+}
+
+fn jalr(rd: u32, rs1: u32, imm: i32) -> u32 {
+    rv_i(imm, rs1, 0b000, rd, 0b1100111)
+}
+
+fn slli(rd: u32, rs1: u32, shamt: i32) -> u32 {
+    rv_i(shamt, rs1, 0b001, rd, 0b0010011)
+}
+
+fn add(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (rs2 << 20) | (rs1 << 15) | (rd << 7) | 0b0110011
+}
+
+const ECALL: u32 = 0x73;
+
+/// Print `msg` through the UART at 0x1000_0000, `!` through
+/// SBI putchar, then shut down with status 7.
+fn uart_image(msg: &[u8]) -> Vec<u8> {
+    let code = [
+        lui(5, 0x10000),  // 0: t0 = UART
+        auipc(11),        // 4: a1 = pc
+        addi(11, 11, 52), // 8: a1 = msg
+        lbu(6, 11, 0),    // 12: t1 = *a1
+        beqz(6, 16),      // 16: done at 32
+        sb(6, 5),         // 20: THR = t1
+        addi(11, 11, 1),  // 24
+        j_back(-16),      // 28: loop at 12
+        addi(10, 0, 33),  // 32: a0 = '!'
+        addi(17, 0, 1),   // 36: putchar
+        ECALL,            // 40
+        addi(10, 0, 7),   // 44: a0 = 7
+        addi(17, 0, 8),   // 48: shutdown
+        ECALL,            // 52
+    ];
+    let mut image: Vec<u8> =
+        code.iter().flat_map(|i| i.to_le_bytes()).collect();
+    assert_eq!(image.len(), 56);
+    image.extend_from_slice(msg);
+    image.push(0);
+    image
+}
+
+#[test]
+fn test_flat_image_uart_and_sbi_exit() {
+    let mut file = tempfile().unwrap();
+    file.write_all(&uart_image(b"hello, uart\n")).unwrap();
+    let out = Command::new(runner_bin())
+        .args(["-kernel", file.path().to_str().unwrap()])
+        .args(["-load-addr", "0x80000000", "-entry", "0x80000000"])
+        .args(["-ecall", "sbi", "-uart", "0x10000000"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(7), "stderr: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hello, uart\n!");
+}
+
+/// Files without ELF magic are flat images without `-kernel`.
+#[test]
+fn test_flat_image_autodetected() {
+    let mut file = tempfile().unwrap();
+    file.write_all(&uart_image(b"auto\n")).unwrap();
+    let out = Command::new(runner_bin())
+        .args(["-ecall", "sbi", "-uart", "0x10000000"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(7));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "auto\n!");
+}
+
+/// Rewrite the function at 96 twice, flushing the icache after
+/// each rewrite, and exit with `first + 4 * second`: 9 when the
+/// second call runs the new code, 5 when it runs a stale TB.
+fn smc_image() -> Vec<u8> {
+    let (f, v1, v2) = (96, 104, 108);
+    let mut code = vec![auipc(8)]; // 0: s0 = base
+    for (v, flags, rd) in [(v1, 0, 9), (v2, 1, 10)] {
+        code.extend([
+            lw(6, 8, v),        // t1 = new insn
+            sw(6, 8, f),        // install it
+            addi(10, 8, f),     // a0 = start
+            addi(11, 10, 8),    // a1 = end
+            addi(12, 0, flags), // a2 = flags
+            addi(17, 0, 259),   // riscv_flush_icache
+            ECALL,              //
+            jalr(1, 8, f),      // call it
+            addi(rd, 10, 0),    // keep the result in rd
+        ]);
+    }
+    code.extend([
+        slli(10, 10, 2), // a0 *= 4
+        add(10, 10, 9),  // a0 += s1
+        addi(17, 0, 93), // exit
+        ECALL,
+    ]);
+    code.resize(f as usize / 4, 0);
+    code.extend([
+        addi(10, 0, 0), // f: overwritten
+        jalr(0, 1, 0),  // ret
+        addi(10, 0, 1), // v1
+        addi(10, 0, 2), // v2
+    ]);
+    code.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+#[test]
+fn test_flush_icache_retranslates_modified_code() {
+    let mut file = tempfile().unwrap();
+    file.write_all(&smc_image()).unwrap();
+    let out = Command::new(runner_bin())
+        .args(["-stats", "-kernel", file.path().to_str().unwrap()])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(9), "stderr: {stderr}");
+    let invalidated = stderr
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("invalidated:"))
+        .and_then(|n| n.trim().parse::<u64>().ok());
+    assert!(invalidated >= Some(1), "stderr: {stderr}");
+}
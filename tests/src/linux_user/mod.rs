@@ -1,7 +1,20 @@
+mod checkpoint;
+mod config;
+mod coverage;
 mod elf;
+mod excp;
+mod fault;
 mod guest_space;
-mod loader;
+pub(crate) mod loader;
+mod machine;
+mod process;
+mod signal;
+mod snapshot;
+mod socket;
+mod vfs;
+mod warmup;
 
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
 use std::sync::OnceLock;
@@ -62,6 +75,14 @@ const GUEST_TESTS: &[GuestTest] = &[
             "argc=3\narg1=foo\narg2=bar baz\n",
         ),
     },
+    GuestTest {
+        name: "mmap_ops",
+        elf: "mmap_ops",
+        args: &[],
+        expected_stdout: StdoutExpectation::Exact(
+            "mremap=0\nmadvise=0\neinval=0\n",
+        ),
+    },
 ];
 
 fn has_riscv_gcc() -> bool {
@@ -224,6 +245,105 @@ fn guest_argv_echo() {
     assert_guest(&GUEST_TESTS[4]);
 }
 
+#[test]
+fn guest_mmap_ops() {
+    ensure_built();
+    assert_guest(&GUEST_TESTS[5]);
+}
+
+#[test]
+fn guest_net_client() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+
+    ensure_built();
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let refused = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ports = [&tcp.local_addr(), &udp.local_addr(), &refused.local_addr()]
+        .map(|a| a.as_ref().unwrap().port().to_string());
+    drop(refused);
+
+    let tcp_server = std::thread::spawn(move || {
+        let (mut s, _) = tcp.accept().unwrap();
+        let mut got = [0u8; 5];
+        s.read_exact(&mut got).unwrap();
+        s.write_all(b"pong!").unwrap();
+        got
+    });
+    let udp_server = std::thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        let (n, from) = udp.recv_from(&mut buf).unwrap();
+        udp.send_to(&buf[..n], from).unwrap();
+    });
+
+    let args: Vec<&str> = ports.iter().map(String::as_str).collect();
+    let out = run_guest("net_client", &args);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "net_client: exit {:?}\nstdout: {stdout}\nstderr: {}",
+        out.status.code(),
+        String::from_utf8_lossy(&out.stderr),
+    );
+    assert_eq!(stdout, "tcp=pong!\nudp=datagram\nrefused=ECONNREFUSED\n");
+    assert_eq!(&tcp_server.join().unwrap(), b"ping!");
+    udp_server.join().unwrap();
+}
+
+/// Run `devices` with stdout on a pty; returns its output
+/// with the pty's CRLF folded back to LF.
+fn run_devices_on_pty(opts: &[&str]) -> String {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+    use std::process::Stdio;
+
+    let (mut master, mut slave) = (0, 0);
+    let r = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(r, 0, "openpty: {}", std::io::Error::last_os_error());
+    let elf = workspace_root().join("target/guest/riscv64/devices");
+    let status = Command::new(runner_bin())
+        .args(opts)
+        .arg(&elf)
+        .stdout(unsafe { Stdio::from_raw_fd(slave) })
+        .status()
+        .expect("failed to run tcg-riscv64");
+    assert!(status.success(), "devices {opts:?}: {status}");
+
+    // The slave is closed, so reading drains the output and
+    // then fails with EIO.
+    let mut master = unsafe { File::from_raw_fd(master) };
+    let mut out = Vec::new();
+    let _ = master.read_to_end(&mut out);
+    String::from_utf8_lossy(&out).replace("\r\n", "\n")
+}
+
+#[test]
+fn guest_devices() {
+    ensure_built();
+    let inherited = run_devices_on_pty(&["-seed", "7"]);
+    let urandom = inherited.lines().next().unwrap().to_string();
+    assert_eq!(urandom.len(), "urandom=".len() + 32, "{inherited}");
+    assert!(
+        inherited.ends_with("zero=0\nnull=16\ntty=1\n"),
+        "{inherited}"
+    );
+
+    let captured = run_devices_on_pty(&["-seed", "7", "-captured-stdio"]);
+    assert_eq!(captured, inherited.replace("tty=1", "tty=0"));
+
+    let reseeded = run_devices_on_pty(&["-seed", "8"]);
+    assert!(!reseeded.starts_with(&urandom), "{reseeded}");
+}
+
 #[test]
 fn guest_summary() {
     if !has_riscv_gcc() {
@@ -0,0 +1,278 @@
+//! Process groups, sessions, job-control tty ioctls and CPU
+//! time reporting.
+
+use std::time::Duration;
+
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
+
+const SYS_IOCTL: u64 = 29;
+const SYS_TIMES: u64 = 153;
+const SYS_SETPGID: u64 = 154;
+const SYS_GETPGID: u64 = 155;
+const SYS_GETSID: u64 = 156;
+const SYS_SETSID: u64 = 157;
+const SYS_GETRUSAGE: u64 = 165;
+const SYS_GETPID: u64 = 172;
+const SYS_GETPPID: u64 = 173;
+const SYS_WAIT4: u64 = 260;
+
+const TIOCSCTTY: u64 = 0x540e;
+const TIOCGPGRP: u64 = 0x540f;
+const TIOCSPGRP: u64 = 0x5410;
+const TIOCGSID: u64 = 0x5429;
+
+const WNOHANG: u64 = 1;
+const WUNTRACED: u64 = 2;
+
+const EPERM: i64 = -1;
+const ESRCH: i64 = -3;
+const ECHILD: i64 = -10;
+const EINVAL: i64 = -22;
+const ENOTTY: i64 = -25;
+
+const MEM: u64 = 0x10000;
+const BUF: u64 = MEM + 0x100;
+
+struct Guest {
+    space: GuestSpace,
+    vfs: Vfs,
+    process: Process,
+}
+
+impl Guest {
+    fn new() -> Self {
+        let mut space = GuestSpace::new().unwrap();
+        space
+            .mmap_fixed(MEM, 0x1000, libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        Self {
+            space,
+            vfs: Vfs::new(None),
+            process: Process::new(),
+        }
+    }
+
+    fn sys(&mut self, nr: u64, args: &[u64]) -> i64 {
+        let mut regs = [0u64; 32];
+        regs[17] = nr;
+        regs[10..10 + args.len()].copy_from_slice(args);
+        match handle_syscall(
+            &mut self.space,
+            &mut self.vfs,
+            &mut self.process,
+            &mut SignalTable::new(),
+            &mut regs,
+            &mut 0,
+            "",
+            &SyscallPolicy::default(),
+        ) {
+            SyscallResult::Continue(v) => v as i64,
+            SyscallResult::Exit(c) => panic!("unexpected exit {c}"),
+        }
+    }
+
+    fn get_u32(&self, addr: u64) -> u32 {
+        let p = self.space.g2h(addr);
+        u32::from_le_bytes(unsafe { *(p as *const [u8; 4]) })
+    }
+
+    fn get_u64(&self, addr: u64) -> u64 {
+        let p = self.space.g2h(addr);
+        u64::from_le_bytes(unsafe { *(p as *const [u8; 8]) })
+    }
+
+    fn put_u32(&self, addr: u64, v: u32) {
+        unsafe { self.space.write_bytes(addr, &v.to_le_bytes()) };
+    }
+}
+
+fn openpty() -> (libc::c_int, libc::c_int) {
+    let (mut master, mut slave) = (0, 0);
+    let r = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(r, 0, "openpty: {}", std::io::Error::last_os_error());
+    (master, slave)
+}
+
+#[test]
+fn ids_of_the_first_process() {
+    let mut g = Guest::new();
+    assert_eq!(g.sys(SYS_GETPID, &[]), 1);
+    assert_eq!(g.sys(SYS_GETPPID, &[]), 0);
+    assert_eq!(g.sys(SYS_GETPGID, &[0]), 0);
+    assert_eq!(g.sys(SYS_GETPGID, &[1]), 0);
+    assert_eq!(g.sys(SYS_GETSID, &[0]), 0);
+    assert_eq!(g.sys(SYS_GETPGID, &[2]), ESRCH);
+    assert_eq!(g.sys(SYS_GETSID, &[2]), ESRCH);
+}
+
+/// `setpgid(0, 0)` as a shell does for a job, then `setsid`
+/// refused to the group leader.
+#[test]
+fn setpgid_makes_a_group_leader() {
+    let mut g = Guest::new();
+    assert_eq!(g.sys(SYS_SETPGID, &[0, -1i64 as u64]), EINVAL);
+    assert_eq!(g.sys(SYS_SETPGID, &[2, 0]), ESRCH);
+    assert_eq!(g.sys(SYS_SETPGID, &[0, 7]), EPERM);
+    assert_eq!(g.sys(SYS_SETPGID, &[0, 0]), 0);
+    assert_eq!(g.sys(SYS_GETPGID, &[0]), 1);
+    // Already in its own group.
+    assert_eq!(g.sys(SYS_SETPGID, &[1, 1]), 0);
+    assert_eq!(g.sys(SYS_SETSID, &[]), EPERM);
+    assert_eq!(g.sys(SYS_GETSID, &[0]), 0);
+}
+
+#[test]
+fn setsid_leads_a_new_session() {
+    let mut g = Guest::new();
+    assert_eq!(g.sys(SYS_SETSID, &[]), 1);
+    assert_eq!(g.sys(SYS_GETSID, &[0]), 1);
+    assert_eq!(g.sys(SYS_GETPGID, &[0]), 1);
+    assert_eq!(g.sys(SYS_SETSID, &[]), EPERM);
+    // A session leader cannot change its group.
+    assert_eq!(g.sys(SYS_SETPGID, &[0, 0]), EPERM);
+}
+
+#[test]
+fn wait4_without_children() {
+    let mut g = Guest::new();
+    for pid in [-1i64, 0, 5, -5] {
+        let r = g.sys(SYS_WAIT4, &[pid as u64, BUF, WNOHANG, 0]);
+        assert_eq!(r, ECHILD, "wait4({pid})");
+    }
+    let r = g.sys(SYS_WAIT4, &[-1i64 as u64, BUF, WUNTRACED, 0]);
+    assert_eq!(r, ECHILD);
+    let r = g.sys(SYS_WAIT4, &[-1i64 as u64, BUF, 0x100, 0]);
+    assert_eq!(r, EINVAL);
+}
+
+#[test]
+fn job_control_ioctls_need_a_tty() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut g = Guest::new();
+    let fd = fds[0] as u64;
+    for req in [TIOCGPGRP, TIOCSPGRP, TIOCGSID, TIOCSCTTY] {
+        assert_eq!(g.sys(SYS_IOCTL, &[fd, req, BUF]), ENOTTY, "{req:#x}");
+    }
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}
+
+/// A shell moving its job into the foreground of a terminal.
+/// The foreground group is emulated, so the host pty keeps the
+/// test's own.
+#[test]
+fn foreground_group_on_pty() {
+    let (master, slave) = openpty();
+    let mut g = Guest::new();
+    let fd = slave as u64;
+
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGPGRP, BUF]), 0);
+    assert_eq!(g.get_u32(BUF), 0);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGSID, BUF]), 0);
+    assert_eq!(g.get_u32(BUF), 0);
+
+    g.put_u32(BUF, 1);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCSPGRP, BUF]), ESRCH);
+    assert_eq!(g.sys(SYS_SETPGID, &[0, 0]), 0);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCSPGRP, BUF]), 0);
+    g.put_u32(BUF, 0);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGPGRP, BUF]), 0);
+    assert_eq!(g.get_u32(BUF), 1);
+    g.put_u32(BUF, -1i32 as u32);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCSPGRP, BUF]), EINVAL);
+    assert_eq!(unsafe { libc::tcgetpgrp(slave) }, -1);
+
+    unsafe {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+/// After `setsid` the terminal is no longer controlling until
+/// `TIOCSCTTY`.
+#[test]
+fn setsid_drops_the_controlling_tty() {
+    let (master, slave) = openpty();
+    let mut g = Guest::new();
+    let fd = slave as u64;
+
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCSCTTY, 0]), EPERM);
+    assert_eq!(g.sys(SYS_SETSID, &[]), 1);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGPGRP, BUF]), ENOTTY);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCSCTTY, 0]), 0);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGPGRP, BUF]), 0);
+    assert_eq!(g.get_u32(BUF), 1);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGSID, BUF]), 0);
+    assert_eq!(g.get_u32(BUF), 1);
+
+    unsafe {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+/// Burn some CPU time of the calling thread.
+fn spin() -> Duration {
+    let start = tcg_exec::budget::thread_cpu_time();
+    let mut x = 0u64;
+    while tcg_exec::budget::thread_cpu_time() - start
+        < Duration::from_millis(30)
+    {
+        x = std::hint::black_box(x.wrapping_add(1));
+    }
+    tcg_exec::budget::thread_cpu_time()
+}
+
+/// User time is what the exec loop reported as guest time,
+/// system time the rest of the thread's CPU time.
+#[test]
+fn getrusage_splits_guest_and_emulator_time() {
+    let mut g = Guest::new();
+    let total = spin();
+    g.process.set_guest_cpu(Duration::from_millis(12_345));
+    assert_eq!(g.sys(SYS_GETRUSAGE, &[0, BUF]), 0);
+    assert_eq!((g.get_u64(BUF), g.get_u64(BUF + 8)), (12, 345_000));
+    let stime = g.get_u64(BUF + 16) * 1_000_000 + g.get_u64(BUF + 24);
+    assert_eq!(stime, 0, "guest time above the thread's is clamped");
+
+    g.process.set_guest_cpu(total / 2);
+    assert_eq!(g.sys(SYS_GETRUSAGE, &[1, BUF]), 0);
+    let utime = g.get_u64(BUF) * 1_000_000 + g.get_u64(BUF + 8);
+    let stime = g.get_u64(BUF + 16) * 1_000_000 + g.get_u64(BUF + 24);
+    assert_eq!(utime, (total / 2).as_micros() as u64);
+    assert!(stime as u128 >= (total / 2).as_micros() - 1, "{stime}");
+    // ru_maxrss is the host's.
+    assert!(g.get_u64(BUF + 32) > 0);
+
+    g.put_u32(BUF, 0xffff_ffff);
+    assert_eq!(g.sys(SYS_GETRUSAGE, &[-1i64 as u64, BUF]), 0);
+    assert!((0..144).step_by(8).all(|o| g.get_u64(BUF + o) == 0));
+    assert_eq!(g.sys(SYS_GETRUSAGE, &[2, BUF]), EINVAL);
+}
+
+#[test]
+fn times_reports_guest_ticks() {
+    let mut g = Guest::new();
+    spin();
+    g.process.set_guest_cpu(Duration::from_millis(250));
+    let now = g.sys(SYS_TIMES, &[BUF]);
+    assert!(now > 0);
+    assert_eq!(g.get_u64(BUF), 25);
+    assert_eq!((g.get_u64(BUF + 16), g.get_u64(BUF + 24)), (0, 0));
+    assert!(g.sys(SYS_TIMES, &[0]) >= now);
+}
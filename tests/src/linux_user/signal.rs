@@ -0,0 +1,128 @@
+//! Guest signal dispositions recorded by `rt_sigaction`.
+
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::{GuestSigaction, SignalTable};
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
+
+const SYS_RT_SIGACTION: u64 = 134;
+
+const EINVAL: i64 = -22;
+const EFAULT: i64 = -14;
+
+const MEM: u64 = 0x10000;
+const ACT: u64 = MEM;
+const OACT: u64 = MEM + 0x100;
+
+const SIGKILL: u64 = 9;
+const SIGSEGV: u64 = 11;
+const SIGBUS: u64 = 7;
+const SA_SIGINFO: u64 = 0x4;
+
+struct Guest {
+    space: GuestSpace,
+    signals: SignalTable,
+}
+
+impl Guest {
+    fn new() -> Self {
+        let mut space = GuestSpace::new().unwrap();
+        space
+            .mmap_fixed(MEM, 0x1000, libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        Self {
+            space,
+            signals: SignalTable::new(),
+        }
+    }
+
+    fn sigaction(&mut self, sig: u64, act: u64, oact: u64, size: u64) -> i64 {
+        let mut regs = [0u64; 32];
+        regs[17] = SYS_RT_SIGACTION;
+        regs[10..14].copy_from_slice(&[sig, act, oact, size]);
+        match handle_syscall(
+            &mut self.space,
+            &mut Vfs::new(None),
+            &mut Process::new(),
+            &mut self.signals,
+            &mut regs,
+            &mut 0,
+            "",
+            &SyscallPolicy::default(),
+        ) {
+            SyscallResult::Continue(v) => v as i64,
+            SyscallResult::Exit(c) => panic!("unexpected exit {c}"),
+        }
+    }
+
+    fn put_action(&self, a: GuestSigaction) {
+        let mut raw = Vec::new();
+        for w in [a.handler, a.flags, a.mask] {
+            raw.extend_from_slice(&w.to_le_bytes());
+        }
+        unsafe { self.space.write_bytes(ACT, &raw) };
+    }
+
+    fn old_action(&self) -> GuestSigaction {
+        let p = self.space.g2h(OACT);
+        let raw = unsafe { std::slice::from_raw_parts(p, 24) };
+        let word = |i: usize| {
+            u64::from_le_bytes(raw[i * 8..i * 8 + 8].try_into().unwrap())
+        };
+        GuestSigaction {
+            handler: word(0),
+            flags: word(1),
+            mask: word(2),
+        }
+    }
+}
+
+#[test]
+fn sigaction_round_trip() {
+    let mut g = Guest::new();
+    let segv = GuestSigaction {
+        handler: 0x1_2340,
+        flags: SA_SIGINFO,
+        mask: 1 << (SIGBUS - 1),
+    };
+    g.put_action(segv);
+    assert_eq!(g.sigaction(SIGSEGV, ACT, OACT, 8), 0);
+    assert_eq!(g.old_action(), GuestSigaction::default());
+    assert_eq!(g.signals.action(SIGSEGV), Some(segv));
+
+    // Query only, then replace and get the previous one back.
+    assert_eq!(g.sigaction(SIGSEGV, 0, OACT, 8), 0);
+    assert_eq!(g.old_action(), segv);
+    g.put_action(GuestSigaction::default());
+    assert_eq!(g.sigaction(SIGSEGV, ACT, OACT, 8), 0);
+    assert_eq!(g.old_action(), segv);
+    assert_eq!(g.signals.action(SIGSEGV), Some(GuestSigaction::default()));
+    assert_eq!(g.signals.action(SIGBUS), Some(GuestSigaction::default()));
+}
+
+#[test]
+fn sigaction_never_blocks_kill_or_stop() {
+    let mut g = Guest::new();
+    g.put_action(GuestSigaction {
+        handler: 0x1000,
+        flags: 0,
+        mask: u64::MAX,
+    });
+    assert_eq!(g.sigaction(SIGBUS, ACT, 0, 8), 0);
+    let mask = g.signals.action(SIGBUS).unwrap().mask;
+    assert_eq!(mask, !(1 << 8 | 1 << 18));
+}
+
+#[test]
+fn sigaction_rejects_bad_arguments() {
+    let mut g = Guest::new();
+    g.put_action(GuestSigaction::default());
+    assert_eq!(g.sigaction(SIGSEGV, ACT, 0, 16), EINVAL);
+    assert_eq!(g.sigaction(0, ACT, 0, 8), EINVAL);
+    assert_eq!(g.sigaction(65, ACT, 0, 8), EINVAL);
+    assert_eq!(g.sigaction(SIGKILL, ACT, 0, 8), EINVAL);
+    assert_eq!(g.sigaction(SIGKILL, 0, OACT, 8), 0);
+    assert_eq!(g.sigaction(SIGSEGV, 0x9000_0000, 0, 8), EFAULT);
+    assert_eq!(g.sigaction(SIGSEGV, 0, 0x9000_0000, 8), EFAULT);
+}
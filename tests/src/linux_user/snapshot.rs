@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use tcg_exec::snapshot::SnapshotMemory;
+use tcg_linux_user::guest_space::{page_size, GuestSpace};
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
+
+const RW: i32 = libc::PROT_READ | libc::PROT_WRITE;
+const MEM: u64 = 0x10000;
+
+fn read(space: &GuestSpace, addr: u64, len: usize) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(space.g2h(addr), len) }.to_vec()
+}
+
+fn fill(space: &GuestSpace, addr: u64, len: usize, byte: u8) {
+    unsafe { space.write_bytes(addr, &vec![byte; len]) };
+}
+
+#[test]
+fn test_rollback_restores_written_pages() {
+    let ps = page_size();
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, 4 * ps, RW).unwrap();
+    fill(&space, MEM, 4 * ps, 0xaa);
+    let image = space.capture().unwrap();
+
+    let (p0, p2) = (MEM, MEM + 2 * ps as u64);
+    fill(&space, p0, 8, 0x11);
+    fill(&space, p2 + 100, 8, 0x22);
+    let rb = space.rollback(&image).unwrap();
+    assert_eq!(rb.pages, 2);
+    assert_eq!(rb.changed, [(p0, p0 + ps as u64), (p2, p2 + ps as u64)]);
+    assert_eq!(read(&space, MEM, 4 * ps), vec![0xaa; 4 * ps]);
+
+    // Tracking goes on from the image.
+    fill(&space, p2, 8, 0x33);
+    assert_eq!(space.rollback(&image).unwrap().pages, 1);
+    assert_eq!(read(&space, p2, 8), [0xaa; 8]);
+    assert_eq!(space.rollback(&image).unwrap().pages, 0);
+}
+
+#[test]
+fn test_rollback_restores_mappings() {
+    let ps = page_size();
+    let mut space = GuestSpace::new().unwrap();
+    let (a, b, c) = (MEM, MEM + 0x10000, MEM + 0x20000);
+    space.mmap_fixed(a, 2 * ps, RW).unwrap();
+    fill(&space, a, 2 * ps, 0xaa);
+    space.mmap_fixed(b, ps, RW).unwrap();
+    fill(&space, b, ps, 0xbb);
+    space.mprotect(b, ps, libc::PROT_READ).unwrap();
+    space.set_brk(0x8000);
+    let maps = space.maps();
+    let image = space.capture().unwrap();
+
+    space.munmap(a, 2 * ps).unwrap();
+    space.mprotect(b, ps, RW).unwrap();
+    fill(&space, b, ps, 0x11);
+    space.mmap_fixed(c, ps, RW).unwrap();
+    space.set_brk(0x9000);
+    let rb = space.rollback(&image).unwrap();
+
+    assert_eq!(space.maps(), maps);
+    assert_eq!(read(&space, a, 2 * ps), vec![0xaa; 2 * ps]);
+    assert_eq!(read(&space, b, ps), vec![0xbb; ps]);
+    assert!(space.is_free(c, c + ps as u64));
+    assert_eq!(space.brk(), 0x8000);
+    for range in [(a, a + 2 * ps as u64), (b, b + ps as u64)] {
+        assert!(
+            rb.changed
+                .iter()
+                .any(|&(s, e)| s <= range.0 && range.1 <= e),
+            "{range:x?} not in {:x?}",
+            rb.changed
+        );
+    }
+
+    // The remapped region is tracked again.
+    fill(&space, a + ps as u64, 8, 0x22);
+    assert_eq!(space.rollback(&image).unwrap().pages, 1);
+    assert_eq!(read(&space, a, 2 * ps), vec![0xaa; 2 * ps]);
+}
+
+#[test]
+fn test_rollback_stale_image_einval() {
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, page_size(), RW).unwrap();
+    let old = space.capture().unwrap();
+    let new = space.capture().unwrap();
+    let e = space.rollback(&old).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+    space.rollback(&new).unwrap();
+}
+
+/// The host kernel filling a protected guest buffer must not
+/// fail with EFAULT.
+#[test]
+fn test_read_into_tracked_buffer() {
+    const SYS_READ: u64 = 63;
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, page_size(), RW).unwrap();
+    let image = space.capture().unwrap();
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    assert_eq!(
+        unsafe { libc::write(fds[1], b"hello".as_ptr().cast(), 5) },
+        5
+    );
+    let mut regs = [0u64; 32];
+    regs[17] = SYS_READ;
+    regs[10..13].copy_from_slice(&[fds[0] as u64, MEM, 16]);
+    let r = handle_syscall(
+        &mut space,
+        &mut Vfs::new(None),
+        &mut Process::new(),
+        &mut SignalTable::new(),
+        &mut regs,
+        &mut 0,
+        "",
+        &SyscallPolicy::default(),
+    );
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+    assert!(matches!(r, SyscallResult::Continue(5)));
+    assert_eq!(read(&space, MEM, 5), b"hello");
+
+    assert_eq!(space.rollback(&image).unwrap().pages, 1);
+    assert_eq!(read(&space, MEM, 5), [0; 5]);
+}
+
+/// Restore time follows the pages written, not the memory size.
+#[test]
+fn test_rollback_cost_scales_with_dirty_pages() {
+    const REGION_PAGES: usize = 16384;
+    let ps = page_size();
+    let mut space = GuestSpace::new().unwrap();
+    space.mmap_fixed(MEM, REGION_PAGES * ps, RW).unwrap();
+    let image = space.capture().unwrap();
+
+    let mut cost = |n: usize| -> Duration {
+        let mut best = Duration::MAX;
+        for round in 0..5 {
+            // Spread out, so that no two pages are copied as one
+            // run.
+            let stride = REGION_PAGES / n;
+            for i in 0..n {
+                let addr = MEM + (i * stride * ps) as u64;
+                fill(&space, addr, 8, round as u8 + 1);
+            }
+            let t = Instant::now();
+            let rb = space.rollback(&image).unwrap();
+            best = best.min(t.elapsed());
+            assert_eq!(rb.pages, n);
+            assert_eq!(rb.changed.len(), n);
+        }
+        best
+    };
+    let few = cost(8);
+    let many = cost(2048);
+    assert!(few * 8 < many, "8 pages: {few:?}, 2048 pages: {many:?}");
+    assert_eq!(read(&space, MEM, 8), [0; 8]);
+}
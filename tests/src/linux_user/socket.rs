@@ -0,0 +1,360 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, UdpSocket};
+use std::thread;
+
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::Vfs;
+
+const SYS_CLOSE: u64 = 57;
+const SYS_FCNTL: u64 = 25;
+const SYS_SOCKET: u64 = 198;
+const SYS_SOCKETPAIR: u64 = 199;
+const SYS_CONNECT: u64 = 203;
+const SYS_GETSOCKNAME: u64 = 204;
+const SYS_SENDTO: u64 = 206;
+const SYS_RECVFROM: u64 = 207;
+const SYS_SETSOCKOPT: u64 = 208;
+const SYS_GETSOCKOPT: u64 = 209;
+const SYS_SENDMSG: u64 = 211;
+const SYS_RECVMSG: u64 = 212;
+
+const AF_INET: u64 = 2;
+const AF_UNIX: u64 = 1;
+const SOCK_STREAM: u64 = 1;
+const SOCK_DGRAM: u64 = 2;
+const SOCK_NONBLOCK: u64 = 0o4000;
+const SOCK_CLOEXEC: u64 = 0o2000000;
+
+/// Guest scratch memory used for syscall arguments.
+const MEM: u64 = 0x10000;
+const ADDR: u64 = MEM;
+const LEN: u64 = MEM + 0x80;
+const BUF: u64 = MEM + 0x100;
+const AUX: u64 = MEM + 0x800;
+
+struct Guest {
+    space: GuestSpace,
+    vfs: Vfs,
+    policy: SyscallPolicy,
+}
+
+impl Guest {
+    fn new() -> Self {
+        let mut space = GuestSpace::new().unwrap();
+        space
+            .mmap_fixed(MEM, 0x1000, libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        Self {
+            space,
+            vfs: Vfs::new(None),
+            policy: SyscallPolicy::default(),
+        }
+    }
+
+    fn sys(&mut self, nr: u64, args: &[u64]) -> i64 {
+        let mut regs = [0u64; 32];
+        regs[17] = nr;
+        regs[10..10 + args.len()].copy_from_slice(args);
+        let mut mmap_next = 0;
+        match handle_syscall(
+            &mut self.space,
+            &mut self.vfs,
+            &mut Process::new(),
+            &mut SignalTable::new(),
+            &mut regs,
+            &mut mmap_next,
+            "",
+            &self.policy,
+        ) {
+            SyscallResult::Continue(v) => v as i64,
+            SyscallResult::Exit(c) => panic!("unexpected exit {c}"),
+        }
+    }
+
+    fn write(&self, addr: u64, data: &[u8]) {
+        unsafe { self.space.write_bytes(addr, data) };
+    }
+
+    fn read(&self, addr: u64, len: usize) -> Vec<u8> {
+        let p = self.space.g2h(addr);
+        unsafe { std::slice::from_raw_parts(p, len).to_vec() }
+    }
+
+    fn read_u32(&self, addr: u64) -> u32 {
+        u32::from_le_bytes(self.read(addr, 4).try_into().unwrap())
+    }
+
+    /// Store a guest `sockaddr_in` at `ADDR`; returns its length.
+    fn put_sockaddr_in(&self, port: u16) -> u64 {
+        let mut sa = [0u8; 16];
+        sa[..2].copy_from_slice(&(AF_INET as u16).to_le_bytes());
+        sa[2..4].copy_from_slice(&port.to_be_bytes());
+        sa[4..8].copy_from_slice(&Ipv4Addr::LOCALHOST.octets());
+        self.write(ADDR, &sa);
+        16
+    }
+}
+
+fn localhost(port: u16) -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)
+}
+
+#[test]
+fn tcp_exchange_with_host_listener() {
+    let listener = TcpListener::bind(localhost(0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut got = [0u8; 5];
+        s.read_exact(&mut got).unwrap();
+        s.write_all(b"pong!").unwrap();
+        got
+    });
+
+    let mut g = Guest::new();
+    let fd = g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM | SOCK_CLOEXEC, 0]);
+    assert!(fd > 2, "socket: {fd}");
+    let len = g.put_sockaddr_in(port);
+    assert_eq!(g.sys(SYS_CONNECT, &[fd as u64, ADDR, len]), 0);
+
+    g.write(BUF, b"ping!");
+    assert_eq!(g.sys(SYS_SENDTO, &[fd as u64, BUF, 5, 0, 0, 0]), 5);
+    let mut got = Vec::new();
+    while got.len() < 5 {
+        let n = g.sys(SYS_RECVFROM, &[fd as u64, BUF, 64, 0, 0, 0]);
+        assert!(n > 0, "recvfrom: {n}");
+        got.extend(g.read(BUF, n as usize));
+    }
+    assert_eq!(got, b"pong!");
+    assert_eq!(&server.join().unwrap(), b"ping!");
+
+    // getsockname reports the full length even when truncated.
+    g.write(LEN, &4u32.to_le_bytes());
+    g.write(ADDR, &[0xff; 16]);
+    assert_eq!(g.sys(SYS_GETSOCKNAME, &[fd as u64, ADDR, LEN]), 0);
+    assert_eq!(g.read_u32(LEN), 16);
+    assert_eq!(g.read(ADDR, 2), (AF_INET as u16).to_le_bytes());
+    assert_eq!(g.read(ADDR + 4, 4), [0xff; 4]);
+
+    assert_eq!(g.sys(SYS_CLOSE, &[fd as u64]), 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[fd as u64]), -libc::EBADF as i64);
+}
+
+#[test]
+fn udp_echo_round_trip() {
+    let echo = UdpSocket::bind(localhost(0)).unwrap();
+    let port = echo.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        let (n, from) = echo.recv_from(&mut buf).unwrap();
+        echo.send_to(&buf[..n], from).unwrap();
+    });
+
+    let mut g = Guest::new();
+    let fd = g.sys(SYS_SOCKET, &[AF_INET, SOCK_DGRAM, 0]) as u64;
+    let len = g.put_sockaddr_in(port);
+    g.write(BUF, b"datagram");
+    assert_eq!(g.sys(SYS_SENDTO, &[fd, BUF, 8, 0, ADDR, len]), 8);
+
+    g.write(ADDR, &[0; 16]);
+    g.write(LEN, &16u32.to_le_bytes());
+    assert_eq!(g.sys(SYS_RECVFROM, &[fd, BUF, 64, 0, ADDR, LEN]), 8);
+    assert_eq!(g.read(BUF, 8), b"datagram");
+    assert_eq!(g.read_u32(LEN), 16);
+    assert_eq!(g.read(ADDR + 2, 2), port.to_be_bytes());
+    server.join().unwrap();
+    g.sys(SYS_CLOSE, &[fd]);
+}
+
+#[test]
+fn connect_refused_sets_errno() {
+    let port = {
+        let l = TcpListener::bind(localhost(0)).unwrap();
+        l.local_addr().unwrap().port()
+    };
+    let mut g = Guest::new();
+    let fd = g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM, 0]) as u64;
+    let len = g.put_sockaddr_in(port);
+    assert_eq!(
+        g.sys(SYS_CONNECT, &[fd, ADDR, len]),
+        -libc::ECONNREFUSED as i64
+    );
+    assert_eq!(
+        g.sys(SYS_CONNECT, &[fd, ADDR, 1024]),
+        -libc::EINVAL as i64,
+        "oversized addrlen"
+    );
+    g.sys(SYS_CLOSE, &[fd]);
+}
+
+#[test]
+fn socket_flags_and_policy() {
+    let mut g = Guest::new();
+    let fd = g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0]);
+    let fl = g.sys(SYS_FCNTL, &[fd as u64, 3, 0]);
+    assert_ne!(fl & libc::O_NONBLOCK as i64, 0);
+    g.sys(SYS_CLOSE, &[fd as u64]);
+    assert_eq!(
+        g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM | 0x100000, 0]),
+        -libc::EINVAL as i64
+    );
+
+    g.policy.deny_sockets = true;
+    assert_eq!(
+        g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM, 0]),
+        -libc::EACCES as i64
+    );
+}
+
+#[test]
+fn sockopts_translate_values() {
+    const SOL_SOCKET: u64 = 1;
+    const SO_ERROR: u64 = 4;
+    const SO_RCVTIMEO: u64 = 20;
+    const IPPROTO_TCP: u64 = 6;
+    const TCP_NODELAY: u64 = 1;
+
+    let mut g = Guest::new();
+    let fd = g.sys(SYS_SOCKET, &[AF_INET, SOCK_STREAM, 0]) as u64;
+
+    let mut tv = [0u8; 16];
+    tv[..8].copy_from_slice(&3i64.to_le_bytes());
+    tv[8..].copy_from_slice(&500_000i64.to_le_bytes());
+    g.write(BUF, &tv);
+    let set = [fd, SOL_SOCKET, SO_RCVTIMEO, BUF, 16];
+    assert_eq!(g.sys(SYS_SETSOCKOPT, &set), 0);
+    g.write(BUF, &[0; 16]);
+    g.write(LEN, &16u32.to_le_bytes());
+    let get = [fd, SOL_SOCKET, SO_RCVTIMEO, BUF, LEN];
+    assert_eq!(g.sys(SYS_GETSOCKOPT, &get), 0);
+    assert_eq!(g.read(BUF, 16), tv);
+    assert_eq!(g.read_u32(LEN), 16);
+
+    g.write(BUF, &1i32.to_le_bytes());
+    let set = [fd, IPPROTO_TCP, TCP_NODELAY, BUF, 4];
+    assert_eq!(g.sys(SYS_SETSOCKOPT, &set), 0);
+    g.write(BUF, &0i32.to_le_bytes());
+    g.write(LEN, &4u32.to_le_bytes());
+    let get = [fd, IPPROTO_TCP, TCP_NODELAY, BUF, LEN];
+    assert_eq!(g.sys(SYS_GETSOCKOPT, &get), 0);
+    assert_ne!(g.read_u32(BUF), 0);
+
+    g.write(BUF, &[0xff; 4]);
+    g.write(LEN, &4u32.to_le_bytes());
+    let get = [fd, SOL_SOCKET, SO_ERROR, BUF, LEN];
+    assert_eq!(g.sys(SYS_GETSOCKOPT, &get), 0);
+    assert_eq!(g.read_u32(BUF), 0);
+
+    let get = [fd, SOL_SOCKET, 0x7777, BUF, LEN];
+    assert_eq!(g.sys(SYS_GETSOCKOPT, &get), -libc::ENOPROTOOPT as i64);
+    g.sys(SYS_CLOSE, &[fd]);
+}
+
+/// Lay out a guest msghdr at `AUX` with one iovec over `BUF`.
+fn put_msghdr(g: &Guest, iov_len: u64, control: u64, controllen: u64) {
+    let iov = AUX + 0x100;
+    g.write(iov, &BUF.to_le_bytes());
+    g.write(iov + 8, &iov_len.to_le_bytes());
+    let mut m = [0u8; 56];
+    m[16..24].copy_from_slice(&iov.to_le_bytes());
+    m[24..32].copy_from_slice(&1u64.to_le_bytes());
+    m[32..40].copy_from_slice(&control.to_le_bytes());
+    m[40..48].copy_from_slice(&controllen.to_le_bytes());
+    g.write(AUX, &m);
+}
+
+fn cmsg(level: i32, ty: i32, fd: i32) -> [u8; 24] {
+    let mut c = [0u8; 24];
+    c[..8].copy_from_slice(&20u64.to_le_bytes());
+    c[8..12].copy_from_slice(&level.to_le_bytes());
+    c[12..16].copy_from_slice(&ty.to_le_bytes());
+    c[16..20].copy_from_slice(&fd.to_le_bytes());
+    c
+}
+
+#[test]
+fn sendmsg_passes_scm_rights() {
+    let mut g = Guest::new();
+    assert_eq!(g.sys(SYS_SOCKETPAIR, &[AF_UNIX, SOCK_STREAM, 0, LEN]), 0);
+    let a = g.read_u32(LEN) as u64;
+    let b = g.read_u32(LEN + 4) as u64;
+
+    let ctl = AUX + 0x200;
+    g.write(BUF, b"fd!");
+    g.write(ctl, &cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, 1));
+    put_msghdr(&g, 3, ctl, 24);
+    assert_eq!(g.sys(SYS_SENDMSG, &[a, AUX, 0]), 3);
+
+    // Anything other than SCM_RIGHTS is rejected explicitly.
+    g.write(ctl, &cmsg(libc::SOL_SOCKET, libc::SCM_CREDENTIALS, 1));
+    assert_eq!(g.sys(SYS_SENDMSG, &[a, AUX, 0]), -libc::EINVAL as i64);
+
+    g.write(BUF, &[0; 3]);
+    g.write(ctl, &[0; 24]);
+    put_msghdr(&g, 16, ctl, 24);
+    assert_eq!(g.sys(SYS_RECVMSG, &[b, AUX, 0]), 3);
+    assert_eq!(g.read(BUF, 3), b"fd!");
+    let c = g.read(ctl, 20);
+    assert_eq!(i32::from_le_bytes(c[8..12].try_into().unwrap()), 1);
+    assert_eq!(i32::from_le_bytes(c[12..16].try_into().unwrap()), 1);
+    let fd = i32::from_le_bytes(c[16..20].try_into().unwrap()) as u64;
+    assert!(fd > 2);
+    assert_eq!(
+        u64::from_le_bytes(g.read(AUX + 40, 8).try_into().unwrap()),
+        24
+    );
+
+    for fd in [a, b, fd] {
+        assert_eq!(g.sys(SYS_CLOSE, &[fd]), 0);
+    }
+}
+
+#[test]
+fn socket_buffers_must_be_accessible() {
+    const RO: u64 = 0x20000;
+    let mut g = Guest::new();
+    g.space.mmap_fixed(RO, 0x1000, libc::PROT_READ).unwrap();
+    assert_eq!(g.sys(SYS_SOCKETPAIR, &[AF_UNIX, SOCK_STREAM, 0, LEN]), 0);
+    let a = g.read_u32(LEN) as u64;
+    let b = g.read_u32(LEN + 4) as u64;
+
+    let efault = -libc::EFAULT as i64;
+    assert_eq!(g.sys(SYS_SENDTO, &[a, MEM + 0x1000, 4, 0, 0, 0]), efault);
+    assert_eq!(g.sys(SYS_SENDTO, &[a, RO, 4, 0, 0, 0]), 4);
+    assert_eq!(g.sys(SYS_RECVFROM, &[b, RO, 4, 0, 0, 0]), efault);
+    assert_eq!(g.sys(SYS_RECVFROM, &[b, BUF, 4, 0, 0, 0]), 4);
+
+    // A msghdr iovec into read-only memory.
+    put_msghdr(&g, 4, 0, 0);
+    g.write(AUX + 0x100, &RO.to_le_bytes());
+    assert_eq!(g.sys(SYS_RECVMSG, &[b, AUX, 0]), efault);
+
+    for fd in [a, b] {
+        assert_eq!(g.sys(SYS_CLOSE, &[fd]), 0);
+    }
+}
+
+#[test]
+fn sendmsg_refuses_emulator_fds() {
+    use std::os::fd::AsRawFd;
+
+    let mut g = Guest::new();
+    assert_eq!(g.sys(SYS_SOCKETPAIR, &[AF_UNIX, SOCK_STREAM, 0, LEN]), 0);
+    let a = g.read_u32(LEN) as u64;
+    let b = g.read_u32(LEN + 4) as u64;
+    let log = std::fs::File::open("/proc/self/stat").unwrap();
+
+    let ctl = AUX + 0x200;
+    g.write(BUF, b"fd!");
+    let fd = log.as_raw_fd();
+    g.write(ctl, &cmsg(libc::SOL_SOCKET, libc::SCM_RIGHTS, fd));
+    put_msghdr(&g, 3, ctl, 24);
+    assert_eq!(g.sys(SYS_SENDMSG, &[a, AUX, 0]), -libc::EBADF as i64);
+
+    for fd in [a, b] {
+        assert_eq!(g.sys(SYS_CLOSE, &[fd]), 0);
+    }
+}
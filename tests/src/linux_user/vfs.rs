@@ -0,0 +1,499 @@
+//! Emulated /dev devices, terminal ioctls and statfs.
+
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use tcg_linux_user::guest_space::GuestSpace;
+use tcg_linux_user::process::Process;
+use tcg_linux_user::signal::SignalTable;
+use tcg_linux_user::syscall::{handle_syscall, SyscallPolicy, SyscallResult};
+use tcg_linux_user::vfs::{
+    Device, StatfsView, Vfs, GUEST_STATFS_SIZE, PROC_SUPER_MAGIC, TMPFS_MAGIC,
+};
+
+const SYS_DUP: u64 = 23;
+const SYS_DUP3: u64 = 24;
+const SYS_FCNTL: u64 = 25;
+const SYS_IOCTL: u64 = 29;
+const SYS_STATFS: u64 = 43;
+const SYS_FSTATFS: u64 = 44;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_FSTAT: u64 = 80;
+const SYS_MMAP: u64 = 222;
+
+const AT_FDCWD: u64 = -100i64 as u64;
+const O_RDWR: u64 = 2;
+const MAP_PRIVATE: u64 = 0x02;
+const PROT_RW: u64 = 3;
+
+const TCGETS: u64 = 0x5401;
+const TCSETS: u64 = 0x5402;
+const TIOCGWINSZ: u64 = 0x5413;
+
+const ENOENT: i64 = -2;
+const EBADF: i64 = -9;
+const ENODEV: i64 = -19;
+const EACCES: i64 = -13;
+const EFAULT: i64 = -14;
+const ENOTTY: i64 = -25;
+
+const MEM: u64 = 0x10000;
+const PATH: u64 = MEM;
+const BUF: u64 = MEM + 0x100;
+
+struct Guest {
+    space: GuestSpace,
+    vfs: Vfs,
+    policy: SyscallPolicy,
+    mmap_next: u64,
+}
+
+impl Guest {
+    fn new(vfs: Vfs) -> Self {
+        let mut space = GuestSpace::new().unwrap();
+        space
+            .mmap_fixed(MEM, 0x1000, libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        Self {
+            space,
+            vfs,
+            policy: SyscallPolicy::default(),
+            mmap_next: 0x1000_0000,
+        }
+    }
+
+    fn sys(&mut self, nr: u64, args: &[u64]) -> i64 {
+        let mut regs = [0u64; 32];
+        regs[17] = nr;
+        regs[10..10 + args.len()].copy_from_slice(args);
+        match handle_syscall(
+            &mut self.space,
+            &mut self.vfs,
+            &mut Process::new(),
+            &mut SignalTable::new(),
+            &mut regs,
+            &mut self.mmap_next,
+            "",
+            &self.policy,
+        ) {
+            SyscallResult::Continue(v) => v as i64,
+            SyscallResult::Exit(c) => panic!("unexpected exit {c}"),
+        }
+    }
+
+    fn open(&mut self, path: &str) -> i64 {
+        let mut p = path.as_bytes().to_vec();
+        p.push(0);
+        unsafe { self.space.write_bytes(PATH, &p) };
+        self.sys(SYS_OPENAT, &[AT_FDCWD, PATH, O_RDWR, 0])
+    }
+
+    /// statfs of `path`, and the buffer past the struct.
+    fn statfs(&mut self, path: &str) -> (i64, Vec<u8>) {
+        let mut p = path.as_bytes().to_vec();
+        p.push(0);
+        unsafe { self.space.write_bytes(PATH, &p) };
+        self.fill(BUF, 0xaa, GUEST_STATFS_SIZE + 8);
+        let r = self.sys(SYS_STATFS, &[PATH, BUF]);
+        (r, self.read(BUF, GUEST_STATFS_SIZE + 8))
+    }
+
+    fn fstatfs(&mut self, fd: u64) -> (i64, Vec<u8>) {
+        self.fill(BUF, 0xaa, GUEST_STATFS_SIZE + 8);
+        let r = self.sys(SYS_FSTATFS, &[fd, BUF]);
+        (r, self.read(BUF, GUEST_STATFS_SIZE + 8))
+    }
+
+    fn read(&self, addr: u64, len: usize) -> Vec<u8> {
+        let p = self.space.g2h(addr);
+        unsafe { std::slice::from_raw_parts(p, len).to_vec() }
+    }
+
+    fn fill(&self, addr: u64, byte: u8, len: usize) {
+        unsafe { self.space.write_bytes(addr, &vec![byte; len]) };
+    }
+}
+
+fn urandom(seed: Option<u64>) -> Vec<u8> {
+    let mut g = Guest::new(Vfs::new(seed));
+    let fd = g.open("/dev/urandom") as u64;
+    assert_eq!(g.sys(SYS_READ, &[fd, BUF, 16]), 16);
+    g.read(BUF, 16)
+}
+
+#[test]
+fn dev_null_reads_eof_and_swallows_writes() {
+    let mut g = Guest::new(Vfs::new(None));
+    let fd = g.open("/dev/null");
+    assert!(fd > 2, "openat: {fd}");
+    assert_eq!(g.vfs.device(fd as u64), Some(Device::Null));
+    let fd = fd as u64;
+    assert_eq!(g.sys(SYS_READ, &[fd, BUF, 64]), 0);
+    assert_eq!(g.sys(SYS_WRITE, &[fd, BUF, 64]), 64);
+    assert_eq!(g.sys(SYS_WRITE, &[fd, 0x9000_0000, 64]), EFAULT);
+    assert_eq!(g.sys(SYS_LSEEK, &[fd, 10, 0]), 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[fd]), 0);
+    assert_eq!(g.vfs.device(fd), None);
+}
+
+#[test]
+fn dev_zero_read_and_mmap() {
+    let mut g = Guest::new(Vfs::new(None));
+    let fd = g.open("/dev/zero") as u64;
+    g.fill(BUF, 0xaa, 32);
+    assert_eq!(g.sys(SYS_READ, &[fd, BUF, 32]), 32);
+    assert_eq!(g.read(BUF, 32), [0; 32]);
+
+    let addr = g.sys(SYS_MMAP, &[0, 0x2000, PROT_RW, MAP_PRIVATE, fd, 0]);
+    assert!(addr > 0, "mmap: {addr}");
+    let addr = addr as u64;
+    assert_eq!(g.read(addr, 0x2000), vec![0; 0x2000]);
+    g.fill(addr + 0x1ff0, 0x5a, 16);
+    assert_eq!(g.read(addr + 0x1ff0, 16), [0x5a; 16]);
+
+    let null = g.open("/dev/null") as u64;
+    let r = g.sys(SYS_MMAP, &[0, 0x1000, PROT_RW, MAP_PRIVATE, null, 0]);
+    assert_eq!(r, ENODEV);
+}
+
+#[test]
+fn dev_urandom_is_seeded_when_deterministic() {
+    let a = urandom(Some(7));
+    assert_eq!(a, urandom(Some(7)));
+    assert_ne!(a, urandom(Some(8)));
+    assert_ne!(a, [0; 16]);
+}
+
+#[test]
+fn deny_random_policy() {
+    let mut g = Guest::new(Vfs::new(None));
+    g.policy.deny_random = true;
+    assert_eq!(g.open("/dev/random"), EACCES);
+    assert_eq!(g.open("/dev/urandom"), EACCES);
+    assert!(g.open("/dev/null") > 2);
+}
+
+#[test]
+fn device_fstat_is_char_device() {
+    let mut g = Guest::new(Vfs::new(None));
+    let fd = g.open("/dev/zero") as u64;
+    assert_eq!(g.sys(SYS_FSTAT, &[fd, BUF]), 0);
+    let st = g.read(BUF, 40);
+    let mode = u32::from_le_bytes(st[16..20].try_into().unwrap());
+    let rdev = u64::from_le_bytes(st[32..40].try_into().unwrap());
+    assert_eq!(mode, 0o020666);
+    assert_eq!(rdev, Device::Zero.rdev());
+}
+
+#[test]
+fn dup_keeps_device() {
+    let mut g = Guest::new(Vfs::new(Some(1)));
+    let fd = g.open("/dev/zero") as u64;
+    let copy = g.sys(SYS_DUP, &[fd]) as u64;
+    assert_eq!(g.sys(SYS_CLOSE, &[fd]), 0);
+    g.fill(BUF, 0xff, 8);
+    assert_eq!(g.sys(SYS_READ, &[copy, BUF, 8]), 8);
+    assert_eq!(g.read(BUF, 8), [0; 8]);
+}
+
+/// Host fds the guest did not open, such as the log or a
+/// checkpoint file, cannot be closed, duplicated or replaced.
+#[test]
+fn emulator_fds_are_not_the_guests() {
+    let mut g = Guest::new(Vfs::new(None));
+    let log = fs::File::open("/proc/self/stat").unwrap();
+    let fd = log.as_raw_fd() as u64;
+    assert_eq!(g.sys(SYS_CLOSE, &[fd]), EBADF);
+    assert_eq!(g.sys(SYS_DUP, &[fd]), EBADF);
+    assert_eq!(g.sys(SYS_DUP3, &[fd, 100, 0]), EBADF);
+    assert_eq!(g.sys(SYS_FCNTL, &[fd, 1, 0]), EBADF);
+
+    let own = g.sys(SYS_DUP, &[1]) as u64;
+    assert!(own > 2);
+    assert_eq!(g.sys(SYS_DUP3, &[own, fd, 0]), EBADF);
+    assert!(unsafe { libc::fcntl(fd as i32, libc::F_GETFD) } >= 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[own]), 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[own]), EBADF);
+
+    // Stdio is the guest's until it closes it.
+    assert!(g.sys(SYS_FCNTL, &[2, 1, 0]) >= 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[2]), 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[2]), EBADF);
+    assert_eq!(g.sys(SYS_DUP, &[2]), EBADF);
+    let copy = g.sys(SYS_DUP, &[1]) as u64;
+    assert_eq!(g.sys(SYS_DUP3, &[copy, 2, 0]), 2);
+    assert_eq!(g.sys(SYS_CLOSE, &[2]), 0);
+    assert_eq!(g.sys(SYS_CLOSE, &[copy]), 0);
+}
+
+/// Buffers must be mapped with the access the call makes.
+#[test]
+fn read_write_check_guest_buffers() {
+    const RO: u64 = 0x20000;
+    const UNMAPPED: u64 = 0x30000;
+    let mut g = Guest::new(Vfs::new(None));
+    g.space.mmap_fixed(RO, 0x1000, libc::PROT_READ).unwrap();
+    let mut p = [0; 2];
+    assert_eq!(unsafe { libc::pipe(p.as_mut_ptr()) }, 0);
+    let (r, w) = (p[0] as u64, p[1] as u64);
+
+    assert_eq!(g.sys(SYS_WRITE, &[w, RO, 4]), 4);
+    assert_eq!(g.sys(SYS_WRITE, &[w, UNMAPPED, 4]), EFAULT);
+    assert_eq!(g.sys(SYS_WRITE, &[w, RO + 0xffc, 8]), EFAULT);
+    assert_eq!(g.sys(SYS_READ, &[r, RO, 4]), EFAULT);
+    assert_eq!(g.sys(SYS_READ, &[r, UNMAPPED, 4]), EFAULT);
+    assert_eq!(g.sys(SYS_READ, &[r, BUF, 4]), 4);
+    unsafe {
+        libc::close(p[0]);
+        libc::close(p[1]);
+    }
+}
+
+#[test]
+fn device_is_not_a_tty() {
+    let mut g = Guest::new(Vfs::new(None));
+    let fd = g.open("/dev/null") as u64;
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TCGETS, BUF]), ENOTTY);
+}
+
+fn openpty() -> (libc::c_int, libc::c_int) {
+    let (mut master, mut slave) = (0, 0);
+    let r = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(r, 0, "openpty: {}", std::io::Error::last_os_error());
+    (master, slave)
+}
+
+#[test]
+fn termios_ioctls_on_pty() {
+    let (master, slave) = openpty();
+    let ws = libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { libc::ioctl(slave, libc::TIOCSWINSZ, &ws) };
+
+    let mut g = Guest::new(Vfs::new(None));
+    let fd = slave as u64;
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TIOCGWINSZ, BUF]), 0);
+    assert_eq!(g.read(BUF, 4), [24, 0, 80, 0]);
+
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TCGETS, BUF]), 0);
+    let lflag =
+        |g: &Guest| u32::from_le_bytes(g.read(BUF + 12, 4).try_into().unwrap());
+    let before = lflag(&g);
+    assert_ne!(before & libc::ECHO, 0);
+
+    // Clear ECHO through the guest layout and read it back.
+    let new = (before & !libc::ECHO).to_le_bytes();
+    unsafe { g.space.write_bytes(BUF + 12, &new) };
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TCSETS, BUF]), 0);
+    let mut t: libc::termios = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::tcgetattr(slave, &mut t) }, 0);
+    assert_eq!(t.c_lflag & libc::ECHO, 0);
+    g.fill(BUF, 0, 36);
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, TCGETS, BUF]), 0);
+    assert_eq!(lflag(&g), before & !libc::ECHO);
+
+    assert_eq!(g.sys(SYS_IOCTL, &[fd, 0x5490, BUF]), ENOTTY);
+    unsafe {
+        libc::close(slave);
+        libc::close(master);
+    }
+}
+
+/// Inherited stdio answers like the host fd; captured stdio is
+/// never a tty.
+#[test]
+fn captured_stdio_is_not_a_tty() {
+    let host_tty = unsafe { libc::isatty(1) } == 1;
+    let mut inherited = Guest::new(Vfs::new(None));
+    let r = inherited.sys(SYS_IOCTL, &[1, TCGETS, BUF]);
+    assert_eq!(r == 0, host_tty, "TCGETS: {r}");
+
+    let mut captured = Guest::new(Vfs::new(None).with_captured_stdio(true));
+    for fd in 0..3 {
+        assert_eq!(captured.sys(SYS_IOCTL, &[fd, TCGETS, BUF]), ENOTTY);
+        assert_eq!(captured.sys(SYS_IOCTL, &[fd, TIOCGWINSZ, BUF]), ENOTTY);
+    }
+}
+
+/// Guest `struct statfs` bytes from its 64-bit words, `f_fsid`
+/// as one word.
+fn statfs_bytes(words: [u64; 11]) -> Vec<u8> {
+    let mut out: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    out.resize(GUEST_STATFS_SIZE, 0);
+    out
+}
+
+fn host_statfs(path: &Path) -> Vec<u8> {
+    let c = std::ffi::CString::new(path.as_os_str().as_encoded_bytes());
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::statfs(c.unwrap().as_ptr(), &mut st) }, 0);
+    // x86-64 uses the same layout as the riscv64 guest.
+    let p = &st as *const libc::statfs as *const u8;
+    unsafe { std::slice::from_raw_parts(p, GUEST_STATFS_SIZE).to_vec() }
+}
+
+fn word(st: &[u8], i: usize) -> u64 {
+    u64::from_le_bytes(st[i * 8..i * 8 + 8].try_into().unwrap())
+}
+
+/// `guest` matches the host's `struct statfs` but for the free
+/// counts, which other tests may change meanwhile, and the
+/// words in `skip`.
+fn assert_host_layout(guest: &[u8], host: &[u8], skip: &[usize]) {
+    for i in 0..GUEST_STATFS_SIZE / 8 {
+        // f_bfree, f_bavail, f_ffree
+        if [3, 4, 6].contains(&i) || skip.contains(&i) {
+            continue;
+        }
+        assert_eq!(word(guest, i), word(host, i), "word {i}");
+    }
+    assert!(word(guest, 4) <= word(guest, 3));
+    assert!(word(guest, 3) <= word(guest, 2));
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(tag: &str) -> Self {
+        let pid = std::process::id();
+        let dir = std::env::temp_dir().join(format!("tcg_statfs_{tag}_{pid}"));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/libc.so.6"), b"").unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn statfs_host_dir_in_guest_layout() {
+    let dir = TempDir::new("layout");
+    let mut g = Guest::new(Vfs::new(None));
+    let (r, st) = g.statfs(dir.0.to_str().unwrap());
+    assert_eq!(r, 0);
+    assert_host_layout(&st, &host_statfs(&dir.0), &[]);
+    assert_eq!(st[GUEST_STATFS_SIZE..], [0xaa; 8]);
+
+    let fd = g.open(dir.0.join("lib/libc.so.6").to_str().unwrap()) as u64;
+    let (r, st) = g.fstatfs(fd);
+    assert_eq!(r, 0);
+    assert_host_layout(&st, &host_statfs(&dir.0), &[]);
+
+    assert_eq!(g.sys(SYS_FSTATFS, &[fd, 0x9000_0000]), EFAULT);
+    assert_eq!(g.statfs("/nonexistent/tcg").0, ENOENT);
+    assert!(g.sys(SYS_FSTATFS, &[9999, BUF]) < 0);
+}
+
+#[test]
+fn statfs_proc_is_synthetic() {
+    let proc = statfs_bytes([
+        PROC_SUPER_MAGIC as u64,
+        4096,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        255,
+        4096,
+        0x102e,
+    ]);
+    let mut g = Guest::new(Vfs::new(None));
+    for path in ["/proc", "/proc/self/status", "/proc/cpuinfo"] {
+        let (r, st) = g.statfs(path);
+        assert_eq!(r, 0, "{path}");
+        assert_eq!(st[..GUEST_STATFS_SIZE], proc[..], "{path}");
+    }
+}
+
+#[test]
+fn statfs_devices_are_devtmpfs() {
+    let dev = statfs_bytes([
+        TMPFS_MAGIC as u64,
+        4096,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        255,
+        4096,
+        0x1022,
+    ]);
+    let mut g = Guest::new(Vfs::new(None));
+    let (r, st) = g.statfs("/dev/null");
+    assert_eq!(r, 0);
+    assert_eq!(st[..GUEST_STATFS_SIZE], dev[..]);
+    let fd = g.open("/dev/urandom") as u64;
+    let (r, st) = g.fstatfs(fd);
+    assert_eq!(r, 0);
+    assert_eq!(st[..GUEST_STATFS_SIZE], dev[..]);
+}
+
+#[test]
+fn statfs_sysroot_fs_type() {
+    const OVERLAYFS_MAGIC: i64 = 0x794c_7630;
+    let dir = TempDir::new("sysroot");
+    let outside = TempDir::new("outside");
+    let vfs = Vfs::new(None).with_statfs(StatfsView {
+        sysroot: Some((dir.0.clone(), OVERLAYFS_MAGIC)),
+        disk_cap: None,
+    });
+    let mut g = Guest::new(vfs);
+    let host = host_statfs(&dir.0);
+    let lib = dir.0.join("lib/libc.so.6");
+    let (r, st) = g.statfs(lib.to_str().unwrap());
+    assert_eq!(r, 0);
+    assert_eq!(word(&st, 0), OVERLAYFS_MAGIC as u64);
+    assert_host_layout(&st, &host, &[0]);
+
+    let fd = g.open(lib.to_str().unwrap()) as u64;
+    let (r, st) = g.fstatfs(fd);
+    assert_eq!(r, 0);
+    assert_eq!(word(&st, 0), OVERLAYFS_MAGIC as u64);
+
+    let (r, st) = g.statfs(outside.0.to_str().unwrap());
+    assert_eq!(r, 0);
+    assert_host_layout(&st, &host_statfs(&outside.0), &[]);
+}
+
+#[test]
+fn statfs_disk_cap() {
+    let dir = TempDir::new("cap");
+    let cap = 1 << 20;
+    let vfs = Vfs::new(None).with_statfs(StatfsView {
+        sysroot: None,
+        disk_cap: Some(cap),
+    });
+    let mut g = Guest::new(vfs);
+    let host = host_statfs(&dir.0);
+    let (r, st) = g.statfs(dir.0.to_str().unwrap());
+    assert_eq!(r, 0);
+    let frsize = word(&st, 9);
+    assert_eq!(word(&st, 2), word(&host, 2).min(cap / frsize));
+    assert!(word(&st, 2) * frsize <= cap);
+    assert_host_layout(&st, &host, &[2]);
+}
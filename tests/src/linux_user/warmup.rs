@@ -0,0 +1,46 @@
+//! Warmup hint files: keys and address filtering.
+
+use tcg_exec::warmup::WarmupHints;
+use tcg_frontend::riscv::ext::RiscvCfg;
+use tcg_linux_user::warmup::{cfg_key, load};
+
+fn saved(binary: u64, cfg: u64) -> String {
+    let hints = WarmupHints {
+        binary,
+        cfg,
+        tbs: vec![(0x10078, 0), (0x10100, 1), (0x7fff_0000, 0)],
+    };
+    let mut out = Vec::new();
+    hints.write(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+const TEXT: &[(u64, u64)] = &[(0x10000, 0x11000)];
+
+#[test]
+fn test_warmup_load_filters_to_exec_ranges() {
+    let tbs = load(&saved(7, 9), 7, 9, TEXT).unwrap();
+    assert_eq!(tbs, [(0x10078, 0), (0x10100, 1)]);
+}
+
+#[test]
+fn test_warmup_stale_file_ignored() {
+    let text = saved(7, 9);
+    // Edited binary hash.
+    let edited =
+        text.replace("binary 0x0000000000000007", "binary 0x0000000000000008");
+    assert_ne!(edited, text);
+    assert!(load(&edited, 7, 9, TEXT).unwrap_err().contains("stale"));
+    // Same binary, other CPU configuration.
+    assert!(load(&text, 7, 10, TEXT).unwrap_err().contains("stale"));
+    assert!(load("garbage", 7, 9, TEXT).is_err());
+}
+
+#[test]
+fn test_warmup_cfg_key_covers_extensions() {
+    let full = RiscvCfg::default();
+    let mut no_zbb = full;
+    no_zbb.ext_zbb = !no_zbb.ext_zbb;
+    assert_eq!(cfg_key(&full), cfg_key(&RiscvCfg::default()));
+    assert_ne!(cfg_key(&full), cfg_key(&no_zbb));
+}
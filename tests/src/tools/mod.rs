@@ -4,6 +4,8 @@ use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 
+use crate::linux_user::loader::{tempfile, TempFile};
+
 fn project_root() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..")
 }
@@ -47,7 +49,7 @@ fn irdump_emit_bin_produces_file() {
     let data = fs::read(tmp).expect("output file missing");
     // Verify magic header
     assert!(data.len() > 20, "file too small");
-    assert_eq!(&data[..4], b"TCIR");
+    assert_eq!(&data[..5], b"TCGIR");
 
     let _ = fs::remove_file(tmp);
 }
@@ -165,3 +167,183 @@ fn irbackend_multiple_tbs() {
 
     let _ = fs::remove_file(tmp_ir);
 }
+
+/// `addi a0, x0, 5; mul a0, a0, a0; c.addi a0, 1; csrr a1, cycle;
+/// ecall`: one TB using I, M, C and Zicsr.
+fn ext_fixture() -> crate::linux_user::loader::TempFile {
+    let mut code = Vec::new();
+    code.extend_from_slice(&0x0050_0513u32.to_le_bytes());
+    code.extend_from_slice(&0x02a5_0533u32.to_le_bytes());
+    code.extend_from_slice(&0x0505u16.to_le_bytes());
+    code.extend_from_slice(&0xc000_25f3u32.to_le_bytes());
+    code.extend_from_slice(&0x0000_0073u32.to_le_bytes());
+    let elf = crate::linux_user::loader::make_elf(&code);
+    let mut f = crate::linux_user::loader::tempfile().unwrap();
+    f.write_all(&elf).unwrap();
+    f
+}
+
+fn irdump(args: &[&str]) -> std::process::Output {
+    ensure_built();
+    Command::new(bin_path("tcg-irdump"))
+        .args(args)
+        .output()
+        .expect("tcg-irdump failed to run")
+}
+
+#[test]
+fn irdump_annotates_extensions() {
+    let elf = ext_fixture();
+    let out = irdump(&[elf.path().to_str().unwrap()]);
+    assert!(out.status.success());
+    let text = String::from_utf8_lossy(&out.stdout);
+
+    let line = |needle: &str| {
+        text.lines()
+            .find(|l| l.contains(needle))
+            .unwrap_or_else(|| panic!("no `{needle}` line in:\n{text}"))
+    };
+    assert!(line("00500513").ends_with("[I]"));
+    assert!(line("02a50533").ends_with("[M]"));
+    assert!(line("  0505 ").ends_with("[C]"));
+    assert!(line("c00025f3").ends_with("[Zicsr]"));
+    assert!(text.contains("\nextensions used: I M C Zicsr\n"));
+    assert!(text.contains("\nextensions used (all TBs): I M C Zicsr\n"));
+}
+
+#[test]
+fn irdump_format_json() {
+    let elf = ext_fixture();
+    let path = elf.path().to_str().unwrap();
+    let out = irdump(&[path, "--format", "json", "--count", "1"]);
+    assert!(out.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout)
+        .unwrap_or_else(|e| {
+            panic!("{e}:\n{}", String::from_utf8_lossy(&out.stdout))
+        });
+
+    let tbs = json.as_array().unwrap();
+    assert_eq!(tbs.len(), 1);
+    let ops = tbs[0]["ops"].as_array().unwrap();
+    let starts: Vec<_> =
+        ops.iter().filter(|o| o["opc"] == "insn_start").collect();
+    assert_eq!(starts[0]["pc"], 0x10000);
+    let asm: Vec<&str> = starts
+        .iter()
+        .map(|o| o["guest_asm"].as_str().unwrap())
+        .collect();
+    assert!(asm[1].starts_with("mul "), "{asm:?}");
+    assert!(tbs[0]["temps"].as_array().unwrap().len() > 1);
+
+    let p = &tbs[0]["pressure"];
+    let timeline = p["timeline"].as_array().unwrap();
+    assert_eq!(timeline.len(), ops.len());
+    let max = timeline.iter().map(|n| n.as_u64().unwrap()).max();
+    assert_eq!(p["max_live"].as_u64(), max);
+    assert!(p["max_live_by_type"]["i64"].as_u64().unwrap() > 0);
+    assert_eq!(p["spill_sites"], 0);
+}
+
+#[test]
+fn irdump_require_ext_accepts_superset() {
+    let elf = ext_fixture();
+    let path = elf.path().to_str().unwrap();
+    let out = irdump(&[path, "--require-ext", "i,m,a,f,d,c,zicsr"]);
+    assert!(out.status.success());
+}
+
+#[test]
+fn irdump_require_ext_names_first_offender() {
+    let elf = ext_fixture();
+    let path = elf.path().to_str().unwrap();
+    let out = irdump(&[path, "--require-ext", "I,Zicsr"]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("error: mul at 0x10004 requires M, not in --require-ext"),
+        "{err}"
+    );
+
+    let out = irdump(&[path, "--require-ext", "I,M,Zicsr"]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.contains("error: addi at 0x10008 requires C"), "{err}");
+}
+
+fn ir_budget_fixture() -> PathBuf {
+    project_root().join("tests/fixtures/ir-budget.txt")
+}
+
+/// The checked-in budgets with every count passed through `f`.
+fn budget_file(f: impl Fn(&str, usize) -> usize) -> TempFile {
+    let text = fs::read_to_string(ir_budget_fixture()).unwrap();
+    let mut budgets = String::new();
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        let (name, max) = line.split_once(' ').unwrap();
+        let max: usize = max.trim().parse().unwrap();
+        budgets += &format!("{name} {}\n", f(name, max));
+    }
+    let mut out = tempfile().unwrap();
+    out.write_all(budgets.as_bytes()).unwrap();
+    out
+}
+
+/// Translation-quality gate: every decode pattern stays within
+/// the checked-in IR budget. After an intended change, update
+/// it with `tcg-irdump --canonical --write-budgets`.
+#[test]
+fn irdump_canonical_within_ir_budget() {
+    let budget = ir_budget_fixture();
+    let out =
+        irdump(&["--canonical", "--lint-ir-budget", budget.to_str().unwrap()]);
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{err}");
+    assert!(!err.contains("warning"), "{err}");
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn irdump_write_budgets_matches_lint() {
+    let written = tempfile().unwrap();
+    let path = written.path().to_str().unwrap();
+    let out = irdump(&["--canonical", "--write-budgets", path]);
+    assert!(out.status.success());
+    let text = fs::read_to_string(path).unwrap();
+    let addi = text.lines().find(|l| l.starts_with("addi ")).unwrap();
+    assert!(addi
+        .split_whitespace()
+        .nth(1)
+        .unwrap()
+        .parse::<usize>()
+        .is_ok());
+
+    let out = irdump(&["--canonical", "--lint-ir-budget", path]);
+    assert!(out.status.success());
+}
+
+#[test]
+fn irdump_ir_budget_inflated_passes_tightened_fails() {
+    let loose = budget_file(|_, max| max + 10);
+    let path = loose.path().to_str().unwrap();
+    let out = irdump(&["--canonical", "--lint-ir-budget", path]);
+    assert!(out.status.success());
+    let elf = ext_fixture();
+    let elf = elf.path().to_str().unwrap();
+    let out = irdump(&[elf, "--lint-ir-budget", path]);
+    assert!(out.status.success());
+
+    let tight = budget_file(|name, max| if name == "mul" { 1 } else { max });
+    let path = tight.path().to_str().unwrap();
+    let out = irdump(&[elf, "--lint-ir-budget", path]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    let found = err.lines().find(|l| l.contains(" at 0x")).unwrap();
+    assert!(found.starts_with("mul at 0x10004: "), "{err}");
+    assert!(found.ends_with(" IR ops, budget 1"), "{err}");
+    assert!(err.contains(" mul_i64 "), "{err}");
+    assert!(
+        err.ends_with("1 instruction(s) over their IR budget\n"),
+        "{err}"
+    );
+    assert!(out.stdout.is_empty());
+}
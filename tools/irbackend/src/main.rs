@@ -10,15 +10,19 @@ use std::io::{self, BufWriter, Write};
 use std::process;
 
 use tcg_backend::code_buffer::CodeBuffer;
-use tcg_backend::translate::translate;
-use tcg_backend::{HostCodeGen, X86_64CodeGen};
-use tcg_core::serialize;
+use tcg_backend::liveness::{pressure_report, LivenessReport};
+use tcg_backend::translate::{self, analyze, codegen};
+use tcg_backend::{HostCodeGen, NativeCodeGen};
+use tcg_core::serialize::{self, MetaWarning};
+use tcg_core::types::Type;
 
 struct Args {
     ir_path: String,
     output: Option<String>,
     raw: bool,
     disas: bool,
+    pressure: bool,
+    arch: Option<String>,
 }
 
 const USAGE: &str = "\
@@ -27,7 +31,9 @@ usage: tcg-irbackend <ir-file> [options]
 Options:
   -o <file>   Output to file (default: stdout)
   --raw       Output raw machine code bytes
-  --disas     Disassemble via objdump
+  --disas     Print a disassembly instead of the hex dump
+  --pressure  Report register pressure per TB
+  --arch <a>  Refuse TBs not translated for guest arch <a>
   -h, --help  Show this help";
 
 fn parse_args() -> Args {
@@ -42,6 +48,8 @@ fn parse_args() -> Args {
         output: None,
         raw: false,
         disas: false,
+        pressure: false,
+        arch: None,
     };
 
     let mut i = 2;
@@ -53,6 +61,11 @@ fn parse_args() -> Args {
             }
             "--raw" => a.raw = true,
             "--disas" => a.disas = true,
+            "--pressure" => a.pressure = true,
+            "--arch" => {
+                i += 1;
+                a.arch = Some(args[i].clone());
+            }
             other => {
                 eprintln!("unknown option: {other}");
                 process::exit(1);
@@ -77,22 +90,39 @@ fn hex_dump(data: &[u8], w: &mut impl Write) -> io::Result<()> {
     Ok(())
 }
 
-fn disassemble(code: &[u8]) {
-    let tmp = "/tmp/tcg-irbackend-tmp.bin";
-    fs::write(tmp, code).expect("write tmp failed");
-    let status = process::Command::new("objdump")
-        .args(["-b", "binary", "-m", "i386:x86-64", "-D", tmp])
-        .status();
-    match status {
-        Ok(s) if s.success() => {}
-        Ok(s) => {
-            eprintln!("objdump exited with {s}");
+fn print_pressure(r: &LivenessReport) {
+    let max_op = r.max_op.map_or("-".to_string(), |oi| oi.to_string());
+    eprintln!(
+        "  pressure: max {} at op {max_op} (i32 {}, i64 {}), \
+         {} spill site(s)",
+        r.max_live,
+        r.max_live_of(Type::I32),
+        r.max_live_of(Type::I64),
+        r.spill_sites,
+    );
+    for (oi, &n) in r.timeline.iter().enumerate() {
+        if n > 0 {
+            eprintln!("  {oi:5} {n:3} {}", "#".repeat(n as usize));
         }
-        Err(e) => {
-            eprintln!("failed to run objdump: {e}");
+    }
+}
+
+/// Print the buffer's instructions, labelling where each TB
+/// starts.
+fn disassemble(backend: &NativeCodeGen, buf: &CodeBuffer, tbs: &[usize]) {
+    let code = buf.as_slice();
+    let insns = translate::disassemble(backend, buf, 0, buf.offset());
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (i, (off, text)) in insns.iter().enumerate() {
+        if let Some(tb) = tbs.iter().position(|&s| s == *off) {
+            writeln!(out, "TB #{tb}:").expect("write failed");
         }
+        let end = insns.get(i + 1).map_or(buf.offset(), |next| next.0);
+        let bytes: Vec<String> =
+            code[*off..end].iter().map(|b| format!("{b:02x}")).collect();
+        writeln!(out, "{off:6x}:  {:<24} {text}", bytes.join(" "))
+            .expect("write failed");
     }
-    let _ = fs::remove_file(tmp);
 }
 
 fn main() {
@@ -105,14 +135,26 @@ fn main() {
     });
 
     let mut cursor = io::Cursor::new(&data);
-    let contexts = serialize::deserialize(&mut cursor).unwrap_or_else(|e| {
-        eprintln!("deserialize error: {e}");
+    let records =
+        serialize::deserialize_records(&mut cursor).unwrap_or_else(|e| {
+            eprintln!("deserialize error: {e}");
+            process::exit(1);
+        });
+
+    eprintln!("loaded {} TB(s)", records.len());
+    let warnings = serialize::check_records(&records, args.arch.as_deref());
+    for w in &warnings {
+        eprintln!("warning: {w}");
+    }
+    if warnings
+        .iter()
+        .any(|w| matches!(w, MetaWarning::ArchMismatch { .. }))
+    {
+        eprintln!("refusing to process TBs of another arch");
         process::exit(1);
-    });
-
-    eprintln!("loaded {} TB(s)", contexts.len());
+    }
 
-    let mut backend = X86_64CodeGen::new();
+    let mut backend = NativeCodeGen::new();
     let mut buf = CodeBuffer::new(64 * 1024).expect("mmap failed");
 
     // Emit prologue + epilogue first (ExitTb needs
@@ -120,14 +162,33 @@ fn main() {
     backend.emit_prologue(&mut buf);
     backend.emit_epilogue(&mut buf);
     let prologue_size = buf.offset();
+    let mut tb_starts = Vec::new();
 
-    for (i, mut ctx) in contexts.into_iter().enumerate() {
+    for (i, rec) in records.into_iter().enumerate() {
+        if let Some(meta) = &rec.meta {
+            eprintln!("TB #{i}: {meta}");
+        }
+        let mut ctx = rec.ctx;
         backend.init_context(&mut ctx);
         backend.clear_goto_tb_offsets();
-        let tb_start = translate(&mut ctx, &backend, &mut buf);
+        analyze(&mut ctx);
+        let report = args
+            .pressure
+            .then(|| pressure_report(&ctx, backend.allocatable_regs().count()));
+        let tb_start = match codegen(&mut ctx, &backend, &mut buf) {
+            Ok(off) => off,
+            Err(e) => {
+                eprintln!("TB #{i}: {e}");
+                process::exit(1);
+            }
+        };
+        tb_starts.push(tb_start);
         let tb_end = buf.offset();
         let tb_size = tb_end - tb_start;
         eprintln!("TB #{i}: {tb_size} bytes @ offset 0x{tb_start:x}");
+        if let Some(report) = &report {
+            print_pressure(report);
+        }
     }
 
     let code = &buf.as_slice()[prologue_size..];
@@ -139,7 +200,7 @@ fn main() {
     );
 
     if args.disas {
-        disassemble(buf.as_slice());
+        disassemble(&backend, &buf, &tb_starts);
     } else if args.raw {
         let mut out: Box<dyn Write> = match &args.output {
             Some(path) => {
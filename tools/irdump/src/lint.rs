@@ -0,0 +1,160 @@
+//! IR budget lint: IR ops per guest instruction against a
+//! checked-in maximum per decode pattern.
+//!
+//! Budget files hold one `<pattern> <max ops>` pair per line;
+//! `#` starts a comment. Counts are `InsnOps::body()`: ops
+//! between an instruction's `insn_start` and the next, without
+//! the TB exits it emits itself and without the TB epilogue.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+
+use tcg_core::dump::dump_op_range;
+use tcg_core::insn_ops::insn_ops;
+use tcg_core::Context;
+
+/// An instruction over its budget: the first instance seen.
+pub struct Violation {
+    pub pc: u64,
+    pub ops: usize,
+    pub max: usize,
+    /// Instances over budget.
+    pub count: usize,
+    /// The IR of the first.
+    pub ir: String,
+}
+
+#[derive(Default)]
+pub struct Lint {
+    budgets: Option<BTreeMap<String, usize>>,
+    /// Largest count seen per pattern.
+    seen: BTreeMap<&'static str, usize>,
+    violations: BTreeMap<&'static str, Violation>,
+}
+
+impl Lint {
+    /// Check against the budget file `path`, if given.
+    pub fn new(path: Option<&str>) -> Result<Self, String> {
+        let budgets = match path {
+            Some(p) => {
+                let text =
+                    fs::read_to_string(p).map_err(|e| format!("{p}: {e}"))?;
+                Some(parse(&text).map_err(|e| format!("{p}: {e}"))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            budgets,
+            ..Self::default()
+        })
+    }
+
+    /// Count the ops of every instruction of the TB in `ir`,
+    /// which must have been translated with insn metadata.
+    pub fn observe(&mut self, ir: &Context) {
+        let meta = ir.insn_meta().expect("insn metadata enabled");
+        for insn in insn_ops(ir).insns {
+            let Some(m) = meta.get(&insn.pc) else {
+                continue;
+            };
+            let ops = insn.body();
+            let seen = self.seen.entry(m.name).or_default();
+            *seen = (*seen).max(ops);
+            let Some(&max) = self.budgets.as_ref().and_then(|b| b.get(m.name))
+            else {
+                continue;
+            };
+            if ops <= max {
+                continue;
+            }
+            self.violations
+                .entry(m.name)
+                .and_modify(|v| v.count += 1)
+                .or_insert_with(|| {
+                    let mut ir_text = Vec::new();
+                    dump_op_range(ir, insn.ops.clone(), &mut ir_text)
+                        .expect("write failed");
+                    Violation {
+                        pc: insn.pc,
+                        ops,
+                        max,
+                        count: 1,
+                        ir: String::from_utf8_lossy(&ir_text).into_owned(),
+                    }
+                });
+        }
+    }
+
+    pub fn violations(&self) -> &BTreeMap<&'static str, Violation> {
+        &self.violations
+    }
+
+    /// Patterns seen but missing from the budget file.
+    pub fn unbudgeted(&self) -> Vec<&'static str> {
+        let Some(budgets) = &self.budgets else {
+            return Vec::new();
+        };
+        self.seen
+            .keys()
+            .copied()
+            .filter(|n| !budgets.contains_key(*n))
+            .collect()
+    }
+
+    /// Report of every violation, with its IR.
+    pub fn report(&self) -> String {
+        let mut s = String::new();
+        for (name, v) in &self.violations {
+            let more = match v.count {
+                1 => String::new(),
+                n => format!(" ({} more instances)", n - 1),
+            };
+            writeln!(
+                s,
+                "{name} at 0x{:x}: {} IR ops, budget {}{more}",
+                v.pc, v.ops, v.max
+            )
+            .unwrap();
+            s.push_str(&v.ir);
+        }
+        s
+    }
+
+    /// Write the largest count seen per pattern as a budget
+    /// file.
+    pub fn write_budgets(&self, path: &str) -> io::Result<()> {
+        let mut s = String::from(
+            "# IR ops per guest instruction, without insn_start and\n\
+             # TB exits; checked by tcg-irdump --lint-ir-budget.\n\
+             # Regenerate with --write-budgets.\n",
+        );
+        let width = self.seen.keys().map(|n| n.len()).max().unwrap_or(0);
+        for (name, ops) in &self.seen {
+            writeln!(s, "{name:width$} {ops}").unwrap();
+        }
+        fs::write(path, s)
+    }
+}
+
+fn parse(text: &str) -> Result<BTreeMap<String, usize>, String> {
+    let mut budgets = BTreeMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(max), None) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!("line {}: expected <pattern> <max>", n + 1));
+        };
+        let max = max
+            .parse()
+            .map_err(|_| format!("line {}: invalid count {max}", n + 1))?;
+        budgets.insert(name.to_string(), max);
+    }
+    Ok(budgets)
+}
@@ -1,23 +1,35 @@
 //! tcg-irdump — static ELF → IR dump tool.
 //!
 //! Reads a guest ELF binary, translates it TB-by-TB into TCG IR,
-//! and prints the IR in a human-readable format.
+//! and prints the IR in a human-readable format, or with
+//! `--format json` as a JSON array with one object per TB. With
+//! `--canonical` it translates the canonical encoding of every
+//! decode pattern instead, one instruction per TB.
 
 mod elf;
+mod lint;
 
+use std::collections::BTreeSet;
 use std::env;
 use std::fs;
 use std::io::{self, BufWriter, Write};
 use std::process;
 
+use tcg_backend::liveness::{liveness_analysis, pressure_report};
+use tcg_backend::{HostCodeGen, NativeCodeGen};
 use tcg_core::context::Context;
-use tcg_core::dump::dump_ops_with;
-use tcg_core::serialize;
-use tcg_core::TempIdx;
-use tcg_frontend::riscv::cpu::NUM_GPRS;
+use tcg_core::dump::{dump_ops_json_with, dump_ops_with};
+use tcg_core::pressure::LivenessReport;
+use tcg_core::serialize::{self, IrMeta};
+use tcg_core::tb::DisasJumpType;
 use tcg_frontend::riscv::ext::RiscvCfg;
-use tcg_frontend::riscv::{RiscvDisasContext, RiscvTranslator};
-use tcg_frontend::{translator_loop, DisasJumpType, TranslatorOps};
+use tcg_frontend::riscv::{
+    insn_len, RiscvDisasContext, RiscvTranslator, CANONICAL_ENCODINGS,
+    CANONICAL_ENCODINGS16,
+};
+use tcg_frontend::translator_loop;
+
+use lint::Lint;
 
 const EM_RISCV: u16 = 243;
 
@@ -49,25 +61,42 @@ impl Arch {
 }
 
 struct Args {
-    elf_path: String,
+    elf_path: Option<String>,
+    canonical: bool,
     arch: Option<String>,
     output: Option<String>,
+    json: bool,
     emit_bin: Option<String>,
     start: Option<u64>,
     count: Option<usize>,
     max_insns: u32,
+    require_ext: Option<Vec<String>>,
+    lint_budget: Option<String>,
+    write_budgets: Option<String>,
 }
 
 const USAGE: &str = "\
 usage: tcg-irdump <elf> [options]
+       tcg-irdump --canonical [options]
 
 Options:
   --arch <name>      Guest architecture (default: auto)
   -o <file>          Output to file
+  --format <fmt>     Output format: text (default) or json
   --emit-bin <file>  Emit binary .tcgir file
   --start <hex>      Start address
   --count <n>        Max TBs to translate
   --max-insns <n>    Max insns per TB (default: 512)
+  --require-ext <l>  Fail on an instruction needing an extension
+                     outside the comma-separated list, e.g. I,M,C
+  --canonical        Translate the canonical encoding of every
+                     decode pattern instead of an ELF
+  --lint-ir-budget <file>
+                     Check IR ops per instruction against the
+                     budgets in <file>; exit 1 if one is exceeded
+  --write-budgets <file>
+                     Write the largest IR op count seen per
+                     instruction to <file> as a budget file
   -h, --help         Show this help
 
 Supported architectures: riscv64";
@@ -80,16 +109,21 @@ fn parse_args() -> Args {
     }
 
     let mut a = Args {
-        elf_path: args[1].clone(),
+        elf_path: None,
+        canonical: false,
         arch: None,
         output: None,
+        json: false,
         emit_bin: None,
         start: None,
         count: None,
         max_insns: 512,
+        require_ext: None,
+        lint_budget: None,
+        write_budgets: None,
     };
 
-    let mut i = 2;
+    let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "--arch" => {
@@ -100,6 +134,17 @@ fn parse_args() -> Args {
                 i += 1;
                 a.output = Some(args[i].clone());
             }
+            "--format" => {
+                i += 1;
+                a.json = match args[i].as_str() {
+                    "text" => false,
+                    "json" => true,
+                    f => {
+                        eprintln!("unknown format: {f}");
+                        process::exit(1);
+                    }
+                };
+            }
             "--emit-bin" => {
                 i += 1;
                 a.emit_bin = Some(args[i].clone());
@@ -119,6 +164,23 @@ fn parse_args() -> Args {
                 i += 1;
                 a.max_insns = args[i].parse().expect("invalid max-insns");
             }
+            "--require-ext" => {
+                i += 1;
+                a.require_ext =
+                    Some(args[i].split(',').map(str::to_string).collect());
+            }
+            "--canonical" => a.canonical = true,
+            "--lint-ir-budget" => {
+                i += 1;
+                a.lint_budget = Some(args[i].clone());
+            }
+            "--write-budgets" => {
+                i += 1;
+                a.write_budgets = Some(args[i].clone());
+            }
+            path if !path.starts_with('-') && a.elf_path.is_none() => {
+                a.elf_path = Some(path.to_string());
+            }
             other => {
                 eprintln!("unknown option: {other}");
                 process::exit(1);
@@ -126,6 +188,10 @@ fn parse_args() -> Args {
         }
         i += 1;
     }
+    if a.canonical == a.elf_path.is_some() {
+        eprintln!("give either an ELF or --canonical\n\n{USAGE}");
+        process::exit(1);
+    }
     a
 }
 
@@ -156,37 +222,114 @@ fn build_image(info: &elf::ElfInfo) -> (u64, Vec<u8>) {
     (lo, image)
 }
 
+/// Order extension tags as in an ISA string: base letters in
+/// `IMAFDC` order, then Z-extensions alphabetically.
+fn ext_key(ext: &str) -> (usize, String) {
+    let lower = ext.to_ascii_lowercase();
+    let rank = match lower.as_str() {
+        "i" => 0,
+        "m" => 1,
+        "a" => 2,
+        "f" => 3,
+        "d" => 4,
+        "c" => 5,
+        _ => 6,
+    };
+    (rank, lower)
+}
+
+fn ext_summary<'a>(exts: impl IntoIterator<Item = &'a str>) -> String {
+    let mut v: Vec<&str> = exts
+        .into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    v.sort_by_key(|e| ext_key(e));
+    v.join(" ")
+}
+
+/// The guest disassembly of the instruction at `pc`.
+fn guest_asm_riscv64(
+    pc: u64,
+    guest_base: *const u8,
+    w: &mut dyn Write,
+) -> io::Result<()> {
+    unsafe {
+        let ptr = guest_base.add(pc as usize);
+        let len = insn_len((ptr as *const u16).read_unaligned());
+        let data = std::slice::from_raw_parts(ptr, len);
+        let (asm, _) = tcg_disas::riscv::print_insn_riscv64(pc, data);
+        write!(w, "{asm}")
+    }
+}
+
 fn insn_annotation_riscv64(
     pc: u64,
     guest_base: *const u8,
+    ir: &Context,
     w: &mut dyn Write,
 ) -> io::Result<()> {
+    let exts = ir
+        .insn_meta()
+        .and_then(|m| m.get(&pc))
+        .map(|m| format!("  [{}]", m.exts.join(",")))
+        .unwrap_or_default();
     unsafe {
         let ptr = guest_base.add(pc as usize);
         let half = (ptr as *const u16).read_unaligned();
-        let len = if half & 0x3 != 0x3 { 2 } else { 4 };
+        let len = insn_len(half);
         let data = std::slice::from_raw_parts(ptr, len);
         let (asm, _) = tcg_disas::riscv::print_insn_riscv64(pc, data);
         if len == 2 {
-            write!(w, "  {half:04x}      {asm}")
+            write!(w, "  {half:04x}      {asm}{exts}")
         } else {
             let insn = (ptr as *const u32).read_unaligned();
-            write!(w, "  {insn:08x}  {asm}")
+            write!(w, "  {insn:08x}  {asm}{exts}")
         }
     }
 }
 
+/// Register pressure of the TB in `ir` against the host
+/// backend's registers. Liveness runs on a copy, so the dump
+/// still shows the IR as translated; it only turns dead ops
+/// into `nop`, keeping the op indices of the timeline.
+fn tb_pressure(ir: &Context) -> LivenessReport {
+    let mut copy = Context::from_raw_parts(
+        ir.temps().to_vec(),
+        ir.ops().to_vec(),
+        ir.labels().to_vec(),
+        ir.nb_globals(),
+    );
+    liveness_analysis(&mut copy);
+    let nb_regs = NativeCodeGen::new().allocatable_regs().count();
+    pressure_report(&copy, nb_regs)
+}
+
 /// Translate one TB starting at `pc` and dump its IR.
+#[allow(clippy::too_many_arguments)]
 fn translate_tb(
     arch: Arch,
     ir: &mut Context,
     pc: u64,
     guest_base: *const u8,
+    code_end: u64,
     max_insns: u32,
+    json: bool,
     w: &mut impl Write,
 ) -> (u64, DisasJumpType) {
     match arch {
-        Arch::Riscv64 => translate_tb_riscv64(ir, pc, guest_base, max_insns, w),
+        Arch::Riscv64 => translate_tb_riscv64(
+            ir, pc, guest_base, code_end, max_insns, json, w,
+        ),
+    }
+}
+
+/// Decode with every implemented extension on, off-by-default
+/// ones included, so dumps and IR budgets cover all patterns.
+fn dump_cfg() -> RiscvCfg {
+    RiscvCfg {
+        ext_zbb: true,
+        ..RiscvCfg::default()
     }
 }
 
@@ -194,57 +337,124 @@ fn translate_tb_riscv64(
     ir: &mut Context,
     pc: u64,
     guest_base: *const u8,
+    code_end: u64,
     max_insns: u32,
+    json: bool,
     w: &mut impl Write,
 ) -> (u64, DisasJumpType) {
-    let cfg = RiscvCfg::default();
-    if ir.nb_globals() == 0 {
-        // First TB — register globals via translator_loop.
-        let mut d = RiscvDisasContext::new(pc, guest_base, cfg);
-        d.base.max_insns = max_insns;
-        translator_loop::<RiscvTranslator>(&mut d, ir);
-        let gb = guest_base;
-        dump_ops_with(ir, w, |pc, w| insn_annotation_riscv64(pc, gb, w))
-            .expect("write failed");
-        (d.base.pc_next, d.base.is_jmp)
-    } else {
-        // Subsequent TBs — globals already registered.
+    if ir.nb_globals() != 0 {
         ir.reset();
-        let mut d = RiscvDisasContext::new(pc, guest_base, cfg);
-        d.base.max_insns = max_insns;
-        d.env = TempIdx(0);
-        for i in 0..NUM_GPRS {
-            d.gpr[i] = TempIdx(1 + i as u32);
-        }
-        d.pc = TempIdx(1 + NUM_GPRS as u32);
-        d.load_res = TempIdx(1 + NUM_GPRS as u32 + 1);
-        d.load_val = TempIdx(1 + NUM_GPRS as u32 + 2);
-        RiscvTranslator::tb_start(&mut d, ir);
-        loop {
-            RiscvTranslator::insn_start(&mut d, ir);
-            RiscvTranslator::translate_insn(&mut d, ir);
-            if d.base.is_jmp != DisasJumpType::Next {
-                break;
-            }
-            if d.base.num_insns >= d.base.max_insns {
-                d.base.is_jmp = DisasJumpType::TooMany;
-                break;
-            }
-        }
-        RiscvTranslator::tb_stop(&mut d, ir);
-        let gb = guest_base;
-        dump_ops_with(ir, w, |pc, w| insn_annotation_riscv64(pc, gb, w))
-            .expect("write failed");
-        (d.base.pc_next, d.base.is_jmp)
     }
+    let mut d = RiscvDisasContext::new(pc, guest_base, dump_cfg());
+    d.base.max_insns = max_insns;
+    d.base.pc_page_end = code_end;
+    let info = translator_loop::<RiscvTranslator>(&mut d, ir);
+    if json {
+        let pressure = tb_pressure(ir);
+        dump_ops_json_with(ir, w, Some(&pressure), |pc, w| {
+            guest_asm_riscv64(pc, guest_base, w)
+        })
+    } else {
+        dump_ops_with(ir, w, |pc, w| {
+            insn_annotation_riscv64(pc, guest_base, ir, w)
+        })
+    }
+    .expect("write failed");
+    (info.next_pc, info.is_jmp)
 }
 
 fn main() {
     let args = parse_args();
+    let mut lint = Lint::new(args.lint_budget.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+    let linting = args.lint_budget.is_some() || args.write_budgets.is_some();
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            let f = fs::File::create(path).unwrap_or_else(|e| {
+                eprintln!("cannot create {path}: {e}");
+                process::exit(1);
+            });
+            Box::new(BufWriter::new(f))
+        }
+        // A lint only prints its findings.
+        None if linting => Box::new(io::sink()),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    if args.json {
+        writeln!(out, "[").expect("write failed");
+    }
+    match &args.elf_path {
+        Some(path) => dump_elf(&args, path, &mut lint, &mut out),
+        None => dump_canonical(args.json, &mut lint, &mut out),
+    }
+    if args.json {
+        writeln!(out, "]").expect("write failed");
+    }
+    out.flush().expect("flush failed");
+
+    if let Some(path) = &args.write_budgets {
+        lint.write_budgets(path).unwrap_or_else(|e| {
+            eprintln!("cannot write {path}: {e}");
+            process::exit(1);
+        });
+    }
+    if args.lint_budget.is_some() {
+        for name in lint.unbudgeted() {
+            eprintln!("warning: {name}: no IR budget");
+        }
+        if !lint.violations().is_empty() {
+            eprint!("{}", lint.report());
+            eprintln!(
+                "{} instruction(s) over their IR budget",
+                lint.violations().len()
+            );
+            process::exit(1);
+        }
+    }
+}
 
-    let data = fs::read(&args.elf_path).unwrap_or_else(|e| {
-        let p = &args.elf_path;
-        eprintln!("failed to read {p}: {e}");
+/// Translate each decode pattern's canonical encoding as a
+/// one-instruction TB at pc 0.
+fn dump_canonical(json: bool, lint: &mut Lint, out: &mut impl Write) {
+    let mut ir = Context::new();
+    ir.enable_insn_meta();
+    let corpus = CANONICAL_ENCODINGS.iter().chain(CANONICAL_ENCODINGS16);
+    for (n, &(name, insn)) in corpus.enumerate() {
+        let code = insn.to_le_bytes();
+        if json {
+            tb_separator(n, out);
+        } else {
+            writeln!(out, "TB #{n} {name}").expect("write failed");
+        }
+        let end = code.len() as u64;
+        let base = code.as_ptr();
+        translate_tb(Arch::Riscv64, &mut ir, 0, base, end, 1, json, out);
+        if !json {
+            writeln!(out).expect("write failed");
+        }
+        lint.observe(&ir);
+    }
+}
+
+/// Separate TB `n` from the previous one in the JSON array.
+fn tb_separator(n: usize, out: &mut impl Write) {
+    if n > 0 {
+        write!(out, ",").expect("write failed");
+    }
+}
+
+fn dump_elf(
+    args: &Args,
+    elf_path: &str,
+    lint: &mut Lint,
+    out: &mut impl Write,
+) {
+    let data = fs::read(elf_path).unwrap_or_else(|e| {
+        eprintln!("failed to read {elf_path}: {e}");
         process::exit(1);
     });
 
@@ -282,18 +492,13 @@ fn main() {
     let start_pc = args.start.unwrap_or(info.entry);
     let max_count = args.count.unwrap_or(usize::MAX);
 
-    let mut out: Box<dyn Write> = match &args.output {
-        Some(path) => {
-            let f = fs::File::create(path).unwrap_or_else(|e| {
-                eprintln!("cannot create {path}: {e}");
-                process::exit(1);
-            });
-            Box::new(BufWriter::new(f))
-        }
-        None => Box::new(BufWriter::new(io::stdout().lock())),
-    };
-
     let mut ir = Context::new();
+    ir.enable_insn_meta();
+    let mut all_exts: BTreeSet<&'static str> = BTreeSet::new();
+    let allowed: Option<BTreeSet<String>> = args
+        .require_ext
+        .as_ref()
+        .map(|l| l.iter().map(|e| e.trim().to_ascii_lowercase()).collect());
     let mut pc = start_pc;
     let mut tb_count = 0usize;
 
@@ -302,16 +507,60 @@ fn main() {
     let emit_bin = args.emit_bin.is_some();
 
     while pc >= base_addr && pc < image_end && tb_count < max_count {
-        writeln!(out, "TB #{tb_count} @ 0x{pc:x}").expect("write failed");
+        // An instruction cut off by the image end is not decoded.
+        let off = (pc - base_addr) as usize;
+        let half = match image.get(off..off + 2) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]),
+            None => break,
+        };
+        if pc + insn_len(half) as u64 > image_end {
+            break;
+        }
+        if args.json {
+            tb_separator(tb_count, out);
+        } else {
+            writeln!(out, "TB #{tb_count} @ 0x{pc:x}").expect("write failed");
+        }
         let (next_pc, _) = translate_tb(
             arch,
             &mut ir,
             pc,
             guest_base,
+            image_end,
             args.max_insns,
-            &mut out,
+            args.json,
+            out,
         );
-        writeln!(out).expect("write failed");
+        lint.observe(&ir);
+        let meta = ir.insn_meta().expect("insn metadata enabled");
+        if !args.json {
+            let tb_exts = meta.values().flat_map(|m| m.exts.iter().copied());
+            writeln!(out, "extensions used: {}", ext_summary(tb_exts))
+                .expect("write failed");
+            writeln!(out).expect("write failed");
+        }
+        all_exts.extend(meta.values().flat_map(|m| m.exts.iter().copied()));
+
+        if let Some(allowed) = &allowed {
+            for (&ipc, m) in meta {
+                let missing: Vec<&str> = m
+                    .exts
+                    .iter()
+                    .copied()
+                    .filter(|e| !allowed.contains(&e.to_ascii_lowercase()))
+                    .collect();
+                if !missing.is_empty() {
+                    out.flush().expect("flush failed");
+                    eprintln!(
+                        "error: {} at 0x{ipc:x} requires {}, \
+                         not in --require-ext",
+                        m.name,
+                        missing.join(",")
+                    );
+                    process::exit(1);
+                }
+            }
+        }
 
         if emit_bin {
             // Snapshot current context for serialization.
@@ -329,14 +578,31 @@ fn main() {
         pc = next_pc;
     }
 
+    if !args.json {
+        writeln!(out, "extensions used (all TBs): {}", ext_summary(all_exts))
+            .expect("write failed");
+    }
+
     if let Some(ref path) = args.emit_bin {
+        let isa = dump_cfg().isa_string();
+        let meta = IrMeta {
+            arch: arch.name().to_string(),
+            pc_range: None,
+            cfg_hash: IrMeta::hash_cfg(&isa),
+            producer: format!("tcg-irdump {}", env!("CARGO_PKG_VERSION")),
+            extra: vec![
+                ("isa".to_string(), isa),
+                ("elf".to_string(), elf_path.to_string()),
+            ],
+        };
         let f = fs::File::create(path).unwrap_or_else(|e| {
             eprintln!("cannot create {path}: {e}");
             process::exit(1);
         });
         let mut bw = BufWriter::new(f);
         for ctx in &bin_contexts {
-            serialize::serialize(ctx, &mut bw).expect("serialize failed");
+            serialize::serialize_with_meta(ctx, &meta, &mut bw)
+                .expect("serialize failed");
         }
         bw.flush().expect("flush failed");
         eprintln!("wrote {} TB(s) to {path}", bin_contexts.len());